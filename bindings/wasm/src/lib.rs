@@ -0,0 +1,238 @@
+//! The note parser and link resolver of obsidian-rs compiled to WebAssembly, so JavaScript
+//! tools and Obsidian plugins resolve links and read front matter exactly as the CLI does.
+//! The modules are the CLI's own sources, included by path rather than copied.
+//!
+//! Build with `wasm-pack build bindings/wasm --target web` (or `--target nodejs`) after
+//! `rustup target add wasm32-unknown-unknown`; the toolchain file leaves that target out.
+
+#[allow(dead_code)]
+#[path = "../../../src/frontmatter.rs"]
+mod frontmatter;
+#[allow(dead_code)]
+#[path = "../../../src/markdown.rs"]
+mod markdown;
+#[allow(dead_code)]
+#[path = "../../../src/note.rs"]
+mod note;
+#[allow(dead_code)]
+#[path = "../../../src/resolver.rs"]
+mod resolver;
+#[allow(dead_code)]
+#[path = "../../../src/util.rs"]
+mod util;
+
+/// What `resolver` needs of the CLI's `data` module; there is no vault to walk here.
+#[allow(dead_code)]
+mod data {
+    pub use crate::note::Note;
+
+    use std::{
+        error::Error,
+        path::{Path, PathBuf},
+    };
+
+    pub fn traverse_vault(_vault_path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        Err("No file system to read a vault from; pass its paths to `new Resolver()`".into())
+    }
+}
+
+use markdown::LinkStyle;
+use note::Note;
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use wasm_bindgen::prelude::*;
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Converts to plain JS objects, `None` as `null`.
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| JsError::new(&e.to_string()))
+}
+
+#[derive(Serialize)]
+struct ParsedNote {
+    path: String,
+    title: String,
+    tags: Vec<String>,
+    aliases: Vec<String>,
+    frontmatter: Option<serde_yaml::Mapping>,
+}
+
+#[derive(Serialize)]
+struct ParsedLink {
+    target: String,
+    anchor: Option<String>,
+    text: Option<String>,
+    embed: bool,
+    /// `wiki` or `markdown`
+    style: &'static str,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Serialize)]
+struct ParsedHeading {
+    level: usize,
+    text: String,
+    /// The `#anchor` that links to the heading
+    slug: String,
+    line: usize,
+}
+
+#[derive(Deserialize)]
+struct NoteText {
+    path: String,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct ParsedBacklink {
+    path: String,
+    line: usize,
+    context: String,
+}
+
+/// `{path, title, tags, aliases, frontmatter}` for a note's text; tags come from the front
+/// matter and the body, without the leading `#`.
+#[wasm_bindgen(js_name = parseNote)]
+pub fn parse_note(path: &str, content: &str) -> Result<JsValue, JsError> {
+    let frontmatter = frontmatter::parse_mapping(content).ok().flatten();
+    let note = Note::from_content(PathBuf::from(path), content.to_string());
+    to_js(&ParsedNote {
+        path: key(&note.path),
+        title: note.title(),
+        aliases: note.front_matter.aliases.clone(),
+        tags: note.tags,
+        frontmatter,
+    })
+}
+
+/// The front matter as an object, `null` without one; invalid YAML throws.
+#[wasm_bindgen(js_name = parseFrontmatter)]
+pub fn parse_frontmatter(content: &str) -> Result<JsValue, JsError> {
+    let mapping = frontmatter::parse_mapping(content).map_err(|e| JsError::new(&e.to_string()))?;
+    to_js(&mapping)
+}
+
+/// Wikilinks, embeds and Markdown links outside code, external ones included.
+#[wasm_bindgen(js_name = parseLinks)]
+pub fn parse_links(content: &str) -> Result<JsValue, JsError> {
+    let links: Vec<ParsedLink> = markdown::parse_links(content)
+        .into_iter()
+        .map(|link| ParsedLink {
+            style: match link.style {
+                LinkStyle::Wiki => "wiki",
+                LinkStyle::Markdown => "markdown",
+            },
+            target: link.target,
+            anchor: link.anchor,
+            text: link.text,
+            embed: link.embed,
+            line: link.line,
+            column: link.column,
+        })
+        .collect();
+    to_js(&links)
+}
+
+#[wasm_bindgen(js_name = parseHeadings)]
+pub fn parse_headings(content: &str) -> Result<JsValue, JsError> {
+    let headings: Vec<ParsedHeading> = markdown::parse_headings(content)
+        .into_iter()
+        .map(|heading| ParsedHeading {
+            level: heading.level,
+            slug: markdown::heading_slug(&heading.text),
+            text: heading.text,
+            line: heading.line,
+        })
+        .collect();
+    to_js(&headings)
+}
+
+/// Inline `#tags` of the body, without the leading `#`.
+#[wasm_bindgen(js_name = parseTags)]
+pub fn parse_tags(content: &str) -> Vec<String> {
+    markdown::parse_tags(content)
+}
+
+/// Resolves link targets against a vault's file list, as `new Resolver(paths)` with
+/// vault-relative paths such as `app.vault.getFiles().map(f => f.path)`.
+#[wasm_bindgen(js_name = Resolver)]
+pub struct JsResolver {
+    resolver: resolver::Resolver,
+}
+
+#[wasm_bindgen(js_class = Resolver)]
+impl JsResolver {
+    #[wasm_bindgen(constructor)]
+    pub fn new(paths: Vec<String>) -> JsResolver {
+        JsResolver {
+            resolver: resolver::Resolver::new(paths.into_iter().map(PathBuf::from).collect()),
+        }
+    }
+
+    /// The vault path `target` means when linked from the note at `source`, `undefined`
+    /// for unresolved links.
+    pub fn resolve(&self, target: &str, source: &str) -> Option<String> {
+        self.resolver.resolve(target, Path::new(source)).map(key)
+    }
+
+    /// Vault files the note links to or embeds.
+    #[wasm_bindgen(js_name = linkedFiles)]
+    pub fn linked_files(&self, path: &str, content: &str) -> Vec<String> {
+        let note = Note::from_content(PathBuf::from(path), content.to_string());
+        resolver::linked_files(&note, &self.resolver)
+            .into_iter()
+            .map(|file| key(&file))
+            .collect()
+    }
+
+    /// `{path, line, context}` for every link to `target` from `notes`, an array of
+    /// `{path, content}`.
+    pub fn backlinks(&self, notes: JsValue, target: &str) -> Result<JsValue, JsError> {
+        let notes: Vec<NoteText> = serde_wasm_bindgen::from_value(notes)?;
+        let notes: Vec<Note> = notes
+            .into_iter()
+            .map(|note| Note::from_content(PathBuf::from(note.path), note.content))
+            .collect();
+        let backlinks: Vec<ParsedBacklink> =
+            resolver::backlinks(&notes, &self.resolver, Path::new(target))
+                .into_iter()
+                .map(|backlink| ParsedBacklink {
+                    path: key(&backlink.path),
+                    line: backlink.line,
+                    context: backlink.context,
+                })
+                .collect();
+        to_js(&backlinks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolver() {
+        let resolver = JsResolver::new(vec![
+            String::from("Home.md"),
+            String::from("projects/Plan.md"),
+            String::from("assets/diagram.png"),
+        ]);
+        assert_eq!(
+            resolver.resolve("Plan", "Home.md").as_deref(),
+            Some("projects/Plan.md")
+        );
+        assert_eq!(resolver.resolve("Missing", "Home.md"), None);
+        assert_eq!(
+            resolver.linked_files("Home.md", "See [[Plan#Goals]] and ![[diagram.png]]."),
+            vec!["projects/Plan.md", "assets/diagram.png"]
+        );
+        assert_eq!(parse_tags("Filed under #work/plans"), vec!["work/plans"]);
+    }
+}
@@ -0,0 +1,16 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "grpc")]
+    {
+        // The bundled protoc, so building needs none installed; $PROTOC still wins
+        if std::env::var_os("PROTOC").is_none() {
+            // SAFETY: build scripts run on a single thread
+            unsafe { std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?) };
+        }
+        tonic_build::compile_protos("proto/vault.proto")?;
+    }
+    // Into OUT_DIR, leaving the checkout clean; ci/update-header.sh refreshes the committed one
+    #[cfg(feature = "ffi")]
+    cbindgen::generate(std::env::var("CARGO_MANIFEST_DIR")?)?
+        .write_to_file(std::path::Path::new(&std::env::var("OUT_DIR")?).join("obsidian_rs.h"));
+    Ok(())
+}
@@ -0,0 +1,23 @@
+#!/bin/sh
+# Checks that the crate builds with no optional features, with each one on its own and
+# with all of them, so a feature never quietly depends on another.
+set -eu
+
+features=$(sed -n '/^\[features\]/,/^\[/p' Cargo.toml |
+    sed -n 's/^\([a-z0-9-]*\) = \[.*/\1/p' |
+    grep -v -e '^default$' -e '^full$')
+
+cargo clippy --all-targets --no-default-features -- -D warnings
+for feature in $features; do
+    echo "== $feature"
+    cargo clippy --all-targets --no-default-features --features "$feature" -- -D warnings
+done
+cargo clippy --all-targets --features full -- -D warnings
+cargo test --features full
+
+# The WebAssembly bindings compile src/ modules by path, so an import those modules gain
+# breaks them without touching the main crate
+rustup target add wasm32-unknown-unknown
+wasm=bindings/wasm/Cargo.toml
+cargo clippy --manifest-path "$wasm" --all-targets --target wasm32-unknown-unknown -- -D warnings
+cargo test --manifest-path "$wasm"
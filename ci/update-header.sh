@@ -0,0 +1,19 @@
+#!/bin/sh
+# Regenerates include/obsidian_rs.h from src/ffi.rs; run it after changing the C API and
+# commit the result. Fails under --check when the committed header is out of date.
+set -eu
+
+cargo build --lib --features ffi
+header=$(ls -t target/debug/build/obsidian-rs-*/out/obsidian_rs.h 2>/dev/null | head -n 1)
+if [ -z "$header" ]; then
+    echo "No generated header found under target/debug/build" >&2
+    exit 1
+fi
+if [ "${1:-}" = "--check" ]; then
+    cmp -s "$header" include/obsidian_rs.h || {
+        echo "include/obsidian_rs.h is out of date; run ci/update-header.sh" >&2
+        exit 1
+    }
+else
+    cp "$header" include/obsidian_rs.h
+fi
@@ -0,0 +1,93 @@
+/* The obsidian-rs C API; generated by cbindgen from src/ffi.rs, do not edit. */
+
+#ifndef OBSIDIAN_RS_H
+#define OBSIDIAN_RS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * An open vault index; opaque to C
+ */
+typedef struct ObsidianIndex ObsidianIndex;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the index of the vault `config_path` configures, or the one of the default
+ * configuration file and active profile when it is null. A file given by path is read
+ * as is, without any profile, since the saved one names a table of the default file.
+ * Returns null on failure.
+ *
+ * # Safety
+ *
+ * `config_path` is null or a NUL-terminated string.
+ */
+ObsidianIndex *obsidian_index_open(const char *config_path);
+
+/**
+ * Brings the index up to date with the vault. Returns 0, or -1 on failure.
+ *
+ * # Safety
+ *
+ * `index` came from `obsidian_index_open` and has not been freed.
+ */
+int obsidian_index_refresh(const ObsidianIndex *index);
+
+/**
+ * Notes matching `query`, in the `query` command's syntax, as a JSON array of
+ * `{path, title, tags, created, modified, size}`; `limit` 0 returns every match.
+ *
+ * # Safety
+ *
+ * `index` came from `obsidian_index_open` and has not been freed; `query` is a
+ * NUL-terminated string.
+ */
+char *obsidian_index_query(const ObsidianIndex *index, const char *query, size_t limit);
+
+/**
+ * Ranks notes against `query` as the `search` command does, refreshing the index first,
+ * and returns the hits as a JSON array of `{path, heading, line, score, snippet}`;
+ * `limit` 0 means 20.
+ *
+ * # Safety
+ *
+ * `index` came from `obsidian_index_open` and has not been freed; `query` is a
+ * NUL-terminated string.
+ */
+char *obsidian_index_search(const ObsidianIndex *index, const char *query, size_t limit);
+
+/**
+ * Closes an index; null is ignored.
+ *
+ * # Safety
+ *
+ * `index` is null or came from `obsidian_index_open`, and is not used afterwards.
+ */
+void obsidian_index_free(ObsidianIndex *index);
+
+/**
+ * Releases a string returned by this library; null is ignored.
+ *
+ * # Safety
+ *
+ * `string` is null or came from this library, and is not used afterwards.
+ */
+void obsidian_string_free(char *string);
+
+/**
+ * Why the last failing call on this thread failed, null before any failure. The string
+ * belongs to the library and stays valid until the next failure on the thread.
+ */
+const char *obsidian_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* OBSIDIAN_RS_H */
@@ -0,0 +1,190 @@
+//! Grouped query results: `query --group-by tag --count` and the like, for dashboards.
+
+use crate::cli::OutputFormat;
+use crate::collation;
+use crate::data::Note;
+use crate::date_query::DateField;
+use crate::frontmatter;
+use crate::search_query::PROPERTY_PREFIX;
+use crate::status;
+use crate::util;
+
+use serde::Serialize;
+use std::{collections::BTreeMap, error::Error};
+
+/// What notes are grouped by
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupKey {
+    /// Each tag of the note; nested tags count as themselves
+    Tag,
+    /// The folder the note is in, `/` for the vault root
+    Folder,
+    Status,
+    /// `frontmatter.key`, each value of the property
+    Property(String),
+    /// `created`, `modified`, `due` or `date`, by day
+    Date(DateField),
+}
+
+impl GroupKey {
+    pub fn parse(text: &str) -> Result<GroupKey, Box<dyn Error>> {
+        if let Some(key) = text.strip_prefix(PROPERTY_PREFIX)
+            && !key.is_empty()
+        {
+            return Ok(GroupKey::Property(key.to_string()));
+        }
+        match text {
+            "tag" => Ok(GroupKey::Tag),
+            "folder" => Ok(GroupKey::Folder),
+            "status" => Ok(GroupKey::Status),
+            text => DateField::parse(text).map(GroupKey::Date).ok_or_else(|| {
+                format!(
+                    "Cannot group by '{}'; expected tag, folder, status, created, modified, \
+                     due, date or frontmatter.<key>",
+                    text
+                )
+                .into()
+            }),
+        }
+    }
+
+    /// The groups `note` belongs to; none means it is listed without a value.
+    pub fn values(&self, note: &Note) -> Vec<String> {
+        match self {
+            GroupKey::Tag => note.tags.clone(),
+            GroupKey::Folder => {
+                let path = note.path.to_string_lossy().replace('\\', "/");
+                match path.rsplit_once('/') {
+                    Some((folder, _)) => vec![folder.to_string()],
+                    None => vec![String::from("/")],
+                }
+            }
+            GroupKey::Status => status::of(note)
+                .map(|status| format!("{:?}", status).to_lowercase())
+                .into_iter()
+                .collect(),
+            GroupKey::Property(key) => frontmatter::scalar_properties(&note.content)
+                .into_iter()
+                .filter(|(own, _)| own == key)
+                .map(|(_, value)| value)
+                .collect(),
+            GroupKey::Date(field) => field
+                .of(note)
+                .map(|date| date.to_string())
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Group {
+    /// Value the notes share, `None` for notes without one
+    pub key: Option<String>,
+    pub count: usize,
+    /// Paths of the notes, left out when only counting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<Vec<String>>,
+}
+
+/// `notes` grouped by `key`, largest group first. A note is in as many groups as it has
+/// values (several tags, say).
+pub fn group(notes: &[&Note], key: &GroupKey, count_only: bool) -> Vec<Group> {
+    let mut grouped: BTreeMap<Option<String>, Vec<String>> = BTreeMap::new();
+    for note in notes {
+        let path = note.path.to_string_lossy().replace('\\', "/");
+        let mut values: Vec<Option<String>> = key.values(note).into_iter().map(Some).collect();
+        values.sort();
+        values.dedup();
+        if values.is_empty() {
+            values.push(None);
+        }
+        for value in values {
+            grouped.entry(value).or_default().push(path.clone());
+        }
+    }
+    let mut groups: Vec<Group> = grouped
+        .into_iter()
+        .map(|(key, paths)| Group {
+            key,
+            count: paths.len(),
+            notes: (!count_only).then_some(paths),
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.count.cmp(&a.count).then_with(|| match (&a.key, &b.key) {
+            (Some(a), Some(b)) => collation::compare(a, b),
+            // Notes without a value come last among groups of their size
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        })
+    });
+    groups
+}
+
+pub fn print_groups(groups: &[Group], format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(groups)?),
+        OutputFormat::Ndjson => {
+            for group in groups {
+                util::print_ndjson(group)?;
+            }
+        }
+        OutputFormat::Text => {
+            for group in groups {
+                let key = group.key.as_deref().unwrap_or("(none)");
+                match &group.notes {
+                    None => println!("{:>6}  {}", group.count, key),
+                    Some(notes) => {
+                        println!("{} ({})", key, group.count);
+                        for path in notes {
+                            println!("  {}", path);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_group() {
+        let notes = [
+            Note::from_content(
+                PathBuf::from("Projects/A.md"),
+                String::from("---\nclient: [ACME, Initech]\n---\n#work #rust\n"),
+            ),
+            Note::from_content(PathBuf::from("Projects/B.md"), String::from("#work\n")),
+            Note::from_content(
+                PathBuf::from("C.md"),
+                String::from("---\nclient: ACME\n---\n"),
+            ),
+        ];
+        let notes: Vec<&Note> = notes.iter().collect();
+
+        let by_tag = group(&notes, &GroupKey::parse("tag").unwrap(), true);
+        let counts: Vec<(Option<&str>, usize)> =
+            by_tag.iter().map(|g| (g.key.as_deref(), g.count)).collect();
+        assert_eq!(counts, [(Some("work"), 2), (Some("rust"), 1), (None, 1)]);
+        assert!(by_tag[0].notes.is_none());
+
+        let by_client = group(
+            &notes,
+            &GroupKey::parse("frontmatter.client").unwrap(),
+            false,
+        );
+        assert_eq!(by_client[0].key.as_deref(), Some("ACME"));
+        assert_eq!(
+            by_client[0].notes.as_deref(),
+            Some(&[String::from("Projects/A.md"), String::from("C.md")][..])
+        );
+        let by_folder = group(&notes, &GroupKey::Folder, true);
+        assert_eq!(by_folder[1].key.as_deref(), Some("/"));
+        assert!(GroupKey::parse("colour").is_err());
+    }
+}
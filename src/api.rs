@@ -0,0 +1,802 @@
+use crate::aggregate::{self, GroupKey};
+use crate::auth;
+use crate::block_ref;
+use crate::bookmarks::Bookmarks;
+use crate::calendar;
+use crate::capture;
+use crate::cli::SearchMode;
+#[cfg(feature = "web")]
+use crate::clip;
+use crate::completions::{self, Candidate, NoteCandidates};
+use crate::config::{AppConfig, ServerConfig};
+use crate::cors;
+use crate::couch;
+use crate::data;
+#[cfg(feature = "graphql")]
+use crate::graphql;
+use crate::http::{self, Request, Response};
+#[cfg(feature = "images")]
+use crate::images;
+use crate::index;
+use crate::listing::{self, NoteEntry, Page, SortKey, TagEntry};
+use crate::markdown;
+#[cfg(feature = "web")]
+use crate::previews;
+use crate::query::Query;
+use crate::recency;
+use crate::render;
+use crate::resolver::{self, Resolver, TitleIndex};
+use crate::review;
+use crate::search;
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::util;
+#[cfg(feature = "web-ui")]
+use crate::web_ui;
+use crate::write_gate;
+use crate::writing;
+
+use clap::ValueEnum;
+use jiff::Zoned;
+use serde::{Deserialize, Serialize};
+use sqlite::Connection;
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// What request handlers need to reach the vault
+#[derive(Debug, Clone)]
+pub struct ApiState {
+    pub vault_path: PathBuf,
+    pub config: AppConfig,
+    /// Notes for routes that scan every note, re-read as they change
+    pub notes: Arc<Mutex<data::NoteCache>>,
+    /// What the daemon found changed in the vault when it started
+    pub startup_changes: Option<Arc<index::IndexStats>>,
+    /// Keys requests must carry; empty when the server is open
+    pub keys: Arc<auth::Keys>,
+}
+
+impl ApiState {
+    pub fn new(vault_path: PathBuf, config: AppConfig) -> ApiState {
+        ApiState {
+            vault_path,
+            config,
+            notes: Arc::default(),
+            startup_changes: None,
+            keys: Arc::default(),
+        }
+    }
+}
+
+pub fn handle(state: &ApiState, request: &Request) -> Response {
+    let keyed = !state.keys.is_empty();
+    if let Some(response) = cors::preflight(&state.config.server, keyed, request) {
+        return response;
+    }
+    // Assets load without an API key: a page asks for the key once it is running
+    let mut response = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", path) if path.starts_with("/static/") => get_static(state, &path[8..]),
+        #[cfg(feature = "web-ui")]
+        ("GET", path) if path == "/ui" || path.starts_with("/ui/") => web_ui::get(&path[3..]),
+        _ => match auth::authorize(&state.keys, request) {
+            Ok(()) => route(state, request),
+            Err(response) => response,
+        },
+    };
+    cors::add_headers(&state.config.server, keyed, request, &mut response);
+    response
+}
+
+fn route(state: &ApiState, request: &Request) -> Response {
+    if state.config.server.couchdb
+        && let Some(rest) = request.path.strip_prefix("/couchdb")
+        && (rest.is_empty() || rest.starts_with('/'))
+    {
+        return couch::handle(state, request, rest);
+    }
+    #[cfg(feature = "graphql")]
+    if request.path == "/graphql" || request.path.starts_with("/graphql/") {
+        return graphql::handle(state, request);
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/capture") => post_capture(state, request),
+        #[cfg(feature = "web")]
+        ("POST", "/clip") => post_clip(state, request),
+        ("POST", "/block-ref") => post_block_ref(state, request),
+        ("POST", "/open") => post_open(state, request),
+        ("GET", "/notes") => get_notes(state, request),
+        ("GET", "/search") => get_search(state, request),
+        ("GET", "/backlinks") => get_backlinks(state, request),
+        ("GET", "/tags") => get_tags(state, request),
+        ("GET", "/groups") => get_groups(state, request),
+        ("GET", "/titles") => get_titles(state, request),
+        ("GET", "/note") => get_note(state, request),
+        ("GET", "/graph") => get_graph(state),
+        ("GET", "/bookmarks") => get_bookmarks(state, request),
+        ("GET", "/complete/notes") => get_completions(state, request, completions::notes),
+        ("GET", "/complete/tags") => get_completions(state, request, completions::tags),
+        ("GET", "/complete/headings") => {
+            get_note_completions(state, request, completions::headings)
+        }
+        ("GET", "/complete/blocks") => get_note_completions(state, request, completions::blocks),
+        ("GET", "/similar") => get_similar(state, request),
+        ("GET", "/metadata") => get_metadata(state, request),
+        ("GET", "/changes") => get_changes(state),
+        ("GET", "/calendar.ics") => get_calendar(state, request),
+        #[cfg(feature = "web")]
+        ("GET", "/preview") => get_preview(state, request),
+        #[cfg(feature = "images")]
+        ("GET", "/thumbnail") => get_thumbnail(state, request),
+        ("GET", "/review/due") => get_review_due(state, request),
+        ("POST", "/review/grade") => post_review_grade(state, request),
+        ("GET", "/writing") => get_writing(state, request),
+        _ => Response::not_found(),
+    }
+}
+
+/// `GET /static/<path>`: a file of `[server] static_dir`, served without an API key so
+/// that browsers can load a front end before it has one
+fn get_static(state: &ApiState, path: &str) -> Response {
+    let Some(root) = &state.config.server.static_dir else {
+        return Response::not_found();
+    };
+    let root = util::expand_tilde(root).map_or_else(|| root.clone(), |root| root.into_owned());
+    let mut file = match path.trim_end_matches('/') {
+        "" => root,
+        path => {
+            if capture::ensure_inside_vault(Path::new(path)).is_err() {
+                return Response::not_found();
+            }
+            root.join(path)
+        }
+    };
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    match std::fs::read(&file) {
+        Ok(body) => Response {
+            status: 200,
+            content_type: String::from(http::content_type_of(&file)),
+            headers: vec![(String::from("Cache-Control"), String::from("no-cache"))],
+            body,
+        },
+        Err(_) => Response::not_found(),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct CaptureBody {
+    text: String,
+    to: Option<String>,
+    #[serde(default)]
+    daily: bool,
+}
+
+/// `POST /capture` with a JSON body `{"text", "to"?, "daily"?}` or plain text
+/// (with `to`/`daily` as query parameters).
+fn post_capture(state: &ApiState, request: &Request) -> Response {
+    if let Err(e) = write_gate::check("capture notes") {
+        return Response::error(403, &e.to_string());
+    }
+    let body = if request.is_json() {
+        match serde_json::from_slice::<CaptureBody>(&request.body) {
+            Ok(body) => body,
+            Err(e) => return Response::error(400, &format!("Invalid capture body: {}", e)),
+        }
+    } else {
+        CaptureBody {
+            text: request.body_text(),
+            to: request.query.get("to").cloned(),
+            daily: request.query.get("daily").is_some_and(|v| v == "true"),
+        }
+    };
+    if body.text.trim().is_empty() {
+        return Response::error(400, "Nothing to capture");
+    }
+
+    match capture::capture(
+        &state.vault_path,
+        &state.config,
+        &body.text,
+        body.to.as_deref(),
+        body.daily,
+    ) {
+        Ok(path) => Response::json(201, &serde_json::json!({ "path": path })),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+#[cfg(feature = "web")]
+#[derive(Deserialize, Debug)]
+struct ClipBody {
+    url: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// `POST /clip` with a JSON body `{"url", "tags"?}`
+#[cfg(feature = "web")]
+fn post_clip(state: &ApiState, request: &Request) -> Response {
+    if let Err(e) = write_gate::check("clip pages") {
+        return Response::error(403, &e.to_string());
+    }
+    let body = match serde_json::from_slice::<ClipBody>(&request.body) {
+        Ok(body) => body,
+        Err(e) => return Response::error(400, &format!("Invalid clip body: {}", e)),
+    };
+    if let Err(e) = clip::parse_source_url(&body.url) {
+        return Response::error(400, &e.to_string());
+    }
+
+    match clip::clip(&state.vault_path, &state.config, &body.url, &body.tags) {
+        Ok(path) => Response::json(201, &serde_json::json!({ "path": path })),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct BlockRefBody {
+    note: String,
+    line: usize,
+}
+
+/// `POST /block-ref` with a JSON body `{"note", "line"}`; responds with the block's id and link
+fn post_block_ref(state: &ApiState, request: &Request) -> Response {
+    if let Err(e) = write_gate::check("add block ids") {
+        return Response::error(403, &e.to_string());
+    }
+    let body = match serde_json::from_slice::<BlockRefBody>(&request.body) {
+        Ok(body) => body,
+        Err(e) => return Response::error(400, &format!("Invalid block-ref body: {}", e)),
+    };
+    match block_ref::block_ref(&state.vault_path, &state.config, &body.note, body.line) {
+        Ok(block) => Response::json(200, &block),
+        Err(e) => Response::error(400, &e.to_string()),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenBody {
+    note: String,
+}
+
+/// `POST /open` with a JSON body `{"note"}`: an editor opened the note, for `recent`
+fn post_open(state: &ApiState, request: &Request) -> Response {
+    let body = match serde_json::from_slice::<OpenBody>(&request.body) {
+        Ok(body) => body,
+        Err(e) => return Response::error(400, &format!("Invalid open body: {}", e)),
+    };
+    let path = match resolve_note(state, &body.note) {
+        Ok(Some(path)) => path,
+        Ok(None) => return Response::not_found(),
+        Err(response) => return response,
+    };
+    let now = jiff::Timestamp::now().as_millisecond();
+    match recency::open(&state.config).and_then(|connection| {
+        Ok(recency::record(
+            &connection,
+            &path,
+            recency::Visit::Open,
+            now,
+        )?)
+    }) {
+        Ok(()) => Response::json(200, &serde_json::json!({ "path": path })),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// Vault-relative path of the note `note` resolves to
+fn resolve_note(state: &ApiState, note: &str) -> Result<Option<String>, Response> {
+    match Resolver::from_vault(&state.vault_path) {
+        Ok(resolver) => Ok(resolver
+            .resolve(note, Path::new(""))
+            .map(|path| path.to_string_lossy().replace('\\', "/"))),
+        Err(e) => Err(Response::error(500, &e.to_string())),
+    }
+}
+
+/// Responds with one page of `items`; see `listing::paginate`.
+fn listed<T: Serialize>(
+    items: Result<Vec<T>, Box<dyn Error>>,
+    page: &Page,
+    field: impl Fn(SortKey) -> Option<&'static str>,
+) -> Response {
+    if page.sort_by != SortKey::Rank && field(page.sort_by).is_none() {
+        return Response::error(
+            400,
+            &format!("Cannot sort by {:?}", page.sort_by).to_lowercase(),
+        );
+    }
+    match items.and_then(|items| listing::paginate(&items, page, field)) {
+        Ok(listing) => Response::json(200, &listing),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// Paging parameters of a list request, or the response refusing them
+fn page_of(request: &Request) -> Result<Page, Response> {
+    Page::from_query(&request.query).map_err(|e| Response::error(400, &e.to_string()))
+}
+
+/// `GET /notes?query=<query>`: the notes matching `query` (all without one)
+fn get_notes(state: &ApiState, request: &Request) -> Response {
+    let page = match page_of(request) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let query = match Query::parse(request.query.get("query").map_or("", String::as_str)) {
+        Ok(query) => query,
+        Err(e) => return Response::error(400, &e.to_string()),
+    };
+    let entries = data::load_notes(&state.vault_path).map(|notes| {
+        query
+            .filter(&notes)
+            .into_iter()
+            .map(NoteEntry::new)
+            .collect::<Vec<_>>()
+    });
+    listed(entries, &page, SortKey::note_field)
+}
+
+/// `GET /search?q=<words>&mode=keyword|semantic|hybrid&snippet_words=&snippets=`: best
+/// matching notes first, with the byte ranges of the matches in each snippet. `total`
+/// counts the hits ranked to fill the page, not every match.
+fn get_search(state: &ApiState, request: &Request) -> Response {
+    let page = match page_of(request) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let Some(query) = request.query.get("q") else {
+        return Response::error(400, "Missing 'q' parameter");
+    };
+    let mode = match request.query.get("mode") {
+        Some(mode) => match SearchMode::from_str(mode, true) {
+            Ok(mode) => Some(mode),
+            Err(e) => return Response::error(400, &e),
+        },
+        None => None,
+    };
+    let mut snippets = state.config.search.snippet_options();
+    for (name, value) in [
+        ("snippet_words", &mut snippets.words),
+        ("snippets", &mut snippets.count),
+    ] {
+        if let Some(given) = request.query.get(name) {
+            match given.parse() {
+                Ok(given) => *value = given,
+                Err(_) => return Response::error(400, &format!("Invalid '{}' parameter", name)),
+            }
+        }
+    }
+    let hits = search::search(
+        &state.vault_path,
+        &state.config,
+        query,
+        mode,
+        page.window(),
+        snippets,
+    );
+    listed(hits, &page, |_| None)
+}
+
+/// `GET /backlinks?note=<path or link>`: lines linking to the note
+fn get_backlinks(state: &ApiState, request: &Request) -> Response {
+    let page = match page_of(request) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let Some(note) = request.query.get("note") else {
+        return Response::error(400, "Missing 'note' parameter");
+    };
+    let path = match resolve_note(state, note) {
+        Ok(Some(path)) => path,
+        Ok(None) => return Response::not_found(),
+        Err(response) => return response,
+    };
+    let found = data::load_notes(&state.vault_path).map(|notes| {
+        let resolver = Resolver::new(notes.iter().map(|note| note.path.clone()).collect());
+        resolver::backlinks(&notes, &resolver, Path::new(&path))
+    });
+    listed(found, &page, |_| None)
+}
+
+/// `GET /note?note=<path or link>`: the note's text and its body rendered to HTML, with
+/// links to other notes pointing at `/note?note=<path>`
+fn get_note(state: &ApiState, request: &Request) -> Response {
+    let Some(note) = request.query.get("note") else {
+        return Response::error(400, "Missing 'note' parameter");
+    };
+    let path = match resolve_note(state, note) {
+        Ok(Some(path)) => path,
+        Ok(None) => return Response::not_found(),
+        Err(response) => return response,
+    };
+    let notes = match data::load_notes(&state.vault_path) {
+        Ok(notes) => notes,
+        Err(e) => return Response::error(500, &e.to_string()),
+    };
+    let Some(note) = notes.iter().find(|note| note.path == Path::new(&path)) else {
+        return Response::not_found();
+    };
+    let resolver = Resolver::new(notes.iter().map(|note| note.path.clone()).collect());
+    let href = |target: &Path| {
+        data::is_note(target).then(|| {
+            let target = target.to_string_lossy().replace('\\', "/");
+            format!("/note?note={}", util::percent_encode(&target))
+        })
+    };
+    Response::json(
+        200,
+        &serde_json::json!({
+            "path": path,
+            "title": note.title(),
+            "tags": note.tags,
+            "content": note.content,
+            "html": render::safe_note_html(note, &resolver, href),
+        }),
+    )
+}
+
+/// `GET /graph`: every note and the links between them, as `{"nodes", "links"}` with
+/// each link a `{"source", "target"}` pair of paths
+fn get_graph(state: &ApiState) -> Response {
+    let notes = match data::load_notes(&state.vault_path) {
+        Ok(notes) => notes,
+        Err(e) => return Response::error(500, &e.to_string()),
+    };
+    let resolver = Resolver::new(notes.iter().map(|note| note.path.clone()).collect());
+    let key = |path: &Path| path.to_string_lossy().replace('\\', "/");
+    let nodes: Vec<serde_json::Value> = notes
+        .iter()
+        .map(|note| serde_json::json!({ "id": key(&note.path), "title": note.title() }))
+        .collect();
+    let mut links = BTreeSet::new();
+    for note in &notes {
+        for link in markdown::parse_links(&note.content) {
+            if link.is_external() || link.target.is_empty() {
+                continue;
+            }
+            if let Some(target) = resolver.resolve(&link.target, &note.path)
+                && data::is_note(target)
+                && target != note.path
+            {
+                links.insert((key(&note.path), key(target)));
+            }
+        }
+    }
+    let links: Vec<serde_json::Value> = links
+        .into_iter()
+        .map(|(source, target)| serde_json::json!({ "source": source, "target": target }))
+        .collect();
+    Response::json(200, &serde_json::json!({ "nodes": nodes, "links": links }))
+}
+
+/// `GET /titles?title=`: notes whose front matter title, alias or file name is `title`
+/// (more than one means `[[title]]` is ambiguous) and the note a link to it would open
+fn get_titles(state: &ApiState, request: &Request) -> Response {
+    let Some(title) = request.query.get("title") else {
+        return Response::error(400, "Missing 'title' parameter");
+    };
+    match data::load_notes(&state.vault_path) {
+        Ok(notes) => {
+            let titles = TitleIndex::new(&notes);
+            let resolver = Resolver::new(notes.iter().map(|note| note.path.clone()).collect());
+            Response::json(
+                200,
+                &serde_json::json!({
+                    "title": title,
+                    "notes": titles.lookup(title),
+                    "resolved": resolver.resolve_title(&titles, title),
+                }),
+            )
+        }
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// Completion candidates from the index, which the daemon keeps current; `prefix` and
+/// `limit` (default 50) come from the query
+fn complete(
+    state: &ApiState,
+    request: &Request,
+    candidates: impl FnOnce(&Connection, &str, usize) -> Result<Vec<Candidate>, sqlite::Error>,
+) -> Response {
+    let limit = match request
+        .query
+        .get("limit")
+        .map(|limit| limit.parse::<usize>())
+    {
+        None => 50,
+        Some(Ok(limit)) => limit,
+        Some(Err(_)) => return Response::error(400, "Invalid 'limit' parameter"),
+    };
+    let prefix = request
+        .query
+        .get("prefix")
+        .map(String::as_str)
+        .unwrap_or_default();
+    let found = index::open(&state.config)
+        .and_then(|connection| Ok(candidates(&connection, prefix, limit)?));
+    match found {
+        Ok(found) => Response::json(200, &found),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// `GET /complete/notes` and `/complete/tags`
+fn get_completions(
+    state: &ApiState,
+    request: &Request,
+    candidates: fn(&Connection, &str, usize) -> Result<Vec<Candidate>, sqlite::Error>,
+) -> Response {
+    complete(state, request, candidates)
+}
+
+/// `GET /complete/headings?note=` and `/complete/blocks?note=`
+fn get_note_completions(
+    state: &ApiState,
+    request: &Request,
+    candidates: NoteCandidates,
+) -> Response {
+    let Some(note) = request.query.get("note") else {
+        return Response::error(400, "Missing 'note' parameter");
+    };
+    let path = match resolve_note(state, note) {
+        Ok(Some(path)) => PathBuf::from(path),
+        Ok(None) => return Response::not_found(),
+        Err(response) => return response,
+    };
+    complete(state, request, |connection, prefix, limit| {
+        candidates(connection, &path, prefix, limit)
+    })
+}
+
+/// `GET /tags`: every tag with the number of notes carrying it, most used first
+fn get_tags(state: &ApiState, request: &Request) -> Response {
+    let page = match page_of(request) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let tags = data::load_notes(&state.vault_path).map(|notes| listing::tag_counts(&notes));
+    listed(tags, &page, TagEntry::field)
+}
+
+/// `GET /groups?by=<key>&query=<query>&count=true`: the notes matching `query` grouped by
+/// `by` (as in `query --group-by`), largest group first; `count` leaves out the paths
+fn get_groups(state: &ApiState, request: &Request) -> Response {
+    let page = match page_of(request) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let Some(by) = request.query.get("by") else {
+        return Response::error(400, "Missing 'by' parameter");
+    };
+    let key = match GroupKey::parse(by) {
+        Ok(key) => key,
+        Err(e) => return Response::error(400, &e.to_string()),
+    };
+    let query = match Query::parse(request.query.get("query").map_or("", String::as_str)) {
+        Ok(query) => query,
+        Err(e) => return Response::error(400, &e.to_string()),
+    };
+    let count = request.query.get("count").is_some_and(|v| v == "true");
+    let groups = data::load_notes(&state.vault_path)
+        .map(|notes| aggregate::group(&query.filter(&notes), &key, count));
+    listed(groups, &page, |_| None)
+}
+
+/// `GET /bookmarks`: Obsidian's bookmarks, groups flattened
+fn get_bookmarks(state: &ApiState, request: &Request) -> Response {
+    let page = match page_of(request) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let bookmarks = Bookmarks::load(&state.vault_path).map(|bookmarks| bookmarks.entries());
+    listed(bookmarks, &page, |_| None)
+}
+
+/// `GET /similar?note=<path or link>`: related notes from the embedding index. `top` is
+/// still accepted in place of `limit`.
+fn get_similar(state: &ApiState, request: &Request) -> Response {
+    let mut page = match page_of(request) {
+        Ok(page) => page,
+        Err(response) => return response,
+    };
+    let Some(note) = request.query.get("note") else {
+        return Response::error(400, "Missing 'note' parameter");
+    };
+    match request.query.get("top").map(|top| top.parse::<usize>()) {
+        None => {}
+        Some(Ok(top)) => page.limit = top,
+        Some(Err(_)) => return Response::error(400, "'top' must be a number"),
+    }
+    let path = match resolve_note(state, note) {
+        Ok(Some(path)) => path,
+        Ok(None) => return Response::not_found(),
+        Err(response) => return response,
+    };
+
+    let neighbors = index::open(&state.config)
+        .and_then(|connection| search::similar(&connection, &state.config, &path, page.window()));
+    listed(neighbors, &page, |_| None)
+}
+
+/// `GET /metadata?note=<path or link>`: fields the plugins and scripts extracted when the
+/// note was last indexed
+fn get_metadata(state: &ApiState, request: &Request) -> Response {
+    let Some(note) = request.query.get("note") else {
+        return Response::error(400, "Missing 'note' parameter");
+    };
+    let path = match resolve_note(state, note) {
+        Ok(Some(path)) => path,
+        Ok(None) => return Response::not_found(),
+        Err(response) => return response,
+    };
+
+    let fields = index::open(&state.config)
+        .and_then(|connection| index::stored_metadata(&connection, &path));
+    match fields {
+        Ok(fields) => Response::json(200, &fields),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// `GET /changes`: notes added, modified, removed and renamed between the daemon's last
+/// run and its start
+fn get_changes(state: &ApiState) -> Response {
+    match &state.startup_changes {
+        Some(changes) => Response::json(200, changes.as_ref()),
+        None => Response::error(503, "The vault was not indexed at startup"),
+    }
+}
+
+/// `GET /calendar.ics[?query=...]`: dated notes and open tasks as an iCalendar feed
+fn get_calendar(state: &ApiState, request: &Request) -> Response {
+    let query = match Query::parse(request.query.get("query").map_or("", String::as_str)) {
+        Ok(query) => query,
+        Err(e) => return Response::error(400, &e.to_string()),
+    };
+    match calendar::vault_calendar(&state.vault_path, &query) {
+        Ok(ics) => Response {
+            status: 200,
+            content_type: String::from("text/calendar; charset=utf-8"),
+            headers: Vec::new(),
+            body: ics.into_bytes(),
+        },
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// `GET /thumbnail?path=<image>&size=<px>`: a PNG thumbnail of an image attachment
+#[cfg(feature = "images")]
+fn get_thumbnail(state: &ApiState, request: &Request) -> Response {
+    let Some(path) = request.query.get("path") else {
+        return Response::error(400, "Missing 'path' parameter");
+    };
+    let size = match request.query.get("size").map(|size| size.parse::<u32>()) {
+        Some(Ok(size)) if (1..=2048).contains(&size) => size,
+        Some(_) => return Response::error(400, "'size' must be between 1 and 2048"),
+        None => state.config.images.thumbnail_size,
+    };
+    if let Err(e) = capture::ensure_inside_vault(Path::new(path)) {
+        return Response::error(400, &e.to_string());
+    }
+    if !state.vault_path.join(path).is_file() {
+        return Response::not_found();
+    }
+    match images::thumbnail(&state.vault_path, &state.config, Path::new(path), size)
+        .and_then(|thumbnail| Ok(std::fs::read(thumbnail)?))
+    {
+        Ok(body) => Response {
+            status: 200,
+            content_type: String::from("image/png"),
+            headers: vec![(String::from("Cache-Control"), String::from("max-age=3600"))],
+            body,
+        },
+        Err(e) => Response::error(400, &e.to_string()),
+    }
+}
+
+/// `GET /preview?url=<url>`: title, description and favicon of an external page
+#[cfg(feature = "web")]
+fn get_preview(state: &ApiState, request: &Request) -> Response {
+    let Some(url) = request.query.get("url") else {
+        return Response::error(400, "Missing 'url' parameter");
+    };
+    match previews::preview(&state.vault_path, &state.config, url) {
+        Ok(Some(preview)) => Response::json(200, &preview),
+        Ok(None) => Response::not_found(),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// `GET /review/due[?query=...&limit=N]`: flashcards due today with their schedules
+fn get_review_due(state: &ApiState, request: &Request) -> Response {
+    let query = match Query::parse(request.query.get("query").map_or("", String::as_str)) {
+        Ok(query) => query,
+        Err(e) => return Response::error(400, &e.to_string()),
+    };
+    let limit = match request.query.get("limit").map(|limit| limit.parse()) {
+        None => 20,
+        Some(Ok(limit)) => limit,
+        Some(Err(_)) => return Response::error(400, "Invalid 'limit' parameter"),
+    };
+    match review::due(&state.vault_path, &state.config, &query, limit) {
+        Ok(cards) => Response::json(200, &cards),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+/// `GET /writing[?days=N]`: words written per day, the streak and each goal's progress,
+/// as of the daemon's last index refresh
+fn get_writing(state: &ApiState, request: &Request) -> Response {
+    let days = match request.query.get("days").map(|days| days.parse()) {
+        None => 7,
+        Some(Ok(days)) => days,
+        Some(Err(_)) => return Response::error(400, "Invalid 'days' parameter"),
+    };
+    let stats = index::open(&state.config).and_then(|connection| {
+        writing::stats(
+            &connection,
+            &state.config.writing,
+            Zoned::now().date(),
+            days,
+        )
+    });
+    match stats {
+        Ok(stats) => Response::json(200, &stats),
+        Err(e) => Response::error(500, &e.to_string()),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct GradeBody {
+    card: String,
+    quality: u8,
+}
+
+/// `POST /review/grade` with a JSON body `{"card", "quality"}`; responds with the card's
+/// new schedule
+fn post_review_grade(state: &ApiState, request: &Request) -> Response {
+    let body = match serde_json::from_slice::<GradeBody>(&request.body) {
+        Ok(body) => body,
+        Err(e) => return Response::error(400, &format!("Invalid grade body: {}", e)),
+    };
+    match review::grade(&state.vault_path, &state.config, &body.card, body.quality) {
+        Ok(schedule) => Response::json(200, &schedule),
+        Err(e) => Response::error(400, &e.to_string()),
+    }
+}
+
+/// TLS and rate limiting for the daemon's listeners, as `[server]` configures them.
+pub fn serve_options(config: &ServerConfig) -> Result<http::ServeOptions, Box<dyn Error>> {
+    #[cfg(not(feature = "tls"))]
+    if config.tls.is_some() {
+        return Err("Serving HTTPS needs obsidian-rs built with the `tls` feature".into());
+    }
+    Ok(http::ServeOptions {
+        #[cfg(feature = "tls")]
+        tls: config.tls.as_ref().map(tls::server_config).transpose()?,
+        rate_limit: config
+            .rate_limit
+            .map(|per_minute| Arc::new(http::RateLimiter::new(per_minute))),
+    })
+}
+
+/// Starts the HTTP API on a background thread.
+pub fn spawn_server(
+    listen: String,
+    options: http::ServeOptions,
+    state: ApiState,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = http::serve(&listen, options, move |request| handle(&state, request)) {
+            log::error!("HTTP API on {} stopped: {}", listen, e);
+        }
+    })
+}
@@ -0,0 +1,242 @@
+//! Archived copies of external links. Links that `check-links --external` found dead or
+//! redirected (or every link, with `--all`) are looked up on or submitted to the web
+//! archive, or clipped into the vault with `--local`, and the note gets an
+//! `([archived](…))` link right after the original.
+
+use crate::changeset::ChangeSet;
+use crate::check_links::{self, UrlCheck};
+use crate::cli::ArchiveLinksArgs;
+use crate::clip;
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::index;
+use crate::markdown;
+use crate::query::Query;
+
+use jiff::Timestamp;
+use sqlite::{Connection, State};
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    ops::Range,
+    path::Path,
+    time::Duration,
+};
+use url::Url;
+
+const LABEL: &str = "archived";
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS link_archives (
+            url TEXT PRIMARY KEY,
+            archive TEXT NOT NULL,
+            archived INTEGER NOT NULL
+        );",
+    )
+}
+
+fn stored(connection: &Connection) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut statement = connection.prepare("SELECT url, archive FROM link_archives")?;
+    let mut archives = HashMap::new();
+    while let State::Row = statement.next()? {
+        archives.insert(statement.read(0)?, statement.read(1)?);
+    }
+    Ok(archives)
+}
+
+fn save(connection: &Connection, url: &str, archive: &str) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO link_archives (url, archive, archived) VALUES (?, ?, ?)",
+    )?;
+    statement.bind((1, url))?;
+    statement.bind((2, archive))?;
+    statement.bind((3, Timestamp::now().as_millisecond()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Whether a check says the link is gone, or moved and so likely to go.
+fn at_risk(check: &UrlCheck) -> bool {
+    check.error.is_some() || check.status >= 300
+}
+
+/// The web links of `note` with the byte range of each, bare URLs included.
+fn external_links(note: &Note) -> Vec<(Range<usize>, String)> {
+    markdown::find_web_urls(&note.content)
+        .into_iter()
+        .filter(|url| !url.embed)
+        .map(|url| (url.span, url.url))
+        .collect()
+}
+
+/// Whether the text after a link already holds an archived copy.
+fn is_annotated(content: &str, end: usize) -> bool {
+    let rest = content[end..].trim_start_matches([' ', '\t']);
+    rest.starts_with(&format!("([{}](", LABEL))
+        || rest.starts_with("([[")
+            && rest
+                .lines()
+                .next()
+                .is_some_and(|line| line.contains(&format!("|{}]])", LABEL)))
+}
+
+/// `content` with `archives[url]` linked after each link to `url` not yet annotated.
+pub fn annotate(note: &Note, archives: &HashMap<String, String>) -> String {
+    let edits = external_links(note)
+        .into_iter()
+        .filter(|(span, _)| !is_annotated(&note.content, span.end))
+        .filter_map(|(span, url)| {
+            let archive = archives.get(&url)?;
+            let link = match archive.starts_with("http") {
+                true => format!("[{}]({})", LABEL, archive),
+                false => markdown::render_wikilink(archive, None, Some(LABEL), false),
+            };
+            Some((span.end..span.end, format!(" ({})", link)))
+        })
+        .collect();
+    markdown::replace_spans(&note.content, edits)
+}
+
+/// Requests `url` following redirects, returning where it ended up if that is a snapshot.
+fn snapshot(agent: &ureq::Agent, url: &str) -> Option<String> {
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(e) => {
+            log::debug!("{}: {}", url, e);
+            return None;
+        }
+    };
+    let final_url = Url::parse(response.get_url()).ok()?;
+    let snapshot = match response.header("Content-Location") {
+        Some(location) => final_url.join(location).ok()?,
+        None => final_url,
+    };
+    let timestamp = snapshot.path().strip_prefix("/web/")?;
+    timestamp
+        .starts_with(|c: char| c.is_ascii_digit())
+        .then(|| snapshot.to_string())
+}
+
+/// The web archive's copy of `url`: the latest snapshot of a dead page, or a fresh one of
+/// a page that is still up.
+fn web_archive(agent: &ureq::Agent, service: &str, url: &str, dead: bool) -> Option<String> {
+    let service = service.trim_end_matches('/');
+    let saved = match dead {
+        true => None,
+        false => snapshot(agent, &format!("{}/save/{}", service, url)),
+    };
+    saved.or_else(|| snapshot(agent, &format!("{}/web/{}", service, url)))
+}
+
+pub fn run_archive_links(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &ArchiveLinksArgs,
+) -> Result<(), Box<dyn Error>> {
+    if args.local && args.changes.dry_run {
+        return Err("--local saves clippings, so it cannot be combined with --dry-run".into());
+    }
+    let notes = data::load_notes(vault_path)?;
+    let notes = Query::parse(args.query.as_deref().unwrap_or(""))?.filter(&notes);
+
+    let connection = index::open(config)?;
+    check_links::ensure_schema(&connection)?;
+    ensure_schema(&connection)?;
+    let checks = check_links::cached_checks(&connection)?;
+    let mut archives = stored(&connection)?;
+
+    let service = config.archive.service.trim_end_matches('/');
+    let urls: BTreeSet<String> = notes
+        .iter()
+        .flat_map(|note| external_links(note))
+        .map(|(_, url)| url)
+        .filter(|url| !url.starts_with(service))
+        .filter(|url| args.all || checks.get(url).is_some_and(at_risk))
+        .collect();
+    if urls.is_empty() && checks.is_empty() && !args.all {
+        log::info!("No checked links; run `check-links --external` first, or pass --all");
+    }
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(config.archive.timeout_secs))
+        .user_agent(concat!("obsidian-rs/", env!("CARGO_PKG_VERSION")))
+        .build();
+    let mut archived = 0;
+    for url in &urls {
+        if archives.contains_key(url) {
+            continue;
+        }
+        let dead = checks
+            .get(url)
+            .is_some_and(|check| check.error.is_some() || check.status >= 400);
+        let archive = match args.local {
+            true if dead => {
+                log::warn!("{} is dead, so it cannot be clipped", url);
+                None
+            }
+            true => match clip::clip(vault_path, config, url, &[]) {
+                Ok(path) => Some(path.with_extension("").to_string_lossy().replace('\\', "/")),
+                Err(e) => {
+                    log::warn!("Could not clip {}: {}", url, e);
+                    None
+                }
+            },
+            false => web_archive(&agent, service, url, dead),
+        };
+        match archive {
+            Some(archive) => {
+                log::info!("Archived {} as {}", url, archive);
+                if !args.changes.dry_run {
+                    save(&connection, url, &archive)?;
+                }
+                archives.insert(url.clone(), archive);
+                archived += 1;
+            }
+            None if !args.local => log::warn!("The web archive has no copy of {}", url),
+            None => {}
+        }
+    }
+    log::info!("Archived {} of {} link(s)", archived, urls.len());
+
+    archives.retain(|url, _| urls.contains(url));
+    let mut changes = ChangeSet::new();
+    for note in notes {
+        let annotated = annotate(note, &archives);
+        if annotated != note.content {
+            changes.propose(note.path.clone(), Some(note.content.clone()), annotated);
+        }
+    }
+    changes.finish(vault_path, &args.changes, "annotate archived links", false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_annotate() {
+        let note = Note::from_content(
+            PathBuf::from("Links.md"),
+            String::from(
+                "[Old](https://a.org/x) and https://b.org/y.\n\
+                 [Done](https://a.org/x) ([archived](https://web.archive.org/web/1/https://a.org/x))\n",
+            ),
+        );
+        let archives = HashMap::from([
+            (
+                String::from("https://a.org/x"),
+                String::from("https://web.archive.org/web/1/https://a.org/x"),
+            ),
+            (String::from("https://b.org/y"), String::from("Clippings/Y")),
+        ]);
+        assert_eq!(
+            annotate(&note, &archives),
+            "[Old](https://a.org/x) ([archived](https://web.archive.org/web/1/https://a.org/x)) \
+             and https://b.org/y ([[Clippings/Y|archived]]).\n\
+             [Done](https://a.org/x) ([archived](https://web.archive.org/web/1/https://a.org/x))\n"
+        );
+    }
+}
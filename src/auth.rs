@@ -0,0 +1,143 @@
+//! API keys for the HTTP server. Keys are read from the environment variables named in
+//! `[[server.keys]]` and sent by clients as `Authorization: Bearer <key>`; a `read` key
+//! only reaches routes that leave the vault alone. With no keys configured, every
+//! request is let through, so the daemon then only serves loopback addresses.
+
+use crate::config::{Scope, ServerConfig};
+use crate::http::{Request, Response};
+
+use std::{env, error::Error};
+
+/// POST routes that only read, mostly those CouchDB replication pulls through
+const READ_ONLY_POSTS: [&str; 6] = [
+    "/_changes",
+    "/_revs_diff",
+    "/_bulk_get",
+    "/_all_docs",
+    "/_ensure_full_commit",
+    "/graphql",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Keys {
+    keys: Vec<(String, Scope)>,
+}
+
+impl Keys {
+    /// The configured keys; an unset or empty variable is an error rather than a key
+    /// that nobody can match.
+    pub fn load(config: &ServerConfig) -> Result<Keys, Box<dyn Error>> {
+        let mut keys = Vec::new();
+        for key in &config.keys {
+            match env::var(&key.env) {
+                Ok(value) if !value.is_empty() => keys.push((value, key.scope)),
+                _ => return Err(format!("${} holds no API key", key.env).into()),
+            }
+        }
+        Ok(Keys { keys })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The scope of the key `presented` matches, if any.
+    pub fn scope_of(&self, presented: &str) -> Option<Scope> {
+        self.keys
+            .iter()
+            .filter(|(key, _)| constant_time_eq(key.as_bytes(), presented.as_bytes()))
+            .map(|(_, scope)| *scope)
+            .max()
+    }
+}
+
+/// Compares without returning early, so timing does not reveal how much of a key matched.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The scope a request needs: reading for GET and the read-only POST routes.
+pub fn required_scope(request: &Request) -> Scope {
+    match request.method.as_str() {
+        "GET" | "HEAD" | "OPTIONS" => Scope::Read,
+        "POST"
+            if READ_ONLY_POSTS
+                .iter()
+                .any(|route| request.path.ends_with(route)) =>
+        {
+            Scope::Read
+        }
+        _ => Scope::Write,
+    }
+}
+
+/// The scope of the bearer key the request carries, if it matches one of `keys`.
+pub fn presented_scope(keys: &Keys, request: &Request) -> Option<Scope> {
+    let presented = request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)?;
+    keys.scope_of(presented)
+}
+
+/// Checks the request's bearer key, answering 401 without a known key and 403 when the
+/// key's scope is too narrow.
+pub fn authorize(keys: &Keys, request: &Request) -> Result<(), Response> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let Some(scope) = presented_scope(keys, request) else {
+        let mut response = Response::error(401, "Missing or unknown API key");
+        response
+            .headers
+            .push((String::from("WWW-Authenticate"), String::from("Bearer")));
+        return Err(response);
+    };
+    if scope < required_scope(request) {
+        return Err(Response::error(403, "This API key may only read"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, key: Option<&str>) -> Request {
+        let mut request = Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            ..Request::default()
+        };
+        if let Some(key) = key {
+            request
+                .headers
+                .insert(String::from("authorization"), format!("Bearer {}", key));
+        }
+        request
+    }
+
+    #[test]
+    fn test_authorize() {
+        let keys = Keys {
+            keys: vec![
+                (String::from("reader"), Scope::Read),
+                (String::from("writer"), Scope::Write),
+            ],
+        };
+        let status = |request: Request| authorize(&keys, &request).err().map(|r| r.status);
+        assert_eq!(status(request("GET", "/notes", None)), Some(401));
+        assert_eq!(status(request("GET", "/notes", Some("nope"))), Some(401));
+        assert_eq!(status(request("GET", "/notes", Some("reader"))), None);
+        assert_eq!(
+            status(request("POST", "/capture", Some("reader"))),
+            Some(403)
+        );
+        assert_eq!(
+            status(request("POST", "/couchdb/notes/_bulk_get", Some("reader"))),
+            None
+        );
+        assert_eq!(status(request("POST", "/capture", Some("writer"))), None);
+        assert!(authorize(&Keys::default(), &request("POST", "/capture", None)).is_ok());
+    }
+}
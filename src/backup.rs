@@ -0,0 +1,379 @@
+//! Zip archives of the whole vault, written by `backup` jobs. Archives are named after
+//! the vault and the time they were taken, so sorting their names sorts them by age. With
+//! `[backup] recipient` set they are encrypted with age and end in `.zip.age`.
+
+use crate::config::BackupConfig;
+use crate::util;
+
+use jiff::Zoned;
+use std::{
+    error::Error,
+    fs,
+    io::{self, Seek, Write},
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+/// Folders never archived: version control keeps its own history
+const SKIPPED_DIRS: [&str; 1] = [".git"];
+
+#[cfg(not(feature = "backup-encrypt"))]
+const NO_ENCRYPTION: &str =
+    "[backup] recipient is set, but this build lacks the `backup-encrypt` feature";
+
+#[cfg(feature = "backup-encrypt")]
+fn encryptor(recipient: &str) -> Result<age::Encryptor, Box<dyn Error>> {
+    let recipient: age::x25519::Recipient = recipient
+        .parse()
+        .map_err(|e| format!("Invalid [backup] recipient: {}", e))?;
+    let recipients: Vec<Box<dyn age::Recipient + Send>> = vec![Box::new(recipient)];
+    Ok(age::Encryptor::with_recipients(recipients).ok_or("No recipient to encrypt to")?)
+}
+
+/// `plain` encrypted to the age `recipient`, for `age --decrypt -i <key file>` to read.
+#[cfg(feature = "backup-encrypt")]
+pub fn encrypt(plain: &[u8], recipient: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut sealed = Vec::new();
+    let mut writer = encryptor(recipient)?.wrap_output(&mut sealed)?;
+    writer.write_all(plain)?;
+    writer.finish()?;
+    Ok(sealed)
+}
+
+#[cfg(not(feature = "backup-encrypt"))]
+pub fn encrypt(_plain: &[u8], _recipient: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    Err(NO_ENCRYPTION.into())
+}
+
+/// A fresh secret of 256 random bits, for keys that must not be guessable.
+#[cfg(feature = "backup-encrypt")]
+pub fn random_secret() -> Result<String, Box<dyn Error>> {
+    use age::secrecy::ExposeSecret;
+    Ok(age::x25519::Identity::generate()
+        .to_string()
+        .expose_secret()
+        .clone())
+}
+
+#[cfg(not(feature = "backup-encrypt"))]
+pub fn random_secret() -> Result<String, Box<dyn Error>> {
+    Err(NO_ENCRYPTION.into())
+}
+
+/// Lets `ZipWriter`, which seeks back to patch each entry's header once its data is
+/// written, write into a stream that cannot seek: the entry being written is held in
+/// memory and passed on once its header has been patched.
+#[cfg(any(test, feature = "backup-encrypt"))]
+struct Spool<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    /// Offset of `buffer[0]` in the archive
+    base: u64,
+    /// Offset the next write goes to
+    position: u64,
+    /// Whether the writer went back into the buffer since it was last passed on
+    patched: bool,
+}
+
+#[cfg(any(test, feature = "backup-encrypt"))]
+impl<W: Write> Spool<W> {
+    fn new(inner: W) -> Spool<W> {
+        Spool {
+            inner,
+            buffer: Vec::new(),
+            base: 0,
+            position: 0,
+            patched: false,
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.base + self.buffer.len() as u64
+    }
+
+    fn pass_on(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.buffer)?;
+        self.base += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.pass_on()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(any(test, feature = "backup-encrypt"))]
+impl<W: Write> Write for Spool<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let start = (self.position - self.base) as usize;
+        let overlap = data.len().min(self.buffer.len() - start);
+        self.buffer[start..start + overlap].copy_from_slice(&data[..overlap]);
+        self.buffer.extend_from_slice(&data[overlap..]);
+        self.position += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(any(test, feature = "backup-encrypt"))]
+impl<W: Write> Seek for Spool<W> {
+    fn seek(&mut self, to: io::SeekFrom) -> io::Result<u64> {
+        let end = self.end();
+        let target = match to {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::End(offset) => end.checked_add_signed(offset),
+            io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        let Some(target) = target.filter(|target| (self.base..=end).contains(target)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot seek outside the entry being written",
+            ));
+        };
+        if target < end {
+            self.patched = true;
+        } else if self.patched {
+            // Back at the end after patching a header: the entry is final
+            self.patched = false;
+            self.pass_on()?;
+        }
+        self.position = target;
+        Ok(target)
+    }
+}
+
+/// Every file of the vault that backups include, by name.
+pub fn vault_files(vault_path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    let walker = WalkDir::new(vault_path).sort_by_file_name().into_iter();
+    for entry in walker.filter_entry(|e| {
+        !e.file_type().is_dir() || !SKIPPED_DIRS.iter().any(|dir| e.file_name() == *dir)
+    }) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+/// The vault name and timestamp of an archive named `<vault>-<timestamp>.zip` or
+/// `.zip.age`, as [`run_backup`] names them.
+fn archive_name(name: &str) -> Option<(&str, &str)> {
+    let stem = name
+        .strip_suffix(".zip.age")
+        .or_else(|| name.strip_suffix(".zip"))?;
+    let (vault, stamp) = stem.rsplit_once('-')?;
+    let is_stamp = stamp.len() == 15
+        && stamp.char_indices().all(|(i, c)| match i {
+            8 => c == 'T',
+            _ => c.is_ascii_digit(),
+        });
+    is_stamp.then_some((vault, stamp))
+}
+
+/// Whether `file` belongs to the backups rather than the vault: the archive `out` being
+/// written, earlier archives next to it, and anything else in its folder when that is a
+/// subfolder of the vault. All paths are canonical.
+fn is_backup(file: &Path, out: &Path, vault_path: &Path) -> bool {
+    let Some(dir) = out.parent() else {
+        return file == out;
+    };
+    let archive = file.parent() == Some(dir)
+        && file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| archive_name(name).is_some());
+    let subfolder = dir != vault_path && dir.starts_with(vault_path);
+    file == out || archive || (subfolder && file.starts_with(dir))
+}
+
+/// Writes every file of the vault as a zip archive into `writer`, leaving out the
+/// backups next to `out`, and returns the writer and how many files there were.
+fn archive_into<W: Write + Seek>(
+    vault_path: &Path,
+    writer: W,
+    out: &Path,
+) -> Result<(W, usize), Box<dyn Error>> {
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let canonical_vault = vault_path.canonicalize()?;
+    let mut files = 0;
+    for file in vault_files(vault_path)? {
+        if is_backup(&file.canonicalize()?, out, &canonical_vault) {
+            continue;
+        }
+        let rel_path = util::get_relative_path(&file, vault_path)?;
+        zip.start_file(rel_path.to_string_lossy().replace('\\', "/"), options)?;
+        io::copy(&mut fs::File::open(&file)?, &mut zip)?;
+        files += 1;
+    }
+    Ok((zip.finish()?, files))
+}
+
+/// Writes every file of the vault into the zip archive `out`, returning how many there
+/// were. Backups are left out when `out` lies inside the vault.
+pub fn write_archive(vault_path: &Path, out: &Path) -> Result<usize, Box<dyn Error>> {
+    let file = fs::File::create(out)?;
+    let (_, files) = archive_into(vault_path, file, &out.canonicalize()?)?;
+    Ok(files)
+}
+
+/// Like [`write_archive`], but encrypts the archive to `recipient` as it is written, so
+/// no plain copy of the vault ever reaches the disk.
+#[cfg(feature = "backup-encrypt")]
+pub fn write_sealed_archive(
+    vault_path: &Path,
+    out: &Path,
+    recipient: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let encryptor = encryptor(recipient)?;
+    let file = fs::File::create(out)?;
+    let out = out.canonicalize()?;
+    let sealed = encryptor.wrap_output(io::BufWriter::new(file))?;
+    let (spool, files) = archive_into(vault_path, Spool::new(sealed), &out)?;
+    spool.finish()?.finish()?.flush()?;
+    Ok(files)
+}
+
+#[cfg(not(feature = "backup-encrypt"))]
+pub fn write_sealed_archive(
+    _vault_path: &Path,
+    _out: &Path,
+    _recipient: &str,
+) -> Result<usize, Box<dyn Error>> {
+    Err(NO_ENCRYPTION.into())
+}
+
+/// Deletes all but the newest `keep` archives of `vault_name` in `dir`.
+fn prune(dir: &Path, vault_name: &str, keep: usize) -> Result<usize, Box<dyn Error>> {
+    let mut archives: Vec<(String, PathBuf)> = fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let (_, stamp) = archive_name(name).filter(|(vault, _)| *vault == vault_name)?;
+            Some((stamp.to_string(), path))
+        })
+        .collect();
+    archives.sort();
+    let stale = archives.len().saturating_sub(keep);
+    for (_, archive) in &archives[..stale] {
+        fs::remove_file(archive)?;
+    }
+    Ok(stale)
+}
+
+/// Archives the vault into `settings.dir`, encrypting it if a recipient is set, and
+/// prunes old archives, returning the new archive's path.
+pub fn run_backup(vault_path: &Path, settings: &BackupConfig) -> Result<PathBuf, Box<dyn Error>> {
+    let dir = settings
+        .dir
+        .as_deref()
+        .ok_or("Set [backup] dir to the folder archives go to")?;
+    let dir = util::expand_tilde(Path::new(dir))
+        .ok_or("Cannot expand the backup folder")?
+        .into_owned();
+    fs::create_dir_all(&dir)?;
+    let vault_name = vault_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("vault"));
+    let out = dir.join(format!(
+        "{}-{}.zip",
+        vault_name,
+        Zoned::now().strftime("%Y%m%dT%H%M%S")
+    ));
+    let (out, files) = match &settings.recipient {
+        Some(recipient) => {
+            let sealed = out.with_extension("zip.age");
+            let files = write_sealed_archive(vault_path, &sealed, recipient)?;
+            (sealed, files)
+        }
+        None => {
+            let files = write_archive(vault_path, &out)?;
+            (out, files)
+        }
+    };
+    log::info!("Archived {} file(s) into {}", files, out.display());
+    let pruned = prune(&dir, &vault_name, settings.keep.max(1))?;
+    log::debug!("Deleted {} old archive(s)", pruned);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_archive_and_prune() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::create_dir_all(vault.path().join("notes")).unwrap();
+        fs::create_dir_all(vault.path().join(".git")).unwrap();
+        fs::write(vault.path().join("notes/a.md"), "# A\n").unwrap();
+        fs::write(vault.path().join("b.png"), [0u8, 1, 2]).unwrap();
+        fs::write(vault.path().join(".git/HEAD"), "ref").unwrap();
+
+        // An archive inside the vault does not archive itself
+        let out = vault.path().join("vault-20240101T000000.zip");
+        assert_eq!(write_archive(vault.path(), &out).unwrap(), 2);
+        let archive = zip::ZipArchive::new(fs::File::open(&out).unwrap()).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, vec!["b.png", "notes/a.md"]);
+
+        for stamp in ["20240102T000000", "20240103T000000"] {
+            fs::write(vault.path().join(format!("vault-{}.zip", stamp)), "").unwrap();
+        }
+        fs::write(vault.path().join("vault-20240104T000000.zip.age"), "").unwrap();
+        fs::write(vault.path().join("other-20230101T000000.zip"), "").unwrap();
+        assert_eq!(prune(vault.path(), "vault", 3).unwrap(), 1);
+        assert!(!out.exists());
+        assert!(vault.path().join("other-20230101T000000.zip").exists());
+    }
+
+    #[test]
+    fn test_backups_are_not_archived_or_pruned_across_vaults() {
+        let vault = tempfile::tempdir().unwrap();
+        let backups = vault.path().join("backups");
+        fs::create_dir_all(&backups).unwrap();
+        fs::write(vault.path().join("a.md"), "# A\n").unwrap();
+        fs::write(backups.join("notes-20230101T000000.zip"), "").unwrap();
+        fs::write(backups.join("notes.txt"), "").unwrap();
+
+        let out = backups.join("notes-20240101T000000.zip");
+        assert_eq!(write_archive(vault.path(), &out).unwrap(), 1);
+
+        fs::write(backups.join("notes-work-20230101T000000.zip"), "").unwrap();
+        fs::write(backups.join("notes-backup.zip"), "").unwrap();
+        assert_eq!(prune(&backups, "notes", 1).unwrap(), 1);
+        assert!(out.exists());
+        assert_eq!(
+            archive_name("notes-work-20230101T000000.zip.age")
+                .unwrap()
+                .0,
+            "notes-work"
+        );
+        assert_eq!(archive_name("notes-backup.zip"), None);
+    }
+
+    #[test]
+    fn test_spool_passes_on_patched_entries() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("a.md"), "# A\n".repeat(1000)).unwrap();
+        fs::write(vault.path().join("b.md"), "b").unwrap();
+        let (spool, files) =
+            archive_into(vault.path(), Spool::new(Vec::new()), Path::new("")).unwrap();
+        assert_eq!(files, 2);
+        let bytes = spool.finish().unwrap();
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(bytes)).unwrap();
+        let mut content = String::new();
+        io::Read::read_to_string(&mut archive.by_name("a.md").unwrap(), &mut content).unwrap();
+        assert_eq!(content, "# A\n".repeat(1000));
+    }
+}
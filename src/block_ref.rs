@@ -0,0 +1,150 @@
+//! Block references: tags a line of a note with a `^id` so `[[Note#^id]]` can link to it.
+
+use crate::changeset::ChangeSet;
+use crate::cli::BlockRefArgs;
+use crate::config::AppConfig;
+use crate::data::Note;
+use crate::index;
+use crate::markdown;
+use crate::resolver::Resolver;
+use crate::util;
+
+use serde::Serialize;
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BlockRef {
+    pub path: PathBuf,
+    pub id: String,
+    /// Ready to paste, e.g. `[[Note#^a1b2c3]]`
+    pub link: String,
+}
+
+/// The block id `line` already ends with
+fn existing_id(line: &str) -> Option<&str> {
+    let id = line.split_whitespace().last()?.strip_prefix('^')?;
+    let valid = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid.then_some(id)
+}
+
+/// Six random-looking base-36 characters not yet used as a block id in `content`.
+fn new_id(content: &str, seed: u128) -> String {
+    (0u64..)
+        .map(|attempt| {
+            let hash = util::content_hash(format!("{}:{}:{}", seed, attempt, content).as_bytes());
+            let mut value = u64::from_str_radix(&hash, 16).unwrap_or_default();
+            let mut id = String::new();
+            for _ in 0..6 {
+                id.push(char::from_digit((value % 36) as u32, 36).unwrap_or('0'));
+                value /= 36;
+            }
+            id
+        })
+        .find(|id| !content.contains(&format!("^{}", id)))
+        .unwrap_or_default()
+}
+
+/// `content` with the 1-based `line` ending in a block id, and that id. A line that
+/// already has one keeps it.
+pub fn add_block_id(
+    content: &str,
+    line: usize,
+    seed: u128,
+) -> Result<(String, String), Box<dyn Error>> {
+    let (_, offset, text) = markdown::body_lines(content)
+        .into_iter()
+        .find(|(line_no, _, _)| *line_no == line)
+        .ok_or_else(|| format!("Line {} is not part of the note body", line))?;
+    if text.trim().is_empty() {
+        return Err(format!("Line {} is blank", line).into());
+    }
+    if let Some(id) = existing_id(text) {
+        return Ok((content.to_string(), id.to_string()));
+    }
+    let id = new_id(content, seed);
+    let end = offset + text.trim_end().len();
+    let updated = format!("{} ^{}{}", &content[..end], id, &content[end..]);
+    Ok((updated, id))
+}
+
+/// Gives `line` of the note `note` a block id, updates the search index and returns the
+/// link to the block.
+pub fn block_ref(
+    vault_path: &Path,
+    config: &AppConfig,
+    note: &str,
+    line: usize,
+) -> Result<BlockRef, Box<dyn Error>> {
+    let resolver = Resolver::from_vault(vault_path)?;
+    let rel_path = resolver
+        .resolve(note, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", note))?
+        .to_path_buf();
+    let content = fs::read_to_string(vault_path.join(&rel_path))?;
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let (updated, id) = add_block_id(&content, line, seed)?;
+
+    if updated != content {
+        let mut changes = ChangeSet::new();
+        changes.propose(&rel_path, Some(content), updated.clone());
+        changes.apply(vault_path, "add block ids", false)?;
+        let note = Note::from_content(rel_path.clone(), updated);
+        if let Err(e) = index::open(config).and_then(|index| index::update_note(&index, &note)) {
+            log::warn!(
+                "Could not update the index for {}: {}",
+                rel_path.display(),
+                e
+            );
+        }
+    }
+    Ok(BlockRef {
+        link: format!("[[{}#^{}]]", resolver.shortest_link(&rel_path), id),
+        path: rel_path,
+        id,
+    })
+}
+
+pub fn run_block_ref(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &BlockRefArgs,
+) -> Result<(), Box<dyn Error>> {
+    let block = block_ref(vault_path, config, &args.note, args.line)?;
+    println!("{}", block.link);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_block_id() {
+        let content = "---\ntitle: T\n---\nFirst\n\nSecond ^keep\n```\ncode\n```\n";
+        let (updated, id) = add_block_id(content, 4, 1).unwrap();
+        assert_eq!(id.len(), 6);
+        assert_eq!(
+            updated,
+            format!(
+                "---\ntitle: T\n---\nFirst ^{}\n\nSecond ^keep\n```\ncode\n```\n",
+                id
+            )
+        );
+
+        assert_eq!(
+            add_block_id(content, 6, 1).unwrap(),
+            (content.to_string(), "keep".to_string())
+        );
+        assert!(add_block_id(content, 2, 1).is_err());
+        assert!(add_block_id(content, 5, 1).is_err());
+        assert!(add_block_id(content, 8, 1).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! Obsidian's bookmarks, stored in `.obsidian/bookmarks.json`. Fields this tool does not
+//! know are kept, so writing the file back leaves other bookmark types and settings intact.
+
+use crate::changeset::ChangeSet;
+use crate::cli::{BookmarkAddArgs, BookmarkRemoveArgs, BookmarksCommand, OutputFormat};
+use crate::resolver::Resolver;
+use crate::util;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+static BOOKMARKS_FILE: &str = ".obsidian/bookmarks.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Bookmark {
+    /// "file", "folder", "heading", "block", "search", "graph", "url" or "group"
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctime: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// `#heading` or `#^block` within `path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subpath: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Members of a group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<Bookmark>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Bookmarks {
+    #[serde(default)]
+    pub items: Vec<Bookmark>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A bookmark as listed, with the groups it sits in
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BookmarkEntry {
+    /// Titles of the enclosing groups, joined with `/`
+    pub group: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// What the bookmark points at: a path with its subpath, a query or a URL
+    pub target: String,
+    pub title: Option<String>,
+}
+
+impl Bookmarks {
+    /// The vault's bookmarks; none if the file does not exist.
+    pub fn load(vault_path: &Path) -> Result<Bookmarks, Box<dyn Error>> {
+        match fs::read_to_string(vault_path.join(BOOKMARKS_FILE)) {
+            Ok(json) => Ok(serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid {}: {}", BOOKMARKS_FILE, e))?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Bookmarks::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Every bookmark, groups flattened, in the order Obsidian shows them.
+    pub fn entries(&self) -> Vec<BookmarkEntry> {
+        fn walk(items: &[Bookmark], group: &str, out: &mut Vec<BookmarkEntry>) {
+            for item in items {
+                if let Some(members) = &item.items {
+                    let title = item.title.as_deref().unwrap_or_default();
+                    let group = match group {
+                        "" => title.to_string(),
+                        _ => format!("{}/{}", group, title),
+                    };
+                    walk(members, &group, out);
+                    continue;
+                }
+                let target = match (&item.path, &item.query, &item.url) {
+                    (Some(path), _, _) => {
+                        format!("{}{}", path, item.subpath.as_deref().unwrap_or_default())
+                    }
+                    (None, Some(query), _) => query.clone(),
+                    (None, None, Some(url)) => url.clone(),
+                    (None, None, None) => String::new(),
+                };
+                out.push(BookmarkEntry {
+                    group: group.to_string(),
+                    kind: item.kind.clone(),
+                    target,
+                    title: item.title.clone(),
+                });
+            }
+        }
+        let mut entries = Vec::new();
+        walk(&self.items, "", &mut entries);
+        entries
+    }
+
+    /// Adds a bookmark to `path` (with an optional `#heading` or `#^block` subpath) at the
+    /// top level; false if it is already bookmarked there.
+    pub fn add(
+        &mut self,
+        path: &str,
+        subpath: Option<&str>,
+        title: Option<&str>,
+        now_ms: i64,
+    ) -> bool {
+        let exists = self
+            .items
+            .iter()
+            .any(|item| item.path.as_deref() == Some(path) && item.subpath.as_deref() == subpath);
+        if exists {
+            return false;
+        }
+        let kind = match subpath {
+            None => "file",
+            Some(subpath) if subpath.starts_with("#^") => "block",
+            Some(_) => "heading",
+        };
+        self.items.push(Bookmark {
+            kind: kind.to_string(),
+            ctime: Some(now_ms),
+            path: Some(path.to_string()),
+            subpath: subpath.map(String::from),
+            title: title.map(String::from),
+            query: None,
+            url: None,
+            items: None,
+            extra: Map::new(),
+        });
+        true
+    }
+
+    /// Removes every bookmark of `path`, in groups too, returning how many there were.
+    pub fn remove(&mut self, path: &str, subpath: Option<&str>) -> usize {
+        fn retain(items: &mut Vec<Bookmark>, path: &str, subpath: Option<&str>) -> usize {
+            let before = items.len();
+            items.retain(|item| {
+                item.path.as_deref() != Some(path)
+                    || subpath.is_some_and(|subpath| item.subpath.as_deref() != Some(subpath))
+            });
+            let mut removed = before - items.len();
+            for item in items.iter_mut() {
+                if let Some(members) = &mut item.items {
+                    removed += retain(members, path, subpath);
+                }
+            }
+            removed
+        }
+        retain(&mut self.items, path, subpath)
+    }
+
+    /// The file contents Obsidian would write: two-space indented JSON.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Splits `note#heading` into the vault path of the note and the subpath.
+fn resolve_target(
+    vault_path: &Path,
+    target: &str,
+) -> Result<(String, Option<String>), Box<dyn Error>> {
+    let (note, subpath) = match target.find('#') {
+        Some(index) => (&target[..index], Some(target[index..].to_string())),
+        None => (target, None),
+    };
+    let path = Resolver::from_vault(vault_path)?
+        .resolve(note, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", note))?
+        .to_string_lossy()
+        .replace('\\', "/");
+    Ok((path, subpath))
+}
+
+fn save(
+    vault_path: &Path,
+    before: Option<String>,
+    bookmarks: &Bookmarks,
+) -> Result<(), Box<dyn Error>> {
+    let mut changes = ChangeSet::new();
+    changes.propose(PathBuf::from(BOOKMARKS_FILE), before, bookmarks.to_json()?);
+    changes.apply(vault_path, "change bookmarks", false)
+}
+
+fn add(vault_path: &Path, args: &BookmarkAddArgs) -> Result<(), Box<dyn Error>> {
+    let before = fs::read_to_string(vault_path.join(BOOKMARKS_FILE)).ok();
+    let mut bookmarks = Bookmarks::load(vault_path)?;
+    let (path, subpath) = resolve_target(vault_path, &args.note)?;
+    let now = Timestamp::now().as_millisecond();
+    if !bookmarks.add(&path, subpath.as_deref(), args.title.as_deref(), now) {
+        log::info!("{} is already bookmarked", args.note);
+        return Ok(());
+    }
+    save(vault_path, before, &bookmarks)
+}
+
+fn remove(vault_path: &Path, args: &BookmarkRemoveArgs) -> Result<(), Box<dyn Error>> {
+    let before = fs::read_to_string(vault_path.join(BOOKMARKS_FILE)).ok();
+    let mut bookmarks = Bookmarks::load(vault_path)?;
+    let (path, subpath) = resolve_target(vault_path, &args.note)?;
+    if bookmarks.remove(&path, subpath.as_deref()) == 0 {
+        return Err(format!("{} is not bookmarked", args.note).into());
+    }
+    save(vault_path, before, &bookmarks)
+}
+
+pub fn run_bookmarks(vault_path: &Path, command: &BookmarksCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        BookmarksCommand::List(args) => {
+            let entries = Bookmarks::load(vault_path)?.entries();
+            match args.format {
+                OutputFormat::Text => {
+                    for entry in &entries {
+                        let group = match entry.group.as_str() {
+                            "" => String::new(),
+                            group => format!("[{}] ", group),
+                        };
+                        match &entry.title {
+                            Some(title) => println!("{}{} ({})", group, entry.target, title),
+                            None => println!("{}{}", group, entry.target),
+                        }
+                    }
+                }
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+                OutputFormat::Ndjson => {
+                    for entry in &entries {
+                        util::print_ndjson(entry)?;
+                    }
+                }
+            }
+        }
+        BookmarksCommand::Add(args) => add(vault_path, args)?,
+        BookmarksCommand::Remove(args) => remove(vault_path, args)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static OBSIDIAN_JSON: &str = r##"{
+  "items": [
+    {
+      "type": "file",
+      "ctime": 1700000000000,
+      "path": "A.md"
+    },
+    {
+      "type": "group",
+      "ctime": 1700000000001,
+      "items": [
+        {
+          "type": "search",
+          "ctime": 1700000000002,
+          "query": "tag:#todo"
+        },
+        {
+          "type": "heading",
+          "ctime": 1700000000003,
+          "path": "B.md",
+          "subpath": "#Plan"
+        }
+      ],
+      "title": "Work"
+    },
+    {
+      "type": "graph",
+      "ctime": 1700000000004,
+      "options": {
+        "showTags": true
+      }
+    }
+  ]
+}"##;
+
+    #[test]
+    fn test_round_trip_and_entries() {
+        let bookmarks: Bookmarks = serde_json::from_str(OBSIDIAN_JSON).unwrap();
+        let round_trip: Value = serde_json::from_str(&bookmarks.to_json().unwrap()).unwrap();
+        assert_eq!(
+            round_trip,
+            serde_json::from_str::<Value>(OBSIDIAN_JSON).unwrap()
+        );
+
+        let targets: Vec<(String, String)> = bookmarks
+            .entries()
+            .into_iter()
+            .map(|entry| (entry.group, entry.target))
+            .collect();
+        assert_eq!(targets[1], ("Work".into(), "tag:#todo".into()));
+        assert_eq!(targets[2], ("Work".into(), "B.md#Plan".into()));
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut bookmarks: Bookmarks = serde_json::from_str(OBSIDIAN_JSON).unwrap();
+        assert!(!bookmarks.add("A.md", None, None, 0));
+        assert!(bookmarks.add("B.md", Some("#^abc"), Some("Block"), 0));
+        assert_eq!(bookmarks.items.last().unwrap().kind, "block");
+
+        assert_eq!(bookmarks.remove("B.md", None), 2);
+        assert_eq!(bookmarks.remove("B.md", None), 0);
+        assert_eq!(bookmarks.entries().len(), 3);
+    }
+}
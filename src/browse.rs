@@ -0,0 +1,141 @@
+//! Listing commands meant for pickers as much as for people: `find`, `backlinks` and
+//! `tags`, each with a `--porcelain` mode of stable tab-separated columns.
+
+use crate::cli::{BacklinksArgs, FindArgs, OutputFormat, TagsArgs};
+use crate::collation;
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::listing::{self, NoteEntry};
+use crate::recency;
+use crate::resolver::{self, Resolver};
+use crate::util;
+
+use jiff::Timestamp;
+use serde::Serialize;
+use std::{collections::HashMap, error::Error, path::Path};
+
+fn print_json<T: Serialize>(items: &[T], format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Ndjson => {
+            for item in items {
+                util::print_ndjson(item)?;
+            }
+        }
+        _ => println!("{}", serde_json::to_string_pretty(items)?),
+    }
+    Ok(())
+}
+
+/// Whether the note's path or title contains `pattern`, ignoring case
+pub fn matches(note: &Note, pattern: &str) -> bool {
+    let pattern = collation::fold(pattern);
+    collation::fold(&note.path.to_string_lossy()).contains(&pattern)
+        || collation::fold(&note.title()).contains(&pattern)
+}
+
+/// Sorts by descending frecency, then by path.
+fn rank(entries: &mut [NoteEntry], frecencies: &HashMap<String, f64>) {
+    let frecency = |entry: &NoteEntry| frecencies.get(&entry.path).copied().unwrap_or_default();
+    entries.sort_by(|a, b| {
+        frecency(b)
+            .total_cmp(&frecency(a))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+}
+
+pub fn run_find(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &FindArgs,
+) -> Result<usize, Box<dyn Error>> {
+    let pattern = args.pattern.as_deref().unwrap_or_default();
+    let mut entries: Vec<NoteEntry> = data::iter_notes(vault_path)?
+        .filter(|note| matches(note, pattern))
+        .map(|note| NoteEntry::new(&note))
+        .collect();
+    let frecencies = recency::open(config)
+        .and_then(|connection| recency::frecencies(&connection, Timestamp::now().as_millisecond()))
+        .unwrap_or_else(|e| {
+            log::warn!("Not ranking by recent use: {}", e);
+            HashMap::new()
+        });
+    rank(&mut entries, &frecencies);
+    if args.porcelain {
+        for entry in &entries {
+            util::print_porcelain(&[&entry.path, &entry.title])?;
+        }
+    } else if args.format == OutputFormat::Text {
+        for entry in &entries {
+            println!("{}", entry.path);
+        }
+    } else {
+        print_json(&entries, args.format)?;
+    }
+    Ok(entries.len())
+}
+
+pub fn run_backlinks(vault_path: &Path, args: &BacklinksArgs) -> Result<usize, Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    let resolver = Resolver::from_vault(vault_path)?;
+    let target = resolver
+        .resolve(&args.note, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", args.note))?;
+    let backlinks = resolver::backlinks(&notes, &resolver, target);
+    for backlink in &backlinks {
+        let path = backlink.path.to_string_lossy().replace('\\', "/");
+        if args.porcelain {
+            let line = backlink.line.to_string();
+            util::print_porcelain(&[&path, &line, &backlink.context])?;
+        } else if args.format == OutputFormat::Text {
+            println!("{}:{}: {}", path, backlink.line, backlink.context);
+        }
+    }
+    if !args.porcelain && args.format != OutputFormat::Text {
+        print_json(&backlinks, args.format)?;
+    }
+    Ok(backlinks.len())
+}
+
+pub fn run_tags(vault_path: &Path, args: &TagsArgs) -> Result<usize, Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    let tags = listing::tag_counts(&notes);
+    for entry in &tags {
+        if args.porcelain {
+            util::print_porcelain(&[&entry.tag, &entry.count.to_string()])?;
+        } else if args.format == OutputFormat::Text {
+            println!("#{} ({})", entry.tag, entry.count);
+        }
+    }
+    if !args.porcelain && args.format != OutputFormat::Text {
+        print_json(&tags, args.format)?;
+    }
+    Ok(tags.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_matches() {
+        let note = Note::from_content(
+            PathBuf::from("projects/plan.md"),
+            String::from("---\ntitle: Roadmap 2025\n---\n"),
+        );
+        assert!(matches(&note, "PROJECTS/"));
+        assert!(matches(&note, "roadmap"));
+        assert!(matches(&note, ""));
+        assert!(!matches(&note, "archive"));
+    }
+
+    #[test]
+    fn test_rank() {
+        let note = |path: &str| NoteEntry::new(&Note::from_content(path.into(), String::new()));
+        let mut entries = vec![note("A.md"), note("B.md"), note("C.md")];
+        let frecencies = HashMap::from([(String::from("C.md"), 2.0), (String::from("B.md"), 0.5)]);
+        rank(&mut entries, &frecencies);
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["C.md", "B.md", "A.md"]);
+    }
+}
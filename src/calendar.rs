@@ -0,0 +1,240 @@
+//! iCalendar feed of dated notes and open tasks. Notes with a `date` or `due` front matter
+//! field and unchecked tasks carrying a due date (`📅 2024-05-01` or `[due:: 2024-05-01]`)
+//! become events, so calendar apps can subscribe to the vault.
+
+use crate::cli::ExportCalendarArgs;
+use crate::data::{self, Note};
+use crate::feed;
+use crate::frontmatter;
+use crate::kanban;
+use crate::markdown;
+use crate::query::Query;
+use crate::util;
+
+use jiff::{Timestamp, civil, tz::TimeZone};
+use regex::Regex;
+use serde_yaml::Value;
+use std::{error::Error, fs, path::Path, sync::LazyLock};
+
+static TASK_DUE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"📅\s*(\d{4}-\d{2}-\d{2})|\[due::\s*([^\]]+)\]").expect("valid regex")
+});
+
+/// When an event happens: all day, or at a moment
+#[derive(Debug, Clone, PartialEq)]
+pub enum When {
+    Day(civil::Date),
+    At(Timestamp),
+}
+
+impl When {
+    fn parse(text: &str) -> Option<When> {
+        let text = text.trim();
+        match text.parse::<civil::Date>() {
+            Ok(date) if text.len() == 10 => Some(When::Day(date)),
+            _ => feed::parse_time(text).map(When::At),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarEvent {
+    pub uid: String,
+    pub summary: String,
+    pub when: When,
+    /// Vault-relative path of the note the event comes from
+    pub note: String,
+}
+
+/// Events for the `date` and `due` fields of `note` and its open tasks with a due date.
+pub fn note_events(note: &Note) -> Vec<CalendarEvent> {
+    let path = note.path.to_string_lossy().replace('\\', "/");
+    let uid = |key: &str| {
+        format!(
+            "{}@obsidian-rs",
+            util::content_hash(format!("{}\0{}", path, key).as_bytes())
+        )
+    };
+    let mut events = Vec::new();
+
+    let mapping = frontmatter::parse_mapping(&note.content)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    for (key, prefix) in [("date", ""), ("due", "Due: ")] {
+        let Some(Value::String(text)) = mapping.get(key) else {
+            continue;
+        };
+        if let Some(when) = When::parse(text) {
+            events.push(CalendarEvent {
+                uid: uid(key),
+                summary: format!("{}{}", prefix, note.title()),
+                when,
+                note: path.clone(),
+            });
+        }
+    }
+
+    // Cards in a complete lane are done even while unchecked
+    let done_cards = kanban::done_cards(note);
+    for (line_no, _, line) in markdown::body_lines(&note.content) {
+        let Some((false, text)) = markdown::parse_task(line) else {
+            continue;
+        };
+        if done_cards.contains(&line_no) {
+            continue;
+        }
+        let Some(due) = TASK_DUE.captures(text) else {
+            continue;
+        };
+        let date = due.get(1).or_else(|| due.get(2)).map_or("", |m| m.as_str());
+        let Some(when) = When::parse(date) else {
+            continue;
+        };
+        let summary = TASK_DUE.replace_all(text, "");
+        events.push(CalendarEvent {
+            // Keyed by the task text, so the event survives lines moving around it
+            uid: uid(summary.trim()),
+            summary: summary.split_whitespace().collect::<Vec<_>>().join(" "),
+            when,
+            note: path.clone(),
+        });
+    }
+    events
+}
+
+/// Escapes a TEXT value (RFC 5545 §3.3.11); any line break becomes `\n`, since a bare
+/// CR would end the content line.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\r', '\n'], "\\n")
+}
+
+/// Appends `line`, folded at 75 octets, with a CRLF.
+fn push_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+    for ch in line.chars() {
+        if width + ch.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(ch);
+        width += ch.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+fn utc(timestamp: Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::UTC)
+        .strftime("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+/// The calendar named `name` with `events`, stamped at `now`.
+pub fn render_ics(name: &str, events: &[CalendarEvent], now: Timestamp) -> String {
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//obsidian-rs//calendar//EN");
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape(name)));
+    for event in events {
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}", event.uid));
+        push_line(&mut ics, &format!("DTSTAMP:{}", utc(now)));
+        match &event.when {
+            When::Day(date) => {
+                push_line(
+                    &mut ics,
+                    &format!("DTSTART;VALUE=DATE:{}", date.strftime("%Y%m%d")),
+                );
+                let next = date.tomorrow().unwrap_or(*date);
+                push_line(
+                    &mut ics,
+                    &format!("DTEND;VALUE=DATE:{}", next.strftime("%Y%m%d")),
+                );
+            }
+            When::At(timestamp) => push_line(&mut ics, &format!("DTSTART:{}", utc(*timestamp))),
+        }
+        push_line(&mut ics, &format!("SUMMARY:{}", escape(&event.summary)));
+        push_line(&mut ics, &format!("DESCRIPTION:{}", escape(&event.note)));
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// The feed for the notes of `vault_path` matching `query`.
+pub fn vault_calendar(vault_path: &Path, query: &Query) -> Result<String, Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    let events: Vec<CalendarEvent> = query
+        .filter(&notes)
+        .into_iter()
+        .flat_map(note_events)
+        .collect();
+    let name = vault_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(render_ics(&name, &events, Timestamp::now()))
+}
+
+pub fn run_export_calendar(
+    vault_path: &Path,
+    args: &ExportCalendarArgs,
+) -> Result<(), Box<dyn Error>> {
+    let ics = vault_calendar(vault_path, &Query::parse(&args.query)?)?;
+    match &args.out {
+        Some(out) => {
+            fs::write(out, ics)?;
+            log::info!("Wrote {}", out.display());
+        }
+        None => print!("{}", ics),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_note_events() {
+        let note = Note::from_content(
+            PathBuf::from("Projects/Launch.md"),
+            String::from(
+                "---\ndate: 2024-05-01T09:30:00Z\ndue: 2024-05-10\n---\n\
+                 - [ ] Book venue 📅 2024-04-20\n- [x] Done 📅 2024-04-01\n\
+                 - [ ] Send invites, all [due:: 2024-04-25]\n- [ ] Someday\n",
+            ),
+        );
+        let events = note_events(&note);
+        let summaries: Vec<&str> = events.iter().map(|e| e.summary.as_str()).collect();
+        assert_eq!(
+            summaries,
+            ["Launch", "Due: Launch", "Book venue", "Send invites, all"]
+        );
+        assert_eq!(
+            events[0].when,
+            When::At("2024-05-01T09:30:00Z".parse().unwrap())
+        );
+        assert_eq!(events[2].when, When::Day(civil::date(2024, 4, 20)));
+
+        let ics = render_ics("Vault", &events[3..], Timestamp::UNIX_EPOCH);
+        assert!(ics.contains("\r\nDTSTAMP:19700101T000000Z\r\n"));
+        assert!(ics.contains("\r\nDTSTART;VALUE=DATE:20240425\r\nDTEND;VALUE=DATE:20240426\r\n"));
+        assert!(ics.contains("\r\nSUMMARY:Send invites\\, all\r\n"));
+        assert!(ics.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a;b,c\\d"), "a\\;b\\,c\\\\d");
+        assert_eq!(escape("one\r\ntwo\rthree\nfour"), "one\\ntwo\\nthree\\nfour");
+    }
+}
@@ -0,0 +1,235 @@
+use crate::cli::CaptureArgs;
+use crate::config::AppConfig;
+use crate::data;
+use crate::folder_config::FolderConfigs;
+use crate::frontmatter;
+use crate::util;
+use crate::write_gate;
+
+use jiff::{Zoned, fmt::strtime};
+use std::{
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+/// Vault-relative path of the daily note for `date`.
+pub fn daily_note_path(config: &AppConfig, date: &Zoned) -> Result<PathBuf, Box<dyn Error>> {
+    let name = format!("{}.md", format_time(&config.daily.format, date)?);
+    Ok(Path::new(&config.daily.folder).join(name))
+}
+
+/// `time` in the configured strftime `format`, with an error rather than a panic when
+/// the format is not valid.
+fn format_time(format: &str, time: &Zoned) -> Result<String, Box<dyn Error>> {
+    strtime::format(format, time)
+        .map_err(|e| format!("Cannot format the time with '{}': {}", format, e).into())
+}
+
+/// Rejects paths that would escape the vault (absolute or containing `..`).
+pub fn ensure_inside_vault(rel_path: &Path) -> Result<(), Box<dyn Error>> {
+    let escapes = rel_path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if escapes || rel_path.as_os_str().is_empty() {
+        return Err(format!("'{}' is not a path inside the vault", rel_path.display()).into());
+    }
+    Ok(())
+}
+
+/// Formats captured text as a bullet, indenting continuation lines under it.
+pub fn format_bullet(text: &str, timestamp: &str) -> String {
+    let mut lines = text.trim().lines();
+    let mut bullet = format!("- {} {}\n", timestamp, lines.next().unwrap_or_default());
+    for line in lines {
+        bullet.push_str(&format!("  {}\n", line));
+    }
+    bullet
+}
+
+/// Placeholders available to templates and front matter defaults of a new note.
+fn note_variables(rel_path: &Path, now: &Zoned) -> Vec<(&'static str, String)> {
+    let title = rel_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    vec![
+        ("date", now.strftime("%Y-%m-%d").to_string()),
+        ("time", now.strftime("%H:%M").to_string()),
+        ("title", title),
+    ]
+}
+
+/// `content` with the front matter defaults of the folders above `rel_path` filled in.
+pub fn with_front_matter_defaults(
+    folders: &FolderConfigs,
+    content: &str,
+    rel_path: &Path,
+    now: &Zoned,
+) -> Result<String, Box<dyn Error>> {
+    let defaults = folders.front_matter(rel_path, &note_variables(rel_path, now));
+    frontmatter::add_defaults(content, &defaults)
+}
+
+/// Initial content for a note created by `capture`, from `template` if one is configured.
+fn initial_content(
+    vault_path: &Path,
+    template: Option<&str>,
+    rel_path: &Path,
+    now: &Zoned,
+) -> String {
+    let Some(template) = template else {
+        return String::new();
+    };
+    match fs::read_to_string(vault_path.join(template)) {
+        Ok(content) => util::render_template(&content, &note_variables(rel_path, now)),
+        Err(e) => {
+            log::warn!("Cannot read template '{}': {}", template, e);
+            String::new()
+        }
+    }
+}
+
+/// Appends `text` as a timestamped bullet to `to`, today's daily note or the inbox,
+/// returning the vault-relative path of the note written.
+pub fn capture(
+    vault_path: &Path,
+    config: &AppConfig,
+    text: &str,
+    to: Option<&str>,
+    daily: bool,
+) -> Result<PathBuf, Box<dyn Error>> {
+    write_gate::check("capture notes")?;
+    let now = Zoned::now();
+    let timestamp = format_time(&config.capture.timestamp_format, &now)?;
+    let (rel_path, template) = match to {
+        Some(note) if data::is_note(Path::new(note)) => {
+            (PathBuf::from(note), config.capture.template.clone())
+        }
+        Some(note) => (
+            PathBuf::from(format!("{}.md", note)),
+            config.capture.template.clone(),
+        ),
+        None if daily || config.capture.daily => (
+            daily_note_path(config, &now)?,
+            config.daily.template.clone(),
+        ),
+        None => (
+            PathBuf::from(&config.capture.inbox),
+            config.capture.template.clone(),
+        ),
+    };
+    ensure_inside_vault(&rel_path)?;
+
+    let file = vault_path.join(&rel_path);
+    let existing = if file.exists() {
+        fs::read_to_string(&file)?
+    } else {
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // A folder's own template wins over the configured one. It comes from the vault
+        // itself, so it may only name a note inside it
+        let folders = FolderConfigs::load(vault_path)?;
+        let folder_template = folders.template(&rel_path).filter(|folder_template| {
+            ensure_inside_vault(Path::new(folder_template))
+                .inspect_err(|e| log::warn!("Ignoring the folder template: {}", e))
+                .is_ok()
+        });
+        let template = folder_template.map(String::from).or(template);
+        let content = initial_content(vault_path, template.as_deref(), &rel_path, &now);
+        let content = with_front_matter_defaults(&folders, &content, &rel_path, &now)?;
+        util::safe_write(&file, &content, false)?;
+        log::info!("Created {}", rel_path.display());
+        content
+    };
+
+    let mut entry = String::new();
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        entry.push('\n');
+    }
+    entry.push_str(&format_bullet(text, &timestamp));
+
+    let mut handle = OpenOptions::new().append(true).open(&file)?;
+    handle.write_all(entry.as_bytes())?;
+    Ok(rel_path)
+}
+
+pub fn run_capture(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &CaptureArgs,
+) -> Result<(), Box<dyn Error>> {
+    let text = if args.text.is_empty() {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        input
+    } else {
+        args.text.join(" ")
+    };
+    if text.trim().is_empty() {
+        return Err("Nothing to capture".into());
+    }
+
+    let rel_path = capture(vault_path, config, &text, args.to.as_deref(), args.daily)?;
+    log::info!("Captured to {}", rel_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bullet() {
+        assert_eq!(format_bullet("  idea \n", "09:30"), "- 09:30 idea\n");
+        assert_eq!(format_bullet("one\ntwo", "09:30"), "- 09:30 one\n  two\n");
+    }
+
+    #[test]
+    fn test_ensure_inside_vault() {
+        assert!(ensure_inside_vault(Path::new("Inbox.md")).is_ok());
+        assert!(ensure_inside_vault(Path::new("daily/2024-01-01.md")).is_ok());
+        assert!(ensure_inside_vault(Path::new("../outside.md")).is_err());
+        assert!(ensure_inside_vault(Path::new("/etc/passwd")).is_err());
+    }
+
+    #[test]
+    fn test_folder_template_stays_in_vault() {
+        let root = tempfile::tempdir().unwrap();
+        let vault = root.path().join("vault");
+        fs::create_dir_all(vault.join("T")).unwrap();
+        fs::write(root.path().join("secret.md"), "secret\n").unwrap();
+        fs::write(vault.join("T/Inbox.md"), "# Inbox\n").unwrap();
+        fs::write(
+            vault.join(crate::folder_config::FOLDER_CONFIG),
+            "template = \"../secret.md\"\n",
+        )
+        .unwrap();
+        let config = AppConfig {
+            capture: crate::config::CaptureConfig {
+                template: Some(String::from("T/Inbox.md")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        capture(&vault, &config, "idea", None, false).unwrap();
+        let inbox = fs::read_to_string(vault.join("Inbox.md")).unwrap();
+        assert!(inbox.starts_with("# Inbox\n- "));
+    }
+
+    #[test]
+    fn test_invalid_time_formats() {
+        let vault = tempfile::tempdir().unwrap();
+        let mut config = AppConfig::default();
+        config.capture.timestamp_format = String::from("%H:%");
+        let error = capture(vault.path(), &config, "idea", None, false).unwrap_err();
+        assert!(error.to_string().contains("'%H:%'"), "{}", error);
+        assert!(!vault.path().join(&config.capture.inbox).exists());
+
+        config.daily.format = String::from("%");
+        assert!(daily_note_path(&config, &Zoned::now()).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Proposed edits to vault files. Commands that rewrite notes collect their edits here
+//! first, so previewing (`--dry-run`, as a diff or JSON) and applying work the same way
+//! everywhere.
+
+use crate::cli::{ChangeArgs, OutputFormat};
+use crate::diff;
+use crate::history;
+use crate::util;
+use crate::write_gate;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Files modified more recently than this are taken to be open in an editor
+const EDITING: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Edit {
+    /// Vault-relative path of the file
+    pub path: PathBuf,
+    /// Current contents; `None` when the file would be created
+    pub before: Option<String>,
+    pub after: String,
+}
+
+impl Edit {
+    pub fn unified_diff(&self) -> String {
+        let name = self.path.to_string_lossy().replace('\\', "/");
+        let old_name = match self.before {
+            Some(_) => format!("a/{}", name),
+            None => String::from("/dev/null"),
+        };
+        diff::unified_diff(
+            self.before.as_deref().unwrap_or_default(),
+            &self.after,
+            &old_name,
+            &format!("b/{}", name),
+            3,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSet {
+    edits: Vec<Edit>,
+}
+
+impl ChangeSet {
+    pub fn new() -> ChangeSet {
+        ChangeSet::default()
+    }
+
+    /// Records that `path` should contain `after`; edits that change nothing are dropped.
+    pub fn propose(&mut self, path: impl Into<PathBuf>, before: Option<String>, after: String) {
+        if before.as_deref() != Some(after.as_str()) {
+            self.edits.push(Edit {
+                path: path.into(),
+                before,
+                after,
+            });
+        }
+    }
+
+    /// Drops the edits to files modified in the last few seconds, which are likely open in
+    /// an editor that would clash with a rewrite, returning how many were dropped.
+    pub fn skip_recently_modified(&mut self, vault_path: &Path) -> usize {
+        let before = self.edits.len();
+        self.edits.retain(|edit| {
+            let modified = fs::metadata(vault_path.join(&edit.path)).and_then(|m| m.modified());
+            modified.ok().is_none_or(|modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .is_ok_and(|age| age >= EDITING)
+            })
+        });
+        before - self.edits.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Prints the edits as unified diffs (text) or as JSON edit objects.
+    pub fn print(&self, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+        match format {
+            OutputFormat::Text => {
+                for edit in &self.edits {
+                    print!("{}", edit.unified_diff());
+                }
+            }
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&self.edits)?),
+            OutputFormat::Ndjson => {
+                for edit in &self.edits {
+                    util::print_ndjson(edit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes every edit into the vault; `action` names the change if the vault is read-only.
+    pub fn apply(
+        &self,
+        vault_path: &Path,
+        action: &str,
+        backup: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        write_gate::check(action)?;
+        for edit in &self.edits {
+            let file = vault_path.join(&edit.path);
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            util::safe_write(&file, &edit.after, backup)?;
+            log::info!("Wrote {}", edit.path.display());
+        }
+        if let Err(e) = history::record(action, &self.edits) {
+            log::warn!("Could not record the change for undo: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Previews or applies the edits as `args` asks, returning how many there were.
+    pub fn finish(
+        &self,
+        vault_path: &Path,
+        args: &ChangeArgs,
+        action: &str,
+        backup: bool,
+    ) -> Result<usize, Box<dyn Error>> {
+        if args.dry_run {
+            self.print(args.changes_format)?;
+        } else {
+            self.apply(vault_path, action, backup)?;
+        }
+        log::info!(
+            "{} note(s) {}.",
+            self.len(),
+            if args.dry_run {
+                "would change"
+            } else {
+                "changed"
+            }
+        );
+        Ok(self.len())
+    }
+}
+
+/// Rewrites the generated sections that `changes` finds out of date, for the daemon once
+/// changes settle; `what` names them in logs and history. Notes saved in the last few
+/// seconds wait for a later pass. Does nothing when the vault is read-only.
+pub fn refresh_generated(
+    vault_path: &Path,
+    what: &str,
+    changes: impl FnOnce() -> Result<ChangeSet, Box<dyn Error>>,
+) {
+    if write_gate::is_read_only() {
+        return;
+    }
+    let refreshed = changes().and_then(|mut changes| {
+        let editing = changes.skip_recently_modified(vault_path);
+        if editing > 0 {
+            log::debug!(
+                "Left {} in {} note(s) being edited for later",
+                what,
+                editing
+            );
+        }
+        changes.apply(vault_path, &format!("refresh {}", what), false)?;
+        Ok(changes.len())
+    });
+    match refreshed {
+        Ok(0) => {}
+        Ok(count) => log::info!("Refreshed {} in {} note(s)", what, count),
+        Err(e) => log::error!("Error refreshing {}: {}", what, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_propose_and_apply() {
+        let vault = tempfile::tempdir().unwrap();
+        let mut changes = ChangeSet::new();
+        changes.propose("Same.md", Some("x".into()), "x".into());
+        changes.propose("A.md", Some("a\n".into()), "b\n".into());
+        changes.propose("sub/New.md", None, "new\n".into());
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes.edits[0].unified_diff(),
+            "--- a/A.md\n+++ b/A.md\n@@ -1,1 +1,1 @@\n-a\n+b\n"
+        );
+
+        changes.apply(vault.path(), "edit notes", false).unwrap();
+        assert_eq!(
+            fs::read_to_string(vault.path().join("sub/New.md")).unwrap(),
+            "new\n"
+        );
+    }
+
+    #[test]
+    fn test_skip_recently_modified() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("Open.md"), "a\n").unwrap();
+        let old = fs::File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(vault.path().join("Old.md"))
+            .unwrap();
+        old.set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        let mut changes = ChangeSet::new();
+        changes.propose("Open.md", Some("a\n".into()), "b\n".into());
+        changes.propose("Old.md", Some(String::new()), "b\n".into());
+        changes.propose("New.md", None, "b\n".into());
+        assert_eq!(changes.skip_recently_modified(vault.path()), 1);
+        let paths: Vec<&Path> = changes
+            .edits
+            .iter()
+            .map(|edit| edit.path.as_path())
+            .collect();
+        assert_eq!(paths, [Path::new("Old.md"), Path::new("New.md")]);
+    }
+}
@@ -0,0 +1,453 @@
+//! Link checking: internal links must resolve to a vault file, and `#heading` or `#^block`
+//! anchors must exist in the note they point at. With `--external`, web links are
+//! requested too, and the answers kept in the cache database for `[check_links] ttl_hours`.
+
+use crate::cli::{CheckLinksArgs, OutputFormat};
+use crate::config::{AppConfig, CheckLinksConfig};
+use crate::data::{self, Note};
+use crate::folder_config;
+use crate::index;
+use crate::markdown;
+use crate::resolver::Resolver;
+use crate::util;
+
+use jiff::Timestamp;
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+#[cfg(feature = "web")]
+use std::{sync::Mutex, thread, time::Duration};
+#[cfg(feature = "web")]
+use url::Url;
+
+const MS_PER_HOUR: i64 = 3_600_000;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LinkProblem {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    /// "broken-link", "missing-heading" or "missing-block"
+    pub problem: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for LinkProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: [{}] {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.problem,
+            self.message
+        )
+    }
+}
+
+/// Whether `note` has the heading or `^block` id `anchor` names. Nested anchors
+/// (`H1#H2`) are matched by their last heading; comparison uses heading slugs, so
+/// `#My%20Heading`, `#my-heading` and `#My Heading` all match "My Heading".
+pub fn has_anchor(note: &Note, anchor: &str) -> bool {
+    let anchor = util::percent_decode(anchor);
+    if let Some(block) = anchor.strip_prefix('^') {
+        let id = format!("^{}", block);
+        return note
+            .content
+            .lines()
+            .any(|line| line.trim_end().ends_with(&id));
+    }
+    let wanted = markdown::heading_slug(anchor.rsplit('#').next().unwrap_or_default());
+    markdown::parse_headings(&note.content)
+        .iter()
+        .any(|heading| markdown::heading_slug(&heading.text) == wanted)
+}
+
+/// Problems with the internal links of `notes`, in path and line order.
+pub fn check_links(notes: &[Note], resolver: &Resolver) -> Vec<LinkProblem> {
+    let by_path: HashMap<&Path, &Note> = notes
+        .iter()
+        .map(|note| (note.path.as_path(), note))
+        .collect();
+    let mut problems = Vec::new();
+    for note in notes {
+        for link in markdown::parse_links(&note.content) {
+            if link.is_external() {
+                continue;
+            }
+            let problem = |problem, message| LinkProblem {
+                path: note.path.clone(),
+                line: link.line,
+                column: link.column,
+                problem,
+                message,
+            };
+            let Some(target) = resolver.resolve(&link.target, &note.path) else {
+                problems.push(problem(
+                    "broken-link",
+                    format!("'{}' does not match any file", link.target),
+                ));
+                continue;
+            };
+            let (Some(anchor), Some(target_note)) = (&link.anchor, by_path.get(target)) else {
+                continue;
+            };
+            if anchor.is_empty() || has_anchor(target_note, anchor) {
+                continue;
+            }
+            let (kind, what) = if anchor.starts_with('^') {
+                ("missing-block", "block")
+            } else {
+                ("missing-heading", "heading")
+            };
+            problems.push(problem(
+                kind,
+                format!(
+                    "'{}' has no {} '{}'",
+                    target.display(),
+                    what,
+                    util::percent_decode(anchor)
+                ),
+            ));
+        }
+    }
+    problems.sort_by(|a, b| a.path.cmp(&b.path).then(a.line.cmp(&b.line)));
+    problems
+}
+
+/// How an external URL answered when it was last requested
+#[derive(Debug, Clone, PartialEq)]
+pub struct UrlCheck {
+    /// HTTP status, or 0 when no response came
+    pub status: u16,
+    /// Where a redirect points
+    pub location: Option<String>,
+    pub error: Option<String>,
+    pub checked_ms: i64,
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS url_checks (
+            url TEXT PRIMARY KEY,
+            status INTEGER NOT NULL,
+            location TEXT,
+            error TEXT,
+            checked INTEGER NOT NULL
+        );",
+    )
+}
+
+/// Every stored URL check, stale ones included.
+pub fn cached_checks(connection: &Connection) -> Result<HashMap<String, UrlCheck>, Box<dyn Error>> {
+    let mut statement =
+        connection.prepare("SELECT url, status, location, error, checked FROM url_checks")?;
+    let mut checks = HashMap::new();
+    while let State::Row = statement.next()? {
+        checks.insert(
+            statement.read::<String, _>(0)?,
+            UrlCheck {
+                status: statement.read::<i64, _>(1)? as u16,
+                location: statement.read(2)?,
+                error: statement.read(3)?,
+                checked_ms: statement.read(4)?,
+            },
+        );
+    }
+    Ok(checks)
+}
+
+fn save_check(connection: &Connection, url: &str, check: &UrlCheck) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO url_checks (url, status, location, error, checked)
+         VALUES (?, ?, ?, ?, ?)",
+    )?;
+    statement.bind((1, url))?;
+    statement.bind((2, check.status as i64))?;
+    statement.bind((3, check.location.as_deref()))?;
+    statement.bind((4, check.error.as_deref()))?;
+    statement.bind((5, check.checked_ms))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Whether `url` passes the `allow` and `deny` patterns.
+fn is_checked(url: &str, settings: &CheckLinksConfig) -> bool {
+    let matches = |patterns: &[String]| {
+        patterns
+            .iter()
+            .any(|pattern| folder_config::wildcard_match(pattern, url))
+    };
+    (settings.allow.is_empty() || matches(&settings.allow)) && !matches(&settings.deny)
+}
+
+/// Requests `url` without following redirects: `HEAD` first, `GET` when the server does
+/// not take `HEAD`, retrying server errors and failed connections.
+#[cfg(feature = "web")]
+fn check_url(agent: &ureq::Agent, url: &str, retries: usize) -> UrlCheck {
+    let mut method = "HEAD";
+    let mut attempt = 0;
+    let (status, location, error) = loop {
+        let outcome = match agent.request(method, url).call() {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(_, response)) => Ok(response),
+            Err(e) => Err(e.to_string()),
+        };
+        let retry = match &outcome {
+            Ok(response) if method == "HEAD" && matches!(response.status(), 403 | 405 | 501) => {
+                method = "GET";
+                continue;
+            }
+            Ok(response) => response.status() >= 500,
+            Err(_) => true,
+        };
+        if retry && attempt < retries {
+            attempt += 1;
+            thread::sleep(Duration::from_millis(500 * attempt as u64));
+            continue;
+        }
+        break match outcome {
+            Ok(response) => {
+                let location = response
+                    .header("Location")
+                    .and_then(|location| Url::parse(url).ok()?.join(location).ok())
+                    .map(String::from);
+                (response.status(), location, None)
+            }
+            Err(e) => (0, None, Some(e)),
+        };
+    };
+    UrlCheck {
+        status,
+        location,
+        error,
+        checked_ms: Timestamp::now().as_millisecond(),
+    }
+}
+
+/// Requests `urls` on `settings.jobs` threads at once.
+#[cfg(feature = "web")]
+fn check_urls(
+    urls: Vec<String>,
+    settings: &CheckLinksConfig,
+) -> Result<HashMap<String, UrlCheck>, Box<dyn Error>> {
+    let agent = ureq::AgentBuilder::new()
+        .redirects(0)
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .user_agent(concat!("obsidian-rs/", env!("CARGO_PKG_VERSION")))
+        .build();
+    let queue = Mutex::new(urls);
+    let results = Mutex::new(HashMap::new());
+    thread::scope(|scope| {
+        for _ in 0..settings.jobs.max(1) {
+            scope.spawn(|| {
+                loop {
+                    let Some(url) = queue.lock().expect("queue lock").pop() else {
+                        break;
+                    };
+                    let check = check_url(&agent, &url, settings.retries);
+                    results.lock().expect("results lock").insert(url, check);
+                }
+            });
+        }
+    });
+    Ok(results.into_inner().expect("results lock"))
+}
+
+#[cfg(not(feature = "web"))]
+fn check_urls(
+    _urls: Vec<String>,
+    _settings: &CheckLinksConfig,
+) -> Result<HashMap<String, UrlCheck>, Box<dyn Error>> {
+    Err("Web links cannot be checked: obsidian-rs was built without the `web` feature".into())
+}
+
+/// Dead and redirected web links of `notes`, given how each URL answered.
+pub fn external_problems(notes: &[Note], checks: &HashMap<String, UrlCheck>) -> Vec<LinkProblem> {
+    let mut problems = Vec::new();
+    for note in notes {
+        for markdown::WebUrl {
+            url, line, column, ..
+        } in markdown::find_web_urls(&note.content)
+        {
+            let Some(check) = checks.get(&url) else {
+                continue;
+            };
+            let (problem, message) = match check {
+                // ureq names the URL in its errors
+                UrlCheck {
+                    error: Some(error), ..
+                } => ("dead-link", error.clone()),
+                UrlCheck { status, .. } if *status >= 400 => {
+                    ("dead-link", format!("{} answered {}", url, status))
+                }
+                UrlCheck {
+                    status,
+                    location: Some(location),
+                    ..
+                } if (300..400).contains(status) => {
+                    ("redirect", format!("{} redirects to {}", url, location))
+                }
+                _ => continue,
+            };
+            problems.push(LinkProblem {
+                path: note.path.clone(),
+                line,
+                column,
+                problem,
+                message,
+            });
+        }
+    }
+    problems
+}
+
+/// Checks the web links of `notes`, reusing answers younger than the TTL.
+fn check_external(notes: &[Note], config: &AppConfig) -> Result<Vec<LinkProblem>, Box<dyn Error>> {
+    let settings = &config.check_links;
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    let mut checks = cached_checks(&connection)?;
+
+    let now = Timestamp::now().as_millisecond();
+    let ttl = settings.ttl_hours as i64 * MS_PER_HOUR;
+    let urls: BTreeSet<String> = notes
+        .iter()
+        .flat_map(|note| markdown::find_web_urls(&note.content))
+        .map(|url| url.url)
+        .filter(|url| is_checked(url, settings))
+        .collect();
+    let stale: Vec<String> = urls
+        .iter()
+        .filter(|url| {
+            checks
+                .get(*url)
+                .is_none_or(|check| now - check.checked_ms >= ttl)
+        })
+        .cloned()
+        .collect();
+    log::info!(
+        "Checking {} of {} external URL(s); the rest were checked recently",
+        stale.len(),
+        urls.len()
+    );
+    for (url, check) in check_urls(stale, settings)? {
+        save_check(&connection, &url, &check)?;
+        checks.insert(url, check);
+    }
+    checks.retain(|url, _| urls.contains(url));
+    Ok(external_problems(notes, &checks))
+}
+
+/// The vault's link problems, with its web links' when `external` is set.
+pub fn find_problems(
+    vault_path: &Path,
+    config: &AppConfig,
+    external: bool,
+) -> Result<Vec<LinkProblem>, Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    let resolver = Resolver::from_vault(vault_path)?;
+    let mut problems = check_links(&notes, &resolver);
+    if external {
+        problems.extend(check_external(&notes, config)?);
+        problems.sort_by(|a, b| a.path.cmp(&b.path).then(a.line.cmp(&b.line)));
+    }
+    Ok(problems)
+}
+
+/// Prints the vault's link problems, returning how many there were.
+pub fn run_check_links(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &CheckLinksArgs,
+) -> Result<usize, Box<dyn Error>> {
+    let problems = find_problems(vault_path, config, args.external)?;
+    match args.format {
+        OutputFormat::Text => {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&problems)?),
+        OutputFormat::Ndjson => {
+            for problem in &problems {
+                util::print_ndjson(problem)?;
+            }
+        }
+    }
+    log::info!("Found {} link problem(s).", problems.len());
+    Ok(problems.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_links() {
+        let notes = vec![
+            Note::from_content(
+                PathBuf::from("Home.md"),
+                String::from(
+                    "[[Plan#Next Steps]] [[Plan#Later]] [[Plan#^done]]\n\
+                     [plan](Plan.md#next-steps) [[Missing]] [[#Home]]\n\
+                     # Home\n",
+                ),
+            ),
+            Note::from_content(
+                PathBuf::from("Plan.md"),
+                String::from("# Plan\n## Next Steps!\nShip it ^done\n"),
+            ),
+        ];
+        let resolver = Resolver::new(notes.iter().map(|note| note.path.clone()).collect());
+        let found: Vec<(&str, usize)> = check_links(&notes, &resolver)
+            .iter()
+            .map(|problem| (problem.problem, problem.line))
+            .collect();
+        assert_eq!(found, vec![("missing-heading", 1), ("broken-link", 2)]);
+    }
+
+    #[test]
+    fn test_external_problems() {
+        let notes = vec![Note::from_content(
+            PathBuf::from("Links.md"),
+            String::from(
+                "[gone](https://a.org/gone) https://b.org/old
+[fine](https://a.org/) mailto:x@y.z
+",
+            ),
+        )];
+        let check = |status, location: Option<&str>, error: Option<&str>| UrlCheck {
+            status,
+            location: location.map(String::from),
+            error: error.map(String::from),
+            checked_ms: 0,
+        };
+        let checks = HashMap::from([
+            (String::from("https://a.org/gone"), check(404, None, None)),
+            (
+                String::from("https://b.org/old"),
+                check(301, Some("https://b.org/new"), None),
+            ),
+            (String::from("https://a.org/"), check(200, None, None)),
+        ]);
+        let found: Vec<(&str, usize, usize)> = external_problems(&notes, &checks)
+            .iter()
+            .map(|problem| (problem.problem, problem.line, problem.column))
+            .collect();
+        assert_eq!(found, vec![("dead-link", 1, 1), ("redirect", 1, 28)]);
+
+        let settings = CheckLinksConfig {
+            deny: vec![String::from("https://a.org/*")],
+            ..CheckLinksConfig::default()
+        };
+        assert!(!is_checked("https://a.org/gone", &settings));
+        assert!(is_checked("https://b.org/old", &settings));
+    }
+}
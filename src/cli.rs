@@ -0,0 +1,1328 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+/// Index, watch and maintain an Obsidian vault.
+///
+/// Without a subcommand the vault is indexed and then watched for changes.
+#[derive(Parser, Debug)]
+#[command(name = "obsidian-rs", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Refuse to change any vault file (also `[workspace] read_only`)
+    #[arg(long, global = true)]
+    pub read_only: bool,
+
+    /// Use the `[profiles.<name>]` settings of config.toml for this command
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Work on this vault from Obsidian's own vault list (see `vault list`)
+    #[arg(long, global = true, value_name = "NAME")]
+    pub vault: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Check notes against the configured lint rules
+    #[command(after_long_help = LINT_EXAMPLES)]
+    Lint(LintArgs),
+    /// Report links to missing files, headings or blocks
+    CheckLinks(CheckLinksArgs),
+    /// Give a line of a note a `^block-id` and print the link to it
+    BlockRef(BlockRefArgs),
+    /// Print the link from one note to another in the vault's link format
+    #[command(after_long_help = LINK_TO_EXAMPLES)]
+    LinkTo(LinkToArgs),
+    /// Replace regex matches across the vault or the notes matching a query
+    #[command(after_long_help = REPLACE_EXAMPLES)]
+    Replace(ReplaceArgs),
+    /// Insert or refresh the table of contents of a note, between `<!-- toc -->` markers
+    Toc(TocArgs),
+    /// Renumber `1.`, `1.2` style numbered headings, optionally closing skipped levels
+    RenumberHeadings(RenumberHeadingsArgs),
+    /// Rewrite internal links between wikilink and Markdown style
+    ConvertLinks(ConvertLinksArgs),
+    /// Copy notes and attachments from another vault or folder into this one
+    Import(ImportArgs),
+    /// Append a timestamped bullet to the inbox or daily note
+    #[command(after_long_help = CAPTURE_EXAMPLES)]
+    Capture(CaptureArgs),
+    /// Search note contents, by keywords or by meaning
+    #[command(after_long_help = SEARCH_EXAMPLES)]
+    Search(SearchArgs),
+    /// Print the lines of notes matching a regular expression, with context
+    Grep(GrepArgs),
+    /// List the notes matching a query, e.g. "tag:#project path:work/"
+    #[command(after_long_help = QUERY_EXAMPLES)]
+    Query(QueryArgs),
+    /// List notes whose path or title contains a pattern
+    #[command(after_long_help = FIND_EXAMPLES)]
+    Find(FindArgs),
+    /// List recently opened or edited notes, most recent first
+    Recent(RecentArgs),
+    /// List the notes linking to a note
+    Backlinks(BacklinksArgs),
+    /// List tags with the number of notes carrying each, most used first
+    Tags(TagsArgs),
+    /// List the notes closest in meaning to a note
+    Similar(SimilarArgs),
+    /// Propose tags for a note from similar tagged notes
+    SuggestTags(SuggestTagsArgs),
+    /// Bundle note excerpts relevant to a query or note into a token-budgeted prompt
+    #[command(after_long_help = CONTEXT_EXAMPLES)]
+    Context(ContextArgs),
+    /// List the loaded WebAssembly plugins and Lua scripts, or the metadata they extract from a note
+    Plugins(PluginsArgs),
+    /// Serve the vault to LLM clients over the Model Context Protocol on stdio
+    Mcp(McpArgs),
+    /// Save a web page as a note in the clippings folder
+    Clip(ClipArgs),
+    /// Print a note with everything it embeds inlined, as one standalone document
+    Flatten(FlattenArgs),
+    /// Export notes out of the vault
+    Export {
+        #[command(subcommand)]
+        command: ExportCommand,
+    },
+    /// List the vaults Obsidian knows, or compare vaults
+    #[command(visible_alias = "vaults")]
+    Vault {
+        #[command(subcommand)]
+        command: VaultCommand,
+    },
+    /// List the configuration profiles or pick the one used without `--profile`
+    #[command(after_long_help = PROFILE_EXAMPLES)]
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+    /// Exchange vault changes with another machine through files
+    Journal {
+        #[command(subcommand)]
+        command: JournalCommand,
+    },
+    /// Report vault problems such as sync conflict copies
+    Doctor(DoctorArgs),
+    /// List or resolve conflict copies left by Dropbox, Syncthing or iCloud
+    Conflicts {
+        #[command(subcommand)]
+        command: ConflictsCommand,
+    },
+    /// List changes obsidian-rs applied to the vault, newest first
+    History(HistoryArgs),
+    /// Revert the latest recorded change, or the one with the given id
+    Undo(UndoArgs),
+    /// Three-way merge two versions of a note against their common ancestor
+    Merge(MergeArgs),
+    /// List, add or remove Obsidian bookmarks
+    Bookmarks {
+        #[command(subcommand)]
+        command: BookmarksCommand,
+    },
+    /// Write or refresh maps of content, index notes listing the notes matching a query
+    #[command(after_long_help = MOC_EXAMPLES)]
+    Moc {
+        #[command(subcommand)]
+        command: MocCommand,
+    },
+    /// List typed notes, e.g. people or books, as configured under `[entities]`
+    Entities {
+        #[command(subcommand)]
+        command: EntitiesCommand,
+    },
+    /// Review the flashcards that are due, scheduling the next review with SM-2
+    Review(ReviewArgs),
+    /// Fetch and show titles and descriptions of the web pages notes link to
+    Previews {
+        #[command(subcommand)]
+        command: PreviewsCommand,
+    },
+    /// Link archived copies of dead or redirected external links
+    ArchiveLinks(ArchiveLinksArgs),
+    /// List image attachments, make thumbnails and shrink oversized images
+    Images {
+        #[command(subcommand)]
+        command: ImagesCommand,
+    },
+    /// List audio and video attachments and print their transcripts
+    Media {
+        #[command(subcommand)]
+        command: MediaCommand,
+    },
+    /// Show Obsidian Kanban boards and query their cards
+    Kanban {
+        #[command(subcommand)]
+        command: KanbanCommand,
+    },
+    /// List the reminders and due dates set in front matter for the coming days
+    Agenda(AgendaArgs),
+    /// Track time spent on notes
+    Clock {
+        #[command(subcommand)]
+        command: ClockCommand,
+    },
+    /// Total the time clocked per note or tag, e.g. `timesheet --week`
+    Timesheet(TimesheetArgs),
+    /// Words written per day, streaks and progress towards the `[writing]` goals
+    Writing {
+        #[command(subcommand)]
+        command: WritingCommand,
+    },
+    /// Chart the vault's notes, words, links and tags from the daily index snapshots
+    Stats(StatsArgs),
+    /// Show the notes and tags that changed since an index snapshot
+    DiffIndex(DiffIndexArgs),
+    /// List or run the jobs configured under `[[schedule.jobs]]`
+    #[command(alias = "schedule")]
+    Jobs {
+        #[command(subcommand)]
+        command: JobsCommand,
+    },
+    /// List, fill or empty Obsidian's `.trash` folder
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand,
+    },
+    /// Print a shell completion script, e.g. `obsidian-rs completions bash`
+    #[command(after_long_help = COMPLETIONS_EXAMPLES)]
+    Completions(CompletionsArgs),
+    /// Write man pages for every command into a directory
+    #[command(hide = true)]
+    GenDocs(GenDocsArgs),
+}
+
+// Shown by `--help` and in the man pages, after the options.
+const LINT_EXAMPLES: &str = "Examples:
+  obsidian-rs lint
+  obsidian-rs lint --fix --dry-run
+  obsidian-rs lint --format json";
+const REPLACE_EXAMPLES: &str = "Examples:
+  obsidian-rs replace --query \"tag:#draft\" --regex 'foo(\\d+)' --with 'bar$1' --dry-run
+  obsidian-rs replace --regex '\\bTODO\\b' --with DONE --ignore-case";
+const PROFILE_EXAMPLES: &str = "Examples:
+  obsidian-rs profile list
+  obsidian-rs profile switch work
+  obsidian-rs --profile personal search recipes";
+const SEARCH_EXAMPLES: &str = "Examples:
+  obsidian-rs search release plan
+  obsidian-rs search --mode semantic \"how do I deploy\"
+  obsidian-rs search todo --porcelain | fzf";
+const QUERY_EXAMPLES: &str = "Examples:
+  obsidian-rs query tag:#project path:work/
+  obsidian-rs query title:meeting budget --format json";
+const FIND_EXAMPLES: &str = "Examples:
+  obsidian-rs find meeting
+  obsidian-rs find --porcelain | fzf --delimiter '\\t' --with-nth 2";
+const LINK_TO_EXAMPLES: &str = "Examples:
+  obsidian-rs link-to \"Project Plan#Budget\" --from daily/2025-01-01.md
+  obsidian-rs link-to Plan --from Index.md --line 3 --style markdown";
+const MOC_EXAMPLES: &str = "Examples:
+  obsidian-rs moc generate --query \"tag:#projectX\" --out Maps/ProjectX.md
+  obsidian-rs moc generate --query path:work/ --out Work.md --group-by tag --dry-run
+  obsidian-rs moc refresh";
+const CAPTURE_EXAMPLES: &str = "Examples:
+  obsidian-rs capture call the plumber
+  echo \"idea\" | obsidian-rs capture --daily";
+const CONTEXT_EXAMPLES: &str = "Examples:
+  obsidian-rs context \"Project Plan\" --budget 4000
+  obsidian-rs context how are releases tagged";
+const COMPLETIONS_EXAMPLES: &str = "Examples:
+  source <(obsidian-rs completions bash)
+  obsidian-rs completions zsh > ~/.zfunc/_obsidian-rs
+  obsidian-rs completions fish > ~/.config/fish/completions/obsidian-rs.fish";
+
+#[derive(Args, Debug)]
+pub struct DoctorArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConflictsCommand {
+    /// List conflict copies and the notes they diverged from
+    List,
+    /// Show how a conflict copy differs from its note, and optionally settle it
+    Resolve(ConflictsResolveArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ConflictsResolveArgs {
+    /// Conflict copy, as a vault-relative path or file name
+    pub file: String,
+
+    /// Version to keep; the other goes to the trash. Without it only the diff is shown
+    #[arg(long, value_enum)]
+    pub keep: Option<Keep>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keep {
+    /// Keep the note as it is
+    Original,
+    /// Replace the note with the conflict copy
+    Copy,
+    /// Write both into the note, with conflict markers around the differences
+    Both,
+}
+
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct UndoArgs {
+    /// Id of the change to revert, as shown by `history`
+    pub id: Option<String>,
+
+    /// Revert even if the files were changed again since
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct CompletionsArgs {
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(Args, Debug)]
+pub struct GenDocsArgs {
+    /// Directory to write the man pages to
+    #[arg(default_value = "man")]
+    pub out_dir: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// Common ancestor of both versions
+    pub base: PathBuf,
+    /// Our version
+    pub ours: PathBuf,
+    /// Their version
+    pub theirs: PathBuf,
+
+    /// File to write the result to; printed when omitted
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TrashCommand {
+    /// List the files in the trash
+    List,
+    /// Move a note or attachment to the trash
+    Put(TrashArgs),
+    /// Move a trashed file back to where it was
+    Restore(TrashArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum JobsCommand {
+    /// List the jobs with the time each last ran and runs next
+    List,
+    /// Run a job now
+    Run(JobsRunArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct JobsRunArgs {
+    /// Name of the job
+    pub job: String,
+}
+
+#[derive(Args, Debug)]
+pub struct AgendaArgs {
+    /// Days ahead to list, counting today
+    #[arg(long, default_value_t = 7)]
+    pub days: u32,
+
+    /// Also list reminders and due dates that have passed
+    #[arg(long)]
+    pub overdue: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ClockCommand {
+    /// Start the clock on a note, stopping any running clock first
+    In(ClockInArgs),
+    /// Stop the running clock
+    Out,
+    /// Show the running clock
+    Status,
+}
+
+#[derive(Args, Debug)]
+pub struct ClockInArgs {
+    /// Note to clock in to, as a path or link target
+    pub note: String,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimesheetGroup {
+    Note,
+    /// Each of the note's tags; time on a note with several tags counts for every one
+    Tag,
+}
+
+#[derive(Args, Debug)]
+pub struct TimesheetArgs {
+    /// Days to total, as in date queries: today, 2024-05, last-week, -7d
+    #[arg(long, default_value = "today", conflicts_with = "week")]
+    pub period: String,
+
+    /// Total this week, from Monday
+    #[arg(long)]
+    pub week: bool,
+
+    #[arg(long, value_enum, default_value_t = TimesheetGroup::Note)]
+    pub by: TimesheetGroup,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum WritingCommand {
+    /// Show today's words, the streak and each goal's progress
+    Stats(WritingStatsArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct WritingStatsArgs {
+    /// Days of history to list
+    #[arg(long, default_value_t = 7)]
+    pub days: usize,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// First day to chart, as in date queries: 2024-01-01, last-month, -30d
+    #[arg(long)]
+    pub since: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffIndexArgs {
+    /// Day to compare with, as in date queries; the latest snapshot up to it is used
+    pub date: String,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct TrashArgs {
+    /// File to move, as a path or note name
+    pub note: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MocCommand {
+    /// Write a map of the notes matching a query into a note, replacing its first map
+    Generate(MocGenerateArgs),
+    /// Regenerate every map of content in the vault
+    Refresh(MocRefreshArgs),
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MocGroup {
+    /// One section per folder
+    Folder,
+    /// One section per tag; notes with several tags are listed under each
+    Tag,
+}
+
+#[derive(Args, Debug)]
+pub struct MocGenerateArgs {
+    /// Notes to list, e.g. "tag:#projectX"
+    #[arg(long)]
+    pub query: String,
+
+    /// Vault-relative note to write the map into, e.g. "Maps/ProjectX.md"
+    #[arg(long)]
+    pub out: String,
+
+    #[arg(long, value_enum, default_value_t = MocGroup::Folder)]
+    pub group_by: MocGroup,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct MocRefreshArgs {
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum EntitiesCommand {
+    /// List the configured kinds with the number of notes of each
+    Kinds(EntitiesKindsArgs),
+    /// List the notes of one kind with their configured fields, e.g. `entities list person`
+    List(EntitiesListArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct EntitiesKindsArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct EntitiesListArgs {
+    /// Kind of note to list, e.g. "person"
+    pub kind: String,
+
+    /// Only list notes also matching this query, e.g. "tag:#family"
+    #[arg(long)]
+    pub query: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print `path<TAB>title<TAB>field…` lines for pickers, one column per configured field
+    #[arg(long, conflicts_with = "format")]
+    pub porcelain: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ReviewArgs {
+    /// Only review cards from notes matching this query, e.g. "tag:#flashcards/spanish"
+    #[arg(long)]
+    pub query: Option<String>,
+
+    /// Most cards to review in one session
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+
+    /// List the due cards instead of reviewing them
+    #[arg(long)]
+    pub list: bool,
+
+    /// How to print the list
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, requires = "list")]
+    pub format: OutputFormat,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PreviewsCommand {
+    /// Fetch the previews that are missing or out of date
+    Fetch(PreviewsFetchArgs),
+    /// Print the preview of a URL
+    Show(PreviewsShowArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct PreviewsFetchArgs {
+    /// Only fetch pages linked from notes matching this query
+    #[arg(long)]
+    pub query: Option<String>,
+
+    /// Fetch again even previews that are not out of date
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct PreviewsShowArgs {
+    pub url: String,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct ArchiveLinksArgs {
+    /// Only links in notes matching this query
+    #[arg(long)]
+    pub query: Option<String>,
+
+    /// Archive every external link, not only those `check-links --external` flagged
+    #[arg(long)]
+    pub all: bool,
+
+    /// Clip a readable copy into the vault instead of using the web archive
+    #[arg(long)]
+    pub local: bool,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImagesCommand {
+    /// List images with their size, dimensions and EXIF date and camera
+    List(ImagesListArgs),
+    /// Print the path of an image's thumbnail, generating it if needed
+    Thumbnail(ImagesThumbnailArgs),
+    /// Scale down and re-encode images over the `[images]` limits
+    Recompress(ImagesRecompressArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ImagesListArgs {
+    /// Only images over the `[images]` limits
+    #[arg(long)]
+    pub oversized: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct ImagesThumbnailArgs {
+    /// Vault-relative path of the image
+    pub path: PathBuf,
+
+    /// Edge of the square the thumbnail fits in, in pixels
+    #[arg(long)]
+    pub size: Option<u32>,
+}
+
+#[derive(Args, Debug)]
+pub struct ImagesRecompressArgs {
+    /// Largest width or height to keep (defaults to `[images] max_dimension`)
+    #[arg(long)]
+    pub max_dimension: Option<u32>,
+
+    /// JPEG quality, 1-100 (defaults to `[images] quality`)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: Option<u8>,
+
+    /// Keep each original as `<name>.bak`
+    #[arg(long)]
+    pub backup: bool,
+
+    /// Only print how much each image would shrink
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MediaCommand {
+    /// List recordings with their kind, codec, duration and size
+    List(MediaListArgs),
+    /// Print the transcript of a recording, transcribing new ones first
+    Transcript(MediaTranscriptArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct MediaListArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct MediaTranscriptArgs {
+    /// Vault-relative path of the recording
+    pub path: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KanbanCommand {
+    /// Print the lanes and cards of a board
+    Show(KanbanShowArgs),
+    /// List the cards of every board, from the index
+    Cards(KanbanCardsArgs),
+    /// Check the cards in lanes marked complete and uncheck the others
+    Sync(KanbanSyncArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct KanbanShowArgs {
+    /// Board note, as a path or link target
+    pub board: String,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct KanbanCardsArgs {
+    /// Only cards in lanes with this name, e.g. "Doing"
+    #[arg(long)]
+    pub lane: Option<String>,
+
+    /// Only cards that are not done
+    #[arg(long, conflicts_with = "done")]
+    pub open: bool,
+
+    /// Only cards that are done
+    #[arg(long)]
+    pub done: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct KanbanSyncArgs {
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BookmarksCommand {
+    /// List the bookmarks, with the groups they are in
+    List(BookmarksListArgs),
+    /// Bookmark a note, or a heading or block with `note#heading` / `note#^block`
+    Add(BookmarkAddArgs),
+    /// Remove the bookmarks of a note, or of one heading or block of it
+    Remove(BookmarkRemoveArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct BookmarksListArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct BookmarkAddArgs {
+    /// Note to bookmark, as a path or link target, optionally with an anchor
+    pub note: String,
+
+    /// Title shown instead of the note name
+    #[arg(long)]
+    pub title: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct BookmarkRemoveArgs {
+    /// Bookmarked note, as a path or link target, optionally with an anchor
+    pub note: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum VaultCommand {
+    /// List the vaults in Obsidian's `obsidian.json`, the open ones marked with `*`
+    List(VaultListArgs),
+    /// List the notes added, removed, renamed and changed between two vaults
+    Diff(VaultDiffArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct VaultListArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommand {
+    /// List the profiles in config.toml, the active one marked with `*`
+    List,
+    /// Use a profile from now on ($OBSIDIAN_RS_PROFILE and `--profile` still win)
+    Switch(ProfileSwitchArgs),
+    /// Go back to the configuration without a profile
+    Clear,
+}
+
+#[derive(Args, Debug)]
+pub struct ProfileSwitchArgs {
+    /// Name of a `[profiles.<name>]` table
+    pub name: String,
+}
+
+#[derive(Args, Debug)]
+pub struct VaultDiffArgs {
+    /// Vault folder, or backup zip archive, to compare from
+    pub a: PathBuf,
+
+    /// Vault folder, or backup zip archive, to compare to
+    pub b: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum JournalCommand {
+    /// Write the changes journaled since a sequence number as a bundle
+    Export(JournalExportArgs),
+    /// Apply a bundle exported on another machine
+    Apply(JournalApplyArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct JournalExportArgs {
+    /// Sequence number the last bundle ended at (its `until`); 0 exports everything
+    #[arg(long, default_value_t = 0)]
+    pub since: i64,
+
+    /// File to write the bundle to; printed when omitted
+    #[arg(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct JournalApplyArgs {
+    /// Bundle written by `journal export`
+    pub bundle: PathBuf,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ExportCommand {
+    /// Copy the notes matching a query, with what they embed, to another directory
+    Subset(ExportSubsetArgs),
+    /// Write an Atom or RSS feed of the recently updated notes matching a query
+    Feed(ExportFeedArgs),
+    /// Render notes to a static HTML site with a sitemap and permalink redirects
+    Html(ExportHtmlArgs),
+    /// Print a note, or every note matching a query, to PDF
+    Pdf(ExportPdfArgs),
+    /// Convert the notes matching a query to Word documents
+    Docx(ExportDocxArgs),
+    /// Write an iCalendar feed of dated notes and open tasks with due dates
+    Calendar(ExportCalendarArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct LintArgs {
+    /// How to print reported issues
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Apply fixes for fixable rules before reporting what is left
+    #[arg(long)]
+    pub fix: bool,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+
+    /// With --fix, keep a `.bak` copy of every rewritten note
+    #[arg(long, requires = "fix")]
+    pub backup: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BlockRefArgs {
+    /// Note to reference, as a path or link target
+    pub note: String,
+    /// Line number of the block, counting from 1
+    pub line: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct LinkToArgs {
+    /// File to link to, optionally with a `#heading` or `#^block` anchor
+    pub target: String,
+
+    /// Note the link will live in
+    #[arg(long)]
+    pub from: String,
+
+    /// Append the link to this line of the source note instead of only printing it
+    #[arg(long)]
+    pub line: Option<usize>,
+
+    /// Link style; defaults to the vault's "Use [[Wikilinks]]" setting
+    #[arg(long, value_enum)]
+    pub style: Option<LinkFormat>,
+
+    /// Path form; defaults to the vault's "New link format" setting
+    #[arg(long, value_enum)]
+    pub path: Option<PathFormat>,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckLinksArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Also request web links and report dead ones and redirects
+    #[arg(long)]
+    pub external: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplaceArgs {
+    /// Only rewrite notes matching this query, e.g. "tag:#draft path:work/"
+    #[arg(long)]
+    pub query: Option<String>,
+
+    /// Regular expression to search for; `^` and `$` match at line boundaries
+    #[arg(long)]
+    pub regex: String,
+
+    /// Replacement text; `$1` or `${name}` insert capture groups
+    #[arg(long = "with")]
+    pub with: String,
+
+    #[arg(long, short = 'i')]
+    pub ignore_case: bool,
+
+    /// Keep a `.bak` copy of every rewritten note
+    #[arg(long)]
+    pub backup: bool,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct TocArgs {
+    /// Note to update, as a path or link target
+    pub note: String,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RenumberHeadingsArgs {
+    /// Note to renumber, as a path or link target; every note when left out
+    pub note: Option<String>,
+
+    /// Also raise headings that skip a level, e.g. an H4 right below an H2
+    #[arg(long)]
+    pub fix_levels: bool,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertLinksArgs {
+    /// Link style to convert to
+    #[arg(long, value_enum)]
+    pub to: LinkFormat,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+/// Previewing for commands that rewrite notes
+#[derive(Args, Debug, Clone, Copy)]
+pub struct ChangeArgs {
+    /// Print the changes instead of writing them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// With --dry-run, print unified diffs (text) or the edits as JSON
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, requires = "dry_run")]
+    pub changes_format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    /// Vault or folder to import from
+    pub source: PathBuf,
+
+    /// Vault folder to place imported notes in (defaults to the vault root)
+    #[arg(long)]
+    pub into: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct CaptureArgs {
+    /// Text to capture; read from stdin when omitted
+    pub text: Vec<String>,
+
+    /// Note to append to instead of the configured inbox
+    #[arg(long)]
+    pub to: Option<String>,
+
+    /// Append to today's daily note
+    #[arg(long, conflicts_with = "to")]
+    pub daily: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SearchArgs {
+    /// Words or "phrases" to search for, combined with AND (the default), OR, NOT and
+    /// parentheses; `word*` matches words starting with `word`. `title:`, `heading:` and
+    /// `body:` scope a term to one field, `path:folder/` and `frontmatter.key:value` narrow
+    /// down the notes searched
+    #[arg(required = true)]
+    pub query: Vec<String>,
+
+    /// Ranking to use; hybrid by default when an embedding backend is configured
+    #[arg(long, value_enum)]
+    pub mode: Option<SearchMode>,
+
+    /// Maximum number of notes to list
+    #[arg(long, default_value_t = 10)]
+    pub limit: usize,
+
+    /// Words in each snippet (defaults to `[search] snippet_words`)
+    #[arg(long)]
+    pub snippet_words: Option<usize>,
+
+    /// Snippets per note, around different matches (defaults to `[search] snippets`)
+    #[arg(long)]
+    pub snippets: Option<usize>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print `path<TAB>line<TAB>heading<TAB>snippet` lines for pickers
+    #[arg(long, conflicts_with = "format")]
+    pub porcelain: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    /// Query terms: tag:, path:, title:, heading:, body:, frontmatter.<key>:, plugin:,
+    /// dates such as created:>=-7d, due:<=today or modified:this-week, and plain words
+    #[arg(required = true)]
+    pub query: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print `path<TAB>title` lines for pickers
+    #[arg(long, conflicts_with = "format")]
+    pub porcelain: bool,
+
+    /// Show how each note matched: the predicates, whether the query cache answered, and
+    /// the search index rank of the plain words
+    #[arg(long, conflicts_with = "porcelain")]
+    pub explain: bool,
+
+    /// Group the notes by tag, folder, status, created, modified, due, date or
+    /// frontmatter.<key>, largest group first
+    #[arg(long, value_name = "KEY", conflicts_with_all = ["porcelain", "explain"])]
+    pub group_by: Option<String>,
+
+    /// Only count the notes in each group
+    #[arg(long, requires = "group_by")]
+    pub count: bool,
+
+    /// Write the results as a table into this vault-relative note, between markers the
+    /// daemon keeps up to date
+    #[arg(
+        long,
+        value_name = "NOTE",
+        conflicts_with_all = ["porcelain", "explain", "group_by"]
+    )]
+    pub render_into: Option<String>,
+
+    /// Table columns: title, path, tags, folder, status, created, modified, due, date
+    /// or a front matter key
+    #[arg(long, default_value = "title", requires = "render_into")]
+    pub columns: String,
+
+    #[command(flatten)]
+    pub changes: ChangeArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct FindArgs {
+    /// Text the note's path or title must contain; every note when omitted. Frequently
+    /// and recently used notes are listed first.
+    pub pattern: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print `path<TAB>title` lines for pickers
+    #[arg(long, conflicts_with = "format")]
+    pub porcelain: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct GrepArgs {
+    /// Regular expression to search for, matched line by line
+    pub pattern: String,
+
+    /// Only search notes matching this query, e.g. "tag:#project"
+    #[arg(long)]
+    pub query: Option<String>,
+
+    /// Lines of context to show before and after each match
+    #[arg(long, short = 'C', default_value_t = 0)]
+    pub context: usize,
+
+    #[arg(long, short = 'i')]
+    pub ignore_case: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print `path<TAB>line<TAB>column<TAB>text` lines for pickers
+    #[arg(long, conflicts_with = "format")]
+    pub porcelain: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct RecentArgs {
+    /// Maximum number of notes to list
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print `path<TAB>last visit<TAB>opens<TAB>edits` lines for pickers
+    #[arg(long, conflicts_with = "format")]
+    pub porcelain: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BacklinksArgs {
+    /// Note whose backlinks to list, as a path or link target
+    pub note: String,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print `path<TAB>line<TAB>context` lines for pickers
+    #[arg(long, conflicts_with = "format")]
+    pub porcelain: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TagsArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print `tag<TAB>count` lines for pickers
+    #[arg(long, conflicts_with = "format")]
+    pub porcelain: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SimilarArgs {
+    /// Note to compare against, as a path or link target
+    pub note: String,
+
+    /// Number of similar notes to list
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct SuggestTagsArgs {
+    /// Note to suggest tags for, as a path or link target
+    pub note: String,
+
+    /// Maximum number of tags to propose
+    #[arg(long, default_value_t = 5)]
+    pub limit: usize,
+
+    /// Also compare notes by meaning, using the embedding index
+    #[arg(long)]
+    pub semantic: bool,
+
+    /// Add the proposed tags to the note's front matter
+    #[arg(long)]
+    pub apply: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct McpArgs {
+    /// Speak msgpack-rpc (as Neovim plugins do) instead of line-delimited JSON-RPC
+    #[arg(long)]
+    pub msgpack: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct PluginsArgs {
+    /// Note to run the plugins' and scripts' metadata hooks on
+    #[arg(long)]
+    pub note: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ContextArgs {
+    /// Note name or path to build the context around, or else a search query
+    #[arg(required = true)]
+    pub input: Vec<String>,
+
+    /// Approximate number of tokens the bundle may use
+    #[arg(long, default_value_t = 8000)]
+    pub budget: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct ClipArgs {
+    /// URL of the page to clip
+    pub url: String,
+
+    /// Extra tag for the clipping (repeatable)
+    #[arg(long = "tag")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct FlattenArgs {
+    /// Note to flatten, as a path or link target
+    pub note: String,
+
+    /// Write the document to this file (outside the vault) instead of printing it
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportSubsetArgs {
+    /// Notes to export, e.g. "tag:#public"
+    #[arg(long)]
+    pub query: String,
+
+    /// Directory to export into (outside the vault)
+    #[arg(long)]
+    pub out: PathBuf,
+
+    /// Also export notes linked from the selection, up to this many links away
+    #[arg(long, default_value_t = 0)]
+    pub depth: usize,
+
+    /// Only list the files that would be exported
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportFeedArgs {
+    /// Notes to include, e.g. "tag:#blog"
+    #[arg(long)]
+    pub query: String,
+
+    /// URL the notes are published under; defaults to [publish] base_url
+    #[arg(long)]
+    pub base_url: Option<String>,
+
+    /// Feed title; defaults to [publish] title or the vault name
+    #[arg(long)]
+    pub title: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = FeedFormat::Atom)]
+    pub format: FeedFormat,
+
+    /// Maximum number of entries
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+
+    /// File to write the feed to instead of stdout
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportHtmlArgs {
+    /// Directory to write the site to (outside the vault)
+    #[arg(long)]
+    pub out: PathBuf,
+
+    /// Notes to export; all notes by default
+    #[arg(long, default_value = "")]
+    pub query: String,
+
+    /// URL the site is served from; defaults to [publish] base_url
+    #[arg(long)]
+    pub base_url: Option<String>,
+
+    /// Skip notes without `publish: true` in their front matter
+    #[arg(long)]
+    pub published_only: bool,
+
+    /// Keep watching the vault and update the site as notes change
+    #[arg(long)]
+    pub watch: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportPdfArgs {
+    /// Note to print, as a path or link target, or a query such as "tag:#report"
+    pub target: String,
+
+    /// PDF file for a single note, or directory for the notes of a query (outside the
+    /// vault); defaults to `<note>.pdf` in the current directory
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+
+    /// Program to print with; defaults to [publish] pdf_engine
+    #[arg(long)]
+    pub engine: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportDocxArgs {
+    /// Notes to convert, e.g. "tag:#handout"
+    #[arg(long)]
+    pub query: String,
+
+    /// Directory to write the `.docx` files to (outside the vault)
+    #[arg(long)]
+    pub out: PathBuf,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportCalendarArgs {
+    /// Notes to include; all notes by default
+    #[arg(long, default_value = "")]
+    pub query: String,
+
+    /// File to write the `.ics` feed to instead of stdout
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Full-text matches ranked by BM25
+    Keyword,
+    /// Cosine similarity of embeddings
+    Semantic,
+    /// Reciprocal rank fusion of both
+    Hybrid,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+/// Obsidian's "New link format"
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathFormat {
+    /// File name alone when unique, otherwise the vault path
+    Shortest,
+    /// Path relative to the linking note
+    Relative,
+    /// Path from the vault root
+    Absolute,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkFormat {
+    /// `[[Note|text]]`
+    Wikilink,
+    /// `[text](Note.md)`
+    Markdown,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    /// One JSON object per line, written as results are produced
+    Ndjson,
+}
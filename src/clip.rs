@@ -0,0 +1,488 @@
+use crate::capture;
+use crate::cli::ClipArgs;
+use crate::config::AppConfig;
+use crate::folder_config::FolderConfigs;
+use crate::net;
+use crate::util;
+use crate::write_gate;
+
+use jiff::Zoned;
+use scraper::{ElementRef, Html, Node, Selector, node::Element};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use url::Url;
+
+/// Elements that never hold article content
+static SKIPPED_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "nav", "header", "footer", "aside", "form",
+    "button", "svg", "iframe",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clipping {
+    pub title: String,
+    pub markdown: String,
+}
+
+#[derive(Serialize)]
+struct ClipFrontMatter<'a> {
+    title: &'a str,
+    source: &'a str,
+    clipped: String,
+    tags: Vec<String>,
+}
+
+/// Accepts only absolute http(s) URLs.
+pub fn parse_source_url(input: &str) -> Result<Url, Box<dyn Error>> {
+    let url = Url::parse(input.trim())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Cannot clip '{}': only http(s) URLs are supported", input).into());
+    }
+    Ok(url)
+}
+
+fn select_first<'a>(document: &'a Html, selector: &str) -> Option<ElementRef<'a>> {
+    let selector = Selector::parse(selector).ok()?;
+    document.select(&selector).next()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn page_title(document: &Html, url: &Url) -> String {
+    let meta = select_first(document, r#"meta[property="og:title"]"#)
+        .and_then(|meta| meta.value().attr("content").map(collapse_whitespace));
+    let title = || {
+        select_first(document, "title").map(|t| collapse_whitespace(&t.text().collect::<String>()))
+    };
+    let heading =
+        || select_first(document, "h1").map(|h| collapse_whitespace(&h.text().collect::<String>()));
+    [meta, title(), heading()]
+        .into_iter()
+        .flatten()
+        .find(|title| !title.is_empty())
+        .unwrap_or_else(|| url.host_str().unwrap_or("Clipping").to_string())
+}
+
+/// Picks the element holding the main text: `<article>`, `<main>`, or else the element
+/// whose direct `<p>` children carry the most text.
+fn content_root(document: &Html) -> ElementRef<'_> {
+    for selector in ["article", "main", r#"[role="main"]"#] {
+        if let Some(element) = select_first(document, selector) {
+            return element;
+        }
+    }
+
+    let paragraphs = Selector::parse("p").expect("valid selector");
+    let mut scores: HashMap<_, usize> = HashMap::new();
+    for paragraph in document.select(&paragraphs) {
+        if let Some(parent) = paragraph.parent().and_then(ElementRef::wrap) {
+            *scores.entry(parent.id()).or_default() +=
+                paragraph.text().map(str::len).sum::<usize>();
+        }
+    }
+    scores
+        .into_iter()
+        .max_by_key(|(_, score)| *score)
+        .and_then(|(id, _)| document.tree.get(id).and_then(ElementRef::wrap))
+        .or_else(|| select_first(document, "body"))
+        .unwrap_or_else(|| document.root_element())
+}
+
+struct Converter<'a> {
+    base: &'a Url,
+}
+
+impl Converter<'_> {
+    fn resolve(&self, href: &str) -> String {
+        self.base
+            .join(href)
+            .map(String::from)
+            .unwrap_or_else(|_| href.to_string())
+    }
+
+    fn children(&self, element: ElementRef, out: &mut String) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => push_text(out, text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.element(child, out);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn inline(&self, element: ElementRef) -> String {
+        let mut out = String::new();
+        self.children(element, &mut out);
+        collapse_whitespace(&out)
+    }
+
+    fn element(&self, element: ElementRef, out: &mut String) {
+        let value: &Element = element.value();
+        let tag = value.name();
+        if SKIPPED_TAGS.contains(&tag) {
+            return;
+        }
+
+        match tag {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = tag[1..].parse().unwrap_or(1);
+                let text = self.inline(element);
+                if !text.is_empty() {
+                    push_block(out, &format!("{} {}", "#".repeat(level), text));
+                }
+            }
+            "p" | "div" | "section" | "figure" | "figcaption" | "dl" | "dd" | "dt" => {
+                start_block(out);
+                self.children(element, out);
+                start_block(out);
+            }
+            "br" => out.push('\n'),
+            "hr" => push_block(out, "---"),
+            "pre" => {
+                let code: String = element.text().collect();
+                push_block(out, &format!("```\n{}\n```", code.trim_end_matches('\n')));
+            }
+            "blockquote" => {
+                let mut inner = String::new();
+                self.children(element, &mut inner);
+                let quoted: Vec<String> = normalize(&inner)
+                    .lines()
+                    .map(|line| format!("> {}", line).trim_end().to_string())
+                    .collect();
+                push_block(out, &quoted.join("\n"));
+            }
+            "ul" | "ol" => {
+                let items = element
+                    .children()
+                    .filter_map(ElementRef::wrap)
+                    .filter(|child| child.value().name() == "li");
+                let mut list = Vec::new();
+                for (index, item) in items.enumerate() {
+                    let marker = if tag == "ol" {
+                        format!("{}. ", index + 1)
+                    } else {
+                        String::from("- ")
+                    };
+                    let mut inner = String::new();
+                    self.children(item, &mut inner);
+                    let indent = " ".repeat(marker.len());
+                    let body = normalize(&inner).replace("\n\n", "\n");
+                    let mut lines = body.lines();
+                    list.push(format!("{}{}", marker, lines.next().unwrap_or_default()));
+                    list.extend(lines.map(|line| format!("{}{}", indent, line)));
+                }
+                push_block(out, &list.join("\n"));
+            }
+            "table" => self.table(element, out),
+            "a" => {
+                let text = self.inline(element);
+                match value.attr("href") {
+                    Some(href) if !text.is_empty() && !href.starts_with("javascript:") => {
+                        let href = if href.starts_with('#') {
+                            href.to_string()
+                        } else {
+                            self.resolve(href)
+                        };
+                        out.push_str(&format!("[{}]({})", text, href));
+                    }
+                    _ => out.push_str(&text),
+                }
+            }
+            "img" => {
+                if let Some(src) = value.attr("src") {
+                    let alt = collapse_whitespace(value.attr("alt").unwrap_or_default());
+                    out.push_str(&format!("![{}]({})", alt, self.resolve(src)));
+                }
+            }
+            "strong" | "b" => wrap_inline(out, "**", &self.inline(element)),
+            "em" | "i" => wrap_inline(out, "*", &self.inline(element)),
+            "code" => wrap_inline(
+                out,
+                "`",
+                &collapse_whitespace(&element.text().collect::<String>()),
+            ),
+            _ => self.children(element, out),
+        }
+    }
+
+    fn table(&self, element: ElementRef, out: &mut String) {
+        let rows = Selector::parse("tr").expect("valid selector");
+        let cells = Selector::parse("th, td").expect("valid selector");
+        let rows: Vec<Vec<String>> = element
+            .select(&rows)
+            .map(|row| {
+                row.select(&cells)
+                    .map(|cell| self.inline(cell).replace('|', "\\|"))
+                    .collect()
+            })
+            .filter(|row: &Vec<String>| !row.is_empty())
+            .collect();
+        let Some(width) = rows.iter().map(Vec::len).max() else {
+            return;
+        };
+
+        let mut lines = Vec::new();
+        for (index, row) in rows.iter().enumerate() {
+            let mut row = row.clone();
+            row.resize(width, String::new());
+            lines.push(format!("| {} |", row.join(" | ")));
+            if index == 0 {
+                lines.push(format!("|{}", " --- |".repeat(width)));
+            }
+        }
+        push_block(out, &lines.join("\n"));
+    }
+}
+
+fn push_text(out: &mut String, text: &str) {
+    let collapsed = collapse_whitespace(text);
+    let at_line_start = out.is_empty() || out.ends_with('\n');
+    if text.starts_with(char::is_whitespace) && !at_line_start && !out.ends_with(' ') {
+        out.push(' ');
+    }
+    out.push_str(&collapsed);
+    if text.ends_with(char::is_whitespace) && !collapsed.is_empty() {
+        out.push(' ');
+    }
+}
+
+fn wrap_inline(out: &mut String, marker: &str, text: &str) {
+    if !text.is_empty() {
+        out.push_str(&format!("{}{}{}", marker, text, marker));
+    }
+}
+
+fn start_block(out: &mut String) {
+    while out.ends_with(' ') {
+        out.pop();
+    }
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+    }
+}
+
+fn push_block(out: &mut String, block: &str) {
+    start_block(out);
+    out.push_str(block);
+    out.push_str("\n\n");
+}
+
+/// Trims trailing spaces and collapses runs of blank lines.
+fn normalize(markdown: &str) -> String {
+    let mut result = String::new();
+    let mut blank = true;
+    for line in markdown.lines().map(str::trim_end) {
+        if line.is_empty() {
+            if !blank {
+                result.push('\n');
+            }
+            blank = true;
+        } else {
+            result.push_str(line);
+            result.push('\n');
+            blank = false;
+        }
+    }
+    result.trim_end().to_string()
+}
+
+/// Extracts the title and the main content of `html` as Markdown.
+pub fn extract(html: &str, url: &Url) -> Clipping {
+    let document = Html::parse_document(html);
+    let converter = Converter { base: url };
+    let mut out = String::new();
+    converter.element(content_root(&document), &mut out);
+    Clipping {
+        title: page_title(&document, url),
+        markdown: normalize(&out),
+    }
+}
+
+/// Makes `title` usable as a note name that wikilinks can point to.
+pub fn note_name(title: &str) -> String {
+    let cleaned: String = title
+        .chars()
+        .map(|c| match c {
+            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | '^' | '[' | ']' => ' ',
+            c => c,
+        })
+        .collect();
+    let name: String = collapse_whitespace(&cleaned).chars().take(100).collect();
+    let name = name.trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        String::from("Clipping")
+    } else {
+        name.to_string()
+    }
+}
+
+fn fetch(url: &Url) -> Result<(String, Url), Box<dyn Error>> {
+    // Whoever asks for a clipping can read it back, so it must not reach local services
+    let agent = net::public_agent().timeout(Duration::from_secs(30)).build();
+    let response = agent
+        .get(url.as_str())
+        .set(
+            "User-Agent",
+            concat!("obsidian-rs/", env!("CARGO_PKG_VERSION")),
+        )
+        .call()?;
+    let final_url = Url::parse(response.get_url()).unwrap_or_else(|_| url.clone());
+    Ok((response.into_string()?, final_url))
+}
+
+/// Renders the note written for a clipping, front matter included.
+pub fn render_note(clipping: &Clipping, source: &Url, tags: Vec<String>, date: &Zoned) -> String {
+    let front_matter = ClipFrontMatter {
+        title: &clipping.title,
+        source: source.as_str(),
+        clipped: date.strftime("%Y-%m-%d").to_string(),
+        tags,
+    };
+    let yaml = serde_yaml::to_string(&front_matter).unwrap_or_default();
+    format!("---\n{}---\n\n{}\n", yaml, clipping.markdown)
+}
+
+/// Fetches `url` and saves it under the clippings folder, returning the vault-relative path.
+pub fn clip(
+    vault_path: &Path,
+    config: &AppConfig,
+    url: &str,
+    extra_tags: &[String],
+) -> Result<PathBuf, Box<dyn Error>> {
+    write_gate::check("clip pages")?;
+    let source = parse_source_url(url)?;
+    let (html, final_url) = fetch(&source)?;
+    let clipping = extract(&html, &final_url);
+
+    let mut tags = config.clip.tags.clone();
+    for tag in extra_tags {
+        let tag = tag.trim_start_matches('#').to_string();
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    let folder = Path::new(&config.clip.folder);
+    let name = note_name(&clipping.title);
+    let mut rel_path = folder.join(format!("{}.md", name));
+    let mut counter = 1;
+    while vault_path.join(&rel_path).exists() {
+        rel_path = folder.join(format!("{} {}.md", name, counter));
+        counter += 1;
+    }
+    capture::ensure_inside_vault(&rel_path)?;
+
+    let file = vault_path.join(&rel_path);
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let now = Zoned::now();
+    let content = capture::with_front_matter_defaults(
+        &FolderConfigs::load(vault_path)?,
+        &render_note(&clipping, &source, tags, &now),
+        &rel_path,
+        &now,
+    )?;
+    util::safe_write(&file, content, false)?;
+    Ok(rel_path)
+}
+
+pub fn run_clip(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &ClipArgs,
+) -> Result<(), Box<dyn Error>> {
+    let rel_path = clip(vault_path, config, &args.url, &args.tags)?;
+    println!("{}", rel_path.display());
+    log::info!("Clipped {} to {}", args.url, rel_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PAGE: &str = r#"<html><head><title>Fallback</title>
+<meta property="og:title" content="A  Post: Part 1"></head>
+<body><nav><a href="/">Home</a></nav>
+<article>
+  <h1>A Post</h1>
+  <p>Some <strong>bold</strong> text with a <a href="/docs/page.html">relative link</a>.</p>
+  <ul><li>one</li><li>two <em>three</em></li></ul>
+  <pre><code>fn main() {}
+</code></pre>
+  <blockquote><p>quoted</p></blockquote>
+  <img src="img/pic.png" alt="A picture">
+</article>
+<footer>Copyright</footer></body></html>"#;
+
+    #[test]
+    fn test_extract() {
+        let url = Url::parse("https://example.com/blog/post").unwrap();
+        let clipping = extract(PAGE, &url);
+        assert_eq!(clipping.title, "A Post: Part 1");
+        assert_eq!(
+            clipping.markdown,
+            "# A Post\n\n\
+             Some **bold** text with a [relative link](https://example.com/docs/page.html).\n\n\
+             - one\n- two *three*\n\n\
+             ```\nfn main() {}\n```\n\n\
+             > quoted\n\n\
+             ![A picture](https://example.com/blog/img/pic.png)"
+        );
+    }
+
+    #[test]
+    fn test_content_root_scores_paragraphs() {
+        let html = "<body><div><p>short</p></div><div class=\"post\"><p>a much longer paragraph</p><p>and another</p></div></body>";
+        let document = Html::parse_document(html);
+        assert_eq!(content_root(&document).value().attr("class"), Some("post"));
+    }
+
+    #[test]
+    fn test_note_name_and_url() {
+        assert_eq!(note_name("A Post: Part 1 | Blog"), "A Post Part 1 Blog");
+        assert_eq!(note_name("???"), "Clipping");
+        assert!(parse_source_url("ftp://example.com").is_err());
+        assert!(parse_source_url("https://example.com").is_ok());
+    }
+
+    #[test]
+    fn test_extract_decodes_entities() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let html = "<html><head><title>Tom &amp; Jerry&#39;s &quot;Best&quot;</title></head>\
+                    <body><article><p>Fish &amp; chips&nbsp;&mdash; cheap</p></article></body></html>";
+        let clipping = extract(html, &url);
+        assert_eq!(clipping.title, "Tom & Jerry's \"Best\"");
+        assert_eq!(clipping.markdown, "Fish & chips — cheap");
+    }
+
+    #[test]
+    fn test_fetch_rejects_local_addresses() {
+        for url in [
+            "http://127.0.0.1:9/",
+            "http://localhost:9/",
+            "http://10.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+        ] {
+            let error = fetch(&Url::parse(url).unwrap()).unwrap_err();
+            assert!(
+                error.to_string().contains("not a public address"),
+                "{}",
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,487 @@
+//! Time tracking. `clock in` appends an open `- CLOCK: [2024-05-01 09:00]` entry to the
+//! note, or to the `[clock] log` note with a link to it, and `clock out` closes it as
+//! `- CLOCK: [2024-05-01 09:00]--[2024-05-01 10:30] => 1:30`. Closed entries are kept in
+//! the index, and `timesheet` totals them per note or tag.
+
+use crate::capture;
+use crate::changeset::ChangeSet;
+use crate::cli::{ClockCommand, ClockInArgs, OutputFormat, TimesheetArgs, TimesheetGroup};
+use crate::config::AppConfig;
+use crate::data::Note;
+use crate::date_query;
+use crate::index;
+use crate::resolver::Resolver;
+use crate::search;
+use crate::util;
+
+use jiff::{
+    Timestamp, Zoned, civil,
+    tz::{Disambiguation, TimeZone},
+};
+use regex::Regex;
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+static CLOCK_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\s*[-*] CLOCK: \[(\d{4}-\d{2}-\d{2} \d{2}:\d{2})\](?:--\[(\d{4}-\d{2}-\d{2} \d{2}:\d{2})\](?: => +\d+:\d{2})?)?(?: \[\[([^\]|#]+)[^\]]*\]\])?\s*$",
+    )
+    .expect("valid regex")
+});
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// A closed clock entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockEntry {
+    /// Link target of an entry in a time log, naming the note the time was spent on
+    pub target: Option<String>,
+    /// Milliseconds since the epoch
+    pub start: i64,
+    pub end: i64,
+}
+
+fn parse_time(text: &str, tz: &TimeZone, disambiguation: Disambiguation) -> Option<Timestamp> {
+    let datetime = civil::DateTime::strptime(TIME_FORMAT, text).ok()?;
+    let zoned = tz.to_ambiguous_zoned(datetime).disambiguate(disambiguation);
+    Some(zoned.ok()?.timestamp())
+}
+
+fn format_time(timestamp: Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::system())
+        .strftime(TIME_FORMAT)
+        .to_string()
+}
+
+/// `minutes` as hours and minutes, e.g. "1:05".
+pub fn format_duration(minutes: i64) -> String {
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// The closed clock entries of `note`.
+pub fn note_entries(note: &Note) -> Vec<ClockEntry> {
+    entries_in(note, &TimeZone::system())
+}
+
+fn entries_in(note: &Note, tz: &TimeZone) -> Vec<ClockEntry> {
+    note.content
+        .lines()
+        .filter_map(|line| {
+            let captures = CLOCK_LINE.captures(line)?;
+            let mut start = parse_time(&captures[1], tz, Disambiguation::Compatible)?;
+            let end = parse_time(captures.get(2)?.as_str(), tz, Disambiguation::Compatible)?;
+            // A start typed into a DST gap reads as after the gap, maybe past the end
+            if start > end {
+                start = parse_time(&captures[1], tz, Disambiguation::Earlier)?;
+            }
+            Some(ClockEntry {
+                target: captures.get(3).map(|m| m.as_str().trim().to_string()),
+                start: start.as_millisecond(),
+                end: end.as_millisecond(),
+            })
+        })
+        .filter(|entry| entry.end >= entry.start)
+        .collect()
+}
+
+/// Stores the closed clock entries of `note` in the index.
+pub fn index_entries(connection: &Connection, note: &Note) -> Result<(), sqlite::Error> {
+    let path = note.path.to_string_lossy().replace('\\', "/");
+    for entry in note_entries(note) {
+        let mut statement = connection
+            .prepare("INSERT INTO clock_entries (path, target, start, end) VALUES (?, ?, ?, ?)")?;
+        statement.bind((1, path.as_str()))?;
+        statement.bind((2, entry.target.as_deref()))?;
+        statement.bind((3, entry.start))?;
+        statement.bind((4, entry.end))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS clock_running (
+            note TEXT NOT NULL,
+            log TEXT NOT NULL,
+            started INTEGER NOT NULL
+        );",
+    )
+}
+
+/// The clock currently running
+struct RunningClock {
+    /// Note clocked in to
+    note: String,
+    /// File holding its open entry
+    log: String,
+    /// Milliseconds since the epoch
+    started: i64,
+}
+
+fn running(connection: &Connection) -> Result<Option<RunningClock>, Box<dyn Error>> {
+    let mut statement = connection.prepare("SELECT note, log, started FROM clock_running")?;
+    if let State::Row = statement.next()? {
+        return Ok(Some(RunningClock {
+            note: statement.read(0)?,
+            log: statement.read(1)?,
+            started: statement.read(2)?,
+        }));
+    }
+    Ok(None)
+}
+
+/// `content` with `entry` appended as a line of its own.
+fn append_line(content: &str, entry: &str) -> String {
+    let mut updated = content.to_string();
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push_str(entry);
+    updated.push('\n');
+    updated
+}
+
+/// `content` with its last open entry started at `start` closed at `end`.
+pub fn close_entry(content: &str, start: Timestamp, end: Timestamp) -> Option<String> {
+    let start_text = format_time(start);
+    let mut lines: Vec<String> = content.split_inclusive('\n').map(String::from).collect();
+    let i = lines.iter().rposition(|line| {
+        CLOCK_LINE
+            .captures(line.trim_end())
+            .is_some_and(|captures| captures[1] == start_text && captures.get(2).is_none())
+    })?;
+    let minutes = end.duration_since(start).as_mins().max(0);
+    let marker = format!("[{}]", start_text);
+    lines[i] = lines[i].replacen(
+        &marker,
+        &format!(
+            "{}--[{}] => {}",
+            marker,
+            format_time(end),
+            format_duration(minutes)
+        ),
+        1,
+    );
+    Some(lines.concat())
+}
+
+fn clock_in(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &ClockInArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    if running(&connection)?.is_some() {
+        clock_out(vault_path, config)?;
+    }
+    let resolver = Resolver::from_vault(vault_path)?;
+    let note = resolver
+        .resolve(&args.note, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", args.note))?
+        .to_path_buf();
+    let now = Timestamp::now();
+    let (file, entry) = match &config.clock.log {
+        Some(log) => (
+            PathBuf::from(log),
+            format!(
+                "- CLOCK: [{}] [[{}]]",
+                format_time(now),
+                resolver.shortest_link(&note)
+            ),
+        ),
+        None => (note.clone(), format!("- CLOCK: [{}]", format_time(now))),
+    };
+    capture::ensure_inside_vault(&file)?;
+    let before = fs::read_to_string(vault_path.join(&file)).ok();
+    let after = append_line(before.as_deref().unwrap_or_default(), &entry);
+    let mut changes = ChangeSet::new();
+    changes.propose(&file, before, after);
+    changes.apply(vault_path, "clock in", false)?;
+
+    let mut statement =
+        connection.prepare("INSERT INTO clock_running (note, log, started) VALUES (?, ?, ?)")?;
+    statement.bind((1, note.to_string_lossy().replace('\\', "/").as_str()))?;
+    statement.bind((2, file.to_string_lossy().replace('\\', "/").as_str()))?;
+    statement.bind((3, now.as_millisecond()))?;
+    statement.next()?;
+    println!("Clocked in to {} at {}", note.display(), format_time(now));
+    Ok(())
+}
+
+fn clock_out(vault_path: &Path, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    let RunningClock {
+        note,
+        log: file,
+        started,
+    } = running(&connection)?.ok_or("No clock is running")?;
+    let start = Timestamp::from_millisecond(started)?;
+    let end = Timestamp::now();
+    let before = fs::read_to_string(vault_path.join(&file))?;
+    let after = close_entry(&before, start, end).ok_or_else(|| {
+        format!(
+            "The open clock entry for {} is gone from {}",
+            format_time(start),
+            file
+        )
+    })?;
+    let mut changes = ChangeSet::new();
+    changes.propose(&file, Some(before), after.clone());
+    changes.apply(vault_path, "clock out", false)?;
+    connection.execute("DELETE FROM clock_running")?;
+    let updated = Note::from_content(PathBuf::from(&file), after);
+    if let Err(e) = index::update_note(&connection, &updated) {
+        log::warn!("Could not update the index for {}: {}", file, e);
+    }
+    let minutes = end.duration_since(start).as_mins().max(0);
+    println!("Clocked out of {} after {}", note, format_duration(minutes));
+    Ok(())
+}
+
+fn status(config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    match running(&connection)? {
+        Some(RunningClock { note, started, .. }) => {
+            let start = Timestamp::from_millisecond(started)?;
+            let minutes = Timestamp::now().duration_since(start).as_mins().max(0);
+            println!(
+                "{} since {} ({})",
+                note,
+                format_time(start),
+                format_duration(minutes)
+            );
+        }
+        None => println!("No clock is running"),
+    }
+    Ok(())
+}
+
+pub fn run_clock(
+    vault_path: &Path,
+    config: &AppConfig,
+    command: &ClockCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        ClockCommand::In(args) => clock_in(vault_path, config, args),
+        ClockCommand::Out => clock_out(vault_path, config),
+        ClockCommand::Status => status(config),
+    }
+}
+
+/// Time spent on one note or tag
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TimeTotal {
+    pub name: String,
+    pub minutes: i64,
+}
+
+/// Indexed clock entries overlapping `from..until`, cut to it, with the vault-relative
+/// path of the note each was spent on.
+fn entries_between(
+    connection: &Connection,
+    resolver: &Resolver,
+    from: i64,
+    until: i64,
+) -> Result<Vec<(PathBuf, i64)>, Box<dyn Error>> {
+    let mut statement = connection.prepare(
+        "SELECT path, target, start, end FROM clock_entries WHERE end > ? AND start < ?",
+    )?;
+    statement.bind((1, from))?;
+    statement.bind((2, until))?;
+    let mut entries = Vec::new();
+    while let State::Row = statement.next()? {
+        let path = PathBuf::from(statement.read::<String, _>(0)?);
+        let target: Option<String> = statement.read(1)?;
+        let start = statement.read::<i64, _>(2)?.max(from);
+        let end = statement.read::<i64, _>(3)?.min(until);
+        let note = match target {
+            Some(target) => match resolver.resolve(&target, &path) {
+                Some(note) => note.to_path_buf(),
+                None => PathBuf::from(target),
+            },
+            None => path,
+        };
+        entries.push((note, end - start));
+    }
+    Ok(entries)
+}
+
+/// Sums `entries` (note, milliseconds) per group, most time first.
+pub fn totals(
+    entries: &[(PathBuf, i64)],
+    group: TimesheetGroup,
+    tags_of: impl Fn(&Path) -> Vec<String>,
+) -> Vec<TimeTotal> {
+    let mut sums: BTreeMap<String, i64> = BTreeMap::new();
+    // Read once per note rather than per entry
+    let mut tags: HashMap<&Path, Vec<String>> = HashMap::new();
+    for (note, ms) in entries {
+        let names = match group {
+            TimesheetGroup::Note => vec![note.to_string_lossy().replace('\\', "/")],
+            TimesheetGroup::Tag => {
+                let note_tags = tags.entry(note).or_insert_with(|| tags_of(note));
+                match note_tags.is_empty() {
+                    true => vec![String::from("(untagged)")],
+                    false => note_tags.iter().map(|tag| format!("#{}", tag)).collect(),
+                }
+            }
+        };
+        for name in names {
+            *sums.entry(name).or_default() += ms;
+        }
+    }
+    let mut totals: Vec<TimeTotal> = sums
+        .into_iter()
+        .map(|(name, ms)| TimeTotal {
+            name,
+            minutes: ms / 60_000,
+        })
+        .collect();
+    totals.sort_by(|a, b| b.minutes.cmp(&a.minutes).then(a.name.cmp(&b.name)));
+    totals
+}
+
+/// Prints the time clocked in the period per note or tag, the running clock included.
+pub fn run_timesheet(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &TimesheetArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    let text = match args.week {
+        true => "this-week",
+        false => args.period.as_str(),
+    };
+    let (first, last) = date_query::period(text, Zoned::now().date())?;
+    let from = first
+        .to_zoned(TimeZone::system())?
+        .timestamp()
+        .as_millisecond();
+    let until = last
+        .to_zoned(TimeZone::system())?
+        .timestamp()
+        .as_millisecond();
+
+    let resolver = Resolver::from_vault(vault_path)?;
+    let mut entries = entries_between(&connection, &resolver, from, until)?;
+    if let Some(RunningClock { note, started, .. }) = running(&connection)? {
+        let now = Timestamp::now().as_millisecond().min(until);
+        if now > started.max(from) {
+            entries.push((PathBuf::from(note), now - started.max(from)));
+        }
+    }
+    let tags_of = |note: &Path| match fs::read_to_string(vault_path.join(note)) {
+        Ok(content) => Note::from_content(note.to_path_buf(), content).tags,
+        Err(_) => Vec::new(),
+    };
+    let totals = totals(&entries, args.by, tags_of);
+
+    match args.format {
+        OutputFormat::Text => {
+            for total in &totals {
+                println!("{:>7}  {}", format_duration(total.minutes), total.name);
+            }
+            if args.by == TimesheetGroup::Note {
+                let minutes: i64 = totals.iter().map(|total| total.minutes).sum();
+                println!("{:>7}  total", format_duration(minutes));
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&totals)?),
+        OutputFormat::Ndjson => {
+            for total in &totals {
+                util::print_ndjson(total)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> Timestamp {
+        parse_time(text, &TimeZone::system(), Disambiguation::Compatible).unwrap()
+    }
+
+    #[test]
+    fn test_close_and_parse_entries() {
+        let content = "# Work\n- CLOCK: [2024-05-01 09:00]--[2024-05-01 09:30] => 0:30\n\
+                       - CLOCK: [2024-05-01 13:00] [[Project X]]\n";
+        let closed = close_entry(content, at("2024-05-01 13:00"), at("2024-05-01 14:05")).unwrap();
+        assert_eq!(
+            closed,
+            "# Work\n- CLOCK: [2024-05-01 09:00]--[2024-05-01 09:30] => 0:30\n\
+             - CLOCK: [2024-05-01 13:00]--[2024-05-01 14:05] => 1:05 [[Project X]]\n"
+        );
+        assert_eq!(
+            close_entry(content, at("2024-05-01 09:00"), at("2024-05-01 10:00")),
+            None
+        );
+
+        let note = Note::from_content(PathBuf::from("Log.md"), closed);
+        let entries = note_entries(&note);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].target, None);
+        assert_eq!(entries[1].target.as_deref(), Some("Project X"));
+        assert_eq!(entries[1].end - entries[1].start, 65 * 60_000);
+    }
+
+    #[test]
+    fn test_entries_across_a_dst_gap() {
+        // Clocks skip from 02:00 to 03:00 on 2024-03-10
+        let tz = TimeZone::posix("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        let note = Note::from_content(
+            PathBuf::from("Log.md"),
+            String::from(
+                "- CLOCK: [2024-03-10 02:30]--[2024-03-10 03:10] => 0:40\n\
+                 - CLOCK: [2024-03-10 01:30]--[2024-03-10 03:30] => 1:00\n",
+            ),
+        );
+        let minutes: Vec<i64> = entries_in(&note, &tz)
+            .iter()
+            .map(|entry| (entry.end - entry.start) / 60_000)
+            .collect();
+        assert_eq!(minutes, vec![40, 60]);
+    }
+
+    #[test]
+    fn test_totals() {
+        let entries = vec![
+            (PathBuf::from("A.md"), 30 * 60_000),
+            (PathBuf::from("B.md"), 90 * 60_000),
+            (PathBuf::from("A.md"), 45 * 60_000),
+        ];
+        let tags_of = |note: &Path| match note.to_str() {
+            Some("A.md") => vec![String::from("work"), String::from("client")],
+            _ => Vec::new(),
+        };
+        let by_note: Vec<(String, i64)> = totals(&entries, TimesheetGroup::Note, tags_of)
+            .into_iter()
+            .map(|total| (total.name, total.minutes))
+            .collect();
+        assert_eq!(
+            by_note,
+            vec![(String::from("B.md"), 90), (String::from("A.md"), 75)]
+        );
+        let by_tag: Vec<String> = totals(&entries, TimesheetGroup::Tag, tags_of)
+            .into_iter()
+            .map(|total| format!("{} {}", total.name, total.minutes))
+            .collect();
+        assert_eq!(by_tag, vec!["(untagged) 90", "#client 75", "#work 75"]);
+        assert_eq!(format_duration(65), "1:05");
+    }
+}
@@ -0,0 +1,259 @@
+//! Language-aware ordering and matching, set under `[collation]`: titles sort by the
+//! vault's alphabet rather than by bytes, accents can be ignored when matching, and the
+//! full-text index can use a tokenizer suited to languages written without spaces or stem
+//! English words. Common words of the vault's language are left out of search queries.
+
+use crate::config::CollationConfig;
+use crate::util;
+
+use sqlite::{Connection, State};
+use std::{cmp::Ordering, error::Error, sync::OnceLock};
+
+static SETTINGS: OnceLock<CollationConfig> = OnceLock::new();
+
+pub fn install(config: &CollationConfig) {
+    let _ = SETTINGS.set(config.clone());
+}
+
+fn settings() -> &'static CollationConfig {
+    SETTINGS.get_or_init(CollationConfig::default)
+}
+
+/// Identifies the installed settings, so cached query results follow changes to them.
+pub fn fingerprint() -> String {
+    util::content_hash(format!("{:?}", settings()).as_bytes())
+}
+
+/// Letters an alphabet orders apart from their base letter, with the keys they sort as.
+/// `{`, `|` and `}` come right after `z`.
+fn tailoring(locale: &str) -> &'static [(char, &'static str)] {
+    let language = locale.split(['-', '_']).next().unwrap_or_default();
+    match language {
+        "sv" | "fi" => &[('å', "{"), ('ä', "|"), ('æ', "|"), ('ö', "}"), ('ø', "}")],
+        "da" | "nb" | "nn" | "no" => &[('æ', "{"), ('ä', "{"), ('ø', "|"), ('ö', "|"), ('å', "}")],
+        "es" => &[('ñ', "n~")],
+        _ => &[],
+    }
+}
+
+/// Sort key of `text` for `locale`: lowercase, with letters transliterated to their base
+/// Latin letters unless the alphabet orders them on their own.
+pub fn sort_key_for(text: &str, locale: Option<&str>) -> String {
+    let tailoring = tailoring(locale.unwrap_or_default());
+    let mut key = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if let Some((_, tailored)) = tailoring.iter().find(|(letter, _)| *letter == c) {
+            key.push_str(tailored);
+        } else if c.is_ascii() {
+            key.push(c);
+        } else {
+            match deunicode::deunicode_char(c) {
+                Some(latin) if !latin.trim().is_empty() => {
+                    key.push_str(&latin.trim().to_lowercase())
+                }
+                _ => key.push(c),
+            }
+        }
+    }
+    key
+}
+
+pub fn sort_key(text: &str) -> String {
+    sort_key_for(text, settings().locale.as_deref())
+}
+
+/// Orders `a` and `b` by their sort keys, then byte-wise so the order is total.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    sort_key(a).cmp(&sort_key(b)).then_with(|| a.cmp(b))
+}
+
+/// `text` lowercased for matching, with accents on Latin letters removed when
+/// `fold_diacritics` is on.
+pub fn fold(text: &str) -> String {
+    let lower = text.to_lowercase();
+    if !settings().fold_diacritics || lower.is_ascii() {
+        return lower;
+    }
+    lower
+        .chars()
+        .map(|c| match c {
+            // Latin-1 Supplement and Latin Extended-A/B
+            '\u{c0}'..='\u{24f}' => match deunicode::deunicode_char(c) {
+                Some(latin) if latin.len() == 1 => latin.chars().next().unwrap_or(c),
+                _ => c,
+            },
+            c => c,
+        })
+        .collect()
+}
+
+/// Common words of the languages with a built-in stop-word list
+fn language_stop_words(language: &str) -> &'static [&'static str] {
+    match language.split(['-', '_']).next().unwrap_or_default() {
+        "en" => &[
+            "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "has", "in", "is",
+            "it", "of", "on", "or", "that", "the", "to", "was", "were", "with",
+        ],
+        "de" => &[
+            "aber", "auch", "auf", "aus", "das", "dass", "dem", "den", "der", "die", "ein", "eine",
+            "einen", "es", "ist", "im", "in", "mit", "nicht", "oder", "sich", "sie", "und", "von",
+            "zu",
+        ],
+        "fr" => &[
+            "au", "aux", "avec", "ce", "dans", "de", "des", "du", "elle", "en", "est", "et", "il",
+            "la", "le", "les", "mais", "ou", "par", "pas", "pour", "que", "qui", "sur", "un",
+            "une",
+        ],
+        "es" => &[
+            "a", "al", "como", "con", "de", "del", "el", "en", "es", "la", "las", "lo", "los",
+            "no", "o", "para", "por", "que", "se", "su", "un", "una", "y",
+        ],
+        _ => &[],
+    }
+}
+
+/// Whether `word` is left out of search queries.
+pub fn is_stop_word(word: &str) -> bool {
+    let settings = settings();
+    let word = word.to_lowercase();
+    settings
+        .language
+        .as_deref()
+        .is_some_and(|language| language_stop_words(language).contains(&word.as_str()))
+        || settings
+            .stop_words
+            .iter()
+            .any(|stop| stop.to_lowercase() == word)
+}
+
+/// The `tokenize` option of the full-text table, or `None` for SQLite's default
+/// (`unicode61`, which already ignores accents).
+fn tokenize_option(settings: &CollationConfig) -> Result<Option<String>, Box<dyn Error>> {
+    let base = match (settings.tokenizer.as_str(), settings.fold_diacritics) {
+        ("unicode61", true) => "unicode61",
+        ("unicode61", false) => "unicode61 remove_diacritics 0",
+        // remove_diacritics needs SQLite 3.45 for trigram, newer than many systems ship
+        ("trigram", _) => "trigram",
+        (other, _) => {
+            return Err(format!(
+                "Unknown tokenizer '{}'; expected unicode61 or trigram",
+                other
+            )
+            .into());
+        }
+    };
+    if !settings.stemming {
+        return Ok((base != "unicode61").then(|| base.to_string()));
+    }
+    if base == "trigram" {
+        return Err(
+            "Stemming works on words, so it cannot be used with the trigram tokenizer".into(),
+        );
+    }
+    if let Some(language) = &settings.language
+        && !language.starts_with("en")
+    {
+        return Err(format!("Stemming is only available for English, not '{}'", language).into());
+    }
+    Ok(Some(format!("porter {}", base)))
+}
+
+/// Recreates the full-text table when it was built with another tokenizer than the
+/// settings ask for, re-reading the stored chunks into it.
+pub fn ensure_tokenizer(
+    connection: &Connection,
+    settings: &CollationConfig,
+) -> Result<(), Box<dyn Error>> {
+    let wanted = tokenize_option(settings)?;
+    let sql = {
+        let mut statement =
+            connection.prepare("SELECT sql FROM sqlite_master WHERE name = 'chunks_fts'")?;
+        match statement.next()? {
+            State::Row => statement.read::<String, _>(0)?,
+            State::Done => String::new(),
+        }
+    };
+    let current = sql
+        .split_once("tokenize='")
+        .and_then(|(_, rest)| rest.split_once('\''))
+        .map(|(option, _)| option.to_string());
+    if current == wanted {
+        return Ok(());
+    }
+
+    log::info!(
+        "Rebuilding the full-text index for the new tokenizer settings ({})",
+        wanted.as_deref().unwrap_or("unicode61")
+    );
+    let tokenize = wanted
+        .map(|option| format!(", tokenize='{}'", option))
+        .unwrap_or_default();
+    connection.execute(format!(
+        "DROP TABLE IF EXISTS chunks_fts;
+         CREATE VIRTUAL TABLE chunks_fts USING fts5(
+             text, heading, title, content='chunks', content_rowid='id'{}
+         );
+         INSERT INTO chunks_fts(chunks_fts) VALUES('rebuild');",
+        tokenize
+    ))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Note;
+    use crate::index;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_sort_keys_and_tokenizer() {
+        let mut words = vec!["Öl", "Zebra", "Äpfel", "apple", "Ångström"];
+        words.sort_by_key(|word| sort_key_for(word, Some("de")));
+        assert_eq!(words, ["Ångström", "Äpfel", "apple", "Öl", "Zebra"]);
+        words.sort_by_key(|word| sort_key_for(word, Some("sv-SE")));
+        assert_eq!(words, ["apple", "Zebra", "Ångström", "Äpfel", "Öl"]);
+        assert!(sort_key_for("ñu", Some("es")) > sort_key_for("nz", Some("es")));
+
+        let note = Note::from_content(
+            PathBuf::from("Tokyo.md"),
+            String::from("東京都の天気は晴れです\n"),
+        );
+        let connection = index::test_connection(&[note]);
+        let hits = |query| {
+            index::keyword_search(&connection, query, 5, index::SnippetOptions::default())
+                .unwrap()
+                .len()
+        };
+        assert_eq!(hits("天気は"), 0);
+        let trigram = CollationConfig {
+            tokenizer: String::from("trigram"),
+            ..CollationConfig::default()
+        };
+        ensure_tokenizer(&connection, &trigram).unwrap();
+        assert_eq!(hits("天気は"), 1);
+        // Already built with it, so nothing to do
+        ensure_tokenizer(&connection, &trigram).unwrap();
+        assert_eq!(hits("天気は"), 1);
+    }
+
+    #[test]
+    fn test_stemming_and_stop_words() {
+        let stemmed = CollationConfig {
+            stemming: true,
+            ..CollationConfig::default()
+        };
+        assert_eq!(
+            tokenize_option(&stemmed).unwrap().as_deref(),
+            Some("porter unicode61")
+        );
+        assert!(
+            tokenize_option(&CollationConfig {
+                tokenizer: String::from("trigram"),
+                ..stemmed
+            })
+            .is_err()
+        );
+        assert_eq!(language_stop_words("en-GB").first(), Some(&"a"));
+    }
+}
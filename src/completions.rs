@@ -0,0 +1,248 @@
+//! Completion candidates for editors: note names and aliases after `[[`, tags after `#`,
+//! and the headings and block ids of a note after `[[Note#`. They are stored in the index
+//! as notes are indexed, so answering a request reads no notes.
+
+use crate::collation;
+use crate::data::Note;
+use crate::markdown;
+
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{cmp::Reverse, collections::BTreeMap, path::Path};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// Text to insert
+    pub label: String,
+    /// Where it comes from: the note path, or the number of notes for tags
+    pub detail: String,
+}
+
+/// Candidates within one note, as [`headings`] and [`blocks`] look them up
+pub type NoteCandidates =
+    fn(&Connection, &Path, &str, usize) -> Result<Vec<Candidate>, sqlite::Error>;
+
+/// The names (file stem, title, aliases), tags, headings and block ids of `note`, each
+/// with its kind, in document order.
+fn items(note: &Note) -> Vec<(&'static str, String)> {
+    let mut names: Vec<String> = note
+        .path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .into_iter()
+        .chain(note.front_matter.title.clone())
+        .chain(note.front_matter.aliases.iter().cloned())
+        .collect();
+    names.dedup();
+    let blocks = markdown::body_lines(&note.content)
+        .into_iter()
+        .filter_map(|(_, _, line)| {
+            let id = line.split_whitespace().last()?.strip_prefix('^')?;
+            let valid = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+            valid.then(|| id.to_string())
+        });
+    let headings = markdown::parse_headings(&note.content)
+        .into_iter()
+        .map(|heading| heading.text);
+    names
+        .into_iter()
+        .map(|name| ("name", name))
+        .chain(note.tags.iter().map(|tag| ("tag", tag.clone())))
+        .chain(headings.map(|heading| ("heading", heading)))
+        .chain(blocks.map(|block| ("block", block)))
+        .collect()
+}
+
+/// Stores what `note` offers for completion in the index.
+pub fn index_items(connection: &Connection, note: &Note) -> Result<(), sqlite::Error> {
+    let path = note.path.to_string_lossy().replace('\\', "/");
+    for (kind, label) in items(note) {
+        let mut statement = connection
+            .prepare("INSERT INTO completion_items (path, kind, label) VALUES (?, ?, ?)")?;
+        statement.bind((1, path.as_str()))?;
+        statement.bind((2, kind))?;
+        statement.bind((3, label.as_str()))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// Indexed items of `kind` as (path, label), in document order, only those of the note
+/// at `path` when given.
+fn stored(
+    connection: &Connection,
+    kind: &str,
+    path: Option<&str>,
+) -> Result<Vec<(String, String)>, sqlite::Error> {
+    let mut statement = connection.prepare(
+        "SELECT path, label FROM completion_items
+         WHERE kind = ? AND (? IS NULL OR path = ?) ORDER BY rowid",
+    )?;
+    statement.bind((1, kind))?;
+    statement.bind((2, path))?;
+    statement.bind((3, path))?;
+    let mut items = Vec::new();
+    while let State::Row = statement.next()? {
+        items.push((
+            statement.read::<String, _>(0)?,
+            statement.read::<String, _>(1)?,
+        ));
+    }
+    Ok(items)
+}
+
+/// Whether `label` is offered for `prefix`, and how well: prefix matches before matches
+/// elsewhere in the label, both case-insensitive.
+fn rank(label: &str, prefix: &str) -> Option<u8> {
+    let (label, prefix) = (collation::fold(label), collation::fold(prefix));
+    if label.starts_with(&prefix) {
+        Some(0)
+    } else if label.contains(&prefix) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+fn ranked(mut candidates: Vec<(u8, Candidate)>, limit: usize) -> Vec<Candidate> {
+    candidates.sort_by(|(a_rank, a), (b_rank, b)| {
+        a_rank
+            .cmp(b_rank)
+            .then_with(|| collation::compare(&a.label, &b.label))
+            .then_with(|| a.detail.cmp(&b.detail))
+    });
+    candidates.dedup_by(|(_, a), (_, b)| a == b);
+    candidates.truncate(limit);
+    candidates
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Note names, titles and aliases for `[[`
+pub fn notes(
+    connection: &Connection,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<Candidate>, sqlite::Error> {
+    let candidates = stored(connection, "name", None)?
+        .into_iter()
+        .filter_map(|(path, name)| {
+            let rank = rank(&name, prefix)?;
+            Some((
+                rank,
+                Candidate {
+                    label: name,
+                    detail: path,
+                },
+            ))
+        })
+        .collect();
+    Ok(ranked(candidates, limit))
+}
+
+/// Tags for `#`, most used first among equally good matches
+pub fn tags(
+    connection: &Connection,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<Candidate>, sqlite::Error> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for (_, tag) in stored(connection, "tag", None)? {
+        *counts.entry(tag).or_insert(0) += 1;
+    }
+    let prefix = prefix.trim_start_matches('#');
+    let mut candidates: Vec<(u8, usize, String)> = counts
+        .into_iter()
+        .filter_map(|(tag, count)| rank(&tag, prefix).map(|rank| (rank, count, tag)))
+        .collect();
+    candidates.sort_by_key(|&(rank, count, _)| (rank, Reverse(count)));
+    Ok(candidates
+        .into_iter()
+        .take(limit)
+        .map(|(_, count, tag)| Candidate {
+            label: tag,
+            detail: count.to_string(),
+        })
+        .collect())
+}
+
+/// Headings of the note at `path` for `[[Note#`, in document order
+pub fn headings(
+    connection: &Connection,
+    path: &Path,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<Candidate>, sqlite::Error> {
+    within(connection, "heading", path, prefix, limit)
+}
+
+/// Block ids of the note at `path` for `[[Note#^`, in document order
+pub fn blocks(
+    connection: &Connection,
+    path: &Path,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<Candidate>, sqlite::Error> {
+    within(
+        connection,
+        "block",
+        path,
+        prefix.trim_start_matches('^'),
+        limit,
+    )
+}
+
+fn within(
+    connection: &Connection,
+    kind: &str,
+    path: &Path,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<Candidate>, sqlite::Error> {
+    let path = path.to_string_lossy().replace('\\', "/");
+    Ok(stored(connection, kind, Some(&path))?
+        .into_iter()
+        .filter(|(_, item)| rank(item, prefix).is_some())
+        .take(limit)
+        .map(|(detail, label)| Candidate { label, detail })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_completions() {
+        let plan = Note::from_content(
+            PathBuf::from("Project Plan.md"),
+            String::from(
+                "---\naliases: [Roadmap]\ntags: [work]\n---\n# Goals\n## Risks\nShip ^ship-it\n#work/q3\n",
+            ),
+        );
+        let planning = Note::from_content(PathBuf::from("Planning.md"), String::from("#work\n"));
+        let connection = index::test_connection(&[plan.clone(), planning]);
+
+        let labels = |candidates: Result<Vec<Candidate>, sqlite::Error>| -> Vec<String> {
+            candidates.unwrap().into_iter().map(|c| c.label).collect()
+        };
+        assert_eq!(
+            labels(notes(&connection, "plan", 10)),
+            ["Planning", "Project Plan"]
+        );
+        assert_eq!(labels(notes(&connection, "road", 10)), ["Roadmap"]);
+        assert_eq!(labels(tags(&connection, "#wo", 10)), ["work", "work/q3"]);
+        let path = Path::new("Project Plan.md");
+        assert_eq!(
+            labels(headings(&connection, path, "", 10)),
+            ["Goals", "Risks"]
+        );
+        assert_eq!(labels(blocks(&connection, path, "^sh", 10)), ["ship-it"]);
+
+        index::update(&connection, &[plan]).unwrap();
+        assert_eq!(labels(notes(&connection, "plan", 10)), ["Project Plan"]);
+    }
+}
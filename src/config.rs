@@ -1,27 +1,938 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     error::Error,
     fs, io,
     path::{Path, PathBuf},
 };
 
+use crate::index::SnippetOptions;
 use crate::util;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default, Clone)]
 pub struct AppConfig {
     pub workspace: Workspace,
+    #[serde(default)]
+    pub lint: LintConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub capture: CaptureConfig,
+    #[serde(default)]
+    pub daily: DailyConfig,
+    #[serde(default)]
+    pub clip: ClipConfig,
+    #[serde(default)]
+    pub publish: PublishConfig,
+    #[serde(default)]
+    pub embeddings: EmbeddingsConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub watcher: WatcherConfig,
+    #[serde(default)]
+    pub status: StatusConfig,
+    #[serde(default)]
+    pub previews: PreviewsConfig,
+    #[serde(default)]
+    pub check_links: CheckLinksConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub images: ImagesConfig,
+    #[serde(default)]
+    pub ocr: OcrConfig,
+    #[serde(default)]
+    pub media: MediaConfig,
+    #[serde(default)]
+    pub collation: CollationConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    #[serde(default)]
+    pub reminders: RemindersConfig,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    #[serde(default)]
+    pub writing: WritingConfig,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    /// Kinds of typed notes by name, e.g. `[entities.person]`
+    #[serde(default)]
+    pub entities: BTreeMap<String, EntityConfig>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default, Clone)]
 pub struct Workspace {
     // name: String,
     pub root: String,
     // port: u16,
+    /// Vault-relative folder for attachments (Obsidian's "attachmentFolderPath")
+    pub attachments: Option<String>,
+    /// Refuse every command that would change vault files, e.g. for a synced vault
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Toggles for the individual `lint` rules
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LintConfig {
+    pub kebab_case_filenames: bool,
+    pub single_h1: bool,
+    pub no_bare_urls: bool,
+    pub wikilinks_only: bool,
+    pub heading_increment: bool,
+    /// Tables of contents between `<!-- toc -->` markers must match the headings
+    pub toc: bool,
+    /// Front matter keys every note must define
+    pub required_front_matter: Vec<String>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            kebab_case_filenames: false,
+            single_h1: true,
+            no_bare_urls: true,
+            wikilinks_only: false,
+            heading_increment: true,
+            toc: true,
+            required_front_matter: Vec::new(),
+        }
+    }
+}
+
+/// HTTP API served by the daemon; disabled unless `listen` is set
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Address to bind, e.g. "127.0.0.1:27123"
+    pub listen: Option<String>,
+    /// Serve a CouchDB-compatible endpoint under `/couchdb` for LiveSync clients
+    pub couchdb: bool,
+    pub local_rest: LocalRestConfig,
+    /// API keys clients must send; without any, requests are not authenticated and only a
+    /// loopback `listen` is served
+    pub keys: Vec<ApiKeyConfig>,
+    /// Serve HTTPS instead of plain HTTP (needs the `tls` feature)
+    pub tls: Option<TlsConfig>,
+    /// Requests a client address may make per minute
+    pub rate_limit: Option<u32>,
+    /// Origins browser front ends may call the API from, e.g. "http://localhost:5173"; "*" allows any, once `keys` are set
+    pub cors_origins: Vec<String>,
+    /// Folder of static files served under `/static/`, e.g. a browser front end
+    pub static_dir: Option<PathBuf>,
+    /// Address for the gRPC API (needs the `grpc` feature), e.g. "127.0.0.1:50051"
+    pub grpc_listen: Option<String>,
+}
+
+/// What an API key may do; writing includes reading
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    #[default]
+    Read,
+    Write,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiKeyConfig {
+    /// Environment variable holding the key
+    pub env: String,
+    #[serde(default)]
+    pub scope: Scope,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain
+    pub cert: PathBuf,
+    /// PEM private key
+    pub key: PathBuf,
+    /// PEM bundle of CAs client certificates must chain to; setting it requires them
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Listener speaking the Obsidian Local REST API plugin's protocol; disabled unless `listen` is set
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LocalRestConfig {
+    /// Address to bind; the plugin itself uses "127.0.0.1:27123"
+    pub listen: Option<String>,
+    /// Environment variable holding the API key clients send as a bearer token
+    pub api_key_env: String,
+}
+
+impl Default for LocalRestConfig {
+    fn default() -> Self {
+        LocalRestConfig {
+            listen: None,
+            api_key_env: String::from("OBSIDIAN_API_KEY"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// Vault-relative note that `capture` appends to
+    pub inbox: String,
+    /// Append to today's daily note instead of the inbox
+    pub daily: bool,
+    /// Template used when the inbox note does not exist yet
+    pub template: Option<String>,
+    /// strftime format of the timestamp in front of each captured bullet
+    pub timestamp_format: String,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            inbox: String::from("Inbox.md"),
+            daily: false,
+            template: None,
+            timestamp_format: String::from("%H:%M"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DailyConfig {
+    /// Vault-relative folder holding daily notes
+    pub folder: String,
+    /// strftime format of daily note names
+    pub format: String,
+    pub template: Option<String>,
+}
+
+impl Default for DailyConfig {
+    fn default() -> Self {
+        DailyConfig {
+            folder: String::new(),
+            format: String::from("%Y-%m-%d"),
+            template: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ClipConfig {
+    /// Vault-relative folder clipped pages are saved to
+    pub folder: String,
+    /// Tags added to the front matter of every clipping
+    pub tags: Vec<String>,
+}
+
+impl Default for ClipConfig {
+    fn default() -> Self {
+        ClipConfig {
+            folder: String::from("Clippings"),
+            tags: vec![String::from("clippings")],
+        }
+    }
+}
+
+/// What the cache database keeps besides the search index
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Keep compressed note bodies so reads skip the vault, e.g. on a network mount
+    pub store_content: bool,
+    /// Larger notes are always read from the vault
+    pub max_note_bytes: usize,
+    /// Compressed bodies stored in total; notes past it are read from the vault
+    pub max_total_bytes: usize,
+    /// Days of daily snapshots `stats` and `diff-index` keep; 0 keeps them all
+    pub snapshot_days: u32,
+    /// Queries whose answers are kept; the least recently run are forgotten past it
+    pub max_queries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            store_content: false,
+            max_note_bytes: 1 << 20,
+            max_total_bytes: 256 << 20,
+            snapshot_days: 365,
+            max_queries: 100,
+        }
+    }
+}
+
+/// Titles and descriptions of external pages linked from notes
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PreviewsConfig {
+    /// Fetch missing previews on request through the API, and add cached ones to HTML
+    /// exports; `previews fetch` works either way
+    pub enabled: bool,
+    /// Previews older than this are fetched again
+    pub ttl_days: u64,
+    /// Pause between two requests to the same host
+    pub host_interval_ms: u64,
+    /// Most pages fetched in one run
+    pub max_fetches: usize,
+}
+
+impl Default for PreviewsConfig {
+    fn default() -> Self {
+        PreviewsConfig {
+            enabled: false,
+            ttl_days: 30,
+            host_interval_ms: 1000,
+            max_fetches: 100,
+        }
+    }
+}
+
+/// How `check-links --external` requests web links
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CheckLinksConfig {
+    /// Only URLs matching one of these patterns (`*` wildcards) are checked, when set
+    pub allow: Vec<String>,
+    /// URLs matching one of these patterns are never checked
+    pub deny: Vec<String>,
+    /// Answers younger than this are reused instead of asking again
+    pub ttl_hours: u64,
+    pub timeout_secs: u64,
+    /// Retries after a failed connection or server error
+    pub retries: usize,
+    /// Requests made at once
+    pub jobs: usize,
+}
+
+impl Default for CheckLinksConfig {
+    fn default() -> Self {
+        CheckLinksConfig {
+            allow: Vec::new(),
+            deny: Vec::new(),
+            ttl_hours: 24,
+            timeout_secs: 10,
+            retries: 2,
+            jobs: 8,
+        }
+    }
+}
+
+/// Where `archive-links` keeps copies of external pages
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Base URL of a Wayback Machine compatible service, with `/save/` and `/web/` endpoints
+    pub service: String,
+    /// Saving a page can take a while
+    pub timeout_secs: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        ArchiveConfig {
+            service: String::from("https://web.archive.org"),
+            timeout_secs: 60,
+        }
+    }
+}
+
+/// Thumbnails and size limits for image attachments
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ImagesConfig {
+    /// Edge of the square thumbnails fit in, in pixels
+    pub thumbnail_size: u32,
+    /// Images larger than this are reported by `doctor`; 0 turns the check off
+    pub max_kb: u64,
+    /// Images wider or taller than this are reported too, and scaled down to it
+    pub max_dimension: u32,
+    /// JPEG quality `images recompress` writes
+    pub quality: u8,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        ImagesConfig {
+            thumbnail_size: 256,
+            max_kb: 2048,
+            max_dimension: 4096,
+            quality: 85,
+        }
+    }
+}
+
+/// Defaults for commands that publish notes to a website
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct PublishConfig {
+    /// Root URL the site is served from, e.g. "https://example.com/notes"
+    pub base_url: Option<String>,
+    /// Site title, defaults to the vault's folder name
+    pub title: Option<String>,
+    /// Program `export pdf` prints with: a Chromium-based browser or wkhtmltopdf. The
+    /// first of those found on the PATH by default.
+    pub pdf_engine: Option<String>,
+}
+
+/// Backend for `search --semantic`; semantic search is off until `backend` is set
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EmbeddingsConfig {
+    /// "ollama" or "openai" (any OpenAI-compatible server)
+    pub backend: Option<String>,
+    /// Base URL of the service, defaults to the backend's usual address
+    pub url: Option<String>,
+    pub model: String,
+    /// Environment variable holding the API key, if the service needs one
+    pub api_key_env: String,
+    /// Nearest neighbours stored per note while indexing, for instant related-note
+    /// lookups; 0 turns the pass off
+    pub neighbors: usize,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        EmbeddingsConfig {
+            backend: None,
+            url: None,
+            model: String::from("nomic-embed-text"),
+            api_key_env: String::from("OPENAI_API_KEY"),
+            neighbors: 0,
+        }
+    }
+}
+
+/// Text recognition in embedded images, for builds with the `ocr` feature; off until
+/// `backend` is set
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct OcrConfig {
+    /// "tesseract" (the command-line program) or "http"
+    pub backend: Option<String>,
+    /// For "http": endpoint the image bytes are POSTed to, answering with the text
+    pub url: Option<String>,
+    pub tesseract: String,
+    /// Tesseract languages, e.g. "eng+deu"
+    pub languages: String,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        OcrConfig {
+            backend: None,
+            url: None,
+            tesseract: String::from("tesseract"),
+            languages: String::from("eng"),
+        }
+    }
+}
+
+/// Audio and video attachments; transcripts are off until `transcribe` is set, and then
+/// made by the daemon, the `reindex` job and `media transcript` rather than searches
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct MediaConfig {
+    /// Shell command printing the transcript of `{{path}}` to stdout, e.g.
+    /// `whisper-cli -nt -np -m ~/models/ggml-base.en.bin -f {{path}}`
+    pub transcribe: Option<String>,
+}
+
+/// How titles sort and how search matches text in the vault's language
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CollationConfig {
+    /// Language whose alphabet orders titles, e.g. "sv" or "es"; elsewhere letters with
+    /// accents sort with their base letter
+    pub locale: Option<String>,
+    /// Full-text tokenizer: "unicode61" splits words at spaces and punctuation; "trigram"
+    /// matches any run of three or more characters, for Chinese, Japanese and Korean
+    pub tokenizer: String,
+    /// Whether "cafe" finds "café" (the trigram tokenizer always tells them apart)
+    pub fold_diacritics: bool,
+    /// Match word forms ("running" finds "runs") with the Porter stemmer, for English text
+    pub stemming: bool,
+    /// Language of the vault's text, e.g. "en" or "de", whose common words are left out of
+    /// search queries
+    pub language: Option<String>,
+    /// More words to leave out of search queries
+    pub stop_words: Vec<String>,
+}
+
+impl Default for CollationConfig {
+    fn default() -> Self {
+        CollationConfig {
+            locale: None,
+            tokenizer: String::from("unicode61"),
+            fold_diacritics: true,
+            stemming: false,
+            language: None,
+            stop_words: Vec::new(),
+        }
+    }
+}
+
+/// What search results show of the text they matched
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Words in each snippet
+    pub snippet_words: usize,
+    /// Snippets per result, each around a different match
+    pub snippets: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        let options = SnippetOptions::default();
+        SearchConfig {
+            snippet_words: options.words,
+            snippets: options.count,
+        }
+    }
+}
+
+impl SearchConfig {
+    pub fn snippet_options(&self) -> SnippetOptions {
+        SnippetOptions {
+            words: self.snippet_words,
+            count: self.snippets,
+        }
+    }
+}
+
+/// What a scheduled job does
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobTask {
+    /// Write `query` into `note` and refresh the note's query tables and maps of content
+    #[default]
+    Report,
+    /// Bring the search index up to date, PDF text and transcripts included, then check the
+    /// cache database for corruption
+    Reindex,
+    /// Write a zip archive of the vault into `[backup] dir` and upload the files that
+    /// changed to `[backup.remote]`
+    Backup,
+    /// Check internal and external links, refreshing the stored URL checks
+    CheckLinks,
+    /// Send a notification when notes match `query`, e.g. "due:today"
+    Notify,
+}
+
+/// A job the daemon runs on a schedule, e.g. under `[[schedule.jobs]]`
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScheduledJob {
+    pub name: String,
+    /// minute hour day-of-month month day-of-week, e.g. "0 8 * * mon-fri"
+    pub cron: String,
+    #[serde(default)]
+    pub task: JobTask,
+    /// Vault-relative note whose query tables and maps of content are refreshed, for
+    /// `report` jobs
+    pub note: Option<String>,
+    /// Notes to write into `note` as a table, or to notify about
+    pub query: Option<String>,
+    /// Columns of that table, as for `query --columns`
+    #[serde(default = "default_columns")]
+    pub columns: String,
+}
+
+fn default_columns() -> String {
+    String::from("title")
+}
+
+/// Jobs run by the daemon
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// Most seconds a job is held back at random, so jobs due together spread out
+    pub jitter_secs: u64,
+    pub jobs: Vec<ScheduledJob>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        ScheduleConfig {
+            jitter_secs: 30,
+            jobs: Vec::new(),
+        }
+    }
+}
+
+/// Zip archives written by `backup` jobs, and uploads to a remote store
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// Folder the archives are written to, e.g. "~/Backups/notes"
+    pub dir: Option<String>,
+    /// Archives kept; older ones are deleted after each backup
+    pub keep: usize,
+    /// age public key ("age1...") that archives and uploads are encrypted to; needs the
+    /// `backup-encrypt` feature
+    pub recipient: Option<String>,
+    /// Store that backups are uploaded to, under `[backup.remote]`
+    pub remote: Option<RemoteBackupConfig>,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            dir: None,
+            keep: 8,
+            recipient: None,
+            remote: None,
+        }
+    }
+}
+
+/// Kind of remote backup store
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteKind {
+    /// Amazon S3 or any S3-compatible store; needs the `backup-s3` feature
+    S3,
+    /// A WebDAV folder, e.g. on Nextcloud; needs the `backup-webdav` feature
+    Webdav,
+}
+
+/// A remote store that `backup` jobs upload changed files to
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemoteBackupConfig {
+    pub kind: RemoteKind,
+    /// For S3 the endpoint with the bucket, e.g. "https://s3.eu-central-1.amazonaws.com/notes";
+    /// for WebDAV the folder's URL
+    pub url: String,
+    /// Folder under `url` the backups go to; the vault's name when unset
+    pub prefix: Option<String>,
+    /// S3 region
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Environment variables holding the S3 access key pair
+    #[serde(default = "default_access_key_env")]
+    pub access_key_env: String,
+    #[serde(default = "default_secret_key_env")]
+    pub secret_key_env: String,
+    /// WebDAV user name
+    pub username: Option<String>,
+    /// Environment variable holding the WebDAV password
+    #[serde(default = "default_password_env")]
+    pub password_env: String,
+}
+
+fn default_region() -> String {
+    String::from("us-east-1")
+}
+
+fn default_access_key_env() -> String {
+    String::from("AWS_ACCESS_KEY_ID")
+}
+
+fn default_secret_key_env() -> String {
+    String::from("AWS_SECRET_ACCESS_KEY")
+}
+
+fn default_password_env() -> String {
+    String::from("WEBDAV_PASSWORD")
+}
+
+/// Where notifications go; each channel that is set receives every notification
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// Show desktop notifications; needs the `desktop-notify` feature
+    pub desktop: bool,
+    /// ntfy topic URL, e.g. "https://ntfy.sh/my-vault"
+    pub ntfy: Option<String>,
+    /// Gotify server URL, e.g. "https://gotify.example.com"
+    pub gotify: Option<String>,
+    /// Application token of the Gotify server
+    pub gotify_token: Option<String>,
+    /// URL that receives each notification as a JSON POST
+    pub webhook: Option<String>,
+    /// Notify when a scheduled job fails
+    pub job_failures: bool,
+    /// Notify when a sync tool leaves a conflict copy of a note
+    pub conflicts: bool,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            desktop: false,
+            ntfy: None,
+            gotify: None,
+            gotify_token: None,
+            webhook: None,
+            job_failures: true,
+            conflicts: true,
+        }
+    }
+}
+
+impl NotifyConfig {
+    /// Whether any channel is set
+    pub fn is_enabled(&self) -> bool {
+        self.desktop || self.ntfy.is_some() || self.gotify.is_some() || self.webhook.is_some()
+    }
+}
+
+/// When front matter reminders notify
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RemindersConfig {
+    /// Time of day, e.g. "09:00", that reminders without a time notify at
+    pub time: String,
+    /// Also notify on the day a note's `due` date comes
+    pub due: bool,
+}
+
+impl Default for RemindersConfig {
+    fn default() -> Self {
+        RemindersConfig {
+            time: String::from("09:00"),
+            due: true,
+        }
+    }
+}
+
+/// Where `clock` writes its entries
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ClockConfig {
+    /// Vault-relative note that gets every entry, with a link to the note clocked in to;
+    /// entries go into the clocked note itself when unset
+    pub log: Option<String>,
+}
+
+/// Word-count goals that `writing stats` reports progress against
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct WritingConfig {
+    /// Words to add across the vault each day; days that reach it extend the streak
+    pub daily_goal: Option<usize>,
+    /// Total words wanted in a note, by vault-relative path, e.g. `"Novel.md" = 50000`
+    pub goals: BTreeMap<String, usize>,
+}
+
+/// How `journal export` names this machine, and how long the journal keeps history
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct JournalConfig {
+    /// Shown on conflict markers where the other machine applies these changes; the
+    /// host name when unset
+    pub name: Option<String>,
+    /// Days before superseded journal rows are compacted away; 0 keeps them all
+    pub keep_days: u32,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        JournalConfig {
+            name: None,
+            keep_days: 90,
+        }
+    }
+}
+
+/// Vault changes a hook can react to
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HookEvent {
+    Created,
+    Modified,
+    Deleted,
+    /// The daemon finished (re-)indexing the vault
+    Indexed,
+}
+
+/// A command and/or webhook run on a vault event
+#[derive(Deserialize, Debug, Clone)]
+pub struct HookRule {
+    pub event: HookEvent,
+    /// Only fire for notes matching this query, e.g. "tag:#project"
+    pub query: Option<String>,
+    /// Shell command; `{{path}}`, `{{title}}`, `{{tags}}` and `{{event}}` become quoted
+    /// references to `$OBSIDIAN_PATH` and the like, so leave them unquoted
+    pub command: Option<String>,
+    /// URL that receives the event as a JSON POST
+    pub webhook: Option<String>,
+}
+
+/// Hooks run by the daemon's watcher
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Quiet time after the last file event before hooks fire, or, without hooks, before
+    /// the daemon re-indexes edited notes
+    pub debounce_ms: u64,
+    pub rules: Vec<HookRule>,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        HooksConfig {
+            debounce_ms: 500,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// How file changes are noticed
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatcherBackend {
+    /// Poll on network file systems or when the native watcher stays silent
+    #[default]
+    Auto,
+    /// inotify, FSEvents or ReadDirectoryChangesW
+    Native,
+    /// Rescan the vault every `poll_interval_ms`, comparing file hashes
+    Poll,
+}
+
+/// Kinds of file events the watcher can react to
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchedEvent {
+    Create,
+    /// Contents written
+    Modify,
+    Rename,
+    Remove,
+    /// Permissions, timestamps and other attributes changed
+    Metadata,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WatcherConfig {
+    pub backend: WatcherBackend,
+    pub poll_interval_ms: u64,
+    /// Event kinds that trigger re-indexing
+    pub events: Vec<WatchedEvent>,
+    /// Vault-relative paths whose events are dropped; `*` and `?` are wildcards, and
+    /// patterns without a `/` match any file or folder name
+    pub ignore: Vec<String>,
+    /// File extensions whose events are dropped, e.g. `["tmp", "swp"]`
+    pub ignore_extensions: Vec<String>,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        WatcherConfig {
+            backend: WatcherBackend::Auto,
+            poll_interval_ms: 2000,
+            events: vec![
+                WatchedEvent::Create,
+                WatchedEvent::Modify,
+                WatchedEvent::Rename,
+                WatchedEvent::Remove,
+            ],
+            // Rewritten constantly while Obsidian is open
+            ignore: vec![
+                String::from(".obsidian/workspace.json"),
+                String::from(".obsidian/workspace-mobile.json"),
+            ],
+            ignore_extensions: Vec::new(),
+        }
+    }
+}
+
+/// Where a note stands, whichever property convention the vault uses
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteStatus {
+    Draft,
+    Published,
+    Archived,
+}
+
+/// A front matter property value that gives a note its status
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct StatusRule {
+    pub property: String,
+    /// Value the property must have, or contain when it is a list (e.g. `cssclasses`)
+    pub value: toml::Value,
+    pub status: NoteStatus,
+}
+
+/// Rules mapping front matter to note status; the first matching rule wins
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StatusConfig {
+    pub rules: Vec<StatusRule>,
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        let rule = |property: &str, value: toml::Value, status| StatusRule {
+            property: property.to_string(),
+            value,
+            status,
+        };
+        StatusConfig {
+            rules: vec![
+                rule("archived", true.into(), NoteStatus::Archived),
+                rule("status", "archived".into(), NoteStatus::Archived),
+                rule("draft", true.into(), NoteStatus::Draft),
+                rule("status", "draft".into(), NoteStatus::Draft),
+                rule("publish", false.into(), NoteStatus::Draft),
+                rule("publish", true.into(), NoteStatus::Published),
+                rule("status", "published".into(), NoteStatus::Published),
+            ],
+        }
+    }
+}
+
+/// What makes a note an entity of one kind, and what to list about it
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EntityConfig {
+    /// Front matter property naming the kind of a note
+    pub property: String,
+    /// Values of `property` that mark this kind; the kind's name when empty
+    pub values: Vec<String>,
+    /// Vault-relative folders whose notes are all of this kind, e.g. "People/"
+    pub folders: Vec<String>,
+    /// Front matter fields listed for entities of this kind, e.g. ["email", "birthday"]
+    pub fields: Vec<String>,
+}
+
+impl Default for EntityConfig {
+    fn default() -> Self {
+        EntityConfig {
+            property: String::from("type"),
+            values: Vec::new(),
+            folders: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// WebAssembly plugins and Lua scripts; none are loaded unless their folder is set
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct PluginsConfig {
+    /// Folder of `.wasm` plugins, relative to the vault root
+    pub dir: Option<String>,
+    /// Folder of `.lua` scripts, relative to the vault root
+    pub scripts: Option<String>,
 }
 
 static DEFAULT_CONFIG_PATH: &str = ".config/obsidian-rs/config.toml";
 
-fn get_config_path() -> Option<String> {
+pub fn get_config_path() -> Option<String> {
     let home_dir = home::home_dir()?;
     let config_path = home_dir.join(DEFAULT_CONFIG_PATH);
     let config_string = config_path.to_str()?;
@@ -33,10 +944,19 @@ pub fn get_root_workspace_path(config: &AppConfig) -> Option<PathBuf> {
     util::expand_tilde(root_path).map(|expanded_cow| expanded_cow.into_owned()) // Convert Cow -> PathBuf
 }
 
-pub fn extract_config() -> Result<AppConfig, Box<dyn Error>> {
+/// Whether the configuration file exists at its default location.
+pub fn config_file_exists() -> bool {
+    get_config_path().is_some_and(|path| Path::new(&path).is_file())
+}
+
+/// Reads the configuration, with the settings of `profile` when one is given.
+pub fn extract_config(profile: Option<&str>) -> Result<AppConfig, Box<dyn Error>> {
     let config_path_str = get_config_path().ok_or("Failed to expand config path!")?;
-    let config_path = Path::new(&config_path_str);
+    load_config(Path::new(&config_path_str), profile)
+}
 
+/// Reads the configuration from `config_path` rather than the default location.
+pub fn load_config(config_path: &Path, profile: Option<&str>) -> Result<AppConfig, Box<dyn Error>> {
     let config_content = fs::read_to_string(config_path).map_err(|io_error| -> Box<dyn Error> {
         if io_error.kind() == io::ErrorKind::NotFound {
             let config_not_found_error = format!(
@@ -51,10 +971,48 @@ pub fn extract_config() -> Result<AppConfig, Box<dyn Error>> {
 
     log::debug!("Read config content: {}", config_content);
 
-    let config: AppConfig = toml::from_str(&config_content)?;
+    let table: toml::Table = toml::from_str(&config_content)?;
+    let config: AppConfig = toml::Value::Table(with_profile(table, profile)?).try_into()?;
+    Ok(config)
+}
+
+/// `config` with its `[profiles.<profile>]` table laid over it: tables are merged key by
+/// key, anything else (lists included) is replaced. The profiles themselves are dropped.
+pub fn with_profile(
+    mut config: toml::Table,
+    profile: Option<&str>,
+) -> Result<toml::Table, Box<dyn Error>> {
+    let profiles = config.remove("profiles");
+    let Some(name) = profile else {
+        return Ok(config);
+    };
+    let overlay = profiles
+        .as_ref()
+        .and_then(|profiles| profiles.get(name))
+        .and_then(toml::Value::as_table)
+        .ok_or_else(|| {
+            format!(
+                "No profile '{}' in the configuration; `obsidian-rs profile clear` forgets a saved one",
+                name
+            )
+        })?;
+    merge_tables(&mut config, overlay.clone());
     Ok(config)
 }
 
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(inner)), toml::Value::Table(value)) => {
+                merge_tables(inner, value)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,7 +1022,9 @@ mod tests {
         let config = AppConfig {
             workspace: Workspace {
                 root: String::from("~/tmp/test"),
+                ..Default::default()
             },
+            ..Default::default()
         };
         let result = get_root_workspace_path(&config);
 
@@ -78,4 +1038,59 @@ mod tests {
 
         assert_eq!(result.unwrap(), expected_path);
     }
+
+    #[test]
+    fn test_lint_section_is_optional() {
+        let config: AppConfig = toml::from_str("[workspace]\nroot = \"~/vault\"\n").unwrap();
+        assert!(config.lint.single_h1);
+        assert!(!config.lint.wikilinks_only);
+
+        let config: AppConfig = toml::from_str(
+            "[workspace]\nroot = \"~/vault\"\n[lint]\nwikilinks_only = true\n",
+        )
+        .unwrap();
+        assert!(config.lint.wikilinks_only);
+        assert!(config.lint.no_bare_urls);
+    }
+
+    #[test]
+    fn test_profile_overrides() {
+        let table: toml::Table = toml::from_str(
+            r#"
+            [workspace]
+            root = "~/notes"
+            [watcher]
+            ignore = ["drafts"]
+            poll_interval_ms = 500
+            [profiles.work.workspace]
+            root = "~/work"
+            read_only = true
+            [profiles.work.watcher]
+            ignore = ["clients/*"]
+            "#,
+        )
+        .unwrap();
+        let config: AppConfig =
+            toml::Value::Table(with_profile(table.clone(), Some("work")).unwrap())
+                .try_into()
+                .unwrap();
+        assert_eq!(config.workspace.root, "~/work");
+        assert!(config.workspace.read_only);
+        assert_eq!(config.watcher.ignore, vec!["clients/*"]);
+        assert_eq!(config.watcher.poll_interval_ms, 500);
+
+        let config: AppConfig = toml::Value::Table(with_profile(table.clone(), None).unwrap())
+            .try_into()
+            .unwrap();
+        assert_eq!(config.workspace.root, "~/notes");
+        assert!(with_profile(table, Some("home")).is_err());
+    }
+
+    #[test]
+    fn test_missing_profile_hints_at_clear() {
+        let table: toml::Table = toml::from_str("[workspace]\nroot = \"~/notes\"\n").unwrap();
+        let error = with_profile(table, Some("gone")).unwrap_err().to_string();
+        assert!(error.starts_with("No profile 'gone'"));
+        assert!(error.contains("profile clear"));
+    }
 }
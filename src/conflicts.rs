@@ -0,0 +1,260 @@
+//! Conflict copies left by file sync tools next to the note they diverged from.
+
+use crate::changeset::ChangeSet;
+use crate::cli::{ConflictsCommand, Keep};
+use crate::data;
+use crate::diff::{self, DiffOp};
+use crate::trash;
+use crate::util;
+use crate::write_gate;
+
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Conflict {
+    /// The conflict copy
+    pub path: PathBuf,
+    /// The file it is a copy of
+    pub original: PathBuf,
+    /// Sync tool whose naming scheme matched
+    pub source: &'static str,
+}
+
+/// The file `rel_path` is a conflict copy of, and which tool names copies that way:
+/// Dropbox `name (… conflicted copy …).md`, Syncthing `name.sync-conflict-….md` and
+/// iCloud `name 2.md`. Only a copy whose original `exists` counts, since names like
+/// "Chapter 2.md" are common on their own.
+pub fn original_of(
+    rel_path: &Path,
+    exists: impl Fn(&Path) -> bool,
+) -> Option<(PathBuf, &'static str)> {
+    let stem = rel_path.file_stem()?.to_str()?;
+    let extension = rel_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let (original, source) = if let Some((name, _)) = stem.split_once(".sync-conflict-") {
+        (name, "Syncthing")
+    } else if let Some((name, suffix)) = stem.rsplit_once(" (")
+        && suffix.ends_with(')')
+        && suffix.contains("conflicted copy")
+    {
+        (name, "Dropbox")
+    } else if let Some((name, number)) = stem.rsplit_once(' ')
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+        && number != "1"
+    {
+        (name, "iCloud")
+    } else {
+        return None;
+    };
+    let original = rel_path.with_file_name(format!("{}{}", original, extension));
+    exists(&original).then_some((original, source))
+}
+
+/// Conflict copies among the vault-relative `files` whose original is also present.
+pub fn find(files: &[PathBuf]) -> Vec<Conflict> {
+    let present: HashSet<&Path> = files.iter().map(PathBuf::as_path).collect();
+    let mut conflicts: Vec<Conflict> = files
+        .iter()
+        .filter_map(|path| {
+            let (original, source) = original_of(path, |original| present.contains(original))?;
+            Some(Conflict {
+                path: path.clone(),
+                original,
+                source,
+            })
+        })
+        .collect();
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+    conflicts
+}
+
+/// Conflicts of the vault's notes.
+pub fn scan(vault_path: &Path) -> Result<Vec<Conflict>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for file in data::traverse_vault(vault_path)? {
+        if data::is_note(&file) {
+            files.push(util::get_relative_path(&file, vault_path)?);
+        }
+    }
+    Ok(find(&files))
+}
+
+/// Whether `text` still has conflict markers `merge_with_markers` wrote.
+pub fn has_markers(text: &str) -> bool {
+    let mut lines = text.lines();
+    lines.any(|line| line.starts_with("<<<<<<< "))
+        && lines.any(|line| line == "=======")
+        && lines.any(|line| line.starts_with(">>>>>>> "))
+}
+
+/// Both versions in one text: shared lines once, differing runs between conflict markers.
+pub fn merge_with_markers(original: &str, copy: &str, copy_name: &str) -> String {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = copy.lines().collect();
+    let mut merged = String::new();
+    let (mut ours, mut theirs) = (Vec::new(), Vec::new());
+    let flush = |merged: &mut String, ours: &mut Vec<&str>, theirs: &mut Vec<&str>| {
+        if ours.is_empty() && theirs.is_empty() {
+            return;
+        }
+        merged.push_str("<<<<<<< original\n");
+        for line in ours.drain(..) {
+            merged.push_str(line);
+            merged.push('\n');
+        }
+        merged.push_str("=======\n");
+        for line in theirs.drain(..) {
+            merged.push_str(line);
+            merged.push('\n');
+        }
+        merged.push_str(&format!(">>>>>>> {}\n", copy_name));
+    };
+    for op in diff::diff_lines(&old, &new) {
+        match op {
+            DiffOp::Equal(i, _) => {
+                flush(&mut merged, &mut ours, &mut theirs);
+                merged.push_str(old[i]);
+                merged.push('\n');
+            }
+            DiffOp::Delete(i) => ours.push(old[i]),
+            DiffOp::Insert(j) => theirs.push(new[j]),
+        }
+    }
+    flush(&mut merged, &mut ours, &mut theirs);
+    merged
+}
+
+/// Shows how a conflict copy differs from its original and, with `keep`, resolves it.
+/// Whatever is given up goes to the trash.
+pub fn resolve(vault_path: &Path, name: &str, keep: Option<Keep>) -> Result<(), Box<dyn Error>> {
+    let conflicts = scan(vault_path)?;
+    let conflict = conflicts
+        .iter()
+        .find(|c| c.path == Path::new(name) || c.path.file_stem() == Some(name.as_ref()))
+        .ok_or_else(|| format!("'{}' is not a known conflict copy", name))?;
+    let original = fs::read_to_string(vault_path.join(&conflict.original))?;
+    let copy = fs::read_to_string(vault_path.join(&conflict.path))?;
+
+    let Some(keep) = keep else {
+        print!(
+            "{}",
+            diff::unified_diff(
+                &original,
+                &copy,
+                &conflict.original.to_string_lossy(),
+                &conflict.path.to_string_lossy(),
+                3
+            )
+        );
+        return Ok(());
+    };
+    write_gate::check("resolve conflicts")?;
+    let kept = match keep {
+        Keep::Original => None,
+        Keep::Copy => Some(copy),
+        Keep::Both => {
+            let copy_name = conflict.path.to_string_lossy();
+            Some(merge_with_markers(&original, &copy, &copy_name))
+        }
+    };
+    if let Some(kept) = kept {
+        trash::move_to_trash(vault_path, &conflict.original)?;
+        let mut changes = ChangeSet::new();
+        changes.propose(&conflict.original, Some(original), kept);
+        changes.apply(vault_path, "resolve conflicts", false)?;
+    }
+    trash::move_to_trash(vault_path, &conflict.path)?;
+    log::info!(
+        "Resolved {} into {}",
+        conflict.path.display(),
+        conflict.original.display()
+    );
+    Ok(())
+}
+
+pub fn run_conflicts(vault_path: &Path, command: &ConflictsCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        ConflictsCommand::List => {
+            for conflict in scan(vault_path)? {
+                println!(
+                    "{} ({} copy of {})",
+                    conflict.path.display(),
+                    conflict.source,
+                    conflict.original.display()
+                );
+            }
+            Ok(())
+        }
+        ConflictsCommand::Resolve(args) => resolve(vault_path, &args.file, args.keep),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let files: Vec<PathBuf> = [
+            "a/Note.md",
+            "a/Note (Sam's conflicted copy 2024-05-01).md",
+            "a/Note.sync-conflict-20240501-101010-ABCDEF1.md",
+            "Plan 2.md",
+            "Plan.md",
+            "Chapter 2.md",
+            "Other (draft).md",
+            "Other.md",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        let conflicts = find(&files);
+        let found: Vec<(&str, &str)> = conflicts
+            .iter()
+            .map(|c| (c.path.to_str().unwrap(), c.source))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("Plan 2.md", "iCloud"),
+                ("a/Note (Sam's conflicted copy 2024-05-01).md", "Dropbox"),
+                (
+                    "a/Note.sync-conflict-20240501-101010-ABCDEF1.md",
+                    "Syncthing"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_original_of_needs_the_original() {
+        let present = |original: &Path| original == Path::new("Plan.md");
+        assert_eq!(
+            original_of(Path::new("Plan 2.md"), present),
+            Some((PathBuf::from("Plan.md"), "iCloud"))
+        );
+        assert_eq!(original_of(Path::new("Chapter 2.md"), present), None);
+        assert_eq!(original_of(Path::new("Plan.md"), present), None);
+    }
+
+    #[test]
+    fn test_merge_with_markers() {
+        let merged = merge_with_markers("a\nb\nc\n", "a\nB\nc\nd\n", "copy.md");
+        assert_eq!(
+            merged,
+            "a\n<<<<<<< original\nb\n=======\nB\n>>>>>>> copy.md\nc\n\
+             <<<<<<< original\n=======\nd\n>>>>>>> copy.md\n"
+        );
+        assert!(has_markers(&merged));
+        assert!(!has_markers("a\n=======\n<<<<<<< not a conflict\n"));
+    }
+}
@@ -0,0 +1,299 @@
+//! Note bodies kept, deflate-compressed, in the cache database when `[cache] store_content`
+//! is on. The daemon keeps the copy current; search, grep and the exporters then read
+//! notes from it instead of walking a possibly slow vault mount. Without a running daemon
+//! the copy may be stale, so notes are then read from the vault as usual.
+
+use crate::config::{AppConfig, CacheConfig};
+use crate::data::{self, Note};
+use crate::folder_config::FolderConfigs;
+use crate::index;
+use crate::lock;
+use crate::util;
+
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use sqlite::{Connection, State, Value};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+static STORE: OnceLock<(Mutex<Connection>, CacheConfig, PathBuf)> = OnceLock::new();
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS note_contents (
+            path TEXT PRIMARY KEY,
+            hash TEXT NOT NULL,
+            modified INTEGER,
+            data BLOB
+        );",
+    )
+}
+
+/// Serves notes from the cache database for the rest of the process, if configured.
+pub fn install(config: &AppConfig) {
+    if !config.cache.store_content {
+        return;
+    }
+    let connection = index::open(config).and_then(|connection| {
+        ensure_schema(&connection)?;
+        Ok((connection, data::get_data_path(config)?))
+    });
+    match connection {
+        Ok((connection, data_path)) => {
+            let _ = STORE.set((Mutex::new(connection), config.cache.clone(), data_path));
+        }
+        Err(e) => log::warn!("Note contents will be read from the vault: {}", e),
+    }
+}
+
+/// Whether notes are served from the cache database: it is installed and a daemon, maybe
+/// this process, is keeping it current.
+pub fn is_installed() -> bool {
+    STORE
+        .get()
+        .is_some_and(|(_, _, data_path)| lock::is_held(data_path))
+}
+
+fn compress(content: &str) -> std::io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content.as_bytes())?;
+    encoder.finish()
+}
+
+fn decompress(data: &[u8]) -> std::io::Result<String> {
+    let mut content = String::new();
+    DeflateDecoder::new(data).read_to_string(&mut content)?;
+    Ok(content)
+}
+
+fn modified_secs(note: &Note) -> Option<i64> {
+    let since_epoch = note.modified?.duration_since(UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_secs() as i64)
+}
+
+/// Stores `note`; its body is left out (and read from the vault later) when it is larger
+/// than `max_note_bytes` or would take the store past `max_total_bytes`, given `total`
+/// bytes stored for the other notes. Returns the stored size.
+fn store_note(
+    connection: &Connection,
+    note: &Note,
+    limits: &CacheConfig,
+    total: usize,
+) -> Result<usize, Box<dyn Error>> {
+    let data = match note.content.len() <= limits.max_note_bytes {
+        true => Some(compress(&note.content)?),
+        false => None,
+    }
+    .filter(|data| total + data.len() <= limits.max_total_bytes);
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO note_contents (path, hash, modified, data) VALUES (?, ?, ?, ?)",
+    )?;
+    statement.bind((1, note.path.to_string_lossy().replace('\\', "/").as_str()))?;
+    statement.bind((2, util::content_hash(note.content.as_bytes()).as_str()))?;
+    statement.bind((3, modified_secs(note).map_or(Value::Null, Value::Integer)))?;
+    let size = data.as_ref().map_or(0, Vec::len);
+    statement.bind((4, data.map_or(Value::Null, Value::Binary)))?;
+    statement.next()?;
+    Ok(size)
+}
+
+/// Bytes stored for every note but `path`.
+fn stored_bytes(connection: &Connection, path: &str) -> Result<usize, sqlite::Error> {
+    let mut statement = connection
+        .prepare("SELECT coalesce(sum(length(data)), 0) FROM note_contents WHERE path != ?")?;
+    statement.bind((1, path))?;
+    statement.next()?;
+    Ok(statement.read::<i64, _>(0)? as usize)
+}
+
+fn remove(connection: &Connection, path: &str) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare("DELETE FROM note_contents WHERE path = ?")?;
+    statement.bind((1, path))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Brings the store in line with `notes`, rewriting only notes whose content changed.
+pub fn update(
+    connection: &Connection,
+    notes: &[Note],
+    limits: &CacheConfig,
+) -> Result<usize, Box<dyn Error>> {
+    let mut known = HashMap::new();
+    let mut total = 0;
+    let mut statement = connection.prepare("SELECT path, hash, length(data) FROM note_contents")?;
+    while let State::Row = statement.next()? {
+        let size = statement.read::<Option<i64>, _>(2)?.unwrap_or_default() as usize;
+        known.insert(
+            statement.read::<String, _>(0)?,
+            (statement.read::<String, _>(1)?, size),
+        );
+        total += size;
+    }
+
+    let mut updated = 0;
+    connection.execute("BEGIN")?;
+    for note in notes {
+        let path = note.path.to_string_lossy().replace('\\', "/");
+        let hash = util::content_hash(note.content.as_bytes());
+        match known.remove(&path) {
+            Some((stored, _)) if stored == hash => {}
+            previous => {
+                total -= previous.map_or(0, |(_, size)| size);
+                total += store_note(connection, note, limits, total)?;
+                updated += 1;
+            }
+        }
+    }
+    for path in known.keys() {
+        remove(connection, path)?;
+    }
+    connection.execute("COMMIT")?;
+    Ok(updated)
+}
+
+/// Updates the stored copies of changed vault files, e.g. from watcher events.
+pub fn refresh_paths(vault_path: &Path, paths: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let Some((connection, limits, _)) = STORE.get() else {
+        return Ok(());
+    };
+    let connection = connection.lock().map_err(|e| e.to_string())?;
+    refresh_paths_in(&connection, vault_path, paths, limits)
+}
+
+fn refresh_paths_in(
+    connection: &Connection,
+    vault_path: &Path,
+    paths: &[PathBuf],
+    limits: &CacheConfig,
+) -> Result<(), Box<dyn Error>> {
+    let folders = FolderConfigs::load(vault_path)?;
+    for path in paths.iter().filter(|path| data::is_note(path)) {
+        let rel_path = util::get_relative_path(path, vault_path)?;
+        let key = rel_path.to_string_lossy().replace('\\', "/");
+        let content = match data::is_listed(&folders, &rel_path) {
+            true => std::fs::read_to_string(path).ok(),
+            false => None,
+        };
+        match content {
+            Some(content) => {
+                let mut note = Note::from_content(rel_path, content);
+                note.modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+                let total = stored_bytes(connection, &key)?;
+                store_note(connection, &note, limits, total)?;
+            }
+            None => remove(connection, &key)?,
+        }
+    }
+    Ok(())
+}
+
+/// Notes stored in `connection`; bodies left out of the store are read from the vault.
+pub fn stored_notes(
+    connection: &Connection,
+    vault_path: &Path,
+) -> Result<Vec<Note>, Box<dyn Error>> {
+    let mut statement =
+        connection.prepare("SELECT path, modified, data FROM note_contents ORDER BY path")?;
+    let mut notes = Vec::new();
+    while let State::Row = statement.next()? {
+        let path = PathBuf::from(statement.read::<String, _>(0)?);
+        let content = match statement.read::<Option<Vec<u8>>, _>(2)? {
+            Some(data) => decompress(&data)?,
+            None => match std::fs::read_to_string(vault_path.join(&path)) {
+                Ok(content) => content,
+                Err(e) => {
+                    log::warn!("Skipping '{}': {}", path.display(), e);
+                    continue;
+                }
+            },
+        };
+        let mut note = Note::from_content(path, content);
+        note.modified = statement
+            .read::<Option<i64>, _>(1)?
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64));
+        notes.push(note);
+    }
+    Ok(notes)
+}
+
+/// The vault's notes, from the store when it is daemon-maintained and filled, otherwise
+/// read from the vault.
+pub fn load_notes(vault_path: &Path) -> Result<Vec<Note>, Box<dyn Error>> {
+    if let Some((connection, _, _)) = STORE.get().filter(|_| is_installed()) {
+        let connection = connection.lock().map_err(|e| e.to_string())?;
+        let notes = stored_notes(&connection, vault_path)?;
+        if !notes.is_empty() {
+            return Ok(notes);
+        }
+    }
+    data::load_notes(vault_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_and_limits() {
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let limits = CacheConfig {
+            store_content: true,
+            max_note_bytes: 100,
+            ..Default::default()
+        };
+        let small = Note::from_content(PathBuf::from("A.md"), "# A\nbody\n".into());
+        let large = Note::from_content(PathBuf::from("B.md"), "x".repeat(200));
+        assert_eq!(update(&connection, &[small, large], &limits).unwrap(), 2);
+
+        let vault = tempfile::tempdir().unwrap();
+        std::fs::write(vault.path().join("B.md"), "from disk").unwrap();
+        let notes = stored_notes(&connection, vault.path()).unwrap();
+        assert_eq!(notes[0].content, "# A\nbody\n");
+        assert_eq!(notes[1].content, "from disk");
+
+        let small = Note::from_content(PathBuf::from("A.md"), "# A\nbody\n".into());
+        assert_eq!(update(&connection, &[small], &limits).unwrap(), 0);
+        assert_eq!(stored_notes(&connection, vault.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_refresh_paths_filters_and_limits() {
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let limits = CacheConfig {
+            store_content: true,
+            max_total_bytes: 15,
+            ..Default::default()
+        };
+        let vault = tempfile::tempdir().unwrap();
+        std::fs::create_dir(vault.path().join(".trash")).unwrap();
+        let paths: Vec<PathBuf> = ["A.md", "B.md", ".trash/C.md"]
+            .iter()
+            .map(|name| vault.path().join(name))
+            .collect();
+        for path in &paths {
+            std::fs::write(path, "words ".repeat(4)).unwrap();
+        }
+        refresh_paths_in(&connection, vault.path(), &paths, &limits).unwrap();
+
+        let mut statement = connection
+            .prepare("SELECT path, data IS NULL FROM note_contents ORDER BY path")
+            .unwrap();
+        let mut rows = Vec::new();
+        while let State::Row = statement.next().unwrap() {
+            rows.push((
+                statement.read::<String, _>(0).unwrap(),
+                statement.read::<i64, _>(1).unwrap(),
+            ));
+        }
+        // The second body would take the store past 15 bytes
+        assert_eq!(rows, [("A.md".to_string(), 0), ("B.md".to_string(), 1)]);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::cli::ContextArgs;
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::index::{self, SearchHit};
+use crate::resolver::{self, Resolver};
+use crate::search;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// Share of a note's priority passed on to the notes it links with
+static LINK_DECAY: f64 = 0.5;
+
+/// Search hits considered when building a pack from a query
+static SEARCH_CANDIDATES: usize = 20;
+
+/// A section of a note proposed for the pack
+#[derive(Debug, Clone, PartialEq)]
+pub struct Excerpt {
+    pub path: PathBuf,
+    pub title: String,
+    pub heading: String,
+    pub line: usize,
+    pub text: String,
+    pub score: f64,
+}
+
+/// Rough token count for budgeting: about four bytes of English text per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+fn excerpts_of(note: &Note) -> Vec<Excerpt> {
+    let title = note.title();
+    index::chunk_note(note, index::MAX_CHUNK_BYTES)
+        .into_iter()
+        .map(|chunk| Excerpt {
+            path: note.path.clone(),
+            title: title.clone(),
+            heading: chunk.heading,
+            line: chunk.line,
+            text: chunk.text,
+            score: 0.0,
+        })
+        .collect()
+}
+
+/// Notes linked from or to `path`
+fn neighbours(notes: &[Note], resolver: &Resolver, path: &Path) -> BTreeSet<PathBuf> {
+    let mut found: BTreeSet<PathBuf> = resolver::backlinks(notes, resolver, path)
+        .into_iter()
+        .map(|link| link.path)
+        .collect();
+    if let Some(note) = notes.iter().find(|note| note.path == path) {
+        found.extend(resolver::linked_files(note, resolver));
+    }
+    found.remove(path);
+    found
+}
+
+/// Scores excerpts from seed notes: every `(path, line, score)` seed adds `score` to that
+/// section (or the whole note for line 0), and `LINK_DECAY * score` to the opening section
+/// of each note it links with. Scores from several seeds add up.
+pub fn gather(
+    notes: &[Note],
+    resolver: &Resolver,
+    seeds: &[(PathBuf, usize, f64)],
+) -> Vec<Excerpt> {
+    let by_path: HashMap<&Path, &Note> = notes.iter().map(|n| (n.path.as_path(), n)).collect();
+    let mut scored: HashMap<(PathBuf, usize), Excerpt> = HashMap::new();
+    let mut add = |excerpt: &Excerpt, score: f64| {
+        scored
+            .entry((excerpt.path.clone(), excerpt.line))
+            .or_insert_with(|| excerpt.clone())
+            .score += score;
+    };
+
+    for (path, line, score) in seeds {
+        let Some(note) = by_path.get(path.as_path()) else {
+            continue;
+        };
+        let sections = excerpts_of(note);
+        for excerpt in sections.iter().filter(|e| *line == 0 || e.line == *line) {
+            add(excerpt, *score);
+        }
+        for neighbour in neighbours(notes, resolver, path) {
+            if let Some(opening) = by_path
+                .get(neighbour.as_path())
+                .and_then(|note| excerpts_of(note).into_iter().next())
+            {
+                add(&opening, score * LINK_DECAY);
+            }
+        }
+    }
+
+    let mut excerpts: Vec<Excerpt> = scored.into_values().collect();
+    excerpts.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.path.cmp(&b.path))
+            .then(a.line.cmp(&b.line))
+    });
+    excerpts
+}
+
+fn render_excerpt(number: usize, excerpt: &Excerpt, text: &str) -> String {
+    let heading = if excerpt.heading.is_empty() {
+        excerpt.title.clone()
+    } else {
+        format!("{} › {}", excerpt.title, excerpt.heading)
+    };
+    format!(
+        "## [{}] {}\nSource: {}, line {}\n\n{}\n\n",
+        number,
+        heading,
+        excerpt.path.to_string_lossy().replace('\\', "/"),
+        excerpt.line,
+        text
+    )
+}
+
+/// Cuts `text` at a word boundary so it stays under `max_bytes`.
+fn truncate(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = text[..end].rfind(char::is_whitespace).unwrap_or(end);
+    format!("{} …", text[..cut].trim_end())
+}
+
+/// Lays the excerpts out as Markdown, best first, while the estimate stays under `budget`.
+/// Excerpts that do not fit are skipped in favour of smaller ones further down; the
+/// first excerpt is shortened rather than left out.
+pub fn assemble(subject: &str, excerpts: &[Excerpt], budget: usize) -> String {
+    let mut pack = format!("# Context: {}\n\n", subject);
+    let mut number = 0;
+    for excerpt in excerpts {
+        let remaining = budget.saturating_sub(estimate_tokens(&pack));
+        let block = render_excerpt(number + 1, excerpt, &excerpt.text);
+        if estimate_tokens(&block) <= remaining {
+            pack.push_str(&block);
+        } else if number == 0 {
+            let overhead = estimate_tokens(&render_excerpt(1, excerpt, " …"));
+            let Some(room) = remaining.checked_sub(overhead) else {
+                break;
+            };
+            pack.push_str(&render_excerpt(
+                1,
+                excerpt,
+                &truncate(&excerpt.text, room * 4),
+            ));
+        } else {
+            continue;
+        }
+        number += 1;
+    }
+    pack
+}
+
+fn hit_seeds(hits: &[SearchHit]) -> Vec<(PathBuf, usize, f64)> {
+    hits.iter()
+        .enumerate()
+        .map(|(rank, hit)| {
+            (
+                PathBuf::from(&hit.path),
+                hit.line,
+                1.0 / (rank as f64 + 1.0),
+            )
+        })
+        .collect()
+}
+
+pub fn run_context(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &ContextArgs,
+) -> Result<(), Box<dyn Error>> {
+    let subject = args.input.join(" ");
+    let notes = data::load_notes(vault_path)?;
+    let resolver = Resolver::from_vault(vault_path)?;
+
+    // A note name builds the pack around that note, anything else is a search
+    let seeds = match resolver.resolve(&subject, Path::new("")) {
+        Some(path) if data::is_note(path) => {
+            log::info!("Building context around {}", path.display());
+            vec![(path.to_path_buf(), 0, 1.0)]
+        }
+        _ => hit_seeds(&search::search(
+            vault_path,
+            config,
+            &subject,
+            None,
+            SEARCH_CANDIDATES,
+            config.search.snippet_options(),
+        )?),
+    };
+    if seeds.is_empty() {
+        return Err(format!("Nothing in the vault matches '{}'", subject).into());
+    }
+
+    let excerpts = gather(&notes, &resolver, &seeds);
+    print!("{}", assemble(&subject, &excerpts, args.budget));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes() -> Vec<Note> {
+        [
+            ("Rust.md", "Intro to rust.\n\n# Ownership\nBorrowing.\n"),
+            ("Cargo.md", "Cargo builds [[Rust]] code.\n"),
+            ("Other.md", "Unrelated.\n"),
+        ]
+        .iter()
+        .map(|(path, content)| Note::from_content(PathBuf::from(path), content.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn test_gather_follows_links() {
+        let notes = notes();
+        let resolver = Resolver::new(notes.iter().map(|n| n.path.clone()).collect());
+        let excerpts = gather(&notes, &resolver, &[(PathBuf::from("Rust.md"), 0, 1.0)]);
+        let order: Vec<(&str, f64)> = excerpts
+            .iter()
+            .map(|e| (e.heading.as_str(), e.score))
+            .collect();
+        assert_eq!(order, vec![("", 1.0), ("Ownership", 1.0), ("", 0.5)]);
+        assert_eq!(excerpts[2].path, PathBuf::from("Cargo.md"));
+
+        // A section hit plus a link from another hit adds up
+        let seeds = [
+            (PathBuf::from("Cargo.md"), 1, 1.0),
+            (PathBuf::from("Rust.md"), 1, 0.5),
+        ];
+        let excerpts = gather(&notes, &resolver, &seeds);
+        assert_eq!(excerpts[0].path, PathBuf::from("Cargo.md"));
+        assert_eq!(excerpts[1].path, PathBuf::from("Rust.md"));
+        assert_eq!(excerpts[1].score, 1.0);
+    }
+
+    #[test]
+    fn test_assemble_respects_budget() {
+        let excerpt = |text: &str, score| Excerpt {
+            path: PathBuf::from("A.md"),
+            title: String::from("A"),
+            heading: String::new(),
+            line: 1,
+            text: text.to_string(),
+            score,
+        };
+        let excerpts = vec![
+            excerpt("first", 1.0),
+            excerpt(&"more ".repeat(100), 0.9),
+            excerpt("short", 0.5),
+        ];
+        let pack = assemble("q", &excerpts, 50);
+        assert!(estimate_tokens(&pack) <= 50);
+        assert!(pack.contains("## [1] A\nSource: A.md, line 1\n\nfirst"));
+        assert!(pack.contains("## [2] A\nSource: A.md, line 1\n\nshort"));
+        assert!(!pack.contains("more"));
+
+        // The best excerpt is shortened rather than dropped
+        let long = [excerpt(&"long words ".repeat(100), 1.0)];
+        let pack = assemble("q", &long, 50);
+        assert!(estimate_tokens(&pack) <= 50);
+        assert!(pack.ends_with("long words …\n\n"));
+        assert_eq!(assemble("q", &long, 1), "# Context: q\n\n");
+    }
+}
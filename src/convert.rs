@@ -0,0 +1,126 @@
+use crate::changeset::ChangeSet;
+use crate::cli::{ConvertLinksArgs, LinkFormat};
+use crate::data;
+use crate::markdown::{self, Link, LinkStyle};
+use crate::resolver::Resolver;
+use crate::util;
+
+use std::{error::Error, fs, path::Path};
+
+fn convert_link(link: &Link, source: &Path, resolver: &Resolver, to: LinkFormat) -> Option<String> {
+    if link.is_external() {
+        return None;
+    }
+    let resolved = resolver.resolve(&link.target, source);
+    let anchor = link.anchor.as_deref().map(util::percent_decode);
+
+    match (to, link.style) {
+        (LinkFormat::Wikilink, LinkStyle::Markdown) => {
+            let target = match resolved {
+                Some(file) if file == source && link.target.is_empty() => String::new(),
+                Some(file) => resolver.shortest_link(file),
+                None => {
+                    let decoded = util::percent_decode(&link.target);
+                    decoded.strip_suffix(".md").unwrap_or(&decoded).to_string()
+                }
+            };
+            Some(markdown::render_wikilink(
+                &target,
+                anchor.as_deref(),
+                link.text.as_deref(),
+                link.embed,
+            ))
+        }
+        (LinkFormat::Markdown, LinkStyle::Wiki) => {
+            let path = match resolved {
+                Some(_) if link.target.is_empty() => String::new(),
+                Some(file) => file.to_string_lossy().replace('\\', "/"),
+                None if Path::new(&link.target).extension().is_some() => link.target.clone(),
+                None => format!("{}.md", link.target),
+            };
+            let text = match (&link.text, &anchor) {
+                (Some(text), _) => text.clone(),
+                (None, Some(anchor)) if link.target.is_empty() => anchor.clone(),
+                (None, Some(anchor)) => format!("{} > {}", link.target, anchor),
+                (None, None) => link.target.clone(),
+            };
+            Some(markdown::render_markdown_link(
+                &path,
+                anchor.as_deref(),
+                &text,
+                link.embed,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Rewrites every internal link in `content` (the note at vault path `source`) to `to` style.
+pub fn convert_note(content: &str, source: &Path, resolver: &Resolver, to: LinkFormat) -> String {
+    let edits = markdown::parse_links(content)
+        .iter()
+        .filter_map(|link| {
+            convert_link(link, source, resolver, to).map(|text| (link.span.clone(), text))
+        })
+        .collect();
+    markdown::replace_spans(content, edits)
+}
+
+pub fn run_convert_links(vault_path: &Path, args: &ConvertLinksArgs) -> Result<(), Box<dyn Error>> {
+    let resolver = Resolver::from_vault(vault_path)?;
+    let mut changes = ChangeSet::new();
+    for rel_path in resolver.files() {
+        if !data::is_note(rel_path) {
+            continue;
+        }
+        let content = fs::read_to_string(vault_path.join(rel_path))?;
+        let converted = convert_note(&content, rel_path, &resolver, args.to);
+        changes.propose(rel_path, Some(content), converted);
+    }
+    changes.finish(vault_path, &args.changes, "convert links", false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn resolver() -> Resolver {
+        Resolver::new(vec![
+            PathBuf::from("Home.md"),
+            PathBuf::from("notes/My Note.md"),
+            PathBuf::from("assets/pic.png"),
+        ])
+    }
+
+    #[test]
+    fn test_wikilinks_to_markdown() {
+        let content = "[[My Note#Part One|see]] [[Home]] ![[pic.png]] [[#Local]] [[Missing]]";
+        let converted = convert_note(
+            content,
+            Path::new("Home.md"),
+            &resolver(),
+            LinkFormat::Markdown,
+        );
+        assert_eq!(
+            converted,
+            "[see](notes/My%20Note.md#Part%20One) [Home](Home.md) ![pic.png](assets/pic.png) \
+             [Local](#Local) [Missing](Missing.md)"
+        );
+    }
+
+    #[test]
+    fn test_markdown_to_wikilinks_round_trip() {
+        let r = resolver();
+        let content =
+            "[see](notes/My%20Note.md#Part%20One) ![pic.png](assets/pic.png) [web](https://x.y)";
+        let converted = convert_note(content, Path::new("Home.md"), &r, LinkFormat::Wikilink);
+        assert_eq!(
+            converted,
+            "[[My Note#Part One|see]] ![[pic.png]] [web](https://x.y)"
+        );
+        let back = convert_note(&converted, Path::new("Home.md"), &r, LinkFormat::Markdown);
+        assert_eq!(back, content);
+    }
+}
@@ -0,0 +1,151 @@
+//! CORS for browser front ends talking to the HTTP API from another origin. Only the
+//! origins listed in `[server] cors_origins` are let in; `"*"` lets in any origin, but
+//! only when API keys are configured, since otherwise any page the user visits could
+//! read and write the vault.
+
+use crate::config::ServerConfig;
+use crate::http::{Request, Response};
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, OPTIONS";
+const DEFAULT_HEADERS: &str = "Authorization, Content-Type";
+/// How long browsers may cache a preflight answer, in seconds
+const MAX_AGE: &str = "600";
+
+/// The request's `Origin`, if the configuration allows it; `keyed` says whether the
+/// server requires API keys.
+pub fn allowed_origin<'a>(
+    config: &ServerConfig,
+    keyed: bool,
+    request: &'a Request,
+) -> Option<&'a str> {
+    let origin = request.header("origin")?;
+    config
+        .cors_origins
+        .iter()
+        .any(|allowed| (allowed == "*" && keyed) || allowed.trim_end_matches('/') == origin)
+        .then_some(origin)
+}
+
+/// Whether `cors_origins` has a `"*"` that goes unused because no keys are configured.
+pub fn ignores_wildcard(config: &ServerConfig, keyed: bool) -> bool {
+    !keyed && config.cors_origins.iter().any(|allowed| allowed == "*")
+}
+
+/// Answers a preflight (`OPTIONS` with `Access-Control-Request-Method`) request; other
+/// requests give `None`.
+pub fn preflight(config: &ServerConfig, keyed: bool, request: &Request) -> Option<Response> {
+    if request.method != "OPTIONS" || request.header("access-control-request-method").is_none() {
+        return None;
+    }
+    let Some(origin) = allowed_origin(config, keyed, request) else {
+        return Some(Response::error(403, "Origin not allowed"));
+    };
+    let headers = request
+        .header("access-control-request-headers")
+        .unwrap_or(DEFAULT_HEADERS);
+    Some(Response {
+        status: 204,
+        content_type: String::from("text/plain"),
+        headers: vec![
+            (
+                String::from("Access-Control-Allow-Origin"),
+                origin.to_string(),
+            ),
+            (
+                String::from("Access-Control-Allow-Methods"),
+                String::from(ALLOWED_METHODS),
+            ),
+            (
+                String::from("Access-Control-Allow-Headers"),
+                headers.to_string(),
+            ),
+            (
+                String::from("Access-Control-Max-Age"),
+                String::from(MAX_AGE),
+            ),
+            (String::from("Vary"), String::from("Origin")),
+        ],
+        body: Vec::new(),
+    })
+}
+
+/// Lets the browser hand `response` to the page that sent `request`, if its origin is allowed.
+pub fn add_headers(config: &ServerConfig, keyed: bool, request: &Request, response: &mut Response) {
+    if let Some(origin) = allowed_origin(config, keyed, request) {
+        response.headers.push((
+            String::from("Access-Control-Allow-Origin"),
+            origin.to_string(),
+        ));
+        response
+            .headers
+            .push((String::from("Vary"), String::from("Origin")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, origin: &str) -> Request {
+        let mut request = Request {
+            method: method.to_string(),
+            path: String::from("/notes"),
+            ..Request::default()
+        };
+        request
+            .headers
+            .insert(String::from("origin"), origin.to_string());
+        request
+    }
+
+    #[test]
+    fn test_preflight() {
+        let config = ServerConfig {
+            cors_origins: vec![String::from("http://localhost:5173")],
+            ..ServerConfig::default()
+        };
+        let mut allowed = request("OPTIONS", "http://localhost:5173");
+        allowed.headers.insert(
+            String::from("access-control-request-method"),
+            String::from("POST"),
+        );
+        let response = preflight(&config, false, &allowed).unwrap();
+        assert_eq!(response.status, 204);
+        assert!(response.headers.contains(&(
+            String::from("Access-Control-Allow-Origin"),
+            String::from("http://localhost:5173")
+        )));
+
+        let mut other = allowed.clone();
+        other
+            .headers
+            .insert(String::from("origin"), String::from("https://evil.example"));
+        assert_eq!(preflight(&config, false, &other).unwrap().status, 403);
+        assert!(preflight(&config, false, &request("GET", "http://localhost:5173")).is_none());
+
+        let mut response = Response::not_found();
+        add_headers(
+            &config,
+            false,
+            &request("GET", "https://evil.example"),
+            &mut response,
+        );
+        assert!(response.headers.is_empty());
+    }
+
+    #[test]
+    fn test_wildcard_needs_keys() {
+        let config = ServerConfig {
+            cors_origins: vec![String::from("*")],
+            ..ServerConfig::default()
+        };
+        let page = request("GET", "https://evil.example");
+        assert_eq!(allowed_origin(&config, false, &page), None);
+        assert_eq!(
+            allowed_origin(&config, true, &page),
+            Some("https://evil.example")
+        );
+        assert!(ignores_wildcard(&config, false));
+        assert!(!ignores_wildcard(&config, true));
+    }
+}
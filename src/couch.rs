@@ -0,0 +1,1288 @@
+//! A CouchDB-compatible endpoint under `/couchdb`, so that Self-hosted LiveSync (and any
+//! other PouchDB client) can replicate with the daemon as if it were a CouchDB server.
+//! Enabled by `[server] couchdb = true`.
+//!
+//! Only the subset of the API that replication uses is served: database info and
+//! creation, `_changes` (normal and longpoll feeds), `_revs_diff`, `_bulk_docs`,
+//! `_bulk_get`, `_all_docs`, `_local` checkpoints and single documents. The winning
+//! revision of a document is picked the way CouchDB does (highest generation, then
+//! highest revision id); the leaves of losing branches are kept as its `_conflicts`.
+//!
+//! The endpoint needs `[[server.keys]]`: clients send their key as a bearer token (a
+//! custom `Authorization` header in LiveSync's settings), and a read key only pulls.
+//!
+//! Notes LiveSync sends unencrypted as plain text are also written into the vault once
+//! all their chunks have arrived, and moved to the trash when deleted. Vault edits made
+//! elsewhere are not turned into documents: the clients remain the source of changes.
+//! A note changed in the vault since it was last synced is kept as a Syncthing-style
+//! conflict copy before the synced version replaces it, and is not trashed.
+
+use crate::api::ApiState;
+use crate::auth;
+use crate::capture;
+use crate::changeset::ChangeSet;
+use crate::config::Scope;
+use crate::http::{Request, Response};
+use crate::index;
+use crate::trash;
+use crate::util;
+
+use jiff::Zoned;
+use serde_json::{Map, Value, json};
+use sqlite::{Connection, State};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Version reported to clients; PouchDB only checks that there is one
+const COUCH_VERSION: &str = "3.3.3";
+/// Longest a longpoll `_changes` request waits for a change
+const MAX_LONGPOLL: Duration = Duration::from_secs(60);
+/// How often a waiting longpoll request looks for changes
+const LONGPOLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Longpoll requests that may wait at once, each holding a thread; later ones are answered
+/// straight away and poll again
+const MAX_WAITING_LONGPOLLS: usize = 4;
+
+static WAITING_LONGPOLLS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS couch_databases (
+            name TEXT PRIMARY KEY
+        );
+        CREATE TABLE IF NOT EXISTS couch_docs (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            db TEXT NOT NULL,
+            id TEXT NOT NULL,
+            rev TEXT NOT NULL,
+            deleted INTEGER NOT NULL,
+            body TEXT NOT NULL,
+            UNIQUE (db, id)
+        );
+        CREATE TABLE IF NOT EXISTS couch_local (
+            db TEXT NOT NULL,
+            id TEXT NOT NULL,
+            body TEXT NOT NULL,
+            PRIMARY KEY (db, id)
+        );
+        CREATE TABLE IF NOT EXISTS couch_pending (
+            db TEXT NOT NULL,
+            id TEXT NOT NULL,
+            PRIMARY KEY (db, id)
+        );
+        CREATE TABLE IF NOT EXISTS couch_conflicts (
+            db TEXT NOT NULL,
+            id TEXT NOT NULL,
+            rev TEXT NOT NULL,
+            body TEXT NOT NULL,
+            PRIMARY KEY (db, id, rev)
+        );
+        CREATE TABLE IF NOT EXISTS couch_written (
+            db TEXT NOT NULL,
+            path TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            PRIMARY KEY (db, path)
+        );",
+    )
+}
+
+/// A CouchDB error body
+fn couch_error(status: u16, error: &str, reason: &str) -> Response {
+    Response::json(status, &json!({ "error": error, "reason": reason }))
+}
+
+fn internal(e: Box<dyn Error>) -> Response {
+    couch_error(500, "internal_server_error", &e.to_string())
+}
+
+/// Generation and id of a revision such as "3-abc"
+fn parse_rev(rev: &str) -> Option<(u64, &str)> {
+    let (generation, id) = rev.split_once('-')?;
+    Some((generation.parse().ok()?, id))
+}
+
+/// Whether `rev` beats `current` as the winning revision.
+fn wins(rev: &str, current: &str) -> bool {
+    parse_rev(rev) > parse_rev(current)
+}
+
+/// The revisions `doc` descends from, newest first, as listed in its `_revisions`.
+fn ancestry(doc: &Value) -> Vec<String> {
+    let revisions = &doc["_revisions"];
+    let Some(start) = revisions["start"].as_u64() else {
+        return doc["_rev"].as_str().map(String::from).into_iter().collect();
+    };
+    revisions["ids"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .enumerate()
+        .filter_map(|(i, id)| Some(format!("{}-{}", start.checked_sub(i as u64)?, id.as_str()?)))
+        .collect()
+}
+
+/// `doc` with `_revisions`, made up from its revision when it came without one.
+fn with_revisions(mut doc: Value) -> Value {
+    if doc.get("_revisions").is_none()
+        && let Some((generation, id)) = doc["_rev"].as_str().and_then(parse_rev)
+    {
+        doc["_revisions"] = json!({ "start": generation, "ids": [id] });
+    }
+    doc
+}
+
+fn database_exists(connection: &Connection, db: &str) -> Result<bool, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT 1 FROM couch_databases WHERE name = ?")?;
+    statement.bind((1, db))?;
+    Ok(matches!(statement.next()?, State::Row))
+}
+
+fn update_seq(connection: &Connection, db: &str) -> Result<i64, sqlite::Error> {
+    let mut statement =
+        connection.prepare("SELECT COALESCE(MAX(seq), 0) FROM couch_docs WHERE db = ?")?;
+    statement.bind((1, db))?;
+    statement.next()?;
+    statement.read::<i64, _>(0)
+}
+
+/// The stored winning revision of a document, with its body.
+fn stored(connection: &Connection, db: &str, id: &str) -> Result<Option<Value>, Box<dyn Error>> {
+    let mut statement =
+        connection.prepare("SELECT body FROM couch_docs WHERE db = ? AND id = ?")?;
+    statement.bind((1, db))?;
+    statement.bind((2, id))?;
+    match statement.next()? {
+        State::Row => Ok(Some(serde_json::from_str(
+            &statement.read::<String, _>(0)?,
+        )?)),
+        State::Done => Ok(None),
+    }
+}
+
+fn store(connection: &Connection, db: &str, doc: &Value) -> Result<(), Box<dyn Error>> {
+    let id = doc["_id"].as_str().ok_or("Document without an _id")?;
+    let rev = doc["_rev"].as_str().ok_or("Document without a _rev")?;
+    // Deleting first gives the document a new, higher sequence number
+    let mut statement = connection.prepare("DELETE FROM couch_docs WHERE db = ? AND id = ?")?;
+    statement.bind((1, db))?;
+    statement.bind((2, id))?;
+    statement.next()?;
+    let mut statement = connection
+        .prepare("INSERT INTO couch_docs (db, id, rev, deleted, body) VALUES (?, ?, ?, ?, ?)")?;
+    statement.bind((1, db))?;
+    statement.bind((2, id))?;
+    statement.bind((3, rev))?;
+    statement.bind((4, doc["_deleted"].as_bool().unwrap_or_default() as i64))?;
+    statement.bind((5, doc.to_string().as_str()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The leaves of a document's losing branches.
+fn conflicts(connection: &Connection, db: &str, id: &str) -> Result<Vec<Value>, Box<dyn Error>> {
+    let mut statement = connection
+        .prepare("SELECT body FROM couch_conflicts WHERE db = ? AND id = ? ORDER BY rev")?;
+    statement.bind((1, db))?;
+    statement.bind((2, id))?;
+    let mut docs = Vec::new();
+    while let State::Row = statement.next()? {
+        docs.push(serde_json::from_str(&statement.read::<String, _>(0)?)?);
+    }
+    Ok(docs)
+}
+
+fn add_conflict(connection: &Connection, db: &str, doc: &Value) -> Result<(), Box<dyn Error>> {
+    let id = doc["_id"].as_str().ok_or("Document without an _id")?;
+    let rev = doc["_rev"].as_str().ok_or("Document without a _rev")?;
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO couch_conflicts (db, id, rev, body) VALUES (?, ?, ?, ?)",
+    )?;
+    statement.bind((1, db))?;
+    statement.bind((2, id))?;
+    statement.bind((3, rev))?;
+    statement.bind((4, doc.to_string().as_str()))?;
+    statement.next()?;
+    Ok(())
+}
+
+fn remove_conflict(
+    connection: &Connection,
+    db: &str,
+    id: &str,
+    rev: &str,
+) -> Result<(), sqlite::Error> {
+    let mut statement =
+        connection.prepare("DELETE FROM couch_conflicts WHERE db = ? AND id = ? AND rev = ?")?;
+    statement.bind((1, db))?;
+    statement.bind((2, id))?;
+    statement.bind((3, rev))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The document at revision `rev`, the winning one or a conflict, or the winning
+/// revision when `rev` is `None`.
+fn revision(
+    connection: &Connection,
+    db: &str,
+    id: &str,
+    rev: Option<&str>,
+) -> Result<Option<Value>, Box<dyn Error>> {
+    let winner = stored(connection, db, id)?;
+    let Some(rev) = rev else {
+        return Ok(winner);
+    };
+    if winner.as_ref().is_some_and(|doc| doc["_rev"] == rev) {
+        return Ok(winner);
+    }
+    Ok(conflicts(connection, db, id)?
+        .into_iter()
+        .find(|doc| doc["_rev"] == rev))
+}
+
+/// Saves a document edited by a client (`new_edits`), which must name the revision it
+/// edits, returning its new revision or a conflict.
+fn save_edit(
+    connection: &Connection,
+    db: &str,
+    mut doc: Value,
+) -> Result<Result<String, Response>, Box<dyn Error>> {
+    let id = doc["_id"]
+        .as_str()
+        .ok_or("Document without an _id")?
+        .to_string();
+    let current = stored(connection, db, &id)?;
+    let current_rev = current
+        .as_ref()
+        .and_then(|doc| doc["_rev"].as_str().map(String::from));
+    let current_deleted = current.as_ref().is_some_and(|doc| doc["_deleted"] == true);
+    if doc["_rev"].as_str() != current_rev.as_deref()
+        && !(current_deleted && doc.get("_rev").is_none())
+    {
+        return Ok(Err(couch_error(
+            409,
+            "conflict",
+            "Document update conflict.",
+        )));
+    }
+    let generation = current_rev
+        .as_deref()
+        .and_then(parse_rev)
+        .map_or(0, |(generation, _)| generation);
+    let mut ids = vec![];
+    let hash = util::content_hash(doc.to_string().as_bytes());
+    ids.push(Value::from(hash.clone()));
+    if let Some(current) = &current {
+        ids.extend(
+            ancestry(current)
+                .iter()
+                .filter_map(|rev| parse_rev(rev).map(|(_, id)| Value::from(id))),
+        );
+    }
+    let rev = format!("{}-{}", generation + 1, hash);
+    doc["_rev"] = Value::from(rev.clone());
+    doc["_revisions"] = json!({ "start": generation + 1, "ids": ids });
+    store(connection, db, &doc)?;
+    Ok(Ok(rev))
+}
+
+/// Saves a replicated revision (`new_edits: false`), returning whether it became the
+/// winning one. A revision on another branch than the winner's is kept as a conflict,
+/// or turns the winner into one when it beats it.
+fn save_replicated(connection: &Connection, db: &str, doc: &Value) -> Result<bool, Box<dyn Error>> {
+    let id = doc["_id"].as_str().ok_or("Document without an _id")?;
+    let rev = doc["_rev"]
+        .as_str()
+        .ok_or("Replicated document without a _rev")?;
+    let doc = with_revisions(doc.clone());
+    let lineage = ancestry(&doc);
+    // A branch the revision extends has a new leaf
+    for conflict in conflicts(connection, db, id)? {
+        if let Some(leaf) = conflict["_rev"].as_str()
+            && lineage.iter().any(|ancestor| ancestor == leaf)
+        {
+            remove_conflict(connection, db, id, leaf)?;
+        }
+    }
+    let Some(current) = stored(connection, db, id)? else {
+        store(connection, db, &doc)?;
+        return Ok(true);
+    };
+    let current_rev = current["_rev"].as_str().unwrap_or_default().to_string();
+    if ancestry(&with_revisions(current.clone()))
+        .iter()
+        .any(|known| known == rev)
+    {
+        return Ok(false);
+    }
+    let extends_current = lineage.contains(&current_rev);
+    if extends_current || wins(rev, &current_rev) {
+        if !extends_current && current["_deleted"] != true {
+            add_conflict(connection, db, &current)?;
+        }
+        store(connection, db, &doc)?;
+        Ok(true)
+    } else {
+        if doc["_deleted"] != true {
+            add_conflict(connection, db, &doc)?;
+        }
+        Ok(false)
+    }
+}
+
+/// The text of a LiveSync note whose chunks have all arrived, `None` while some are
+/// missing. Encrypted and binary notes are not readable here and give an error.
+fn note_text(
+    connection: &Connection,
+    db: &str,
+    doc: &Value,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if doc["type"] != "plain" {
+        return Err("only plain-text notes are written to the vault".into());
+    }
+    let mut text = String::new();
+    for child in doc["children"].as_array().into_iter().flatten() {
+        let Some(chunk) = stored(connection, db, child.as_str().unwrap_or_default())? else {
+            return Ok(None);
+        };
+        let data = chunk["data"].as_str().unwrap_or_default();
+        if chunk["e_"] == true || data.starts_with('%') {
+            return Err("the note is end-to-end encrypted".into());
+        }
+        text.push_str(data);
+    }
+    Ok(Some(text))
+}
+
+/// Vault-relative path of a LiveSync note document, if it is a note of the vault rather
+/// than a chunk, an internal file or an obfuscated path.
+fn note_path(doc: &Value) -> Option<&str> {
+    let path = doc["path"].as_str()?;
+    let is_note = matches!(doc["type"].as_str(), Some("plain" | "newnote"));
+    let internal = path.starts_with("i:") || path.starts_with("ix:") || path.starts_with("ps:");
+    let obfuscated = path.starts_with("/\\:") || path.starts_with('%');
+    (is_note && !internal && !obfuscated && !util::is_hidden_path(Path::new(""), Path::new(path)))
+        .then_some(path)
+}
+
+fn set_pending(
+    connection: &Connection,
+    db: &str,
+    id: &str,
+    pending: bool,
+) -> Result<(), sqlite::Error> {
+    let sql = match pending {
+        true => "INSERT OR IGNORE INTO couch_pending (db, id) VALUES (?, ?)",
+        false => "DELETE FROM couch_pending WHERE db = ? AND id = ?",
+    };
+    let mut statement = connection.prepare(sql)?;
+    statement.bind((1, db))?;
+    statement.bind((2, id))?;
+    statement.next()?;
+    Ok(())
+}
+
+fn pending(connection: &Connection, db: &str) -> Result<Vec<String>, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT id FROM couch_pending WHERE db = ?")?;
+    statement.bind((1, db))?;
+    let mut ids = Vec::new();
+    while let State::Row = statement.next()? {
+        ids.push(statement.read::<String, _>(0)?);
+    }
+    Ok(ids)
+}
+
+/// Hash of the text replication last wrote to `path`, if it wrote it.
+fn written_hash(
+    connection: &Connection,
+    db: &str,
+    path: &str,
+) -> Result<Option<String>, sqlite::Error> {
+    let mut statement =
+        connection.prepare("SELECT hash FROM couch_written WHERE db = ? AND path = ?")?;
+    statement.bind((1, db))?;
+    statement.bind((2, path))?;
+    match statement.next()? {
+        State::Row => Ok(Some(statement.read::<String, _>(0)?)),
+        State::Done => Ok(None),
+    }
+}
+
+fn set_written(
+    connection: &Connection,
+    db: &str,
+    path: &str,
+    hash: Option<&str>,
+) -> Result<(), sqlite::Error> {
+    let mut statement = match hash {
+        Some(hash) => {
+            let mut statement = connection.prepare(
+                "INSERT OR REPLACE INTO couch_written (db, path, hash) VALUES (?, ?, ?)",
+            )?;
+            statement.bind((3, hash))?;
+            statement
+        }
+        None => connection.prepare("DELETE FROM couch_written WHERE db = ? AND path = ?")?,
+    };
+    statement.bind((1, db))?;
+    statement.bind((2, path))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Where a note changed in the vault is kept when a synced version replaces it, named the
+/// way Syncthing names its conflict copies so the `conflicts` command finds it.
+fn conflict_copy(rel_path: &Path) -> PathBuf {
+    let stem = rel_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = rel_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let stamp = Zoned::now().strftime("%Y%m%d-%H%M%S");
+    rel_path.with_file_name(format!(
+        "{}.sync-conflict-{}-LIVESYNC{}",
+        stem, stamp, extension
+    ))
+}
+
+/// Writes the notes among `ids`, and those still waiting for chunks, into the vault.
+/// Notes changed in the vault since replication last wrote them are kept: as a conflict
+/// copy when a new version arrives, in place when the note is deleted.
+fn write_notes(
+    connection: &Connection,
+    vault_path: &Path,
+    db: &str,
+    ids: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let mut edits = ChangeSet::new();
+    let mut deletions = Vec::new();
+    let mut written: Vec<(String, Option<String>)> = Vec::new();
+    let mut waiting = pending(connection, db)?;
+    waiting.extend(ids.iter().cloned());
+    waiting.sort();
+    waiting.dedup();
+    for id in waiting {
+        let Some(doc) = stored(connection, db, &id)? else {
+            set_pending(connection, db, &id, false)?;
+            continue;
+        };
+        let Some(path) = note_path(&doc) else {
+            continue;
+        };
+        let rel_path = Path::new(path);
+        capture::ensure_inside_vault(rel_path)?;
+        let current = fs::read_to_string(vault_path.join(rel_path)).ok();
+        // Whether the vault holds what replication last wrote there, or nothing
+        let synced = match &current {
+            None => true,
+            Some(current) => {
+                written_hash(connection, db, path)?.as_deref()
+                    == Some(util::content_hash(current.as_bytes()).as_str())
+            }
+        };
+        if doc["_deleted"] == true || doc["deleted"] == true {
+            if current.is_some() && synced {
+                deletions.push(rel_path.to_path_buf());
+            } else if current.is_some() {
+                log::warn!("Keeping {}: it changed since it was last synced", path);
+            }
+            written.push((path.to_string(), None));
+            set_pending(connection, db, &id, false)?;
+            continue;
+        }
+        match note_text(connection, db, &doc) {
+            Ok(Some(text)) => {
+                if let Some(local) = &current
+                    && !synced
+                    && *local != text
+                {
+                    let copy = conflict_copy(rel_path);
+                    log::warn!(
+                        "{} changed since it was last synced; keeping it as {}",
+                        path,
+                        copy.display()
+                    );
+                    edits.propose(copy, None, local.clone());
+                }
+                written.push((path.to_string(), Some(util::content_hash(text.as_bytes()))));
+                edits.propose(rel_path, current, text);
+                set_pending(connection, db, &id, false)?;
+            }
+            Ok(None) => set_pending(connection, db, &id, true)?,
+            Err(e) => {
+                log::debug!("Not writing {} into the vault: {}", path, e);
+                set_pending(connection, db, &id, false)?;
+            }
+        }
+    }
+    edits.apply(vault_path, "sync notes from LiveSync", false)?;
+    for (path, hash) in &written {
+        set_written(connection, db, path, hash.as_deref())?;
+    }
+    for rel_path in deletions {
+        trash::move_to_trash(vault_path, &rel_path)?;
+        log::info!("Moved {} to the trash", rel_path.display());
+    }
+    Ok(())
+}
+
+fn body_json(request: &Request) -> Result<Value, Response> {
+    serde_json::from_slice(&request.body)
+        .map_err(|e| couch_error(400, "bad_request", &format!("Invalid JSON body: {}", e)))
+}
+
+fn get_database(connection: &Connection, db: &str) -> Result<Response, Box<dyn Error>> {
+    if !database_exists(connection, db)? {
+        return Ok(couch_error(404, "not_found", "Database does not exist."));
+    }
+    let mut statement =
+        connection.prepare("SELECT COUNT(*) FROM couch_docs WHERE db = ? AND deleted = 0")?;
+    statement.bind((1, db))?;
+    statement.next()?;
+    let doc_count = statement.read::<i64, _>(0)?;
+    Ok(Response::json(
+        200,
+        &json!({
+            "db_name": db,
+            "doc_count": doc_count,
+            "update_seq": update_seq(connection, db)?,
+            "instance_start_time": "0",
+        }),
+    ))
+}
+
+fn put_database(connection: &Connection, db: &str) -> Result<Response, Box<dyn Error>> {
+    if database_exists(connection, db)? {
+        return Ok(couch_error(
+            412,
+            "file_exists",
+            "The database could not be created, the file already exists.",
+        ));
+    }
+    let mut statement = connection.prepare("INSERT INTO couch_databases (name) VALUES (?)")?;
+    statement.bind((1, db))?;
+    statement.next()?;
+    Ok(Response::json(201, &json!({ "ok": true })))
+}
+
+fn delete_database(connection: &Connection, db: &str) -> Result<Response, Box<dyn Error>> {
+    for table in [
+        "couch_docs",
+        "couch_local",
+        "couch_pending",
+        "couch_conflicts",
+        "couch_written",
+    ] {
+        let mut statement = connection.prepare(format!("DELETE FROM {} WHERE db = ?", table))?;
+        statement.bind((1, db))?;
+        statement.next()?;
+    }
+    let mut statement = connection.prepare("DELETE FROM couch_databases WHERE name = ?")?;
+    statement.bind((1, db))?;
+    statement.next()?;
+    Ok(Response::json(200, &json!({ "ok": true })))
+}
+
+/// Changes after `since`, optionally only of `doc_ids`, at most `limit` of them.
+fn changes(
+    connection: &Connection,
+    db: &str,
+    since: i64,
+    limit: Option<i64>,
+    doc_ids: Option<&[String]>,
+    include_docs: bool,
+) -> Result<Vec<Value>, Box<dyn Error>> {
+    let mut statement = connection.prepare(
+        "SELECT seq, id, rev, deleted, body FROM couch_docs
+         WHERE db = ? AND seq > ? ORDER BY seq",
+    )?;
+    statement.bind((1, db))?;
+    statement.bind((2, since))?;
+    let limit = limit.map_or(usize::MAX, |limit| limit.max(0) as usize);
+    let mut results = Vec::new();
+    while results.len() < limit
+        && let State::Row = statement.next()?
+    {
+        let id = statement.read::<String, _>(1)?;
+        if doc_ids.is_some_and(|ids| !ids.contains(&id)) {
+            continue;
+        }
+        let mut change = json!({
+            "seq": statement.read::<i64, _>(0)?,
+            "id": id,
+            "changes": [{ "rev": statement.read::<String, _>(2)? }],
+        });
+        if statement.read::<i64, _>(3)? != 0 {
+            change["deleted"] = Value::Bool(true);
+        }
+        if include_docs {
+            change["doc"] = serde_json::from_str(&statement.read::<String, _>(4)?)?;
+        }
+        results.push(change);
+    }
+    Ok(results)
+}
+
+/// Counts a longpoll request among the waiting ones while it lives.
+struct WaitingLongpoll;
+
+impl WaitingLongpoll {
+    fn enter() -> WaitingLongpoll {
+        WAITING_LONGPOLLS.fetch_add(1, Ordering::SeqCst);
+        WaitingLongpoll
+    }
+}
+
+impl Drop for WaitingLongpoll {
+    fn drop(&mut self) {
+        WAITING_LONGPOLLS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn get_changes(
+    connection: &Connection,
+    db: &str,
+    request: &Request,
+) -> Result<Response, Box<dyn Error>> {
+    let body: Value = match request.method.as_str() {
+        "POST" if !request.body.is_empty() => serde_json::from_slice(&request.body)?,
+        _ => Value::Null,
+    };
+    let since = match request.query.get("since").map(String::as_str) {
+        Some("now") => update_seq(connection, db)?,
+        Some(since) => since.parse().unwrap_or_default(),
+        None => 0,
+    };
+    let limit = request
+        .query
+        .get("limit")
+        .and_then(|limit| limit.parse().ok());
+    let doc_ids: Option<Vec<String>> = match request.query.get("filter").map(String::as_str) {
+        Some("_doc_ids") => match request.query.get("doc_ids") {
+            Some(ids) => Some(serde_json::from_str(ids)?),
+            None => Some(serde_json::from_value(body["doc_ids"].clone())?),
+        },
+        _ => None,
+    };
+    let include_docs = request
+        .query
+        .get("include_docs")
+        .is_some_and(|value| value == "true");
+    let wait = match request.query.get("feed").map(String::as_str) {
+        Some("longpoll") => request
+            .query
+            .get("timeout")
+            .and_then(|timeout| timeout.parse().ok())
+            .map_or(MAX_LONGPOLL, Duration::from_millis)
+            .min(MAX_LONGPOLL),
+        _ => Duration::ZERO,
+    };
+
+    let _waiting = (wait > Duration::ZERO).then(WaitingLongpoll::enter);
+    let wait = match WAITING_LONGPOLLS.load(Ordering::SeqCst) > MAX_WAITING_LONGPOLLS {
+        true => Duration::ZERO,
+        false => wait,
+    };
+    let started = Instant::now();
+    let results = loop {
+        let results = changes(
+            connection,
+            db,
+            since,
+            limit,
+            doc_ids.as_deref(),
+            include_docs,
+        )?;
+        if !results.is_empty() || started.elapsed() >= wait {
+            break results;
+        }
+        thread::sleep(LONGPOLL_INTERVAL);
+    };
+    let last_seq = results
+        .last()
+        .and_then(|change| change["seq"].as_i64())
+        .unwrap_or(since);
+    Ok(Response::json(
+        200,
+        &json!({ "results": results, "last_seq": last_seq, "pending": 0 }),
+    ))
+}
+
+fn post_revs_diff(
+    connection: &Connection,
+    db: &str,
+    body: &Value,
+) -> Result<Response, Box<dyn Error>> {
+    let mut missing = Map::new();
+    for (id, revs) in body.as_object().into_iter().flatten() {
+        let mut known: Vec<String> = stored(connection, db, id)?
+            .map(|doc| ancestry(&with_revisions(doc)))
+            .unwrap_or_default();
+        for conflict in conflicts(connection, db, id)? {
+            known.extend(ancestry(&with_revisions(conflict)));
+        }
+        let absent: Vec<&Value> = revs
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|rev| {
+                !rev.as_str()
+                    .is_some_and(|rev| known.iter().any(|known| known == rev))
+            })
+            .collect();
+        if !absent.is_empty() {
+            missing.insert(id.clone(), json!({ "missing": absent }));
+        }
+    }
+    Ok(Response::json(200, &missing))
+}
+
+fn post_bulk_docs(
+    connection: &Connection,
+    vault_path: &Path,
+    db: &str,
+    body: &Value,
+) -> Result<Response, Box<dyn Error>> {
+    let new_edits = body["new_edits"].as_bool().unwrap_or(true);
+    let mut results = Vec::new();
+    let mut saved = Vec::new();
+    connection.execute("BEGIN")?;
+    for doc in body["docs"].as_array().into_iter().flatten() {
+        let id = doc["_id"].as_str().unwrap_or_default().to_string();
+        if !new_edits {
+            if save_replicated(connection, db, doc)? {
+                saved.push(id);
+            }
+            continue;
+        }
+        match save_edit(connection, db, doc.clone())? {
+            Ok(rev) => {
+                results.push(json!({ "ok": true, "id": id, "rev": rev }));
+                saved.push(id);
+            }
+            Err(_) => results.push(json!({
+                "id": id,
+                "error": "conflict",
+                "reason": "Document update conflict.",
+            })),
+        }
+    }
+    connection.execute("COMMIT")?;
+    if let Err(e) = write_notes(connection, vault_path, db, &saved) {
+        log::warn!("Writing synced notes into the vault failed: {}", e);
+    }
+    Ok(Response::json(201, &results))
+}
+
+fn post_bulk_get(
+    connection: &Connection,
+    db: &str,
+    body: &Value,
+) -> Result<Response, Box<dyn Error>> {
+    let mut results = Vec::new();
+    for wanted in body["docs"].as_array().into_iter().flatten() {
+        let id = wanted["id"].as_str().unwrap_or_default();
+        let found = revision(connection, db, id, wanted["rev"].as_str())?.map(with_revisions);
+        let entry = match found {
+            Some(doc) => json!({ "ok": doc }),
+            None => json!({ "error": {
+                "id": id,
+                "rev": wanted["rev"],
+                "error": "not_found",
+                "reason": "missing",
+            }}),
+        };
+        results.push(json!({ "id": id, "docs": [entry] }));
+    }
+    Ok(Response::json(200, &json!({ "results": results })))
+}
+
+fn get_all_docs(
+    connection: &Connection,
+    db: &str,
+    request: &Request,
+) -> Result<Response, Box<dyn Error>> {
+    let include_docs = request
+        .query
+        .get("include_docs")
+        .is_some_and(|value| value == "true");
+    let keys: Option<Vec<String>> = match request.method.as_str() {
+        "POST" => {
+            serde_json::from_value(serde_json::from_slice::<Value>(&request.body)?["keys"].clone())?
+        }
+        _ => match request.query.get("keys") {
+            Some(keys) => Some(serde_json::from_str(keys)?),
+            None => None,
+        },
+    };
+    let mut statement = connection
+        .prepare("SELECT id, rev, body FROM couch_docs WHERE db = ? AND deleted = 0 ORDER BY id")?;
+    statement.bind((1, db))?;
+    let mut rows = Vec::new();
+    while let State::Row = statement.next()? {
+        let id = statement.read::<String, _>(0)?;
+        if keys.as_ref().is_some_and(|keys| !keys.contains(&id)) {
+            continue;
+        }
+        let mut row =
+            json!({ "id": id, "key": id, "value": { "rev": statement.read::<String, _>(1)? } });
+        if include_docs {
+            row["doc"] = serde_json::from_str(&statement.read::<String, _>(2)?)?;
+        }
+        rows.push(row);
+    }
+    Ok(Response::json(
+        200,
+        &json!({ "total_rows": rows.len(), "offset": 0, "rows": rows }),
+    ))
+}
+
+fn local_doc(
+    connection: &Connection,
+    db: &str,
+    id: &str,
+    request: &Request,
+) -> Result<Response, Box<dyn Error>> {
+    let full_id = format!("_local/{}", id);
+    match request.method.as_str() {
+        "GET" => {
+            let mut statement =
+                connection.prepare("SELECT body FROM couch_local WHERE db = ? AND id = ?")?;
+            statement.bind((1, db))?;
+            statement.bind((2, id))?;
+            match statement.next()? {
+                State::Row => {
+                    let doc: Value = serde_json::from_str(&statement.read::<String, _>(0)?)?;
+                    Ok(Response::json(200, &doc))
+                }
+                State::Done => Ok(couch_error(404, "not_found", "missing")),
+            }
+        }
+        "PUT" => {
+            let mut doc: Value = serde_json::from_slice(&request.body)?;
+            doc["_id"] = Value::from(full_id.clone());
+            doc["_rev"] = Value::from("0-1");
+            let mut statement = connection
+                .prepare("INSERT OR REPLACE INTO couch_local (db, id, body) VALUES (?, ?, ?)")?;
+            statement.bind((1, db))?;
+            statement.bind((2, id))?;
+            statement.bind((3, doc.to_string().as_str()))?;
+            statement.next()?;
+            Ok(Response::json(
+                201,
+                &json!({ "ok": true, "id": full_id, "rev": "0-1" }),
+            ))
+        }
+        "DELETE" => {
+            let mut statement =
+                connection.prepare("DELETE FROM couch_local WHERE db = ? AND id = ?")?;
+            statement.bind((1, db))?;
+            statement.bind((2, id))?;
+            statement.next()?;
+            Ok(Response::json(
+                200,
+                &json!({ "ok": true, "id": full_id, "rev": "0-0" }),
+            ))
+        }
+        _ => Ok(couch_error(
+            405,
+            "method_not_allowed",
+            "Only GET, PUT and DELETE are allowed",
+        )),
+    }
+}
+
+fn document(
+    connection: &Connection,
+    vault_path: &Path,
+    db: &str,
+    id: &str,
+    request: &Request,
+) -> Result<Response, Box<dyn Error>> {
+    match request.method.as_str() {
+        "GET" | "HEAD" => {
+            let rev = request.query.get("rev").map(String::as_str);
+            let mut doc = revision(connection, db, id, rev)?;
+            let revs = request
+                .query
+                .get("revs")
+                .is_some_and(|value| value == "true");
+            if request
+                .query
+                .get("conflicts")
+                .is_some_and(|value| value == "true")
+                && let Some(doc) = doc.as_mut()
+            {
+                let leaves: Vec<Value> = conflicts(connection, db, id)?
+                    .into_iter()
+                    .map(|conflict| conflict["_rev"].clone())
+                    .collect();
+                if !leaves.is_empty() {
+                    doc["_conflicts"] = Value::Array(leaves);
+                }
+            }
+            match doc {
+                Some(doc) if doc["_deleted"] != true => Ok(Response::json(
+                    200,
+                    &match revs {
+                        true => with_revisions(doc),
+                        false => {
+                            let mut doc = doc;
+                            if let Some(doc) = doc.as_object_mut() {
+                                doc.remove("_revisions");
+                            }
+                            doc
+                        }
+                    },
+                )),
+                Some(_) => Ok(couch_error(404, "not_found", "deleted")),
+                None => Ok(couch_error(404, "not_found", "missing")),
+            }
+        }
+        "PUT" | "DELETE" => {
+            let mut doc = match request.method.as_str() {
+                "PUT" => serde_json::from_slice(&request.body)?,
+                _ => json!({ "_deleted": true }),
+            };
+            doc["_id"] = Value::from(id);
+            if let Some(rev) = request.query.get("rev") {
+                doc["_rev"] = Value::from(rev.as_str());
+            }
+            if request
+                .query
+                .get("new_edits")
+                .is_some_and(|value| value == "false")
+            {
+                save_replicated(connection, db, &doc)?;
+                let rev = doc["_rev"].clone();
+                write_notes(connection, vault_path, db, &[id.to_string()])?;
+                return Ok(Response::json(
+                    201,
+                    &json!({ "ok": true, "id": id, "rev": rev }),
+                ));
+            }
+            match save_edit(connection, db, doc)? {
+                Ok(rev) => {
+                    write_notes(connection, vault_path, db, &[id.to_string()])?;
+                    let status = if request.method == "PUT" { 201 } else { 200 };
+                    Ok(Response::json(
+                        status,
+                        &json!({ "ok": true, "id": id, "rev": rev }),
+                    ))
+                }
+                Err(conflict) => Ok(conflict),
+            }
+        }
+        _ => Ok(couch_error(
+            405,
+            "method_not_allowed",
+            "Only GET, PUT and DELETE are allowed",
+        )),
+    }
+}
+
+fn database_request(
+    connection: &Connection,
+    vault_path: &Path,
+    db: &str,
+    rest: &str,
+    request: &Request,
+) -> Result<Response, Box<dyn Error>> {
+    let method = request.method.as_str();
+    match (method, rest) {
+        ("GET" | "HEAD", "") => return get_database(connection, db),
+        ("PUT", "") => return put_database(connection, db),
+        ("DELETE", "") => return delete_database(connection, db),
+        _ => {}
+    }
+    if !database_exists(connection, db)? {
+        return Ok(couch_error(404, "not_found", "Database does not exist."));
+    }
+    let body = || body_json(request);
+    Ok(match (method, rest) {
+        ("GET" | "POST", "_changes") => get_changes(connection, db, request)?,
+        ("POST", "_revs_diff") => match body() {
+            Ok(body) => post_revs_diff(connection, db, &body)?,
+            Err(response) => response,
+        },
+        ("POST", "_bulk_docs") => match body() {
+            Ok(body) => post_bulk_docs(connection, vault_path, db, &body)?,
+            Err(response) => response,
+        },
+        ("POST", "_bulk_get") => match body() {
+            Ok(body) => post_bulk_get(connection, db, &body)?,
+            Err(response) => response,
+        },
+        ("GET" | "POST", "_all_docs") => get_all_docs(connection, db, request)?,
+        ("POST", "_ensure_full_commit") => {
+            Response::json(201, &json!({ "ok": true, "instance_start_time": "0" }))
+        }
+        (_, rest) if rest.starts_with("_local/") => {
+            local_doc(connection, db, &rest["_local/".len()..], request)?
+        }
+        (_, rest) if rest.starts_with('_') && !rest.starts_with("_design/") => couch_error(
+            400,
+            "illegal_docid",
+            "Only reserved document ids may start with underscore.",
+        ),
+        (_, id) => document(connection, vault_path, db, id, request)?,
+    })
+}
+
+/// Serves the CouchDB API request `request`, `path` being its path under `/couchdb`.
+pub fn handle(state: &ApiState, request: &Request, path: &str) -> Response {
+    let path = path.trim_start_matches('/');
+    if let ("GET" | "HEAD", "") = (request.method.as_str(), path) {
+        return Response::json(
+            200,
+            &json!({
+                "couchdb": "Welcome",
+                "version": COUCH_VERSION,
+                "vendor": { "name": "obsidian-rs" },
+            }),
+        );
+    }
+    // Replication writes into the vault, so it is never open to whoever can connect
+    if state.keys.is_empty() {
+        return couch_error(
+            401,
+            "unauthorized",
+            "The CouchDB endpoint needs an API key; configure [[server.keys]].",
+        );
+    }
+    if path == "_session" {
+        let roles = match auth::presented_scope(&state.keys, request) {
+            Some(Scope::Write) => json!(["_admin"]),
+            _ => json!([]),
+        };
+        return Response::json(
+            200,
+            &json!({ "ok": true, "userCtx": { "name": null, "roles": roles } }),
+        );
+    }
+    let connection = match index::open(&state.config) {
+        Ok(connection) => connection,
+        Err(e) => return internal(e),
+    };
+    if let Err(e) = ensure_schema(&connection) {
+        return internal(e.into());
+    }
+    if path == "_all_dbs" {
+        let mut statement =
+            match connection.prepare("SELECT name FROM couch_databases ORDER BY name") {
+                Ok(statement) => statement,
+                Err(e) => return internal(e.into()),
+            };
+        let mut names = Vec::new();
+        while let Ok(State::Row) = statement.next() {
+            names.extend(statement.read::<String, _>(0).ok());
+        }
+        return Response::json(200, &names);
+    }
+    let (db, rest) = path.split_once('/').unwrap_or((path, ""));
+    database_request(&connection, &state.vault_path, db, rest, request).unwrap_or_else(internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, body: Value) -> Request {
+        Request {
+            method: method.to_string(),
+            body: body.to_string().into_bytes(),
+            ..Request::default()
+        }
+    }
+
+    fn json_of(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_replication_writes_notes() {
+        let vault = tempfile::tempdir().unwrap();
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let call = |method: &str, rest: &str, body: Value| {
+            database_request(
+                &connection,
+                vault.path(),
+                "notes",
+                rest,
+                &request(method, body),
+            )
+            .unwrap()
+        };
+        assert_eq!(call("GET", "", Value::Null).status, 404);
+        assert_eq!(call("PUT", "", Value::Null).status, 201);
+        assert_eq!(call("PUT", "", Value::Null).status, 412);
+
+        let note = json!({
+            "_id": "inbox/idea.md",
+            "_rev": "2-b",
+            "_revisions": { "start": 2, "ids": ["b", "a"] },
+            "path": "Inbox/Idea.md",
+            "type": "plain",
+            "children": ["h:1", "h:2"],
+        });
+        let diff = call(
+            "POST",
+            "_revs_diff",
+            json!({ "inbox/idea.md": ["2-b"], "h:1": ["1-x"] }),
+        );
+        assert_eq!(json_of(&diff)["inbox/idea.md"]["missing"], json!(["2-b"]));
+
+        // The note waits for its second chunk
+        let first = json!({ "new_edits": false, "docs": [
+            note,
+            { "_id": "h:1", "_rev": "1-x", "type": "leaf", "data": "Hello, " },
+        ]});
+        assert_eq!(call("POST", "_bulk_docs", first).status, 201);
+        assert!(!vault.path().join("Inbox/Idea.md").exists());
+        let second = json!({ "new_edits": false, "docs": [
+            { "_id": "h:2", "_rev": "1-y", "type": "leaf", "data": "world\n" },
+        ]});
+        call("POST", "_bulk_docs", second);
+        assert_eq!(
+            fs::read_to_string(vault.path().join("Inbox/Idea.md")).unwrap(),
+            "Hello, world\n"
+        );
+
+        // Older revisions are known, a losing one is not stored
+        let diff = call(
+            "POST",
+            "_revs_diff",
+            json!({ "inbox/idea.md": ["1-a", "2-b"] }),
+        );
+        assert_eq!(json_of(&diff), json!({}));
+        let stale =
+            json!({ "new_edits": false, "docs": [{ "_id": "h:1", "_rev": "1-a", "data": "x" }] });
+        call("POST", "_bulk_docs", stale);
+
+        let changes = json_of(&call("GET", "_changes", Value::Null));
+        let ids: Vec<&str> = changes["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|change| change["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, vec!["inbox/idea.md", "h:1", "h:2"]);
+        assert_eq!(changes["last_seq"], 3);
+
+        let got = json_of(&call(
+            "POST",
+            "_bulk_get",
+            json!({ "docs": [{ "id": "h:1", "rev": "1-x" }] }),
+        ));
+        assert_eq!(got["results"][0]["docs"][0]["ok"]["data"], "Hello, ");
+
+        // Edits through the API must name the revision they change
+        let put = call("PUT", "settings", json!({ "theme": "dark" }));
+        let rev = json_of(&put)["rev"].as_str().unwrap().to_string();
+        assert!(rev.starts_with("1-"));
+        assert_eq!(
+            call("PUT", "settings", json!({ "theme": "light" })).status,
+            409
+        );
+        let update = call("PUT", "settings", json!({ "theme": "light", "_rev": rev }));
+        assert_eq!(update.status, 201);
+    }
+
+    #[test]
+    fn test_conflicts_and_local_edits() {
+        let vault = tempfile::tempdir().unwrap();
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let call = |method: &str, rest: &str, body: Value| {
+            database_request(
+                &connection,
+                vault.path(),
+                "notes",
+                rest,
+                &request(method, body),
+            )
+            .unwrap()
+        };
+        call("PUT", "", Value::Null);
+        let note = |rev: &str, chunk: &str| {
+            json!({
+                "_id": "a.md",
+                "_rev": rev,
+                "path": "A.md",
+                "type": "plain",
+                "children": [chunk],
+            })
+        };
+        let replicate = |docs: Value| {
+            call(
+                "POST",
+                "_bulk_docs",
+                json!({ "new_edits": false, "docs": docs }),
+            )
+        };
+        replicate(json!([
+            { "_id": "h:1", "_rev": "1-a", "type": "leaf", "data": "one\n" },
+            { "_id": "h:2", "_rev": "1-b", "type": "leaf", "data": "two\n" },
+            note("1-a", "h:1"),
+        ]));
+        assert_eq!(
+            fs::read_to_string(vault.path().join("A.md")).unwrap(),
+            "one\n"
+        );
+
+        // A sibling revision that loses is kept as a conflict, not dropped
+        replicate(json!([note("1-0", "h:2")]));
+        assert_eq!(
+            stored(&connection, "notes", "a.md").unwrap().unwrap()["_rev"],
+            "1-a"
+        );
+        let stored_conflicts = conflicts(&connection, "notes", "a.md").unwrap();
+        assert_eq!(stored_conflicts.len(), 1);
+        assert_eq!(stored_conflicts[0]["_rev"], "1-0");
+        let diff = call("POST", "_revs_diff", json!({ "a.md": ["1-0"] }));
+        assert_eq!(json_of(&diff), json!({}));
+
+        // An edit made in the vault survives as a conflict copy
+        fs::write(vault.path().join("A.md"), "local\n").unwrap();
+        let mut next = note("2-c", "h:2");
+        next["_revisions"] = json!({ "start": 2, "ids": ["c", "a"] });
+        replicate(json!([next]));
+        assert_eq!(
+            fs::read_to_string(vault.path().join("A.md")).unwrap(),
+            "two\n"
+        );
+        let copies: Vec<String> = fs::read_dir(vault.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".sync-conflict-"))
+            .collect();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(
+            fs::read_to_string(vault.path().join(&copies[0])).unwrap(),
+            "local\n"
+        );
+
+        // doc_ids are filtered before the limit applies
+        let found = changes(
+            &connection,
+            "notes",
+            0,
+            Some(1),
+            Some(&[String::from("a.md")]),
+            false,
+        )
+        .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0]["id"], "a.md");
+    }
+}
@@ -1,9 +1,12 @@
 use crate::config;
+use crate::folder_config::FolderConfigs;
+pub use crate::note::{FrontMatter, Note};
 use crate::util;
 
 use sqlite::{Connection, Error as SqliteError};
 use serde::Deserialize;
 use std::{
+    collections::HashMap,
     env,
     error::Error,
     fmt, fs,
@@ -42,30 +45,46 @@ fn get_local_data_dir() -> Option<PathBuf> {
 }
 
 pub fn get_data_path(config: &config::AppConfig) -> Result<PathBuf, Box<dyn Error>> {
-    let mut data_path = get_local_data_dir()
+    let data_dir = get_local_data_dir()
         .ok_or_else(|| -> Box<dyn Error> { Box::from("Local data folder not found.") })?;
     let root_workspace_path = config::get_root_workspace_path(config)
         .ok_or("Failed to get Root Workspace Path from Config.")?;
+    vault_data_path(&data_dir.join(DEFAULT_DATA_DIR), &root_workspace_path)
+}
 
-    let file_name_os_str = root_workspace_path
-        .file_name()
-        .ok_or_else(|| -> Box<dyn Error> {
-            Box::from(format!(
-                "Invalid configuration: Workspace path '{}' has no final component (filename).",
-                root_workspace_path.display()
-            ))
-        })?;
+/// The folder in `data_dir` for the vault at `root`: named after the vault, and keyed by
+/// its canonical path so that vaults sharing a folder name keep apart. A folder named
+/// after the vault alone, as older versions made, is moved there.
+fn vault_data_path(data_dir: &Path, root: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let file_name_os_str = root.file_name().ok_or_else(|| -> Box<dyn Error> {
+        Box::from(format!(
+            "Invalid configuration: Workspace path '{}' has no final component (filename).",
+            root.display()
+        ))
+    })?;
 
     let vault_name = file_name_os_str.to_str().ok_or_else(|| -> Box<dyn Error> {
         Box::from(format!(
             "Invalid configuration: Vault name in path '{}' contains non-UTF-8 characters.",
-            root_workspace_path.display()
+            root.display()
         ))
     })?;
 
-    data_path.push(DEFAULT_DATA_DIR);
-    data_path.push(vault_name);
-
+    let canonical = util::resolve_path(root).unwrap_or_else(|_| root.to_path_buf());
+    let key = util::content_hash(canonical.to_string_lossy().as_bytes());
+    let data_path = data_dir.join(format!("{}-{}", vault_name, &key[..8]));
+    let legacy = data_dir.join(vault_name);
+    if !data_path.exists() && legacy.is_dir() {
+        match fs::rename(&legacy, &data_path) {
+            Ok(()) => log::info!(
+                "Moved the data of {} from {} to {}",
+                root.display(),
+                legacy.display(),
+                data_path.display()
+            ),
+            Err(e) => log::warn!("Cannot move {}: {}", legacy.display(), e),
+        }
+    }
     Ok(data_path)
 }
 
@@ -91,45 +110,70 @@ impl fmt::Display for NodeData {
     }
 }
 
-#[derive(Deserialize, Debug, Default)]
-pub struct FrontMatter {
-    pub title: Option<String>,
-    pub github: Option<String>,
-    pub created: Option<Vec<String>>,
-    pub tags: Option<Vec<String>>,
-    pub authors: Option<Vec<String>>,
+/// Reads every note of the vault into memory.
+pub fn load_notes(vault_path: &Path) -> Result<Vec<Note>, Box<dyn Error>> {
+    Ok(iter_notes(vault_path)?.collect())
 }
 
-impl fmt::Display for FrontMatter {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut output_parts = Vec::new();
-
-        if let Some(title) = &self.title {
-            output_parts.push(format!("Title: {}", title));
-        }
-
-        if let Some(github) = &self.github {
-            output_parts.push(format!("github: {}", github));
-        }
-
-        if let Some(created_dates) = &self.created {
-            if !created_dates.is_empty() {
-                output_parts.push(format!("Created: {}", created_dates.join(", ")));
-            }
-        }
+/// Reads the vault's notes one at a time, for passes that need not hold them all.
+pub fn iter_notes(vault_path: &Path) -> Result<impl Iterator<Item = Note> + '_, Box<dyn Error>> {
+    let files = traverse_vault(vault_path)?;
+    Ok(files
+        .into_iter()
+        .filter(|file| is_note(file))
+        .filter_map(move |file| {
+            let content = match fs::read_to_string(&file) {
+                Ok(content) => content,
+                Err(e) => {
+                    log::warn!("Skipping '{}': {}", file.display(), e);
+                    return None;
+                }
+            };
+            let rel_path = util::get_relative_path(&file, vault_path).ok()?;
+            let mut note = Note::from_content(rel_path, content);
+            note.modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+            Some(note)
+        }))
+}
 
-        if let Some(tags) = &self.tags {
-            if !tags.is_empty() {
-                output_parts.push(format!("Tags: {}", tags.join(", ")));
-            }
-        }
+/// The vault's notes kept between calls, each read again only when its modification
+/// time changes.
+#[derive(Debug, Default)]
+pub struct NoteCache {
+    notes: Vec<Note>,
+}
 
-        if let Some(authors) = &self.authors {
-            if !authors.is_empty() {
-                output_parts.push(format!("Authors: {}", authors.join(", ")));
+impl NoteCache {
+    /// Brings the notes up to date with the vault and returns them, ordered by path.
+    pub fn refresh(&mut self, vault_path: &Path) -> Result<&[Note], Box<dyn Error>> {
+        let mut known: HashMap<PathBuf, Note> = std::mem::take(&mut self.notes)
+            .into_iter()
+            .map(|note| (note.path.clone(), note))
+            .collect();
+        for file in traverse_vault(vault_path)? {
+            if !is_note(&file) {
+                continue;
             }
+            let rel_path = util::get_relative_path(&file, vault_path)?;
+            let modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+            let note = match known.remove(&rel_path) {
+                Some(note) if modified.is_some() && note.modified == modified => note,
+                _ => match fs::read_to_string(&file) {
+                    Ok(content) => {
+                        let mut note = Note::from_content(rel_path, content);
+                        note.modified = modified;
+                        note
+                    }
+                    Err(e) => {
+                        log::warn!("Skipping '{}': {}", file.display(), e);
+                        continue;
+                    }
+                },
+            };
+            self.notes.push(note);
         }
-        write!(f, "{}", output_parts.join("\n"))
+        self.notes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(&self.notes)
     }
 }
 
@@ -141,11 +185,32 @@ fn is_hidden(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
+/// Whether [`traverse_vault`] would list `rel_path`: nothing on it is hidden (which
+/// covers `.trash`) and it is not in an ignored folder.
+pub fn is_listed(folders: &FolderConfigs, rel_path: &Path) -> bool {
+    let hidden = rel_path
+        .components()
+        .any(|c| c.as_os_str().to_str().is_some_and(|s| s.starts_with('.')));
+    !hidden && !folders.is_ignored(rel_path)
+}
+
+/// Markdown notes, as opposed to attachments
+pub fn is_note(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("md"))
+}
+
 pub fn traverse_vault(vault_path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
     let walker = WalkDir::new(vault_path).into_iter();
     let mut files = Vec::<PathBuf>::new();
+    let folders = FolderConfigs::load(vault_path)?;
+    let ignored = |e: &DirEntry| {
+        !folders.is_empty()
+            && e.path()
+                .strip_prefix(vault_path)
+                .is_ok_and(|rel_path| folders.is_ignored(rel_path))
+    };
 
-    for entry in walker.filter_entry(|e| !is_hidden(e)) {
+    for entry in walker.filter_entry(|e| !is_hidden(e) && !ignored(e)) {
         let current_entry = entry?;
         let path_to_current_entry = current_entry.path();
 
@@ -242,12 +307,17 @@ pub fn parse_yaml_front_matter(file_path: &Path) -> Result<Option<FrontMatter>,
     Ok(Some(data))
 }
 
+/// How long to wait for another process's write to the cache before giving up
+const BUSY_TIMEOUT_MS: usize = 5000;
+
 /// Check to see if caching database exists
 pub fn get_cache(data_path: &Path) -> Result<Connection, SqliteError> {
-    if fs::create_dir_all(data_path).is_err() {}
+    if let Err(e) = fs::create_dir_all(data_path) {
+        log::warn!("Could not create data directory {}: {}", data_path.display(), e);
+    }
     let mut cache_path = data_path.to_owned(); // Clones automatically
     cache_path.push("cache.db3");
-    let db = match sqlite::open(&cache_path) {
+    let mut db = match sqlite::open(&cache_path) {
         Err(e) => {
             log::error!(
                 "Problem opening or creating database {}: {}",
@@ -258,6 +328,8 @@ pub fn get_cache(data_path: &Path) -> Result<Connection, SqliteError> {
         }
         Ok(connection) => connection,
     };
+    // Other processes (CLI commands next to the daemon) may hold the write lock briefly
+    db.set_busy_timeout(BUSY_TIMEOUT_MS)?;
 
     db.execute(
         "CREATE TABLE IF NOT EXISTS nodes (
@@ -294,7 +366,7 @@ pub fn invalidate_cache(
 
 /// Exists in cache?
 fn exists_in_cache(_entry: &Path, _cache: &Connection) -> Result<bool, StripPrefixError> {
-    return Ok(true);
+    Ok(true)
 }
 
 /// Add entry to cache
@@ -309,3 +381,39 @@ fn _remove_from_cache() {}
 fn update_in_cache(_entry: &Path) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault_data_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        fs::create_dir_all(data_dir.join("Notes")).unwrap();
+        fs::write(data_dir.join("Notes/cache.db"), "old").unwrap();
+        let first = dir.path().join("a/Notes");
+        let second = dir.path().join("b/Notes");
+
+        let first_data = vault_data_path(&data_dir, &first).unwrap();
+        let second_data = vault_data_path(&data_dir, &second).unwrap();
+        assert_ne!(first_data, second_data);
+        assert!(
+            first_data
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("Notes-")
+        );
+        // The first vault to ask takes over the folder older versions shared
+        assert_eq!(
+            fs::read_to_string(first_data.join("cache.db")).unwrap(),
+            "old"
+        );
+        assert!(!data_dir.join("Notes").exists());
+        assert_eq!(
+            vault_data_path(&data_dir, &first.join(".")).unwrap(),
+            first_data
+        );
+    }
+}
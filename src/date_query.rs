@@ -0,0 +1,312 @@
+//! Date conditions in queries: `created:>=-7d`, `due:<=today`, `modified:this-week`.
+//!
+//! A condition compares a note date with a period of whole days: a date (`2024-05-01`),
+//! month (`2024-05`) or year, `today`, `yesterday` or `tomorrow`, a number of days, weeks,
+//! months or years from today (`-7d`, `+2w`), or `this-`, `last-` or `next-` followed by
+//! `week` (starting on Monday), `month` or `year`. Without an operator the date must fall
+//! in the period; `>=` and `<` compare with its first day, `<=` and `>` with its last.
+
+use crate::data::Note;
+use crate::frontmatter;
+
+use jiff::{Span, Timestamp, ToSpan, civil, tz::TimeZone};
+use std::{error::Error, fmt};
+
+/// A note date queries can compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateField {
+    /// Front matter `created`
+    Created,
+    /// Modification time of the file, or front matter `modified` or `updated`
+    Modified,
+    /// Front matter `due`
+    Due,
+    /// Front matter `date`
+    Date,
+}
+
+impl DateField {
+    pub fn parse(name: &str) -> Option<DateField> {
+        match name {
+            "created" => Some(DateField::Created),
+            "modified" => Some(DateField::Modified),
+            "due" => Some(DateField::Due),
+            "date" => Some(DateField::Date),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DateField::Created => "created",
+            DateField::Modified => "modified",
+            DateField::Due => "due",
+            DateField::Date => "date",
+        }
+    }
+
+    /// The day, in the local time zone, `note` has for the field.
+    pub fn of(self, note: &Note) -> Option<civil::Date> {
+        if self == DateField::Modified
+            && let Some(modified) = note.modified
+            && let Ok(timestamp) = Timestamp::try_from(modified)
+        {
+            return Some(timestamp.to_zoned(TimeZone::system()).date());
+        }
+        let keys: &[&str] = match self {
+            DateField::Created => &["created"],
+            DateField::Modified => &["modified", "updated"],
+            DateField::Due => &["due"],
+            DateField::Date => &["date"],
+        };
+        let properties = frontmatter::scalar_properties(&note.content);
+        keys.iter().find_map(|key| {
+            let (_, text) = properties.iter().find(|(own, _)| own == key)?;
+            parse_day(text)
+        })
+    }
+}
+
+/// The day `text` names: a date, a date and time, or a moment with an offset taken in the
+/// local time zone.
+fn parse_day(text: &str) -> Option<civil::Date> {
+    let text = text.trim();
+    if let Ok(datetime) = text.parse::<civil::DateTime>() {
+        return Some(datetime.date());
+    }
+    let timestamp = text.parse::<Timestamp>().ok()?;
+    Some(timestamp.to_zoned(TimeZone::system()).date())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Before,
+    AtMost,
+    Within,
+    AtLeast,
+    After,
+}
+
+impl Comparison {
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Before => "<",
+            Comparison::AtMost => "<=",
+            Comparison::Within => "",
+            Comparison::AtLeast => ">=",
+            Comparison::After => ">",
+        }
+    }
+}
+
+/// A note date compared with a period
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateCondition {
+    pub field: DateField,
+    pub comparison: Comparison,
+    /// The period as written
+    pub period: String,
+    /// First day of the period, and the day after it
+    pub start: civil::Date,
+    pub end: civil::Date,
+}
+
+impl fmt::Display for DateCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}{}",
+            self.field.name(),
+            self.comparison.symbol(),
+            self.period
+        )
+    }
+}
+
+/// `start` with `span` added, or an error naming `text` when that leaves the calendar.
+fn shift(start: civil::Date, span: Span, text: &str) -> Result<civil::Date, Box<dyn Error>> {
+    start
+        .checked_add(span)
+        .map_err(|_| format!("Date '{}' is out of range", text).into())
+}
+
+/// The days `text` covers, from `today`.
+pub fn period(
+    text: &str,
+    today: civil::Date,
+) -> Result<(civil::Date, civil::Date), Box<dyn Error>> {
+    let day = |date: civil::Date| Ok((date, shift(date, 1.day(), text)?));
+    match text {
+        "today" => return day(today),
+        "yesterday" => return day(shift(today, (-1).day(), text)?),
+        "tomorrow" => return day(shift(today, 1.day(), text)?),
+        _ => {}
+    }
+
+    if let Some((when, unit)) = text.split_once('-')
+        && let Some(offset) = match when {
+            "last" => Some(-1),
+            "this" => Some(0),
+            "next" => Some(1),
+            _ => None,
+        }
+    {
+        let (first, length) = match unit {
+            "week" => {
+                let monday = -i64::from(today.weekday().to_monday_zero_offset());
+                (shift(today, monday.days(), text)?, 1.week())
+            }
+            "month" => (today.first_of_month(), 1.month()),
+            "year" => (today.first_of_year(), 1.year()),
+            _ => return Err(unknown(text)),
+        };
+        let start = shift(first, length * offset, text)?;
+        return Ok((start, shift(start, length, text)?));
+    }
+
+    // -7d, +2w, 3m, -1y
+    let (sign, rest) = match text.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if let Some(unit) = rest.chars().last()
+        && unit.is_ascii_alphabetic()
+        && let Ok(count) = rest[..rest.len() - unit.len_utf8()].parse::<i64>()
+    {
+        let count = sign * count;
+        let span = match unit {
+            'd' => Span::new().try_days(count),
+            'w' => Span::new().try_weeks(count),
+            'm' => Span::new().try_months(count),
+            'y' => Span::new().try_years(count),
+            _ => return Err(unknown(text)),
+        }
+        .map_err(|_| format!("Date '{}' is out of range", text))?;
+        return day(shift(today, span, text)?);
+    }
+
+    // 2024-05-01, 2024-05 or 2024
+    let (date, length) = match text.len() {
+        10 => (text.to_string(), 1.day()),
+        7 => (format!("{}-01", text), 1.month()),
+        4 => (format!("{}-01-01", text), 1.year()),
+        _ => return Err(unknown(text)),
+    };
+    let start = date.parse::<civil::Date>().map_err(|_| unknown(text))?;
+    Ok((start, shift(start, length, text)?))
+}
+
+fn unknown(text: &str) -> Box<dyn Error> {
+    format!(
+        "Unknown date '{}'; expected e.g. 2024-05-01, 2024-05, today, -7d, +2w or this-week",
+        text
+    )
+    .into()
+}
+
+impl DateCondition {
+    /// Parses `<=today`-style `text` for `field`, taking relative dates from `today`.
+    pub fn parse(
+        field: DateField,
+        text: &str,
+        today: civil::Date,
+    ) -> Result<DateCondition, Box<dyn Error>> {
+        let (comparison, rest) = [
+            (">=", Comparison::AtLeast),
+            ("<=", Comparison::AtMost),
+            (">", Comparison::After),
+            ("<", Comparison::Before),
+            ("=", Comparison::Within),
+        ]
+        .into_iter()
+        .find_map(|(symbol, comparison)| Some((comparison, text.strip_prefix(symbol)?)))
+        .unwrap_or((Comparison::Within, text));
+        let (start, end) = period(rest, today)?;
+        Ok(DateCondition {
+            field,
+            comparison,
+            period: rest.to_string(),
+            start,
+            end,
+        })
+    }
+
+    /// Whether the period is counted from today, so the condition changes day by day.
+    pub fn is_relative(&self) -> bool {
+        !self.period.bytes().all(|b| b.is_ascii_digit() || b == b'-')
+    }
+
+    pub fn matches(&self, date: civil::Date) -> bool {
+        match self.comparison {
+            Comparison::Before => date < self.start,
+            Comparison::AtMost => date < self.end,
+            Comparison::Within => self.start <= date && date < self.end,
+            Comparison::AtLeast => date >= self.start,
+            Comparison::After => date >= self.end,
+        }
+    }
+
+    /// Whether the field of `note` satisfies the condition; notes without the date never do.
+    pub fn matches_note(&self, note: &Note) -> bool {
+        self.field.of(note).is_some_and(|date| self.matches(date))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_and_match() {
+        // A Wednesday
+        let today = civil::date(2024, 5, 15);
+        let parse = |text: &str| DateCondition::parse(DateField::Due, text, today).unwrap();
+
+        let week = parse("this-week");
+        assert_eq!(
+            (week.start, week.end),
+            (civil::date(2024, 5, 13), civil::date(2024, 5, 20))
+        );
+        let last_month = parse("last-month");
+        assert_eq!(
+            (last_month.start, last_month.end),
+            (civil::date(2024, 4, 1), civil::date(2024, 5, 1))
+        );
+        assert_eq!(parse(">=-7d").start, civil::date(2024, 5, 8));
+        assert_eq!(parse("2024").end, civil::date(2025, 1, 1));
+        assert_eq!(parse("<=today").to_string(), "due:<=today");
+        assert!(parse("3d").is_relative() && !parse("2024-05").is_relative());
+
+        assert!(parse("<=today").matches(today));
+        assert!(!parse("<today").matches(today));
+        assert!(parse(">yesterday").matches(today));
+        assert!(parse("2024-05").matches(today));
+        assert!(!parse("next-week").matches(today));
+
+        let note = Note::from_content(
+            PathBuf::from("Task.md"),
+            String::from("---\ndue: 2024-05-14\ncreated: 2024-05-01T10:00:00\n---\n"),
+        );
+        assert!(parse("yesterday").matches_note(&note));
+        assert_eq!(DateField::Created.of(&note), Some(civil::date(2024, 5, 1)));
+        assert!(DateField::Date.of(&note).is_none());
+
+        for bad in ["soon", "this-decade", "-7x", "2024-13-01", ""] {
+            assert!(
+                DateCondition::parse(DateField::Due, bad, today).is_err(),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_offsets() {
+        let today = civil::date(2024, 5, 15);
+        for huge in ["-99999999y", "+9223372036854775807d", "99999999999m"] {
+            let err = DateCondition::parse(DateField::Due, huge, today).unwrap_err();
+            assert!(err.to_string().contains("out of range"), "{}", huge);
+        }
+    }
+}
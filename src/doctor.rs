@@ -0,0 +1,121 @@
+//! Vault health checks that need attention but are not lint issues of a single note.
+
+use crate::cli::{DoctorArgs, OutputFormat};
+use crate::config::AppConfig;
+use crate::conflicts;
+use crate::data;
+#[cfg(feature = "images")]
+use crate::images;
+#[cfg(feature = "images")]
+use crate::index;
+use crate::resolver::TitleIndex;
+use crate::util;
+
+use serde::Serialize;
+use std::{error::Error, fmt, path::Path};
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Finding {
+    pub check: &'static str,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: [{}] {}", self.path, self.check, self.message)
+    }
+}
+
+/// Images over the `[images]` limits.
+#[cfg(feature = "images")]
+fn oversized_images(vault_path: &Path, config: &AppConfig) -> Result<Vec<Finding>, Box<dyn Error>> {
+    // Without the cache database, images are read afresh instead
+    let connection = match index::open(config) {
+        Ok(connection) => connection,
+        Err(e) => {
+            log::warn!("Checking images without the cache database: {}", e);
+            sqlite::open(":memory:")?
+        }
+    };
+    let mut findings = Vec::new();
+    for image in images::refresh(&connection, vault_path)? {
+        if let Some(message) = images::oversized(&image, &config.images) {
+            findings.push(Finding {
+                check: "oversized-image",
+                path: image.path,
+                message: format!("{}; see `images recompress`", message),
+            });
+        }
+    }
+    Ok(findings)
+}
+
+/// Without the `images` feature images are not read, so none is oversized.
+#[cfg(not(feature = "images"))]
+fn oversized_images(
+    _vault_path: &Path,
+    _config: &AppConfig,
+) -> Result<Vec<Finding>, Box<dyn Error>> {
+    Ok(Vec::new())
+}
+
+pub fn check_vault(vault_path: &Path, config: &AppConfig) -> Result<Vec<Finding>, Box<dyn Error>> {
+    let mut findings = Vec::new();
+    for conflict in conflicts::scan(vault_path)? {
+        findings.push(Finding {
+            check: "sync-conflict",
+            path: conflict.path.to_string_lossy().replace('\\', "/"),
+            message: format!(
+                "{} conflict copy of '{}'; see `conflicts resolve`",
+                conflict.source,
+                conflict.original.display()
+            ),
+        });
+    }
+
+    let notes = data::load_notes(vault_path)?;
+    for (title, paths) in TitleIndex::new(&notes).duplicates() {
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        findings.push(Finding {
+            check: "duplicate-title",
+            path: paths[0].clone(),
+            message: format!(
+                "'{}' is also the title, alias or name of {}; [[{}]] is ambiguous",
+                title,
+                paths[1..].join(", "),
+                title
+            ),
+        });
+    }
+
+    findings.extend(oversized_images(vault_path, config)?);
+    Ok(findings)
+}
+
+/// Prints the findings, returning how many there were.
+pub fn run_doctor(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &DoctorArgs,
+) -> Result<usize, Box<dyn Error>> {
+    let findings = check_vault(vault_path, config)?;
+    match args.format {
+        OutputFormat::Text => {
+            for finding in &findings {
+                println!("{}", finding);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&findings)?),
+        OutputFormat::Ndjson => {
+            for finding in &findings {
+                util::print_ndjson(finding)?;
+            }
+        }
+    }
+    log::info!("Doctor found {} problem(s).", findings.len());
+    Ok(findings.len())
+}
@@ -0,0 +1,624 @@
+//! Word export. Notes are flattened and walked as Markdown events, which map onto
+//! WordprocessingML: headings and quotes become paragraph styles, lists use numbering
+//! definitions, tables become Word tables and local images are embedded in the package.
+
+use crate::cli::ExportDocxArgs;
+use crate::data::{self, Note};
+use crate::flatten;
+use crate::frontmatter;
+use crate::query::Query;
+use crate::render;
+use crate::resolver::Resolver;
+use crate::util::{self, xml_escape};
+
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+/// English Metric Units per pixel at 96 dpi
+const EMU_PER_PIXEL: u64 = 9525;
+/// Widest an image may be: the text width of a Letter or A4 page, 6 inches
+const MAX_IMAGE_WIDTH: u64 = 6 * 914400;
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Default Extension="png" ContentType="image/png"/><Default Extension="jpeg" ContentType="image/jpeg"/><Default Extension="gif" ContentType="image/gif"/><Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/><Override PartName="/word/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml"/><Override PartName="/word/numbering.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml"/><Override PartName="/docProps/core.xml" ContentType="application/vnd.openxmlformats-package.core-properties+xml"/></Types>
+"#;
+
+const PACKAGE_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/></Relationships>
+"#;
+
+const STYLES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:styles xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:style w:type="paragraph" w:default="1" w:styleId="Normal"><w:name w:val="Normal"/><w:pPr><w:spacing w:after="120"/></w:pPr></w:style><w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/><w:spacing w:before="360"/><w:outlineLvl w:val="0"/></w:pPr><w:rPr><w:b/><w:sz w:val="36"/></w:rPr></w:style><w:style w:type="paragraph" w:styleId="Heading2"><w:name w:val="heading 2"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/><w:spacing w:before="240"/><w:outlineLvl w:val="1"/></w:pPr><w:rPr><w:b/><w:sz w:val="30"/></w:rPr></w:style><w:style w:type="paragraph" w:styleId="Heading3"><w:name w:val="heading 3"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/><w:spacing w:before="240"/><w:outlineLvl w:val="2"/></w:pPr><w:rPr><w:b/><w:sz w:val="26"/></w:rPr></w:style><w:style w:type="paragraph" w:styleId="Heading4"><w:name w:val="heading 4"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/><w:outlineLvl w:val="3"/></w:pPr><w:rPr><w:b/><w:i/></w:rPr></w:style><w:style w:type="paragraph" w:styleId="Heading5"><w:name w:val="heading 5"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/><w:outlineLvl w:val="4"/></w:pPr><w:rPr><w:b/></w:rPr></w:style><w:style w:type="paragraph" w:styleId="Heading6"><w:name w:val="heading 6"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/><w:outlineLvl w:val="5"/></w:pPr><w:rPr><w:i/></w:rPr></w:style><w:style w:type="paragraph" w:styleId="Quote"><w:name w:val="Quote"/><w:basedOn w:val="Normal"/><w:pPr><w:ind w:left="720"/></w:pPr><w:rPr><w:i/></w:rPr></w:style><w:style w:type="paragraph" w:styleId="Code"><w:name w:val="Code"/><w:basedOn w:val="Normal"/><w:pPr><w:spacing w:after="0"/></w:pPr><w:rPr><w:rFonts w:ascii="Consolas" w:hAnsi="Consolas"/><w:sz w:val="20"/></w:rPr></w:style><w:style w:type="character" w:styleId="Hyperlink"><w:name w:val="Hyperlink"/><w:rPr><w:color w:val="0563C1"/><w:u w:val="single"/></w:rPr></w:style><w:style w:type="table" w:styleId="TableGrid"><w:name w:val="Table Grid"/><w:tblPr><w:tblBorders><w:top w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:left w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:bottom w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:right w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:insideH w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:insideV w:val="single" w:sz="4" w:space="0" w:color="auto"/></w:tblBorders></w:tblPr></w:style></w:styles>
+"#;
+
+/// Text formatting in effect for the next run
+#[derive(Debug, Default)]
+struct RunStyle {
+    bold: usize,
+    italic: usize,
+    strike: usize,
+    link: bool,
+}
+
+/// A Word document being built from Markdown events.
+struct Document<'a> {
+    vault_path: &'a Path,
+    body: String,
+    /// Paragraph being written, as (properties, runs)
+    paragraph: Option<(String, String)>,
+    style: RunStyle,
+    /// Numbering id of each open list
+    lists: Vec<usize>,
+    /// Ordered lists, as (level, first number); numbering id 1 is for bullets
+    ordered: Vec<(usize, u64)>,
+    /// Whether the next paragraph starts a list item
+    item_pending: bool,
+    quotes: usize,
+    in_code: bool,
+    /// Destination and alt text of the image being read
+    image: Option<(String, String)>,
+    /// Open table, row and cell contents
+    table: Option<String>,
+    row: String,
+    cell: Option<String>,
+    /// Relationships of the main part, as (target, type, external)
+    relationships: Vec<(String, &'static str, bool)>,
+    /// Embedded images, as (file name in word/media, bytes)
+    media: Vec<(String, Vec<u8>)>,
+}
+
+/// Size of a PNG, GIF or JPEG image in pixels, from its header.
+fn image_size(bytes: &[u8]) -> Option<(u64, u64)> {
+    let be = |at: usize, len: usize| {
+        bytes
+            .get(at..at + len)
+            .map(|b| b.iter().fold(0u64, |n, &byte| n << 8 | byte as u64))
+    };
+    if bytes.starts_with(b"\x89PNG") {
+        return Some((be(16, 4)?, be(20, 4)?));
+    }
+    if bytes.starts_with(b"GIF8") {
+        let le = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+        return Some((le(6)? as u64, le(8)? as u64));
+    }
+    if bytes.starts_with(b"\xFF\xD8") {
+        let mut at = 2;
+        while bytes.get(at) == Some(&0xFF) {
+            let marker = *bytes.get(at + 1)?;
+            if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+                return Some((be(at + 7, 2)?, be(at + 5, 2)?));
+            }
+            at += 2 + be(at + 2, 2)? as usize;
+        }
+    }
+    None
+}
+
+fn has_scheme(url: &str) -> bool {
+    url.contains("://") || url.starts_with("mailto:")
+}
+
+impl<'a> Document<'a> {
+    fn new(vault_path: &'a Path) -> Self {
+        Document {
+            vault_path,
+            body: String::new(),
+            paragraph: None,
+            style: RunStyle::default(),
+            lists: Vec::new(),
+            ordered: Vec::new(),
+            item_pending: false,
+            quotes: 0,
+            in_code: false,
+            image: None,
+            table: None,
+            row: String::new(),
+            cell: None,
+            relationships: Vec::new(),
+            media: Vec::new(),
+        }
+    }
+
+    fn relationship(&mut self, target: String, kind: &'static str, external: bool) -> String {
+        self.relationships.push((target, kind, external));
+        format!("rId{}", self.relationships.len())
+    }
+
+    /// Properties of a paragraph opened at the current position.
+    fn paragraph_properties(&mut self) -> String {
+        let mut properties = String::new();
+        if self.quotes > 0 {
+            properties.push_str("<w:pStyle w:val=\"Quote\"/>");
+        }
+        if let Some(&num) = self.lists.last() {
+            match std::mem::take(&mut self.item_pending) {
+                true => properties.push_str(&format!(
+                    "<w:numPr><w:ilvl w:val=\"{}\"/><w:numId w:val=\"{}\"/></w:numPr>",
+                    self.lists.len() - 1,
+                    num
+                )),
+                false => {
+                    properties.push_str(&format!("<w:ind w:left=\"{}\"/>", 720 * self.lists.len()))
+                }
+            }
+        }
+        properties
+    }
+
+    fn begin(&mut self) {
+        if self.paragraph.is_none() {
+            let properties = self.paragraph_properties();
+            self.paragraph = Some((properties, String::new()));
+        }
+    }
+
+    fn begin_styled(&mut self, style: &str) {
+        self.end();
+        self.paragraph = Some((format!("<w:pStyle w:val=\"{}\"/>", style), String::new()));
+    }
+
+    fn end(&mut self) {
+        if let Some((properties, runs)) = self.paragraph.take() {
+            let paragraph = match properties.is_empty() {
+                true => format!("<w:p>{}</w:p>", runs),
+                false => format!("<w:p><w:pPr>{}</w:pPr>{}</w:p>", properties, runs),
+            };
+            self.push_block(&paragraph);
+        }
+    }
+
+    fn push_block(&mut self, block: &str) {
+        match &mut self.cell {
+            Some(cell) => cell.push_str(block),
+            None => self.body.push_str(block),
+        }
+    }
+
+    fn push_run(&mut self, run: &str) {
+        self.begin();
+        if let Some((_, runs)) = &mut self.paragraph {
+            runs.push_str(run);
+        }
+    }
+
+    fn text(&mut self, text: &str, code: bool, superscript: bool) {
+        let mut properties = String::new();
+        if self.style.link {
+            properties.push_str("<w:rStyle w:val=\"Hyperlink\"/>");
+        }
+        if code {
+            properties.push_str("<w:rFonts w:ascii=\"Consolas\" w:hAnsi=\"Consolas\"/>");
+        }
+        if self.style.bold > 0 {
+            properties.push_str("<w:b/>");
+        }
+        if self.style.italic > 0 {
+            properties.push_str("<w:i/>");
+        }
+        if self.style.strike > 0 {
+            properties.push_str("<w:strike/>");
+        }
+        if superscript {
+            properties.push_str("<w:vertAlign w:val=\"superscript\"/>");
+        }
+        let properties = match properties.is_empty() {
+            true => properties,
+            false => format!("<w:rPr>{}</w:rPr>", properties),
+        };
+        self.push_run(&format!(
+            "<w:r>{}<w:t xml:space=\"preserve\">{}</w:t></w:r>",
+            properties,
+            xml_escape(text)
+        ));
+    }
+
+    /// Embeds the vault image at the percent-encoded `url`, or writes its alt text when it
+    /// is remote, missing, outside the vault or in a format Word does not show.
+    fn image(&mut self, url: &str, alt: &str) {
+        let path = self.vault_path.join(util::percent_decode(url));
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        let extension = match extension.as_deref() {
+            Some("jpg" | "jpeg") => "jpeg",
+            Some("png") => "png",
+            Some("gif") => "gif",
+            _ => "",
+        };
+        let bytes = match has_scheme(url) || extension.is_empty() {
+            true => None,
+            false => Some(&path)
+                .filter(|path| util::is_within(path, self.vault_path))
+                .and_then(|path| fs::read(path).ok()),
+        };
+        let Some(bytes) = bytes else {
+            let text = if alt.is_empty() { url } else { alt };
+            self.text(text, false, false);
+            return;
+        };
+
+        let (width, height) = image_size(&bytes).unwrap_or((384, 288));
+        let (mut cx, mut cy) = (width * EMU_PER_PIXEL, height * EMU_PER_PIXEL);
+        if cx > MAX_IMAGE_WIDTH {
+            cy = (cy as u128 * MAX_IMAGE_WIDTH as u128 / cx as u128) as u64;
+            cx = MAX_IMAGE_WIDTH;
+        }
+        let id = self.media.len() + 1;
+        let name = format!("image{}.{}", id, extension);
+        let rel = self.relationship(
+            format!("media/{}", name),
+            "http://schemas.openxmlformats.org/officeDocument/2006/relationships/image",
+            false,
+        );
+        self.media.push((name.clone(), bytes));
+        self.push_run(&format!(
+            "<w:r><w:drawing><wp:inline distT=\"0\" distB=\"0\" distL=\"0\" distR=\"0\"><wp:extent cx=\"{cx}\" cy=\"{cy}\"/><wp:docPr id=\"{id}\" name=\"{alt}\"/><a:graphic xmlns:a=\"http://schemas.openxmlformats.org/drawingml/2006/main\"><a:graphicData uri=\"http://schemas.openxmlformats.org/drawingml/2006/picture\"><pic:pic xmlns:pic=\"http://schemas.openxmlformats.org/drawingml/2006/picture\"><pic:nvPicPr><pic:cNvPr id=\"{id}\" name=\"{name}\"/><pic:cNvPicPr/></pic:nvPicPr><pic:blipFill><a:blip r:embed=\"{rel}\"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill><pic:spPr><a:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"{cx}\" cy=\"{cy}\"/></a:xfrm><a:prstGeom prst=\"rect\"><a:avLst/></a:prstGeom></pic:spPr></pic:pic></a:graphicData></a:graphic></wp:inline></w:drawing></w:r>",
+            alt = xml_escape(alt),
+        ));
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.begin(),
+            Tag::Heading { level, .. } => self.begin_styled(&format!("Heading{}", level as usize)),
+            Tag::BlockQuote(_) => {
+                self.end();
+                self.quotes += 1;
+            }
+            Tag::CodeBlock(_) => {
+                self.end();
+                self.in_code = true;
+            }
+            Tag::List(first) => {
+                self.end();
+                let num = match first {
+                    Some(first) => {
+                        self.ordered.push((self.lists.len(), first));
+                        self.ordered.len() + 1
+                    }
+                    None => 1,
+                };
+                self.lists.push(num);
+            }
+            Tag::Item => {
+                self.end();
+                self.item_pending = true;
+            }
+            Tag::FootnoteDefinition(label) => {
+                self.end();
+                self.text(&format!("[{}] ", label), false, false);
+            }
+            Tag::Table(_) => {
+                self.end();
+                self.table = Some(String::new());
+            }
+            Tag::TableHead | Tag::TableRow => self.row.clear(),
+            Tag::TableCell => {
+                self.cell = Some(String::new());
+                self.begin();
+            }
+            Tag::Emphasis => self.style.italic += 1,
+            Tag::Strong => self.style.bold += 1,
+            Tag::Strikethrough => self.style.strike += 1,
+            Tag::Link { dest_url, .. } if has_scheme(&dest_url) => {
+                let rel = self.relationship(
+                    dest_url.to_string(),
+                    "http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink",
+                    true,
+                );
+                self.push_run(&format!("<w:hyperlink r:id=\"{}\">", rel));
+                self.style.link = true;
+            }
+            Tag::Image { dest_url, .. } => self.image = Some((dest_url.to_string(), String::new())),
+            _ => {}
+        }
+    }
+
+    fn end_tag(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::FootnoteDefinition => {
+                self.end()
+            }
+            TagEnd::BlockQuote(_) => {
+                self.end();
+                self.quotes -= 1;
+            }
+            TagEnd::CodeBlock => self.in_code = false,
+            TagEnd::List(_) => {
+                self.end();
+                self.lists.pop();
+            }
+            TagEnd::TableCell => {
+                self.end();
+                let cell = self.cell.take().unwrap_or_default();
+                self.row.push_str(&format!("<w:tc>{}</w:tc>", cell));
+            }
+            TagEnd::TableHead | TagEnd::TableRow => {
+                let header = match tag {
+                    TagEnd::TableHead => "<w:trPr><w:tblHeader/></w:trPr>",
+                    _ => "",
+                };
+                if let Some(table) = &mut self.table {
+                    table.push_str(&format!("<w:tr>{}{}</w:tr>", header, self.row));
+                }
+            }
+            TagEnd::Table => {
+                if let Some(rows) = self.table.take() {
+                    self.body.push_str(&format!(
+                        "<w:tbl><w:tblPr><w:tblStyle w:val=\"TableGrid\"/><w:tblW w:w=\"0\" w:type=\"auto\"/></w:tblPr>{}</w:tbl>",
+                        rows
+                    ));
+                }
+            }
+            TagEnd::Emphasis => self.style.italic -= 1,
+            TagEnd::Strong => self.style.bold -= 1,
+            TagEnd::Strikethrough => self.style.strike -= 1,
+            TagEnd::Link if self.style.link => {
+                self.push_run("</w:hyperlink>");
+                self.style.link = false;
+            }
+            TagEnd::Image => {
+                if let Some((url, alt)) = self.image.take() {
+                    self.image(&url, &alt);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end_tag(tag),
+            Event::Text(text) | Event::Code(text) if self.image.is_some() => {
+                if let Some((_, alt)) = &mut self.image {
+                    alt.push_str(&text);
+                }
+            }
+            Event::Text(text) if self.in_code => {
+                for line in text.lines() {
+                    self.begin_styled("Code");
+                    self.text(line, false, false);
+                    self.end();
+                }
+            }
+            Event::Text(text) => self.text(&text, false, false),
+            Event::Code(code) => self.text(&code, true, false),
+            Event::SoftBreak => self.text(" ", false, false),
+            Event::HardBreak => self.push_run("<w:r><w:br/></w:r>"),
+            Event::Rule => {
+                self.end();
+                self.push_block("<w:p><w:pPr><w:pBdr><w:bottom w:val=\"single\" w:sz=\"6\" w:space=\"1\" w:color=\"auto\"/></w:pBdr></w:pPr></w:p>");
+            }
+            Event::TaskListMarker(done) => self.text(if done { "☑ " } else { "☐ " }, false, false),
+            Event::FootnoteReference(label) => self.text(&format!("[{}]", label), false, true),
+            _ => {}
+        }
+    }
+
+    fn document_xml(&self) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\" xmlns:wp=\"http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing\"><w:body>{}<w:sectPr/></w:body></w:document>\n",
+            self.body
+        )
+    }
+
+    fn relationships_xml(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\"><Relationship Id=\"rIdStyles\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles\" Target=\"styles.xml\"/><Relationship Id=\"rIdNumbering\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering\" Target=\"numbering.xml\"/>",
+        );
+        for (i, (target, kind, external)) in self.relationships.iter().enumerate() {
+            let mode = if *external {
+                " TargetMode=\"External\""
+            } else {
+                ""
+            };
+            xml.push_str(&format!(
+                "<Relationship Id=\"rId{}\" Type=\"{}\" Target=\"{}\"{}/>",
+                i + 1,
+                kind,
+                xml_escape(target),
+                mode
+            ));
+        }
+        xml.push_str("</Relationships>\n");
+        xml
+    }
+
+    /// Bullets and decimal numbers for nine levels, plus a restart for every ordered list.
+    fn numbering_xml(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:numbering xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">",
+        );
+        for (id, format) in [(0, "bullet"), (1, "decimal")] {
+            xml.push_str(&format!("<w:abstractNum w:abstractNumId=\"{}\">", id));
+            for level in 0..9 {
+                let text = match format {
+                    "bullet" => String::from("•"),
+                    _ => format!("%{}.", level + 1),
+                };
+                xml.push_str(&format!(
+                    "<w:lvl w:ilvl=\"{}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"{}\"/><w:lvlText w:val=\"{}\"/><w:lvlJc w:val=\"left\"/><w:pPr><w:ind w:left=\"{}\" w:hanging=\"360\"/></w:pPr></w:lvl>",
+                    level,
+                    format,
+                    text,
+                    720 * (level + 1)
+                ));
+            }
+            xml.push_str("</w:abstractNum>");
+        }
+        xml.push_str("<w:num w:numId=\"1\"><w:abstractNumId w:val=\"0\"/></w:num>");
+        for (i, (level, first)) in self.ordered.iter().enumerate() {
+            xml.push_str(&format!(
+                "<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"1\"/><w:lvlOverride w:ilvl=\"{}\"><w:startOverride w:val=\"{}\"/></w:lvlOverride></w:num>",
+                i + 2,
+                level,
+                first
+            ));
+        }
+        xml.push_str("</w:numbering>\n");
+        xml
+    }
+}
+
+/// Title, authors and tags of `note` as the package's core properties.
+fn core_xml(note: &Note) -> String {
+    let authors = note
+        .front_matter
+        .authors
+        .as_ref()
+        .map(|authors| authors.join(", "))
+        .unwrap_or_default();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\"><dc:title>{}</dc:title><dc:creator>{}</dc:creator><cp:keywords>{}</cp:keywords></cp:coreProperties>\n",
+        xml_escape(&note.title()),
+        xml_escape(&authors),
+        xml_escape(&note.tags.join(", "))
+    )
+}
+
+/// The Word document for the body of `markdown`, images read from `vault_path`.
+fn convert<'a>(markdown: &str, vault_path: &'a Path) -> Document<'a> {
+    let mut document = Document::new(vault_path);
+    for event in Parser::new_ext(markdown, render::markdown_options()) {
+        document.event(event);
+    }
+    document.end();
+    document
+}
+
+fn write_docx(note: &Note, document: &Document, out: &Path) -> Result<(), Box<dyn Error>> {
+    let mut zip = ZipWriter::new(fs::File::create(out)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut parts: Vec<(String, Vec<u8>)> = vec![
+        (String::from("[Content_Types].xml"), CONTENT_TYPES.into()),
+        (String::from("_rels/.rels"), PACKAGE_RELS.into()),
+        (String::from("docProps/core.xml"), core_xml(note).into()),
+        (
+            String::from("word/document.xml"),
+            document.document_xml().into(),
+        ),
+        (String::from("word/styles.xml"), STYLES.into()),
+        (
+            String::from("word/numbering.xml"),
+            document.numbering_xml().into(),
+        ),
+        (
+            String::from("word/_rels/document.xml.rels"),
+            document.relationships_xml().into(),
+        ),
+    ];
+    for (name, bytes) in &document.media {
+        parts.push((format!("word/media/{}", name), bytes.clone()));
+    }
+    for (name, bytes) in parts {
+        zip.start_file(name, options)?;
+        zip.write_all(&bytes)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+pub fn run_export_docx(vault_path: &Path, args: &ExportDocxArgs) -> Result<(), Box<dyn Error>> {
+    let out = util::expand_tilde(&args.out)
+        .map(|p| p.into_owned())
+        .ok_or("Failed to expand output path")?;
+    if util::is_within(&out, vault_path) {
+        return Err("The export directory must be outside the vault".into());
+    }
+    let resolver = Resolver::from_vault(vault_path)?;
+    let notes = data::load_notes(vault_path)?;
+    let selected = Query::parse(&args.query)?.filter(&notes);
+    let contents: HashMap<PathBuf, String> = notes
+        .iter()
+        .map(|note| (note.path.clone(), note.content.clone()))
+        .collect();
+
+    for note in &selected {
+        let flattened = flatten::flatten_note(&note.path, &resolver, &contents)?;
+        let flat_note = Note::from_content(note.path.clone(), flattened);
+        // Attachments keep their vault path for `image` to read; other notes become text
+        let href = |target: &Path| {
+            (!data::is_note(target))
+                .then(|| util::percent_encode(&target.to_string_lossy().replace('\\', "/")))
+        };
+        let expanded = render::expand_links(&flat_note, &resolver, href);
+        let document = convert(frontmatter::split(&expanded).1, vault_path);
+
+        let target = out.join(&note.path).with_extension("docx");
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_docx(&flat_note, &document, &target)?;
+    }
+    log::info!(
+        "Exported {} document(s) to {}",
+        selected.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let markdown = "# Plan\nSome **bold** and `code`, see <https://example.com>.\n\n\
+                        - one\n  1. first\n  2. second\n- [x] done\n\n\
+                        | A | B |\n|---|---|\n| 1 | 2 |\n\n> quoted\n";
+        let document = convert(markdown, Path::new("/vault"));
+        let xml = document.document_xml();
+
+        assert!(xml.contains(
+            "<w:p><w:pPr><w:pStyle w:val=\"Heading1\"/></w:pPr><w:r><w:t xml:space=\"preserve\">Plan</w:t></w:r></w:p>"
+        ));
+        assert!(
+            xml.contains("<w:r><w:rPr><w:b/></w:rPr><w:t xml:space=\"preserve\">bold</w:t></w:r>")
+        );
+        assert!(
+            xml.contains("<w:hyperlink r:id=\"rId1\"><w:r><w:rPr><w:rStyle w:val=\"Hyperlink\"/>")
+        );
+        assert!(xml.contains(
+            "<w:numPr><w:ilvl w:val=\"1\"/><w:numId w:val=\"2\"/></w:numPr></w:pPr><w:r><w:t xml:space=\"preserve\">first</w:t>"
+        ));
+        assert!(xml.contains("☑ "));
+        assert!(xml.contains("<w:tr><w:trPr><w:tblHeader/></w:trPr><w:tc><w:p><w:r><w:t xml:space=\"preserve\">A</w:t>"));
+        assert!(xml.contains("<w:pStyle w:val=\"Quote\"/>"));
+        assert!(document.numbering_xml().contains(
+            "<w:num w:numId=\"2\"><w:abstractNumId w:val=\"1\"/><w:lvlOverride w:ilvl=\"1\"><w:startOverride w:val=\"1\"/>"
+        ));
+
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\x02\x80\0\0\x01\xe0";
+        assert_eq!(image_size(png), Some((640, 480)));
+    }
+
+    #[test]
+    fn test_images() {
+        let root = tempfile::tempdir().unwrap();
+        let vault = root.path().join("vault");
+        fs::create_dir(&vault).unwrap();
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\xff\xff\xff\xff\xff\xff\xff\xff";
+        fs::write(vault.join("wide.png"), png).unwrap();
+        fs::write(root.path().join("secret.png"), png).unwrap();
+
+        let markdown = "![A *wide* one](wide.png) ![Secret](../secret.png)";
+        let document = convert(markdown, &vault);
+        let xml = document.document_xml();
+        assert_eq!(document.media.len(), 1);
+        assert!(xml.contains("name=\"A wide one\""));
+        assert!(xml.contains(&format!(
+            "cx=\"{}\" cy=\"{}\"",
+            MAX_IMAGE_WIDTH, MAX_IMAGE_WIDTH
+        )));
+        assert!(xml.contains("<w:t xml:space=\"preserve\">Secret</w:t>"));
+    }
+}
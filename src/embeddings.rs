@@ -0,0 +1,505 @@
+use crate::config::EmbeddingsConfig;
+use crate::index::{self, Chunk, SearchHit, SnippetOptions};
+use crate::search_query::SearchQuery;
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use sqlite::{Connection, State};
+#[cfg(feature = "web")]
+use std::time::Duration;
+use std::{collections::HashMap, env, error::Error};
+
+/// Texts sent to the backend per request
+static BATCH_SIZE: usize = 32;
+
+/// Turns text into vectors; one implementation per supported service.
+pub trait Embedder {
+    /// Model name, stored with each vector so switching models re-embeds everything
+    fn model(&self) -> &str;
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>>;
+}
+
+#[cfg(feature = "web")]
+fn post_json(url: &str, body: &Value, api_key: Option<&str>) -> Result<Value, Box<dyn Error>> {
+    let mut request = ureq::post(url).timeout(Duration::from_secs(120));
+    if let Some(key) = api_key {
+        request = request.set("Authorization", &format!("Bearer {}", key));
+    }
+    Ok(request.send_json(body)?.into_json()?)
+}
+
+/// Every embedding backend is a web service, which builds without `web` cannot reach.
+#[cfg(not(feature = "web"))]
+fn post_json(_url: &str, _body: &Value, _api_key: Option<&str>) -> Result<Value, Box<dyn Error>> {
+    Err("Embeddings need the `web` feature, which this build lacks".into())
+}
+
+fn parse_vectors(values: Option<&Value>) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+    let vectors = values
+        .and_then(Value::as_array)
+        .ok_or("Embedding response has no vectors")?;
+    vectors
+        .iter()
+        .map(|vector| {
+            vector
+                .as_array()
+                .ok_or("Embedding is not an array")?
+                .iter()
+                .map(|x| {
+                    x.as_f64()
+                        .map(|x| x as f32)
+                        .ok_or("Embedding value is not a number".into())
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Ollama's `/api/embed` endpoint
+pub struct Ollama {
+    url: String,
+    model: String,
+}
+
+impl Embedder for Ollama {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let url = format!("{}/api/embed", self.url.trim_end_matches('/'));
+        let response = post_json(&url, &json!({ "model": self.model, "input": texts }), None)?;
+        parse_vectors(response.get("embeddings"))
+    }
+}
+
+/// OpenAI-compatible `/embeddings` endpoint (also served by many local model servers)
+pub struct OpenAi {
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl Embedder for OpenAi {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let url = format!("{}/embeddings", self.url.trim_end_matches('/'));
+        let body = json!({ "model": self.model, "input": texts });
+        let response = post_json(&url, &body, self.api_key.as_deref())?;
+        let embeddings: Vec<Value> = response
+            .get("data")
+            .and_then(Value::as_array)
+            .ok_or("Embedding response has no data")?
+            .iter()
+            .filter_map(|item| item.get("embedding").cloned())
+            .collect();
+        parse_vectors(Some(&Value::Array(embeddings)))
+    }
+}
+
+/// The backend selected by `[embeddings] backend`.
+pub fn backend(config: &EmbeddingsConfig) -> Result<Box<dyn Embedder>, Box<dyn Error>> {
+    let model = config.model.clone();
+    match config.backend.as_deref() {
+        Some("ollama") => Ok(Box::new(Ollama {
+            url: config
+                .url
+                .clone()
+                .unwrap_or_else(|| String::from("http://localhost:11434")),
+            model,
+        })),
+        Some("openai") => Ok(Box::new(OpenAi {
+            url: config
+                .url
+                .clone()
+                .unwrap_or_else(|| String::from("https://api.openai.com/v1")),
+            model,
+            api_key: env::var(&config.api_key_env).ok(),
+        })),
+        Some("onnx") => Err("The onnx embedding backend is not available in this build".into()),
+        Some(other) => Err(format!("Unknown embedding backend '{}'", other).into()),
+        None => Err("Semantic search needs an [embeddings] backend in the config".into()),
+    }
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS embeddings (
+            hash TEXT NOT NULL,
+            model TEXT NOT NULL,
+            vector BLOB NOT NULL,
+            PRIMARY KEY (hash, model)
+        );
+        CREATE TABLE IF NOT EXISTS neighbors (
+            model TEXT NOT NULL,
+            path TEXT NOT NULL,
+            rank INTEGER NOT NULL,
+            neighbor TEXT NOT NULL,
+            score REAL NOT NULL,
+            PRIMARY KEY (model, path, rank)
+        );",
+    )
+}
+
+pub fn encode(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub fn decode(bytes: &[u8]) -> Vec<f32> {
+    let (floats, _) = bytes.as_chunks::<4>();
+    floats.iter().map(|b| f32::from_le_bytes(*b)).collect()
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f64, 0.0f64, 0.0f64);
+    for (x, y) in a.iter().zip(b) {
+        dot += f64::from(*x) * f64::from(*y);
+        norm_a += f64::from(*x) * f64::from(*x);
+        norm_b += f64::from(*y) * f64::from(*y);
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Embeds every indexed chunk that has no vector for the backend's model yet.
+/// Returns how many chunks were embedded.
+pub fn update(connection: &Connection, embedder: &dyn Embedder) -> Result<usize, Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let mut statement = connection.prepare(
+        "SELECT DISTINCT c.hash, c.title, c.heading, c.text FROM chunks c
+         WHERE NOT EXISTS (SELECT 1 FROM embeddings e WHERE e.hash = c.hash AND e.model = ?)",
+    )?;
+    statement.bind((1, embedder.model()))?;
+    let mut missing: Vec<(String, String)> = Vec::new();
+    while let State::Row = statement.next()? {
+        let chunk = Chunk {
+            path: String::new(),
+            heading: statement.read::<String, _>(2)?,
+            line: 0,
+            text: statement.read::<String, _>(3)?,
+        };
+        let title = statement.read::<String, _>(1)?;
+        missing.push((
+            statement.read::<String, _>(0)?,
+            chunk.embedding_input(&title),
+        ));
+    }
+
+    for batch in missing.chunks(BATCH_SIZE) {
+        let texts: Vec<String> = batch.iter().map(|(_, text)| text.clone()).collect();
+        let vectors = embedder.embed(&texts)?;
+        if vectors.len() != batch.len() {
+            return Err("Embedding backend returned the wrong number of vectors".into());
+        }
+        for ((hash, _), vector) in batch.iter().zip(vectors) {
+            let mut insert = connection.prepare(
+                "INSERT OR REPLACE INTO embeddings (hash, model, vector) VALUES (?, ?, ?)",
+            )?;
+            insert.bind((1, hash.as_str()))?;
+            insert.bind((2, embedder.model()))?;
+            insert.bind((3, &encode(&vector)[..]))?;
+            insert.next()?;
+        }
+        log::info!("Embedded {} chunk(s)", batch.len());
+    }
+    Ok(missing.len())
+}
+
+/// Notes ranked by the cosine similarity of their best chunk to the words of `query`,
+/// among the notes its `path:` and `frontmatter.key:` terms filter to.
+pub fn semantic_search(
+    connection: &Connection,
+    embedder: &dyn Embedder,
+    query: &str,
+    limit: usize,
+    options: SnippetOptions,
+) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+    let query = SearchQuery::parse(query)?;
+    if query.text.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query_vector = embedder
+        .embed(std::slice::from_ref(&query.text))?
+        .pop()
+        .ok_or("Embedding backend returned no vector")?;
+
+    let (filters, values) = query.filter_sql();
+    let mut statement = connection.prepare(format!(
+        "SELECT c.path, c.heading, c.line, c.text, e.vector FROM chunks c
+         JOIN embeddings e ON e.hash = c.hash AND e.model = ?
+         WHERE 1{}",
+        filters
+    ))?;
+    statement.bind((1, embedder.model()))?;
+    for (i, value) in values.iter().enumerate() {
+        statement.bind((i + 2, *value))?;
+    }
+    let mut best: HashMap<String, SearchHit> = HashMap::new();
+    while let State::Row = statement.next()? {
+        let score = cosine(&query_vector, &decode(&statement.read::<Vec<u8>, _>(4)?));
+        let path = statement.read::<String, _>(0)?;
+        if best.get(&path).is_some_and(|hit| hit.score >= score) {
+            continue;
+        }
+        let (snippet, snippets) = index::hit_snippets(&statement.read::<String, _>(3)?, options);
+        best.insert(
+            path.clone(),
+            SearchHit {
+                path,
+                heading: statement.read::<String, _>(1)?,
+                line: statement.read::<i64, _>(2)? as usize,
+                score,
+                snippet,
+                snippets,
+                ..Default::default()
+            },
+        );
+    }
+
+    let mut hits: Vec<SearchHit> = best.into_values().collect();
+    hits.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    hits.truncate(limit);
+    Ok(hits)
+}
+
+/// A note close to another one in embedding space
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Neighbor {
+    pub path: String,
+    pub score: f64,
+}
+
+/// One vector per indexed note: the mean of its chunk vectors for `model`.
+pub fn note_vectors(
+    connection: &Connection,
+    model: &str,
+) -> Result<HashMap<String, Vec<f32>>, Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let mut statement = connection.prepare(
+        "SELECT c.path, e.vector FROM chunks c
+         JOIN embeddings e ON e.hash = c.hash AND e.model = ?",
+    )?;
+    statement.bind((1, model))?;
+    let mut sums: HashMap<String, (Vec<f32>, usize)> = HashMap::new();
+    while let State::Row = statement.next()? {
+        let vector = decode(&statement.read::<Vec<u8>, _>(1)?);
+        let (sum, count) = sums
+            .entry(statement.read::<String, _>(0)?)
+            .or_insert_with(|| (vec![0.0; vector.len()], 0));
+        for (total, x) in sum.iter_mut().zip(&vector) {
+            *total += x;
+        }
+        *count += 1;
+    }
+    Ok(sums
+        .into_iter()
+        .map(|(path, (sum, count))| (path, sum.iter().map(|x| x / count as f32).collect()))
+        .collect())
+}
+
+/// The `top` notes most similar to `path`, best first.
+pub fn nearest(vectors: &HashMap<String, Vec<f32>>, path: &str, top: usize) -> Vec<Neighbor> {
+    let Some(target) = vectors.get(path) else {
+        return Vec::new();
+    };
+    let mut neighbors: Vec<Neighbor> = vectors
+        .iter()
+        .filter(|(other, _)| other.as_str() != path)
+        .map(|(other, vector)| Neighbor {
+            path: other.clone(),
+            score: cosine(target, vector),
+        })
+        .collect();
+    neighbors.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    neighbors.truncate(top);
+    neighbors
+}
+
+/// Recomputes the stored `k` nearest neighbours of every note for `model`.
+pub fn store_neighbors(
+    connection: &Connection,
+    model: &str,
+    k: usize,
+) -> Result<usize, Box<dyn Error>> {
+    let vectors = note_vectors(connection, model)?;
+    connection.execute("BEGIN")?;
+    let mut statement = connection.prepare("DELETE FROM neighbors WHERE model = ?")?;
+    statement.bind((1, model))?;
+    statement.next()?;
+    for path in vectors.keys() {
+        for (rank, neighbor) in nearest(&vectors, path, k).into_iter().enumerate() {
+            let mut insert = connection.prepare(
+                "INSERT INTO neighbors (model, path, rank, neighbor, score) VALUES (?, ?, ?, ?, ?)",
+            )?;
+            insert.bind((1, model))?;
+            insert.bind((2, path.as_str()))?;
+            insert.bind((3, rank as i64))?;
+            insert.bind((4, neighbor.path.as_str()))?;
+            insert.bind((5, neighbor.score))?;
+            insert.next()?;
+        }
+    }
+    connection.execute("COMMIT")?;
+    Ok(vectors.len())
+}
+
+/// Forgets the stored neighbours of every note. A changed note can move into or out of
+/// any other note's nearest, so they are stale as soon as one note changes.
+pub fn forget_neighbors(connection: &Connection) -> Result<(), sqlite::Error> {
+    ensure_schema(connection)?;
+    connection.execute("DELETE FROM neighbors")
+}
+
+/// Whether neighbours are stored for `model`.
+pub fn has_neighbors(connection: &Connection, model: &str) -> Result<bool, sqlite::Error> {
+    ensure_schema(connection)?;
+    let mut statement = connection.prepare("SELECT 1 FROM neighbors WHERE model = ? LIMIT 1")?;
+    statement.bind((1, model))?;
+    Ok(matches!(statement.next()?, State::Row))
+}
+
+/// Neighbours of `path` saved by [`store_neighbors`], or `None` if none were stored.
+pub fn stored_neighbors(
+    connection: &Connection,
+    model: &str,
+    path: &str,
+    top: usize,
+) -> Result<Option<Vec<Neighbor>>, Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let mut statement = connection.prepare(
+        "SELECT neighbor, score FROM neighbors WHERE model = ? AND path = ? ORDER BY rank LIMIT ?",
+    )?;
+    statement.bind((1, model))?;
+    statement.bind((2, path))?;
+    statement.bind((3, top as i64))?;
+    let mut neighbors = Vec::new();
+    while let State::Row = statement.next()? {
+        neighbors.push(Neighbor {
+            path: statement.read::<String, _>(0)?,
+            score: statement.read::<f64, _>(1)?,
+        });
+    }
+    Ok(if neighbors.is_empty() {
+        None
+    } else {
+        Some(neighbors)
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::data::Note;
+    use crate::index;
+    use std::path::PathBuf;
+
+    /// Counts a few keywords so tests can run without a model
+    pub struct KeywordEmbedder;
+
+    impl Embedder for KeywordEmbedder {
+        fn model(&self) -> &str {
+            "keywords"
+        }
+
+        fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    let text = text.to_lowercase();
+                    ["rust", "garden", "tomato"]
+                        .iter()
+                        .map(|word| text.matches(word).count() as f32)
+                        .collect()
+                })
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_vector_helpers() {
+        let vector = vec![0.5, -1.0, 2.0];
+        assert_eq!(decode(&encode(&vector)), vector);
+        assert!((cosine(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-9);
+        assert_eq!(cosine(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+    }
+
+    #[test]
+    fn test_semantic_search() {
+        let notes = vec![
+            Note::from_content(PathBuf::from("Rust.md"), String::from("rust rust")),
+            Note::from_content(PathBuf::from("Garden.md"), String::from("garden tomato")),
+        ];
+        let connection = index::test_connection(&notes);
+        assert_eq!(update(&connection, &KeywordEmbedder).unwrap(), 2);
+        assert_eq!(update(&connection, &KeywordEmbedder).unwrap(), 0);
+
+        let hits = semantic_search(
+            &connection,
+            &KeywordEmbedder,
+            "tomato",
+            10,
+            SnippetOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(hits[0].path, "Garden.md");
+        assert_eq!(hits[1].score, 0.0);
+    }
+
+    #[test]
+    fn test_neighbors() {
+        let notes = vec![
+            Note::from_content(PathBuf::from("Rust.md"), String::from("rust rust")),
+            Note::from_content(PathBuf::from("Tomato.md"), String::from("tomato")),
+            Note::from_content(PathBuf::from("Garden.md"), String::from("garden tomato")),
+        ];
+        let connection = index::test_connection(&notes);
+        update(&connection, &KeywordEmbedder).unwrap();
+
+        let vectors = note_vectors(&connection, "keywords").unwrap();
+        let similar = nearest(&vectors, "Tomato.md", 10);
+        assert_eq!(similar.len(), 2);
+        assert_eq!(similar[0].path, "Garden.md");
+        assert!(nearest(&vectors, "Missing.md", 10).is_empty());
+
+        assert_eq!(
+            stored_neighbors(&connection, "keywords", "Tomato.md", 5).unwrap(),
+            None
+        );
+        assert_eq!(store_neighbors(&connection, "keywords", 1).unwrap(), 3);
+        let stored = stored_neighbors(&connection, "keywords", "Tomato.md", 5).unwrap();
+        assert_eq!(stored, Some(vec![similar[0].clone()]));
+    }
+
+    #[test]
+    fn test_forget_neighbors() {
+        let notes = vec![
+            Note::from_content(PathBuf::from("Tomato.md"), String::from("tomato")),
+            Note::from_content(PathBuf::from("Garden.md"), String::from("garden tomato")),
+        ];
+        let connection = index::test_connection(&notes);
+        update(&connection, &KeywordEmbedder).unwrap();
+        store_neighbors(&connection, "keywords", 1).unwrap();
+        assert!(has_neighbors(&connection, "keywords").unwrap());
+        assert!(!has_neighbors(&connection, "other").unwrap());
+
+        forget_neighbors(&connection).unwrap();
+        assert!(!has_neighbors(&connection, "keywords").unwrap());
+        assert_eq!(
+            stored_neighbors(&connection, "keywords", "Tomato.md", 1).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Typed notes. An `[entities.<kind>]` section says which front matter `type` values and
+//! folders make a note an entity of that kind (a person, a book, a project), and which of
+//! its fields to list.
+//!
+//! ```toml
+//! [entities.person]
+//! values = ["person", "contact"]
+//! folders = ["People/"]
+//! fields = ["email", "birthday"]
+//! ```
+
+use crate::cli::{EntitiesCommand, EntitiesListArgs, OutputFormat};
+use crate::collation;
+use crate::config::{AppConfig, EntityConfig};
+use crate::data::{self, Note};
+use crate::query::Query;
+use crate::util;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use serde_yaml::Mapping;
+use std::{collections::BTreeMap, error::Error, path::Path, sync::OnceLock};
+
+static KINDS: OnceLock<BTreeMap<String, EntityConfig>> = OnceLock::new();
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Entity {
+    pub kind: String,
+    pub path: String,
+    pub title: String,
+    /// The configured fields the note sets
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+struct KindCount {
+    kind: String,
+    count: usize,
+}
+
+/// Uses the entity kinds of `config` for the rest of the process.
+pub fn install(config: &AppConfig) {
+    let _ = KINDS.set(config.entities.clone());
+}
+
+fn kinds() -> &'static BTreeMap<String, EntityConfig> {
+    KINDS.get_or_init(BTreeMap::new)
+}
+
+/// Identifies the installed kinds, so cached query results follow changes to them.
+pub fn fingerprint() -> String {
+    util::content_hash(format!("{:?}", kinds()).as_bytes())
+}
+
+/// Whether a note at `rel_path` with front matter `properties` is of the kind `name`.
+fn is_kind(name: &str, kind: &EntityConfig, rel_path: &str, properties: &Mapping) -> bool {
+    let in_folder = kind.folders.iter().any(|folder| {
+        let folder = folder.trim_matches('/');
+        rel_path.starts_with(&format!("{}/", folder))
+    });
+    let types: Vec<&str> = match properties.get(kind.property.as_str()) {
+        Some(serde_yaml::Value::String(value)) => vec![value.as_str()],
+        Some(serde_yaml::Value::Sequence(values)) => values
+            .iter()
+            .filter_map(serde_yaml::Value::as_str)
+            .collect(),
+        _ => Vec::new(),
+    };
+    in_folder
+        || types.iter().any(|value| match kind.values.is_empty() {
+            true => value.eq_ignore_ascii_case(name),
+            false => kind.values.iter().any(|v| v.eq_ignore_ascii_case(value)),
+        })
+}
+
+/// Whether `note` is of the kind `name`. Kinds without a configuration are recognized by
+/// a front matter `type` equal to their name.
+pub fn is_of(note: &Note, name: &str) -> bool {
+    let default = EntityConfig::default();
+    let kind = kinds().get(name).unwrap_or(&default);
+    let rel_path = note.path.to_string_lossy().replace('\\', "/");
+    is_kind(name, kind, &rel_path, &note.properties)
+}
+
+/// The notes among `notes` of the kind `name`, by title.
+fn entities<'a>(
+    notes: impl IntoIterator<Item = &'a Note>,
+    name: &str,
+    kind: &EntityConfig,
+) -> Vec<Entity> {
+    let mut entities: Vec<Entity> = notes
+        .into_iter()
+        .filter_map(|note| {
+            let rel_path = note.path.to_string_lossy().replace('\\', "/");
+            let properties = &note.properties;
+            if !is_kind(name, kind, &rel_path, properties) {
+                return None;
+            }
+            let fields = kind
+                .fields
+                .iter()
+                .filter_map(|field| {
+                    let value = serde_json::to_value(properties.get(field.as_str())?).ok()?;
+                    Some((field.clone(), value))
+                })
+                .collect();
+            Some(Entity {
+                kind: name.to_string(),
+                path: rel_path,
+                title: note.title(),
+                fields,
+            })
+        })
+        .collect();
+    entities.sort_by_cached_key(|entity| collation::sort_key(&entity.title));
+    entities
+}
+
+/// `value` as it reads in front matter: strings without quotes.
+fn field_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(field_text).collect::<Vec<_>>().join(", "),
+        value => value.to_string(),
+    }
+}
+
+fn list(vault_path: &Path, args: &EntitiesListArgs) -> Result<(), Box<dyn Error>> {
+    let default = EntityConfig::default();
+    let kind = match kinds().get(&args.kind) {
+        Some(kind) => kind,
+        None => {
+            log::warn!(
+                "No [entities.{}] section; listing notes with `type: {}`",
+                args.kind,
+                args.kind
+            );
+            &default
+        }
+    };
+    let notes = data::load_notes(vault_path)?;
+    let notes = match &args.query {
+        Some(query) => Query::parse(query)?.filter(&notes),
+        None => notes.iter().collect(),
+    };
+    let entities = entities(notes, &args.kind, kind);
+
+    if args.porcelain {
+        for entity in &entities {
+            let mut columns = vec![entity.path.clone(), entity.title.clone()];
+            columns.extend(
+                kind.fields
+                    .iter()
+                    .map(|field| entity.fields.get(field).map(field_text).unwrap_or_default()),
+            );
+            util::print_porcelain(&columns.iter().map(String::as_str).collect::<Vec<_>>())?;
+        }
+        return Ok(());
+    }
+    match args.format {
+        OutputFormat::Text => {
+            for entity in &entities {
+                let fields: Vec<String> = entity
+                    .fields
+                    .iter()
+                    .map(|(field, value)| format!("{}: {}", field, field_text(value)))
+                    .collect();
+                match fields.is_empty() {
+                    true => println!("{}", entity.title),
+                    false => println!("{}  ({})", entity.title, fields.join(", ")),
+                }
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entities)?),
+        OutputFormat::Ndjson => {
+            for entity in &entities {
+                util::print_ndjson(entity)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list_kinds(vault_path: &Path, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    let counts: Vec<KindCount> = kinds()
+        .iter()
+        .map(|(name, kind)| KindCount {
+            kind: name.clone(),
+            count: entities(&notes, name, kind).len(),
+        })
+        .collect();
+    match format {
+        OutputFormat::Text => {
+            for count in &counts {
+                println!("{}  {}", count.kind, count.count);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&counts)?),
+        OutputFormat::Ndjson => {
+            for count in &counts {
+                util::print_ndjson(count)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn run_entities(vault_path: &Path, command: &EntitiesCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        EntitiesCommand::Kinds(args) => list_kinds(vault_path, args.format),
+        EntitiesCommand::List(args) => list(vault_path, args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_entities() {
+        let notes = [
+            ("People/Ada.md", "---\nemail: ada@example.com\n---\n"),
+            (
+                "Bob.md",
+                "---\ntype: [contact]\nbirthday: 1990-01-02\n---\n",
+            ),
+            ("Carol.md", "---\ntype: book\n---\n"),
+        ]
+        .map(|(path, content)| Note::from_content(PathBuf::from(path), content.to_string()));
+        let person = EntityConfig {
+            values: vec![String::from("Contact")],
+            folders: vec![String::from("People/")],
+            fields: vec![String::from("email"), String::from("birthday")],
+            ..EntityConfig::default()
+        };
+
+        let people = entities(&notes, "person", &person);
+        assert_eq!(
+            people.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(),
+            ["People/Ada.md", "Bob.md"]
+        );
+        assert_eq!(people[0].fields["email"], "ada@example.com");
+        assert_eq!(people[1].fields["birthday"], "1990-01-02");
+
+        let books = entities(&notes, "book", &EntityConfig::default());
+        assert_eq!(books.len(), 1);
+        assert!(books[0].fields.is_empty());
+    }
+}
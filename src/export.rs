@@ -0,0 +1,199 @@
+use crate::cli::ExportSubsetArgs;
+use crate::data::{self, Note};
+use crate::markdown;
+use crate::query::Query;
+use crate::resolver::Resolver;
+use crate::util;
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Files to export for the notes matching `query`: the notes themselves, everything they
+/// embed (transitively) and notes they link to, up to `depth` links away.
+pub fn select_subset(
+    notes: &[Note],
+    query: &Query,
+    resolver: &Resolver,
+    depth: usize,
+) -> BTreeSet<PathBuf> {
+    let by_path: HashMap<&Path, &Note> = notes.iter().map(|n| (n.path.as_path(), n)).collect();
+    let mut selected = BTreeSet::new();
+    let mut queue: VecDeque<(PathBuf, usize)> = query
+        .filter(notes)
+        .into_iter()
+        .map(|note| (note.path.clone(), depth))
+        .collect();
+
+    while let Some((path, remaining)) = queue.pop_front() {
+        if !selected.insert(path.clone()) {
+            continue;
+        }
+        let Some(note) = by_path.get(path.as_path()) else {
+            continue;
+        };
+        for link in markdown::parse_links(&note.content) {
+            if link.is_external() {
+                continue;
+            }
+            let Some(target) = resolver.resolve(&link.target, &note.path) else {
+                continue;
+            };
+            if link.embed {
+                queue.push_back((target.to_path_buf(), remaining));
+            } else if remaining > 0 && data::is_note(target) {
+                queue.push_back((target.to_path_buf(), remaining - 1));
+            }
+        }
+    }
+    selected
+}
+
+/// Replaces links to files outside the export with their plain text so nothing dangles.
+pub fn unlink_excluded(note: &Note, resolver: &Resolver, included: &BTreeSet<PathBuf>) -> String {
+    let edits = markdown::parse_links(&note.content)
+        .into_iter()
+        .filter(|link| !link.is_external())
+        .filter(|link| {
+            resolver
+                .resolve(&link.target, &note.path)
+                .is_some_and(|target| !included.contains(target))
+        })
+        .map(|link| {
+            let text = link
+                .text
+                .clone()
+                .filter(|text| !text.is_empty())
+                .unwrap_or_else(|| util::percent_decode(&link.target));
+            (link.span.clone(), text)
+        })
+        .collect();
+    markdown::replace_spans(&note.content, edits)
+}
+
+pub fn run_export_subset(vault_path: &Path, args: &ExportSubsetArgs) -> Result<(), Box<dyn Error>> {
+    let out = util::expand_tilde(&args.out)
+        .map(|p| p.into_owned())
+        .ok_or("Failed to expand output path")?;
+    if util::is_within(&out, vault_path) {
+        return Err("The export directory must be outside the vault".into());
+    }
+
+    let query = Query::parse(&args.query)?;
+    let notes = data::load_notes(vault_path)?;
+    let resolver = Resolver::from_vault(vault_path)?;
+    let selected = select_subset(&notes, &query, &resolver, args.depth);
+    let by_path: HashMap<&Path, &Note> = notes.iter().map(|n| (n.path.as_path(), n)).collect();
+
+    for rel_path in &selected {
+        println!("{}", rel_path.display());
+        if args.dry_run {
+            continue;
+        }
+        let destination = out.join(rel_path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match by_path.get(rel_path.as_path()) {
+            Some(note) => fs::write(&destination, unlink_excluded(note, &resolver, &selected))?,
+            None => {
+                fs::copy(vault_path.join(rel_path), &destination)?;
+            }
+        }
+    }
+
+    log::info!(
+        "{} {} file(s) to {}.",
+        if args.dry_run {
+            "Would export"
+        } else {
+            "Exported"
+        },
+        selected.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> (Vec<Note>, Resolver) {
+        let notes = vec![
+            Note::from_content(
+                PathBuf::from("Public.md"),
+                String::from("#public ![[Embedded]] [[Linked]] [[Secret|hidden]]"),
+            ),
+            Note::from_content(PathBuf::from("Embedded.md"), String::from("![[pic.png]]")),
+            Note::from_content(PathBuf::from("Linked.md"), String::from("[[Deeper]]")),
+            Note::from_content(PathBuf::from("Deeper.md"), String::from("end")),
+            Note::from_content(PathBuf::from("Secret.md"), String::from("#private")),
+        ];
+        let mut files: Vec<PathBuf> = notes.iter().map(|n| n.path.clone()).collect();
+        files.push(PathBuf::from("assets/pic.png"));
+        (notes, Resolver::new(files))
+    }
+
+    #[test]
+    fn test_select_subset_follows_embeds_and_depth() {
+        let (notes, resolver) = vault();
+        let query = Query::parse("tag:public").unwrap();
+
+        let selected = select_subset(&notes, &query, &resolver, 0);
+        let expected: BTreeSet<PathBuf> = ["Public.md", "Embedded.md", "assets/pic.png"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(selected, expected);
+
+        let selected = select_subset(&notes, &query, &resolver, 1);
+        assert!(selected.contains(Path::new("Linked.md")));
+        assert!(selected.contains(Path::new("Secret.md")));
+        assert!(!selected.contains(Path::new("Deeper.md")));
+    }
+
+    #[test]
+    fn test_unlink_excluded() {
+        let (notes, resolver) = vault();
+        let query = Query::parse("tag:public").unwrap();
+        let selected = select_subset(&notes, &query, &resolver, 0);
+        assert_eq!(
+            unlink_excluded(&notes[0], &resolver, &selected),
+            "#public ![[Embedded]] Linked hidden"
+        );
+    }
+
+    #[test]
+    fn test_refuses_out_inside_the_vault_by_any_spelling() {
+        let vault = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        let link = elsewhere.path().join("link");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(vault.path(), &link).unwrap();
+        let mut outs = vec![
+            vault.path().join("./site"),
+            elsewhere
+                .path()
+                .join("..")
+                .join(vault.path().file_name().unwrap())
+                .join("site"),
+        ];
+        if link.exists() {
+            outs.push(link.join("site"));
+        }
+        for out in outs {
+            let args = ExportSubsetArgs {
+                query: String::from("tag:public"),
+                out,
+                depth: 0,
+                dry_run: true,
+            };
+            let e = run_export_subset(vault.path(), &args).unwrap_err();
+            assert!(e.to_string().contains("outside the vault"));
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::cli::{ExportFeedArgs, FeedFormat};
+use crate::config::PublishConfig;
+use crate::content_store;
+use crate::data::Note;
+use crate::frontmatter;
+use crate::query::Query;
+use crate::render;
+use crate::resolver::Resolver;
+use crate::util::xml_escape;
+
+use jiff::{Timestamp, civil, tz::TimeZone};
+use serde_yaml::Value;
+use std::{error::Error, fs, path::Path};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedEntry {
+    pub title: String,
+    pub url: String,
+    pub published: Timestamp,
+    pub updated: Timestamp,
+    pub html: String,
+}
+
+/// Parses RFC 3339 timestamps as well as plain dates and date-times (taken as UTC).
+pub fn parse_time(text: &str) -> Option<Timestamp> {
+    let text = text.trim();
+    if let Ok(timestamp) = text.parse::<Timestamp>() {
+        return Some(timestamp);
+    }
+    let datetime = text
+        .parse::<civil::DateTime>()
+        .or_else(|_| {
+            text.parse::<civil::Date>()
+                .map(|date| date.to_datetime(civil::Time::midnight()))
+        })
+        .ok()?;
+    datetime
+        .to_zoned(TimeZone::UTC)
+        .ok()
+        .map(|zoned| zoned.timestamp())
+}
+
+/// First of `keys` in the note's front matter holding a date; lists use their first item.
+fn front_matter_time(note: &Note, keys: &[&str]) -> Option<Timestamp> {
+    let mapping = frontmatter::parse_mapping(&note.content).ok().flatten()?;
+    keys.iter().find_map(|key| {
+        let text = match mapping.get(*key)? {
+            Value::String(text) => text.as_str(),
+            Value::Sequence(items) => items.first()?.as_str()?,
+            _ => return None,
+        };
+        parse_time(text)
+    })
+}
+
+/// When the note was published and last updated, from front matter or the file's mtime.
+pub fn note_times(note: &Note) -> (Timestamp, Timestamp) {
+    let modified = note
+        .modified
+        .and_then(|time| Timestamp::try_from(time).ok());
+    let published = front_matter_time(note, &["date", "published", "created"])
+        .or(modified)
+        .unwrap_or(Timestamp::UNIX_EPOCH);
+    let updated = front_matter_time(note, &["updated", "modified"])
+        .or(modified)
+        .unwrap_or(published)
+        .max(published);
+    (published, updated)
+}
+
+/// Entries for the notes matching `query`, most recently updated first.
+pub fn build_entries(
+    notes: &[Note],
+    query: &Query,
+    resolver: &Resolver,
+    base_url: &str,
+    limit: usize,
+) -> Vec<FeedEntry> {
+    let mut entries: Vec<FeedEntry> = query
+        .filter(notes)
+        .into_iter()
+        .map(|note| {
+            let (published, updated) = note_times(note);
+            FeedEntry {
+                title: note.title(),
+                url: render::site_url(base_url, &note.path),
+                published,
+                updated,
+                html: render::note_html(note, resolver, |path| {
+                    Some(render::site_url(base_url, path))
+                }),
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| {
+        b.updated
+            .cmp(&a.updated)
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    entries.truncate(limit);
+    entries
+}
+
+pub fn render_atom(title: &str, base_url: &str, entries: &[FeedEntry]) -> String {
+    let updated = entries
+        .iter()
+        .map(|entry| entry.updated)
+        .max()
+        .unwrap_or(Timestamp::UNIX_EPOCH);
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("  <title>{}</title>\n", xml_escape(title)));
+    xml.push_str(&format!("  <link href=\"{}\"/>\n", xml_escape(base_url)));
+    xml.push_str(&format!("  <id>{}</id>\n", xml_escape(base_url)));
+    xml.push_str(&format!("  <updated>{}</updated>\n", updated));
+    for entry in entries {
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!(
+            "    <title>{}</title>\n",
+            xml_escape(&entry.title)
+        ));
+        xml.push_str(&format!(
+            "    <link href=\"{}\"/>\n",
+            xml_escape(&entry.url)
+        ));
+        xml.push_str(&format!("    <id>{}</id>\n", xml_escape(&entry.url)));
+        xml.push_str(&format!("    <published>{}</published>\n", entry.published));
+        xml.push_str(&format!("    <updated>{}</updated>\n", entry.updated));
+        xml.push_str(&format!(
+            "    <content type=\"html\">{}</content>\n",
+            xml_escape(&entry.html)
+        ));
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn rfc2822(timestamp: Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::UTC)
+        .strftime("%a, %d %b %Y %H:%M:%S +0000")
+        .to_string()
+}
+
+pub fn render_rss(title: &str, base_url: &str, entries: &[FeedEntry]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\">\n<channel>\n");
+    xml.push_str(&format!("  <title>{}</title>\n", xml_escape(title)));
+    xml.push_str(&format!("  <link>{}</link>\n", xml_escape(base_url)));
+    xml.push_str(&format!(
+        "  <description>{}</description>\n",
+        xml_escape(title)
+    ));
+    for entry in entries {
+        xml.push_str("  <item>\n");
+        xml.push_str(&format!(
+            "    <title>{}</title>\n",
+            xml_escape(&entry.title)
+        ));
+        xml.push_str(&format!("    <link>{}</link>\n", xml_escape(&entry.url)));
+        xml.push_str(&format!(
+            "    <guid isPermaLink=\"true\">{}</guid>\n",
+            xml_escape(&entry.url)
+        ));
+        xml.push_str(&format!(
+            "    <pubDate>{}</pubDate>\n",
+            rfc2822(entry.published)
+        ));
+        xml.push_str(&format!(
+            "    <description>{}</description>\n",
+            xml_escape(&entry.html)
+        ));
+        xml.push_str("  </item>\n");
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+pub fn run_export_feed(
+    vault_path: &Path,
+    publish: &PublishConfig,
+    args: &ExportFeedArgs,
+) -> Result<(), Box<dyn Error>> {
+    let base_url = args
+        .base_url
+        .clone()
+        .or_else(|| publish.base_url.clone())
+        .ok_or("No base URL: pass --base-url or set [publish] base_url")?;
+    let title = args
+        .title
+        .clone()
+        .or_else(|| publish.title.clone())
+        .or_else(|| {
+            vault_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default();
+
+    let query = Query::parse(&args.query)?;
+    let notes = content_store::load_notes(vault_path)?;
+    let resolver = Resolver::from_vault(vault_path)?;
+    let entries = build_entries(&notes, &query, &resolver, &base_url, args.limit);
+    let xml = match args.format {
+        FeedFormat::Atom => render_atom(&title, &base_url, &entries),
+        FeedFormat::Rss => render_rss(&title, &base_url, &entries),
+    };
+
+    match &args.out {
+        Some(out) => {
+            fs::write(out, xml)?;
+            log::info!("Wrote {} entries to {}", entries.len(), out.display());
+        }
+        None => print!("{}", xml),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_time() {
+        let expected: Timestamp = "2024-03-01T00:00:00Z".parse().unwrap();
+        assert_eq!(parse_time("2024-03-01"), Some(expected));
+        assert_eq!(parse_time("2024-03-01T00:00"), Some(expected));
+        assert_eq!(parse_time("2024-03-01T01:00:00+01:00"), Some(expected));
+        assert_eq!(parse_time("March"), None);
+    }
+
+    #[test]
+    fn test_build_entries_sorted_and_filtered() {
+        let notes = vec![
+            Note::from_content(
+                PathBuf::from("blog/Old Post.md"),
+                String::from("---\ndate: 2024-01-01\ntags: [blog]\n---\nOld & [[New Post]]"),
+            ),
+            Note::from_content(
+                PathBuf::from("blog/New Post.md"),
+                String::from("---\ndate: 2024-02-01\ntags: [blog]\n---\nNew"),
+            ),
+            Note::from_content(PathBuf::from("Private.md"), String::from("secret")),
+        ];
+        let resolver = Resolver::new(notes.iter().map(|n| n.path.clone()).collect());
+        let query = Query::parse("tag:blog").unwrap();
+        let entries = build_entries(&notes, &query, &resolver, "https://example.com/", 10);
+
+        let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, vec!["New Post", "Old Post"]);
+        assert_eq!(entries[1].url, "https://example.com/blog/Old%20Post.html");
+        assert!(
+            entries[1]
+                .html
+                .contains("href=\"https://example.com/blog/New%20Post.html\"")
+        );
+
+        let atom = render_atom("Blog", "https://example.com/", &entries);
+        assert!(atom.contains("<updated>2024-02-01T00:00:00Z</updated>"));
+        assert!(atom.contains("Old &amp;amp; "));
+        let rss = render_rss("Blog", "https://example.com/", &entries);
+        assert!(rss.contains("<pubDate>Thu, 01 Feb 2024 00:00:00 +0000</pubDate>"));
+    }
+}
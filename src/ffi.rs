@@ -0,0 +1,363 @@
+//! A C API over the index, for editors and apps in C, C++ or Swift that embed obsidian-rs
+//! rather than run it. The declarations are in `include/obsidian_rs.h`, which
+//! `ci/update-header.sh` regenerates (builds with the `ffi` feature write a copy to
+//! `OUT_DIR`); build the library itself with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib` (or `staticlib`).
+//!
+//! Results are JSON in strings the caller releases with `obsidian_string_free`. Failing
+//! calls, panics included, return null or -1, and `obsidian_last_error` says why. The
+//! caches the library keeps are per process, so a process opens only one vault.
+
+use crate::collation;
+use crate::config::{self, AppConfig};
+use crate::content_store;
+use crate::data;
+use crate::entities;
+use crate::index;
+use crate::listing::NoteEntry;
+use crate::lock;
+use crate::plugins;
+use crate::profiles;
+use crate::query::Query;
+use crate::query_cache;
+use crate::search;
+
+use serde::Serialize;
+use sqlite::Connection;
+use std::{
+    cell::RefCell,
+    error::Error,
+    ffi::{CStr, CString, c_char, c_int},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    ptr,
+    sync::OnceLock,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The vault this process opened. The query cache, plugins, kinds and collation `open`
+/// installs are process-wide, so a second vault would see the first one's.
+static OPENED: OnceLock<PathBuf> = OnceLock::new();
+
+/// An open vault index; opaque to C
+pub struct ObsidianIndex {
+    vault_path: PathBuf,
+    config: AppConfig,
+    /// Folder holding the cache database
+    data_path: PathBuf,
+    connection: Connection,
+}
+
+fn set_error(e: impl fmt::Display) {
+    let message = CString::new(e.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `call`, recording why it failed for `obsidian_last_error`. A panic counts as a
+/// failure rather than unwinding into C, which would abort the host.
+fn report<T>(call: impl FnOnce() -> Result<T, Box<dyn Error>>) -> Option<T> {
+    let result = panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(format!("Internal error: {}", message).into())
+    });
+    result.map_err(set_error).ok()
+}
+
+unsafe fn text<'a>(string: *const c_char) -> Result<&'a str, Box<dyn Error>> {
+    if string.is_null() {
+        return Err("Unexpected null string".into());
+    }
+    Ok(unsafe { CStr::from_ptr(string) }.to_str()?)
+}
+
+unsafe fn handle<'a>(index: *const ObsidianIndex) -> Result<&'a ObsidianIndex, Box<dyn Error>> {
+    unsafe { index.as_ref() }.ok_or_else(|| "Unexpected null index".into())
+}
+
+fn json_string<T: Serialize>(value: &T) -> Result<*mut c_char, Box<dyn Error>> {
+    Ok(CString::new(serde_json::to_string(value)?)?.into_raw())
+}
+
+/// Opens the vault `config_path` configures, keeping its cache database in `data_path`
+/// when given instead of the usual data folder.
+fn open(
+    config_path: Option<&str>,
+    data_path: Option<&Path>,
+) -> Result<ObsidianIndex, Box<dyn Error>> {
+    let config = match config_path {
+        // Profiles belong to the default file; an explicit one is taken as written
+        Some(path) => config::load_config(Path::new(path), None)?,
+        None => config::extract_config(profiles::active(None).as_deref())?,
+    };
+    let vault_path =
+        config::get_root_workspace_path(&config).ok_or("Vault path not found in configuration.")?;
+    let canonical = vault_path.canonicalize()?;
+    let opened = OPENED.get_or_init(|| canonical.clone());
+    if *opened != canonical {
+        return Err(format!(
+            "This process already opened the vault at {}; open one vault per process",
+            opened.display()
+        )
+        .into());
+    }
+    let data_path = match data_path {
+        Some(path) => path.to_path_buf(),
+        None => data::get_data_path(&config)?,
+    };
+    plugins::install(&vault_path, &config);
+    query_cache::install_in(&data_path, &config);
+    entities::install(&config);
+    collation::install(&config.collation);
+    let connection = index::open_in(&data_path, &config)?;
+    if config.cache.store_content {
+        content_store::ensure_schema(&connection)?;
+    }
+    Ok(ObsidianIndex {
+        vault_path,
+        config,
+        data_path,
+        connection,
+    })
+}
+
+fn query_notes(
+    index: &ObsidianIndex,
+    query: &str,
+    limit: usize,
+) -> Result<*mut c_char, Box<dyn Error>> {
+    let query = Query::parse(query)?;
+    let mut notes = Vec::new();
+    // Only a running daemon keeps the stored contents current
+    if index.config.cache.store_content && lock::is_held(&index.data_path) {
+        notes = content_store::stored_notes(&index.connection, &index.vault_path)?;
+    }
+    if notes.is_empty() {
+        notes = data::load_notes(&index.vault_path)?;
+    }
+    let limit = match limit {
+        0 => usize::MAX,
+        limit => limit,
+    };
+    let entries: Vec<NoteEntry> = query
+        .filter(&notes)
+        .into_iter()
+        .take(limit)
+        .map(NoteEntry::new)
+        .collect();
+    json_string(&entries)
+}
+
+fn search_notes(
+    index: &ObsidianIndex,
+    query: &str,
+    limit: usize,
+) -> Result<*mut c_char, Box<dyn Error>> {
+    let limit = match limit {
+        0 => 20,
+        limit => limit,
+    };
+    let snippets = index.config.search.snippet_options();
+    let hits = search::search_with(
+        &index.connection,
+        &index.vault_path,
+        &index.config,
+        query,
+        None,
+        limit,
+        snippets,
+    )?;
+    json_string(&hits)
+}
+
+/// Opens the index of the vault `config_path` configures, or the one of the default
+/// configuration file and active profile when it is null. A file given by path is read
+/// as is, without any profile, since the saved one names a table of the default file.
+/// Returns null on failure.
+///
+/// # Safety
+///
+/// `config_path` is null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn obsidian_index_open(config_path: *const c_char) -> *mut ObsidianIndex {
+    let opened = report(|| {
+        if config_path.is_null() {
+            open(None, None)
+        } else {
+            open(Some(unsafe { text(config_path) }?), None)
+        }
+    });
+    opened.map_or(ptr::null_mut(), |index| Box::into_raw(Box::new(index)))
+}
+
+/// Brings the index up to date with the vault. Returns 0, or -1 on failure.
+///
+/// # Safety
+///
+/// `index` came from `obsidian_index_open` and has not been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn obsidian_index_refresh(index: *const ObsidianIndex) -> c_int {
+    let refreshed = report(|| {
+        let index = unsafe { handle(index) }?;
+        let embed = index.config.embeddings.backend.is_some();
+        search::refresh_index(&index.connection, &index.vault_path, &index.config, embed)
+    });
+    match refreshed {
+        Some(_) => 0,
+        None => -1,
+    }
+}
+
+/// Notes matching `query`, in the `query` command's syntax, as a JSON array of
+/// `{path, title, tags, created, modified, size}`; `limit` 0 returns every match.
+///
+/// # Safety
+///
+/// `index` came from `obsidian_index_open` and has not been freed; `query` is a
+/// NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn obsidian_index_query(
+    index: *const ObsidianIndex,
+    query: *const c_char,
+    limit: usize,
+) -> *mut c_char {
+    report(|| unsafe { query_notes(handle(index)?, text(query)?, limit) })
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Ranks notes against `query` as the `search` command does, refreshing the index first,
+/// and returns the hits as a JSON array of `{path, heading, line, score, snippet}`;
+/// `limit` 0 means 20.
+///
+/// # Safety
+///
+/// `index` came from `obsidian_index_open` and has not been freed; `query` is a
+/// NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn obsidian_index_search(
+    index: *const ObsidianIndex,
+    query: *const c_char,
+    limit: usize,
+) -> *mut c_char {
+    report(|| unsafe { search_notes(handle(index)?, text(query)?, limit) })
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Closes an index; null is ignored.
+///
+/// # Safety
+///
+/// `index` is null or came from `obsidian_index_open`, and is not used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn obsidian_index_free(index: *mut ObsidianIndex) {
+    if !index.is_null() {
+        report(|| {
+            drop(unsafe { Box::from_raw(index) });
+            Ok(())
+        });
+    }
+}
+
+/// Releases a string returned by this library; null is ignored.
+///
+/// # Safety
+///
+/// `string` is null or came from this library, and is not used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn obsidian_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        report(|| {
+            drop(unsafe { CString::from_raw(string) });
+            Ok(())
+        });
+    }
+}
+
+/// Why the last failing call on this thread failed, null before any failure. The string
+/// belongs to the library and stays valid until the next failure on the thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn obsidian_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(obsidian_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_panics_set_last_error() {
+        assert!(report::<()>(|| panic!("boom")).is_none());
+        assert_eq!(last_error(), "Internal error: boom");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_open_query_search_free() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("vault");
+        std::fs::create_dir(&vault).unwrap();
+        std::fs::write(vault.join("Plan.md"), "# Plan\nShip the #work release\n").unwrap();
+        std::fs::write(vault.join("Other.md"), "# Other\nNothing here\n").unwrap();
+        let config = dir.path().join("config.toml");
+        let toml = format!("[workspace]\nroot = {:?}\n", vault.to_string_lossy());
+        std::fs::write(&config, toml).unwrap();
+
+        // Keep the cache database out of the real data folder
+        let data = dir.path().join("data");
+        let opened = open(Some(&config.to_string_lossy()), Some(&data)).unwrap();
+        let index = Box::into_raw(Box::new(opened));
+        assert_eq!(
+            unsafe { obsidian_index_refresh(index) },
+            0,
+            "{}",
+            last_error()
+        );
+
+        let read = |found: *mut c_char| -> serde_json::Value {
+            assert!(!found.is_null(), "{}", last_error());
+            let json = unsafe { CStr::from_ptr(found) }
+                .to_str()
+                .unwrap()
+                .to_string();
+            unsafe { obsidian_string_free(found) };
+            serde_json::from_str(&json).unwrap()
+        };
+        let query = CString::new("tag:work").unwrap();
+        let found = read(unsafe { obsidian_index_query(index, query.as_ptr(), 0) });
+        assert_eq!(found[0]["path"], "Plan.md");
+        assert_eq!(found.as_array().unwrap().len(), 1);
+
+        let words = CString::new("release").unwrap();
+        let hits = read(unsafe { obsidian_index_search(index, words.as_ptr(), 5) });
+        assert_eq!(hits[0]["path"], "Plan.md");
+
+        unsafe { obsidian_index_free(index) };
+    }
+
+    #[test]
+    fn test_failures_set_last_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing =
+            CString::new(dir.path().join("config.toml").to_string_lossy().as_ref()).unwrap();
+        assert!(unsafe { obsidian_index_open(missing.as_ptr()) }.is_null());
+        assert!(last_error().starts_with("Configuration file not found"));
+
+        let query = CString::new("tag:work").unwrap();
+        let found = unsafe { obsidian_index_query(ptr::null(), query.as_ptr(), 0) };
+        assert!(found.is_null());
+        assert_eq!(last_error(), "Unexpected null index");
+    }
+}
@@ -0,0 +1,226 @@
+//! Standalone copies of notes: every `![[embed]]` of another note (or of one of its
+//! headings or blocks) is replaced by what it shows, recursively, and the links left over
+//! point at full vault paths so they still make sense outside the note's folder.
+
+use crate::cli::FlattenArgs;
+use crate::data;
+use crate::markdown::{self, Link, LinkStyle};
+use crate::resolver::Resolver;
+use crate::util;
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// `link` pointing at the vault-relative `target` by its full path.
+fn absolute_link(link: &Link, target: &Path, embed: bool) -> String {
+    let path = target.to_string_lossy().replace('\\', "/");
+    let anchor = link.anchor.as_deref().map(util::percent_decode);
+    match link.style {
+        LinkStyle::Wiki => markdown::render_wikilink(
+            path.strip_suffix(".md").unwrap_or(&path),
+            anchor.as_deref(),
+            link.text.as_deref(),
+            embed,
+        ),
+        LinkStyle::Markdown => {
+            let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+            let text = link.text.as_deref().unwrap_or(&stem);
+            markdown::render_markdown_link(&path, anchor.as_deref(), text, embed)
+        }
+    }
+}
+
+/// The part of `content` an embed with `anchor` shows: the section under a heading (with
+/// the heading), the block carrying a `^id` (without the id), or the body of the note.
+fn embedded_part(content: &str, anchor: Option<&str>) -> Option<String> {
+    let Some(anchor) = anchor.map(util::percent_decode) else {
+        return Some(content[markdown::body_start(content)..].to_string());
+    };
+    let lines: Vec<&str> = content.lines().collect();
+
+    if let Some(id) = anchor.strip_prefix('^') {
+        let marker = format!("^{}", id);
+        let at = lines
+            .iter()
+            .position(|line| line.trim_end().ends_with(&marker))?;
+        let own_line = lines[at].trim() == marker;
+        let is_item = |line: &str| {
+            let line = line.trim_start();
+            line.starts_with("- ") || line.starts_with("* ") || line.starts_with("+ ")
+        };
+        // A block is its paragraph, or a single list item; an id on a line of its own
+        // belongs to the block above
+        let headings: HashSet<usize> = markdown::parse_headings(content)
+            .iter()
+            .map(|heading| heading.line - 1)
+            .collect();
+        let end = if own_line { at } else { at + 1 };
+        let start = match !own_line && is_item(lines[at]) {
+            true => at,
+            false => (0..end)
+                .rev()
+                .find(|&i| lines[i].trim().is_empty() || headings.contains(&i))
+                .map_or(0, |boundary| boundary + 1),
+        };
+        let block = lines[start..end].join("\n");
+        return Some(
+            block
+                .trim_end()
+                .trim_end_matches(&marker)
+                .trim_end()
+                .to_string(),
+        );
+    }
+
+    let wanted = markdown::heading_slug(anchor.rsplit('#').next().unwrap_or_default());
+    let headings = markdown::parse_headings(content);
+    let (i, heading) = headings
+        .iter()
+        .enumerate()
+        .find(|(_, heading)| markdown::heading_slug(&heading.text) == wanted)?;
+    let end = headings[i + 1..]
+        .iter()
+        .find(|next| next.level <= heading.level)
+        .map_or(lines.len(), |next| next.line - 1);
+    Some(lines[heading.line - 1..end].join("\n"))
+}
+
+struct Flattener<'a> {
+    resolver: &'a Resolver,
+    /// Note contents by vault-relative path
+    contents: &'a HashMap<PathBuf, String>,
+    /// Embeds already inlined, as (note, anchor)
+    inlined: HashSet<(PathBuf, Option<String>)>,
+}
+
+impl Flattener<'_> {
+    /// `content` of the note at `source` with its embeds inlined. `stack` holds the notes
+    /// being inlined, so an embed of one of them stays a link instead of recursing forever.
+    fn flatten(&mut self, source: &Path, content: &str, stack: &mut Vec<PathBuf>) -> String {
+        let mut edits = Vec::new();
+        for link in markdown::parse_links(content) {
+            if link.is_external() {
+                continue;
+            }
+            let target = match link.target.as_str() {
+                "" => source.to_path_buf(),
+                target => match self.resolver.resolve(target, source) {
+                    Some(target) => target.to_path_buf(),
+                    None => continue,
+                },
+            };
+            let part = match link.embed && data::is_note(&target) {
+                true => self
+                    .contents
+                    .get(&target)
+                    .and_then(|content| embedded_part(content, link.anchor.as_deref())),
+                false => None,
+            };
+            let replacement = match part {
+                Some(_) if stack.contains(&target) => absolute_link(&link, &target, false),
+                Some(_) if !self.inlined.insert((target.clone(), link.anchor.clone())) => {
+                    format!(
+                        "*(included above: {})*",
+                        absolute_link(&link, &target, false)
+                    )
+                }
+                Some(part) => {
+                    stack.push(target.clone());
+                    let flattened = self.flatten(&target, &part, stack);
+                    stack.pop();
+                    flattened.trim().to_string()
+                }
+                None => absolute_link(&link, &target, link.embed),
+            };
+            edits.push((link.span.clone(), replacement));
+        }
+        markdown::replace_spans(content, edits)
+    }
+}
+
+/// The note at `path` with everything it embeds inlined.
+pub fn flatten_note(
+    path: &Path,
+    resolver: &Resolver,
+    contents: &HashMap<PathBuf, String>,
+) -> Result<String, Box<dyn Error>> {
+    let content = contents
+        .get(path)
+        .ok_or_else(|| format!("{} is not a note", path.display()))?;
+    let mut flattener = Flattener {
+        resolver,
+        contents,
+        inlined: HashSet::new(),
+    };
+    Ok(flattener.flatten(path, content, &mut vec![path.to_path_buf()]))
+}
+
+pub fn run_flatten(vault_path: &Path, args: &FlattenArgs) -> Result<(), Box<dyn Error>> {
+    let resolver = Resolver::from_vault(vault_path)?;
+    let path = resolver
+        .resolve(&args.note, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", args.note))?
+        .to_path_buf();
+    let contents: HashMap<PathBuf, String> = data::load_notes(vault_path)?
+        .into_iter()
+        .map(|note| (note.path, note.content))
+        .collect();
+    let flattened = flatten_note(&path, &resolver, &contents)?;
+
+    match &args.out {
+        Some(out) => {
+            let out = util::expand_tilde(out)
+                .map(|p| p.into_owned())
+                .ok_or("Failed to expand output path")?;
+            if util::is_within(&out, vault_path) {
+                return Err("The flattened note must be written outside the vault".into());
+            }
+            fs::write(&out, flattened)?;
+            log::info!("Wrote {}", out.display());
+        }
+        None => print!("{}", flattened),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatten_note() {
+        let contents: HashMap<PathBuf, String> = [
+            (
+                "Main.md",
+                "# Main\n![[Part]]\n![[Part]]\n![[Deep/Plan#Risks]]\n![[Deep/Plan#^goal]]\n\
+                 See [[Other|the other]] and ![[pic.png]]\n",
+            ),
+            (
+                "Part.md",
+                "---\ntags: [x]\n---\nPart body [[#Details]] ![[Main]]\n",
+            ),
+            (
+                "Deep/Plan.md",
+                "# Plan\nShip it. ^goal\n## Risks\nLate.\n### Minor\nTypos.\n## Next\n",
+            ),
+            ("Deep/Other.md", "other"),
+        ]
+        .into_iter()
+        .map(|(path, content)| (PathBuf::from(path), content.to_string()))
+        .collect();
+        let mut files: Vec<PathBuf> = contents.keys().cloned().collect();
+        files.push(PathBuf::from("assets/pic.png"));
+        let resolver = Resolver::new(files);
+
+        assert_eq!(
+            flatten_note(Path::new("Main.md"), &resolver, &contents).unwrap(),
+            "# Main\nPart body [[Part#Details]] [[Main]]\n*(included above: [[Part]])*\n\
+             ## Risks\nLate.\n### Minor\nTypos.\nShip it.\n\
+             See [[Deep/Other|the other]] and ![[assets/pic.png]]\n"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Per-folder settings. A `.obsidian-rs.toml` inside a vault folder applies to that folder
+//! and everything below it; deeper files override shallower ones key by key.
+//!
+//! ```toml
+//! ignore = ["drafts", "*.excalidraw.md"]
+//! template = "Templates/Meeting.md"
+//!
+//! [front_matter]
+//! type = "meeting"
+//! created = "{{date}}"
+//!
+//! [lint]
+//! single_h1 = false
+//! ```
+
+use crate::config::LintConfig;
+use crate::util;
+
+use serde::Deserialize;
+use serde_yaml::Mapping;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+pub static FOLDER_CONFIG: &str = ".obsidian-rs.toml";
+
+/// Lint rule toggles; unset ones keep the value inherited from above
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LintOverrides {
+    pub kebab_case_filenames: Option<bool>,
+    pub single_h1: Option<bool>,
+    pub no_bare_urls: Option<bool>,
+    pub wikilinks_only: Option<bool>,
+    pub heading_increment: Option<bool>,
+    pub toc: Option<bool>,
+    pub required_front_matter: Option<Vec<String>>,
+}
+
+impl LintOverrides {
+    fn apply(&self, rules: &mut LintConfig) {
+        let toggles = [
+            (&mut rules.kebab_case_filenames, self.kebab_case_filenames),
+            (&mut rules.single_h1, self.single_h1),
+            (&mut rules.no_bare_urls, self.no_bare_urls),
+            (&mut rules.wikilinks_only, self.wikilinks_only),
+            (&mut rules.heading_increment, self.heading_increment),
+            (&mut rules.toc, self.toc),
+        ];
+        for (rule, value) in toggles {
+            if let Some(value) = value {
+                *rule = value;
+            }
+        }
+        if let Some(keys) = &self.required_front_matter {
+            rules.required_front_matter = keys.clone();
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FolderConfig {
+    /// File and folder names to leave out below this folder; `*` and `?` are wildcards
+    pub ignore: Vec<String>,
+    /// Vault-relative template for notes created below this folder
+    pub template: Option<String>,
+    /// Entries added to the front matter of notes created below this folder
+    pub front_matter: toml::Table,
+    pub lint: LintOverrides,
+}
+
+/// `value` as YAML, with `{{name}}` placeholders in its strings substituted.
+fn to_yaml(value: &toml::Value, variables: &[(&str, String)]) -> serde_yaml::Value {
+    use serde_yaml::Value as Yaml;
+    match value {
+        toml::Value::String(s) => Yaml::String(util::render_template(s, variables)),
+        toml::Value::Integer(n) => Yaml::Number((*n).into()),
+        toml::Value::Float(n) => Yaml::Number((*n).into()),
+        toml::Value::Boolean(b) => Yaml::Bool(*b),
+        toml::Value::Datetime(d) => Yaml::String(d.to_string()),
+        toml::Value::Array(items) => {
+            Yaml::Sequence(items.iter().map(|item| to_yaml(item, variables)).collect())
+        }
+        toml::Value::Table(table) => Yaml::Mapping(
+            table
+                .iter()
+                .map(|(key, value)| (Yaml::String(key.clone()), to_yaml(value, variables)))
+                .collect(),
+        ),
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` is any run of characters and `?` any one.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The folder configurations of a vault, keyed by vault-relative folder.
+#[derive(Debug, Default, Clone)]
+pub struct FolderConfigs {
+    folders: HashMap<PathBuf, FolderConfig>,
+}
+
+impl FolderConfigs {
+    pub fn load(vault_path: &Path) -> Result<FolderConfigs, Box<dyn Error>> {
+        let mut folders = HashMap::new();
+        let walker = WalkDir::new(vault_path).into_iter().filter_entry(|entry| {
+            entry.depth() == 0
+                || (entry.file_type().is_dir()
+                    && !entry.file_name().to_string_lossy().starts_with('.'))
+        });
+        for entry in walker {
+            let dir = entry?.into_path();
+            let file = dir.join(FOLDER_CONFIG);
+            if !file.is_file() {
+                continue;
+            }
+            let config: FolderConfig = toml::from_str(&fs::read_to_string(&file)?)
+                .map_err(|e| format!("Invalid {}: {}", file.display(), e))?;
+            folders.insert(dir.strip_prefix(vault_path)?.to_path_buf(), config);
+        }
+        Ok(FolderConfigs { folders })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.folders.is_empty()
+    }
+
+    /// Configurations that apply to the vault-relative `rel_path`, outermost first, with
+    /// the folder each belongs to.
+    fn chain<'a, 'p>(
+        &'a self,
+        rel_path: &'p Path,
+    ) -> impl Iterator<Item = (&'p Path, &'a FolderConfig)> + use<'a, 'p> {
+        let mut folders: Vec<&Path> = rel_path.ancestors().skip(1).collect();
+        folders.reverse();
+        folders
+            .into_iter()
+            .filter_map(|folder| self.folders.get(folder).map(|config| (folder, config)))
+    }
+
+    /// Whether a folder configuration above `rel_path` ignores it or one of its folders.
+    pub fn is_ignored(&self, rel_path: &Path) -> bool {
+        self.chain(rel_path).any(|(folder, config)| {
+            rel_path
+                .strip_prefix(folder)
+                .unwrap_or(rel_path)
+                .components()
+                .any(|part| {
+                    let part = part.as_os_str().to_string_lossy();
+                    config
+                        .ignore
+                        .iter()
+                        .any(|pattern| wildcard_match(pattern, &part))
+                })
+        })
+    }
+
+    /// `rules` with the overrides of every folder above `rel_path` applied.
+    pub fn lint_rules(&self, rules: &LintConfig, rel_path: &Path) -> LintConfig {
+        let mut rules = rules.clone();
+        for (_, config) in self.chain(rel_path) {
+            config.lint.apply(&mut rules);
+        }
+        rules
+    }
+
+    /// Front matter defaults for a new note at `rel_path`; deeper folders win per key.
+    pub fn front_matter(&self, rel_path: &Path, variables: &[(&str, String)]) -> Mapping {
+        let mut defaults = Mapping::new();
+        for (_, config) in self.chain(rel_path) {
+            for (key, value) in &config.front_matter {
+                defaults.insert(key.as_str().into(), to_yaml(value, variables));
+            }
+        }
+        defaults
+    }
+
+    /// The template set by the nearest folder above `rel_path`.
+    pub fn template(&self, rel_path: &Path) -> Option<&str> {
+        self.chain(rel_path)
+            .filter_map(|(_, config)| config.template.as_deref())
+            .last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("drafts", "drafts"));
+        assert!(wildcard_match("*.excalidraw.md", "Plan.excalidraw.md"));
+        assert!(wildcard_match("Note ?", "Note 2"));
+        assert!(!wildcard_match("*.md", "Note.txt"));
+        assert!(!wildcard_match("draft", "drafts"));
+    }
+
+    #[test]
+    fn test_folder_configs() {
+        let vault = tempfile::tempdir().unwrap();
+        let root = vault.path();
+        fs::create_dir_all(root.join("work/meetings")).unwrap();
+        fs::write(
+            root.join(FOLDER_CONFIG),
+            "ignore = [\"*.tmp.md\"]\n[lint]\nsingle_h1 = false\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("work").join(FOLDER_CONFIG),
+            "ignore = [\"drafts\"]\ntemplate = \"T/Work.md\"\n[lint]\nkebab_case_filenames = true\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("work/meetings").join(FOLDER_CONFIG),
+            "template = \"T/Meeting.md\"\n[front_matter]\ntype = \"meeting\"\n\
+             created = \"{{date}}\"\n[lint]\nsingle_h1 = true\n",
+        )
+        .unwrap();
+        let folders = FolderConfigs::load(root).unwrap();
+
+        assert!(folders.is_ignored(Path::new("work/drafts/A.md")));
+        assert!(folders.is_ignored(Path::new("x.tmp.md")));
+        assert!(!folders.is_ignored(Path::new("drafts/A.md")));
+
+        let rules = folders.lint_rules(&LintConfig::default(), Path::new("work/meetings/A.md"));
+        assert!(rules.single_h1 && rules.kebab_case_filenames);
+        let rules = folders.lint_rules(&LintConfig::default(), Path::new("work/A.md"));
+        assert!(!rules.single_h1 && rules.kebab_case_filenames);
+
+        assert_eq!(
+            folders.template(Path::new("work/meetings/A.md")),
+            Some("T/Meeting.md")
+        );
+        assert_eq!(folders.template(Path::new("A.md")), None);
+
+        let defaults = folders.front_matter(
+            Path::new("work/meetings/A.md"),
+            &[("date", String::from("2025-01-02"))],
+        );
+        assert_eq!(
+            serde_yaml::to_string(&defaults).unwrap(),
+            "created: 2025-01-02\ntype: meeting\n"
+        );
+        assert!(folders.front_matter(Path::new("A.md"), &[]).is_empty());
+    }
+}
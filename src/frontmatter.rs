@@ -0,0 +1,252 @@
+use serde_yaml::{Mapping, Value};
+use std::error::Error;
+
+/// Splits a note into its raw YAML front matter (without the `---` lines) and its body.
+pub fn split(content: &str) -> (Option<&str>, &str) {
+    let mut lines = content.split_inclusive('\n');
+    let first = match lines.next() {
+        Some(line) if line.trim() == "---" => line,
+        _ => return (None, content),
+    };
+    let yaml_start = first.len();
+    let mut offset = yaml_start;
+    for line in lines {
+        if line.trim() == "---" {
+            return (
+                Some(&content[yaml_start..offset]),
+                &content[offset + line.len()..],
+            );
+        }
+        offset += line.len();
+    }
+    (None, content)
+}
+
+/// Parses the front matter block into a YAML mapping, if the note has one.
+pub fn parse_mapping(content: &str) -> Result<Option<Mapping>, Box<dyn Error>> {
+    match split(content).0 {
+        None => Ok(None),
+        Some(yaml) if yaml.trim().is_empty() => Ok(Some(Mapping::new())),
+        Some(yaml) => Ok(Some(serde_yaml::from_str(yaml)?)),
+    }
+}
+
+/// Keys from `required` that are absent from the note's front matter.
+pub fn missing_keys(content: &str, required: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mapping = parse_mapping(content)?.unwrap_or_default();
+    Ok(required
+        .iter()
+        .filter(|key| !mapping.contains_key(key.as_str()))
+        .cloned()
+        .collect())
+}
+
+/// Appends empty entries for the missing keys, creating the front matter block if needed.
+///
+/// Existing YAML is left untouched so formatting and comments survive.
+pub fn add_missing_keys(content: &str, required: &[String]) -> Result<String, Box<dyn Error>> {
+    let missing = missing_keys(content, required)?;
+    if missing.is_empty() {
+        return Ok(content.to_string());
+    }
+    let additions: String = missing.iter().map(|key| format!("{}:\n", key)).collect();
+    Ok(append_entries(content, &additions))
+}
+
+/// Adds the entries of `defaults` whose keys the note's front matter lacks.
+pub fn add_defaults(content: &str, defaults: &Mapping) -> Result<String, Box<dyn Error>> {
+    let mapping = parse_mapping(content)?.unwrap_or_default();
+    let missing: Mapping = defaults
+        .iter()
+        .filter(|(key, _)| !mapping.contains_key(*key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    if missing.is_empty() {
+        return Ok(content.to_string());
+    }
+    Ok(append_entries(content, &serde_yaml::to_string(&missing)?))
+}
+
+/// Appends raw YAML lines to the front matter, creating the block if needed.
+fn append_entries(content: &str, additions: &str) -> String {
+    match split(content) {
+        (Some(yaml), body) => {
+            let mut yaml = yaml.to_string();
+            if !yaml.is_empty() && !yaml.ends_with('\n') {
+                yaml.push('\n');
+            }
+            format!("---\n{}{}---\n{}", yaml, additions, body)
+        }
+        (None, body) => format!("---\n{}---\n{}", additions, body),
+    }
+}
+
+fn scalar_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Every property of the front matter with its text values; a list gives one pair per
+/// item, and nested mappings are left out.
+pub fn scalar_properties(content: &str) -> Vec<(String, String)> {
+    let mapping = parse_mapping(content).ok().flatten().unwrap_or_default();
+    let mut properties = Vec::new();
+    for (key, value) in &mapping {
+        let Some(key) = scalar_string(key) else {
+            continue;
+        };
+        match value {
+            Value::Sequence(items) => properties.extend(
+                items
+                    .iter()
+                    .filter_map(scalar_string)
+                    .map(|item| (key.clone(), item)),
+            ),
+            value => properties.extend(scalar_string(value).map(|value| (key, value))),
+        }
+    }
+    properties
+}
+
+/// Adds `tags` to the front matter `tags` list, creating the key or block if needed.
+///
+/// Only the `tags` entry is rewritten, in its original flow (`[a, b]`) or block style.
+pub fn add_tags(content: &str, tags: &[String]) -> Result<String, Box<dyn Error>> {
+    let mapping = parse_mapping(content)?.unwrap_or_default();
+    let mut merged: Vec<String> = match mapping.get("tags") {
+        Some(Value::Sequence(items)) => items.iter().filter_map(scalar_string).collect(),
+        Some(Value::String(list)) => list
+            .split([',', ' '])
+            .filter(|tag| !tag.is_empty())
+            .map(String::from)
+            .collect(),
+        Some(value) => scalar_string(value).into_iter().collect(),
+        None => Vec::new(),
+    };
+    let known = merged.len();
+    for tag in tags {
+        if !merged.contains(tag) {
+            merged.push(tag.clone());
+        }
+    }
+    if merged.len() == known {
+        return Ok(content.to_string());
+    }
+
+    let (yaml, body) = split(content);
+    let lines: Vec<&str> = yaml.unwrap_or_default().split_inclusive('\n').collect();
+    let start = lines.iter().position(|line| line.starts_with("tags:"));
+    let end = start.map_or(lines.len(), |start| {
+        start
+            + 1
+            + lines[start + 1..]
+                .iter()
+                .take_while(|line| line.starts_with([' ', '\t', '-']))
+                .count()
+    });
+    let flow = start.is_some_and(|start| lines[start][5..].trim_start().starts_with('['));
+    let indent = start
+        .and_then(|start| {
+            lines[start + 1..end]
+                .iter()
+                .find_map(|line| line.find('-').map(|n| &line[..n]))
+        })
+        .unwrap_or("  ");
+    let entry = if flow {
+        format!("tags: [{}]\n", merged.join(", "))
+    } else {
+        let items: String = merged
+            .iter()
+            .map(|tag| format!("{}- {}\n", indent, tag))
+            .collect();
+        format!("tags:\n{}", items)
+    };
+
+    let mut yaml = match start {
+        Some(start) => format!(
+            "{}{}{}",
+            lines[..start].concat(),
+            entry,
+            lines[end..].concat()
+        ),
+        None => {
+            let mut yaml = lines.concat();
+            if !yaml.is_empty() && !yaml.ends_with('\n') {
+                yaml.push('\n');
+            }
+            yaml + &entry
+        }
+    };
+    if !yaml.ends_with('\n') {
+        yaml.push('\n');
+    }
+    Ok(format!("---\n{}---\n{}", yaml, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let (yaml, body) = split("---\ntitle: A\n---\nBody\n");
+        assert_eq!(yaml, Some("title: A\n"));
+        assert_eq!(body, "Body\n");
+
+        assert_eq!(split("No front matter\n"), (None, "No front matter\n"));
+        assert_eq!(split("---\nunterminated\n"), (None, "---\nunterminated\n"));
+    }
+
+    #[test]
+    fn test_add_missing_keys() {
+        let required = vec![String::from("title"), String::from("tags")];
+
+        let updated = add_missing_keys("---\ntitle: A # keep\n---\nBody\n", &required).unwrap();
+        assert_eq!(updated, "---\ntitle: A # keep\ntags:\n---\nBody\n");
+
+        let created = add_missing_keys("Body\n", &required).unwrap();
+        assert_eq!(created, "---\ntitle:\ntags:\n---\nBody\n");
+    }
+
+    #[test]
+    fn test_add_defaults() {
+        let defaults: Mapping = serde_yaml::from_str("type: person\ntags: [people]\n").unwrap();
+        let updated = add_defaults("---\ntags: [work]\n---\nBody\n", &defaults).unwrap();
+        assert_eq!(updated, "---\ntags: [work]\ntype: person\n---\nBody\n");
+        assert_eq!(
+            add_defaults("", &defaults).unwrap(),
+            "---\ntype: person\ntags:\n- people\n---\n"
+        );
+    }
+
+    #[test]
+    fn test_add_tags() {
+        let tags = vec![String::from("rust"), String::from("cli")];
+
+        let block = "---\ntitle: A\ntags:\n    - rust\ndate: 2024\n---\nBody\n";
+        assert_eq!(
+            add_tags(block, &tags).unwrap(),
+            "---\ntitle: A\ntags:\n    - rust\n    - cli\ndate: 2024\n---\nBody\n"
+        );
+
+        let flow = "---\ntags: [rust, 2024]\n---\nBody\n";
+        assert_eq!(
+            add_tags(flow, &tags).unwrap(),
+            "---\ntags: [rust, 2024, cli]\n---\nBody\n"
+        );
+
+        assert_eq!(
+            add_tags("Body\n", &tags).unwrap(),
+            "---\ntags:\n  - rust\n  - cli\n---\nBody\n"
+        );
+        assert_eq!(
+            add_tags("---\ntitle: A\n---\n", &tags[..1]).unwrap(),
+            "---\ntitle: A\ntags:\n  - rust\n---\n"
+        );
+        assert_eq!(add_tags(block, &tags[..1]).unwrap(), block);
+    }
+}
@@ -0,0 +1,399 @@
+//! GraphQL over the vault at `/graphql`, for clients that want nested data (a note, its
+//! backlinks and their front matter) in one request instead of several REST calls. The
+//! schema covers notes, links, tags and tasks and is read-only; `GET /graphql/schema`
+//! prints it in SDL.
+
+use crate::api::ApiState;
+use crate::data::{self, Note};
+use crate::http::{Request, Response};
+use crate::kanban;
+use crate::markdown;
+use crate::query::Query;
+use crate::resolver::{self, Resolver};
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Json, Object, Result, Schema, SimpleObject,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+};
+
+type VaultSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Deepest nesting a query may use, e.g. `note { backlinks { note { ... } } }` is three
+const MAX_DEPTH: usize = 8;
+/// Most work a query may ask for, where a field costs one and a list multiplies what it
+/// selects by `LIST_COST`
+const MAX_COMPLEXITY: usize = 2000;
+const LIST_COST: usize = 20;
+
+static SCHEMA: LazyLock<VaultSchema> = LazyLock::new(|| {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+});
+
+/// The vault as one request sees it
+struct Vault {
+    notes: Vec<Note>,
+    resolver: Resolver,
+    by_path: HashMap<PathBuf, usize>,
+}
+
+impl Vault {
+    fn new(notes: Vec<Note>) -> Vault {
+        let resolver = Resolver::new(notes.iter().map(|note| note.path.clone()).collect());
+        let by_path = notes
+            .iter()
+            .enumerate()
+            .map(|(i, note)| (note.path.clone(), i))
+            .collect();
+        Vault {
+            notes,
+            resolver,
+            by_path,
+        }
+    }
+
+    fn index_of(&self, path: &Path) -> Option<usize> {
+        self.by_path.get(path).copied()
+    }
+}
+
+fn vault<'a>(ctx: &Context<'a>) -> &'a Vault {
+    ctx.data_unchecked::<Arc<Vault>>()
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The note a path or link text resolves to
+    async fn note(&self, ctx: &Context<'_>, path: String) -> Option<NoteObject> {
+        let vault = vault(ctx);
+        let resolved = vault.resolver.resolve(&path, Path::new(""))?;
+        vault.index_of(resolved).map(NoteObject)
+    }
+
+    /// Notes matching a query in the `query` command's syntax, all without one
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn notes(
+        &self,
+        ctx: &Context<'_>,
+        query: Option<String>,
+        limit: Option<usize>,
+    ) -> Result<Vec<NoteObject>> {
+        let query = Query::parse(query.as_deref().unwrap_or_default())
+            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        Ok(vault(ctx)
+            .notes
+            .iter()
+            .enumerate()
+            .filter(|(_, note)| query.matches(note))
+            .map(|(i, _)| NoteObject(i))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// Every tag with the notes carrying it, most used first
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn tags(&self, ctx: &Context<'_>) -> Vec<TagObject> {
+        let mut tags: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+        for (i, note) in vault(ctx).notes.iter().enumerate() {
+            for tag in &note.tags {
+                tags.entry(tag).or_default().push(i);
+            }
+        }
+        let mut tags: Vec<TagObject> = tags
+            .into_iter()
+            .map(|(name, notes)| TagObject {
+                name: name.to_string(),
+                notes,
+            })
+            .collect();
+        tags.sort_by_key(|tag| std::cmp::Reverse(tag.notes.len()));
+        tags
+    }
+
+    /// Tasks across the vault, optionally only open or only done ones
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn tasks(&self, ctx: &Context<'_>, done: Option<bool>) -> Vec<Task> {
+        vault(ctx)
+            .notes
+            .iter()
+            .flat_map(tasks_of)
+            .filter(|task| done.is_none_or(|done| task.done == done))
+            .collect()
+    }
+}
+
+pub struct NoteObject(usize);
+
+#[Object(name = "Note")]
+impl NoteObject {
+    async fn path(&self, ctx: &Context<'_>) -> String {
+        key(&vault(ctx).notes[self.0].path)
+    }
+
+    async fn title(&self, ctx: &Context<'_>) -> String {
+        vault(ctx).notes[self.0].title()
+    }
+
+    async fn content(&self, ctx: &Context<'_>) -> String {
+        vault(ctx).notes[self.0].content.clone()
+    }
+
+    async fn tags(&self, ctx: &Context<'_>) -> Vec<String> {
+        vault(ctx).notes[self.0].tags.clone()
+    }
+
+    /// The front matter as a JSON object, `null` without one
+    async fn frontmatter(&self, ctx: &Context<'_>) -> Option<Json<serde_json::Value>> {
+        let mapping = crate::frontmatter::parse_mapping(&vault(ctx).notes[self.0].content)
+            .ok()
+            .flatten()?;
+        serde_json::to_value(mapping).ok().map(Json)
+    }
+
+    /// Links to notes and files, external links left out
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn links(&self, ctx: &Context<'_>) -> Vec<LinkObject> {
+        let note = &vault(ctx).notes[self.0];
+        markdown::parse_links(&note.content)
+            .into_iter()
+            .filter(|link| !link.is_external() && !link.target.is_empty())
+            .map(|link| LinkObject {
+                source: self.0,
+                target: link.target,
+                anchor: link.anchor,
+                line: link.line,
+            })
+            .collect()
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn backlinks(&self, ctx: &Context<'_>) -> Vec<BacklinkObject> {
+        let vault = vault(ctx);
+        let target = &vault.notes[self.0].path;
+        resolver::backlinks(&vault.notes, &vault.resolver, target)
+            .into_iter()
+            .filter_map(|backlink| {
+                Some(BacklinkObject {
+                    source: vault.index_of(&backlink.path)?,
+                    line: backlink.line,
+                    context: backlink.context,
+                })
+            })
+            .collect()
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn tasks(&self, ctx: &Context<'_>) -> Vec<Task> {
+        tasks_of(&vault(ctx).notes[self.0])
+    }
+}
+
+pub struct LinkObject {
+    source: usize,
+    target: String,
+    anchor: Option<String>,
+    line: usize,
+}
+
+#[Object(name = "Link")]
+impl LinkObject {
+    /// The link text's target, as written
+    async fn target(&self) -> &str {
+        &self.target
+    }
+
+    async fn anchor(&self) -> Option<&str> {
+        self.anchor.as_deref()
+    }
+
+    async fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The note the link opens, `null` for unresolved links and attachments
+    async fn note(&self, ctx: &Context<'_>) -> Option<NoteObject> {
+        let vault = vault(ctx);
+        let resolved = vault
+            .resolver
+            .resolve(&self.target, &vault.notes[self.source].path)?;
+        vault.index_of(resolved).map(NoteObject)
+    }
+}
+
+pub struct BacklinkObject {
+    source: usize,
+    line: usize,
+    context: String,
+}
+
+#[Object(name = "Backlink")]
+impl BacklinkObject {
+    /// The linking note
+    async fn note(&self) -> NoteObject {
+        NoteObject(self.source)
+    }
+
+    async fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The linking line, trimmed
+    async fn context(&self) -> &str {
+        &self.context
+    }
+}
+
+pub struct TagObject {
+    name: String,
+    notes: Vec<usize>,
+}
+
+#[Object(name = "Tag")]
+impl TagObject {
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn count(&self) -> usize {
+        self.notes.len()
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn notes(&self) -> Vec<NoteObject> {
+        self.notes.iter().copied().map(NoteObject).collect()
+    }
+}
+
+/// A `- [ ]` checkbox item
+#[derive(SimpleObject, Debug, Clone, PartialEq)]
+pub struct Task {
+    pub text: String,
+    pub done: bool,
+    /// Path of the note holding the task
+    pub path: String,
+    pub line: usize,
+}
+
+fn tasks_of(note: &Note) -> Vec<Task> {
+    let done_cards = kanban::done_cards(note);
+    markdown::body_lines(&note.content)
+        .into_iter()
+        .filter_map(|(line, _, text)| {
+            let (done, text) = markdown::parse_task(text)?;
+            Some(Task {
+                text: text.trim().to_string(),
+                done: done || done_cards.contains(&line),
+                path: key(&note.path),
+                line,
+            })
+        })
+        .collect()
+}
+
+/// Runs a GraphQL request against the notes of `vault_path`.
+fn execute(vault_path: &Path, request: async_graphql::Request) -> async_graphql::Response {
+    match data::load_notes(vault_path) {
+        Ok(notes) => {
+            let request = request.data(Arc::new(Vault::new(notes)));
+            pollster::block_on(SCHEMA.execute(request))
+        }
+        Err(e) => async_graphql::Response::from_errors(vec![async_graphql::ServerError::new(
+            e.to_string(),
+            None,
+        )]),
+    }
+}
+
+/// `POST /graphql` with a JSON body `{"query", "variables"?, "operationName"?}`, or
+/// `GET /graphql?query=`; `GET /graphql/schema` gives the schema in SDL.
+pub fn handle(state: &ApiState, request: &Request) -> Response {
+    let graphql_request = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/graphql/schema") => {
+            return Response {
+                status: 200,
+                content_type: String::from("text/plain; charset=utf-8"),
+                headers: Vec::new(),
+                body: SCHEMA.sdl().into_bytes(),
+            };
+        }
+        ("GET", "/graphql") => match request.query.get("query") {
+            Some(query) => async_graphql::Request::new(query),
+            None => return Response::error(400, "Missing 'query' parameter"),
+        },
+        ("POST", "/graphql") => match serde_json::from_slice(&request.body) {
+            Ok(graphql_request) => graphql_request,
+            Err(e) => return Response::error(400, &format!("Invalid GraphQL request: {}", e)),
+        },
+        _ => return Response::not_found(),
+    };
+    Response::json(200, &execute(&state.vault_path, graphql_request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_deep_queries_are_refused() {
+        let request = async_graphql::Request::new(
+            "{ notes { backlinks { note { backlinks { note { backlinks { line } } } } } } }",
+        )
+        .data(Arc::new(Vault::new(Vec::new())));
+        let response = pollster::block_on(SCHEMA.execute(request));
+        assert!(!response.errors.is_empty());
+    }
+
+    #[test]
+    fn test_nested_query() {
+        let notes = vec![
+            Note::from_content(
+                PathBuf::from("Plan.md"),
+                String::from("---\nstatus: draft\n---\nSee [[Goals]].\n- [ ] Write #work\n"),
+            ),
+            Note::from_content(
+                PathBuf::from("Goals.md"),
+                String::from("# Goals\n- [x] Decide\n"),
+            ),
+        ];
+        let request = async_graphql::Request::new(
+            r#"{
+                note(path: "Goals") {
+                    title
+                    backlinks { line note { path frontmatter } }
+                }
+                tasks(done: false) { text path }
+                tags { name count }
+            }"#,
+        )
+        .data(Arc::new(Vault::new(notes)));
+        let response = pollster::block_on(SCHEMA.execute(request));
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({
+                "note": {
+                    "title": "Goals",
+                    "backlinks": [{
+                        "line": 4,
+                        "note": { "path": "Plan.md", "frontmatter": { "status": "draft" } },
+                    }],
+                },
+                "tasks": [{ "text": "Write #work", "path": "Plan.md" }],
+                "tags": [{ "name": "work", "count": 1 }],
+            })
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Regex search over the vault's notes with context lines. While a daemon keeps the index
+//! current, the notes come from it, so the vault is not walked and hidden folders and
+//! folder-config ignores are skipped for free.
+
+use crate::cli::{GrepArgs, OutputFormat};
+use crate::config::AppConfig;
+use crate::content_store;
+use crate::data::{self, Note};
+use crate::index;
+use crate::lock;
+use crate::query::Query;
+use crate::util;
+
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct GrepMatch {
+    pub path: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    /// 1-based line of the match
+    pub line: usize,
+    /// 1-based character column of the first match on the line
+    pub column: usize,
+    pub text: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+/// Lines of `note` matching `regex`, each with up to `context` lines around it.
+pub fn grep_note(note: &Note, regex: &Regex, context: usize) -> Vec<GrepMatch> {
+    let lines: Vec<&str> = note.content.lines().collect();
+    let path = note.path.to_string_lossy().replace('\\', "/");
+    let title = note.title();
+    lines
+        .iter()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let found = regex.find(line)?;
+            let to_owned = |slice: &[&str]| slice.iter().map(|l| l.to_string()).collect();
+            Some(GrepMatch {
+                path: path.clone(),
+                title: title.clone(),
+                tags: note.tags.clone(),
+                line: index + 1,
+                column: line[..found.start()].chars().count() + 1,
+                text: line.to_string(),
+                before: to_owned(&lines[index.saturating_sub(context)..index]),
+                after: to_owned(&lines[index + 1..(index + 1 + context).min(lines.len())]),
+            })
+        })
+        .collect()
+}
+
+/// Prints one note's matches like `grep -n`: `path:line:column:text` for matches and
+/// `path-line-text` for context, with `--` between groups of lines when there is context.
+fn print_text(matches: &[GrepMatch], context: usize, printed: &mut bool) {
+    let mut lines: BTreeMap<usize, (Option<usize>, &str)> = BTreeMap::new();
+    for found in matches {
+        let first = found.line - found.before.len();
+        for (offset, text) in found.before.iter().chain(&found.after).enumerate() {
+            let line = match offset < found.before.len() {
+                true => first + offset,
+                false => found.line + 1 + offset - found.before.len(),
+            };
+            lines.entry(line).or_insert((None, text));
+        }
+        lines.insert(found.line, (Some(found.column), &found.text));
+    }
+    let mut previous = None;
+    for (line, (column, text)) in lines {
+        if context > 0 && *printed && previous.is_none_or(|previous| previous + 1 < line) {
+            println!("--");
+        }
+        let path = &matches[0].path;
+        match column {
+            Some(column) => println!("{}:{}:{}:{}", path, line, column, text),
+            None => println!("{}-{}-{}", path, line, text),
+        }
+        previous = Some(line);
+        *printed = true;
+    }
+}
+
+/// The notes to search: the stored contents when kept, else the notes the index tracks,
+/// read from the vault. Without a daemon the index may be stale, so the vault is walked.
+fn notes_to_search<'a>(
+    vault_path: &'a Path,
+    config: &AppConfig,
+) -> Result<Box<dyn Iterator<Item = Note> + 'a>, Box<dyn Error>> {
+    if content_store::is_installed() {
+        return Ok(Box::new(content_store::load_notes(vault_path)?.into_iter()));
+    }
+    let maintained = data::get_data_path(config).is_ok_and(|path| lock::is_held(&path));
+    if maintained {
+        let paths = index::indexed_notes(&index::open(config)?)?;
+        if !paths.is_empty() {
+            return Ok(Box::new(paths.into_iter().filter_map(move |path| {
+                let file = vault_path.join(&path);
+                let content = match fs::read_to_string(&file) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        log::warn!("Skipping '{}': {}", path.display(), e);
+                        return None;
+                    }
+                };
+                let mut note = Note::from_content(path, content);
+                note.modified = fs::metadata(&file).and_then(|m| m.modified()).ok();
+                Some(note)
+            })));
+        }
+    }
+    Ok(Box::new(data::iter_notes(vault_path)?))
+}
+
+pub fn run_grep(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &GrepArgs,
+) -> Result<usize, Box<dyn Error>> {
+    let regex = RegexBuilder::new(&args.pattern)
+        .case_insensitive(args.ignore_case)
+        .build()?;
+    let query = args.query.as_deref().map(Query::parse).transpose()?;
+
+    let mut total = 0;
+    let mut printed = false;
+    let mut all = Vec::new();
+    for note in notes_to_search(vault_path, config)? {
+        if query.as_ref().is_some_and(|query| !query.matches(&note)) {
+            continue;
+        }
+        let matches = grep_note(&note, &regex, args.context);
+        total += matches.len();
+        if args.porcelain {
+            for found in &matches {
+                let (line, column) = (found.line.to_string(), found.column.to_string());
+                util::print_porcelain(&[&found.path, &line, &column, &found.text])?;
+            }
+        } else {
+            match args.format {
+                OutputFormat::Text => print_text(&matches, args.context, &mut printed),
+                OutputFormat::Ndjson => {
+                    for found in &matches {
+                        util::print_ndjson(found)?;
+                    }
+                }
+                OutputFormat::Json => all.extend(matches),
+            }
+        }
+    }
+    if !args.porcelain && args.format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&all)?);
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_grep_note() {
+        let note = Note::from_content(
+            PathBuf::from("A.md"),
+            String::from("one\ntwo TODO\nthree\nfour todo\n"),
+        );
+        let regex = RegexBuilder::new("todo")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let matches = grep_note(&note, &regex, 1);
+        assert_eq!(matches.len(), 2);
+        assert_eq!((matches[0].line, matches[0].column), (2, 5));
+        assert_eq!(matches[0].before, ["one"]);
+        assert_eq!(matches[0].after, ["three"]);
+        assert!(matches[1].after.is_empty());
+    }
+}
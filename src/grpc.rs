@@ -0,0 +1,435 @@
+//! The vault API over gRPC (`proto/vault.proto`), for backends in other languages that
+//! prefer generated clients to JSON over HTTP. Served with tonic on `[server] grpc_listen`
+//! when built with the `grpc` feature; search, backlinks and file changes are streamed.
+//! Clients send the same API keys as the HTTP API, as `authorization: Bearer <key>`
+//! metadata. The listener uses neither `[server.tls]` nor the rate limit, so keys would
+//! travel in the clear: only loopback addresses are served.
+
+// Every call, generated or not, fails with a `tonic::Status`, which is large but is what
+// tonic expects
+#![allow(clippy::result_large_err)]
+
+use crate::auth::Keys;
+use crate::cli::SearchMode;
+use crate::config::AppConfig;
+use crate::data::{Note, NoteCache};
+use crate::frontmatter;
+use crate::http;
+use crate::index;
+use crate::listing;
+use crate::query::Query;
+use crate::resolver::{self, Resolver};
+use crate::search;
+use crate::watcher;
+
+use std::{
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+use tonic::{Request, Response, Status, transport::Server};
+
+pub mod proto {
+    tonic::include_proto!("obsidian_rs.v1");
+}
+
+use proto::vault_server::{Vault, VaultServer};
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+struct VaultService {
+    vault_path: PathBuf,
+    config: AppConfig,
+    /// Notes kept between calls, so each call only reads the ones that changed
+    notes: Arc<Mutex<NoteCache>>,
+}
+
+impl VaultService {
+    /// Runs `work` off the async workers on the vault's notes, brought up to date first.
+    async fn with_notes<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&[Note]) -> Result<T, Status> + Send + 'static,
+    ) -> Result<T, Status> {
+        let (vault_path, notes) = (self.vault_path.clone(), Arc::clone(&self.notes));
+        blocking(move || {
+            let mut notes = notes.lock().map_err(internal)?;
+            work(notes.refresh(&vault_path).map_err(internal)?)
+        })
+        .await
+    }
+}
+
+fn key(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn internal(e: impl std::fmt::Display) -> Status {
+    Status::internal(e.to_string())
+}
+
+/// Runs blocking vault work off the async workers.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, Status> + Send + 'static,
+) -> Result<T, Status> {
+    tokio::task::spawn_blocking(work).await.map_err(internal)?
+}
+
+fn stream_of<T: Send + 'static>(items: Vec<T>) -> ResponseStream<T> {
+    Box::pin(tokio_stream::iter(items.into_iter().map(Ok)))
+}
+
+fn resolver_of(notes: &[Note]) -> Resolver {
+    Resolver::new(notes.iter().map(|note| note.path.clone()).collect())
+}
+
+/// The note `note` (a path or link text) resolves to.
+fn resolve<'a>(notes: &'a [Note], resolver: &Resolver, note: &str) -> Result<&'a Note, Status> {
+    resolver
+        .resolve(note, Path::new(""))
+        .and_then(|path| notes.iter().find(|note| note.path == path))
+        .ok_or_else(|| Status::not_found(format!("No note '{}'", note)))
+}
+
+#[tonic::async_trait]
+impl Vault for VaultService {
+    async fn list_notes(
+        &self,
+        request: Request<proto::ListNotesRequest>,
+    ) -> Result<Response<proto::ListNotesResponse>, Status> {
+        let request = request.into_inner();
+        let query =
+            Query::parse(&request.query).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let notes = self
+            .with_notes(move |notes| {
+                let limit = match request.limit {
+                    0 => usize::MAX,
+                    limit => limit as usize,
+                };
+                Ok(query
+                    .filter(notes)
+                    .into_iter()
+                    .take(limit)
+                    .map(|note| proto::NoteSummary {
+                        path: key(&note.path),
+                        title: note.title(),
+                        tags: note.tags.clone(),
+                    })
+                    .collect())
+            })
+            .await?;
+        Ok(Response::new(proto::ListNotesResponse { notes }))
+    }
+
+    async fn get_note(
+        &self,
+        request: Request<proto::GetNoteRequest>,
+    ) -> Result<Response<proto::Note>, Status> {
+        let note = request.into_inner().note;
+        let note = self
+            .with_notes(move |notes| {
+                let note = resolve(notes, &resolver_of(notes), &note)?;
+                let frontmatter_json = frontmatter::parse_mapping(&note.content)
+                    .ok()
+                    .flatten()
+                    .and_then(|mapping| serde_json::to_string(&mapping).ok())
+                    .unwrap_or_default();
+                Ok(proto::Note {
+                    path: key(&note.path),
+                    title: note.title(),
+                    tags: note.tags.clone(),
+                    content: note.content.clone(),
+                    frontmatter_json,
+                })
+            })
+            .await?;
+        Ok(Response::new(note))
+    }
+
+    type SearchStream = ResponseStream<proto::SearchHit>;
+
+    async fn search(
+        &self,
+        request: Request<proto::SearchRequest>,
+    ) -> Result<Response<Self::SearchStream>, Status> {
+        let request = request.into_inner();
+        let mode = match request.mode() {
+            proto::SearchMode::Default => None,
+            proto::SearchMode::Keyword => Some(SearchMode::Keyword),
+            proto::SearchMode::Semantic => Some(SearchMode::Semantic),
+            proto::SearchMode::Hybrid => Some(SearchMode::Hybrid),
+        };
+        let limit = match request.limit {
+            0 => 20,
+            limit => limit as usize,
+        };
+        let (vault_path, config) = (self.vault_path.clone(), self.config.clone());
+        let hits = blocking(move || {
+            let snippets = config.search.snippet_options();
+            search::search(&vault_path, &config, &request.query, mode, limit, snippets)
+                .map_err(internal)
+        })
+        .await?;
+        let hits = hits
+            .into_iter()
+            .map(|hit| proto::SearchHit {
+                path: hit.path,
+                heading: hit.heading,
+                line: hit.line as u32,
+                score: hit.score,
+                snippet: hit.snippet,
+            })
+            .collect();
+        Ok(Response::new(stream_of(hits)))
+    }
+
+    type BacklinksStream = ResponseStream<proto::Backlink>;
+
+    async fn backlinks(
+        &self,
+        request: Request<proto::BacklinksRequest>,
+    ) -> Result<Response<Self::BacklinksStream>, Status> {
+        let note = request.into_inner().note;
+        let backlinks = self
+            .with_notes(move |notes| {
+                let resolver = resolver_of(notes);
+                let target = &resolve(notes, &resolver, &note)?.path;
+                Ok(resolver::backlinks(notes, &resolver, target)
+                    .into_iter()
+                    .map(|backlink| proto::Backlink {
+                        path: key(&backlink.path),
+                        line: backlink.line as u32,
+                        context: backlink.context,
+                    })
+                    .collect())
+            })
+            .await?;
+        Ok(Response::new(stream_of(backlinks)))
+    }
+
+    async fn tags(
+        &self,
+        _request: Request<proto::TagsRequest>,
+    ) -> Result<Response<proto::TagsResponse>, Status> {
+        let tags = self
+            .with_notes(|notes| {
+                Ok(listing::tag_counts(notes)
+                    .into_iter()
+                    .map(|entry| proto::Tag {
+                        name: entry.tag,
+                        count: entry.count as u32,
+                    })
+                    .collect())
+            })
+            .await?;
+        Ok(Response::new(proto::TagsResponse { tags }))
+    }
+
+    async fn refresh_index(
+        &self,
+        _request: Request<proto::RefreshIndexRequest>,
+    ) -> Result<Response<proto::IndexChanges>, Status> {
+        let (vault_path, config) = (self.vault_path.clone(), self.config.clone());
+        let changes = blocking(move || {
+            let embed = config.embeddings.backend.is_some();
+            index::open(&config)
+                .and_then(|connection| {
+                    search::refresh_index(&connection, &vault_path, &config, embed)
+                })
+                .map_err(internal)
+        })
+        .await?;
+        Ok(Response::new(proto::IndexChanges {
+            added: changes.added,
+            modified: changes.modified,
+            removed: changes.removed,
+            renamed: changes
+                .renamed
+                .into_iter()
+                .map(|(from, to)| proto::Rename { from, to })
+                .collect(),
+        }))
+    }
+
+    type WatchStream = ResponseStream<proto::VaultEvent>;
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let quiet = match request.into_inner().quiet_ms {
+            0 => Duration::from_millis(500),
+            quiet => Duration::from_millis(quiet.into()),
+        };
+        let (sender, receiver) = mpsc::channel(64);
+        let vault_path = self.vault_path.clone();
+        thread::spawn(move || {
+            let watched = watcher::watch_while(&vault_path, quiet, |paths| {
+                // The client went away while the vault was quiet
+                if sender.is_closed() {
+                    return ControlFlow::Break(());
+                }
+                for path in paths {
+                    let Ok(relative) = path.strip_prefix(&vault_path) else {
+                        continue;
+                    };
+                    let event = proto::VaultEvent {
+                        path: key(relative),
+                        removed: !path.exists(),
+                    };
+                    // The client went away
+                    if sender.blocking_send(Ok(event)).is_err() {
+                        return ControlFlow::Break(());
+                    }
+                }
+                ControlFlow::Continue(())
+            });
+            if let Err(e) = watched {
+                let _ = sender.blocking_send(Err(internal(e)));
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+}
+
+/// Rejects calls without one of `keys`; every call only reads, so any scope will do.
+fn check_key(keys: &Keys, request: Request<()>) -> Result<Request<()>, Status> {
+    if keys.is_empty() {
+        return Ok(request);
+    }
+    let presented = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented.and_then(|key| keys.scope_of(key.trim())) {
+        Some(_) => Ok(request),
+        None => Err(Status::unauthenticated("Missing or unknown API key")),
+    }
+}
+
+/// Serves the gRPC API on `listen` on a background thread with its own runtime.
+pub fn spawn_server(
+    listen: String,
+    keys: Arc<Keys>,
+    vault_path: PathBuf,
+    config: AppConfig,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        if !http::is_loopback(&listen) {
+            return log::error!(
+                "Not serving gRPC on {}: without TLS only loopback addresses are served",
+                listen
+            );
+        }
+        let serve = async {
+            let address = listen.parse()?;
+            let service = VaultService {
+                vault_path,
+                config,
+                notes: Arc::default(),
+            };
+            let service =
+                VaultServer::with_interceptor(service, move |request| check_key(&keys, request));
+            log::info!("gRPC API listening on {}", listen);
+            Server::builder()
+                .add_service(service)
+                .serve(address)
+                .await?;
+            Ok::<(), Box<dyn std::error::Error>>(())
+        };
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => return log::error!("Cannot start the gRPC runtime: {}", e),
+        };
+        if let Err(e) = runtime.block_on(serve) {
+            log::error!("gRPC API on {} stopped: {}", listen, e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKeyConfig, Scope, ServerConfig};
+    use tokio_stream::StreamExt;
+
+    #[test]
+    fn test_notes_and_backlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        // Not the temporary folder itself, whose dot name the vault walk skips
+        let vault = dir.path().join("vault");
+        std::fs::create_dir(&vault).unwrap();
+        std::fs::write(vault.join("Plan.md"), "See [[Goals]] #work\n").unwrap();
+        std::fs::write(vault.join("Goals.md"), "# Goals\n").unwrap();
+        let service = VaultService {
+            vault_path: vault,
+            config: AppConfig::default(),
+            notes: Arc::default(),
+        };
+        let note = |note: &str| {
+            Request::new(proto::GetNoteRequest {
+                note: note.to_string(),
+            })
+        };
+        let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+        runtime.block_on(async {
+            let found = service.get_note(note("Goals")).await.unwrap().into_inner();
+            assert_eq!(found.path, "Goals.md");
+            let missing = service.get_note(note("Nope")).await.unwrap_err();
+            assert_eq!(missing.code(), tonic::Code::NotFound);
+
+            let request = Request::new(proto::ListNotesRequest {
+                query: String::from("tag:work"),
+                limit: 0,
+            });
+            let listed = service.list_notes(request).await.unwrap().into_inner();
+            assert_eq!(listed.notes.len(), 1);
+
+            let request = Request::new(proto::BacklinksRequest {
+                note: String::from("Goals"),
+            });
+            let backlinks: Vec<_> = service
+                .backlinks(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .collect()
+                .await;
+            assert_eq!(backlinks.len(), 1);
+            assert_eq!(backlinks[0].as_ref().unwrap().path, "Plan.md");
+        });
+    }
+
+    #[test]
+    fn test_check_key() {
+        unsafe { std::env::set_var("OBSIDIAN_RS_TEST_GRPC_KEY", "secret") };
+        let keys = Keys::load(&ServerConfig {
+            keys: vec![ApiKeyConfig {
+                env: String::from("OBSIDIAN_RS_TEST_GRPC_KEY"),
+                scope: Scope::Read,
+            }],
+            ..ServerConfig::default()
+        })
+        .unwrap();
+        let with_key = |value: &str| {
+            let mut request = Request::new(());
+            request
+                .metadata_mut()
+                .insert("authorization", value.parse().unwrap());
+            request
+        };
+        assert!(check_key(&keys, with_key("Bearer secret")).is_ok());
+        let refused = check_key(&keys, with_key("Bearer guess")).unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::Unauthenticated);
+        assert!(check_key(&keys, Request::new(())).is_err());
+        assert!(check_key(&Keys::default(), Request::new(())).is_ok());
+    }
+}
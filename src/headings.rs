@@ -0,0 +1,235 @@
+//! Heading outline fixes: renumbering `1.`, `1.2`, `2.1.3)` style numbered headings after
+//! sections were added, moved or removed, and optionally closing skipped heading levels.
+
+use crate::changeset::ChangeSet;
+use crate::cli::RenumberHeadingsArgs;
+use crate::data;
+use crate::lint;
+use crate::markdown::{self, LinkStyle};
+use crate::resolver::Resolver;
+use crate::util;
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// The number a heading text starts with, e.g. "1.2" for "1.2. Scope" or "3" for
+/// "3) Results". A bare number needs a `.` or `)` so "2024 plans" is not numbered.
+fn heading_number(text: &str) -> Option<&str> {
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (token, rest) = text.split_at(end);
+    let (number, marked, rest) = match (token.strip_suffix('.'), rest.strip_prefix(')')) {
+        (Some(number), _) => (number, true, rest),
+        (None, Some(rest)) => (token, true, rest),
+        (None, None) => (token, token.contains('.'), rest),
+    };
+    let valid = number
+        .split('.')
+        .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    (valid && marked && (rest.is_empty() || rest.starts_with(' '))).then_some(number)
+}
+
+/// New numbers for the numbered headings of `content` counted up in document order, by
+/// line, as (old number, new number). A heading's depth is how many numbered headings
+/// above it it sits under, so an H4 right below an H2 is numbered "1.1", not "1.0.1".
+fn new_numbers(content: &str) -> HashMap<usize, (String, String)> {
+    let headings = markdown::parse_headings(content);
+    let mut parents: Vec<usize> = Vec::new();
+    let mut counters: Vec<usize> = Vec::new();
+    let mut new_numbers = HashMap::new();
+    for heading in &headings {
+        let Some(number) = heading_number(&heading.text) else {
+            continue;
+        };
+        while parents.last().is_some_and(|level| *level >= heading.level) {
+            parents.pop();
+        }
+        let depth = parents.len();
+        parents.push(heading.level);
+        counters.resize(depth + 1, 0);
+        counters[depth] += 1;
+        let renumbered = counters
+            .iter()
+            .map(usize::to_string)
+            .collect::<Vec<_>>()
+            .join(".");
+        if renumbered != number {
+            new_numbers.insert(heading.line, (number.to_string(), renumbered));
+        }
+    }
+    new_numbers
+}
+
+/// `content` with its numbered headings counted up again in document order. The
+/// shallowest numbered level gets single numbers, each level below one more part.
+pub fn renumber(content: &str) -> String {
+    let new_numbers = new_numbers(content);
+    content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| match new_numbers.get(&(index + 1)) {
+            Some((old, new)) => {
+                let hashes = line.find('#').unwrap_or(0);
+                let start = hashes + line[hashes..].find(old).unwrap_or(0);
+                format!("{}{}{}", &line[..start], new, &line[start + old.len()..])
+            }
+            None => line.to_string(),
+        })
+        .collect()
+}
+
+/// Headings whose text differs between `original` and `fixed`, as (slug of the old
+/// text, new text). Fixing only rewrites heading lines in place, so they pair up in order.
+fn renamed_headings(original: &str, fixed: &str) -> Vec<(String, String)> {
+    markdown::parse_headings(original)
+        .into_iter()
+        .zip(markdown::parse_headings(fixed))
+        .filter(|(old, new)| old.text != new.text)
+        .map(|(old, new)| (markdown::heading_slug(&old.text), new.text))
+        .collect()
+}
+
+/// `content` with its links to the `renamed` headings of each note pointing at the new
+/// heading text, in the form the link used: a slug, percent-encoded or as written.
+fn retarget_links(
+    content: &str,
+    source: &Path,
+    resolver: &Resolver,
+    renamed: &HashMap<PathBuf, Vec<(String, String)>>,
+) -> String {
+    let mut edits = Vec::new();
+    for link in markdown::parse_links(content) {
+        let Some(anchor) = &link.anchor else {
+            continue;
+        };
+        let Some(headings) = resolver
+            .resolve(&link.target, source)
+            .and_then(|target| renamed.get(target))
+        else {
+            continue;
+        };
+        let slug = markdown::heading_slug(&util::percent_decode(anchor));
+        let Some((_, text)) = headings.iter().find(|(old, _)| *old == slug) else {
+            continue;
+        };
+        let new_anchor = if *anchor == slug {
+            markdown::heading_slug(text)
+        } else if link.style == LinkStyle::Markdown {
+            util::percent_encode(text)
+        } else {
+            text.clone()
+        };
+        let written = &content[link.span.clone()];
+        if let Some(at) = written.find(&format!("#{}", anchor)) {
+            let start = link.span.start + at + 1;
+            edits.push((start..start + anchor.len(), new_anchor));
+        }
+    }
+    markdown::replace_spans(content, edits)
+}
+
+pub fn run_renumber_headings(
+    vault_path: &Path,
+    args: &RenumberHeadingsArgs,
+) -> Result<(), Box<dyn Error>> {
+    // Every note is read, as any of them may link to a renumbered heading
+    let notes = data::load_notes(vault_path)?;
+    let resolver = Resolver::from_vault(vault_path)?;
+    let selected = match &args.note {
+        Some(name) => Some(
+            resolver
+                .resolve(name, Path::new(""))
+                .ok_or_else(|| format!("No note matches '{}'", name))?,
+        ),
+        None => None,
+    };
+
+    let mut fixed_notes = HashMap::new();
+    let mut renamed = HashMap::new();
+    for note in &notes {
+        if selected.is_some_and(|path| path != note.path.as_path()) {
+            continue;
+        }
+        let mut fixed = match args.fix_levels {
+            true => lint::normalize_heading_levels(&note.content),
+            false => note.content.clone(),
+        };
+        fixed = renumber(&fixed);
+        let headings = renamed_headings(&note.content, &fixed);
+        if !headings.is_empty() {
+            renamed.insert(note.path.clone(), headings);
+        }
+        fixed_notes.insert(&note.path, fixed);
+    }
+
+    let mut changes = ChangeSet::new();
+    for note in &notes {
+        let content = fixed_notes.get(&note.path).unwrap_or(&note.content);
+        let fixed = retarget_links(content, &note.path, &resolver, &renamed);
+        changes.propose(note.path.clone(), Some(note.content.clone()), fixed);
+    }
+    changes.finish(vault_path, &args.changes, "renumber headings", false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renumber() {
+        assert_eq!(heading_number("1.2. Scope"), Some("1.2"));
+        assert_eq!(heading_number("1.2 Scope"), Some("1.2"));
+        assert_eq!(heading_number("3) Results"), Some("3"));
+        assert_eq!(heading_number("2024 plans"), None);
+        assert_eq!(heading_number("1.5x speedup"), None);
+        assert_eq!(heading_number("Intro"), None);
+
+        let content = "# Title\n## 1. Intro\n### 1.1 Scope\n## 3. Method\n\
+                       ### 3.4 Data\n### 3.1 Tools\n## Appendix\n## 7. End\n";
+        assert_eq!(
+            renumber(content),
+            "# Title\n## 1. Intro\n### 1.1 Scope\n## 2. Method\n\
+             ### 2.1 Data\n### 2.2 Tools\n## Appendix\n## 3. End\n"
+        );
+
+        let skipped = lint::normalize_heading_levels("## 1. A\n#### 1.1.1 B\n");
+        assert_eq!(renumber(&skipped), "## 1. A\n### 1.1 B\n");
+    }
+
+    #[test]
+    fn test_renumber_skipped_levels() {
+        assert_eq!(renumber("## 1. A\n#### 1.0.1 B\n"), "## 1. A\n#### 1.1 B\n");
+        assert_eq!(
+            renumber("## 1. A\n#### 1.1 B\n### 1.2 C\n## 2. D\n"),
+            "## 1. A\n#### 1.1 B\n### 1.2 C\n## 2. D\n"
+        );
+    }
+
+    #[test]
+    fn test_retarget_links() {
+        let original = "# 1. Intro\n# 3. Method\n";
+        let fixed = renumber(original);
+        let renamed = HashMap::from([(
+            PathBuf::from("Paper.md"),
+            renamed_headings(original, &fixed),
+        )]);
+        let resolver = Resolver::new(vec![PathBuf::from("Paper.md"), PathBuf::from("Notes.md")]);
+        let content = "See [[Paper#3. Method|how]], [m](Paper.md#3.%20Method), \
+                       [s](Paper.md#3-method), [[Paper#1. Intro]] and [[#3. Method]]\n";
+        assert_eq!(
+            retarget_links(content, Path::new("Notes.md"), &resolver, &renamed),
+            "See [[Paper#2. Method|how]], [m](Paper.md#2.%20Method), \
+             [s](Paper.md#2-method), [[Paper#1. Intro]] and [[#3. Method]]\n"
+        );
+        let own = format!("{}See [[#3. Method]]\n", fixed);
+        assert_eq!(
+            retarget_links(&own, Path::new("Paper.md"), &resolver, &renamed),
+            format!("{}See [[#2. Method]]\n", fixed)
+        );
+    }
+}
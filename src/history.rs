@@ -0,0 +1,323 @@
+//! Undo log. Every applied change set is saved as a JSON file in the data directory's
+//! `history` folder, so `undo` can put the notes back. The contents before and after each
+//! edit are kept once each in `history/blobs`, named by their SHA-256, so a note edited
+//! over and over shares the versions its entries have in common.
+
+use crate::changeset::Edit;
+use crate::cli::{HistoryArgs, OutputFormat, UndoArgs};
+use crate::config::AppConfig;
+use crate::data;
+use crate::trash;
+use crate::util;
+use crate::write_gate;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+static HISTORY_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Older entries are dropped once there are more than this
+const MAX_ENTRIES: usize = 100;
+
+/// An edit as recorded, with the contents as names of blobs
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RecordedEdit {
+    pub path: PathBuf,
+    /// Contents the edit replaced; `None` when it created the file
+    pub before_blob: Option<String>,
+    pub after_blob: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Entry {
+    pub id: String,
+    pub time: String,
+    /// What the change was, e.g. "fix notes"
+    pub action: String,
+    pub edits: Vec<RecordedEdit>,
+}
+
+/// Records applied change sets in the vault's data directory for the rest of the process.
+pub fn install(config: &AppConfig) {
+    match data::get_data_path(config) {
+        Ok(data_path) => {
+            let _ = HISTORY_DIR.set(data_path.join("history"));
+        }
+        Err(e) => log::warn!("Changes will not be recorded for undo: {}", e),
+    }
+}
+
+fn installed() -> Result<&'static Path, Box<dyn Error>> {
+    HISTORY_DIR
+        .get()
+        .map(PathBuf::as_path)
+        .ok_or_else(|| "No history directory is available".into())
+}
+
+/// Saves applied `edits`; a no-op unless `install` was called.
+pub fn record(action: &str, edits: &[Edit]) -> Result<(), Box<dyn Error>> {
+    match HISTORY_DIR.get() {
+        Some(dir) => record_in(dir, action, edits).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+fn blob_name(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Stores `content` in the blobs folder unless it is there already, returning its name.
+fn store_blob(dir: &Path, content: &str) -> Result<String, Box<dyn Error>> {
+    let name = blob_name(content);
+    let file = dir.join("blobs").join(&name);
+    if !file.exists() {
+        util::safe_write(&file, content, false)?;
+    }
+    Ok(name)
+}
+
+fn read_blob(dir: &Path, name: &str) -> Result<String, Box<dyn Error>> {
+    fs::read_to_string(dir.join("blobs").join(name))
+        .map_err(|e| format!("History blob {} is unreadable: {}", name, e).into())
+}
+
+/// Deletes the blobs no remaining entry refers to.
+fn remove_unused_blobs(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let blobs = dir.join("blobs");
+    if !blobs.is_dir() {
+        return Ok(());
+    }
+    let entries = entries(dir)?;
+    let used: HashSet<&str> = entries
+        .iter()
+        .flat_map(|entry| &entry.edits)
+        .flat_map(|edit| [edit.before_blob.as_deref(), Some(edit.after_blob.as_str())])
+        .flatten()
+        .collect();
+    for file in fs::read_dir(&blobs)? {
+        let path = file?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        if !used.contains(name) {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn record_in(dir: &Path, action: &str, edits: &[Edit]) -> Result<Entry, Box<dyn Error>> {
+    fs::create_dir_all(dir.join("blobs"))?;
+    let now = Timestamp::now();
+    let mut id = now.as_millisecond();
+    while dir.join(format!("{}.json", id)).exists() {
+        id += 1;
+    }
+    let entry = Entry {
+        id: id.to_string(),
+        time: now.to_string(),
+        action: action.to_string(),
+        edits: edits
+            .iter()
+            .map(|edit| {
+                Ok(RecordedEdit {
+                    path: edit.path.clone(),
+                    before_blob: match &edit.before {
+                        Some(before) => Some(store_blob(dir, before)?),
+                        None => None,
+                    },
+                    after_blob: store_blob(dir, &edit.after)?,
+                })
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?,
+    };
+    util::safe_write(
+        &dir.join(format!("{}.json", entry.id)),
+        serde_json::to_string(&entry)?,
+        false,
+    )?;
+
+    let entries = entries(dir)?;
+    for old in &entries[..entries.len().saturating_sub(MAX_ENTRIES)] {
+        fs::remove_file(dir.join(format!("{}.json", old.id)))?;
+    }
+    remove_unused_blobs(dir)?;
+    Ok(entry)
+}
+
+/// Recorded change sets, oldest first.
+pub fn entries(dir: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            match serde_json::from_str::<Entry>(&fs::read_to_string(&path)?) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => log::warn!("Skipping history entry {}: {}", path.display(), e),
+            }
+        }
+    }
+    entries.sort_by_key(|entry| entry.id.parse::<i64>().unwrap_or_default());
+    Ok(entries)
+}
+
+/// Reverts the change set `id` (the latest one by default) and forgets it. Files changed
+/// since are left alone unless `force`; files the change created go to the trash.
+fn undo_in(
+    dir: &Path,
+    vault_path: &Path,
+    id: Option<&str>,
+    force: bool,
+) -> Result<Entry, Box<dyn Error>> {
+    let entries = entries(dir)?;
+    let entry = match id {
+        Some(id) => entries.into_iter().find(|entry| entry.id == id),
+        None => entries.into_iter().last(),
+    }
+    .ok_or("Nothing to undo")?;
+
+    let changed_since: Vec<String> = entry
+        .edits
+        .iter()
+        .filter(|edit| {
+            fs::read_to_string(vault_path.join(&edit.path))
+                .ok()
+                .map(|content| blob_name(&content))
+                .as_ref()
+                != Some(&edit.after_blob)
+        })
+        .map(|edit| edit.path.display().to_string())
+        .collect();
+    if !changed_since.is_empty() && !force {
+        return Err(format!(
+            "Changed since: {}; use --force to undo anyway",
+            changed_since.join(", ")
+        )
+        .into());
+    }
+
+    write_gate::check("undo changes")?;
+    for edit in &entry.edits {
+        match &edit.before_blob {
+            Some(before) => {
+                let before = read_blob(dir, before)?;
+                util::safe_write(&vault_path.join(&edit.path), before, false)?;
+            }
+            None if vault_path.join(&edit.path).exists() => {
+                trash::move_to_trash(vault_path, &edit.path)?;
+            }
+            None => {}
+        }
+    }
+    fs::remove_file(dir.join(format!("{}.json", entry.id)))?;
+    remove_unused_blobs(dir)?;
+    Ok(entry)
+}
+
+pub fn run_history(args: &HistoryArgs) -> Result<(), Box<dyn Error>> {
+    let entries = entries(installed()?)?;
+    match args.format {
+        OutputFormat::Text => {
+            for entry in entries.iter().rev() {
+                println!(
+                    "{}  {}  {} ({} file(s))",
+                    entry.id,
+                    entry.time,
+                    entry.action,
+                    entry.edits.len()
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        OutputFormat::Ndjson => {
+            for entry in &entries {
+                util::print_ndjson(entry)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn run_undo(vault_path: &Path, args: &UndoArgs) -> Result<(), Box<dyn Error>> {
+    let entry = undo_in(installed()?, vault_path, args.id.as_deref(), args.force)?;
+    log::info!(
+        "Undid '{}' from {} ({} file(s))",
+        entry.action,
+        entry.time,
+        entry.edits.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_undo() {
+        let vault = tempfile::tempdir().unwrap();
+        let history = tempfile::tempdir().unwrap();
+        let root = vault.path();
+        fs::write(root.join("A.md"), "new").unwrap();
+        fs::write(root.join("B.md"), "created").unwrap();
+        let edits = vec![
+            Edit {
+                path: PathBuf::from("A.md"),
+                before: Some("old".into()),
+                after: "new".into(),
+            },
+            Edit {
+                path: PathBuf::from("B.md"),
+                before: None,
+                after: "created".into(),
+            },
+        ];
+        record_in(history.path(), "fix notes", &edits).unwrap();
+
+        fs::write(root.join("A.md"), "edited by hand").unwrap();
+        assert!(undo_in(history.path(), root, None, false).is_err());
+        fs::write(root.join("A.md"), "new").unwrap();
+
+        let entry = undo_in(history.path(), root, None, false).unwrap();
+        assert_eq!(entry.action, "fix notes");
+        assert_eq!(fs::read_to_string(root.join("A.md")).unwrap(), "old");
+        assert!(!root.join("B.md").exists());
+        assert!(entries(history.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_versions_are_stored_once() {
+        let vault = tempfile::tempdir().unwrap();
+        let history = tempfile::tempdir().unwrap();
+        let root = vault.path();
+        let edit = |before: &str, after: &str| Edit {
+            path: PathBuf::from("A.md"),
+            before: Some(before.into()),
+            after: after.into(),
+        };
+        let blobs = || fs::read_dir(history.path().join("blobs")).unwrap().count();
+        record_in(history.path(), "fix notes", &[edit("one", "two")]).unwrap();
+        record_in(history.path(), "fix notes", &[edit("two", "three")]).unwrap();
+        assert_eq!(blobs(), 3);
+
+        fs::write(root.join("A.md"), "three").unwrap();
+        undo_in(history.path(), root, None, false).unwrap();
+        assert_eq!(fs::read_to_string(root.join("A.md")).unwrap(), "two");
+        assert_eq!(blobs(), 2);
+    }
+}
@@ -0,0 +1,374 @@
+use crate::config::{AppConfig, HookEvent, HookRule};
+use crate::data::{self, Note};
+use crate::index;
+use crate::moc;
+use crate::notify::{self, ConflictAlerts};
+use crate::query::Query;
+use crate::query_cache;
+use crate::query_table;
+use crate::recency;
+use crate::search;
+use crate::util;
+use crate::watcher;
+
+use jiff::Timestamp;
+#[cfg(feature = "web")]
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    thread,
+    time::Duration,
+};
+
+/// Content hashes of the vault's notes, to tell what a burst of file events did.
+#[derive(Debug, Default)]
+pub struct VaultState {
+    hashes: HashMap<PathBuf, String>,
+}
+
+impl VaultState {
+    pub fn scan(vault_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let hashes = data::load_notes(vault_path)?
+            .into_iter()
+            .map(|note| (note.path, util::content_hash(note.content.as_bytes())))
+            .collect();
+        Ok(VaultState { hashes })
+    }
+
+    /// Classifies the notes among `paths` as created, modified or deleted, updating the
+    /// known hashes. Touched files whose content did not change yield nothing.
+    pub fn classify(&mut self, vault_path: &Path, paths: &[PathBuf]) -> Vec<(HookEvent, PathBuf)> {
+        let mut events = Vec::new();
+        for path in paths {
+            if !data::is_note(path) || util::is_hidden_path(vault_path, path) {
+                continue;
+            }
+            let Ok(rel_path) = util::get_relative_path(path, vault_path) else {
+                continue;
+            };
+            let known = self.hashes.contains_key(&rel_path);
+            match fs::read(path) {
+                Ok(content) => {
+                    let hash = util::content_hash(&content);
+                    match self.hashes.insert(rel_path.clone(), hash.clone()) {
+                        None => events.push((HookEvent::Created, rel_path)),
+                        Some(old) if old != hash => events.push((HookEvent::Modified, rel_path)),
+                        Some(_) => {}
+                    }
+                }
+                Err(_) if known => {
+                    self.hashes.remove(&rel_path);
+                    events.push((HookEvent::Deleted, rel_path));
+                }
+                Err(_) => {}
+            }
+        }
+        events
+    }
+}
+
+/// Template variables of an event; `note` is absent for deleted notes.
+pub fn variables(
+    event: HookEvent,
+    rel_path: &Path,
+    note: Option<&Note>,
+) -> Vec<(&'static str, String)> {
+    let title = match note {
+        Some(note) => note.title(),
+        None => rel_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    vec![
+        ("event", event_name(event).to_string()),
+        ("path", rel_path.to_string_lossy().replace('\\', "/")),
+        ("title", title),
+        (
+            "tags",
+            note.map(|note| note.tags.join(",")).unwrap_or_default(),
+        ),
+    ]
+}
+
+fn event_name(event: HookEvent) -> &'static str {
+    match event {
+        HookEvent::Created => "created",
+        HookEvent::Modified => "modified",
+        HookEvent::Deleted => "deleted",
+        HookEvent::Indexed => "indexed",
+    }
+}
+
+/// Whether `rule` fires for `event` on `note`. Rules with a query never fire for deleted
+/// notes, whose content is gone.
+pub fn matches(rule: &HookRule, event: HookEvent, note: Option<&Note>) -> bool {
+    if rule.event != event {
+        return false;
+    }
+    let Some(query) = &rule.query else {
+        return true;
+    };
+    match (Query::parse(query), note) {
+        (Ok(query), Some(note)) => query.matches(note),
+        (Err(e), _) => {
+            log::warn!("Ignoring hook with invalid query '{}': {}", query, e);
+            false
+        }
+        (Ok(_), None) => false,
+    }
+}
+
+fn env_name(name: &str) -> String {
+    format!("OBSIDIAN_{}", name.to_uppercase())
+}
+
+/// A quoted reference to the environment variable holding `name`, which the shell expands
+/// after parsing the command, so no value is ever read as shell syntax.
+fn env_reference(name: &str) -> String {
+    if cfg!(windows) {
+        format!("\"!{}!\"", env_name(name))
+    } else {
+        format!("\"${}\"", env_name(name))
+    }
+}
+
+/// Runs `command` through the shell with the variables set as `OBSIDIAN_<NAME>`
+/// environment variables and each `{{name}}` replaced by a quoted reference to one.
+pub fn run_command(
+    command: &str,
+    vault_path: &Path,
+    variables: &[(&str, String)],
+) -> Result<ExitStatus, Box<dyn Error>> {
+    Ok(shell_command(command, vault_path, variables).status()?)
+}
+
+/// The process [`run_command`] runs, for callers that want its output.
+pub fn shell_command(command: &str, vault_path: &Path, variables: &[(&str, String)]) -> Command {
+    let references: Vec<(&str, String)> = variables
+        .iter()
+        .map(|(name, _)| (*name, env_reference(name)))
+        .collect();
+    let rendered = util::render_template(command, &references);
+    let mut process = if cfg!(windows) {
+        // Delayed expansion (`!NAME!`) happens after cmd has parsed the line
+        let mut process = Command::new("cmd");
+        process.args(["/V:ON", "/C"]);
+        process
+    } else {
+        let mut process = Command::new("sh");
+        process.arg("-c");
+        process
+    };
+    process.arg(&rendered).current_dir(vault_path);
+    for (name, value) in variables {
+        process.env(env_name(name), value);
+    }
+    process
+}
+
+#[cfg(feature = "web")]
+fn post_webhook(url: &str, variables: &[(&str, String)]) -> Result<(), Box<dyn Error>> {
+    let body: serde_json::Map<String, serde_json::Value> = variables
+        .iter()
+        .map(|(name, value)| (name.to_string(), json!(value)))
+        .collect();
+    ureq::post(url)
+        .timeout(Duration::from_secs(30))
+        .send_json(body)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "web"))]
+fn post_webhook(_url: &str, _variables: &[(&str, String)]) -> Result<(), Box<dyn Error>> {
+    Err("obsidian-rs was built without the `web` feature".into())
+}
+
+/// Runs the rule's command and webhook on a background thread so slow hooks do not hold
+/// up the watcher.
+fn fire(rule: &HookRule, vault_path: &Path, variables: Vec<(&'static str, String)>) {
+    let rule = rule.clone();
+    let vault_path = vault_path.to_path_buf();
+    thread::spawn(move || {
+        if let Some(command) = &rule.command {
+            match run_command(command, &vault_path, &variables) {
+                Ok(status) if !status.success() => {
+                    log::warn!("Hook command '{}' exited with {}", command, status)
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("Hook command '{}' failed: {}", command, e),
+            }
+        }
+        if let Some(url) = &rule.webhook
+            && let Err(e) = post_webhook(url, &variables)
+        {
+            log::error!("Webhook {} failed: {}", url, e);
+        }
+    });
+}
+
+fn fire_matching(config: &AppConfig, vault_path: &Path, event: HookEvent, rel_path: &Path) {
+    let note = match event {
+        HookEvent::Deleted | HookEvent::Indexed => None,
+        _ => fs::read_to_string(vault_path.join(rel_path))
+            .ok()
+            .map(|content| Note::from_content(rel_path.to_path_buf(), content)),
+    };
+    for rule in &config.hooks.rules {
+        if matches(rule, event, note.as_ref()) {
+            log::info!("Running {} hook {}", event_name(event), rel_path.display());
+            fire(rule, vault_path, variables(event, rel_path, note.as_ref()));
+        }
+    }
+}
+
+/// Watches the vault, keeping the search index and query cache fresh and running the
+/// configured hooks on note changes and after each re-index.
+pub fn run_hooks(vault_path: &Path, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let mut state = VaultState::scan(vault_path)?;
+    let connection = index::open(config)?;
+    query_cache::ensure_schema(&connection)?;
+    recency::ensure_schema(&connection)?;
+    let embed = config.embeddings.backend.is_some();
+    let mut conflict_alerts = ConflictAlerts::default();
+    fire_matching(config, vault_path, HookEvent::Indexed, Path::new(""));
+
+    let quiet = Duration::from_millis(config.hooks.debounce_ms);
+    watcher::watch_debounced(vault_path, quiet, |paths| {
+        let events = state.classify(vault_path, &paths);
+        if events.is_empty() {
+            return;
+        }
+        let changed: Vec<String> = events
+            .iter()
+            .map(|(_, rel_path)| rel_path.to_string_lossy().replace('\\', "/"))
+            .collect();
+        if let Err(e) = query_cache::invalidate(&connection, &changed) {
+            log::error!("Error invalidating cached query results: {}", e);
+        }
+        let now = Timestamp::now().as_millisecond();
+        for ((event, _), path) in events.iter().zip(&changed) {
+            let recorded = match event {
+                HookEvent::Deleted => recency::forget(&connection, path),
+                _ => recency::record(&connection, path, recency::Visit::Edit, now),
+            };
+            if let Err(e) = recorded {
+                log::error!("Error recording a change to {}: {}", path, e);
+            }
+        }
+        for (event, rel_path) in &events {
+            fire_matching(config, vault_path, *event, rel_path);
+            if config.notify.conflicts
+                && let Some(alert) = conflict_alerts.check(vault_path, &vault_path.join(rel_path))
+            {
+                notify::send_in_background(&config.notify, alert);
+            }
+        }
+        moc::refresh_vault(vault_path);
+        query_table::refresh_vault(vault_path);
+        let refreshed = search::refresh_index(&connection, vault_path, config, embed)
+            .and_then(|_| search::refresh_attachments(&connection, vault_path, config));
+        match refreshed {
+            Ok(_) => fire_matching(config, vault_path, HookEvent::Indexed, Path::new("")),
+            Err(e) => log::error!("Error refreshing the search index: {}", e),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(event: HookEvent, query: Option<&str>) -> HookRule {
+        HookRule {
+            event,
+            query: query.map(String::from),
+            command: None,
+            webhook: None,
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        let root = vault.path();
+        fs::write(root.join("A.md"), "a").unwrap();
+        fs::write(root.join("B.md"), "b").unwrap();
+        let mut state = VaultState::scan(root).unwrap();
+
+        fs::write(root.join("A.md"), "changed").unwrap();
+        fs::remove_file(root.join("B.md")).unwrap();
+        fs::write(root.join("C.md"), "c").unwrap();
+        fs::create_dir(root.join(".obsidian")).unwrap();
+        fs::write(root.join(".obsidian/x.md"), "hidden").unwrap();
+        let paths: Vec<PathBuf> = ["A.md", "B.md", "C.md", ".obsidian/x.md", "D.md"]
+            .iter()
+            .map(|name| root.join(name))
+            .collect();
+        let events = state.classify(root, &paths);
+        assert_eq!(
+            events,
+            vec![
+                (HookEvent::Modified, PathBuf::from("A.md")),
+                (HookEvent::Deleted, PathBuf::from("B.md")),
+                (HookEvent::Created, PathBuf::from("C.md")),
+            ]
+        );
+        // Saving without changes is not a modification
+        assert!(state.classify(root, &paths[..1]).is_empty());
+    }
+
+    #[test]
+    fn test_matches() {
+        let note = Note::from_content(PathBuf::from("P.md"), String::from("#project"));
+        let tagged = rule(HookEvent::Modified, Some("tag:#project"));
+        assert!(matches(&tagged, HookEvent::Modified, Some(&note)));
+        assert!(!matches(&tagged, HookEvent::Created, Some(&note)));
+        assert!(!matches(&tagged, HookEvent::Deleted, None));
+        assert!(matches(
+            &rule(HookEvent::Deleted, None),
+            HookEvent::Deleted,
+            None
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_command_quotes_variables() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        let note = Note::from_content(
+            PathBuf::from("It's $HOME.md"),
+            String::from("---\ntags: [a, b]\n---\n"),
+        );
+        let vars = variables(HookEvent::Created, &note.path, Some(&note));
+        let command = "printf '%s|%s|%s' {{title}} {{tags}} \"$OBSIDIAN_EVENT\" > out.txt";
+        assert!(run_command(command, vault.path(), &vars).unwrap().success());
+        assert_eq!(
+            fs::read_to_string(vault.path().join("out.txt")).unwrap(),
+            "It's $HOME|a,b|created"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_command_with_hostile_title() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        let title = "x'; touch pwned; echo '{{path}} $(touch pwned2)";
+        let vars = vec![
+            ("title", String::from(title)),
+            ("path", String::from("a.md")),
+        ];
+        let command = "printf '%s' {{title}} > out.txt";
+        assert!(run_command(command, vault.path(), &vars).unwrap().success());
+        assert_eq!(
+            fs::read_to_string(vault.path().join("out.txt")).unwrap(),
+            title
+        );
+        assert!(!vault.path().join("pwned").exists());
+        assert!(!vault.path().join("pwned2").exists());
+    }
+}
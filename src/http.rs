@@ -0,0 +1,504 @@
+use crate::util;
+
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+static MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+/// How long a read or write on a connection may stall, TLS handshakes included
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// Connections served at once; more are turned away until one closes
+const MAX_CONNECTIONS: usize = 64;
+/// Bytes the request line and headers may take together
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+/// Header lines a request may have
+const MAX_HEADERS: usize = 100;
+/// How long a client may take to send the request line and headers
+const HEAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Percent-decoded path without the query string
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// Header names are lowercased
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    /// Address of the client, when served over TCP
+    pub remote: Option<IpAddr>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.header("content-type")
+            .is_some_and(|value| value.starts_with("application/json"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Response {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response {
+                status,
+                content_type: String::from("application/json"),
+                headers: Vec::new(),
+                body,
+            },
+            Err(e) => Response::error(500, &format!("Failed to serialize response: {}", e)),
+        }
+    }
+
+    /// JSON error body of the form `{"error": message}`
+    pub fn error(status: u16, message: &str) -> Response {
+        let body = serde_json::json!({ "error": message });
+        Response {
+            status,
+            content_type: String::from("application/json"),
+            headers: Vec::new(),
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    pub fn not_found() -> Response {
+        Response::error(404, "Not found")
+    }
+}
+
+/// Media type for a static file, by its extension.
+pub fn content_type_of(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "md" => "text/markdown; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ if status >= 500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                util::percent_decode(&key.replace('+', " ")),
+                util::percent_decode(&value.replace('+', " ")),
+            )
+        })
+        .collect()
+}
+
+/// A request head past `MAX_HEAD_BYTES` or `MAX_HEADERS`, answered with 431
+#[derive(Debug)]
+struct HeadTooLarge;
+
+impl fmt::Display for HeadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Request headers are too large")
+    }
+}
+
+impl Error for HeadTooLarge {}
+
+/// Reads from `inner` until `until` passes, so a client trickling bytes in under the
+/// socket timeout cannot hold a connection forever
+struct Deadline<R> {
+    inner: R,
+    until: Option<Instant>,
+}
+
+impl<R: Read> Read for Deadline<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.until.is_some_and(|until| Instant::now() >= until) {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Request headers took too long",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Reads a line of the request head into `line`, failing once the head runs past
+/// `MAX_HEAD_BYTES`.
+fn read_head_line<R: BufRead>(
+    head: &mut io::Take<R>,
+    line: &mut String,
+) -> Result<usize, Box<dyn Error>> {
+    let read = head.read_line(line)?;
+    if !line.ends_with('\n') && head.limit() == 0 {
+        return Err(HeadTooLarge.into());
+    }
+    Ok(read)
+}
+
+pub fn read_request(stream: &mut impl Read) -> Result<Request, Box<dyn Error>> {
+    let mut reader = BufReader::new(Deadline {
+        inner: stream,
+        until: Some(Instant::now() + HEAD_TIMEOUT),
+    });
+    let mut head = reader.by_ref().take(MAX_HEAD_BYTES);
+    let mut request_line = String::new();
+    read_head_line(&mut head, &mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or("Empty request")?.to_uppercase();
+    let target = parts.next().ok_or("Missing request target")?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = HashMap::new();
+    for count in 0.. {
+        let mut line = String::new();
+        if read_head_line(&mut head, &mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(HeadTooLarge.into());
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    // The body has its own cap and may take longer to arrive
+    reader.get_mut().until = None;
+
+    let length: usize = match headers.get("content-length") {
+        Some(value) => value.parse()?,
+        None => 0,
+    };
+    if length > MAX_BODY_BYTES {
+        return Err(format!("Request body of {} bytes is too large", length).into());
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        path: util::percent_decode(path),
+        query: parse_query(query),
+        headers,
+        body,
+        remote: None,
+    })
+}
+
+pub fn write_response(stream: &mut impl Write, response: &Response) -> Result<(), Box<dyn Error>> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Counts requests per client address in one-minute windows.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> RateLimiter {
+        RateLimiter {
+            per_minute,
+            windows: Mutex::default(),
+        }
+    }
+
+    /// Whether `client` may make another request at `now`.
+    pub fn allow(&self, client: IpAddr, now: Instant) -> bool {
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        windows.retain(|_, (start, _)| now.duration_since(*start) < Duration::from_secs(60));
+        let (_, count) = windows.entry(client).or_insert((now, 0));
+        *count += 1;
+        *count <= self.per_minute
+    }
+}
+
+/// How `serve` secures and throttles its connections
+#[derive(Debug, Clone, Default)]
+pub struct ServeOptions {
+    #[cfg(feature = "tls")]
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    pub rate_limit: Option<Arc<RateLimiter>>,
+}
+
+fn handle_connection<S, F>(
+    mut stream: S,
+    client: Option<IpAddr>,
+    options: &ServeOptions,
+    handler: &F,
+) where
+    S: Read + Write,
+    F: Fn(&Request) -> Response,
+{
+    let response = match read_request(&mut stream) {
+        Ok(_)
+            if options
+                .rate_limit
+                .as_ref()
+                .zip(client)
+                .is_some_and(|(limiter, client)| !limiter.allow(client, Instant::now())) =>
+        {
+            let mut response = Response::error(429, "Too many requests");
+            response
+                .headers
+                .push((String::from("Retry-After"), String::from("60")));
+            response
+        }
+        Ok(mut request) => {
+            request.remote = client;
+            log::debug!("{} {}", request.method, request.path);
+            handler(&request)
+        }
+        Err(e) if e.is::<HeadTooLarge>() => Response::error(431, &e.to_string()),
+        Err(e) => Response::error(400, &e.to_string()),
+    };
+    if let Err(e) = write_response(&mut stream, &response) {
+        log::warn!("Failed to write HTTP response: {}", e);
+    }
+}
+
+/// Hands a connection to `handle_connection`, over TLS when `options` asks for it.
+fn accept<F>(stream: TcpStream, options: &ServeOptions, handler: &F)
+where
+    F: Fn(&Request) -> Response,
+{
+    if let Err(e) = stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)))
+    {
+        log::warn!("Failed to set HTTP connection timeouts: {}", e);
+        return;
+    }
+    let client = stream.peer_addr().ok().map(|address| address.ip());
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &options.tls {
+        match rustls::ServerConnection::new(Arc::clone(tls)) {
+            Ok(connection) => {
+                let stream = rustls::StreamOwned::new(connection, stream);
+                handle_connection(stream, client, options, handler);
+            }
+            Err(e) => log::warn!("Failed to start a TLS session: {}", e),
+        }
+        return;
+    }
+    handle_connection(stream, client, options, handler);
+}
+
+/// Whether every address `listen` resolves to is a loopback address.
+pub fn is_loopback(listen: &str) -> bool {
+    listen.to_socket_addrs().is_ok_and(|addresses| {
+        let addresses: Vec<_> = addresses.collect();
+        !addresses.is_empty() && addresses.iter().all(|address| address.ip().is_loopback())
+    })
+}
+
+/// A connection being served, counted in `serve`'s total until it is dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(open: &Arc<AtomicUsize>) -> Option<Slot> {
+        let mut count = open.load(Ordering::SeqCst);
+        while count < MAX_CONNECTIONS {
+            match open.compare_exchange(count, count + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return Some(Slot(Arc::clone(open))),
+                Err(current) => count = current,
+            }
+        }
+        None
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Serves HTTP/1.1 on `listen`, one thread per connection up to `MAX_CONNECTIONS`, until
+/// the listener fails.
+pub fn serve<F>(listen: &str, options: ServeOptions, handler: F) -> Result<(), Box<dyn Error>>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(listen)?;
+    log::info!("HTTP API listening on {}", listen);
+    let handler = Arc::new(handler);
+    let options = Arc::new(options);
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let Some(slot) = Slot::take(&open) else {
+                    log::warn!("Turning a connection away: {} are open", MAX_CONNECTIONS);
+                    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                    let _ = write_response(&mut stream, &Response::error(503, "Server busy"));
+                    continue;
+                };
+                let handler = Arc::clone(&handler);
+                let options = Arc::clone(&options);
+                thread::spawn(move || {
+                    accept(stream, &options, handler.as_ref());
+                    drop(slot);
+                });
+            }
+            Err(e) => log::warn!("Failed to accept HTTP connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "POST /capture?to=Inbox%20Two.md&x=a+b HTTP/1.1\r\nHost: x\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/capture");
+        assert_eq!(request.query["to"], "Inbox Two.md");
+        assert_eq!(request.query["x"], "a b");
+        assert_eq!(request.header("Content-Type"), Some("text/plain"));
+        assert_eq!(request.body_text(), "hello");
+    }
+
+    #[test]
+    fn test_read_request_caps_the_head() {
+        let long_line = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(20_000));
+        let error = read_request(&mut long_line.as_bytes()).unwrap_err();
+        assert!(error.is::<HeadTooLarge>());
+
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-A: b\r\n".repeat(MAX_HEADERS + 1)
+        );
+        let error = read_request(&mut many.as_bytes()).unwrap_err();
+        assert!(error.is::<HeadTooLarge>());
+
+        let enough = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEADERS));
+        assert!(read_request(&mut enough.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2);
+        let client = IpAddr::from([127, 0, 0, 1]);
+        let other = IpAddr::from([10, 0, 0, 2]);
+        let start = Instant::now();
+        assert!(limiter.allow(client, start));
+        assert!(limiter.allow(client, start));
+        assert!(!limiter.allow(client, start));
+        assert!(limiter.allow(other, start));
+        assert!(limiter.allow(client, start + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        assert!(is_loopback("127.0.0.1:27123"));
+        assert!(is_loopback("[::1]:27123"));
+        assert!(!is_loopback("0.0.0.0:27123"));
+        assert!(!is_loopback("192.168.1.5:27123"));
+        assert!(!is_loopback("not an address"));
+    }
+
+    #[test]
+    fn test_connection_slots() {
+        let open = Arc::new(AtomicUsize::new(0));
+        let slots: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| Slot::take(&open).unwrap())
+            .collect();
+        assert!(Slot::take(&open).is_none());
+        drop(slots);
+        assert!(Slot::take(&open).is_some());
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut output = Vec::new();
+        write_response(&mut output, &Response::json(201, &"ok")).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(output.contains("Content-Type: application/json\r\n"));
+        assert!(output.ends_with("\r\n\r\n\"ok\""));
+    }
+}
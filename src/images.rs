@@ -0,0 +1,565 @@
+//! Image attachments: their size, dimensions and EXIF date and camera kept in the cache
+//! database, thumbnails generated into the data directory, and recompression of images
+//! over the `[images]` limits.
+
+use crate::capture;
+use crate::cli::{ImagesCommand, ImagesListArgs, ImagesRecompressArgs, OutputFormat};
+use crate::config::{AppConfig, ImagesConfig};
+use crate::data;
+use crate::index;
+use crate::util;
+use crate::write_gate;
+
+use image::{
+    DynamicImage, ImageDecoder, ImageEncoder, ImageFormat, ImageReader,
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+    },
+    imageops,
+    metadata::Orientation,
+};
+use serde::Serialize;
+use sqlite::{Connection, State};
+#[cfg(feature = "exif")]
+use std::io::BufReader;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+/// Thumbnails not served for this long are deleted when the next one is made
+const THUMBNAIL_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ImageInfo {
+    /// Vault-relative path
+    pub path: String,
+    pub bytes: u64,
+    pub width: u32,
+    pub height: u32,
+    pub format: String,
+    /// EXIF `DateTimeOriginal`, as written by the camera
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taken: Option<String>,
+    /// EXIF `Make` and `Model`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+}
+
+/// Whether this build reads EXIF data. Rows record it, so enabling the `exif` feature
+/// later reads the photos an earlier build could not.
+const READS_EXIF: bool = cfg!(feature = "exif");
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    index::drop_outdated(connection, "images", "exif_read")?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS images (
+            path TEXT PRIMARY KEY,
+            bytes INTEGER NOT NULL,
+            modified INTEGER NOT NULL,
+            width INTEGER NOT NULL,
+            height INTEGER NOT NULL,
+            format TEXT NOT NULL,
+            taken TEXT,
+            camera TEXT,
+            exif_read INTEGER NOT NULL
+        );",
+    )
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// The `DateTimeOriginal` and camera of a photo, if it has EXIF data.
+#[cfg(feature = "exif")]
+fn read_exif(file: &Path) -> (Option<String>, Option<String>) {
+    let Ok(exif) = fs::File::open(file)
+        .map(BufReader::new)
+        .map_err(exif::Error::Io)
+        .and_then(|mut reader| exif::Reader::new().read_from_container(&mut reader))
+    else {
+        return (None, None);
+    };
+    let field = |tag| {
+        let field = exif.get_field(tag, exif::In::PRIMARY)?;
+        let value = field.display_value().to_string();
+        let value = value.trim_matches('"').trim().to_string();
+        (!value.is_empty()).then_some(value)
+    };
+    let camera = match (field(exif::Tag::Make), field(exif::Tag::Model)) {
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.or(model),
+    };
+    (field(exif::Tag::DateTimeOriginal), camera)
+}
+
+/// Without the `exif` feature images are listed without date or camera.
+#[cfg(not(feature = "exif"))]
+fn read_exif(_file: &Path) -> (Option<String>, Option<String>) {
+    (None, None)
+}
+
+fn read_info(vault_path: &Path, rel_path: &str, bytes: u64) -> Result<ImageInfo, Box<dyn Error>> {
+    let file = vault_path.join(rel_path);
+    let reader = ImageReader::open(&file)?.with_guessed_format()?;
+    let format = reader
+        .format()
+        .map_or("unknown", |format| format.extensions_str()[0])
+        .to_string();
+    let (width, height) = reader.into_dimensions()?;
+    let (taken, camera) = read_exif(&file);
+    Ok(ImageInfo {
+        path: rel_path.to_string(),
+        bytes,
+        width,
+        height,
+        format,
+        taken,
+        camera,
+    })
+}
+
+/// Brings the images table up to date with the vault, reading only new or changed files,
+/// and returns every image in it.
+pub fn refresh(
+    connection: &Connection,
+    vault_path: &Path,
+) -> Result<Vec<ImageInfo>, Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let mut known = HashMap::new();
+    let mut statement =
+        connection.prepare("SELECT path, bytes, modified, exif_read FROM images")?;
+    while let State::Row = statement.next()? {
+        known.insert(
+            statement.read::<String, _>(0)?,
+            (
+                (
+                    statement.read::<i64, _>(2)?,
+                    statement.read::<i64, _>(1)? as u64,
+                ),
+                statement.read::<i64, _>(3)? != 0,
+            ),
+        );
+    }
+
+    for file in data::traverse_vault(vault_path)?
+        .into_iter()
+        .filter(|file| is_image(file))
+    {
+        let Ok(rel_path) = file.strip_prefix(vault_path) else {
+            continue;
+        };
+        let rel_path = rel_path.to_string_lossy().replace('\\', "/");
+        let stamp = match util::file_stamp(&file) {
+            Ok(stamp) => stamp,
+            Err(e) => {
+                log::warn!("Could not read image {}: {}", rel_path, e);
+                continue;
+            }
+        };
+        if known
+            .remove(&rel_path)
+            .is_some_and(|(known, exif_read)| known == stamp && (exif_read || !READS_EXIF))
+        {
+            continue;
+        }
+        let info = match read_info(vault_path, &rel_path, stamp.1) {
+            Ok(info) => info,
+            Err(e) => {
+                log::warn!("Could not read image {}: {}", rel_path, e);
+                continue;
+            }
+        };
+        let mut statement = connection.prepare(
+            "INSERT OR REPLACE INTO images (path, bytes, modified, width, height, format, taken, camera, exif_read)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        statement.bind((1, info.path.as_str()))?;
+        statement.bind((2, info.bytes as i64))?;
+        statement.bind((3, stamp.0))?;
+        statement.bind((4, info.width as i64))?;
+        statement.bind((5, info.height as i64))?;
+        statement.bind((6, info.format.as_str()))?;
+        statement.bind((7, info.taken.as_deref()))?;
+        statement.bind((8, info.camera.as_deref()))?;
+        statement.bind((9, i64::from(READS_EXIF)))?;
+        statement.next()?;
+    }
+    for gone in known.keys() {
+        let mut statement = connection.prepare("DELETE FROM images WHERE path = ?")?;
+        statement.bind((1, gone.as_str()))?;
+        statement.next()?;
+    }
+
+    let mut statement = connection.prepare(
+        "SELECT path, bytes, width, height, format, taken, camera FROM images ORDER BY path",
+    )?;
+    let mut images = Vec::new();
+    while let State::Row = statement.next()? {
+        images.push(ImageInfo {
+            path: statement.read(0)?,
+            bytes: statement.read::<i64, _>(1)? as u64,
+            width: statement.read::<i64, _>(2)? as u32,
+            height: statement.read::<i64, _>(3)? as u32,
+            format: statement.read(4)?,
+            taken: statement.read(5)?,
+            camera: statement.read(6)?,
+        });
+    }
+    Ok(images)
+}
+
+/// Why `image` is over the configured limits, if it is.
+pub fn oversized(image: &ImageInfo, settings: &ImagesConfig) -> Option<String> {
+    let too_large = settings.max_kb > 0 && image.bytes > settings.max_kb * 1024;
+    let too_wide =
+        settings.max_dimension > 0 && image.width.max(image.height) > settings.max_dimension;
+    (too_large || too_wide).then(|| {
+        format!(
+            "{} KB, {}×{} (limits are {} KB and {} px)",
+            image.bytes / 1024,
+            image.width,
+            image.height,
+            settings.max_kb,
+            settings.max_dimension
+        )
+    })
+}
+
+/// Deletes the thumbnails in `folder` that start with `prefix`, older versions of one
+/// being replaced, and those not served for [`THUMBNAIL_TTL`].
+fn prune_thumbnails(folder: &Path, prefix: &str) -> Result<(), Box<dyn Error>> {
+    let expired = SystemTime::now() - THUMBNAIL_TTL;
+    for entry in fs::read_dir(folder)? {
+        let entry = entry?;
+        let stale = entry.file_name().to_string_lossy().starts_with(prefix)
+            || entry.metadata()?.modified()? < expired;
+        if stale {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// The image scaled to fit in a `size` square, kept as a PNG in the data directory and
+/// regenerated when the image changes.
+pub fn thumbnail(
+    vault_path: &Path,
+    config: &AppConfig,
+    rel_path: &Path,
+    size: u32,
+) -> Result<PathBuf, Box<dyn Error>> {
+    capture::ensure_inside_vault(rel_path)?;
+    let file = vault_path.join(rel_path);
+    if !is_image(&file) {
+        return Err(format!("{} is not an image", rel_path.display()).into());
+    }
+    let (modified, bytes) = util::file_stamp(&file)?;
+    // Named after the image and size, then its version, so older versions can be found
+    let image_key = format!("{}\0{}", rel_path.display(), size);
+    let prefix = format!("{}-", &util::content_hash(image_key.as_bytes())[..16]);
+    let version = format!("{}\0{}", modified, bytes);
+    let name = format!(
+        "{}{}.png",
+        prefix,
+        &util::content_hash(version.as_bytes())[..16]
+    );
+    let folder = data::get_data_path(config)?.join("thumbnails");
+    let thumbnail = folder.join(name);
+    if thumbnail.exists() {
+        fs::File::options()
+            .write(true)
+            .open(&thumbnail)?
+            .set_modified(SystemTime::now())?;
+        return Ok(thumbnail);
+    }
+    fs::create_dir_all(&folder)?;
+    if let Err(e) = prune_thumbnails(&folder, &prefix) {
+        log::warn!("Could not prune thumbnails: {}", e);
+    }
+    decode(&file)?
+        .image
+        .thumbnail(size, size)
+        .save_with_format(&thumbnail, ImageFormat::Png)?;
+    Ok(thumbnail)
+}
+
+/// An image file read back, turned upright
+struct Decoded {
+    image: DynamicImage,
+    format: ImageFormat,
+    /// EXIF data, its orientation reset to match
+    exif: Option<Vec<u8>>,
+}
+
+fn decode(file: &Path) -> Result<Decoded, Box<dyn Error>> {
+    let reader = ImageReader::open(file)?.with_guessed_format()?;
+    let format = reader.format().ok_or("unknown image format")?;
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut exif = decoder.exif_metadata()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    if let Some(exif) = &mut exif {
+        let _ = Orientation::remove_from_exif_chunk(exif);
+    }
+    Ok(Decoded {
+        image,
+        format,
+        exif,
+    })
+}
+
+/// `image` scaled down to `max_dimension` and encoded again as `format`, keeping `exif`,
+/// or `None` for formats that cannot be written smaller.
+fn recompress(
+    image: DynamicImage,
+    format: ImageFormat,
+    max_dimension: u32,
+    quality: u8,
+    exif: Option<Vec<u8>>,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let image = match max_dimension {
+        0 => image,
+        max if image.width().max(image.height()) > max => {
+            image.resize(max, max, imageops::FilterType::Lanczos3)
+        }
+        _ => image,
+    };
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new_with_quality(&mut out, quality);
+            if let Some(exif) = exif {
+                encoder.set_exif_metadata(exif)?;
+            }
+            image.to_rgb8().write_with_encoder(encoder)?
+        }
+        ImageFormat::Png => {
+            let mut encoder =
+                PngEncoder::new_with_quality(&mut out, CompressionType::Best, FilterType::Adaptive);
+            if let Some(exif) = exif {
+                encoder.set_exif_metadata(exif)?;
+            }
+            image.write_with_encoder(encoder)?
+        }
+        _ => return Ok(None),
+    }
+    Ok(Some(out))
+}
+
+fn list(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &ImagesListArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    let mut images = refresh(&connection, vault_path)?;
+    if args.oversized {
+        images.retain(|image| oversized(image, &config.images).is_some());
+    }
+    match args.format {
+        OutputFormat::Text => {
+            for image in &images {
+                let mut line = format!(
+                    "{}  {}×{}  {} KB",
+                    image.path,
+                    image.width,
+                    image.height,
+                    image.bytes / 1024
+                );
+                for extra in [&image.taken, &image.camera].into_iter().flatten() {
+                    line.push_str("  ");
+                    line.push_str(extra);
+                }
+                println!("{}", line);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&images)?),
+        OutputFormat::Ndjson => {
+            for image in &images {
+                util::print_ndjson(image)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn recompress_vault(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &ImagesRecompressArgs,
+) -> Result<(), Box<dyn Error>> {
+    if !args.dry_run {
+        write_gate::check("recompress images")?;
+    }
+    let settings = &config.images;
+    let max_dimension = args.max_dimension.unwrap_or(settings.max_dimension);
+    let quality = args.quality.unwrap_or(settings.quality);
+    let connection = index::open(config)?;
+    let mut saved = 0;
+    for image in refresh(&connection, vault_path)? {
+        if oversized(&image, settings).is_none() {
+            continue;
+        }
+        let file = vault_path.join(&image.path);
+        let recompressed = decode(&file).and_then(|upright| {
+            let exif = upright.exif;
+            recompress(upright.image, upright.format, max_dimension, quality, exif)
+        });
+        let bytes = match recompressed {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => {
+                log::warn!("{}: cannot recompress {} images", image.path, image.format);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("{}: skipped, {}", image.path, e);
+                continue;
+            }
+        };
+        if bytes.len() as u64 >= image.bytes {
+            log::info!("{}: already as small as it gets", image.path);
+            continue;
+        }
+        println!(
+            "{}: {} KB -> {} KB",
+            image.path,
+            image.bytes / 1024,
+            bytes.len() / 1024
+        );
+        saved += image.bytes - bytes.len() as u64;
+        if !args.dry_run {
+            util::safe_write(&file, bytes, args.backup)?;
+        }
+    }
+    log::info!(
+        "{} {} KB",
+        if args.dry_run { "Would save" } else { "Saved" },
+        saved / 1024
+    );
+    Ok(())
+}
+
+pub fn run_images(
+    vault_path: &Path,
+    config: &AppConfig,
+    command: &ImagesCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        ImagesCommand::List(args) => list(vault_path, config, args),
+        ImagesCommand::Thumbnail(args) => {
+            let size = args.size.unwrap_or(config.images.thumbnail_size);
+            println!(
+                "{}",
+                thumbnail(vault_path, config, &args.path, size)?.display()
+            );
+            Ok(())
+        }
+        ImagesCommand::Recompress(args) => recompress_vault(vault_path, config, args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn test_index_and_recompress() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        let photo = RgbImage::from_fn(400, 300, |x, y| {
+            image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        });
+        photo.save(vault.path().join("photo.png")).unwrap();
+        fs::write(vault.path().join("Note.md"), "![[photo.png]]\n").unwrap();
+
+        let connection = Connection::open(":memory:").unwrap();
+        let images = refresh(&connection, vault.path()).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!((images[0].width, images[0].height), (400, 300));
+        assert_eq!(images[0].format, "png");
+
+        let settings = ImagesConfig {
+            max_dimension: 200,
+            ..ImagesConfig::default()
+        };
+        assert!(oversized(&images[0], &settings).is_some());
+        let smaller = recompress(
+            DynamicImage::ImageRgb8(photo),
+            ImageFormat::Jpeg,
+            200,
+            80,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        let shrunk = image::load_from_memory(&smaller).unwrap();
+        assert_eq!((shrunk.width(), shrunk.height()), (200, 150));
+    }
+
+    #[test]
+    fn test_orientation_and_exif_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        // A little-endian TIFF header with a single Orientation entry: rotate 90° clockwise
+        let mut exif = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0".to_vec();
+        exif.extend_from_slice(&[0; 4]);
+        let mut jpeg = Vec::new();
+        let mut encoder = JpegEncoder::new(&mut jpeg);
+        encoder.set_exif_metadata(exif).unwrap();
+        RgbImage::new(40, 20).write_with_encoder(encoder).unwrap();
+        let file = dir.path().join("photo.jpg");
+        fs::write(&file, jpeg).unwrap();
+
+        let Decoded {
+            image,
+            format,
+            exif,
+        } = decode(&file).unwrap();
+        assert_eq!((image.width(), image.height()), (20, 40));
+        let exif = exif.unwrap();
+        assert_eq!(
+            Orientation::from_exif_chunk(&exif),
+            Some(Orientation::NoTransforms)
+        );
+
+        let bytes = recompress(image, format, 0, 80, Some(exif))
+            .unwrap()
+            .unwrap();
+        fs::write(&file, bytes).unwrap();
+        let again = decode(&file).unwrap();
+        assert_eq!((again.image.width(), again.image.height()), (20, 40));
+        assert!(again.exif.is_some());
+    }
+
+    #[test]
+    fn test_prune_thumbnails() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a-old.png", "b-current.png", "c-unused.png"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        fs::File::options()
+            .write(true)
+            .open(dir.path().join("c-unused.png"))
+            .unwrap()
+            .set_modified(SystemTime::now() - THUMBNAIL_TTL * 2)
+            .unwrap();
+
+        prune_thumbnails(dir.path(), "a-").unwrap();
+        let mut left: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["b-current.png"]);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::capture;
+use crate::changeset::ChangeSet;
+use crate::cli::{ImportArgs, OutputFormat};
+use crate::data;
+use crate::markdown::{self, LinkStyle};
+use crate::resolver::Resolver;
+use crate::util;
+use crate::write_gate;
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportItem {
+    /// Path relative to the imported folder
+    pub source: PathBuf,
+    /// Path relative to the current vault
+    pub destination: PathBuf,
+    pub renamed: bool,
+}
+
+fn path_key(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/").to_lowercase()
+}
+
+/// Picks `wanted`, or `Name 1.ext`, `Name 2.ext`, ... when that path is already taken.
+fn unique_destination(wanted: PathBuf, taken: &HashSet<String>) -> (PathBuf, bool) {
+    if !taken.contains(&path_key(&wanted)) {
+        return (wanted, false);
+    }
+    let stem = wanted
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = wanted.extension().map(|e| e.to_string_lossy().into_owned());
+    let mut counter = 1;
+    loop {
+        let name = match &extension {
+            Some(ext) => format!("{} {}.{}", stem, counter, ext),
+            None => format!("{} {}", stem, counter),
+        };
+        let candidate = wanted.with_file_name(name);
+        if !taken.contains(&path_key(&candidate)) {
+            return (candidate, true);
+        }
+        counter += 1;
+    }
+}
+
+/// Decides where every imported file goes. Notes keep their folder structure under `into`,
+/// attachments are gathered into `attachments` when one is configured.
+pub fn plan_import(
+    source_files: &[PathBuf],
+    existing: &[PathBuf],
+    into: &Path,
+    attachments: Option<&Path>,
+) -> Vec<ImportItem> {
+    let mut taken: HashSet<String> = existing.iter().map(|p| path_key(p)).collect();
+    let mut plan = Vec::new();
+
+    for source in source_files {
+        let wanted = match attachments {
+            Some(folder) if !data::is_note(source) => {
+                folder.join(source.file_name().unwrap_or(source.as_os_str()))
+            }
+            _ => into.join(source),
+        };
+        let (destination, renamed) = unique_destination(wanted, &taken);
+        taken.insert(path_key(&destination));
+        plan.push(ImportItem {
+            source: source.clone(),
+            destination,
+            renamed,
+        });
+    }
+    plan
+}
+
+/// Points links inside an imported note at the imported copies of their targets.
+pub fn remap_links(
+    content: &str,
+    source_note: &Path,
+    source_resolver: &Resolver,
+    mapping: &HashMap<PathBuf, PathBuf>,
+    combined: &Resolver,
+) -> String {
+    let mut edits = Vec::new();
+    for link in markdown::parse_links(content) {
+        if link.is_external() || link.target.is_empty() {
+            continue;
+        }
+        let Some(destination) = source_resolver
+            .resolve(&link.target, source_note)
+            .and_then(|resolved| mapping.get(resolved))
+        else {
+            continue;
+        };
+
+        let rewritten = match link.style {
+            LinkStyle::Wiki => {
+                let target = combined.shortest_link(destination);
+                let text = link.text.clone().or_else(|| {
+                    // Keep the displayed name stable if the note had to be renamed
+                    let shown = target.rsplit('/').next().unwrap_or(&target);
+                    (shown != link.target).then(|| link.target.clone())
+                });
+                markdown::render_wikilink(
+                    &target,
+                    link.anchor.as_deref(),
+                    text.as_deref(),
+                    link.embed,
+                )
+            }
+            LinkStyle::Markdown => {
+                let anchor = link.anchor.as_deref().map(util::percent_decode);
+                markdown::render_markdown_link(
+                    &destination.to_string_lossy().replace('\\', "/"),
+                    anchor.as_deref(),
+                    link.text.as_deref().unwrap_or_default(),
+                    link.embed,
+                )
+            }
+        };
+        if rewritten != content[link.span.clone()] {
+            edits.push((link.span.clone(), rewritten));
+        }
+    }
+    markdown::replace_spans(content, edits)
+}
+
+pub fn run_import(
+    vault_path: &Path,
+    attachments: Option<&Path>,
+    args: &ImportArgs,
+) -> Result<Vec<ImportItem>, Box<dyn Error>> {
+    let dry_run = args.changes.dry_run;
+    if !dry_run {
+        write_gate::check("import notes")?;
+    }
+    let into = args.into.clone().unwrap_or_default();
+    if !into.as_os_str().is_empty() {
+        capture::ensure_inside_vault(&into)?;
+    }
+    let source_root = util::expand_tilde(&args.source)
+        .map(|p| p.into_owned())
+        .ok_or("Failed to expand import path")?;
+    if !source_root.is_dir() {
+        return Err(format!(
+            "Import source '{}' is not a directory",
+            source_root.display()
+        )
+        .into());
+    }
+
+    let source_resolver = Resolver::from_vault(&source_root)?;
+    let existing = Resolver::from_vault(vault_path)?;
+    let plan = plan_import(
+        source_resolver.files(),
+        existing.files(),
+        &into,
+        attachments,
+    );
+
+    let mapping: HashMap<PathBuf, PathBuf> = plan
+        .iter()
+        .map(|item| (item.source.clone(), item.destination.clone()))
+        .collect();
+    let mut combined_files = existing.files().to_vec();
+    combined_files.extend(plan.iter().map(|item| item.destination.clone()));
+    let combined = Resolver::new(combined_files);
+
+    // Notes go through a change set like any other rewrite; attachments are copied as is
+    let mut changes = ChangeSet::new();
+    let mut attachments = Vec::new();
+    for item in &plan {
+        if args.changes.changes_format == OutputFormat::Text {
+            println!(
+                "{} -> {}{}",
+                item.source.display(),
+                item.destination.display(),
+                if item.renamed { " (renamed)" } else { "" }
+            );
+        }
+        let from = source_root.join(&item.source);
+        if data::is_note(&item.source) {
+            let content = fs::read_to_string(&from)?;
+            let content = remap_links(
+                &content,
+                &item.source,
+                &source_resolver,
+                &mapping,
+                &combined,
+            );
+            changes.propose(&item.destination, None, content);
+        } else {
+            attachments.push((from, vault_path.join(&item.destination)));
+        }
+    }
+    changes.finish(vault_path, &args.changes, "import notes", false)?;
+    if !dry_run {
+        for (from, to) in attachments {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&from, &to)?;
+        }
+    }
+
+    log::info!(
+        "{} {} file(s) from {}.",
+        if dry_run {
+            "Would import"
+        } else {
+            "Imported"
+        },
+        plan.len(),
+        source_root.display()
+    );
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::ChangeArgs;
+
+    #[test]
+    fn test_plan_import_renames_collisions() {
+        let existing = vec![PathBuf::from("Plan.md"), PathBuf::from("assets/pic.png")];
+        let source = vec![
+            PathBuf::from("Plan.md"),
+            PathBuf::from("sub/Other.md"),
+            PathBuf::from("img/pic.png"),
+        ];
+        let plan = plan_import(&source, &existing, Path::new(""), Some(Path::new("assets")));
+
+        assert_eq!(plan[0].destination, PathBuf::from("Plan 1.md"));
+        assert!(plan[0].renamed);
+        assert_eq!(plan[1].destination, PathBuf::from("sub/Other.md"));
+        assert_eq!(plan[2].destination, PathBuf::from("assets/pic 1.png"));
+    }
+
+    #[test]
+    fn test_remap_links() {
+        let source_resolver = Resolver::new(vec![
+            PathBuf::from("Plan.md"),
+            PathBuf::from("sub/Other.md"),
+            PathBuf::from("img/pic.png"),
+        ]);
+        let mapping = HashMap::from([
+            (PathBuf::from("Plan.md"), PathBuf::from("Plan 1.md")),
+            (PathBuf::from("sub/Other.md"), PathBuf::from("sub/Other.md")),
+            (
+                PathBuf::from("img/pic.png"),
+                PathBuf::from("assets/pic 1.png"),
+            ),
+        ]);
+        let combined = Resolver::new(vec![
+            PathBuf::from("Plan.md"),
+            PathBuf::from("Plan 1.md"),
+            PathBuf::from("sub/Other.md"),
+            PathBuf::from("assets/pic.png"),
+            PathBuf::from("assets/pic 1.png"),
+        ]);
+
+        let content = "[[Plan#Goals]] [[Other]] ![[pic.png]] [p](Plan.md) [[Unknown]]";
+        let remapped = remap_links(
+            content,
+            Path::new("sub/Other.md"),
+            &source_resolver,
+            &mapping,
+            &combined,
+        );
+        assert_eq!(
+            remapped,
+            "[[Plan 1#Goals|Plan]] [[Other]] ![[pic 1.png|pic.png]] [p](Plan%201.md) [[Unknown]]"
+        );
+    }
+
+    #[test]
+    fn test_import_stays_inside_the_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let vault = dir.path().join("vault");
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&vault).unwrap();
+        fs::write(source.join("Plan.md"), "# Plan\n").unwrap();
+
+        let outside = dir.path().join("outside");
+        for into in [outside.clone(), PathBuf::from("../outside")] {
+            let args = ImportArgs {
+                source: source.clone(),
+                into: Some(into),
+                changes: ChangeArgs {
+                    dry_run: false,
+                    changes_format: OutputFormat::Text,
+                },
+            };
+            assert!(run_import(&vault, None, &args).is_err());
+        }
+        assert!(!outside.exists());
+    }
+}
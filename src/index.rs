@@ -0,0 +1,958 @@
+use crate::clock;
+use crate::collation;
+use crate::completions;
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::frontmatter;
+use crate::kanban;
+use crate::markdown;
+use crate::plugins;
+use crate::reminders;
+use crate::resolver::Resolver;
+use crate::scripts;
+use crate::search_query::SearchQuery;
+use crate::util;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlite::{Connection, State};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// Version of what indexing a note stores. Raise it when an extractor is added or
+/// changed, e.g. Kanban cards or clock entries, so every note is indexed again once.
+const NOTE_SCHEMA: i64 = 2;
+
+/// Longest chunk, in bytes, before a section is split at paragraph breaks
+pub static MAX_CHUNK_BYTES: usize = 1500;
+
+/// A searchable piece of a note: a section under one heading, or part of one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub path: String,
+    /// Nearest heading above the chunk, empty before the first heading
+    pub heading: String,
+    /// 1-based line the chunk starts on
+    pub line: usize,
+    pub text: String,
+}
+
+impl Chunk {
+    /// Text handed to embedding backends, prefixed with where the chunk comes from.
+    pub fn embedding_input(&self, title: &str) -> String {
+        if self.heading.is_empty() {
+            format!("{}\n\n{}", title, self.text)
+        } else {
+            format!("{} > {}\n\n{}", title, self.heading, self.text)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct SearchHit {
+    pub path: String,
+    pub heading: String,
+    pub line: usize,
+    pub score: f64,
+    /// Component scores when results of several rankings were combined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyword_score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub semantic_score: Option<f64>,
+    /// The snippets joined, with matches in `**bold**`
+    pub snippet: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub snippets: Vec<Snippet>,
+}
+
+/// A passage of a hit's text with the byte ranges of the words the query matched in it
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    pub highlights: Vec<[usize; 2]>,
+}
+
+impl Snippet {
+    /// The text with its highlights in `**bold**`.
+    pub fn marked(&self) -> String {
+        let mut marked = String::new();
+        let mut end = 0;
+        for [from, to] in &self.highlights {
+            marked.push_str(&self.text[end..*from]);
+            marked.push_str("**");
+            marked.push_str(&self.text[*from..*to]);
+            marked.push_str("**");
+            end = *to;
+        }
+        marked.push_str(&self.text[end..]);
+        marked
+    }
+}
+
+/// How much of the matching text each search hit shows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnippetOptions {
+    /// Words per snippet
+    pub words: usize,
+    /// Snippets per hit, each around a different match in the best-matching chunk
+    pub count: usize,
+}
+
+impl Default for SnippetOptions {
+    fn default() -> Self {
+        SnippetOptions {
+            words: 12,
+            count: 1,
+        }
+    }
+}
+
+/// Markers `highlight()` puts around matches, which never occur in note text
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// Snippets of `marked` (text with matches between [`MATCH_START`] and [`MATCH_END`]),
+/// each starting a few words before a match not shown yet. Text without matches gives
+/// its first words.
+pub fn snippets(marked: &str, options: SnippetOptions) -> Vec<Snippet> {
+    let mut text = String::with_capacity(marked.len());
+    let mut matches: Vec<Range<usize>> = Vec::new();
+    let mut words: Vec<Range<usize>> = Vec::new();
+    let mut match_start = 0;
+    let mut word_start = None;
+    for ch in marked.chars() {
+        match ch {
+            MATCH_START => match_start = text.len(),
+            MATCH_END => matches.push(match_start..text.len()),
+            ch if ch.is_whitespace() => {
+                if let Some(start) = word_start.take() {
+                    words.push(start..text.len());
+                }
+                text.push(' ');
+            }
+            ch => {
+                word_start.get_or_insert(text.len());
+                text.push(ch);
+            }
+        }
+    }
+    if let Some(start) = word_start {
+        words.push(start..text.len());
+    }
+
+    let length = options.words.max(1);
+    let mut windows: Vec<Range<usize>> = Vec::new();
+    for found in &matches {
+        if windows.len() == options.count {
+            break;
+        }
+        let word = words.partition_point(|word| word.end <= found.start);
+        if windows.last().is_some_and(|window| word < window.end) {
+            continue;
+        }
+        // Lead in with some context, but never repeat words of the previous snippet
+        let start = word
+            .saturating_sub(length / 4)
+            .max(windows.last().map_or(0, |window| window.end));
+        windows.push(start..(start + length).min(words.len()));
+    }
+    if windows.is_empty() && options.count > 0 && !words.is_empty() {
+        windows.push(0..length.min(words.len()));
+    }
+
+    windows
+        .into_iter()
+        .map(|window| {
+            let from = words[window.start].start;
+            let to = words[window.end - 1].end;
+            let lead = if window.start > 0 { "…" } else { "" };
+            let highlights = matches
+                .iter()
+                .filter(|found| found.start >= from && found.end <= to)
+                .map(|found| {
+                    [
+                        found.start - from + lead.len(),
+                        found.end - from + lead.len(),
+                    ]
+                })
+                .collect();
+            let tail = if window.end < words.len() { "…" } else { "" };
+            Snippet {
+                text: format!("{}{}{}", lead, &text[from..to], tail),
+                highlights,
+            }
+        })
+        .collect()
+}
+
+/// [`SearchHit::snippets`] and [`SearchHit::snippet`] for `marked` text.
+pub fn hit_snippets(marked: &str, options: SnippetOptions) -> (String, Vec<Snippet>) {
+    let snippets = snippets(marked, options);
+    let joined = snippets
+        .iter()
+        .map(Snippet::marked)
+        .collect::<Vec<_>>()
+        .join(" ");
+    (joined, snippets)
+}
+
+/// What an [`update`] found changed since the index was last brought up to date, as
+/// vault-relative paths
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct IndexStats {
+    pub added: Vec<String>,
+    /// Notes whose content changed (all of them when the plugins or scripts changed)
+    pub modified: Vec<String>,
+    pub removed: Vec<String>,
+    /// `(from, to)` pairs of notes that moved with their content unchanged
+    pub renamed: Vec<(String, String)>,
+}
+
+impl IndexStats {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.modified.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+    }
+}
+
+impl fmt::Display for IndexStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} modified, {} removed, {} renamed",
+            self.added.len(),
+            self.modified.len(),
+            self.removed.len(),
+            self.renamed.len()
+        )
+    }
+}
+
+fn push_chunk(chunks: &mut Vec<Chunk>, path: &str, heading: &str, line: usize, text: &str) {
+    let text = text.trim();
+    if !text.is_empty() {
+        chunks.push(Chunk {
+            path: path.to_string(),
+            heading: heading.to_string(),
+            line,
+            text: text.to_string(),
+        });
+    }
+}
+
+/// Splits the note body into one chunk per section, breaking long sections at blank lines.
+pub fn chunk_note(note: &Note, max_bytes: usize) -> Vec<Chunk> {
+    let path = note.path.to_string_lossy().replace('\\', "/");
+    let start = markdown::body_start(&note.content);
+    let first_line = note.content[..start].matches('\n').count() + 1;
+
+    let mut chunks = Vec::new();
+    let mut heading = String::new();
+    let mut text = String::new();
+    let mut chunk_line = first_line;
+    let mut in_fence = false;
+
+    for (index, line) in note.content[start..].lines().enumerate() {
+        let line_no = first_line + index;
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        let is_heading = !in_fence
+            && trimmed.starts_with('#')
+            && trimmed.trim_start_matches('#').starts_with(' ');
+        let too_long = line.trim().is_empty() && !in_fence && text.len() >= max_bytes;
+
+        if is_heading || too_long {
+            push_chunk(&mut chunks, &path, &heading, chunk_line, &text);
+            text.clear();
+            chunk_line = line_no;
+        }
+        if is_heading {
+            heading = trimmed.trim_start_matches('#').trim().to_string();
+            chunk_line = line_no + 1;
+            continue;
+        }
+        if text.is_empty() && line.trim().is_empty() {
+            chunk_line = line_no + 1;
+            continue;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    push_chunk(&mut chunks, &path, &heading, chunk_line, &text);
+    chunks
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS indexed_files (
+            path TEXT PRIMARY KEY,
+            hash TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS chunks (
+            id INTEGER PRIMARY KEY,
+            path TEXT NOT NULL,
+            title TEXT NOT NULL,
+            heading TEXT NOT NULL,
+            line INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            hash TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS chunks_path ON chunks(path);
+        CREATE TABLE IF NOT EXISTS metadata (
+            path TEXT NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (path, key)
+        );
+        CREATE TABLE IF NOT EXISTS properties (
+            path TEXT NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS properties_key ON properties(key, value COLLATE NOCASE);
+        CREATE TABLE IF NOT EXISTS kanban_cards (
+            path TEXT NOT NULL,
+            lane TEXT NOT NULL,
+            line INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            done INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS kanban_cards_path ON kanban_cards(path);
+        CREATE TABLE IF NOT EXISTS reminders (
+            path TEXT NOT NULL,
+            title TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            at INTEGER NOT NULL,
+            all_day INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS reminders_at ON reminders(at);
+        CREATE TABLE IF NOT EXISTS clock_entries (
+            path TEXT NOT NULL,
+            target TEXT,
+            start INTEGER NOT NULL,
+            end INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS clock_entries_path ON clock_entries(path);
+        CREATE TABLE IF NOT EXISTS reminders_sent (
+            path TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            at INTEGER NOT NULL,
+            PRIMARY KEY (path, kind, at)
+        );
+        CREATE TABLE IF NOT EXISTS attachment_links (
+            note TEXT NOT NULL,
+            attachment TEXT NOT NULL,
+            line INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS attachment_links_attachment ON attachment_links(attachment);
+        CREATE TABLE IF NOT EXISTS completion_items (
+            path TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            label TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS completion_items_kind ON completion_items(kind, path);
+        CREATE VIRTUAL TABLE IF NOT EXISTS chunks_fts USING fts5(
+            text, heading, title, content='chunks', content_rowid='id'
+        );",
+    )?;
+    reindex_outdated(connection)
+}
+
+/// Forgets the hashes of notes indexed under an older [`NOTE_SCHEMA`], so the next
+/// update indexes each of them again as modified. Attachments keep theirs.
+fn reindex_outdated(connection: &Connection) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare("PRAGMA user_version")?;
+    statement.next()?;
+    if statement.read::<i64, _>(0)? == NOTE_SCHEMA {
+        return Ok(());
+    }
+    let mut statement = connection.prepare("SELECT path FROM indexed_files")?;
+    let mut notes = Vec::new();
+    while let State::Row = statement.next()? {
+        let path = statement.read::<String, _>(0)?;
+        if data::is_note(Path::new(&path)) {
+            notes.push(path);
+        }
+    }
+    connection.execute("BEGIN")?;
+    for path in notes {
+        let mut statement =
+            connection.prepare("UPDATE indexed_files SET hash = '' WHERE path = ?")?;
+        statement.bind((1, path.as_str()))?;
+        statement.next()?;
+    }
+    connection.execute(format!("PRAGMA user_version = {}", NOTE_SCHEMA))?;
+    connection.execute("COMMIT")
+}
+
+/// Drops the cache `table` when it predates `column`, for the caller's `CREATE TABLE IF
+/// NOT EXISTS` to build it afresh.
+pub fn drop_outdated(
+    connection: &Connection,
+    table: &str,
+    column: &str,
+) -> Result<(), sqlite::Error> {
+    if connection
+        .prepare(format!("SELECT {} FROM {} LIMIT 0", column, table))
+        .is_err()
+    {
+        connection.execute(format!("DROP TABLE IF EXISTS {}", table))?;
+    }
+    Ok(())
+}
+
+/// Opens the vault's cache database with the search tables in place.
+pub fn open(config: &AppConfig) -> Result<Connection, Box<dyn Error>> {
+    open_in(&data::get_data_path(config)?, config)
+}
+
+/// Opens the cache database kept in `data_path` rather than the vault's usual folder.
+pub fn open_in(data_path: &Path, config: &AppConfig) -> Result<Connection, Box<dyn Error>> {
+    let connection = data::get_cache(data_path)?;
+    ensure_schema(&connection)?;
+    collation::ensure_tokenizer(&connection, &config.collation)?;
+    Ok(connection)
+}
+
+/// Drops everything indexed for `path`.
+pub fn remove_file(connection: &Connection, path: &str) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT INTO chunks_fts(chunks_fts, rowid, text, heading, title)
+         SELECT 'delete', id, text, heading, title FROM chunks WHERE path = ?",
+    )?;
+    statement.bind((1, path))?;
+    statement.next()?;
+    for sql in [
+        "DELETE FROM chunks WHERE path = ?",
+        "DELETE FROM metadata WHERE path = ?",
+        "DELETE FROM properties WHERE path = ?",
+        "DELETE FROM kanban_cards WHERE path = ?",
+        "DELETE FROM reminders WHERE path = ?",
+        "DELETE FROM clock_entries WHERE path = ?",
+        "DELETE FROM completion_items WHERE path = ?",
+        "DELETE FROM indexed_files WHERE path = ?",
+    ] {
+        let mut statement = connection.prepare(sql)?;
+        statement.bind((1, path))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+fn index_note(connection: &Connection, note: &Note, hash: &str) -> Result<(), sqlite::Error> {
+    let path = note.path.to_string_lossy().replace('\\', "/");
+    let title = note.title();
+    remove_file(connection, &path)?;
+
+    for chunk in chunk_note(note, MAX_CHUNK_BYTES) {
+        insert_chunk(connection, &chunk, &title)?;
+    }
+
+    // Fields from plugins and scripts, stored as JSON
+    if plugins::active() || scripts::active() {
+        for (key, value) in plugins::metadata(note) {
+            let mut statement =
+                connection.prepare("INSERT INTO metadata (path, key, value) VALUES (?, ?, ?)")?;
+            statement.bind((1, path.as_str()))?;
+            statement.bind((2, key.as_str()))?;
+            statement.bind((3, value.to_string().as_str()))?;
+            statement.next()?;
+        }
+    }
+
+    for (key, value) in frontmatter::scalar_properties(&note.content) {
+        let mut statement =
+            connection.prepare("INSERT INTO properties (path, key, value) VALUES (?, ?, ?)")?;
+        statement.bind((1, path.as_str()))?;
+        statement.bind((2, key.as_str()))?;
+        statement.bind((3, value.as_str()))?;
+        statement.next()?;
+    }
+
+    kanban::index_cards(connection, note)?;
+    reminders::index_reminders(connection, note)?;
+    clock::index_entries(connection, note)?;
+    completions::index_items(connection, note)?;
+
+    mark_indexed(connection, &path, hash)
+}
+
+/// Stores `chunk` of the file titled `title` for keyword and semantic search.
+pub fn insert_chunk(
+    connection: &Connection,
+    chunk: &Chunk,
+    title: &str,
+) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT INTO chunks (path, title, heading, line, text, hash) VALUES (?, ?, ?, ?, ?, ?)",
+    )?;
+    let chunk_hash = util::content_hash(chunk.embedding_input(title).as_bytes());
+    statement.bind((1, chunk.path.as_str()))?;
+    statement.bind((2, title))?;
+    statement.bind((3, chunk.heading.as_str()))?;
+    statement.bind((4, chunk.line as i64))?;
+    statement.bind((5, chunk.text.as_str()))?;
+    statement.bind((6, chunk_hash.as_str()))?;
+    statement.next()?;
+    connection.execute(
+        "INSERT INTO chunks_fts(rowid, text, heading, title)
+         SELECT id, text, heading, title FROM chunks WHERE id = last_insert_rowid()",
+    )
+}
+
+/// `text` in pieces of whole lines of at most `max_bytes` (or one line), blank lines dropped.
+pub fn split_lines(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if !piece.is_empty() && piece.len() + line.len() >= max_bytes {
+            pieces.push(std::mem::take(&mut piece));
+        }
+        if !piece.is_empty() {
+            piece.push('\n');
+        }
+        piece.push_str(line);
+    }
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+/// Vault-relative paths of the indexed notes, attachments left out.
+pub fn indexed_notes(connection: &Connection) -> Result<Vec<PathBuf>, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT path FROM indexed_files ORDER BY path")?;
+    let mut notes = Vec::new();
+    while let State::Row = statement.next()? {
+        let path = PathBuf::from(statement.read::<String, _>(0)?);
+        if data::is_note(&path) {
+            notes.push(path);
+        }
+    }
+    Ok(notes)
+}
+
+/// Whether any file besides notes is indexed, e.g. PDF text or media transcripts.
+pub fn has_attachments(connection: &Connection) -> Result<bool, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT path FROM indexed_files")?;
+    while let State::Row = statement.next()? {
+        if !data::is_note(Path::new(&statement.read::<String, _>(0)?)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Records which notes link to or embed each attachment, for [`credit_attachment_hits`].
+pub fn update_attachment_links(
+    connection: &Connection,
+    resolver: &Resolver,
+    notes: &[Note],
+) -> Result<(), sqlite::Error> {
+    connection.execute("BEGIN")?;
+    connection.execute("DELETE FROM attachment_links")?;
+    for note in notes {
+        let source = note.path.to_string_lossy().replace('\\', "/");
+        for link in markdown::parse_links(&note.content) {
+            if link.is_external() {
+                continue;
+            }
+            let Some(target) = resolver.resolve(&link.target, &note.path) else {
+                continue;
+            };
+            if data::is_note(target) {
+                continue;
+            }
+            let mut statement = connection.prepare(
+                "INSERT INTO attachment_links (note, attachment, line) VALUES (?, ?, ?)",
+            )?;
+            statement.bind((1, source.as_str()))?;
+            statement.bind((2, target.to_string_lossy().replace('\\', "/").as_str()))?;
+            statement.bind((3, link.line as i64))?;
+            statement.next()?;
+        }
+    }
+    connection.execute("COMMIT")
+}
+
+/// `hits` with those inside an attachment moved to the first note linking to it, the
+/// attachment named in the heading. Attachments no note links to stay as they are.
+pub fn credit_attachment_hits(
+    connection: &Connection,
+    hits: Vec<SearchHit>,
+) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+    let mut seen = HashSet::new();
+    let mut credited = Vec::new();
+    for mut hit in hits {
+        if !data::is_note(Path::new(&hit.path)) {
+            let mut statement = connection.prepare(
+                "SELECT note, line FROM attachment_links WHERE attachment = ?
+                 ORDER BY note, line LIMIT 1",
+            )?;
+            statement.bind((1, hit.path.as_str()))?;
+            if let State::Row = statement.next()? {
+                hit.heading = format!("{} › {}", hit.path, hit.heading);
+                hit.path = statement.read(0)?;
+                hit.line = statement.read::<i64, _>(1)? as usize;
+            }
+        }
+        if seen.insert(hit.path.clone()) {
+            credited.push(hit);
+        }
+    }
+    Ok(credited)
+}
+
+/// Records that `path` is indexed as of `hash`.
+pub fn mark_indexed(connection: &Connection, path: &str, hash: &str) -> Result<(), sqlite::Error> {
+    let mut statement =
+        connection.prepare("INSERT INTO indexed_files (path, hash) VALUES (?, ?)")?;
+    statement.bind((1, path))?;
+    statement.bind((2, hash))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Content hash a note is indexed under. Changing the plugins or scripts (`extensions`)
+/// re-indexes every note for its metadata.
+fn note_hash(note: &Note, extensions: &str) -> String {
+    let hash = util::content_hash(note.content.as_bytes());
+    if extensions.is_empty() {
+        return hash;
+    }
+    util::content_hash(format!("{}:{}", hash, extensions).as_bytes())
+}
+
+/// Re-indexes a single note right away, e.g. after a command changed it.
+pub fn update_note(connection: &Connection, note: &Note) -> Result<(), Box<dyn Error>> {
+    let hash = note_hash(note, &plugins::fingerprint());
+    connection.execute("BEGIN")?;
+    index_note(connection, note, &hash)?;
+    connection.execute("COMMIT")?;
+    Ok(())
+}
+
+/// Brings the index in line with `notes`, re-chunking only notes whose content (or the set
+/// of plugins and scripts) changed.
+pub fn update(connection: &Connection, notes: &[Note]) -> Result<IndexStats, Box<dyn Error>> {
+    let mut known = HashMap::new();
+    let mut statement = connection.prepare("SELECT path, hash FROM indexed_files")?;
+    while let State::Row = statement.next()? {
+        known.insert(
+            statement.read::<String, _>(0)?,
+            statement.read::<String, _>(1)?,
+        );
+    }
+
+    let extensions = plugins::fingerprint();
+    let mut stats = IndexStats::default();
+    let mut added = Vec::new();
+    connection.execute("BEGIN")?;
+    for note in notes {
+        let path = note.path.to_string_lossy().replace('\\', "/");
+        let hash = note_hash(note, &extensions);
+        match known.remove(&path) {
+            Some(known_hash) if known_hash == hash => continue,
+            Some(_) => stats.modified.push(path),
+            None => added.push((path, hash.clone())),
+        }
+        index_note(connection, note, &hash)?;
+    }
+    // Attachments indexed by other passes are theirs to remove
+    known.retain(|path, _| data::is_note(Path::new(path)));
+    let mut removed: Vec<(String, String)> = known.into_iter().collect();
+    removed.sort();
+    for (path, _) in &removed {
+        remove_file(connection, path)?;
+    }
+    connection.execute("COMMIT")?;
+
+    // A note that disappeared while one with the same content appeared was moved
+    for (path, hash) in added {
+        match removed
+            .iter()
+            .position(|(_, removed_hash)| *removed_hash == hash)
+        {
+            Some(i) => stats.renamed.push((removed.remove(i).0, path)),
+            None => stats.added.push(path),
+        }
+    }
+    stats.removed = removed.into_iter().map(|(path, _)| path).collect();
+    Ok(stats)
+}
+
+/// Notes matching all terms of `query`, best BM25 score first, one hit per note, with
+/// snippets of the best-matching chunk. A query of filters only lists the notes passing
+/// them, unranked.
+pub fn keyword_search(
+    connection: &Connection,
+    query: &str,
+    limit: usize,
+    options: SnippetOptions,
+) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+    let query = SearchQuery::parse(query)?;
+    let (filters, values) = query.filter_sql();
+    let mut statement = match (query.fts.is_empty(), query.has_filters()) {
+        (true, false) => return Ok(Vec::new()),
+        (true, true) => {
+            let mut statement = connection.prepare(format!(
+                "SELECT c.path, c.heading, c.line, 0.0, c.text FROM chunks c
+                 WHERE 1{}
+                 ORDER BY c.path, c.line",
+                filters
+            ))?;
+            for (i, value) in values.iter().enumerate() {
+                statement.bind((i + 1, *value))?;
+            }
+            statement
+        }
+        (false, _) => {
+            let mut statement = connection.prepare(format!(
+                // bm25() is lower for better matches; flip it so higher is better everywhere
+                "SELECT c.path, c.heading, c.line, -bm25(chunks_fts),
+                        highlight(chunks_fts, 0, char(2), char(3))
+                 FROM chunks_fts JOIN chunks c ON c.id = chunks_fts.rowid
+                 WHERE chunks_fts MATCH ?{}
+                 ORDER BY bm25(chunks_fts)",
+                filters
+            ))?;
+            statement.bind((1, query.fts.as_str()))?;
+            for (i, value) in values.iter().enumerate() {
+                statement.bind((i + 2, *value))?;
+            }
+            statement
+        }
+    };
+
+    let mut hits: Vec<SearchHit> = Vec::new();
+    while let State::Row = statement.next()? {
+        let path = statement.read::<String, _>(0)?;
+        if hits.iter().any(|hit| hit.path == path) {
+            continue;
+        }
+        let (snippet, snippets) = hit_snippets(&statement.read::<String, _>(4)?, options);
+        hits.push(SearchHit {
+            path,
+            heading: statement.read::<String, _>(1)?,
+            line: statement.read::<i64, _>(2)? as usize,
+            score: statement.read::<f64, _>(3)?,
+            snippet,
+            snippets,
+            ..Default::default()
+        });
+        if hits.len() == limit {
+            break;
+        }
+    }
+    Ok(hits)
+}
+
+/// Metadata the plugins and scripts extracted from `path` when it was last indexed.
+pub fn stored_metadata(
+    connection: &Connection,
+    path: &str,
+) -> Result<Map<String, Value>, Box<dyn Error>> {
+    let mut statement =
+        connection.prepare("SELECT key, value FROM metadata WHERE path = ? ORDER BY key")?;
+    statement.bind((1, path))?;
+    let mut fields = Map::new();
+    while let State::Row = statement.next()? {
+        let value = serde_json::from_str(&statement.read::<String, _>(1)?)?;
+        fields.insert(statement.read::<String, _>(0)?, value);
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+pub fn test_connection(notes: &[Note]) -> Connection {
+    let connection = sqlite::open(":memory:").unwrap();
+    ensure_schema(&connection).unwrap();
+    update(&connection, notes).unwrap();
+    connection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    #[test]
+    fn test_chunk_note() {
+        let sections = note(
+            "A.md",
+            "---\ntags: [x]\n---\nIntro\n\n# First\nbody\n```\n# not a heading\n```\n## Second\n\nmore\n",
+        );
+        let chunks = chunk_note(&sections, 1500);
+        let summary: Vec<(&str, usize, &str)> = chunks
+            .iter()
+            .map(|c| (c.heading.as_str(), c.line, c.text.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("", 4, "Intro"),
+                ("First", 7, "body\n```\n# not a heading\n```"),
+                ("Second", 13, "more"),
+            ]
+        );
+
+        let long = note("B.md", "one one one\n\ntwo two two\n\nthree\n");
+        assert_eq!(chunk_note(&long, 10).len(), 3);
+    }
+
+    #[test]
+    fn test_update_and_keyword_search() {
+        let mut notes = vec![
+            note("Rust.md", "# Ownership\nBorrowing rules in Rust.\n"),
+            note("Garden.md", "Tomatoes need sun. Rust fungus on leaves.\n"),
+        ];
+        let connection = test_connection(&notes);
+
+        let hits =
+            keyword_search(&connection, "rust borrowing", 10, SnippetOptions::default()).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, "Rust.md");
+        assert_eq!(hits[0].heading, "Ownership");
+        assert_eq!(hits[0].snippet, "**Borrowing** rules in **Rust**.");
+        assert_eq!(hits[0].snippets[0].highlights, [[0, 9], [19, 23]]);
+        assert_eq!(
+            keyword_search(&connection, "rust", 10, SnippetOptions::default())
+                .unwrap()
+                .len(),
+            2
+        );
+
+        notes[1] = note("Garden.md", "Only tomatoes now.\n");
+        notes[0].path = PathBuf::from("Lang/Rust.md");
+        notes.push(note("New.md", "new\n"));
+        let stats = update(&connection, &notes).unwrap();
+        assert_eq!(
+            stats,
+            IndexStats {
+                added: vec![String::from("New.md")],
+                modified: vec![String::from("Garden.md")],
+                removed: Vec::new(),
+                renamed: vec![(String::from("Rust.md"), String::from("Lang/Rust.md"))],
+            }
+        );
+        assert_eq!(
+            stats.to_string(),
+            "1 added, 1 modified, 0 removed, 1 renamed"
+        );
+        notes.remove(0);
+        assert_eq!(
+            update(&connection, &notes).unwrap().removed,
+            ["Lang/Rust.md"]
+        );
+        assert!(
+            keyword_search(&connection, "rust", 10, SnippetOptions::default())
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(update(&connection, &notes).unwrap(), IndexStats::default());
+    }
+
+    #[test]
+    fn test_schema_bump_reindexes_notes() {
+        let notes = vec![note("A.md", "one\n"), note("B.md", "two\n")];
+        let connection = test_connection(&notes);
+        mark_indexed(&connection, "talk.mp3", "media:1:2").unwrap();
+        assert!(update(&connection, &notes).unwrap().is_empty());
+
+        // As left by a build with an older set of extractors
+        connection.execute("PRAGMA user_version = 0").unwrap();
+        ensure_schema(&connection).unwrap();
+        let stats = update(&connection, &notes).unwrap();
+        assert_eq!(stats.modified, vec!["A.md", "B.md"]);
+        assert!(update(&connection, &notes).unwrap().is_empty());
+
+        let mut statement = connection
+            .prepare("SELECT hash FROM indexed_files WHERE path = 'talk.mp3'")
+            .unwrap();
+        statement.next().unwrap();
+        assert_eq!(statement.read::<String, _>(0).unwrap(), "media:1:2");
+    }
+
+    #[test]
+    fn test_indexed_notes() {
+        let connection = test_connection(&[note("b/B.md", "two\n"), note("A.md", "one\n")]);
+        mark_indexed(&connection, "talk.mp3", "media:1:2").unwrap();
+        assert_eq!(
+            indexed_notes(&connection).unwrap(),
+            [PathBuf::from("A.md"), PathBuf::from("b/B.md")]
+        );
+    }
+
+    #[test]
+    fn test_field_scoped_search() {
+        let notes = vec![
+            note(
+                "Projects/Acme.md",
+                "---\nclient: [ACME, Initech]\n---\n# Design review\nNotes on the budget.\n",
+            ),
+            note(
+                "Projects/Other.md",
+                "# Budget\nA design review elsewhere.\n",
+            ),
+            note(
+                "Journal/Day.md",
+                "---\nclient: acme\n---\nReview of the design.\n",
+            ),
+        ];
+        let connection = test_connection(&notes);
+        let paths = |query: &str| -> Vec<String> {
+            let mut paths: Vec<String> =
+                keyword_search(&connection, query, 10, SnippetOptions::default())
+                    .unwrap()
+                    .into_iter()
+                    .map(|hit| hit.path)
+                    .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(paths(r#"heading:"design review""#), ["Projects/Acme.md"]);
+        assert_eq!(paths("body:budget"), ["Projects/Acme.md"]);
+        assert_eq!(paths("review path:Journal/"), ["Journal/Day.md"]);
+        assert_eq!(
+            paths("frontmatter.client:acme"),
+            ["Journal/Day.md", "Projects/Acme.md"]
+        );
+        assert_eq!(
+            paths("design frontmatter.client:initech"),
+            ["Projects/Acme.md"]
+        );
+
+        let query = SearchQuery::parse("title:x path:/a/ frontmatter.k:v").unwrap();
+        assert_eq!(query.fts, "title : \"x\"");
+        assert_eq!(query.paths, ["a/"]);
+        assert_eq!(query.properties, [(String::from("k"), String::from("v"))]);
+    }
+
+    #[test]
+    fn test_snippets() {
+        let marked = "one \u{2}two\u{3} three four five\nsix seven \u{2}eight\u{3} nine ten";
+        let options = SnippetOptions { words: 4, count: 2 };
+        let found = snippets(marked, options);
+        let texts: Vec<&str> = found.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["one two three four…", "…seven eight nine ten"]);
+        assert_eq!(found[0].highlights, [[4, 7]]);
+        assert_eq!(found[1].marked(), "…seven **eight** nine ten");
+
+        let plain = snippets("no matches here", SnippetOptions { words: 2, count: 3 });
+        assert_eq!(plain[0].text, "no matches…");
+        assert!(plain[0].highlights.is_empty());
+    }
+}
@@ -0,0 +1,607 @@
+//! Change journal for exchanging vault changes between machines without a sync service.
+//! Each index refresh appends the notes it saw created, changed or deleted to an
+//! append-only journal in the cache, with the revision (content hash) before and after.
+//! `journal export` bundles the changes after a sequence number into a JSON file that can
+//! travel any way at all, and `journal apply` replays one on the other machine: a change
+//! applies cleanly when the local note is still at the revision it was made from, and
+//! otherwise both versions are kept between conflict markers. Rows older than
+//! `journal.keep_days` that a later row of the same note supersedes are compacted away.
+
+use crate::capture;
+use crate::changeset::ChangeSet;
+use crate::cli::{ChangeArgs, JournalApplyArgs, JournalCommand, JournalExportArgs};
+use crate::config::{AppConfig, JournalConfig};
+use crate::conflicts;
+use crate::data::Note;
+use crate::index::{self, IndexStats};
+use crate::search;
+use crate::trash;
+use crate::util;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlite::{Connection, State};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs,
+    path::Path,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Put,
+    Delete,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Put => "put",
+            Op::Delete => "delete",
+        }
+    }
+}
+
+/// A note's net change over a stretch of the journal
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub op: Op,
+    /// Revision the change was made from; `None` for a note that did not exist
+    pub base: Option<String>,
+    /// Revision after the change; `None` for deletions
+    pub hash: Option<String>,
+    /// The note's text after the change, for puts
+    pub content: Option<String>,
+}
+
+/// What `journal export` writes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Bundle {
+    /// Name of the machine the changes come from
+    pub origin: String,
+    /// Last journal sequence number included; the next export continues after it
+    pub until: i64,
+    pub changes: Vec<Change>,
+}
+
+/// Outcome of applying a bundle
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Applied {
+    pub written: usize,
+    pub deleted: usize,
+    /// Notes both machines changed
+    pub conflicts: Vec<String>,
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS journal (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL,
+            op TEXT NOT NULL,
+            base TEXT,
+            hash TEXT,
+            at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS journal_path ON journal (path, seq);
+        CREATE TABLE IF NOT EXISTS journal_heads (
+            path TEXT PRIMARY KEY,
+            hash TEXT NOT NULL
+        );
+        -- Heads from before revisions were SHA-256; the notes are journaled afresh
+        DELETE FROM journal_heads WHERE length(hash) <> 64;",
+    )
+}
+
+/// The revision of a note's text: its SHA-256 in hex.
+pub fn revision(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn heads(connection: &Connection) -> Result<HashMap<String, String>, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT path, hash FROM journal_heads")?;
+    let mut heads = HashMap::new();
+    while let State::Row = statement.next()? {
+        heads.insert(
+            statement.read::<String, _>(0)?,
+            statement.read::<String, _>(1)?,
+        );
+    }
+    Ok(heads)
+}
+
+fn append(
+    connection: &Connection,
+    path: &str,
+    base: Option<&str>,
+    hash: Option<&str>,
+    at: i64,
+) -> Result<(), sqlite::Error> {
+    let op = match hash {
+        Some(_) => Op::Put,
+        None => Op::Delete,
+    };
+    let mut statement = connection
+        .prepare("INSERT INTO journal (path, op, base, hash, at) VALUES (?, ?, ?, ?, ?)")?;
+    statement.bind((1, path))?;
+    statement.bind((2, op.as_str()))?;
+    statement.bind((3, base))?;
+    statement.bind((4, hash))?;
+    statement.bind((5, at))?;
+    statement.next()?;
+
+    let mut statement = match hash {
+        Some(hash) => {
+            let mut statement = connection
+                .prepare("INSERT OR REPLACE INTO journal_heads (path, hash) VALUES (?, ?)")?;
+            statement.bind((2, hash))?;
+            statement
+        }
+        None => connection.prepare("DELETE FROM journal_heads WHERE path = ?")?,
+    };
+    statement.bind((1, path))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Appends the changes an index refresh found to the journal, returning how many. The
+/// first time every note is journaled as created, so a first export carries the vault.
+pub fn record(
+    connection: &Connection,
+    notes: &[Note],
+    stats: &IndexStats,
+    at: Timestamp,
+) -> Result<usize, sqlite::Error> {
+    ensure_schema(connection)?;
+    let heads = heads(connection)?;
+    let by_path: HashMap<String, &Note> = notes
+        .iter()
+        .map(|note| (note.path.to_string_lossy().replace('\\', "/"), note))
+        .collect();
+    let mut touched: Vec<&String> = match heads.is_empty() {
+        true => by_path.keys().collect(),
+        false => stats
+            .added
+            .iter()
+            .chain(&stats.modified)
+            .chain(stats.removed.iter())
+            .chain(stats.renamed.iter().flat_map(|(from, to)| [from, to]))
+            .collect(),
+    };
+    touched.sort();
+    touched.dedup();
+
+    let at = at.as_millisecond();
+    let mut recorded = 0;
+    connection.execute("BEGIN")?;
+    for path in touched {
+        let base = heads.get(path).map(String::as_str);
+        let hash = by_path.get(path).map(|note| revision(&note.content));
+        if base != hash.as_deref() {
+            append(connection, path, base, hash.as_deref(), at)?;
+            recorded += 1;
+        }
+    }
+    connection.execute("COMMIT")?;
+    Ok(recorded)
+}
+
+/// Deletes the rows journaled more than `keep_days` before `now` that a later row of the
+/// same note supersedes; 0 keeps them all. Each note keeps its latest row, so an export
+/// from before the cutoff still carries every note, though from a later base that may
+/// mark more conflicts.
+pub fn compact(
+    connection: &Connection,
+    keep_days: u32,
+    now: Timestamp,
+) -> Result<(), sqlite::Error> {
+    if keep_days == 0 {
+        return Ok(());
+    }
+    let cutoff = now
+        .as_millisecond()
+        .saturating_sub(i64::from(keep_days) * 86_400_000);
+    let mut statement = connection.prepare(
+        "DELETE FROM journal WHERE at < ? AND EXISTS (
+            SELECT 1 FROM journal AS later
+            WHERE later.path = journal.path AND later.seq > journal.seq
+        )",
+    )?;
+    statement.bind((1, cutoff))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The net change of each note journaled after `since`, with the last sequence number.
+/// Puts carry the note's text as read from `vault_path`.
+pub fn changes_since(
+    connection: &Connection,
+    vault_path: &Path,
+    since: i64,
+) -> Result<(Vec<Change>, i64), Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let mut statement = connection
+        .prepare("SELECT seq, path, op, base, hash FROM journal WHERE seq > ? ORDER BY seq")?;
+    statement.bind((1, since))?;
+    let mut until = since;
+    let mut net: BTreeMap<String, Change> = BTreeMap::new();
+    while let State::Row = statement.next()? {
+        until = statement.read::<i64, _>(0)?;
+        let path = statement.read::<String, _>(1)?;
+        let op = match statement.read::<String, _>(2)?.as_str() {
+            "delete" => Op::Delete,
+            _ => Op::Put,
+        };
+        let base = statement.read::<Option<String>, _>(3)?;
+        let hash = statement.read::<Option<String>, _>(4)?;
+        net.entry(path.clone())
+            .and_modify(|change| {
+                change.op = op;
+                change.hash = hash.clone();
+            })
+            .or_insert(Change {
+                path,
+                op,
+                base,
+                hash,
+                content: None,
+            });
+    }
+
+    let mut changes = Vec::new();
+    for (path, mut change) in net {
+        // Changed and changed back
+        if change.base == change.hash {
+            continue;
+        }
+        if change.op == Op::Put {
+            let content = fs::read_to_string(vault_path.join(&path))?;
+            change.hash = Some(revision(&content));
+            change.content = Some(content);
+        }
+        changes.push(change);
+    }
+    Ok((changes, until))
+}
+
+/// This machine's name in bundles.
+fn origin(settings: &JournalConfig) -> String {
+    settings
+        .name
+        .clone()
+        .or_else(|| gethostname::gethostname().into_string().ok())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("remote"))
+}
+
+/// Replays `bundle` onto the vault, or previews it as `args` asks. Writes go through one
+/// change set; deletions move the notes to the trash.
+pub fn apply(
+    vault_path: &Path,
+    bundle: &Bundle,
+    args: &ChangeArgs,
+) -> Result<Applied, Box<dyn Error>> {
+    let mut applied = Applied::default();
+    let mut edits = ChangeSet::new();
+    let mut deletions = Vec::new();
+    for change in &bundle.changes {
+        let rel_path = Path::new(&change.path);
+        capture::ensure_inside_vault(rel_path)?;
+        let local = fs::read_to_string(vault_path.join(rel_path)).ok();
+        let local_hash = local.as_deref().map(revision);
+        if local_hash == change.hash {
+            continue;
+        }
+        let clean = local_hash == change.base;
+        match (change.op, &change.content) {
+            (Op::Put, Some(content)) => {
+                let after = match (&local, clean) {
+                    (Some(local), false) => {
+                        applied.conflicts.push(change.path.clone());
+                        // Either side is still marked up from an earlier conflict; merging
+                        // again would nest the markers and send them back and forth
+                        if conflicts::has_markers(local) || conflicts::has_markers(content) {
+                            continue;
+                        }
+                        let label = format!("{} ({})", change.path, bundle.origin);
+                        conflicts::merge_with_markers(local, content, &label)
+                    }
+                    _ => content.clone(),
+                };
+                edits.propose(rel_path, local, after);
+                applied.written += 1;
+            }
+            (Op::Put, None) => {
+                return Err(format!("The change to '{}' carries no text", change.path).into());
+            }
+            (Op::Delete, _) if local.is_none() => {}
+            (Op::Delete, _) if clean => {
+                deletions.push(rel_path);
+                applied.deleted += 1;
+            }
+            // Deleted there but edited here: the edit wins
+            (Op::Delete, _) => applied.conflicts.push(change.path.clone()),
+        }
+    }
+    edits.finish(vault_path, args, "apply journal changes", false)?;
+    if args.dry_run {
+        for rel_path in &deletions {
+            println!("Would delete {}", rel_path.display());
+        }
+        return Ok(applied);
+    }
+    for rel_path in deletions {
+        trash::move_to_trash(vault_path, rel_path)?;
+        log::info!("Moved {} to the trash", rel_path.display());
+    }
+    Ok(applied)
+}
+
+fn run_export(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &JournalExportArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    let (changes, until) = changes_since(&connection, vault_path, args.since)?;
+    let bundle = Bundle {
+        origin: origin(&config.journal),
+        until,
+        changes,
+    };
+    let json = serde_json::to_string_pretty(&bundle)?;
+    match &args.out {
+        Some(out) => {
+            util::safe_write(out, json, false)?;
+            log::info!(
+                "Wrote {} change(s) to {}",
+                bundle.changes.len(),
+                out.display()
+            );
+        }
+        None => println!("{}", json),
+    }
+    log::info!(
+        "Exported the journal up to {}; export with --since {} next time",
+        until,
+        until
+    );
+    Ok(())
+}
+
+/// Applies a bundle, returning the number of conflicts left to resolve.
+fn run_apply(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &JournalApplyArgs,
+) -> Result<usize, Box<dyn Error>> {
+    let bundle: Bundle = serde_json::from_str(&fs::read_to_string(&args.bundle)?)?;
+    let applied = apply(vault_path, &bundle, &args.changes)?;
+    if !args.changes.dry_run {
+        // Journal the applied changes now, so they are not mistaken for local edits later
+        let connection = index::open(config)?;
+        search::refresh_index(&connection, vault_path, config, false)?;
+    }
+    log::info!(
+        "{} note(s) written and {} deleted from {}",
+        applied.written,
+        applied.deleted,
+        bundle.origin
+    );
+    for path in &applied.conflicts {
+        log::warn!("Both machines changed {}", path);
+    }
+    Ok(applied.conflicts.len())
+}
+
+pub fn run_journal(
+    vault_path: &Path,
+    config: &AppConfig,
+    command: &JournalCommand,
+) -> Result<usize, Box<dyn Error>> {
+    match command {
+        JournalCommand::Export(args) => run_export(vault_path, config, args).map(|_| 0),
+        JournalCommand::Apply(args) => run_apply(vault_path, config, args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::OutputFormat;
+    use std::path::PathBuf;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    #[test]
+    fn test_record_and_export() {
+        let vault = tempfile::tempdir().unwrap();
+        let connection = sqlite::open(":memory:").unwrap();
+        let at = Timestamp::UNIX_EPOCH;
+        fs::write(vault.path().join("A.md"), "one").unwrap();
+        fs::write(vault.path().join("B.md"), "two").unwrap();
+        let notes = vec![note("A.md", "one"), note("B.md", "two")];
+        // Everything is journaled the first time
+        assert_eq!(
+            record(&connection, &notes, &IndexStats::default(), at).unwrap(),
+            2
+        );
+        let (_, first) = changes_since(&connection, vault.path(), 0).unwrap();
+
+        fs::write(vault.path().join("A.md"), "one more").unwrap();
+        fs::remove_file(vault.path().join("B.md")).unwrap();
+        let notes = vec![note("A.md", "one more")];
+        let stats = IndexStats {
+            modified: vec![String::from("A.md")],
+            removed: vec![String::from("B.md")],
+            ..IndexStats::default()
+        };
+        assert_eq!(record(&connection, &notes, &stats, at).unwrap(), 2);
+
+        let (changes, until) = changes_since(&connection, vault.path(), first).unwrap();
+        assert_eq!(until, 4);
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    path: String::from("A.md"),
+                    op: Op::Put,
+                    base: Some(revision("one")),
+                    hash: Some(revision("one more")),
+                    content: Some(String::from("one more")),
+                },
+                Change {
+                    path: String::from("B.md"),
+                    op: Op::Delete,
+                    base: Some(revision("two")),
+                    hash: None,
+                    content: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_marks_conflicts() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("Clean.md"), "old\n").unwrap();
+        fs::write(vault.path().join("Both.md"), "mine\n").unwrap();
+        fs::write(vault.path().join("Gone.md"), "bye\n").unwrap();
+        let put = |path: &str, base: &str, content: &str| Change {
+            path: path.to_string(),
+            op: Op::Put,
+            base: Some(revision(base)),
+            hash: Some(revision(content)),
+            content: Some(content.to_string()),
+        };
+        let bundle = Bundle {
+            origin: String::from("laptop"),
+            until: 3,
+            changes: vec![
+                put("Clean.md", "old\n", "new\n"),
+                put("Both.md", "base\n", "theirs\n"),
+                Change {
+                    path: String::from("Gone.md"),
+                    op: Op::Delete,
+                    base: Some(revision("bye\n")),
+                    hash: None,
+                    content: None,
+                },
+            ],
+        };
+        let args = ChangeArgs {
+            dry_run: false,
+            changes_format: OutputFormat::Text,
+        };
+        let applied = apply(vault.path(), &bundle, &args).unwrap();
+        assert_eq!(applied.written, 2);
+        assert_eq!(applied.deleted, 1);
+        assert_eq!(applied.conflicts, vec!["Both.md"]);
+        let read = |name: &str| fs::read_to_string(vault.path().join(name)).unwrap();
+        assert_eq!(read("Clean.md"), "new\n");
+        assert_eq!(
+            read("Both.md"),
+            "<<<<<<< original\nmine\n=======\ntheirs\n>>>>>>> Both.md (laptop)\n"
+        );
+        assert!(!vault.path().join("Gone.md").exists());
+
+        // Applying again changes nothing
+        let again = apply(vault.path(), &bundle, &args).unwrap();
+        assert_eq!(again.written, 0);
+        assert_eq!(again.conflicts, vec!["Both.md"]);
+    }
+
+    #[test]
+    fn test_marked_up_notes_are_not_merged_again() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("Both.md"), "mine\n").unwrap();
+        // The other machine still has this note marked up from an earlier conflict
+        let theirs = conflicts::merge_with_markers("base\n", "mine\n", "Both.md (here)");
+        let bundle = Bundle {
+            origin: String::from("laptop"),
+            until: 1,
+            changes: vec![Change {
+                path: String::from("Both.md"),
+                op: Op::Put,
+                base: Some(revision("base\n")),
+                hash: Some(revision(&theirs)),
+                content: Some(theirs),
+            }],
+        };
+        let args = ChangeArgs {
+            dry_run: false,
+            changes_format: OutputFormat::Text,
+        };
+        let applied = apply(vault.path(), &bundle, &args).unwrap();
+        assert_eq!(applied.written, 0);
+        assert_eq!(applied.conflicts, vec!["Both.md"]);
+        let read = fs::read_to_string(vault.path().join("Both.md")).unwrap();
+        assert_eq!(read, "mine\n");
+    }
+
+    #[test]
+    fn test_compact() {
+        let connection = sqlite::open(":memory:").unwrap();
+        let day = |n: i64| Timestamp::from_millisecond(n * 86_400_000).unwrap();
+        let stats = IndexStats {
+            modified: vec![String::from("A.md")],
+            ..IndexStats::default()
+        };
+        record(
+            &connection,
+            &[note("A.md", "1"), note("B.md", "1")],
+            &IndexStats::default(),
+            day(0),
+        )
+        .unwrap();
+        record(
+            &connection,
+            &[note("A.md", "2"), note("B.md", "1")],
+            &stats,
+            day(1),
+        )
+        .unwrap();
+        record(
+            &connection,
+            &[note("A.md", "3"), note("B.md", "1")],
+            &stats,
+            day(50),
+        )
+        .unwrap();
+        compact(&connection, 30, day(60)).unwrap();
+
+        let mut statement = connection
+            .prepare("SELECT seq, path FROM journal ORDER BY seq")
+            .unwrap();
+        let mut rows = Vec::new();
+        while let State::Row = statement.next().unwrap() {
+            rows.push((
+                statement.read::<i64, _>(0).unwrap(),
+                statement.read::<String, _>(1).unwrap(),
+            ));
+        }
+        // A's first two rows are superseded and old; B's only row stays
+        assert_eq!(
+            rows,
+            vec![(2, String::from("B.md")), (4, String::from("A.md"))]
+        );
+    }
+
+    #[test]
+    fn test_revisions_are_sha256() {
+        assert_eq!(
+            revision("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Boards of the Obsidian Kanban plugin: notes with `kanban-plugin` front matter, one
+//! `## Lane` heading per column and a `- [ ] card` item per card. A lane with a
+//! `**Complete**` line under its heading marks the cards in it as done.
+
+use crate::changeset::ChangeSet;
+use crate::cli::{ChangeArgs, KanbanCardsArgs, KanbanCommand, KanbanShowArgs, OutputFormat};
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::frontmatter;
+use crate::index;
+use crate::markdown;
+use crate::resolver::Resolver;
+use crate::search;
+use crate::util;
+
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::Path,
+};
+
+/// Where the plugin keeps a board's settings, after the lanes
+const SETTINGS: &str = "%% kanban:settings";
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Card {
+    pub text: String,
+    /// Checked, or in a lane marked complete
+    pub done: bool,
+    /// Line of the card's checkbox, counting from 1
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Lane {
+    pub name: String,
+    pub complete: bool,
+    pub cards: Vec<Card>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Board {
+    pub path: String,
+    pub title: String,
+    pub lanes: Vec<Lane>,
+}
+
+/// A card as stored in the index, with the board and lane it is on
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CardEntry {
+    pub board: String,
+    pub lane: String,
+    pub text: String,
+    pub done: bool,
+    pub line: usize,
+}
+
+pub fn is_board(note: &Note) -> bool {
+    frontmatter::parse_mapping(&note.content)
+        .ok()
+        .flatten()
+        .is_some_and(|mapping| mapping.contains_key("kanban-plugin"))
+}
+
+/// The checkbox state and text of a `- [ ] card` line.
+fn parse_card(line: &str) -> Option<(bool, &str)> {
+    let rest = line.strip_prefix("- [")?;
+    let mut chars = rest.chars();
+    let mark = chars.next()?;
+    let text = chars.as_str().strip_prefix("] ")?;
+    Some((mark != ' ', text.trim()))
+}
+
+/// The board `note` holds, if it is one.
+pub fn parse(note: &Note) -> Option<Board> {
+    if !is_board(note) {
+        return None;
+    }
+    let mut lanes: Vec<Lane> = Vec::new();
+    for (line_no, _, line) in markdown::body_lines(&note.content) {
+        if line.trim_start().starts_with(SETTINGS) {
+            break;
+        }
+        if let Some(name) = line.strip_prefix("## ") {
+            lanes.push(Lane {
+                name: name.trim().to_string(),
+                complete: false,
+                cards: Vec::new(),
+            });
+            continue;
+        }
+        let Some(lane) = lanes.last_mut() else {
+            continue;
+        };
+        if line.trim() == "**Complete**" {
+            lane.complete = true;
+        } else if let Some((checked, text)) = parse_card(line) {
+            lane.cards.push(Card {
+                text: text.to_string(),
+                done: checked,
+                line: line_no,
+            });
+        } else if line.starts_with(' ') || line.starts_with('\t') {
+            // Indented lines continue the card above
+            if let Some(card) = lane.cards.last_mut() {
+                card.text.push('\n');
+                card.text.push_str(line.trim());
+            }
+        }
+    }
+    for lane in lanes.iter_mut().filter(|lane| lane.complete) {
+        for card in &mut lane.cards {
+            card.done = true;
+        }
+    }
+    Some(Board {
+        path: note.path.to_string_lossy().replace('\\', "/"),
+        title: note.title(),
+        lanes,
+    })
+}
+
+/// Lines of the cards on `note`'s board that are done, checked or in a complete lane, so
+/// task lists agree with the board; none for other notes.
+pub fn done_cards(note: &Note) -> HashSet<usize> {
+    parse(note)
+        .into_iter()
+        .flat_map(|board| board.lanes)
+        .flat_map(|lane| lane.cards)
+        .filter(|card| card.done)
+        .map(|card| card.line)
+        .collect()
+}
+
+/// `content` of a board with each card checked exactly when its lane is marked complete,
+/// as the plugin does when cards are moved. Boards without a complete lane are left alone.
+pub fn sync_checkboxes(note: &Note) -> Option<String> {
+    let board = parse(note)?;
+    if !board.lanes.iter().any(|lane| lane.complete) {
+        return None;
+    }
+    let mut marks = HashMap::new();
+    for lane in &board.lanes {
+        for card in &lane.cards {
+            marks.insert(card.line, if lane.complete { 'x' } else { ' ' });
+        }
+    }
+    let synced = note
+        .content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| match marks.get(&(index + 1)) {
+            Some(mark) => format!("- [{}]{}", mark, &line[line.find(']').unwrap_or(3) + 1..]),
+            None => line.to_string(),
+        })
+        .collect();
+    Some(synced)
+}
+
+/// Stores the cards of `note` in the index, if it is a board.
+pub fn index_cards(connection: &Connection, note: &Note) -> Result<(), sqlite::Error> {
+    let Some(board) = parse(note) else {
+        return Ok(());
+    };
+    for lane in &board.lanes {
+        for card in &lane.cards {
+            let mut statement = connection.prepare(
+                "INSERT INTO kanban_cards (path, lane, line, text, done) VALUES (?, ?, ?, ?, ?)",
+            )?;
+            statement.bind((1, board.path.as_str()))?;
+            statement.bind((2, lane.name.as_str()))?;
+            statement.bind((3, card.line as i64))?;
+            statement.bind((4, card.text.as_str()))?;
+            statement.bind((5, card.done as i64))?;
+            statement.next()?;
+        }
+    }
+    Ok(())
+}
+
+/// Indexed cards, optionally only those in `lane` (any board) or with the given status.
+pub fn stored_cards(
+    connection: &Connection,
+    lane: Option<&str>,
+    done: Option<bool>,
+) -> Result<Vec<CardEntry>, Box<dyn Error>> {
+    let mut statement = connection.prepare(
+        "SELECT path, lane, text, done, line FROM kanban_cards
+         WHERE (?1 IS NULL OR lane = ?1 COLLATE NOCASE) AND (?2 IS NULL OR done = ?2)
+         ORDER BY path, line",
+    )?;
+    statement.bind((1, lane))?;
+    statement.bind((2, done.map(i64::from)))?;
+    let mut cards = Vec::new();
+    while let State::Row = statement.next()? {
+        cards.push(CardEntry {
+            board: statement.read(0)?,
+            lane: statement.read(1)?,
+            text: statement.read(2)?,
+            done: statement.read::<i64, _>(3)? != 0,
+            line: statement.read::<i64, _>(4)? as usize,
+        });
+    }
+    Ok(cards)
+}
+
+fn show(vault_path: &Path, args: &KanbanShowArgs) -> Result<(), Box<dyn Error>> {
+    let resolver = Resolver::from_vault(vault_path)?;
+    let path = resolver
+        .resolve(&args.board, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", args.board))?;
+    let content = fs::read_to_string(vault_path.join(path))?;
+    let note = Note::from_content(path.to_path_buf(), content);
+    let board = parse(&note).ok_or_else(|| format!("{} is not a Kanban board", path.display()))?;
+
+    match args.format {
+        OutputFormat::Text => {
+            for (i, lane) in board.lanes.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                println!("{} ({})", lane.name, lane.cards.len());
+                for card in &lane.cards {
+                    let mark = if card.done { 'x' } else { ' ' };
+                    println!("  [{}] {}", mark, card.text.replace('\n', " "));
+                }
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&board)?),
+        OutputFormat::Ndjson => {
+            for lane in &board.lanes {
+                util::print_ndjson(lane)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn cards(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &KanbanCardsArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    let done = match (args.open, args.done) {
+        (true, _) => Some(false),
+        (_, true) => Some(true),
+        _ => None,
+    };
+    let cards = stored_cards(&connection, args.lane.as_deref(), done)?;
+
+    match args.format {
+        OutputFormat::Text => {
+            for card in &cards {
+                let mark = if card.done { 'x' } else { ' ' };
+                println!(
+                    "{}:{}  {}  [{}] {}",
+                    card.board,
+                    card.line,
+                    card.lane,
+                    mark,
+                    card.text.replace('\n', " ")
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&cards)?),
+        OutputFormat::Ndjson => {
+            for card in &cards {
+                util::print_ndjson(card)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn sync(vault_path: &Path, args: &ChangeArgs) -> Result<(), Box<dyn Error>> {
+    let mut changes = ChangeSet::new();
+    for note in data::load_notes(vault_path)? {
+        if let Some(synced) = sync_checkboxes(&note) {
+            changes.propose(note.path, Some(note.content), synced);
+        }
+    }
+    changes.finish(vault_path, args, "sync kanban cards", false)?;
+    Ok(())
+}
+
+pub fn run_kanban(
+    vault_path: &Path,
+    config: &AppConfig,
+    command: &KanbanCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        KanbanCommand::Show(args) => show(vault_path, args),
+        KanbanCommand::Cards(args) => cards(vault_path, config, args),
+        KanbanCommand::Sync(args) => sync(vault_path, &args.changes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_and_sync() {
+        let note = Note::from_content(
+            PathBuf::from("Boards/Work.md"),
+            String::from(
+                "---\nkanban-plugin: basic\n---\n\n## To do\n\n- [ ] Write docs\n  with examples\n\
+                 - [x] Moved back\n\n## Done\n\n**Complete**\n- [ ] Ship it\n\n\
+                 %% kanban:settings\n```\n{}\n```\n%%\n",
+            ),
+        );
+        let board = parse(&note).unwrap();
+        assert_eq!(board.lanes.len(), 2);
+        assert_eq!(board.lanes[0].cards[0].text, "Write docs\nwith examples");
+        assert!(board.lanes[0].cards[1].done);
+        assert!(board.lanes[1].complete && board.lanes[1].cards[0].done);
+
+        let synced = sync_checkboxes(&note).unwrap();
+        assert!(synced.contains("- [ ] Moved back\n"));
+        assert!(synced.contains("**Complete**\n- [x] Ship it\n"));
+
+        let connection = index::test_connection(&[note]);
+        let open = stored_cards(&connection, Some("to do"), Some(false)).unwrap();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].board, "Boards/Work.md");
+        assert_eq!(
+            stored_cards(&connection, None, Some(true)).unwrap().len(),
+            2
+        );
+
+        let plain = Note::from_content(PathBuf::from("Plain.md"), String::from("## A\n- [ ] x\n"));
+        assert_eq!(parse(&plain), None);
+    }
+
+    #[test]
+    fn test_done_cards() {
+        let note = Note::from_content(
+            PathBuf::from("Board.md"),
+            String::from(
+                "---\nkanban-plugin: basic\n---\n## To do\n- [ ] Open\n- [x] Checked\n\
+                 ## Done\n**Complete**\n- [ ] Moved here\n",
+            ),
+        );
+        let mut lines: Vec<usize> = done_cards(&note).into_iter().collect();
+        lines.sort();
+        assert_eq!(lines, vec![6, 9]);
+        let plain = Note::from_content(PathBuf::from("Plain.md"), String::from("- [x] x\n"));
+        assert!(done_cards(&plain).is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+//! The vault engine behind the `obsidian-rs` command line: parsing, indexing, search and
+//! the servers. With the `ffi` feature, `ffi` exposes the index to C.
+
+pub mod aggregate;
+#[cfg(feature = "server")]
+pub mod api;
+#[cfg(feature = "web")]
+pub mod archive;
+pub mod auth;
+pub mod backup;
+pub mod block_ref;
+pub mod bookmarks;
+pub mod browse;
+pub mod calendar;
+pub mod capture;
+pub mod changeset;
+pub mod check_links;
+pub mod cli;
+#[cfg(feature = "web")]
+pub mod clip;
+pub mod clock;
+pub mod collation;
+pub mod completions;
+pub mod config;
+pub mod conflicts;
+pub mod content_store;
+pub mod context;
+pub mod convert;
+pub mod cors;
+#[cfg(feature = "server")]
+pub mod couch;
+pub mod data;
+pub mod date_query;
+pub mod diff;
+pub mod doctor;
+pub mod docx;
+pub mod embeddings;
+pub mod entities;
+pub mod export;
+pub mod feed;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod flatten;
+pub mod folder_config;
+pub mod frontmatter;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod grep;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod headings;
+pub mod history;
+pub mod hooks;
+pub mod http;
+#[cfg(feature = "images")]
+pub mod images;
+pub mod import;
+pub mod index;
+pub mod journal;
+pub mod kanban;
+pub mod link_to;
+pub mod lint;
+pub mod listing;
+#[cfg(feature = "server")]
+pub mod local_rest;
+pub mod lock;
+pub mod man;
+pub mod markdown;
+pub mod mcp;
+pub mod media;
+pub mod merge;
+pub mod moc;
+pub mod msgpack_rpc;
+#[cfg(feature = "web")]
+pub mod net;
+pub mod note;
+pub mod notify;
+pub mod obsidian_vaults;
+#[cfg(feature = "ocr")]
+pub mod ocr;
+pub mod pdf;
+#[cfg(feature = "pdf-text")]
+pub mod pdf_text;
+pub mod plugins;
+pub mod previews;
+pub mod profiles;
+pub mod query;
+pub mod query_cache;
+pub mod query_table;
+pub mod recency;
+pub mod reminders;
+pub mod remote_backup;
+pub mod render;
+pub mod replace;
+pub mod resolver;
+pub mod review;
+pub mod scheduler;
+pub mod scripts;
+pub mod search;
+pub mod search_query;
+pub mod shell_completions;
+pub mod site;
+pub mod snapshots;
+pub mod status;
+pub mod suggest;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod toc;
+pub mod trash;
+pub mod util;
+pub mod vault_diff;
+pub mod watcher;
+#[cfg(feature = "web-ui")]
+pub mod web_ui;
+pub mod write_gate;
+pub mod writing;
@@ -0,0 +1,237 @@
+//! Link text as Obsidian would write it, following the vault's "New link format" and
+//! "Use [[Wikilinks]]" settings from `.obsidian/app.json`.
+
+use crate::changeset::ChangeSet;
+use crate::cli::{LinkFormat, LinkToArgs, PathFormat};
+use crate::data;
+use crate::markdown;
+use crate::resolver::Resolver;
+
+use std::{
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkSettings {
+    pub style: LinkFormat,
+    pub path: PathFormat,
+}
+
+impl Default for LinkSettings {
+    fn default() -> Self {
+        LinkSettings {
+            style: LinkFormat::Wikilink,
+            path: PathFormat::Shortest,
+        }
+    }
+}
+
+impl LinkSettings {
+    /// The vault's settings; Obsidian's defaults when `app.json` is missing or silent.
+    pub fn from_vault(vault_path: &Path) -> LinkSettings {
+        let mut settings = LinkSettings::default();
+        let app = fs::read_to_string(vault_path.join(".obsidian/app.json"))
+            .ok()
+            .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok());
+        let Some(app) = app else {
+            return settings;
+        };
+        if app["useMarkdownLinks"].as_bool() == Some(true) {
+            settings.style = LinkFormat::Markdown;
+        }
+        settings.path = match app["newLinkFormat"].as_str() {
+            Some("relative") => PathFormat::Relative,
+            Some("absolute") => PathFormat::Absolute,
+            _ => PathFormat::Shortest,
+        };
+        settings
+    }
+}
+
+/// `target` relative to the folder of `source`, both vault-relative.
+fn relative_path(source: &Path, target: &Path) -> PathBuf {
+    let from: Vec<Component> = source
+        .parent()
+        .unwrap_or(Path::new(""))
+        .components()
+        .collect();
+    let to: Vec<Component> = target.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    path
+}
+
+/// Link from `source` to `target` (both vault-relative files), with an optional
+/// `#heading` or `#^block` anchor.
+pub fn link_text(
+    resolver: &Resolver,
+    source: &Path,
+    target: &Path,
+    anchor: Option<&str>,
+    settings: LinkSettings,
+) -> String {
+    let path = match settings.path {
+        PathFormat::Shortest => {
+            let shortest = resolver.shortest_link(target);
+            if data::is_note(target) && !shortest.ends_with(".md") {
+                format!("{}.md", shortest)
+            } else {
+                shortest
+            }
+        }
+        PathFormat::Relative => relative_path(source, target).to_string_lossy().into_owned(),
+        PathFormat::Absolute => target.to_string_lossy().into_owned(),
+    }
+    .replace('\\', "/");
+    let embed = !data::is_note(target);
+    match settings.style {
+        LinkFormat::Wikilink => {
+            let path = path.strip_suffix(".md").unwrap_or(&path);
+            markdown::render_wikilink(path, anchor, None, embed)
+        }
+        LinkFormat::Markdown => {
+            let text = match anchor {
+                Some(anchor) => anchor.trim_start_matches('^').to_string(),
+                None => target
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            };
+            markdown::render_markdown_link(&path, anchor, &text, embed)
+        }
+    }
+}
+
+/// `content` with `link` appended to line `line`, counted from 1, or added as a new last
+/// line when there are fewer lines; lines end in CRLF if the file used CRLF.
+fn append_to_line(content: &str, line: usize, link: &str) -> Result<String, Box<dyn Error>> {
+    let mut lines: Vec<&str> = content.lines().collect();
+    let index = line.checked_sub(1).ok_or("Lines count from 1")?;
+    let updated_line;
+    if index < lines.len() {
+        updated_line = match lines[index].trim_end() {
+            "" => link.to_string(),
+            text => format!("{} {}", text, link),
+        };
+        lines[index] = &updated_line;
+    } else {
+        lines.push(link);
+    }
+    let newline = match content.contains("\r\n") {
+        true => "\r\n",
+        false => "\n",
+    };
+    let mut updated = lines.join(newline);
+    updated.push_str(newline);
+    Ok(updated)
+}
+
+/// Prints the link, or with `--line` appends it to that line of the source note.
+pub fn run_link_to(vault_path: &Path, args: &LinkToArgs) -> Result<(), Box<dyn Error>> {
+    let resolver = Resolver::from_vault(vault_path)?;
+    let resolve = |name: &str| {
+        resolver
+            .resolve(name, Path::new(""))
+            .map(Path::to_path_buf)
+            .ok_or_else(|| format!("No file matches '{}'", name))
+    };
+    let source = resolve(&args.from)?;
+    let (target, anchor) = match args.target.split_once('#') {
+        Some((target, anchor)) => (target, Some(anchor)),
+        None => (args.target.as_str(), None),
+    };
+    let target = match target {
+        "" => source.clone(),
+        target => resolve(target)?,
+    };
+
+    let vault_settings = LinkSettings::from_vault(vault_path);
+    let settings = LinkSettings {
+        style: args.style.unwrap_or(vault_settings.style),
+        path: args.path.unwrap_or(vault_settings.path),
+    };
+    let link = link_text(&resolver, &source, &target, anchor, settings);
+
+    let Some(line) = args.line else {
+        println!("{}", link);
+        return Ok(());
+    };
+    let content = fs::read_to_string(vault_path.join(&source))?;
+    let updated = append_to_line(&content, line, &link)?;
+
+    let mut changes = ChangeSet::new();
+    changes.propose(&source, Some(content), updated);
+    changes.apply(vault_path, "insert links", false)?;
+    println!("{}", link);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_text() {
+        let resolver = Resolver::new(vec![
+            PathBuf::from("Home.md"),
+            PathBuf::from("a/Plan.md"),
+            PathBuf::from("b/Plan.md"),
+            PathBuf::from("b/My Note.md"),
+            PathBuf::from("assets/pic.png"),
+        ]);
+        let source = Path::new("a/Plan.md");
+        let link = |target: &str, anchor, style, path| {
+            link_text(
+                &resolver,
+                source,
+                Path::new(target),
+                anchor,
+                LinkSettings { style, path },
+            )
+        };
+        use LinkFormat::*;
+        use PathFormat::*;
+        assert_eq!(
+            link("b/My Note.md", None, Wikilink, Shortest),
+            "[[My Note]]"
+        );
+        assert_eq!(
+            link("b/Plan.md", Some("Goals"), Wikilink, Shortest),
+            "[[b/Plan#Goals]]"
+        );
+        assert_eq!(
+            link("b/My Note.md", None, Markdown, Relative),
+            "[My Note](../b/My%20Note.md)"
+        );
+        assert_eq!(link("Home.md", None, Wikilink, Absolute), "[[Home]]");
+        assert_eq!(
+            link("assets/pic.png", None, Wikilink, Shortest),
+            "![[pic.png]]"
+        );
+    }
+
+    #[test]
+    fn test_append_to_line() {
+        assert_eq!(
+            append_to_line("# A\nSee\n", 2, "[[B]]").unwrap(),
+            "# A\nSee [[B]]\n"
+        );
+        assert_eq!(
+            append_to_line("# A\r\nSee\r\n", 2, "[[B]]").unwrap(),
+            "# A\r\nSee [[B]]\r\n"
+        );
+        assert_eq!(
+            append_to_line("# A\r\n", 5, "[[B]]").unwrap(),
+            "# A\r\n[[B]]\r\n"
+        );
+        assert!(append_to_line("# A\n", 0, "[[B]]").is_err());
+    }
+}
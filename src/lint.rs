@@ -0,0 +1,475 @@
+use crate::changeset::ChangeSet;
+use crate::cli::{LinkFormat, LintArgs, OutputFormat};
+use crate::config::LintConfig;
+use crate::data;
+use crate::folder_config::FolderConfigs;
+use crate::frontmatter;
+use crate::markdown::{self, Link, LinkStyle};
+use crate::plugins;
+use crate::toc;
+use crate::util;
+
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LintIssue {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub rule: &'static str,
+    pub message: String,
+    /// Whether `lint --fix` can repair this issue
+    pub fixable: bool,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: [{}] {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.rule,
+            self.message
+        )?;
+        if self.fixable {
+            write!(f, " (fixable)")?;
+        }
+        Ok(())
+    }
+}
+
+fn issue(
+    path: &Path,
+    line: usize,
+    column: usize,
+    rule: &'static str,
+    message: String,
+) -> LintIssue {
+    LintIssue {
+        path: path.to_path_buf(),
+        line,
+        column,
+        rule,
+        fixable: matches!(
+            rule,
+            "wikilinks-only" | "heading-increment" | "required-front-matter" | "toc"
+        ),
+        message,
+    }
+}
+
+fn is_internal_markdown_link(link: &Link) -> bool {
+    link.style == LinkStyle::Markdown && !link.is_external()
+}
+
+fn is_kebab_case(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('-').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        })
+}
+
+/// Run every enabled rule against a single note. `rel_path` is only used for reporting.
+pub fn lint_note(rel_path: &Path, content: &str, rules: &LintConfig) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+
+    if rules.kebab_case_filenames {
+        let stem = rel_path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        if !is_kebab_case(stem) {
+            issues.push(issue(
+                rel_path,
+                1,
+                1,
+                "kebab-case-filename",
+                format!("File name '{}' is not kebab-case", stem),
+            ));
+        }
+    }
+
+    if rules.single_h1 {
+        let h1s: Vec<_> = markdown::parse_headings(content)
+            .into_iter()
+            .filter(|h| h.level == 1)
+            .collect();
+        match h1s.as_slice() {
+            [] => issues.push(issue(
+                rel_path,
+                1,
+                1,
+                "single-h1",
+                String::from("Note has no H1 heading"),
+            )),
+            [_] => {}
+            [_, extra @ ..] => {
+                for heading in extra {
+                    issues.push(issue(
+                        rel_path,
+                        heading.line,
+                        1,
+                        "single-h1",
+                        format!("Additional H1 heading '{}'", heading.text),
+                    ));
+                }
+            }
+        }
+    }
+
+    if rules.no_bare_urls {
+        for url in markdown::find_bare_urls(content) {
+            issues.push(issue(
+                rel_path,
+                url.line,
+                url.column,
+                "no-bare-urls",
+                format!("Bare URL '{}' should be a link", url.url),
+            ));
+        }
+    }
+
+    if rules.wikilinks_only {
+        for link in markdown::parse_links(content) {
+            if is_internal_markdown_link(&link) {
+                issues.push(issue(
+                    rel_path,
+                    link.line,
+                    link.column,
+                    "wikilinks-only",
+                    format!("Markdown link to '{}' should be a wikilink", link.target),
+                ));
+            }
+        }
+    }
+
+    if rules.heading_increment {
+        let mut previous: Option<usize> = None;
+        for heading in markdown::parse_headings(content) {
+            if let Some(prev) = previous
+                && heading.level > prev + 1
+            {
+                issues.push(issue(
+                    rel_path,
+                    heading.line,
+                    1,
+                    "heading-increment",
+                    format!("Heading jumps from H{} to H{}", prev, heading.level),
+                ));
+            }
+            previous = Some(heading.level);
+        }
+    }
+
+    if rules.toc
+        && let Some(refreshed) = toc::refresh(content, LinkFormat::Wikilink)
+        && refreshed != content
+    {
+        let line = content
+            .lines()
+            .position(|line| line.trim() == "<!-- toc -->")
+            .unwrap_or_default();
+        issues.push(issue(
+            rel_path,
+            line + 1,
+            1,
+            "toc",
+            String::from("Table of contents does not match the headings"),
+        ));
+    }
+
+    if !rules.required_front_matter.is_empty() {
+        match frontmatter::missing_keys(content, &rules.required_front_matter) {
+            Ok(missing) => {
+                for key in missing {
+                    issues.push(issue(
+                        rel_path,
+                        1,
+                        1,
+                        "required-front-matter",
+                        format!("Front matter is missing '{}'", key),
+                    ));
+                }
+            }
+            Err(e) => {
+                let mut invalid = issue(
+                    rel_path,
+                    1,
+                    1,
+                    "required-front-matter",
+                    format!("Front matter could not be parsed: {}", e),
+                );
+                invalid.fixable = false;
+                issues.push(invalid);
+            }
+        }
+    }
+
+    issues
+}
+
+/// Lowers headings that skip a level so each is at most one deeper than the one before.
+pub fn normalize_heading_levels(content: &str) -> String {
+    let mut new_levels = HashMap::new();
+    let mut previous: Option<usize> = None;
+    for heading in markdown::parse_headings(content) {
+        let level = match previous {
+            Some(prev) if heading.level > prev + 1 => prev + 1,
+            _ => heading.level,
+        };
+        if level != heading.level {
+            new_levels.insert(heading.line, (heading.level, level));
+        }
+        previous = Some(level);
+    }
+    if new_levels.is_empty() {
+        return content.to_string();
+    }
+
+    content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| match new_levels.get(&(index + 1)) {
+            Some((old, new)) => line.replacen(&"#".repeat(*old), &"#".repeat(*new), 1),
+            None => line.to_string(),
+        })
+        .collect()
+}
+
+/// Applies the fix for every enabled fixable rule and returns the new note content.
+pub fn fix_note(content: &str, rules: &LintConfig) -> Result<String, Box<dyn Error>> {
+    let mut fixed = content.to_string();
+
+    if rules.wikilinks_only {
+        let edits = markdown::parse_links(&fixed)
+            .into_iter()
+            .filter(is_internal_markdown_link)
+            .map(|link| (link.span.clone(), markdown::to_wikilink(&link)))
+            .collect();
+        fixed = markdown::replace_spans(&fixed, edits);
+    }
+
+    if rules.heading_increment {
+        fixed = normalize_heading_levels(&fixed);
+    }
+
+    if !rules.required_front_matter.is_empty() {
+        fixed = frontmatter::add_missing_keys(&fixed, &rules.required_front_matter)?;
+    }
+
+    if rules.toc
+        && let Some(refreshed) = toc::refresh(&fixed, LinkFormat::Wikilink)
+    {
+        fixed = refreshed;
+    }
+
+    Ok(fixed)
+}
+
+/// Notes in the vault as (absolute path, path relative to the vault)
+fn vault_notes(vault_path: &Path) -> Result<Vec<(PathBuf, PathBuf)>, Box<dyn Error>> {
+    let mut notes = Vec::new();
+    for file in data::traverse_vault(vault_path)? {
+        if data::is_note(&file) {
+            let rel_path = util::get_relative_path(&file, vault_path)?;
+            notes.push((file, rel_path));
+        }
+    }
+    Ok(notes)
+}
+
+/// The fixes for every note with fixable issues.
+pub fn fix_vault(vault_path: &Path, rules: &LintConfig) -> Result<ChangeSet, Box<dyn Error>> {
+    let folders = FolderConfigs::load(vault_path)?;
+    let mut changes = ChangeSet::new();
+    for (file, rel_path) in vault_notes(vault_path)? {
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(e) => {
+                log::warn!("Skipping '{}': {}", file.display(), e);
+                continue;
+            }
+        };
+        match fix_note(&content, &folders.lint_rules(rules, &rel_path)) {
+            Ok(fixed) => changes.propose(rel_path, Some(content), fixed),
+            Err(e) => log::warn!("Cannot fix '{}': {}", rel_path.display(), e),
+        }
+    }
+    Ok(changes)
+}
+
+pub fn lint_vault(vault_path: &Path, rules: &LintConfig) -> Result<Vec<LintIssue>, Box<dyn Error>> {
+    let folders = FolderConfigs::load(vault_path)?;
+    let mut issues = Vec::new();
+    for (file, rel_path) in vault_notes(vault_path)? {
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(e) => {
+                log::warn!("Skipping '{}': {}", file.display(), e);
+                continue;
+            }
+        };
+        let rules = folders.lint_rules(rules, &rel_path);
+        issues.extend(lint_note(&rel_path, &content, &rules));
+        if plugins::active() {
+            let note = data::Note::from_content(rel_path.clone(), content);
+            for (plugin, line, message) in plugins::lint(&note) {
+                let message = format!("[{}] {}", plugin, message);
+                issues.push(issue(&rel_path, line, 1, "plugin", message));
+            }
+        }
+    }
+    Ok(issues)
+}
+
+/// Lint the whole vault (fixing first if asked) and print the issues, returning how many remain.
+pub fn run_lint(
+    vault_path: &Path,
+    rules: &LintConfig,
+    args: &LintArgs,
+) -> Result<usize, Box<dyn Error>> {
+    if args.fix {
+        fix_vault(vault_path, rules)?.finish(
+            vault_path,
+            &args.changes,
+            "fix notes",
+            args.backup,
+        )?;
+    } else if args.changes.dry_run {
+        return Err("--dry-run only applies to --fix".into());
+    }
+
+    let issues = lint_vault(vault_path, rules)?;
+    match args.format {
+        OutputFormat::Text => {
+            for issue in &issues {
+                println!("{}", issue);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&issues)?),
+        OutputFormat::Ndjson => {
+            for issue in &issues {
+                util::print_ndjson(issue)?;
+            }
+        }
+    }
+    log::info!("Lint finished with {} issue(s).", issues.len());
+    Ok(issues.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_rules() -> LintConfig {
+        LintConfig {
+            kebab_case_filenames: true,
+            single_h1: true,
+            no_bare_urls: true,
+            wikilinks_only: true,
+            heading_increment: true,
+            toc: true,
+            required_front_matter: Vec::new(),
+        }
+    }
+
+    /// Every rule, with notes required to list their tags
+    fn fixing_rules() -> LintConfig {
+        LintConfig {
+            required_front_matter: vec![String::from("tags")],
+            ..all_rules()
+        }
+    }
+
+    fn rules_hit(path: &str, content: &str) -> Vec<&'static str> {
+        lint_note(Path::new(path), content, &all_rules())
+            .into_iter()
+            .map(|i| i.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_note() {
+        assert!(rules_hit("notes/clean-note-2.md", "# Title\n\nSee [[other]].\n").is_empty());
+    }
+
+    #[test]
+    fn test_kebab_case_filename() {
+        assert_eq!(rules_hit("My Note.md", "# T\n"), vec!["kebab-case-filename"]);
+        assert_eq!(rules_hit("double--dash.md", "# T\n"), vec!["kebab-case-filename"]);
+    }
+
+    #[test]
+    fn test_single_h1() {
+        assert_eq!(rules_hit("a.md", "## Only h2\n"), vec!["single-h1"]);
+        let issues = lint_note(Path::new("a.md"), "# One\n# Two\n", &all_rules());
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 2);
+    }
+
+    #[test]
+    fn test_bare_urls_and_markdown_links() {
+        let content = "# T\nhttps://example.com\n[x](other.md) [y](https://ok.com)\n";
+        assert_eq!(rules_hit("a.md", content), vec!["no-bare-urls", "wikilinks-only"]);
+    }
+
+    #[test]
+    fn test_disabled_rules_are_skipped() {
+        let rules = LintConfig {
+            kebab_case_filenames: false,
+            single_h1: false,
+            no_bare_urls: false,
+            wikilinks_only: false,
+            heading_increment: false,
+            toc: false,
+            required_front_matter: Vec::new(),
+        };
+        assert!(lint_note(Path::new("Bad Name.md"), "https://x.y", &rules).is_empty());
+    }
+
+    #[test]
+    fn test_heading_increment_and_front_matter() {
+        let content = "# A\n### C\n";
+        let hit: Vec<&str> = lint_note(Path::new("a.md"), content, &fixing_rules())
+            .into_iter()
+            .map(|i| i.rule)
+            .collect();
+        assert_eq!(hit, vec!["heading-increment", "required-front-matter"]);
+    }
+
+    #[test]
+    fn test_fix_note() {
+        let content = "# A\n### C\n#### D\nSee [Other](Other%20Note.md) and https://x.y\n";
+        let fixed = fix_note(content, &fixing_rules()).unwrap();
+        assert_eq!(
+            fixed,
+            "---\ntags:\n---\n# A\n## C\n### D\nSee [[Other Note|Other]] and https://x.y\n"
+        );
+        let remaining = lint_note(Path::new("a.md"), &fixed, &fixing_rules());
+        assert!(remaining.iter().all(|issue| !issue.fixable));
+        assert_eq!(remaining[0].rule, "no-bare-urls");
+    }
+
+    #[test]
+    fn test_fix_toc() {
+        let content = "# A\n<!-- toc -->\n<!-- /toc -->\n## C\n### D\n";
+        assert!(rules_hit("a.md", content).contains(&"toc"));
+        let fixed = fix_note(content, &all_rules()).unwrap();
+        assert_eq!(
+            fixed,
+            "# A\n<!-- toc -->\n- [[#C]]\n  - [[#D]]\n<!-- /toc -->\n## C\n### D\n"
+        );
+        assert!(!rules_hit("a.md", &fixed).contains(&"toc"));
+    }
+}
@@ -0,0 +1,348 @@
+//! Paging, sorting and field selection for list results (notes, search hits, backlinks,
+//! tags), shared by the library functions and the HTTP API.
+
+use crate::collation;
+use crate::config::NoteStatus;
+use crate::data::Note;
+use crate::status;
+
+use jiff::Timestamp;
+use serde::Serialize;
+use serde_json::Value;
+use std::{cmp::Ordering, collections::BTreeMap, collections::HashMap, error::Error};
+
+/// Items returned when a request sets no `limit`
+pub static DEFAULT_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Title,
+    Created,
+    Modified,
+    Size,
+    /// The order the items were produced in, e.g. search relevance
+    Rank,
+}
+
+impl SortKey {
+    pub fn parse(name: &str) -> Result<SortKey, Box<dyn Error>> {
+        match name {
+            "title" => Ok(SortKey::Title),
+            "created" => Ok(SortKey::Created),
+            "modified" => Ok(SortKey::Modified),
+            "size" => Ok(SortKey::Size),
+            "rank" => Ok(SortKey::Rank),
+            _ => Err(format!(
+                "Unknown sort '{}'; expected title, created, modified, size or rank",
+                name
+            )
+            .into()),
+        }
+    }
+
+    /// Field holding the key in note-like items
+    pub fn note_field(self) -> Option<&'static str> {
+        match self {
+            SortKey::Title => Some("title"),
+            SortKey::Created => Some("created"),
+            SortKey::Modified => Some("modified"),
+            SortKey::Size => Some("size"),
+            SortKey::Rank => None,
+        }
+    }
+}
+
+/// Which slice of a list to return, in what order and with which fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub limit: usize,
+    pub offset: usize,
+    pub sort_by: SortKey,
+    /// Defaults to ascending for titles and rank, descending for the rest
+    pub descending: Option<bool>,
+    /// Fields to keep in each item; all when `None`
+    pub fields: Option<Vec<String>>,
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page {
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+            sort_by: SortKey::Rank,
+            descending: None,
+            fields: None,
+        }
+    }
+}
+
+impl Page {
+    /// Reads `limit`, `offset`, `sort_by`, `order` (`asc`/`desc`) and `fields` (comma
+    /// separated) from query parameters.
+    pub fn from_query(query: &HashMap<String, String>) -> Result<Page, Box<dyn Error>> {
+        let number = |name: &str| -> Result<Option<usize>, Box<dyn Error>> {
+            match query.get(name) {
+                Some(value) => Ok(Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("'{}' must be a number", name))?,
+                )),
+                None => Ok(None),
+            }
+        };
+        let descending = match query.get("order").map(String::as_str) {
+            None => None,
+            Some("asc") => Some(false),
+            Some("desc") => Some(true),
+            Some(order) => return Err(format!("Unknown order '{}'", order).into()),
+        };
+        Ok(Page {
+            limit: number("limit")?.unwrap_or(DEFAULT_LIMIT),
+            offset: number("offset")?.unwrap_or(0),
+            sort_by: match query.get("sort_by") {
+                Some(name) => SortKey::parse(name)?,
+                None => SortKey::Rank,
+            },
+            descending,
+            fields: query.get("fields").map(|fields| {
+                fields
+                    .split(',')
+                    .map(|field| field.trim().to_string())
+                    .filter(|field| !field.is_empty())
+                    .collect()
+            }),
+        })
+    }
+
+    /// Items needed from a ranked source to fill this page without sorting
+    pub fn window(&self) -> usize {
+        self.offset.saturating_add(self.limit)
+    }
+}
+
+/// One page of a list, with the size of the whole list
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Listing {
+    pub total: usize,
+    pub offset: usize,
+    pub items: Vec<Value>,
+}
+
+/// Summary of a note for listings
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NoteEntry {
+    pub path: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub created: Option<String>,
+    /// RFC 3339 modification time
+    pub modified: Option<String>,
+    /// Size in bytes
+    pub size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<NoteStatus>,
+}
+
+impl NoteEntry {
+    pub fn new(note: &Note) -> NoteEntry {
+        NoteEntry {
+            path: note.path.to_string_lossy().replace('\\', "/"),
+            title: note.title(),
+            tags: note.tags.clone(),
+            created: note.front_matter.created.iter().flatten().next().cloned(),
+            modified: note
+                .modified
+                .and_then(|time| Timestamp::try_from(time).ok())
+                .map(|time| time.to_string()),
+            size: note.content.len(),
+            status: status::of(note),
+        }
+    }
+}
+
+/// A tag with the number of notes carrying it
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TagEntry {
+    pub tag: String,
+    pub count: usize,
+}
+
+impl TagEntry {
+    /// Sorting tags by title orders them by name, by size by how often they are used
+    pub fn field(key: SortKey) -> Option<&'static str> {
+        match key {
+            SortKey::Title => Some("tag"),
+            SortKey::Size => Some("count"),
+            _ => None,
+        }
+    }
+}
+
+/// Every tag of `notes`, most used first.
+pub fn tag_counts(notes: &[Note]) -> Vec<TagEntry> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for tag in notes.iter().flat_map(|note| &note.tags) {
+        *counts.entry(tag).or_insert(0) += 1;
+    }
+    let mut tags: Vec<TagEntry> = counts
+        .into_iter()
+        .map(|(tag, count)| TagEntry {
+            tag: tag.to_string(),
+            count,
+        })
+        .collect();
+    tags.sort_by_key(|entry| std::cmp::Reverse(entry.count));
+    tags
+}
+
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .unwrap_or_default()
+            .total_cmp(&b.as_f64().unwrap_or_default()),
+        (Value::String(a), Value::String(b)) => collation::compare(a, b),
+        // Missing values go last
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        _ => Ordering::Equal,
+    }
+}
+
+/// Sorts, slices and projects `items`. `field` names the item field holding each sort
+/// key; keys without a field are refused, except `rank`, which keeps the given order.
+pub fn paginate<T: Serialize>(
+    items: &[T],
+    page: &Page,
+    field: impl Fn(SortKey) -> Option<&'static str>,
+) -> Result<Listing, Box<dyn Error>> {
+    let mut values = items
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<Value>, _>>()?;
+
+    if page.sort_by != SortKey::Rank {
+        let name = field(page.sort_by).ok_or_else(|| {
+            format!("Cannot sort these items by {:?}", page.sort_by).to_lowercase()
+        })?;
+        let descending = page.descending.unwrap_or(page.sort_by != SortKey::Title);
+        // Stable sort, so ties keep their rank; missing keys stay last either way
+        values.sort_by(|a, b| {
+            let (a, b) = (&a[name], &b[name]);
+            match (a.is_null(), b.is_null(), descending) {
+                (false, false, true) => compare(b, a),
+                _ => compare(a, b),
+            }
+        });
+    } else if page.descending == Some(true) {
+        values.reverse();
+    }
+
+    let total = values.len();
+    let items = values
+        .into_iter()
+        .skip(page.offset)
+        .take(page.limit)
+        .map(|value| match (&page.fields, value) {
+            (Some(fields), Value::Object(object)) => Value::Object(
+                object
+                    .into_iter()
+                    .filter(|(key, _)| fields.contains(key))
+                    .collect(),
+            ),
+            (_, value) => value,
+        })
+        .collect();
+    Ok(Listing {
+        total,
+        offset: page.offset,
+        items,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn entries() -> Vec<NoteEntry> {
+        [("b.md", "B", 30), ("a.md", "a", 10), ("c.md", "C", 20)]
+            .iter()
+            .map(|(path, title, size)| NoteEntry {
+                path: path.to_string(),
+                title: title.to_string(),
+                tags: Vec::new(),
+                created: None,
+                modified: None,
+                size: *size,
+                status: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_paginate() {
+        let query: HashMap<String, String> = [("sort_by", "size"), ("fields", "path,size")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut page = Page::from_query(&query).unwrap();
+        let listing = paginate(&entries(), &page, SortKey::note_field).unwrap();
+        assert_eq!(listing.total, 3);
+        assert_eq!(
+            listing.items,
+            vec![
+                json!({ "path": "b.md", "size": 30 }),
+                json!({ "path": "c.md", "size": 20 }),
+                json!({ "path": "a.md", "size": 10 }),
+            ]
+        );
+
+        page.sort_by = SortKey::Title;
+        page.offset = 1;
+        page.limit = 1;
+        let listing = paginate(&entries(), &page, SortKey::note_field).unwrap();
+        assert_eq!(listing.items, vec![json!({ "path": "b.md", "size": 30 })]);
+
+        page.sort_by = SortKey::Created;
+        assert!(paginate(&entries(), &page, TagEntry::field).is_err());
+    }
+
+    #[test]
+    fn test_huge_offset() {
+        let query: HashMap<String, String> = [("offset", usize::MAX.to_string())]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        let page = Page::from_query(&query).unwrap();
+        assert_eq!(page.window(), usize::MAX);
+        let listing = paginate(&entries(), &page, SortKey::note_field).unwrap();
+        assert!(listing.items.is_empty());
+    }
+
+    #[test]
+    fn test_tag_counts() {
+        let notes: Vec<Note> = ["#rust #cli", "#rust"]
+            .iter()
+            .enumerate()
+            .map(|(i, content)| {
+                Note::from_content(PathBuf::from(format!("{}.md", i)), content.to_string())
+            })
+            .collect();
+        assert_eq!(
+            tag_counts(&notes),
+            vec![
+                TagEntry {
+                    tag: String::from("rust"),
+                    count: 2
+                },
+                TagEntry {
+                    tag: String::from("cli"),
+                    count: 1
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,604 @@
+//! The routes of the Obsidian Local REST API plugin, served on their own listener
+//! (`[server.local_rest]`) so that tools written against the plugin keep working without
+//! Obsidian running. Requests authenticate with `Authorization: Bearer <key>`, the key
+//! being read from the environment.
+//!
+//! Covered: `/vault/` (reading, writing, appending to, patching and deleting files, and
+//! listing folders), `/periodic/daily/`, `/search/simple/` and `/open/`. There is no
+//! active file or command palette outside Obsidian, so `/active/` answers 404 and
+//! `/commands/` lists nothing; Dataview and JsonLogic searches are not supported.
+
+use crate::api::ApiState;
+use crate::auth;
+use crate::capture;
+use crate::changeset::ChangeSet;
+use crate::data::{self, Note};
+use crate::frontmatter;
+use crate::http::{self, Request, Response};
+use crate::markdown;
+use crate::recency;
+use crate::trash;
+use crate::util;
+use crate::write_gate;
+
+use jiff::{Timestamp, Zoned};
+use serde_json::json;
+use std::{error::Error, fs, path::Path, thread, time::SystemTime};
+
+/// `Accept` type asking for a note with its metadata rather than its text
+const NOTE_JSON: &str = "application/vnd.olrapi.note+json";
+
+/// An error in the plugin's shape, `{"errorCode", "message"}`
+fn rest_error(status: u16, message: &str) -> Response {
+    Response::json(
+        status,
+        &json!({ "errorCode": status as u32 * 100, "message": message }),
+    )
+}
+
+fn no_content() -> Response {
+    Response {
+        status: 204,
+        content_type: String::from("text/plain"),
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operation {
+    Append,
+    Prepend,
+    Replace,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    /// Heading path from the outermost heading down
+    Heading(Vec<String>),
+    Block(String),
+    Frontmatter(String),
+}
+
+/// Where a `PATCH` request inserts its body, read from its `Operation`, `Target-Type`,
+/// `Target` and `Target-Delimiter` headers.
+fn patch_target(request: &Request) -> Result<(Operation, Target), String> {
+    let operation = match request.header("operation") {
+        Some("append") => Operation::Append,
+        Some("prepend") => Operation::Prepend,
+        Some("replace") => Operation::Replace,
+        other => return Err(format!("Invalid Operation header: {:?}", other)),
+    };
+    let target = util::percent_decode(request.header("target").ok_or("Missing Target header")?);
+    let target = match request.header("target-type") {
+        Some("heading") => {
+            let delimiter = request.header("target-delimiter").unwrap_or("::");
+            Target::Heading(
+                target
+                    .split(delimiter)
+                    .map(|part| part.trim().to_string())
+                    .collect(),
+            )
+        }
+        Some("block") => Target::Block(target.trim_start_matches('^').to_string()),
+        Some("frontmatter") => Target::Frontmatter(target),
+        other => return Err(format!("Invalid Target-Type header: {:?}", other)),
+    };
+    Ok((operation, target))
+}
+
+fn with_newline(text: &str) -> String {
+    match text.ends_with('\n') || text.is_empty() {
+        true => text.to_string(),
+        false => format!("{}\n", text),
+    }
+}
+
+/// Range of 0-based lines below the heading at `path`, up to the next heading of the same
+/// or a higher level, along with the heading's own line.
+fn heading_section(content: &str, path: &[String]) -> Option<(usize, usize)> {
+    let headings = markdown::parse_headings(content);
+    let mut stack: Vec<&markdown::Heading> = Vec::new();
+    for (i, heading) in headings.iter().enumerate() {
+        while stack.last().is_some_and(|open| open.level >= heading.level) {
+            stack.pop();
+        }
+        stack.push(heading);
+        if stack.len() == path.len()
+            && stack
+                .iter()
+                .zip(path)
+                .all(|(open, text)| open.text == *text)
+        {
+            let end = headings[i + 1..]
+                .iter()
+                .find(|next| next.level <= heading.level)
+                .map_or(content.split_inclusive('\n').count(), |next| next.line - 1);
+            return Some((heading.line - 1, end));
+        }
+    }
+    None
+}
+
+/// 0-based line carrying the block id `id`.
+fn block_line(content: &str, id: &str) -> Option<usize> {
+    let marker = format!("^{}", id);
+    markdown::body_lines(content)
+        .into_iter()
+        .find(|(_, _, line)| {
+            line.trim_end().ends_with(&format!(" {}", marker)) || line.trim() == marker
+        })
+        .map(|(line_no, _, _)| line_no - 1)
+}
+
+/// Merges `value` into the front matter property `key`: lists are extended, text is
+/// joined, and anything else is replaced.
+fn patch_property(
+    content: &str,
+    key: &str,
+    operation: Operation,
+    value: serde_json::Value,
+) -> Result<String, Box<dyn Error>> {
+    let mut mapping = frontmatter::parse_mapping(content)?.unwrap_or_default();
+    let value: serde_yaml::Value = serde_yaml::to_value(value)?;
+    let merged = match (operation, mapping.get(key).cloned(), value) {
+        (Operation::Replace, _, value) | (_, None, value) => value,
+        (operation, Some(serde_yaml::Value::Sequence(items)), value) => {
+            let added = match value {
+                serde_yaml::Value::Sequence(added) => added,
+                value => vec![value],
+            };
+            serde_yaml::Value::Sequence(match operation {
+                Operation::Append => items.into_iter().chain(added).collect(),
+                _ => added.into_iter().chain(items).collect(),
+            })
+        }
+        (operation, Some(serde_yaml::Value::String(text)), serde_yaml::Value::String(added)) => {
+            serde_yaml::Value::String(match operation {
+                Operation::Append => text + &added,
+                _ => added + &text,
+            })
+        }
+        (_, Some(_), value) => value,
+    };
+    mapping.insert(serde_yaml::Value::from(key), merged);
+    let body = frontmatter::split(content).1;
+    Ok(format!(
+        "---\n{}---\n{}",
+        serde_yaml::to_string(&mapping)?,
+        body
+    ))
+}
+
+/// Applies a `PATCH` request's edit to `content`.
+fn patch(
+    content: &str,
+    operation: Operation,
+    target: &Target,
+    body: &[u8],
+) -> Result<String, Box<dyn Error>> {
+    let text = String::from_utf8_lossy(body);
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let (start, end, replacement) = match target {
+        Target::Frontmatter(key) => {
+            let value = serde_json::from_slice(body)
+                .unwrap_or_else(|_| serde_json::Value::from(text.into_owned()));
+            return patch_property(content, key, operation, value);
+        }
+        Target::Heading(path) => {
+            let (heading, end) = heading_section(content, path)
+                .ok_or_else(|| format!("No heading '{}' in the note", path.join("::")))?;
+            match operation {
+                Operation::Append => (end, end, with_newline(&text)),
+                Operation::Prepend => (heading + 1, heading + 1, with_newline(&text)),
+                Operation::Replace => (heading + 1, end, with_newline(&text)),
+            }
+        }
+        Target::Block(id) => {
+            let line =
+                block_line(content, id).ok_or_else(|| format!("No block ^{} in the note", id))?;
+            match operation {
+                Operation::Append => (line + 1, line + 1, with_newline(&text)),
+                Operation::Prepend => (line, line, with_newline(&text)),
+                Operation::Replace => (line, line + 1, format!("{} ^{}\n", text.trim_end(), id)),
+            }
+        }
+    };
+    let mut before = lines[..start].concat();
+    if !before.is_empty() && !before.ends_with('\n') {
+        before.push('\n');
+    }
+    Ok(format!(
+        "{}{}{}",
+        before,
+        replacement,
+        lines[end..].concat()
+    ))
+}
+
+fn millis(time: std::io::Result<SystemTime>) -> i64 {
+    time.ok()
+        .and_then(|time| Timestamp::try_from(time).ok())
+        .map_or(0, |time| time.as_millisecond())
+}
+
+/// The note at `rel_path` as the plugin describes it for `application/vnd.olrapi.note+json`.
+fn note_json(vault_path: &Path, rel_path: &Path) -> Result<serde_json::Value, Box<dyn Error>> {
+    let file = vault_path.join(rel_path);
+    let content = fs::read_to_string(&file)?;
+    let metadata = fs::metadata(&file)?;
+    let frontmatter = frontmatter::parse_mapping(&content)
+        .ok()
+        .flatten()
+        .and_then(|mapping| serde_json::to_value(mapping).ok())
+        .unwrap_or_else(|| json!({}));
+    let note = Note::from_content(rel_path.to_path_buf(), content);
+    Ok(json!({
+        "path": rel_path.to_string_lossy().replace('\\', "/"),
+        "content": note.content,
+        "frontmatter": frontmatter,
+        "tags": note.tags,
+        "stat": {
+            "ctime": millis(metadata.created()),
+            "mtime": millis(metadata.modified()),
+            "size": metadata.len(),
+        },
+    }))
+}
+
+/// Files and folders (with a trailing `/`) directly inside the vault folder `rel_dir`.
+fn list_folder(vault_path: &Path, rel_dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(vault_path.join(rel_dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        match entry.file_type()?.is_dir() {
+            true => files.push(format!("{}/", name)),
+            false => files.push(name),
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Refuses paths outside the vault and hidden ones, such as `.git/hooks/` or `.obsidian/`.
+fn check_path(rel_path: &Path) -> Result<(), Response> {
+    if let Err(e) = capture::ensure_inside_vault(rel_path) {
+        return Err(rest_error(400, &e.to_string()));
+    }
+    if util::is_hidden_path(Path::new(""), rel_path) {
+        return Err(rest_error(403, "Hidden files are not served"));
+    }
+    Ok(())
+}
+
+/// Serves a request for the vault file `rel_path`.
+fn file_request(state: &ApiState, request: &Request, rel_path: &Path) -> Response {
+    if let Err(response) = check_path(rel_path) {
+        return response;
+    }
+    if request.method != "GET"
+        && let Err(e) = write_gate::check("write notes over the Local REST API")
+    {
+        return rest_error(403, &e.to_string());
+    }
+    let file = state.vault_path.join(rel_path);
+    let exists = file.is_file();
+    if !exists && matches!(request.method.as_str(), "GET" | "DELETE" | "PATCH") {
+        return rest_error(404, "File does not exist");
+    }
+    let current = fs::read_to_string(&file).ok();
+    let updated = match request.method.as_str() {
+        "GET"
+            if request
+                .header("accept")
+                .is_some_and(|accept| accept.contains(NOTE_JSON)) =>
+        {
+            return match note_json(&state.vault_path, rel_path) {
+                Ok(note) => {
+                    let mut response = Response::json(200, &note);
+                    response.content_type = String::from(NOTE_JSON);
+                    response
+                }
+                Err(e) => rest_error(500, &e.to_string()),
+            };
+        }
+        "GET" => {
+            return match fs::read(&file) {
+                Ok(body) => Response {
+                    status: 200,
+                    content_type: match data::is_note(rel_path) {
+                        true => String::from("text/markdown; charset=UTF-8"),
+                        false => String::from("application/octet-stream"),
+                    },
+                    headers: Vec::new(),
+                    body,
+                },
+                Err(e) => rest_error(500, &e.to_string()),
+            };
+        }
+        "DELETE" => {
+            return match trash::move_to_trash(&state.vault_path, rel_path) {
+                Ok(_) => no_content(),
+                Err(e) => rest_error(500, &e.to_string()),
+            };
+        }
+        "PUT" => request.body_text(),
+        "POST" => format!(
+            "{}{}",
+            current.as_deref().unwrap_or_default(),
+            request.body_text()
+        ),
+        "PATCH" => {
+            let (operation, target) = match patch_target(request) {
+                Ok(target) => target,
+                Err(e) => return rest_error(400, &e),
+            };
+            let content = current.as_deref().unwrap_or_default();
+            match patch(content, operation, &target, &request.body) {
+                Ok(updated) => updated,
+                Err(e) => return rest_error(400, &e.to_string()),
+            }
+        }
+        _ => return rest_error(405, "Method not allowed"),
+    };
+    let mut edits = ChangeSet::new();
+    edits.propose(rel_path, current, updated);
+    match edits.apply(
+        &state.vault_path,
+        "write notes over the Local REST API",
+        false,
+    ) {
+        Ok(()) if request.method == "PATCH" => Response {
+            status: 200,
+            ..no_content()
+        },
+        Ok(()) => no_content(),
+        Err(e) => rest_error(500, &e.to_string()),
+    }
+}
+
+/// `GET /vault/` and `GET /vault/{folder}/`
+fn folder_request(state: &ApiState, request: &Request, rel_dir: &Path) -> Response {
+    if request.method != "GET" {
+        return rest_error(405, "Folders can only be listed");
+    }
+    if rel_dir.as_os_str().is_empty() || check_path(rel_dir).is_ok() {
+        match list_folder(&state.vault_path, rel_dir) {
+            Ok(files) => Response::json(200, &json!({ "files": files })),
+            Err(_) => rest_error(404, "Folder does not exist"),
+        }
+    } else {
+        rest_error(400, "Not a folder inside the vault")
+    }
+}
+
+/// `POST /search/simple/?query=…&contextLength=…`: notes containing the query, ignoring case.
+fn search_simple(state: &ApiState, request: &Request) -> Response {
+    let Some(query) = request.query.get("query").filter(|query| !query.is_empty()) else {
+        return rest_error(400, "Missing query");
+    };
+    let context_length: usize = request
+        .query
+        .get("contextLength")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(100);
+    let mut cache = match state.notes.lock() {
+        Ok(cache) => cache,
+        Err(e) => return rest_error(500, &e.to_string()),
+    };
+    let notes = match cache.refresh(&state.vault_path) {
+        Ok(notes) => notes,
+        Err(e) => return rest_error(500, &e.to_string()),
+    };
+    let needle = query.to_lowercase();
+    let mut results = Vec::new();
+    for note in notes {
+        let haystack = note.content.to_lowercase();
+        // Lowercasing can change byte lengths; those notes are matched but not located
+        let same_offsets = haystack.len() == note.content.len();
+        let matches: Vec<serde_json::Value> = haystack
+            .match_indices(&needle)
+            .filter(|_| same_offsets)
+            .map(|(start, _)| {
+                let end = start + needle.len();
+                let mut from = start.saturating_sub(context_length);
+                while !note.content.is_char_boundary(from) {
+                    from -= 1;
+                }
+                let mut to = (end + context_length).min(note.content.len());
+                while !note.content.is_char_boundary(to) {
+                    to += 1;
+                }
+                json!({
+                    "match": { "start": start - from, "end": end - from },
+                    "context": &note.content[from..to],
+                })
+            })
+            .collect();
+        if matches.is_empty() && !haystack.contains(&needle) {
+            continue;
+        }
+        results.push((note, matches));
+    }
+    // Notes with the most matches first
+    results.sort_by_key(|(_, matches)| std::cmp::Reverse(matches.len()));
+    let results: Vec<serde_json::Value> = results
+        .into_iter()
+        .map(|(note, matches)| {
+            json!({
+                "filename": note.path.to_string_lossy().replace('\\', "/"),
+                "score": matches.len(),
+                "matches": matches,
+            })
+        })
+        .collect();
+    Response::json(200, &results)
+}
+
+/// `POST /open/{file}`: recorded as a visit, as there is no editor to open it in.
+fn open_note(state: &ApiState, rel_path: &Path) -> Response {
+    if let Err(response) = check_path(rel_path) {
+        return response;
+    }
+    if !state.vault_path.join(rel_path).is_file() {
+        return rest_error(404, "File does not exist");
+    }
+    let path = rel_path.to_string_lossy().replace('\\', "/");
+    let now = Timestamp::now().as_millisecond();
+    match recency::open(&state.config).and_then(|connection| {
+        Ok(recency::record(
+            &connection,
+            &path,
+            recency::Visit::Open,
+            now,
+        )?)
+    }) {
+        Ok(()) => Response {
+            status: 200,
+            ..no_content()
+        },
+        Err(e) => rest_error(500, &e.to_string()),
+    }
+}
+
+fn is_authorized(request: &Request, api_key: &str) -> bool {
+    request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|key| auth::constant_time_eq(key.trim().as_bytes(), api_key.as_bytes()))
+}
+
+pub fn handle(state: &ApiState, api_key: &str, request: &Request) -> Response {
+    let authenticated = is_authorized(request, api_key);
+    if request.path == "/" && request.method == "GET" {
+        return Response::json(
+            200,
+            &json!({
+                "status": "OK",
+                "service": "Obsidian Local REST API",
+                "authenticated": authenticated,
+                "versions": { "obsidian": "obsidian-rs", "self": env!("CARGO_PKG_VERSION") },
+            }),
+        );
+    }
+    if !authenticated {
+        return rest_error(
+            401,
+            "Authorization required. Find your API key in the obsidian-rs configuration.",
+        );
+    }
+    let path = request.path.as_str();
+    if let Some(rest) = path
+        .strip_prefix("/vault/")
+        .or((path == "/vault").then_some(""))
+    {
+        return match rest.is_empty() || rest.ends_with('/') {
+            true => folder_request(state, request, Path::new(rest.trim_end_matches('/'))),
+            false => file_request(state, request, Path::new(rest)),
+        };
+    }
+    if let Some(period) = path.strip_prefix("/periodic/") {
+        return match period.trim_end_matches('/') {
+            "daily" => match capture::daily_note_path(&state.config, &Zoned::now()) {
+                Ok(rel_path) => file_request(state, request, &rel_path),
+                Err(e) => rest_error(500, &e.to_string()),
+            },
+            _ => rest_error(400, "Only daily periodic notes are configured"),
+        };
+    }
+    match (request.method.as_str(), path) {
+        ("POST", "/search/simple/" | "/search/simple") => search_simple(state, request),
+        ("POST", "/search/" | "/search") => {
+            rest_error(400, "Dataview and JsonLogic searches are not supported")
+        }
+        ("GET", "/commands/" | "/commands") => Response::json(200, &json!({ "commands": [] })),
+        (_, path) if path.starts_with("/active") => {
+            rest_error(404, "There is no active file outside Obsidian")
+        }
+        ("POST", path) if path.starts_with("/open/") => {
+            open_note(state, Path::new(&path["/open/".len()..]))
+        }
+        _ => rest_error(404, "Not found"),
+    }
+}
+
+/// Serves the plugin's API on `listen` on a background thread.
+pub fn spawn_server(
+    listen: String,
+    api_key: String,
+    options: http::ServeOptions,
+    state: ApiState,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let serve = move |request: &Request| handle(&state, &api_key, request);
+        if let Err(e) = http::serve(&listen, options, serve) {
+            log::error!("Local REST API on {} stopped: {}", listen, e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_heading_and_block() {
+        let content = "# Plan\nIntro\n## Tasks\n- one ^first\n## Notes\nSome\n";
+        let tasks = Target::Heading(vec![String::from("Plan"), String::from("Tasks")]);
+        assert_eq!(
+            patch(content, Operation::Append, &tasks, b"- two").unwrap(),
+            "# Plan\nIntro\n## Tasks\n- one ^first\n- two\n## Notes\nSome\n"
+        );
+        assert_eq!(
+            patch(content, Operation::Replace, &tasks, b"Nothing\n").unwrap(),
+            "# Plan\nIntro\n## Tasks\nNothing\n## Notes\nSome\n"
+        );
+        let notes = Target::Heading(vec![String::from("Plan"), String::from("Notes")]);
+        assert_eq!(
+            patch(content, Operation::Prepend, &notes, b"First").unwrap(),
+            "# Plan\nIntro\n## Tasks\n- one ^first\n## Notes\nFirst\nSome\n"
+        );
+        let block = Target::Block(String::from("first"));
+        assert_eq!(
+            patch(content, Operation::Replace, &block, b"- uno").unwrap(),
+            "# Plan\nIntro\n## Tasks\n- uno ^first\n## Notes\nSome\n"
+        );
+        assert!(
+            patch(
+                content,
+                Operation::Append,
+                &Target::Heading(vec![String::from("Tasks")]),
+                b"x"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_hidden_and_outside_paths_are_refused() {
+        assert!(check_path(Path::new("Notes/a.md")).is_ok());
+        let status = |path: &str| check_path(Path::new(path)).unwrap_err().status;
+        assert_eq!(status(".git/hooks/post-commit"), 403);
+        assert_eq!(status("Notes/.obsidian/app.json"), 403);
+        assert_eq!(status("../outside.md"), 400);
+    }
+
+    #[test]
+    fn test_patch_frontmatter() {
+        let content = "---\ntags: [a]\ntitle: Plan\n---\nBody\n";
+        let tags = Target::Frontmatter(String::from("tags"));
+        assert_eq!(
+            patch(content, Operation::Append, &tags, b"[\"b\"]").unwrap(),
+            "---\ntags:\n- a\n- b\ntitle: Plan\n---\nBody\n"
+        );
+        let status = Target::Frontmatter(String::from("status"));
+        assert_eq!(
+            patch("Body\n", Operation::Replace, &status, b"\"done\"").unwrap(),
+            "---\nstatus: done\n---\nBody\n"
+        );
+    }
+}
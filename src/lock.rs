@@ -0,0 +1,102 @@
+//! Leader lock for the daemon. Only one process may index and watch a vault at a time;
+//! the lock is an advisory lock on `daemon.lock` in the data directory, so the OS drops it
+//! when the holder exits, even after a crash. The file itself is never removed: unlinking
+//! it would let a new daemon lock a fresh file while another still waits on the old one.
+
+use std::{
+    error::Error,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::Write,
+    path::Path,
+};
+
+static LOCK_FILE: &str = "daemon.lock";
+
+/// Held for as long as the daemon runs.
+#[derive(Debug)]
+pub struct DaemonLock {
+    file: File,
+}
+
+impl DaemonLock {
+    /// Takes the lock in `data_path`, or explains which process already holds it.
+    pub fn acquire(data_path: &Path) -> Result<DaemonLock, Box<dyn Error>> {
+        fs::create_dir_all(data_path)?;
+        let path = data_path.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = fs::read_to_string(&path).unwrap_or_default();
+                let holder = match pid.trim() {
+                    "" => String::from("another process"),
+                    pid => format!("process {}", pid),
+                };
+                return Err(format!(
+                    "The vault is already being watched by {} (lock: {}); \
+                     use its HTTP API or stop it first",
+                    holder,
+                    path.display()
+                )
+                .into());
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        Ok(DaemonLock { file })
+    }
+}
+
+/// Whether a daemon currently holds the lock in `data_path`.
+pub fn is_held(data_path: &Path) -> bool {
+    match File::open(data_path.join(LOCK_FILE)) {
+        Ok(file) => matches!(file.try_lock(), Err(TryLockError::WouldBlock)),
+        Err(_) => false,
+    }
+}
+
+impl Drop for DaemonLock {
+    /// Clears the pid while still holding the lock; the file stays in place.
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = DaemonLock::acquire(dir.path()).unwrap();
+        let err = DaemonLock::acquire(dir.path()).unwrap_err().to_string();
+        assert!(err.contains(&format!("process {}", std::process::id())));
+        drop(lock);
+        assert!(DaemonLock::acquire(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_is_held() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_held(dir.path()));
+        let lock = DaemonLock::acquire(dir.path()).unwrap();
+        assert!(is_held(dir.path()));
+        drop(lock);
+        assert!(!is_held(dir.path()));
+    }
+
+    #[test]
+    fn test_lock_file_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        drop(DaemonLock::acquire(dir.path()).unwrap());
+        let path = dir.path().join(LOCK_FILE);
+        assert_eq!(fs::read_to_string(path).unwrap(), "");
+    }
+}
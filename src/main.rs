@@ -1,10 +1,31 @@
-mod config;
-mod data;
-mod util;
-mod watcher;
-
+use clap::Parser;
+use cli::{Cli, Command, ExportCommand};
 use config::AppConfig;
 use data::NodeData;
+#[cfg(any(feature = "server", feature = "grpc"))]
+use obsidian_rs::auth;
+#[cfg(feature = "images")]
+use obsidian_rs::images;
+#[cfg(feature = "server")]
+use obsidian_rs::{api, cors, http, local_rest};
+#[cfg(feature = "web")]
+use obsidian_rs::{archive, clip};
+use obsidian_rs::{
+    block_ref, bookmarks, browse, calendar, capture, check_links, cli, clock, collation, config,
+    conflicts, content_store, context, convert, data, doctor, docx, entities, export, feed,
+    flatten, grep, headings, history, hooks, import, index, journal, kanban, link_to, lint, lock,
+    man, mcp, media, merge, moc, msgpack_rpc, notify, obsidian_vaults, pdf, plugins, previews,
+    profiles, query, query_cache, query_table, recency, reminders, replace, review, scheduler,
+    scripts, search, shell_completions, site, snapshots, status, suggest, toc, trash, util,
+    vault_diff, watcher, write_gate, writing,
+};
+#[cfg(any(feature = "server", feature = "grpc"))]
+use std::sync::Arc;
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 fn main() {
     env_logger::init_from_env(
@@ -13,15 +34,428 @@ fn main() {
             .write_style("LOG_STYLE"),
     );
 
-    let config: AppConfig = match config::extract_config() {
+    let cli = Cli::parse();
+
+    // Completion scripts, docs, vault diffs and profiles must not depend on a configured vault
+    if let Some(Command::Completions(args)) = &cli.command {
+        shell_completions::run_completions(args);
+        return;
+    }
+    if let Some(Command::GenDocs(args)) = &cli.command {
+        if let Err(e) = man::run_gen_docs(args) {
+            log::error!("Generating docs failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Vault { command }) = &cli.command {
+        if let Err(e) = vault_diff::run_vault(command) {
+            log::error!("Vault diff failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Profile { command }) = &cli.command {
+        if let Err(e) = profiles::run_profile(command, cli.profile.as_deref()) {
+            log::error!("Profile failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let vault = match cli.vault.as_deref().map(obsidian_vaults::find).transpose() {
+        Ok(vault) => vault,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let profile = profiles::active(cli.profile.as_deref());
+    let mut config: AppConfig = match config::extract_config(profile.as_deref()) {
         Ok(cfg) => cfg,
+        // A vault from Obsidian's list needs no configuration file
+        Err(_) if vault.is_some() && !config::config_file_exists() => AppConfig::default(),
         Err(e) => {
             log::error!("Failed to load configuration: {}", e);
             std::process::exit(1);
         }
     };
 
-    let data = match data::get_data_path(&config) {
+    if let Some(vault) = vault {
+        log::debug!("Using Obsidian's vault '{}'", vault.path.display());
+        config.workspace.root = vault.path.to_string_lossy().into_owned();
+    }
+
+    let vault_path = match config::get_root_workspace_path(&config) {
+        Some(path) => path,
+        None => {
+            log::error!("Vault path not found in configuration.");
+            std::process::exit(1);
+        }
+    };
+
+    write_gate::set_read_only(cli.read_only || config.workspace.read_only);
+    plugins::install(&vault_path, &config);
+    scripts::install(&vault_path, &config);
+    query_cache::install(&config);
+    history::install(&config);
+    content_store::install(&config);
+    watcher::install(&config.watcher);
+    status::install(&config.status);
+    entities::install(&config);
+    collation::install(&config.collation);
+
+    match cli.command {
+        Some(Command::Lint(args)) => match lint::run_lint(&vault_path, &config.lint, &args) {
+            Ok(0) => {}
+            Ok(_) => std::process::exit(1),
+            Err(e) => {
+                log::error!("Lint failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::CheckLinks(args)) => {
+            match check_links::run_check_links(&vault_path, &config, &args) {
+                Ok(0) => {}
+                Ok(_) => std::process::exit(1),
+                Err(e) => {
+                    log::error!("Link check failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::BlockRef(args)) => {
+            if let Err(e) = block_ref::run_block_ref(&vault_path, &config, &args) {
+                log::error!("Block reference failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::LinkTo(args)) => {
+            if let Err(e) = link_to::run_link_to(&vault_path, &args) {
+                log::error!("Link failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Replace(args)) => {
+            if let Err(e) = replace::run_replace(&vault_path, &args) {
+                log::error!("Replace failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Toc(args)) => {
+            if let Err(e) = toc::run_toc(&vault_path, &args) {
+                log::error!("Toc failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::RenumberHeadings(args)) => {
+            if let Err(e) = headings::run_renumber_headings(&vault_path, &args) {
+                log::error!("Renumbering headings failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::ConvertLinks(args)) => {
+            if let Err(e) = convert::run_convert_links(&vault_path, &args) {
+                log::error!("Link conversion failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Import(args)) => {
+            let attachments = config.workspace.attachments.as_deref().map(Path::new);
+            if let Err(e) = import::run_import(&vault_path, attachments, &args) {
+                log::error!("Import failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Capture(args)) => {
+            if let Err(e) = capture::run_capture(&vault_path, &config, &args) {
+                log::error!("Capture failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Search(args)) => match search::run_search(&vault_path, &config, &args) {
+            Ok(0) => std::process::exit(1),
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Search failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Query(args)) => match query::run_query(&vault_path, &config, &args) {
+            Ok(0) => std::process::exit(1),
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Query failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Grep(args)) => match grep::run_grep(&vault_path, &config, &args) {
+            Ok(0) => std::process::exit(1),
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Grep failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Find(args)) => {
+            if let Err(e) = browse::run_find(&vault_path, &config, &args) {
+                log::error!("Find failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Recent(args)) => {
+            if let Err(e) = recency::run_recent(&vault_path, &config, &args) {
+                log::error!("Listing recent notes failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Backlinks(args)) => {
+            if let Err(e) = browse::run_backlinks(&vault_path, &args) {
+                log::error!("Backlinks failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Tags(args)) => {
+            if let Err(e) = browse::run_tags(&vault_path, &args) {
+                log::error!("Tags failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Similar(args)) => {
+            if let Err(e) = search::run_similar(&vault_path, &config, &args) {
+                log::error!("Similar failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::SuggestTags(args)) => {
+            if let Err(e) = suggest::run_suggest_tags(&vault_path, &config, &args) {
+                log::error!("Tag suggestion failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Context(args)) => {
+            if let Err(e) = context::run_context(&vault_path, &config, &args) {
+                log::error!("Context failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Plugins(args)) => {
+            if let Err(e) = plugins::run_plugins(&vault_path, &args) {
+                log::error!("Plugins failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Mcp(args)) => {
+            let served = if args.msgpack {
+                msgpack_rpc::run_msgpack_rpc(&vault_path, &config)
+            } else {
+                mcp::run_mcp(&vault_path, &config)
+            };
+            if let Err(e) = served {
+                log::error!("MCP server failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "web")]
+        Some(Command::Clip(args)) => {
+            if let Err(e) = clip::run_clip(&vault_path, &config, &args) {
+                log::error!("Clip failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(not(feature = "web"))]
+        Some(Command::Clip(_)) => missing_feature("clip", "web"),
+        Some(Command::Flatten(args)) => {
+            if let Err(e) = flatten::run_flatten(&vault_path, &args) {
+                log::error!("Flatten failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Export { command }) => {
+            let result = match command {
+                ExportCommand::Subset(args) => export::run_export_subset(&vault_path, &args),
+                ExportCommand::Feed(args) => {
+                    feed::run_export_feed(&vault_path, &config.publish, &args)
+                }
+                ExportCommand::Html(args) => site::run_export_html(&vault_path, &config, &args),
+                ExportCommand::Calendar(args) => calendar::run_export_calendar(&vault_path, &args),
+                ExportCommand::Docx(args) => docx::run_export_docx(&vault_path, &args),
+                ExportCommand::Pdf(args) => {
+                    pdf::run_export_pdf(&vault_path, &config.publish, &args)
+                }
+            };
+            if let Err(e) = result {
+                log::error!("Export failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Doctor(args)) => match doctor::run_doctor(&vault_path, &config, &args) {
+            Ok(0) => {}
+            Ok(_) => std::process::exit(1),
+            Err(e) => {
+                log::error!("Doctor failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Conflicts { command }) => {
+            if let Err(e) = conflicts::run_conflicts(&vault_path, &command) {
+                log::error!("Conflict resolution failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::History(args)) => {
+            if let Err(e) = history::run_history(&args) {
+                log::error!("History failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Undo(args)) => {
+            if let Err(e) = history::run_undo(&vault_path, &args) {
+                log::error!("Undo failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Merge(args)) => match merge::run_merge(&vault_path, &args) {
+            Ok(0) => {}
+            Ok(_) => std::process::exit(1),
+            Err(e) => {
+                log::error!("Merge failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Journal { command }) => {
+            match journal::run_journal(&vault_path, &config, &command) {
+                Ok(0) => {}
+                Ok(_) => std::process::exit(1),
+                Err(e) => {
+                    log::error!("Journal failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::Bookmarks { command }) => {
+            if let Err(e) = bookmarks::run_bookmarks(&vault_path, &command) {
+                log::error!("Bookmarks failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Moc { command }) => {
+            if let Err(e) = moc::run_moc(&vault_path, &command) {
+                log::error!("Moc failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Entities { command }) => {
+            if let Err(e) = entities::run_entities(&vault_path, &command) {
+                log::error!("Entities failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Review(args)) => {
+            if let Err(e) = review::run_review(&vault_path, &config, &args) {
+                log::error!("Review failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Previews { command }) => {
+            if let Err(e) = previews::run_previews(&vault_path, &config, &command) {
+                log::error!("Previews failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "web")]
+        Some(Command::ArchiveLinks(args)) => {
+            if let Err(e) = archive::run_archive_links(&vault_path, &config, &args) {
+                log::error!("Archiving links failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(not(feature = "web"))]
+        Some(Command::ArchiveLinks(_)) => missing_feature("archive-links", "web"),
+        #[cfg(feature = "images")]
+        Some(Command::Images { command }) => {
+            if let Err(e) = images::run_images(&vault_path, &config, &command) {
+                log::error!("Images failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(not(feature = "images"))]
+        Some(Command::Images { .. }) => missing_feature("images", "images"),
+        Some(Command::Media { command }) => {
+            if let Err(e) = media::run_media(&vault_path, &config, &command) {
+                log::error!("Media failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Kanban { command }) => {
+            if let Err(e) = kanban::run_kanban(&vault_path, &config, &command) {
+                log::error!("Kanban failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Agenda(args)) => {
+            if let Err(e) = reminders::run_agenda(&vault_path, &config, &args) {
+                log::error!("Agenda failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Clock { command }) => {
+            if let Err(e) = clock::run_clock(&vault_path, &config, &command) {
+                log::error!("Clock failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Timesheet(args)) => {
+            if let Err(e) = clock::run_timesheet(&vault_path, &config, &args) {
+                log::error!("Timesheet failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Writing { command }) => {
+            if let Err(e) = writing::run_writing(&vault_path, &config, &command) {
+                log::error!("Writing stats failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Stats(args)) => {
+            if let Err(e) = snapshots::run_stats(&vault_path, &config, &args) {
+                log::error!("Stats failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::DiffIndex(args)) => {
+            if let Err(e) = snapshots::run_diff_index(&vault_path, &config, &args) {
+                log::error!("Diff failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Jobs { command }) => {
+            if let Err(e) = scheduler::run_jobs(&vault_path, &config, &command) {
+                log::error!("Jobs failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Trash { command }) => {
+            if let Err(e) = trash::run_trash(&vault_path, &command) {
+                log::error!("Trash failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(
+            Command::Completions(_)
+            | Command::GenDocs(_)
+            | Command::Vault { .. }
+            | Command::Profile { .. },
+        ) => {}
+        None => run_daemon(&config, &vault_path),
+    }
+}
+
+/// Index the vault into the cache and keep watching it for changes.
+fn run_daemon(config: &AppConfig, vault_path: &PathBuf) {
+    let data = match data::get_data_path(config) {
         Err(e) => {
             log::error!("Problem retrieving data-path: {}", e);
             std::process::exit(1);
@@ -32,6 +466,15 @@ fn main() {
         }
     };
 
+    // Held until the daemon exits
+    let _lock = match lock::DaemonLock::acquire(&data) {
+        Err(e) => {
+            log::error!("Cannot start the daemon: {}", e);
+            std::process::exit(1);
+        }
+        Ok(lock) => lock,
+    };
+
     let cache = match data::get_cache(&data) {
         Err(e) => {
             log::error!("Problem retrieving cache db: {}", e);
@@ -44,15 +487,7 @@ fn main() {
 
     // ------
 
-    let vault_path = match config::get_root_workspace_path(&config) {
-        Some(path) => path,
-        None => {
-            log::error!("Vault path not found in configuration.");
-            std::process::exit(1);
-        }
-    };
-
-    let vault_content = match data::traverse_vault(&vault_path.as_path()) {
+    let vault_content = match data::traverse_vault(vault_path.as_path()) {
         Err(e) => {
             log::error!("Error in path_traversal: {}", e);
             std::process::exit(1);
@@ -60,39 +495,229 @@ fn main() {
         Ok(nodes) => nodes,
     };
 
-    let _cache_state = match data::invalidate_cache(&vault_content, &vault_path, &cache) {
-        Err(e) => {
-            log::error!("Error in invalidation: {}", e);
-            std::process::exit(1);
-        }
-        _ => {}
-    };
+    if let Err(e) = data::invalidate_cache(&vault_content, vault_path, &cache) {
+        log::error!("Error in invalidation: {}", e);
+        std::process::exit(1);
+    }
 
     let mut nodes: Vec<NodeData> = Vec::new();
     for file in vault_content {
-        match data::parse_yaml_front_matter(&file.as_path()) {
-            Err(_) => {}
-            Ok(fm_opt) => match fm_opt {
-                Some(fm) => {
-                    let rel_path = util::get_relative_path(&file, &vault_path).unwrap();
-                    let node = NodeData {
-                        id: Some(rel_path),
-                        front_matter: Some(fm),
-                    };
-                    log::info!("{}", node);
-                    nodes.push(node);
-                }
-                None => {}
-            },
-        };
+        if let Ok(Some(fm)) = data::parse_yaml_front_matter(file.as_path()) {
+            let rel_path = util::get_relative_path(&file, vault_path).unwrap();
+            let node = NodeData {
+                id: Some(rel_path),
+                front_matter: Some(fm),
+            };
+            log::info!("{}", node);
+            nodes.push(node);
+        }
     }
 
     // ------
 
-    if let Err(e) = watcher::run_watcher(&vault_path) {
+    // Keep the search index, and with it the stored related notes, fresh for the API
+    let embed = config.embeddings.backend.is_some();
+    let startup_changes = match index::open(config)
+        .and_then(|index| search::refresh_index(&index, vault_path, config, embed))
+    {
+        Ok(changes) => {
+            log::info!("Since the last run: {}", changes);
+            for (from, to) in &changes.renamed {
+                log::debug!("  renamed {} -> {}", from, to);
+            }
+            for (label, paths) in [
+                ("added", &changes.added),
+                ("modified", &changes.modified),
+                ("removed", &changes.removed),
+            ] {
+                paths
+                    .iter()
+                    .for_each(|path| log::debug!("  {} {}", label, path));
+            }
+            Some(changes)
+        }
+        Err(e) => {
+            log::error!("Error refreshing the search index: {}", e);
+            None
+        }
+    };
+
+    serve_http(config, vault_path, startup_changes);
+
+    if let Some(listen) = &config.server.grpc_listen {
+        #[cfg(feature = "grpc")]
+        match auth::Keys::load(&config.server) {
+            Ok(keys) => {
+                obsidian_rs::grpc::spawn_server(
+                    listen.clone(),
+                    Arc::new(keys),
+                    vault_path.clone(),
+                    config.clone(),
+                );
+            }
+            Err(e) => log::error!("Not serving the gRPC API: {}", e),
+        }
+        #[cfg(not(feature = "grpc"))]
+        log::error!(
+            "Not serving gRPC on {}: obsidian-rs was built without the `grpc` feature",
+            listen
+        );
+    }
+
+    scheduler::spawn_scheduler(vault_path.clone(), config);
+    reminders::spawn_notifier(config);
+    if let Err(e) = index::open(config)
+        .and_then(|index| search::refresh_attachments(&index, vault_path, config))
+    {
+        log::error!("Error indexing attachments: {}", e);
+    }
+
+    let mut edits = recency::open(config)
+        .inspect_err(|e| log::warn!("Edits will not be recorded: {}", e))
+        .ok()
+        .map(recency::EditLog::new);
+    let mut conflict_alerts = notify::ConflictAlerts::default();
+    let notes_changed = Cell::new(false);
+    // Only notes whose content changed are re-indexed and re-stored, and the notifier
+    // finds new reminders in the index
+    let refresh_index = || {
+        let refreshed = index::open(config).and_then(|index| {
+            search::refresh_index(&index, vault_path, config, embed)?;
+            search::refresh_attachments(&index, vault_path, config)
+        });
+        if let Err(e) = refreshed {
+            log::error!("Error refreshing the search index: {}", e);
+        }
+    };
+    if !config.hooks.rules.is_empty() {
+        if let Err(e) = hooks::run_hooks(vault_path, config) {
+            log::error!("Watcher failed to run: {}", e);
+            std::process::exit(1);
+        }
+    } else if let Err(e) = watcher::run_watcher(
+        vault_path,
+        Duration::from_millis(config.hooks.debounce_ms),
+        |path| {
+            record_change(vault_path, edits.as_mut(), path);
+            if config.notify.conflicts
+                && let Some(alert) = conflict_alerts.check(vault_path, path)
+            {
+                notify::send_in_background(&config.notify, alert);
+            }
+            if data::is_note(path) {
+                notes_changed.set(true);
+            }
+        },
+        refresh_index,
+        || {
+            // Once a burst of edits is over, so a save storm refreshes everything once
+            if notes_changed.replace(false) {
+                moc::refresh_vault(vault_path);
+                query_table::refresh_vault(vault_path);
+                refresh_index();
+            }
+        },
+    ) {
         log::error!("Watcher failed to run: {}", e);
         std::process::exit(1);
     } else {
         log::info!("Watcher finished successfully.");
     }
 }
+
+/// Exits with an error for a `command` this build leaves out.
+#[cfg(not(all(feature = "web", feature = "images")))]
+fn missing_feature(command: &str, feature: &str) -> ! {
+    log::error!(
+        "`{}` needs the `{}` feature, which this build of obsidian-rs lacks",
+        command,
+        feature
+    );
+    std::process::exit(1)
+}
+
+/// Starts the HTTP API and the Local REST API servers `[server]` configures.
+#[cfg(feature = "server")]
+fn serve_http(config: &AppConfig, vault_path: &Path, startup_changes: Option<index::IndexStats>) {
+    let serve_options = match api::serve_options(&config.server) {
+        Ok(options) => Some(options),
+        Err(e) => {
+            log::error!("Not serving HTTP: {}", e);
+            None
+        }
+    };
+    if let Some(listen) = &config.server.listen
+        && let Some(options) = &serve_options
+    {
+        match auth::Keys::load(&config.server) {
+            Ok(keys) if keys.is_empty() && !http::is_loopback(listen) => log::error!(
+                "Not serving the HTTP API on {}: it is not a loopback address, so set [[server.keys]]",
+                listen
+            ),
+            Ok(keys) => {
+                if cors::ignores_wildcard(&config.server, !keys.is_empty()) {
+                    log::warn!("Ignoring \"*\" in [server] cors_origins: it needs [[server.keys]]");
+                }
+                let mut state = api::ApiState::new(vault_path.to_path_buf(), config.clone());
+                state.startup_changes = startup_changes.map(Arc::new);
+                state.keys = Arc::new(keys);
+                api::spawn_server(listen.clone(), options.clone(), state);
+            }
+            Err(e) => log::error!("Not serving the HTTP API: {}", e),
+        }
+    }
+    if let Some(listen) = &config.server.local_rest.listen
+        && let Some(options) = &serve_options
+    {
+        let key_env = &config.server.local_rest.api_key_env;
+        match std::env::var(key_env) {
+            Ok(api_key) if !api_key.is_empty() => {
+                let state = api::ApiState::new(vault_path.to_path_buf(), config.clone());
+                local_rest::spawn_server(listen.clone(), api_key, options.clone(), state);
+            }
+            _ => log::error!(
+                "Not serving the Local REST API: ${} holds no API key",
+                key_env
+            ),
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+fn serve_http(config: &AppConfig, _vault_path: &Path, _startup_changes: Option<index::IndexStats>) {
+    let listens = [&config.server.listen, &config.server.local_rest.listen];
+    for listen in listens.into_iter().flatten() {
+        log::error!(
+            "Not serving HTTP on {}: obsidian-rs was built without the `server` feature",
+            listen
+        );
+    }
+}
+
+/// Keeps the visit counts and stored note contents in step with a changed vault file.
+fn record_change(vault_path: &Path, edits: Option<&mut recency::EditLog>, path: &Path) {
+    if !data::is_note(path) {
+        return;
+    }
+    if let Err(e) = content_store::refresh_paths(vault_path, &[path.to_path_buf()]) {
+        log::error!(
+            "Error updating the stored contents of {}: {}",
+            path.display(),
+            e
+        );
+    }
+    let (Some(edits), Ok(rel_path)) = (edits, util::get_relative_path(path, vault_path)) else {
+        return;
+    };
+    let rel_path = rel_path.to_string_lossy().replace('\\', "/");
+    let recorded = match path.exists() {
+        true => {
+            let now = jiff::Timestamp::now().as_millisecond();
+            edits.record(&rel_path, now)
+        }
+        false => edits.forget(&rel_path),
+    };
+    if let Err(e) = recorded {
+        log::error!("Error recording a change to {}: {}", rel_path, e);
+    }
+}
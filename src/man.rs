@@ -0,0 +1,38 @@
+//! Man pages generated from the clap definitions, so they never drift from `--help`.
+
+use crate::cli::{Cli, GenDocsArgs};
+
+use clap::CommandFactory;
+use std::{error::Error, fs, path::Path};
+
+/// Writes `obsidian-rs.1` and one page per visible subcommand, e.g. `obsidian-rs-search.1`.
+pub fn write_man_pages(out_dir: &Path) -> Result<usize, Box<dyn Error>> {
+    fs::create_dir_all(out_dir)?;
+    clap_mangen::generate_to(Cli::command(), out_dir)?;
+    let pages = fs::read_dir(out_dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "1"))
+        .count();
+    Ok(pages)
+}
+
+pub fn run_gen_docs(args: &GenDocsArgs) -> Result<(), Box<dyn Error>> {
+    let pages = write_man_pages(&args.out_dir)?;
+    log::info!("Wrote {} man page(s) to {}", pages, args.out_dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_man_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        write_man_pages(dir.path()).unwrap();
+        let search = fs::read_to_string(dir.path().join("obsidian-rs-search.1")).unwrap();
+        assert!(search.contains(".SH EXTRA\nExamples:"));
+        assert!(dir.path().join("obsidian-rs-trash-list.1").exists());
+        assert!(!dir.path().join("obsidian-rs-gen-docs.1").exists());
+    }
+}
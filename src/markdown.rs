@@ -0,0 +1,600 @@
+use crate::util;
+
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub level: usize,
+    pub text: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStyle {
+    Wiki,
+    Markdown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub style: LinkStyle,
+    pub embed: bool,
+    /// Note or file the link points at, without the `#anchor` part
+    pub target: String,
+    pub anchor: Option<String>,
+    /// Wikilink alias or Markdown link text
+    pub text: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    /// Byte range of the whole link (including `!`) in the document
+    pub span: Range<usize>,
+}
+
+impl Link {
+    pub fn is_external(&self) -> bool {
+        self.target.contains("://") || self.target.starts_with("mailto:")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BareUrl {
+    pub url: String,
+    pub line: usize,
+    pub column: usize,
+    /// Byte range of the URL in the document
+    pub span: Range<usize>,
+}
+
+/// An http(s) URL in a document, linked or bare
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebUrl {
+    pub url: String,
+    /// Embedded with `![](...)` rather than linked
+    pub embed: bool,
+    pub line: usize,
+    pub column: usize,
+    /// Byte range of the link, or of the bare URL, in the document
+    pub span: Range<usize>,
+}
+
+/// A body line as (1-based line number, byte offset in the document, text without newline)
+pub type BodyLine<'a> = (usize, usize, &'a str);
+
+/// Byte offset where the note body starts, i.e. just after the YAML front matter.
+pub fn body_start(content: &str) -> usize {
+    let mut lines = content.split_inclusive('\n');
+    let first = match lines.next() {
+        Some(line) if line.trim() == "---" => line,
+        _ => return 0,
+    };
+    let mut offset = first.len();
+    for line in lines {
+        offset += line.len();
+        if line.trim() == "---" {
+            return offset;
+        }
+    }
+    // Unterminated front matter: treat everything as body
+    0
+}
+
+/// Lines of the note body, skipping front matter and fenced code blocks.
+pub fn body_lines(content: &str) -> Vec<BodyLine<'_>> {
+    let start = body_start(content);
+    let mut result = Vec::new();
+    let mut offset = 0;
+    let mut fence: Option<&str> = None;
+
+    for (index, raw) in content.split_inclusive('\n').enumerate() {
+        let line_offset = offset;
+        offset += raw.len();
+        if line_offset < start {
+            continue;
+        }
+        let line = raw.trim_end_matches(['\n', '\r']);
+        let trimmed = line.trim_start();
+
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            continue;
+        }
+        if trimmed.starts_with("```") {
+            fence = Some("```");
+            continue;
+        }
+        if trimmed.starts_with("~~~") {
+            fence = Some("~~~");
+            continue;
+        }
+        result.push((index + 1, line_offset, line));
+    }
+    result
+}
+
+/// Byte ranges of inline code spans (`code`) within a single line.
+pub fn code_spans(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'`' {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i] == b'`' {
+            i += 1;
+        }
+        let ticks = &line[start..i];
+        match line[i..].find(ticks) {
+            Some(close) => {
+                let end = i + close + ticks.len();
+                spans.push(start..end);
+                i = end;
+            }
+            None => break,
+        }
+    }
+    spans
+}
+
+/// Whether a `- [ ]` list item is checked, and its text; `None` for other lines.
+pub fn parse_task(line: &str) -> Option<(bool, &str)> {
+    let item = line.trim_start();
+    let rest = match item.strip_prefix(['-', '*', '+']) {
+        Some(rest) => rest,
+        None => {
+            let number = item.trim_start_matches(|c: char| c.is_ascii_digit());
+            if number.len() == item.len() {
+                return None;
+            }
+            number.strip_prefix(['.', ')'])?
+        }
+    };
+    let mut chars = rest.strip_prefix(" [")?.chars();
+    let mark = chars.next()?;
+    let text = chars.as_str().strip_prefix("] ")?;
+    Some((mark != ' ', text))
+}
+
+fn in_ranges(ranges: &[Range<usize>], position: usize) -> bool {
+    ranges.iter().any(|range| range.contains(&position))
+}
+
+/// Form of a heading that anchors are compared by: lowercase words joined by `-`, with
+/// punctuation dropped, so "Next Steps!" and `next-steps` agree.
+pub fn heading_slug(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub fn parse_headings(content: &str) -> Vec<Heading> {
+    body_lines(content)
+        .into_iter()
+        .filter_map(|(line_no, _, line)| parse_heading_line(line, line_no))
+        .collect()
+}
+
+fn parse_heading_line(line: &str, line_no: usize) -> Option<Heading> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let level = rest.bytes().take_while(|b| *b == b'#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let after = &rest[level..];
+    if !after.is_empty() && !after.starts_with([' ', '\t']) {
+        return None;
+    }
+    let text = after.trim().trim_end_matches('#').trim_end();
+    Some(Heading {
+        level,
+        text: text.to_string(),
+        line: line_no,
+    })
+}
+
+pub fn parse_links(content: &str) -> Vec<Link> {
+    body_lines(content)
+        .into_iter()
+        .flat_map(|(line_no, offset, line)| links_in_line(line, line_no, offset))
+        .collect()
+}
+
+/// Links found in one line; `offset` is the byte offset of the line in the document.
+pub fn links_in_line(line: &str, line_no: usize, offset: usize) -> Vec<Link> {
+    let code = code_spans(line);
+    let mut links = Vec::new();
+    let mut i = 0;
+
+    while i < line.len() {
+        if in_ranges(&code, i) || !line.is_char_boundary(i) {
+            i += 1;
+            continue;
+        }
+        let rest = &line[i..];
+        let embed = rest.starts_with('!');
+        let start = i;
+        let open = if embed { i + 1 } else { i };
+        let after_bang = &line[open..];
+
+        if let Some(wiki) = after_bang.strip_prefix("[[") {
+            if let Some(close) = wiki.find("]]") {
+                let inner = &wiki[..close];
+                let end = open + 2 + close + 2;
+                let (target_part, alias) = match inner.split_once('|') {
+                    Some((target, alias)) => (target, Some(alias.to_string())),
+                    None => (inner, None),
+                };
+                let (target, anchor) = split_anchor(target_part);
+                links.push(Link {
+                    style: LinkStyle::Wiki,
+                    embed,
+                    target,
+                    anchor,
+                    text: alias,
+                    line: line_no,
+                    column: start + 1,
+                    span: offset + start..offset + end,
+                });
+                i = end;
+                continue;
+            }
+        } else if after_bang.starts_with('[')
+            && let Some((link, end)) = markdown_link_at(line, open)
+        {
+            let (text, destination) = link;
+            let (target, anchor) = if destination.contains("://") {
+                (destination, None)
+            } else {
+                split_anchor(&destination)
+            };
+            links.push(Link {
+                style: LinkStyle::Markdown,
+                embed,
+                target,
+                anchor,
+                text: Some(text),
+                line: line_no,
+                column: start + 1,
+                span: offset + start..offset + end,
+            });
+            i = end;
+            continue;
+        }
+        i += 1;
+    }
+    links
+}
+
+/// Parses `[text](destination)` starting at `open` (the `[`), returning the parts and end offset.
+fn markdown_link_at(line: &str, open: usize) -> Option<((String, String), usize)> {
+    let rest = &line[open + 1..];
+    let text_end = rest.find(']')?;
+    let text = &rest[..text_end];
+    let after_text = &rest[text_end + 1..];
+    if !after_text.starts_with('(') {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut dest_end = None;
+    for (index, ch) in after_text.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    dest_end = Some(index);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let dest_end = dest_end?;
+    let raw = after_text[1..dest_end].trim();
+    let destination = if let Some(stripped) = raw.strip_prefix('<') {
+        stripped.split('>').next().unwrap_or_default()
+    } else {
+        raw.split_whitespace().next().unwrap_or_default()
+    };
+    let end = open + 1 + text_end + 1 + dest_end + 1;
+    Some(((text.to_string(), destination.to_string()), end))
+}
+
+fn split_anchor(target: &str) -> (String, Option<String>) {
+    match target.split_once('#') {
+        Some((note, anchor)) => (note.trim().to_string(), Some(anchor.trim().to_string())),
+        None => (target.trim().to_string(), None),
+    }
+}
+
+/// Formats `[[target#anchor|text]]`, omitting the alias when it matches the target name.
+pub fn render_wikilink(
+    target: &str,
+    anchor: Option<&str>,
+    text: Option<&str>,
+    embed: bool,
+) -> String {
+    let mut inner = target.to_string();
+    if let Some(anchor) = anchor {
+        inner.push('#');
+        inner.push_str(anchor);
+    }
+
+    let display_name = target.rsplit('/').next().unwrap_or(target);
+    if let Some(text) = text
+        && !text.is_empty()
+        && text != inner
+        && text != display_name
+    {
+        inner.push('|');
+        inner.push_str(text);
+    }
+    format!("{}[[{}]]", if embed { "!" } else { "" }, inner)
+}
+
+/// Formats `[text](path#anchor)`, percent-encoding the destination.
+pub fn render_markdown_link(path: &str, anchor: Option<&str>, text: &str, embed: bool) -> String {
+    let mut destination = util::percent_encode(path);
+    if let Some(anchor) = anchor {
+        destination.push('#');
+        destination.push_str(&util::percent_encode(anchor));
+    }
+    format!(
+        "{}[{}]({})",
+        if embed { "!" } else { "" },
+        text,
+        destination
+    )
+}
+
+/// Renders a link as a wikilink, dropping the `.md` extension and URL-encoding.
+pub fn to_wikilink(link: &Link) -> String {
+    let target = util::percent_decode(&link.target);
+    let target = target.strip_suffix(".md").unwrap_or(&target);
+    let anchor = link.anchor.as_deref().map(util::percent_decode);
+    render_wikilink(target, anchor.as_deref(), link.text.as_deref(), link.embed)
+}
+
+/// Applies `(byte range, replacement)` edits to `content`; ranges must not overlap.
+pub fn replace_spans(content: &str, mut edits: Vec<(Range<usize>, String)>) -> String {
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut result = content.to_string();
+    for (range, replacement) in edits {
+        result.replace_range(range, &replacement);
+    }
+    result
+}
+
+/// Inline `#tags` in the note body, without the leading '#'.
+pub fn parse_tags(content: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    for (line_no, offset, line) in body_lines(content) {
+        let mut masked = code_spans(line);
+        masked.extend(
+            links_in_line(line, line_no, offset)
+                .into_iter()
+                .map(|link| link.span.start - offset..link.span.end - offset),
+        );
+
+        for (index, _) in line.match_indices('#') {
+            let preceded_by_space = line[..index]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace);
+            if !preceded_by_space || in_ranges(&masked, index) {
+                continue;
+            }
+            let tag: String = line[index + 1..]
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '/'))
+                .collect();
+            let tag = tag.trim_end_matches('/');
+            // Purely numeric strings like #123 are not tags in Obsidian
+            if !tag.is_empty()
+                && !tag.chars().all(|c| c.is_ascii_digit())
+                && !tags.iter().any(|t| t == tag)
+            {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+    tags
+}
+
+/// URLs written as plain text rather than as a link or `<autolink>`.
+pub fn find_bare_urls(content: &str) -> Vec<BareUrl> {
+    let mut urls = Vec::new();
+    for (line_no, offset, line) in body_lines(content) {
+        let mut masked = code_spans(line);
+        masked.extend(
+            links_in_line(line, line_no, offset)
+                .into_iter()
+                .map(|link| link.span.start - offset..link.span.end - offset),
+        );
+
+        let mut search_from = 0;
+        while let Some(found) = find_url_start(&line[search_from..]) {
+            let start = search_from + found;
+            let length = line[start..]
+                .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '\''))
+                .unwrap_or(line.len() - start);
+            let url = line[start..start + length].trim_end_matches(['.', ',', ';', ':', ')', ']']);
+            search_from = start + length.max(1);
+
+            let autolinked = line[..start].ends_with('<');
+            if url.is_empty() || autolinked || in_ranges(&masked, start) {
+                continue;
+            }
+            urls.push(BareUrl {
+                url: url.to_string(),
+                line: line_no,
+                column: start + 1,
+                span: offset + start..offset + start + url.len(),
+            });
+        }
+    }
+    urls
+}
+
+/// The http(s) URLs of `content`, linked ones first, then bare ones.
+pub fn find_web_urls(content: &str) -> Vec<WebUrl> {
+    let linked = parse_links(content)
+        .into_iter()
+        .filter(|link| link.is_external())
+        .map(|link| WebUrl {
+            url: link.target,
+            embed: link.embed,
+            line: link.line,
+            column: link.column,
+            span: link.span,
+        });
+    let bare = find_bare_urls(content).into_iter().map(|url| WebUrl {
+        url: url.url,
+        embed: false,
+        line: url.line,
+        column: url.column,
+        span: url.span,
+    });
+    linked
+        .chain(bare)
+        .filter(|url| url.url.starts_with("http://") || url.url.starts_with("https://"))
+        .collect()
+}
+
+fn find_url_start(haystack: &str) -> Option<usize> {
+    match (haystack.find("http://"), haystack.find("https://")) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body_lines_skip_front_matter_and_fences() {
+        let content = "---\ntitle: x\n---\n# Head\n```\n# not a heading\n```\ntext\n";
+        let lines: Vec<_> = body_lines(content)
+            .into_iter()
+            .map(|(n, _, l)| (n, l))
+            .collect();
+        assert_eq!(lines, vec![(4, "# Head"), (8, "text")]);
+    }
+
+    #[test]
+    fn test_parse_task() {
+        assert_eq!(parse_task("- [ ] Open"), Some((false, "Open")));
+        assert_eq!(parse_task("  * [x] Done"), Some((true, "Done")));
+        assert_eq!(parse_task("12) [/] Half"), Some((true, "Half")));
+        assert_eq!(parse_task("- [] Not a task"), None);
+        assert_eq!(parse_task("[ ] Not a list item"), None);
+        assert_eq!(parse_task("1 [ ] Not either"), None);
+    }
+
+    #[test]
+    fn test_parse_headings() {
+        let content = "# One\nno#heading\n## Two ##\n####### too deep\n";
+        let headings = parse_headings(content);
+        assert_eq!(headings.len(), 2);
+        assert_eq!(headings[0].text, "One");
+        assert_eq!(headings[1].level, 2);
+        assert_eq!(headings[1].text, "Two");
+        assert_eq!(headings[1].line, 3);
+    }
+
+    #[test]
+    fn test_parse_wikilinks_and_embeds() {
+        let content = "See [[Other Note#Section|alias]] and ![[image.png]].";
+        let links = parse_links(content);
+        assert_eq!(links.len(), 2);
+
+        assert_eq!(links[0].style, LinkStyle::Wiki);
+        assert_eq!(links[0].target, "Other Note");
+        assert_eq!(links[0].anchor.as_deref(), Some("Section"));
+        assert_eq!(links[0].text.as_deref(), Some("alias"));
+        assert_eq!(
+            &content[links[0].span.clone()],
+            "[[Other Note#Section|alias]]"
+        );
+
+        assert!(links[1].embed);
+        assert_eq!(links[1].target, "image.png");
+    }
+
+    #[test]
+    fn test_parse_markdown_links() {
+        let content = "A [note](folder/note.md#head) and [site](https://example.com/a#b) `[x](y)`";
+        let links = parse_links(content);
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].style, LinkStyle::Markdown);
+        assert_eq!(links[0].target, "folder/note.md");
+        assert_eq!(links[0].anchor.as_deref(), Some("head"));
+        assert!(links[1].is_external());
+        assert_eq!(links[1].target, "https://example.com/a#b");
+    }
+
+    #[test]
+    fn test_to_wikilink() {
+        let content = "[My Note](folder/My%20Note.md#Part) [note](note.md) ![pic](img.png)";
+        let links = parse_links(content);
+        assert_eq!(to_wikilink(&links[0]), "[[folder/My Note#Part]]");
+        assert_eq!(to_wikilink(&links[1]), "[[note]]");
+        assert_eq!(to_wikilink(&links[2]), "![[img.png|pic]]");
+
+        let edits = links
+            .iter()
+            .map(|l| (l.span.clone(), to_wikilink(l)))
+            .collect();
+        assert_eq!(
+            replace_spans(content, edits),
+            "[[folder/My Note#Part]] [[note]] ![[img.png|pic]]"
+        );
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        let content = "---\ntags: [fm]\n---\n# Heading #h\n#todo and #area/work, not#this #123\n`#code` [[a#b]]\n";
+        assert_eq!(parse_tags(content), vec!["h", "todo", "area/work"]);
+    }
+
+    #[test]
+    fn test_find_bare_urls() {
+        let content =
+            "Visit https://example.com. Not [this](https://a.b) or <https://c.d>\n`https://code`";
+        let urls = find_bare_urls(content);
+        assert_eq!(urls.len(), 1);
+        assert_eq!(urls[0].url, "https://example.com");
+        assert_eq!(urls[0].column, 7);
+    }
+
+    #[test]
+    fn test_find_web_urls() {
+        let content =
+            "[x](https://a.org) ![](http://i.png) https://b.org/x. [[Local]] [m](mailto:a@b)\n";
+        let found = find_web_urls(content);
+        let urls: Vec<(&str, bool)> = found
+            .iter()
+            .map(|url| (url.url.as_str(), url.embed))
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                ("https://a.org", false),
+                ("http://i.png", true),
+                ("https://b.org/x", false)
+            ]
+        );
+    }
+}
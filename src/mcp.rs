@@ -0,0 +1,393 @@
+use crate::capture;
+use crate::config::AppConfig;
+use crate::data;
+use crate::resolver::{self, Resolver};
+use crate::search;
+use crate::util;
+use crate::write_gate;
+
+use serde_json::{Value, json};
+use std::{
+    error::Error,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+/// Protocol revision answered when the client does not ask for one
+static PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Prefix of the resource URIs under which notes are listed
+static NOTE_URI_PREFIX: &str = "note:///";
+
+fn tool_definitions() -> Value {
+    json!([
+        {
+            "name": "search_notes",
+            "description": "Search the vault's notes by keywords (or meaning, if embeddings are configured). Returns paths, headings and snippets.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "What to search for" },
+                    "limit": { "type": "integer", "description": "Maximum number of notes, default 10" }
+                },
+                "required": ["query"]
+            }
+        },
+        {
+            "name": "read_note",
+            "description": "Read the full Markdown of a note, given its path or link target.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "note": { "type": "string", "description": "Vault-relative path or note name" }
+                },
+                "required": ["note"]
+            }
+        },
+        {
+            "name": "list_backlinks",
+            "description": "List the notes linking to a note, with the linking line.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "note": { "type": "string", "description": "Vault-relative path or note name" }
+                },
+                "required": ["note"]
+            }
+        },
+        {
+            "name": "append_to_note",
+            "description": "Append Markdown text to the end of a note, creating the note if it does not exist.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "note": { "type": "string", "description": "Vault-relative path or note name" },
+                    "text": { "type": "string", "description": "Markdown to append" }
+                },
+                "required": ["note", "text"]
+            }
+        }
+    ])
+}
+
+fn string_arg<'a>(arguments: &'a Value, name: &str) -> Result<&'a str, Box<dyn Error>> {
+    arguments
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("Missing string argument '{}'", name).into())
+}
+
+fn resolve_note(vault_path: &Path, note: &str) -> Result<PathBuf, Box<dyn Error>> {
+    Resolver::from_vault(vault_path)?
+        .resolve(note, Path::new(""))
+        .map(Path::to_path_buf)
+        .ok_or_else(|| format!("No note matches '{}'", note).into())
+}
+
+/// Appends `text` to `note`, resolving it like a link or creating it as a new path.
+fn append_to_note(vault_path: &Path, note: &str, text: &str) -> Result<PathBuf, Box<dyn Error>> {
+    write_gate::check("append to notes")?;
+    let rel_path = match resolve_note(vault_path, note) {
+        Ok(path) => path,
+        Err(_) if data::is_note(Path::new(note)) => PathBuf::from(note),
+        Err(_) => PathBuf::from(format!("{}.md", note)),
+    };
+    capture::ensure_inside_vault(&rel_path)?;
+
+    let file = vault_path.join(&rel_path);
+    let existing = if file.exists() {
+        fs::read_to_string(&file)?
+    } else {
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        log::info!("Created {}", rel_path.display());
+        String::new()
+    };
+    let mut entry = String::new();
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        entry.push('\n');
+    }
+    entry.push_str(text.trim_end());
+    entry.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&file)?
+        .write_all(entry.as_bytes())?;
+    Ok(rel_path)
+}
+
+fn call_tool(
+    vault_path: &Path,
+    config: &AppConfig,
+    name: &str,
+    arguments: &Value,
+) -> Result<String, Box<dyn Error>> {
+    match name {
+        "search_notes" => {
+            let limit = arguments.get("limit").and_then(Value::as_u64).unwrap_or(10);
+            let hits = search::search(
+                vault_path,
+                config,
+                string_arg(arguments, "query")?,
+                None,
+                limit as usize,
+                config.search.snippet_options(),
+            )?;
+            Ok(serde_json::to_string_pretty(&hits)?)
+        }
+        "read_note" => {
+            let rel_path = resolve_note(vault_path, string_arg(arguments, "note")?)?;
+            Ok(fs::read_to_string(vault_path.join(rel_path))?)
+        }
+        "list_backlinks" => {
+            let target = resolve_note(vault_path, string_arg(arguments, "note")?)?;
+            let notes = data::load_notes(vault_path)?;
+            let resolver = Resolver::from_vault(vault_path)?;
+            let found = resolver::backlinks(&notes, &resolver, &target);
+            Ok(serde_json::to_string_pretty(&found)?)
+        }
+        "append_to_note" => {
+            let rel_path = append_to_note(
+                vault_path,
+                string_arg(arguments, "note")?,
+                string_arg(arguments, "text")?,
+            )?;
+            Ok(format!("Appended to {}", rel_path.display()))
+        }
+        _ => Err(format!("Unknown tool '{}'", name).into()),
+    }
+}
+
+fn note_uri(rel_path: &Path) -> String {
+    let path = rel_path.to_string_lossy().replace('\\', "/");
+    format!("{}{}", NOTE_URI_PREFIX, util::percent_encode(&path))
+}
+
+fn list_resources(vault_path: &Path) -> Result<Value, Box<dyn Error>> {
+    let resources: Vec<Value> = data::load_notes(vault_path)?
+        .iter()
+        .map(|note| {
+            json!({
+                "uri": note_uri(&note.path),
+                "name": note.title(),
+                "mimeType": "text/markdown",
+            })
+        })
+        .collect();
+    Ok(json!({ "resources": resources }))
+}
+
+fn read_resource(vault_path: &Path, uri: &str) -> Result<Value, Box<dyn Error>> {
+    let rel_path = uri
+        .strip_prefix(NOTE_URI_PREFIX)
+        .map(|path| PathBuf::from(util::percent_decode(path)))
+        .ok_or_else(|| format!("Unknown resource '{}'", uri))?;
+    capture::ensure_inside_vault(&rel_path)?;
+    let text = fs::read_to_string(vault_path.join(&rel_path))?;
+    Ok(json!({
+        "contents": [{ "uri": uri, "mimeType": "text/markdown", "text": text }]
+    }))
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Answers one JSON-RPC message; notifications get no response.
+pub fn handle(vault_path: &Path, config: &AppConfig, message: &Value) -> Option<Value> {
+    let id = message.get("id").cloned()?;
+    let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = match method {
+        "initialize" => json!({
+            "protocolVersion": params
+                .get("protocolVersion")
+                .and_then(Value::as_str)
+                .unwrap_or(PROTOCOL_VERSION),
+            "capabilities": { "tools": {}, "resources": {} },
+            "serverInfo": { "name": "obsidian-rs", "version": env!("CARGO_PKG_VERSION") },
+        }),
+        "ping" => json!({}),
+        "tools/list" => {
+            // Clients of a read-only vault are not offered tools that change notes
+            let mut tools = tool_definitions();
+            if write_gate::is_read_only()
+                && let Some(tools) = tools.as_array_mut()
+            {
+                tools.retain(|tool| tool["name"] != "append_to_note");
+            }
+            json!({ "tools": tools })
+        }
+        "tools/call" => {
+            let Some(name) = params.get("name").and_then(Value::as_str) else {
+                return Some(error_response(id, -32602, "Missing tool name"));
+            };
+            let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+            // Tool failures are reported to the model rather than as protocol errors
+            match call_tool(vault_path, config, name, &arguments) {
+                Ok(text) => json!({ "content": [{ "type": "text", "text": text }] }),
+                Err(e) => json!({
+                    "content": [{ "type": "text", "text": e.to_string() }],
+                    "isError": true,
+                }),
+            }
+        }
+        "resources/list" => match list_resources(vault_path) {
+            Ok(result) => result,
+            Err(e) => return Some(error_response(id, -32603, &e.to_string())),
+        },
+        "resources/read" => {
+            let Some(uri) = params.get("uri").and_then(Value::as_str) else {
+                return Some(error_response(id, -32602, "Missing resource uri"));
+            };
+            match read_resource(vault_path, uri) {
+                Ok(result) => result,
+                Err(e) => return Some(error_response(id, -32002, &e.to_string())),
+            }
+        }
+        _ => return Some(error_response(id, -32601, "Method not found")),
+    };
+    Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+}
+
+/// Serves the vault over the Model Context Protocol on stdin/stdout, one JSON-RPC
+/// message per line, until stdin closes.
+pub fn run_mcp(vault_path: &Path, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    log::info!("MCP server ready on stdio");
+    let mut stdout = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => handle(vault_path, config, &message),
+            Err(e) => Some(error_response(Value::Null, -32700, &e.to_string())),
+        };
+        if let Some(response) = response {
+            writeln!(stdout, "{}", response)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> tempfile::TempDir {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        fs::create_dir(vault.path().join("sub")).unwrap();
+        fs::write(
+            vault.path().join("Home.md"),
+            "# Home\nSee [[B]] and [[B#Part]].\n",
+        )
+        .unwrap();
+        fs::write(
+            vault.path().join("sub/B.md"),
+            "Back to [Home](../Home.md)\n",
+        )
+        .unwrap();
+        vault
+    }
+
+    fn call(vault_path: &Path, method: &str, params: Value) -> Value {
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        handle(vault_path, &AppConfig::default(), &message).unwrap()
+    }
+
+    fn tool_text(response: &Value) -> &str {
+        response["result"]["content"][0]["text"].as_str().unwrap()
+    }
+
+    #[test]
+    fn test_handle_protocol() {
+        let vault = vault();
+        let init = call(vault.path(), "initialize", json!({}));
+        assert_eq!(init["result"]["protocolVersion"], PROTOCOL_VERSION);
+        let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+        assert!(handle(vault.path(), &AppConfig::default(), &notification).is_none());
+        assert_eq!(
+            call(vault.path(), "nope", json!({}))["error"]["code"],
+            -32601
+        );
+
+        let tools = call(vault.path(), "tools/list", json!({}));
+        assert_eq!(tools["result"]["tools"].as_array().unwrap().len(), 4);
+
+        let resources = call(vault.path(), "resources/list", json!({}));
+        let uris: Vec<&str> = resources["result"]["resources"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|r| r["uri"].as_str())
+            .collect();
+        assert!(uris.contains(&"note:///sub/B.md"));
+        let read = call(
+            vault.path(),
+            "resources/read",
+            json!({ "uri": "note:///sub/B.md" }),
+        );
+        assert!(
+            read["result"]["contents"][0]["text"]
+                .as_str()
+                .unwrap()
+                .contains("Back to")
+        );
+        let escape = call(
+            vault.path(),
+            "resources/read",
+            json!({ "uri": "note:///../x.md" }),
+        );
+        assert!(escape.get("error").is_some());
+    }
+
+    #[test]
+    fn test_tools() {
+        let vault = vault();
+        let read = call(
+            vault.path(),
+            "tools/call",
+            json!({ "name": "read_note", "arguments": { "note": "B" } }),
+        );
+        assert_eq!(tool_text(&read), "Back to [Home](../Home.md)\n");
+
+        let backlinks = call(
+            vault.path(),
+            "tools/call",
+            json!({ "name": "list_backlinks", "arguments": { "note": "Home" } }),
+        );
+        assert!(tool_text(&backlinks).contains("sub/B.md"));
+
+        call(
+            vault.path(),
+            "tools/call",
+            json!({ "name": "append_to_note", "arguments": { "note": "B", "text": "- more" } }),
+        );
+        call(
+            vault.path(),
+            "tools/call",
+            json!({ "name": "append_to_note", "arguments": { "note": "New", "text": "hi" } }),
+        );
+        assert_eq!(
+            fs::read_to_string(vault.path().join("sub/B.md")).unwrap(),
+            "Back to [Home](../Home.md)\n- more\n"
+        );
+        assert_eq!(
+            fs::read_to_string(vault.path().join("New.md")).unwrap(),
+            "hi\n"
+        );
+
+        let missing = call(
+            vault.path(),
+            "tools/call",
+            json!({ "name": "read_note", "arguments": { "note": "Missing" } }),
+        );
+        assert_eq!(missing["result"]["isError"], true);
+    }
+}
@@ -0,0 +1,430 @@
+//! Audio and video attachments: container, codec and duration kept in the cache database,
+//! and transcripts from the `[media] transcribe` command, indexed under the recording's own
+//! path so search finds what was said, credited to the notes that embed it.
+
+use crate::cli::{MediaCommand, MediaListArgs, OutputFormat};
+use crate::config::AppConfig;
+use crate::data;
+use crate::hooks;
+use crate::index::{self, Chunk, MAX_CHUNK_BYTES};
+use crate::search;
+use crate::util;
+
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{collections::HashMap, error::Error, path::Path};
+#[cfg(feature = "media-probe")]
+use std::fs;
+#[cfg(feature = "media-probe")]
+use symphonia::core::{
+    formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav", "m4a", "ogg", "oga", "opus", "flac", "aac"];
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm", "ogv"];
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct MediaInfo {
+    /// Vault-relative path
+    pub path: String,
+    /// "audio" or "video"
+    pub kind: String,
+    pub bytes: u64,
+    /// Codec of the main track, when it could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// Length in seconds, when the container records it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+}
+
+/// Whether this build reads codecs and durations. Rows record it, so enabling the
+/// `media-probe` feature later probes the files an earlier build could not.
+const PROBES: bool = cfg!(feature = "media-probe");
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    index::drop_outdated(connection, "media", "probed")?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS media (
+            path TEXT PRIMARY KEY,
+            bytes INTEGER NOT NULL,
+            modified INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            codec TEXT,
+            duration REAL,
+            probed INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS transcripts (
+            path TEXT PRIMARY KEY,
+            text TEXT NOT NULL
+        );",
+    )
+}
+
+/// "audio" or "video" for media attachments, judged by extension.
+pub fn kind(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    if AUDIO_EXTENSIONS.contains(&ext.as_str()) {
+        Some("audio")
+    } else if VIDEO_EXTENSIONS.contains(&ext.as_str()) {
+        Some("video")
+    } else {
+        None
+    }
+}
+
+/// The codec of the default track and the longest track's duration.
+#[cfg(feature = "media-probe")]
+fn probe(file: &Path) -> Result<(Option<String>, Option<f64>), Box<dyn Error>> {
+    let source = MediaSourceStream::new(Box::new(fs::File::open(file)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = file.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let reader = probed.format;
+    let codec = reader
+        .default_track()
+        .or_else(|| reader.tracks().first())
+        .and_then(|track| symphonia::default::get_codecs().get_codec(track.codec_params.codec))
+        .map(|codec| codec.short_name.to_string());
+    let duration = reader
+        .tracks()
+        .iter()
+        .filter_map(|track| {
+            let params = &track.codec_params;
+            let time = params.time_base?.calc_time(params.n_frames?);
+            Some(time.seconds as f64 + time.frac)
+        })
+        .reduce(f64::max);
+    Ok((codec, duration))
+}
+
+/// Without the `media-probe` feature recordings are listed without codec or duration.
+#[cfg(not(feature = "media-probe"))]
+fn probe(_file: &Path) -> Result<(Option<String>, Option<f64>), Box<dyn Error>> {
+    Ok((None, None))
+}
+
+/// Brings the media table up to date with the vault, probing only new or changed files,
+/// and returns every recording in it.
+pub fn refresh(
+    connection: &Connection,
+    vault_path: &Path,
+) -> Result<Vec<MediaInfo>, Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let mut known = HashMap::new();
+    let mut statement = connection.prepare("SELECT path, bytes, modified, probed FROM media")?;
+    while let State::Row = statement.next()? {
+        known.insert(
+            statement.read::<String, _>(0)?,
+            (
+                (
+                    statement.read::<i64, _>(2)?,
+                    statement.read::<i64, _>(1)? as u64,
+                ),
+                statement.read::<i64, _>(3)? != 0,
+            ),
+        );
+    }
+
+    for file in data::traverse_vault(vault_path)? {
+        let (Some(kind), Ok(rel_path)) = (kind(&file), file.strip_prefix(vault_path)) else {
+            continue;
+        };
+        let rel_path = rel_path.to_string_lossy().replace('\\', "/");
+        let stamp = util::file_stamp(&file)?;
+        if known
+            .remove(&rel_path)
+            .is_some_and(|(known, probed)| known == stamp && (probed || !PROBES))
+        {
+            continue;
+        }
+        let (codec, duration) = probe(&file).unwrap_or_else(|e| {
+            log::debug!("Could not probe {}: {}", rel_path, e);
+            (None, None)
+        });
+        let mut statement = connection.prepare(
+            "INSERT OR REPLACE INTO media (path, bytes, modified, kind, codec, duration, probed)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        statement.bind((1, rel_path.as_str()))?;
+        statement.bind((2, stamp.1 as i64))?;
+        statement.bind((3, stamp.0))?;
+        statement.bind((4, kind))?;
+        statement.bind((5, codec.as_deref()))?;
+        statement.bind((6, duration))?;
+        statement.bind((7, i64::from(PROBES)))?;
+        statement.next()?;
+    }
+    for gone in known.keys() {
+        let mut statement = connection.prepare("DELETE FROM media WHERE path = ?")?;
+        statement.bind((1, gone.as_str()))?;
+        statement.next()?;
+    }
+
+    let mut statement =
+        connection.prepare("SELECT path, kind, bytes, codec, duration FROM media ORDER BY path")?;
+    let mut media = Vec::new();
+    while let State::Row = statement.next()? {
+        media.push(MediaInfo {
+            path: statement.read(0)?,
+            kind: statement.read(1)?,
+            bytes: statement.read::<i64, _>(2)? as u64,
+            codec: statement.read(3)?,
+            duration: statement.read(4)?,
+        });
+    }
+    Ok(media)
+}
+
+/// Runs the transcribe command on `file`, returning what it printed.
+fn transcribe(command: &str, vault_path: &Path, file: &Path) -> Result<String, Box<dyn Error>> {
+    let variables = [("path", file.to_string_lossy().into_owned())];
+    let output = hooks::shell_command(command, vault_path, &variables).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{} ({})", output.status, stderr.trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn store_transcript(
+    connection: &Connection,
+    path: &str,
+    text: Option<&str>,
+) -> Result<(), sqlite::Error> {
+    let mut statement = match text {
+        Some(_) => {
+            connection.prepare("INSERT OR REPLACE INTO transcripts (path, text) VALUES (?, ?)")?
+        }
+        None => connection.prepare("DELETE FROM transcripts WHERE path = ?")?,
+    };
+    statement.bind((1, path))?;
+    if let Some(text) = text {
+        statement.bind((2, text))?;
+    }
+    statement.next()?;
+    Ok(())
+}
+
+/// Transcribes the recordings that changed and indexes their transcripts, returning how
+/// many were transcribed. Without `[media] transcribe`, drops the transcripts instead.
+pub fn update_transcripts(
+    connection: &Connection,
+    vault_path: &Path,
+    config: &AppConfig,
+) -> Result<usize, Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let mut known = HashMap::new();
+    let mut statement = connection.prepare("SELECT path, hash FROM indexed_files")?;
+    while let State::Row = statement.next()? {
+        let path = statement.read::<String, _>(0)?;
+        if kind(Path::new(&path)).is_some() {
+            known.insert(path, statement.read::<String, _>(1)?);
+        }
+    }
+
+    let Some(command) = &config.media.transcribe else {
+        // Transcripts were turned off; forget those indexed before
+        for path in known.keys() {
+            index::remove_file(connection, path)?;
+            store_transcript(connection, path, None)?;
+        }
+        return Ok(0);
+    };
+
+    let mut transcribed = 0;
+    for file in data::traverse_vault(vault_path)? {
+        let (Some(_), Ok(rel_path)) = (kind(&file), file.strip_prefix(vault_path)) else {
+            continue;
+        };
+        let path = rel_path.to_string_lossy().replace('\\', "/");
+        let (modified, bytes) = util::file_stamp(&file)?;
+        let hash = format!("media:{}:{}", bytes, modified);
+        if known.remove(&path).as_ref() == Some(&hash) {
+            continue;
+        }
+        log::info!("Transcribing {}", path);
+        // A recording that fails is still marked, so it is not retried until it changes
+        let text = transcribe(command, vault_path, &file).unwrap_or_else(|e| {
+            log::warn!("Could not transcribe {}: {}", path, e);
+            String::new()
+        });
+        let title = rel_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        connection.execute("BEGIN")?;
+        index::remove_file(connection, &path)?;
+        store_transcript(connection, &path, Some(text.trim()))?;
+        for text in index::split_lines(&text, MAX_CHUNK_BYTES) {
+            let chunk = Chunk {
+                path: path.clone(),
+                heading: String::from("Transcript"),
+                line: 1,
+                text,
+            };
+            index::insert_chunk(connection, &chunk, &title)?;
+        }
+        index::mark_indexed(connection, &path, &hash)?;
+        connection.execute("COMMIT")?;
+        transcribed += 1;
+    }
+    for gone in known.keys() {
+        index::remove_file(connection, gone)?;
+        store_transcript(connection, gone, None)?;
+    }
+    Ok(transcribed)
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+fn list(vault_path: &Path, config: &AppConfig, args: &MediaListArgs) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    let media = refresh(&connection, vault_path)?;
+    match args.format {
+        OutputFormat::Text => {
+            for item in &media {
+                println!(
+                    "{}  {}  {}  {}  {} KB",
+                    item.path,
+                    item.kind,
+                    item.codec.as_deref().unwrap_or("?"),
+                    item.duration.map_or(String::from("?"), format_duration),
+                    item.bytes / 1024
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&media)?),
+        OutputFormat::Ndjson => {
+            for item in &media {
+                util::print_ndjson(item)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn show_transcript(
+    vault_path: &Path,
+    config: &AppConfig,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    if config.media.transcribe.is_none() {
+        return Err("Transcripts are off; set [media] transcribe".into());
+    }
+    let connection = index::open(config)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    search::refresh_attachments(&connection, vault_path, config)?;
+    let mut statement = connection.prepare("SELECT text FROM transcripts WHERE path = ?")?;
+    statement.bind((1, path.trim_start_matches("./")))?;
+    match statement.next()? {
+        State::Row => println!("{}", statement.read::<String, _>(0)?),
+        State::Done => return Err(format!("No transcript of '{}'", path).into()),
+    }
+    Ok(())
+}
+
+pub fn run_media(
+    vault_path: &Path,
+    config: &AppConfig,
+    command: &MediaCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        MediaCommand::List(args) => list(vault_path, config, args),
+        MediaCommand::Transcript(args) => show_transcript(vault_path, config, &args.path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MediaConfig;
+    use std::fs;
+
+    #[test]
+    fn test_probe_and_transcribe() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        // One second of 8 kHz mono silence
+        let mut wav = b"RIFF".to_vec();
+        wav.extend((36u32 + 16000).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes()); // PCM
+        wav.extend(1u16.to_le_bytes()); // mono
+        wav.extend(8000u32.to_le_bytes());
+        wav.extend(16000u32.to_le_bytes()); // bytes per second
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(16000u32.to_le_bytes());
+        wav.resize(wav.len() + 16000, 0);
+        fs::write(vault.path().join("memo.wav"), wav).unwrap();
+
+        let connection = index::test_connection(&[]);
+        let media = refresh(&connection, vault.path()).unwrap();
+        assert_eq!(media.len(), 1);
+        assert_eq!(media[0].kind, "audio");
+        #[cfg(feature = "media-probe")]
+        assert_eq!(media[0].duration, Some(1.0));
+        assert_eq!(format_duration(3725.0), "1:02:05");
+
+        let config = AppConfig {
+            media: MediaConfig {
+                transcribe: Some(String::from("echo 'the quarterly numbers'")),
+            },
+            ..AppConfig::default()
+        };
+        assert_eq!(
+            update_transcripts(&connection, vault.path(), &config).unwrap(),
+            1
+        );
+        assert_eq!(
+            update_transcripts(&connection, vault.path(), &config).unwrap(),
+            0
+        );
+        let hits = index::keyword_search(
+            &connection,
+            "quarterly",
+            5,
+            index::SnippetOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(hits[0].path, "memo.wav");
+    }
+
+    #[test]
+    fn test_builds_that_could_not_probe_are_redone() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        fs::write(vault.path().join("memo.mp3"), "not audio").unwrap();
+        let connection = index::test_connection(&[]);
+        // A media table from before rows recorded whether they were probed
+        connection
+            .execute("CREATE TABLE media (path TEXT PRIMARY KEY, bytes INTEGER NOT NULL)")
+            .unwrap();
+
+        refresh(&connection, vault.path()).unwrap();
+        connection
+            .execute("UPDATE media SET probed = 0, codec = 'stale'")
+            .unwrap();
+        let media = refresh(&connection, vault.path()).unwrap();
+        let expected = match PROBES {
+            true => None,
+            false => Some(String::from("stale")),
+        };
+        assert_eq!(media[0].codec, expected);
+    }
+}
@@ -0,0 +1,248 @@
+//! Three-way merge of notes: front matter key by key, the body line by line (diff3).
+
+use crate::cli::MergeArgs;
+use crate::diff::{self, DiffOp};
+use crate::frontmatter;
+use crate::util;
+use crate::write_gate;
+
+use serde_yaml::{Mapping, Value};
+use std::{error::Error, fs, path::Path};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    pub text: String,
+    /// Places where both sides changed the same thing differently
+    pub conflicts: usize,
+}
+
+/// Names shown on conflict markers
+#[derive(Debug, Clone, Copy)]
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub theirs: &'a str,
+}
+
+fn push_lines(out: &mut String, lines: &[&str]) {
+    for line in lines {
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+/// Base line index -> line index in `side`, for lines the diff keeps
+fn matches(base: &[&str], side: &[&str]) -> Vec<Option<usize>> {
+    let mut matched = vec![None; base.len()];
+    for op in diff::diff_lines(base, side) {
+        if let DiffOp::Equal(i, j) = op {
+            matched[i] = Some(j);
+        }
+    }
+    matched
+}
+
+/// Line-based diff3: regions changed on one side only take that side; regions changed
+/// on both sides identically are taken once; anything else becomes a conflict.
+pub fn merge_lines(base: &str, ours: &str, theirs: &str, labels: Labels) -> Merged {
+    let base: Vec<&str> = base.lines().collect();
+    let ours: Vec<&str> = ours.lines().collect();
+    let theirs: Vec<&str> = theirs.lines().collect();
+    let in_ours = matches(&base, &ours);
+    let in_theirs = matches(&base, &theirs);
+
+    let mut text = String::new();
+    let mut conflicts = 0;
+    let (mut i, mut j, mut k) = (0, 0, 0);
+    loop {
+        // Lines unchanged on both sides are copied as they are
+        while i < base.len() && in_ours[i] == Some(j) && in_theirs[i] == Some(k) {
+            push_lines(&mut text, &[base[i]]);
+            (i, j, k) = (i + 1, j + 1, k + 1);
+        }
+        if i >= base.len() && j >= ours.len() && k >= theirs.len() {
+            break;
+        }
+        // The changed region runs up to the next base line both sides kept
+        let next = (i..base.len()).find(|&n| in_ours[n].is_some() && in_theirs[n].is_some());
+        let (end_base, end_ours, end_theirs) = match next {
+            Some(n) => (n, in_ours[n].unwrap_or(j), in_theirs[n].unwrap_or(k)),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+        let (b, o, t) = (
+            &base[i..end_base],
+            &ours[j..end_ours],
+            &theirs[k..end_theirs],
+        );
+        if o == b || o == t {
+            push_lines(&mut text, t);
+        } else if t == b {
+            push_lines(&mut text, o);
+        } else {
+            conflicts += 1;
+            text.push_str(&format!("<<<<<<< {}\n", labels.ours));
+            push_lines(&mut text, o);
+            text.push_str("=======\n");
+            push_lines(&mut text, t);
+            text.push_str(&format!(">>>>>>> {}\n", labels.theirs));
+        }
+        (i, j, k) = (end_base, end_ours, end_theirs);
+    }
+    Merged { text, conflicts }
+}
+
+/// Items of `ours` and `theirs` together, less those either side removed from `base`.
+fn merge_sequences(base: &[Value], ours: &[Value], theirs: &[Value]) -> Vec<Value> {
+    let removed = |side: &[Value], item: &Value| base.contains(item) && !side.contains(item);
+    let mut merged: Vec<Value> = Vec::new();
+    for item in ours.iter().chain(theirs) {
+        if !merged.contains(item) && !removed(ours, item) && !removed(theirs, item) {
+            merged.push(item.clone());
+        }
+    }
+    merged
+}
+
+/// Merges two YAML mappings key by key. Lists changed on both sides are combined; other
+/// keys changed differently on both sides keep our value and count as conflicts.
+pub fn merge_mappings(base: &Mapping, ours: &Mapping, theirs: &Mapping) -> (Mapping, usize) {
+    let mut merged = Mapping::new();
+    let mut conflicts = 0;
+    for key in ours
+        .keys()
+        .chain(theirs.keys().filter(|key| !ours.contains_key(*key)))
+    {
+        let (b, o, t) = (base.get(key), ours.get(key), theirs.get(key));
+        let value = if o == t || t == b {
+            o
+        } else if o == b {
+            t
+        } else if let (Some(Value::Sequence(o)), Some(Value::Sequence(t))) = (o, t) {
+            let b = match b {
+                Some(Value::Sequence(b)) => b.as_slice(),
+                _ => &[],
+            };
+            merged.insert(key.clone(), Value::Sequence(merge_sequences(b, o, t)));
+            continue;
+        } else {
+            conflicts += 1;
+            o
+        };
+        if let Some(value) = value {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+    (merged, conflicts)
+}
+
+/// Three-way merge of a note's versions. Front matter changed on both sides is merged key
+/// by key (and rewritten, losing comments); the body is merged with `merge_lines`.
+pub fn merge_notes(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    labels: Labels,
+) -> Result<Merged, Box<dyn Error>> {
+    let (base_yaml, base_body) = frontmatter::split(base);
+    let (ours_yaml, ours_body) = frontmatter::split(ours);
+    let (theirs_yaml, theirs_body) = frontmatter::split(theirs);
+    let body = merge_lines(base_body, ours_body, theirs_body, labels);
+
+    let (yaml, yaml_conflicts) = if ours_yaml == theirs_yaml || theirs_yaml == base_yaml {
+        (ours_yaml.map(String::from), 0)
+    } else if ours_yaml == base_yaml {
+        (theirs_yaml.map(String::from), 0)
+    } else {
+        let mapping = |yaml: Option<&str>| -> Result<Mapping, Box<dyn Error>> {
+            match yaml {
+                Some(yaml) if !yaml.trim().is_empty() => Ok(serde_yaml::from_str(yaml)?),
+                _ => Ok(Mapping::new()),
+            }
+        };
+        let (merged, conflicts) = merge_mappings(
+            &mapping(base_yaml)?,
+            &mapping(ours_yaml)?,
+            &mapping(theirs_yaml)?,
+        );
+        (Some(serde_yaml::to_string(&merged)?), conflicts)
+    };
+
+    let text = match yaml {
+        Some(yaml) => format!("---\n{}---\n{}", yaml, body.text),
+        None => body.text,
+    };
+    Ok(Merged {
+        text,
+        conflicts: body.conflicts + yaml_conflicts,
+    })
+}
+
+/// Merges the files and writes the result, returning the number of conflicts.
+pub fn run_merge(vault_path: &Path, args: &MergeArgs) -> Result<usize, Box<dyn Error>> {
+    let read = |path: &Path| {
+        fs::read_to_string(path).map_err(|e| format!("Cannot read '{}': {}", path.display(), e))
+    };
+    let ours_label = args.ours.to_string_lossy();
+    let theirs_label = args.theirs.to_string_lossy();
+    let merged = merge_notes(
+        &read(&args.base)?,
+        &read(&args.ours)?,
+        &read(&args.theirs)?,
+        Labels {
+            ours: &ours_label,
+            theirs: &theirs_label,
+        },
+    )?;
+
+    match &args.out {
+        Some(out) => {
+            if util::is_within(out, vault_path) {
+                write_gate::check("write merged notes")?;
+            }
+            util::safe_write(out, &merged.text, false)?;
+            log::info!("Wrote {}", out.display());
+        }
+        None => print!("{}", merged.text),
+    }
+    if merged.conflicts > 0 {
+        log::warn!("{} conflict(s) left to resolve", merged.conflicts);
+    }
+    Ok(merged.conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static LABELS: Labels = Labels {
+        ours: "ours",
+        theirs: "theirs",
+    };
+
+    #[test]
+    fn test_merge_lines() {
+        let base = "a\nb\nc\nd\n";
+        let merged = merge_lines(base, "A\nb\nc\nd\n", "a\nb\nc\nD\n", LABELS);
+        assert_eq!(merged.text, "A\nb\nc\nD\n");
+        assert_eq!(merged.conflicts, 0);
+
+        let merged = merge_lines(base, "a\nX\nc\nd\n", "a\nY\nc\nd\ne\n", LABELS);
+        assert_eq!(
+            merged.text,
+            "a\n<<<<<<< ours\nX\n=======\nY\n>>>>>>> theirs\nc\nd\ne\n"
+        );
+        assert_eq!(merged.conflicts, 1);
+    }
+
+    #[test]
+    fn test_merge_notes_front_matter() {
+        let base = "---\ntitle: T\ntags: [a, b]\n---\nbody\n";
+        let ours = "---\ntitle: Ours\ntags: [a, b, c]\n---\nbody\n";
+        let theirs = "---\ntitle: T\ntags: [b, d]\nstatus: done\n---\nbody\nmore\n";
+        let merged = merge_notes(base, ours, theirs, LABELS).unwrap();
+        assert_eq!(
+            merged.text,
+            "---\ntitle: Ours\ntags:\n- b\n- c\n- d\nstatus: done\n---\nbody\nmore\n"
+        );
+        assert_eq!(merged.conflicts, 0);
+    }
+}
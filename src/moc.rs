@@ -0,0 +1,287 @@
+//! Maps of content: index notes listing the notes that match a query, grouped by folder
+//! or tag. The list sits between `%% begin moc … %%` and `%% end moc %%` comments, which
+//! Obsidian hides in reading view, so `moc refresh` and the daemon can rewrite it in place.
+
+use crate::changeset::{self, ChangeSet};
+use crate::cli::{ChangeArgs, MocCommand, MocGenerateArgs, MocGroup};
+use crate::collation;
+use crate::data::{self, Note};
+use crate::link_to::{self, LinkSettings};
+use crate::query::Query;
+use crate::resolver::Resolver;
+
+use clap::ValueEnum;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const BEGIN: &str = "%% begin moc";
+const END: &str = "%% end moc %%";
+
+/// What a map lists, as recorded in its begin marker
+#[derive(Debug, Clone, PartialEq)]
+struct MocSpec {
+    query: String,
+    group_by: MocGroup,
+}
+
+impl MocSpec {
+    fn marker(&self) -> String {
+        let group_by = self
+            .group_by
+            .to_possible_value()
+            .expect("no skipped variants");
+        format!("{} ({}): {} %%", BEGIN, group_by.get_name(), self.query)
+    }
+
+    /// The spec of a `%% begin moc (group): query %%` line.
+    fn parse(line: &str) -> Option<MocSpec> {
+        let rest = line.trim().strip_prefix(BEGIN)?.strip_suffix("%%")?;
+        let (group_by, query) = rest.trim().strip_prefix('(')?.split_once("):")?;
+        Some(MocSpec {
+            query: query.trim().to_string(),
+            group_by: MocGroup::from_str(group_by, true).ok()?,
+        })
+    }
+}
+
+/// Everything needed to write links into a map
+struct Links {
+    resolver: Resolver,
+    settings: LinkSettings,
+}
+
+/// The block for `spec`, markers included, listing `notes` except the map at `own_path`.
+fn render_block(
+    spec: &MocSpec,
+    notes: &[Note],
+    own_path: &Path,
+    links: &Links,
+) -> Result<String, Box<dyn Error>> {
+    let query = Query::parse(&spec.query)?;
+    let mut groups: BTreeMap<String, Vec<&Note>> = BTreeMap::new();
+    for note in query.filter(notes) {
+        if note.path == own_path {
+            continue;
+        }
+        match spec.group_by {
+            MocGroup::Folder => {
+                let folder = match note.path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => {
+                        parent.to_string_lossy().replace('\\', "/")
+                    }
+                    _ => String::from("/"),
+                };
+                groups.entry(folder).or_default().push(note);
+            }
+            MocGroup::Tag if note.tags.is_empty() => {
+                groups
+                    .entry(String::from("Untagged"))
+                    .or_default()
+                    .push(note);
+            }
+            MocGroup::Tag => {
+                for tag in &note.tags {
+                    groups.entry(tag.clone()).or_default().push(note);
+                }
+            }
+        }
+    }
+
+    let mut block = format!("{}\n", spec.marker());
+    for (i, (group, notes)) in groups.iter_mut().enumerate() {
+        notes.sort_by_cached_key(|note| collation::sort_key(&note.title()));
+        if i > 0 {
+            block.push('\n');
+        }
+        // Tags are written without `#` so the headings do not tag the map itself
+        block.push_str(&format!("## {}\n", group));
+        for note in notes.iter() {
+            let link =
+                link_to::link_text(&links.resolver, own_path, &note.path, None, links.settings);
+            block.push_str(&format!("- {}\n", link));
+        }
+    }
+    block.push_str(END);
+    block.push('\n');
+    Ok(block)
+}
+
+/// `content` of the map at `own_path` with every block regenerated. `spec`, if given,
+/// replaces what the first block lists.
+fn refresh_content(
+    content: &str,
+    spec: Option<&MocSpec>,
+    notes: &[Note],
+    own_path: &Path,
+    links: &Links,
+) -> Result<String, Box<dyn Error>> {
+    let mut refreshed = String::new();
+    let mut lines = content.split_inclusive('\n');
+    let mut first = true;
+    while let Some(line) = lines.next() {
+        let Some(found) = MocSpec::parse(line) else {
+            refreshed.push_str(line);
+            continue;
+        };
+        if !lines.any(|line| line.trim() == END) {
+            return Err(format!("'{}' has no matching '{}'", line.trim(), END).into());
+        }
+        let spec = match spec {
+            Some(spec) if first => spec,
+            _ => &found,
+        };
+        refreshed.push_str(&render_block(spec, notes, own_path, links)?);
+        first = false;
+    }
+    Ok(refreshed)
+}
+
+fn contains_map(content: &str) -> bool {
+    content.lines().any(|line| MocSpec::parse(line).is_some())
+}
+
+fn generate(vault_path: &Path, args: &MocGenerateArgs) -> Result<(), Box<dyn Error>> {
+    let mut out = PathBuf::from(&args.out);
+    if out.extension().is_none() {
+        out.set_extension("md");
+    }
+    let spec = MocSpec {
+        query: args.query.clone(),
+        group_by: args.group_by,
+    };
+    let notes = data::load_notes(vault_path)?;
+    let links = Links {
+        resolver: Resolver::from_vault(vault_path)?,
+        settings: LinkSettings::from_vault(vault_path),
+    };
+
+    let before = fs::read_to_string(vault_path.join(&out)).ok();
+    let after = match &before {
+        Some(content) if contains_map(content) => {
+            refresh_content(content, Some(&spec), &notes, &out, &links)?
+        }
+        Some(content) => format!(
+            "{}\n\n{}",
+            content.trim_end(),
+            render_block(&spec, &notes, &out, &links)?
+        ),
+        None => {
+            let title = out.file_stem().unwrap_or_default().to_string_lossy();
+            format!(
+                "# {}\n\n{}",
+                title,
+                render_block(&spec, &notes, &out, &links)?
+            )
+        }
+    };
+    let mut changes = ChangeSet::new();
+    changes.propose(out, before, after);
+    changes.finish(vault_path, &args.changes, "generate map of content", false)?;
+    Ok(())
+}
+
+/// Edits regenerating every map of content in the vault, or only those in the note at
+/// `only`.
+fn refresh_changes(vault_path: &Path, only: Option<&Path>) -> Result<ChangeSet, Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    let mut changes = ChangeSet::new();
+    let maps: Vec<&Note> = notes
+        .iter()
+        .filter(|note| only.is_none_or(|path| note.path == path) && contains_map(&note.content))
+        .collect();
+    if maps.is_empty() {
+        return Ok(changes);
+    }
+    let links = Links {
+        resolver: Resolver::from_vault(vault_path)?,
+        settings: LinkSettings::from_vault(vault_path),
+    };
+    for map in maps {
+        match refresh_content(&map.content, None, &notes, &map.path, &links) {
+            Ok(after) => changes.propose(&map.path, Some(map.content.clone()), after),
+            Err(e) => log::warn!("Skipping map {}: {}", map.path.display(), e),
+        }
+    }
+    Ok(changes)
+}
+
+/// Rewrites the maps of content that are out of date, for the daemon once changes settle.
+pub fn refresh_vault(vault_path: &Path) {
+    changeset::refresh_generated(vault_path, "maps of content", || {
+        refresh_changes(vault_path, None)
+    });
+}
+
+/// Regenerates the maps of content in the note at `path`, returning the number of edits.
+pub fn refresh_note(vault_path: &Path, path: &Path) -> Result<usize, Box<dyn Error>> {
+    let changes = refresh_changes(vault_path, Some(path))?;
+    changes.apply(vault_path, "refresh maps of content", false)?;
+    Ok(changes.len())
+}
+
+fn refresh(vault_path: &Path, args: &ChangeArgs) -> Result<(), Box<dyn Error>> {
+    refresh_changes(vault_path, None)?.finish(
+        vault_path,
+        args,
+        "refresh maps of content",
+        false,
+    )?;
+    Ok(())
+}
+
+pub fn run_moc(vault_path: &Path, command: &MocCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        MocCommand::Generate(args) => generate(vault_path, args),
+        MocCommand::Refresh(args) => refresh(vault_path, &args.changes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh_content() {
+        let notes: Vec<Note> = [
+            ("Projects/A.md", "#projectx\n"),
+            ("Projects/Sub/B.md", "#projectx #urgent\n"),
+            ("C.md", "#other\n"),
+            ("Maps/X.md", "#projectx\n"),
+        ]
+        .iter()
+        .map(|(path, content)| Note::from_content(PathBuf::from(path), content.to_string()))
+        .collect();
+        let links = Links {
+            resolver: Resolver::new(notes.iter().map(|note| note.path.clone()).collect()),
+            settings: LinkSettings::default(),
+        };
+        let own_path = Path::new("Maps/X.md");
+        let content =
+            "# X\n\nIntro\n%% begin moc (folder): tag:#projectx %%\nstale\n%% end moc %%\nOutro\n";
+
+        let spec = MocSpec::parse("%% begin moc (folder): tag:#projectx %%").unwrap();
+        assert_eq!(spec.group_by, MocGroup::Folder);
+        assert_eq!(spec.marker(), "%% begin moc (folder): tag:#projectx %%");
+
+        let refreshed = refresh_content(content, None, &notes, own_path, &links).unwrap();
+        assert_eq!(
+            refreshed,
+            "# X\n\nIntro\n%% begin moc (folder): tag:#projectx %%\n## Projects\n- [[A]]\n\n\
+             ## Projects/Sub\n- [[B]]\n%% end moc %%\nOutro\n"
+        );
+
+        let by_tag = MocSpec {
+            query: String::from("tag:#projectx"),
+            group_by: MocGroup::Tag,
+        };
+        let refreshed = refresh_content(content, Some(&by_tag), &notes, own_path, &links).unwrap();
+        assert!(refreshed.contains("## projectx\n- [[A]]\n- [[B]]\n\n## urgent\n- [[B]]\n"));
+        assert!(
+            refresh_content("%% begin moc (tag): x %%\n", None, &notes, own_path, &links).is_err()
+        );
+    }
+}
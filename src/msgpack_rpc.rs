@@ -0,0 +1,169 @@
+//! msgpack-rpc transport for the stdio server, as preferred by Neovim plugins. Requests
+//! are translated to the JSON-RPC methods of `mcp::handle`, so both share one backend.
+//!
+//! A request `[0, msgid, method, params]` takes its JSON-RPC params from `params[0]`
+//! (e.g. `rpcrequest(chan, "tools/call", {"name": ...})`); the reply is
+//! `[1, msgid, error, result]` with `error` as `{code, message}` or nil.
+
+use crate::config::AppConfig;
+use crate::mcp;
+
+use rmpv::Value as Msgpack;
+use serde_json::{Value, json};
+use std::{
+    error::Error,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+const REQUEST: i64 = 0;
+const RESPONSE: i64 = 1;
+const NOTIFICATION: i64 = 2;
+
+fn to_json(value: Msgpack) -> Result<Value, Box<dyn Error>> {
+    Ok(rmpv::ext::from_value(value)?)
+}
+
+fn to_msgpack(value: &Value) -> Result<Msgpack, Box<dyn Error>> {
+    Ok(rmpv::ext::to_value(value)?)
+}
+
+/// JSON-RPC params from msgpack-rpc's positional argument list
+fn params_of(params: Option<Msgpack>) -> Result<Value, Box<dyn Error>> {
+    match params.map(to_json).transpose()? {
+        Some(Value::Array(mut args)) if args.len() == 1 => Ok(args.remove(0)),
+        Some(Value::Array(args)) if args.is_empty() => Ok(Value::Null),
+        Some(other) => Ok(other),
+        None => Ok(Value::Null),
+    }
+}
+
+/// Answers one msgpack-rpc message; notifications and responses get no reply.
+pub fn handle(
+    vault_path: &Path,
+    config: &AppConfig,
+    message: Msgpack,
+) -> Result<Option<Msgpack>, Box<dyn Error>> {
+    let Msgpack::Array(parts) = message else {
+        return Err("msgpack-rpc messages are arrays".into());
+    };
+    let mut parts = parts.into_iter();
+    let kind = parts.next().and_then(|kind| kind.as_i64());
+    let (id, method, params) = match kind {
+        Some(REQUEST) => {
+            let id = parts.next().ok_or("Request without msgid")?;
+            (Some(id), parts.next(), parts.next())
+        }
+        Some(NOTIFICATION) => (None, parts.next(), parts.next()),
+        Some(RESPONSE) => return Ok(None),
+        _ => return Err("Unknown msgpack-rpc message type".into()),
+    };
+    let method = method
+        .as_ref()
+        .and_then(Msgpack::as_str)
+        .ok_or("Message without method name")?
+        .to_string();
+
+    let mut request = json!({ "jsonrpc": "2.0", "method": method, "params": params_of(params)? });
+    if let Some(id) = &id {
+        request["id"] = to_json(id.clone())?;
+    }
+    let (Some(id), Some(response)) = (id, mcp::handle(vault_path, config, &request)) else {
+        return Ok(None);
+    };
+    let error = match response.get("error") {
+        Some(error) => to_msgpack(error)?,
+        None => Msgpack::Nil,
+    };
+    let result = match response.get("result") {
+        Some(result) => to_msgpack(result)?,
+        None => Msgpack::Nil,
+    };
+    Ok(Some(Msgpack::Array(vec![
+        Msgpack::from(RESPONSE),
+        id,
+        error,
+        result,
+    ])))
+}
+
+/// Serves msgpack-rpc on stdin/stdout until stdin closes.
+pub fn run_msgpack_rpc(vault_path: &Path, config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    log::info!("msgpack-rpc server ready on stdio");
+    serve(vault_path, config, io::stdin().lock(), io::stdout().lock())
+}
+
+fn serve(
+    vault_path: &Path,
+    config: &AppConfig,
+    input: impl Read,
+    output: impl Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut input = BufReader::new(input);
+    let mut output = BufWriter::new(output);
+    loop {
+        let message = match rmpv::decode::read_value(&mut input) {
+            Ok(message) => message,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        match handle(vault_path, config, message) {
+            Ok(Some(reply)) => {
+                rmpv::encode::write_value(&mut output, &reply)?;
+                output.flush()?;
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("Ignoring msgpack-rpc message: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serve() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        let mut input = Vec::new();
+        for message in [
+            Msgpack::Array(vec![
+                2.into(),
+                "notifications/initialized".into(),
+                Msgpack::Array(vec![]),
+            ]),
+            Msgpack::Array(vec![
+                0.into(),
+                7.into(),
+                "ping".into(),
+                Msgpack::Array(vec![]),
+            ]),
+            Msgpack::Array(vec![
+                0.into(),
+                8.into(),
+                "nope".into(),
+                Msgpack::Array(vec![]),
+            ]),
+        ] {
+            rmpv::encode::write_value(&mut input, &message).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(
+            vault.path(),
+            &AppConfig::default(),
+            input.as_slice(),
+            &mut output,
+        )
+        .unwrap();
+
+        let mut replies = output.as_slice();
+        let ping = rmpv::decode::read_value(&mut replies).unwrap();
+        assert_eq!(
+            ping,
+            Msgpack::Array(vec![1.into(), 7.into(), Msgpack::Nil, Msgpack::Map(vec![])])
+        );
+        let unknown = rmpv::decode::read_value(&mut replies).unwrap();
+        assert_eq!(unknown[2]["code"], Msgpack::from(-32601));
+        assert!(replies.is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+//! Notes as parsed from their text, kept apart from the vault walking and storage in
+//! `data` so the WASM bindings can build it on its own.
+
+use crate::frontmatter;
+use crate::markdown;
+
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use std::{fmt, path::PathBuf, time::SystemTime};
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub github: Option<String>,
+    pub created: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+    pub authors: Option<Vec<String>>,
+    /// Other names the note goes by; a single string or a list
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub aliases: Vec<String>,
+}
+
+fn string_or_list<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    // Numbers and booleans count too, e.g. `aliases: [2024]`; anything else is skipped
+    // rather than losing the rest of the front matter
+    fn scalar(value: Value) -> Option<String> {
+        match value {
+            Value::String(text) => Some(text),
+            Value::Number(number) => Some(number.to_string()),
+            Value::Bool(flag) => Some(flag.to_string()),
+            _ => None,
+        }
+    }
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Sequence(values) => values.into_iter().filter_map(scalar).collect(),
+        value => scalar(value).into_iter().collect(),
+    })
+}
+
+impl fmt::Display for FrontMatter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut output_parts = Vec::new();
+
+        if let Some(title) = &self.title {
+            output_parts.push(format!("Title: {}", title));
+        }
+
+        if let Some(github) = &self.github {
+            output_parts.push(format!("github: {}", github));
+        }
+
+        if let Some(created_dates) = &self.created
+            && !created_dates.is_empty()
+        {
+            output_parts.push(format!("Created: {}", created_dates.join(", ")));
+        }
+
+        if let Some(tags) = &self.tags
+            && !tags.is_empty()
+        {
+            output_parts.push(format!("Tags: {}", tags.join(", ")));
+        }
+
+        if let Some(authors) = &self.authors
+            && !authors.is_empty()
+        {
+            output_parts.push(format!("Authors: {}", authors.join(", ")));
+        }
+        write!(f, "{}", output_parts.join("\n"))
+    }
+}
+
+/// A note read into memory, for commands that query or rewrite note contents
+#[derive(Debug, Clone, Default)]
+pub struct Note {
+    /// Path relative to the vault root
+    pub path: PathBuf,
+    pub content: String,
+    pub front_matter: FrontMatter,
+    /// Every front matter property, parsed once; empty when there is none
+    pub properties: Mapping,
+    /// Front matter and inline tags, without the leading '#'
+    pub tags: Vec<String>,
+    /// Modification time of the file, when read from disk
+    pub modified: Option<SystemTime>,
+}
+
+impl Note {
+    pub fn from_content(path: PathBuf, content: String) -> Note {
+        let properties = frontmatter::parse_mapping(&content)
+            .unwrap_or_else(|e| {
+                log::warn!("Ignoring front matter of '{}': {}", path.display(), e);
+                None
+            })
+            .unwrap_or_default();
+        let front_matter = match properties.is_empty() {
+            true => FrontMatter::default(),
+            false => {
+                serde_yaml::from_value(Value::Mapping(properties.clone())).unwrap_or_else(|e| {
+                    log::warn!("Ignoring front matter of '{}': {}", path.display(), e);
+                    FrontMatter::default()
+                })
+            }
+        };
+
+        let mut tags: Vec<String> = front_matter
+            .tags
+            .iter()
+            .flatten()
+            .map(|tag| tag.trim_start_matches('#').to_string())
+            .collect();
+        for tag in markdown::parse_tags(&content) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        Note {
+            path,
+            content,
+            front_matter,
+            properties,
+            tags,
+            modified: None,
+        }
+    }
+
+    /// Front matter title, falling back to the file stem
+    pub fn title(&self) -> String {
+        self.front_matter.title.clone().unwrap_or_else(|| {
+            self.path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Whether the note carries `tag` or one of its nested tags (`tag/child`)
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.trim_start_matches('#').to_lowercase();
+        self.tags.iter().any(|own| {
+            let own = own.to_lowercase();
+            own == tag || own.starts_with(&format!("{}/", tag))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases_accept_scalars() {
+        let note = Note::from_content(
+            PathBuf::from("Year.md"),
+            String::from("---\ntitle: Year\naliases: [2024, true, Twenty]\n---\n"),
+        );
+        assert_eq!(note.front_matter.aliases, ["2024", "true", "Twenty"]);
+        assert_eq!(note.title(), "Year");
+
+        let note = Note::from_content(
+            PathBuf::from("N.md"),
+            String::from("---\naliases: 7\n---\n"),
+        );
+        assert_eq!(note.front_matter.aliases, ["7"]);
+    }
+
+    #[test]
+    fn test_properties() {
+        let note = Note::from_content(
+            PathBuf::from("Draft.md"),
+            String::from("---\ntitle: Draft\ndraft: true\n---\nBody\n"),
+        );
+        assert_eq!(note.properties.get("draft"), Some(&Value::Bool(true)));
+        assert_eq!(note.title(), "Draft");
+
+        let note = Note::from_content(PathBuf::from("Plain.md"), String::from("Body\n"));
+        assert!(note.properties.is_empty());
+    }
+}
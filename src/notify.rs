@@ -0,0 +1,266 @@
+//! Notifications pushed when something in the vault needs attention: notes matching a
+//! scheduled `notify` job's query, a failed job, a sync conflict copy or a reminder. Each channel
+//! set under `[notify]` (desktop, ntfy, Gotify, webhook) receives every notification.
+
+use crate::config::NotifyConfig;
+use crate::conflicts;
+use crate::util;
+
+use serde::Serialize;
+#[cfg(feature = "web")]
+use std::time::Duration;
+use std::{
+    collections::HashSet,
+    error::Error,
+    path::{Path, PathBuf},
+    thread,
+};
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Notification {
+    /// What fired it: "query", "job-failed", "conflict" or "reminder"
+    pub event: &'static str,
+    pub title: String,
+    pub message: String,
+}
+
+/// Outcome of sending to one channel, named for the log
+type Outcome = (&'static str, Result<(), Box<dyn Error>>);
+
+/// `text` as an RFC 2047 encoded word when it is not plain printable ASCII, since header
+/// values can't carry anything else; ntfy decodes it.
+#[cfg(feature = "web")]
+fn header_text(text: &str) -> String {
+    if text.bytes().all(|b| (b' '..=b'~').contains(&b)) {
+        return text.to_string();
+    }
+    let mut encoded = String::from("=?UTF-8?Q?");
+    for byte in text.bytes() {
+        match byte {
+            b' ' => encoded.push('_'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'!' | b'*' | b'+' | b'-' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("={:02X}", byte)),
+        }
+    }
+    encoded.push_str("?=");
+    encoded
+}
+
+#[cfg(feature = "web")]
+fn post_ntfy(
+    agent: &ureq::Agent,
+    topic: &str,
+    notification: &Notification,
+) -> Result<(), Box<dyn Error>> {
+    agent
+        .post(topic)
+        .set("Title", &header_text(&notification.title))
+        .set("Tags", notification.event)
+        .send_string(&notification.message)?;
+    Ok(())
+}
+
+#[cfg(feature = "web")]
+fn post_gotify(
+    agent: &ureq::Agent,
+    server: &str,
+    token: Option<&str>,
+    notification: &Notification,
+) -> Result<(), Box<dyn Error>> {
+    let token = token.ok_or("Set [notify] gotify_token to the application's token")?;
+    agent
+        .post(&format!("{}/message", server.trim_end_matches('/')))
+        .set("X-Gotify-Key", token)
+        .send_json(serde_json::json!({
+            "title": notification.title,
+            "message": notification.message,
+            "priority": 5,
+        }))?;
+    Ok(())
+}
+
+/// Posts `notification` to the ntfy, Gotify and webhook channels that are set.
+#[cfg(feature = "web")]
+fn post_web(settings: &NotifyConfig, notification: &Notification) -> Vec<Outcome> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build();
+    let mut outcomes: Vec<Outcome> = Vec::new();
+    if let Some(topic) = &settings.ntfy {
+        outcomes.push(("ntfy", post_ntfy(&agent, topic, notification)));
+    }
+    if let Some(server) = &settings.gotify {
+        let token = settings.gotify_token.as_deref();
+        outcomes.push(("Gotify", post_gotify(&agent, server, token, notification)));
+    }
+    if let Some(url) = &settings.webhook {
+        let sent = agent
+            .post(url)
+            .send_json(notification)
+            .map(|_| ())
+            .map_err(Box::from);
+        outcomes.push(("webhook", sent));
+    }
+    outcomes
+}
+
+#[cfg(not(feature = "web"))]
+fn post_web(settings: &NotifyConfig, _notification: &Notification) -> Vec<Outcome> {
+    [
+        ("ntfy", settings.ntfy.is_some()),
+        ("Gotify", settings.gotify.is_some()),
+        ("webhook", settings.webhook.is_some()),
+    ]
+    .into_iter()
+    .filter(|(_, set)| *set)
+    .map(|(channel, _)| (channel, Err("this build lacks the `web` feature".into())))
+    .collect()
+}
+
+#[cfg(feature = "desktop-notify")]
+fn show_desktop(notification: &Notification) -> Result<(), Box<dyn Error>> {
+    notify_rust::Notification::new()
+        .appname("obsidian-rs")
+        .summary(&notification.title)
+        .body(&notification.message)
+        .show()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "desktop-notify"))]
+fn show_desktop(_notification: &Notification) -> Result<(), Box<dyn Error>> {
+    Err("this build lacks the `desktop-notify` feature".into())
+}
+
+/// Sends `notification` to every configured channel, logging the ones that fail.
+pub fn send(settings: &NotifyConfig, notification: &Notification) {
+    log::info!(
+        "Notifying: {}: {}",
+        notification.title,
+        notification.message
+    );
+    let mut outcomes: Vec<Outcome> = Vec::new();
+    if settings.desktop {
+        outcomes.push(("desktop", show_desktop(notification)));
+    }
+    outcomes.extend(post_web(settings, notification));
+    for (channel, outcome) in outcomes {
+        if let Err(e) = outcome {
+            log::error!("Sending a {} notification failed: {}", channel, e);
+        }
+    }
+}
+
+/// Like [`send`], on a background thread so a slow server does not hold up the caller.
+pub fn send_in_background(settings: &NotifyConfig, notification: Notification) {
+    if !settings.is_enabled() {
+        return;
+    }
+    let settings = settings.clone();
+    thread::spawn(move || send(&settings, &notification));
+}
+
+/// The notification about `titles` matching `query`, if any do.
+pub fn query_matches(job: &str, query: &str, titles: &[String]) -> Option<Notification> {
+    const LISTED: usize = 5;
+    if titles.is_empty() {
+        return None;
+    }
+    let mut message = titles[..titles.len().min(LISTED)].join(", ");
+    if titles.len() > LISTED {
+        message.push_str(&format!(" and {} more", titles.len() - LISTED));
+    }
+    Some(Notification {
+        event: "query",
+        title: format!("{}: {} note(s) match {}", job, titles.len(), query),
+        message,
+    })
+}
+
+pub fn job_failed(job: &str, error: &str) -> Notification {
+    Notification {
+        event: "job-failed",
+        title: format!("Job '{}' failed", job),
+        message: error.to_string(),
+    }
+}
+
+/// Conflict copies the watcher has already notified about, so that each is reported once
+/// however many file events it causes.
+#[derive(Debug, Default)]
+pub struct ConflictAlerts {
+    seen: HashSet<PathBuf>,
+}
+
+impl ConflictAlerts {
+    /// The notification about `path` if it is a new conflict copy of a vault note.
+    pub fn check(&mut self, vault_path: &Path, path: &Path) -> Option<Notification> {
+        let rel_path = util::get_relative_path(path, vault_path).ok()?;
+        let (original, source) =
+            conflicts::original_of(&rel_path, |original| vault_path.join(original).exists())?;
+        if !path.exists() {
+            self.seen.remove(&rel_path);
+            return None;
+        }
+        if !self.seen.insert(rel_path.clone()) {
+            return None;
+        }
+        Some(Notification {
+            event: "conflict",
+            title: format!("{} conflict copy of {}", source, original.display()),
+            message: format!(
+                "{} diverged from {}; see `conflicts resolve`",
+                rel_path.display(),
+                original.display()
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_query_matches() {
+        assert_eq!(query_matches("due", "due:today", &[]), None);
+        let titles: Vec<String> = (1..=7).map(|n| format!("Task {}", n)).collect();
+        let notification = query_matches("due", "due:today", &titles).unwrap();
+        assert_eq!(notification.title, "due: 7 note(s) match due:today");
+        assert_eq!(
+            notification.message,
+            "Task 1, Task 2, Task 3, Task 4, Task 5 and 2 more"
+        );
+    }
+
+    #[test]
+    fn test_conflict_alerts_fire_once() {
+        let vault = tempfile::tempdir().unwrap();
+        let root = vault.path();
+        fs::write(root.join("Plan.md"), "a").unwrap();
+        let copy = root.join("Plan.sync-conflict-20240101-120000-ABCDEFG.md");
+        fs::write(&copy, "b").unwrap();
+
+        let mut alerts = ConflictAlerts::default();
+        assert_eq!(alerts.check(root, &root.join("Plan.md")), None);
+        let alert = alerts.check(root, &copy).unwrap();
+        assert_eq!(alert.title, "Syncthing conflict copy of Plan.md");
+        assert_eq!(alerts.check(root, &copy), None);
+
+        // Reported again if it comes back after being resolved
+        fs::remove_file(&copy).unwrap();
+        assert_eq!(alerts.check(root, &copy), None);
+        fs::write(&copy, "c").unwrap();
+        assert!(alerts.check(root, &copy).is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "web")]
+    fn test_header_text() {
+        assert_eq!(header_text("Job 'daily' failed"), "Job 'daily' failed");
+        assert_eq!(header_text("Café = ok?"), "=?UTF-8?Q?Caf=C3=A9_=3D_ok=3F?=");
+    }
+}
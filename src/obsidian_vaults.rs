@@ -0,0 +1,244 @@
+//! The vaults Obsidian itself knows, read from its `obsidian.json`, so `--vault <name>` can
+//! target any of them without a `[workspace] root` in config.toml.
+
+use crate::cli::{OutputFormat, VaultListArgs};
+use crate::util;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::HashMap, error::Error, fs, path::PathBuf};
+
+#[derive(Deserialize)]
+struct ObsidianJson {
+    #[serde(default)]
+    vaults: HashMap<String, VaultEntry>,
+}
+
+#[derive(Deserialize)]
+struct VaultEntry {
+    path: PathBuf,
+    /// Milliseconds since the epoch the vault was last opened
+    #[serde(default)]
+    ts: Option<i64>,
+    #[serde(default)]
+    open: bool,
+}
+
+/// A vault registered with Obsidian
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct KnownVault {
+    /// Obsidian's id for the vault
+    pub id: String,
+    /// Folder name, which Obsidian shows as the vault name
+    pub name: String,
+    pub path: PathBuf,
+    /// Whether the vault is open in Obsidian
+    pub open: bool,
+    /// RFC 3339 time the vault was last opened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_opened: Option<String>,
+}
+
+/// Where Obsidian keeps `obsidian.json`: the native install first, then Flatpak and Snap.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn config_files() -> Vec<PathBuf> {
+    let Some(home) = util::get_home_dir() else {
+        return Vec::new();
+    };
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".config"));
+    vec![
+        config_home.join("obsidian/obsidian.json"),
+        home.join(".var/app/md.obsidian.Obsidian/config/obsidian/obsidian.json"),
+        home.join("snap/obsidian/current/.config/obsidian/obsidian.json"),
+    ]
+}
+
+#[cfg(target_os = "macos")]
+fn config_files() -> Vec<PathBuf> {
+    util::get_home_dir()
+        .map(|home| home.join("Library/Application Support/obsidian/obsidian.json"))
+        .into_iter()
+        .collect()
+}
+
+#[cfg(windows)]
+fn config_files() -> Vec<PathBuf> {
+    std::env::var_os("APPDATA")
+        .map(|app_data| {
+            PathBuf::from(app_data)
+                .join("obsidian")
+                .join("obsidian.json")
+        })
+        .into_iter()
+        .collect()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn config_files() -> Vec<PathBuf> {
+    Vec::new()
+}
+
+/// The vaults listed in the contents of an `obsidian.json`, most recently opened first.
+pub fn parse(content: &str) -> Result<Vec<KnownVault>, Box<dyn Error>> {
+    let parsed: ObsidianJson = serde_json::from_str(content)?;
+    let mut vaults: Vec<(Option<i64>, KnownVault)> = parsed
+        .vaults
+        .into_iter()
+        .map(|(id, entry)| {
+            let vault = KnownVault {
+                id,
+                name: entry
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                last_opened: entry
+                    .ts
+                    .and_then(|ts| Timestamp::from_millisecond(ts).ok())
+                    .map(|ts| ts.to_string()),
+                path: entry.path,
+                open: entry.open,
+            };
+            (entry.ts, vault)
+        })
+        .collect();
+    vaults.sort_by(|(a_ts, a), (b_ts, b)| {
+        Reverse(a_ts)
+            .cmp(&Reverse(b_ts))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(vaults.into_iter().map(|(_, vault)| vault).collect())
+}
+
+/// Every vault in the `obsidian.json` files of this machine.
+pub fn known() -> Result<Vec<KnownVault>, Box<dyn Error>> {
+    read_lists(&config_files())
+}
+
+/// The vaults of the lists among `files` that exist and can be read.
+fn read_lists(files: &[PathBuf]) -> Result<Vec<KnownVault>, Box<dyn Error>> {
+    let mut vaults: Vec<KnownVault> = Vec::new();
+    let mut found = false;
+    let mut failed = Vec::new();
+    for file in files.iter().filter(|file| file.is_file()) {
+        found = true;
+        // One broken list, say of an uninstalled Flatpak, leaves the others usable
+        let listed = fs::read_to_string(file)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|content| parse(&content));
+        match listed {
+            Ok(listed) => {
+                for vault in listed {
+                    if !vaults.iter().any(|known| known.path == vault.path) {
+                        vaults.push(vault);
+                    }
+                }
+            }
+            Err(e) => failed.push(format!("Cannot read '{}': {}", file.display(), e)),
+        }
+    }
+    if vaults.is_empty() && !failed.is_empty() {
+        return Err(failed.join("; ").into());
+    }
+    failed.iter().for_each(|failure| log::warn!("{}", failure));
+    if !found {
+        let looked_in: Vec<String> = files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        return Err(format!(
+            "Obsidian's vault list was not found (looked for {})",
+            looked_in.join(", ")
+        )
+        .into());
+    }
+    Ok(vaults)
+}
+
+/// The known vault with the id or (case-insensitive) folder name `name`.
+pub fn find(name: &str) -> Result<KnownVault, Box<dyn Error>> {
+    let wanted = name.to_lowercase();
+    let mut matches: Vec<KnownVault> = known()?
+        .into_iter()
+        .filter(|vault| vault.id == name || vault.name.to_lowercase() == wanted)
+        .collect();
+    match matches.len() {
+        0 => Err(format!("Obsidian knows no vault named '{}'", name).into()),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            let paths: Vec<String> = matches
+                .iter()
+                .map(|vault| format!("{} ({})", vault.path.display(), vault.id))
+                .collect();
+            Err(format!(
+                "Several vaults are named '{}'; pass one of their ids: {}",
+                name,
+                paths.join(", ")
+            )
+            .into())
+        }
+    }
+}
+
+pub fn run_list(args: &VaultListArgs) -> Result<(), Box<dyn Error>> {
+    let vaults = known()?;
+    match args.format {
+        OutputFormat::Text => {
+            for vault in &vaults {
+                let marker = if vault.open { '*' } else { ' ' };
+                println!("{} {}  {}", marker, vault.name, vault.path.display());
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&vaults)?),
+        OutputFormat::Ndjson => {
+            for vault in &vaults {
+                util::print_ndjson(vault)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vault_list() {
+        let vaults = parse(
+            r#"{
+                "vaults": {
+                    "a1b2": { "path": "/home/me/Work", "ts": 1700000000000 },
+                    "c3d4": { "path": "/home/me/Personal", "ts": 1710000000000, "open": true }
+                },
+                "frame": "hidden"
+            }"#,
+        )
+        .unwrap();
+        let names: Vec<&str> = vaults.iter().map(|vault| vault.name.as_str()).collect();
+        assert_eq!(names, vec!["Personal", "Work"]);
+        assert!(vaults[0].open);
+        assert_eq!(vaults[1].id, "a1b2");
+        assert_eq!(
+            vaults[1].last_opened.as_deref(),
+            Some("2023-11-14T22:13:20Z")
+        );
+        assert!(parse("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_broken_lists_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = dir.path().join("good.json");
+        let broken = dir.path().join("broken.json");
+        fs::write(&good, r#"{"vaults": {"a1": {"path": "/home/me/Work"}}}"#).unwrap();
+        fs::write(&broken, "{ not json").unwrap();
+
+        let vaults = read_lists(&[broken.clone(), good, dir.path().join("missing.json")]).unwrap();
+        assert_eq!(vaults.len(), 1);
+        assert!(read_lists(&[broken]).is_err());
+        assert!(read_lists(&[dir.path().join("missing.json")]).is_err());
+    }
+}
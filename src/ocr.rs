@@ -0,0 +1,318 @@
+//! Text recognised in embedded images (built with the `ocr` feature). Each image is read
+//! once by the configured backend and cached by size and modification time; the text is
+//! then indexed as chunks of the notes that embed the image, under an `![[image]]`
+//! heading, so full-text search finds screenshots. Failures are cached the same way and
+//! retried after a day.
+
+use crate::config::{AppConfig, OcrConfig};
+use crate::data::Note;
+use crate::images;
+use crate::markdown;
+use crate::resolver::Resolver;
+use crate::util;
+
+use sqlite::{Connection, State};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::Path,
+    process::Command,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Seconds before an image the backend failed on is tried again, unless it changes
+const RETRY_AFTER: i64 = 24 * 60 * 60;
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS ocr_text (
+            image TEXT PRIMARY KEY,
+            bytes INTEGER NOT NULL,
+            modified INTEGER NOT NULL,
+            text TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS ocr_failures (
+            image TEXT PRIMARY KEY,
+            bytes INTEGER NOT NULL,
+            modified INTEGER NOT NULL,
+            at INTEGER NOT NULL
+        );",
+    )
+}
+
+/// Runs the configured backend on `file`.
+fn recognise(file: &Path, settings: &OcrConfig) -> Result<String, Box<dyn Error>> {
+    match settings.backend.as_deref() {
+        Some("tesseract") => {
+            let output = Command::new(&settings.tesseract)
+                .arg(file)
+                .arg("stdout")
+                .args(["-l", &settings.languages])
+                .output()?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("tesseract failed: {}", stderr.trim()).into());
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Some("http") => {
+            let url = settings.url.as_deref().ok_or("[ocr] url is not set")?;
+            let response = ureq::post(url)
+                .set("Content-Type", "application/octet-stream")
+                .timeout(Duration::from_secs(60))
+                .send_bytes(&fs::read(file)?)?
+                .into_string()?;
+            // Either plain text or `{"text": "..."}`
+            Ok(match serde_json::from_str::<serde_json::Value>(&response) {
+                Ok(serde_json::Value::Object(object)) => object
+                    .get("text")
+                    .and_then(|text| text.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                _ => response,
+            })
+        }
+        Some(other) => Err(format!("Unknown OCR backend '{}'", other).into()),
+        None => Err("OCR is off; set [ocr] backend".into()),
+    }
+}
+
+/// The text of `image` (vault-relative), recognised again only when the file changed.
+/// A failure is recorded and the image left out, without asking the backend, until the
+/// file changes or [`RETRY_AFTER`] passes.
+fn image_text(
+    connection: &Connection,
+    vault_path: &Path,
+    image: &str,
+    settings: &OcrConfig,
+) -> Result<String, Box<dyn Error>> {
+    let file = vault_path.join(image);
+    let metadata = fs::metadata(&file)?;
+    let bytes = metadata.len() as i64;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+    let mut statement = connection
+        .prepare("SELECT text FROM ocr_text WHERE image = ? AND bytes = ? AND modified = ?")?;
+    statement.bind((1, image))?;
+    statement.bind((2, bytes))?;
+    statement.bind((3, modified))?;
+    if let State::Row = statement.next()? {
+        return Ok(statement.read(0)?);
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let mut statement = connection
+        .prepare("SELECT at FROM ocr_failures WHERE image = ? AND bytes = ? AND modified = ?")?;
+    statement.bind((1, image))?;
+    statement.bind((2, bytes))?;
+    statement.bind((3, modified))?;
+    if let State::Row = statement.next()?
+        && now - statement.read::<i64, _>(0)? < RETRY_AFTER
+    {
+        return Ok(String::new());
+    }
+
+    log::info!("Recognising text in {}", image);
+    let text = match recognise(&file, settings) {
+        Ok(text) => text,
+        Err(e) => {
+            let mut statement = connection.prepare(
+                "INSERT OR REPLACE INTO ocr_failures (image, bytes, modified, at)
+                 VALUES (?, ?, ?, ?)",
+            )?;
+            statement.bind((1, image))?;
+            statement.bind((2, bytes))?;
+            statement.bind((3, modified))?;
+            statement.bind((4, now))?;
+            statement.next()?;
+            return Err(e);
+        }
+    };
+    let text = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO ocr_text (image, bytes, modified, text) VALUES (?, ?, ?, ?)",
+    )?;
+    statement.bind((1, image))?;
+    statement.bind((2, bytes))?;
+    statement.bind((3, modified))?;
+    statement.bind((4, text.as_str()))?;
+    statement.next()?;
+    let mut statement = connection.prepare("DELETE FROM ocr_failures WHERE image = ?")?;
+    statement.bind((1, image))?;
+    statement.next()?;
+    Ok(text)
+}
+
+/// `(heading, line, image)` for each image `note` embeds that resolves to a vault file.
+fn embedded_images(note: &Note, resolver: &Resolver) -> Vec<(String, usize, String)> {
+    markdown::parse_links(&note.content)
+        .into_iter()
+        .filter(|link| link.embed && !link.is_external())
+        .filter_map(|link| {
+            let path = resolver.resolve(&link.target, &note.path)?;
+            images::is_image(path).then(|| {
+                (
+                    format!("![[{}]]", link.target),
+                    link.line,
+                    path.to_string_lossy().replace('\\', "/"),
+                )
+            })
+        })
+        .collect()
+}
+
+/// The OCR chunks indexed for `path`, as `(heading, line, text)`.
+fn indexed_chunks(
+    connection: &Connection,
+    path: &str,
+) -> Result<Vec<(String, usize, String)>, sqlite::Error> {
+    let mut statement = connection.prepare(
+        "SELECT heading, line, text FROM chunks WHERE path = ? AND heading LIKE '![[%' ORDER BY id",
+    )?;
+    statement.bind((1, path))?;
+    let mut chunks = Vec::new();
+    while let State::Row = statement.next()? {
+        chunks.push((
+            statement.read(0)?,
+            statement.read::<i64, _>(1)? as usize,
+            statement.read(2)?,
+        ));
+    }
+    Ok(chunks)
+}
+
+fn replace_chunks(
+    connection: &Connection,
+    note: &Note,
+    path: &str,
+    chunks: &[(String, usize, String)],
+) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT INTO chunks_fts(chunks_fts, rowid, text, heading, title)
+         SELECT 'delete', id, text, heading, title FROM chunks
+         WHERE path = ? AND heading LIKE '![[%'",
+    )?;
+    statement.bind((1, path))?;
+    statement.next()?;
+    let mut statement =
+        connection.prepare("DELETE FROM chunks WHERE path = ? AND heading LIKE '![[%'")?;
+    statement.bind((1, path))?;
+    statement.next()?;
+
+    let title = note.title();
+    for (heading, line, text) in chunks {
+        let mut statement = connection.prepare(
+            "INSERT INTO chunks (path, title, heading, line, text, hash) VALUES (?, ?, ?, ?, ?, ?)",
+        )?;
+        statement.bind((1, path))?;
+        statement.bind((2, title.as_str()))?;
+        statement.bind((3, heading.as_str()))?;
+        statement.bind((4, *line as i64))?;
+        statement.bind((5, text.as_str()))?;
+        statement.bind((6, util::content_hash(text.as_bytes()).as_str()))?;
+        statement.next()?;
+        connection.execute(
+            "INSERT INTO chunks_fts(rowid, text, heading, title)
+             SELECT id, text, heading, title FROM chunks WHERE id = last_insert_rowid()",
+        )?;
+    }
+    Ok(())
+}
+
+/// Indexes the text of the images `notes` embed, returning how many notes changed.
+/// Does nothing unless `[ocr] backend` is set.
+pub fn update(
+    connection: &Connection,
+    vault_path: &Path,
+    notes: &[Note],
+    config: &AppConfig,
+) -> Result<usize, Box<dyn Error>> {
+    let settings = &config.ocr;
+    if settings.backend.is_none() {
+        return Ok(0);
+    }
+    ensure_schema(connection)?;
+    let resolver = Resolver::from_vault(vault_path)?;
+    let mut texts: HashMap<String, String> = HashMap::new();
+    let mut changed = 0;
+    for note in notes {
+        let path = note.path.to_string_lossy().replace('\\', "/");
+        let mut chunks = Vec::new();
+        for (heading, line, image) in embedded_images(note, &resolver) {
+            if !texts.contains_key(&image) {
+                let text =
+                    image_text(connection, vault_path, &image, settings).unwrap_or_else(|e| {
+                        log::warn!("Could not read text in {}: {}", image, e);
+                        String::new()
+                    });
+                texts.insert(image.clone(), text);
+            }
+            let text = &texts[&image];
+            if !text.is_empty() {
+                chunks.push((heading, line, text.clone()));
+            }
+        }
+        if indexed_chunks(connection, &path)? != chunks {
+            replace_chunks(connection, note, &path, &chunks)?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_ocr_chunks_are_searchable() {
+        let note = Note::from_content(
+            PathBuf::from("Screens.md"),
+            String::from("# Shots\n![[error.png]]\n"),
+        );
+        let connection = index::test_connection(std::slice::from_ref(&note));
+        let chunks = vec![(
+            String::from("![[error.png]]"),
+            2,
+            String::from("Segmentation fault (core dumped)"),
+        )];
+        replace_chunks(&connection, &note, "Screens.md", &chunks).unwrap();
+        replace_chunks(&connection, &note, "Screens.md", &chunks).unwrap();
+        assert_eq!(indexed_chunks(&connection, "Screens.md").unwrap(), chunks);
+
+        let mut statement = connection
+            .prepare("SELECT count(*) FROM chunks_fts WHERE chunks_fts MATCH 'segmentation'")
+            .unwrap();
+        statement.next().unwrap();
+        assert_eq!(statement.read::<i64, _>(0).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_failures_are_not_retried_at_once() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("shot.png"), b"not really a png").unwrap();
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let settings = OcrConfig {
+            backend: Some(String::from("tesseract")),
+            tesseract: String::from("/nonexistent/tesseract"),
+            ..OcrConfig::default()
+        };
+        assert!(image_text(&connection, vault.path(), "shot.png", &settings).is_err());
+        assert_eq!(
+            image_text(&connection, vault.path(), "shot.png", &settings).unwrap(),
+            ""
+        );
+
+        // A changed file is tried again
+        fs::write(vault.path().join("shot.png"), b"a longer fake png").unwrap();
+        assert!(image_text(&connection, vault.path(), "shot.png", &settings).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! PDF export. Notes go through `flatten` and the HTML renderer into a standalone page,
+//! which a headless browser (or wkhtmltopdf) prints; the note's front matter becomes the
+//! document's title, author and keywords.
+
+use crate::cli::ExportPdfArgs;
+use crate::config::PublishConfig;
+use crate::data::{self, Note};
+use crate::flatten;
+use crate::query::Query;
+use crate::render;
+use crate::resolver::Resolver;
+use crate::util::{self, xml_escape};
+
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use url::Url;
+
+/// Engines tried, in order, when none is configured
+const ENGINES: &[&str] = &[
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "wkhtmltopdf",
+];
+
+/// A standalone HTML document for `note`, with attachments linked by `file://` URL so the
+/// engine can load them from the vault.
+fn document_html(note: &Note, resolver: &Resolver, vault_path: &Path) -> String {
+    let href = |target: &Path| {
+        if data::is_note(target) {
+            return None;
+        }
+        Url::from_file_path(vault_path.join(target))
+            .ok()
+            .map(String::from)
+    };
+    let body = render::note_html(note, resolver, href);
+
+    let mut meta = String::new();
+    if let Some(authors) = &note.front_matter.authors {
+        meta.push_str(&format!(
+            "<meta name=\"author\" content=\"{}\">\n",
+            xml_escape(&authors.join(", "))
+        ));
+    }
+    if !note.tags.is_empty() {
+        meta.push_str(&format!(
+            "<meta name=\"keywords\" content=\"{}\">\n",
+            xml_escape(&note.tags.join(", "))
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n{}</head>\n<body>\n<article>\n{}</article>\n</body>\n</html>\n",
+        xml_escape(&note.title()),
+        meta,
+        body
+    )
+}
+
+/// The program on the PATH called `name`, or `name` itself when it is a path.
+fn find_program(name: &str) -> Option<PathBuf> {
+    if Path::new(name).components().count() > 1 {
+        return Path::new(name).is_file().then(|| PathBuf::from(name));
+    }
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+fn find_engine(configured: Option<&str>) -> Result<PathBuf, Box<dyn Error>> {
+    match configured {
+        Some(name) => {
+            find_program(name).ok_or_else(|| format!("PDF engine '{}' not found", name).into())
+        }
+        None => ENGINES
+            .iter()
+            .find_map(|name| find_program(name))
+            .ok_or_else(|| {
+                format!(
+                    "No PDF engine found; install one of {} or set [publish] pdf_engine",
+                    ENGINES.join(", ")
+                )
+                .into()
+            }),
+    }
+}
+
+/// Prints the HTML file `html` to `out` with `engine`.
+fn print(engine: &Path, html: &Path, out: &Path, title: &str) -> Result<(), Box<dyn Error>> {
+    let name = engine.file_name().unwrap_or_default().to_string_lossy();
+    let mut command = Command::new(engine);
+    if name.contains("wkhtmltopdf") {
+        command
+            .args(["--quiet", "--enable-local-file-access", "--title", title])
+            .arg(html)
+            .arg(out);
+    } else {
+        command
+            .args(["--headless", "--disable-gpu", "--no-pdf-header-footer"])
+            .arg(format!("--print-to-pdf={}", out.display()))
+            .arg(
+                Url::from_file_path(html)
+                    .map_err(|_| "Unexpected relative path")?
+                    .as_str(),
+            );
+    }
+    let output = command.output()?;
+    if !output.status.success() || !out.exists() {
+        return Err(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+pub fn run_export_pdf(
+    vault_path: &Path,
+    publish: &PublishConfig,
+    args: &ExportPdfArgs,
+) -> Result<(), Box<dyn Error>> {
+    let engine = find_engine(args.engine.as_deref().or(publish.pdf_engine.as_deref()))?;
+    let resolver = Resolver::from_vault(vault_path)?;
+    let notes = data::load_notes(vault_path)?;
+
+    // A target naming a note prints that note, anything else is a query
+    let (selected, batch): (Vec<&Note>, bool) = match resolver.resolve(&args.target, Path::new(""))
+    {
+        Some(path) if data::is_note(path) => (
+            notes.iter().filter(|note| note.path == path).collect(),
+            false,
+        ),
+        _ => (Query::parse(&args.target)?.filter(&notes), true),
+    };
+    if selected.is_empty() {
+        return Err(format!("No notes match '{}'", args.target).into());
+    }
+
+    let out = match &args.out {
+        Some(out) => util::expand_tilde(out)
+            .map(|p| p.into_owned())
+            .ok_or("Failed to expand output path")?,
+        None if batch => return Err("Pass --out with a directory to export several notes".into()),
+        None => {
+            let mut file = PathBuf::from(selected[0].path.file_name().unwrap_or_default());
+            file.set_extension("pdf");
+            file
+        }
+    };
+    let out = env::current_dir()?.join(out);
+    if util::is_within(&out, vault_path) {
+        return Err("PDFs must be written outside the vault".into());
+    }
+
+    let contents: HashMap<PathBuf, String> = notes
+        .iter()
+        .map(|note| (note.path.clone(), note.content.clone()))
+        .collect();
+    // A private directory, so no one else can swap the page for their own
+    let scratch = tempfile::Builder::new().prefix("obsidian-rs-").tempdir()?;
+    let html = scratch.path().join("note.html");
+    let mut exported = 0;
+    for note in &selected {
+        let target = match batch {
+            true => out.join(&note.path).with_extension("pdf"),
+            false => out.clone(),
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let flattened = flatten::flatten_note(&note.path, &resolver, &contents)?;
+        let flat_note = Note::from_content(note.path.clone(), flattened);
+        fs::write(&html, document_html(&flat_note, &resolver, vault_path))?;
+        match print(&engine, &html, &target, &flat_note.title()) {
+            Ok(()) => exported += 1,
+            Err(e) if batch => log::warn!("Skipping {}: {}", note.path.display(), e),
+            Err(e) => return Err(e),
+        }
+    }
+    log::info!("Exported {} PDF(s) to {}", exported, out.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_html() {
+        let resolver = Resolver::new(vec![
+            PathBuf::from("Report.md"),
+            PathBuf::from("img/a b.png"),
+        ]);
+        let note = Note::from_content(
+            PathBuf::from("Report.md"),
+            String::from(
+                "---\ntitle: Q3 <Report>\nauthors: [Ada, Bob]\ntags: [work]\n---\n![[a b.png]]\n",
+            ),
+        );
+        let html = document_html(&note, &resolver, Path::new("/vault"));
+        assert!(html.contains("<title>Q3 &lt;Report&gt;</title>\n"));
+        assert!(html.contains("<meta name=\"author\" content=\"Ada, Bob\">\n"));
+        assert!(html.contains("<meta name=\"keywords\" content=\"work\">\n"));
+        assert!(html.contains("<img src=\"file:///vault/img/a%20b.png\""));
+    }
+}
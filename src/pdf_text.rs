@@ -0,0 +1,164 @@
+//! Text of PDF attachments (built with the `pdf-text` feature), indexed page by page under
+//! the PDF's own path. Search credits hits in a PDF to a note that links to or embeds it,
+//! so results lead back into the vault. The daemon and the `reindex` job read new PDFs;
+//! searches do not.
+
+use crate::data;
+use crate::index::{self, Chunk, MAX_CHUNK_BYTES};
+
+use sqlite::{Connection, State};
+use std::{collections::HashMap, error::Error, fs, panic, path::Path, time::UNIX_EPOCH};
+
+fn is_pdf(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+/// The text of each page of `file`.
+fn extract(file: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    // pdf-extract panics on some malformed files
+    match panic::catch_unwind(|| pdf_extract::extract_text_by_pages(file)) {
+        Ok(pages) => Ok(pages?),
+        Err(_) => Err("the PDF could not be parsed".into()),
+    }
+}
+
+/// Chunks of at most `max_bytes` (or one line) per page, headed `Page N`.
+fn page_chunks(path: &str, pages: &[String], max_bytes: usize) -> Vec<Chunk> {
+    pages
+        .iter()
+        .enumerate()
+        .flat_map(|(index, page)| {
+            index::split_lines(page, max_bytes)
+                .into_iter()
+                .map(move |text| Chunk {
+                    path: path.to_string(),
+                    heading: format!("Page {}", index + 1),
+                    line: index + 1,
+                    text,
+                })
+        })
+        .collect()
+}
+
+/// Size and modification time, which is cheaper to compare than the content.
+fn stamp(file: &Path) -> Result<String, Box<dyn Error>> {
+    let metadata = fs::metadata(file)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+    Ok(format!("pdf:{}:{}", metadata.len(), modified))
+}
+
+/// Re-indexes the PDFs that changed, returning how many were read.
+pub fn update(connection: &Connection, vault_path: &Path) -> Result<usize, Box<dyn Error>> {
+    let mut known = HashMap::new();
+    let mut statement = connection.prepare("SELECT path, hash FROM indexed_files")?;
+    while let State::Row = statement.next()? {
+        let path = statement.read::<String, _>(0)?;
+        if is_pdf(Path::new(&path)) {
+            known.insert(path, statement.read::<String, _>(1)?);
+        }
+    }
+
+    let mut read = 0;
+    connection.execute("BEGIN")?;
+    for file in data::traverse_vault(vault_path)?
+        .into_iter()
+        .filter(|file| is_pdf(file))
+    {
+        let Ok(rel_path) = file.strip_prefix(vault_path) else {
+            continue;
+        };
+        let path = rel_path.to_string_lossy().replace('\\', "/");
+        let known_stamp = known.remove(&path);
+        // Left as last indexed, so one unreadable file does not stop the rest
+        let stamp = match stamp(&file) {
+            Ok(stamp) => stamp,
+            Err(e) => {
+                log::warn!("Skipping {}: {}", path, e);
+                continue;
+            }
+        };
+        if known_stamp.as_ref() == Some(&stamp) {
+            continue;
+        }
+        log::info!("Extracting text from {}", path);
+        index::remove_file(connection, &path)?;
+        // A PDF that cannot be read is still marked, so it is not retried until it changes
+        let pages = extract(&file).unwrap_or_else(|e| {
+            log::warn!("Could not extract text from {}: {}", path, e);
+            Vec::new()
+        });
+        let title = rel_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for chunk in page_chunks(&path, &pages, MAX_CHUNK_BYTES) {
+            index::insert_chunk(connection, &chunk, &title)?;
+        }
+        index::mark_indexed(connection, &path, &stamp)?;
+        read += 1;
+    }
+    for gone in known.keys() {
+        index::remove_file(connection, gone)?;
+    }
+
+    connection.execute("COMMIT")?;
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Note;
+    use crate::resolver::Resolver;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_pages_are_credited_to_linking_notes() {
+        let pages = vec![
+            String::from("Attention is all\n\nyou need\n"),
+            String::from("Transformers\n"),
+        ];
+        let chunks = page_chunks("Papers/attention.pdf", &pages, 20);
+        let texts: Vec<(&str, &str)> = chunks
+            .iter()
+            .map(|chunk| (chunk.heading.as_str(), chunk.text.as_str()))
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                ("Page 1", "Attention is all"),
+                ("Page 1", "you need"),
+                ("Page 2", "Transformers")
+            ]
+        );
+
+        let note = Note::from_content(
+            PathBuf::from("Reading.md"),
+            String::from("# Reading\n\n![[attention.pdf]]\n"),
+        );
+        let notes = [note];
+        let connection = index::test_connection(&notes);
+        for chunk in &chunks {
+            index::insert_chunk(&connection, chunk, "attention").unwrap();
+        }
+        let resolver = Resolver::new(vec![
+            PathBuf::from("Reading.md"),
+            PathBuf::from("Papers/attention.pdf"),
+        ]);
+        index::update_attachment_links(&connection, &resolver, &notes).unwrap();
+
+        let hits = index::keyword_search(
+            &connection,
+            "transformers",
+            10,
+            index::SnippetOptions::default(),
+        )
+        .unwrap();
+        let hits = index::credit_attachment_hits(&connection, hits).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, "Reading.md");
+        assert_eq!(hits[0].line, 3);
+        assert_eq!(hits[0].heading, "Papers/attention.pdf › Page 2");
+    }
+}
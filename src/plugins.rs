@@ -0,0 +1,354 @@
+//! WebAssembly plugins loaded from the vault's plugin folder.
+//!
+//! A plugin is a `.wasm` module exporting `memory` and `alloc(len: i32) -> i32`, plus any
+//! of these hooks, each `(ptr: i32, len: i32) -> i64`:
+//!
+//! - `metadata`: gets the note as JSON, returns an object of extra metadata fields
+//! - `lint`: gets the note, returns an array of `{"line", "message"}` issues
+//! - `query`: gets `{"note", "arg"}` for a `plugin:<name>:<arg>` query term, returns a bool
+//!
+//! Input is written to memory obtained from `alloc`. The result is JSON in the plugin's
+//! memory, returned as `(ptr << 32) | len`; a zero length means no result.
+//!
+//! Plugins only load in builds with the `wasm-plugins` feature.
+
+use crate::cli::PluginsArgs;
+use crate::config::AppConfig;
+use crate::data::Note;
+use crate::resolver::Resolver;
+use crate::scripts;
+use crate::util;
+
+use serde_json::{Map, Value, json};
+use std::{
+    error::Error,
+    fs,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+#[cfg(feature = "wasm-plugins")]
+use wasmi::{Config, Engine, Instance, Linker, Module, Store};
+
+/// Instructions a single hook call may execute before it is aborted
+#[cfg(feature = "wasm-plugins")]
+static FUEL_PER_CALL: u64 = 50_000_000;
+
+static REGISTRY: OnceLock<Mutex<Vec<Plugin>>> = OnceLock::new();
+
+pub struct Plugin {
+    pub name: String,
+    /// Hash of the module, to notice when it changes
+    pub hash: String,
+    #[cfg(feature = "wasm-plugins")]
+    store: Store<()>,
+    #[cfg(feature = "wasm-plugins")]
+    instance: Instance,
+}
+
+#[cfg(feature = "wasm-plugins")]
+impl Plugin {
+    /// Instantiates a module given as WebAssembly binary (or text, for tests).
+    pub fn from_bytes(name: &str, bytes: &[u8]) -> Result<Plugin, Box<dyn Error>> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, bytes)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::<()>::new(&engine).instantiate_and_start(&mut store, &module)?;
+        Ok(Plugin {
+            name: name.to_string(),
+            hash: util::content_hash(bytes),
+            store,
+            instance,
+        })
+    }
+
+    pub fn has_hook(&self, hook: &str) -> bool {
+        self.instance.get_func(&self.store, hook).is_some()
+    }
+
+    /// Calls `hook` with `input`, returning its JSON result. Plugins without the hook
+    /// return `None`.
+    pub fn call(&mut self, hook: &str, input: &Value) -> Result<Option<Value>, Box<dyn Error>> {
+        if !self.has_hook(hook) {
+            return Ok(None);
+        }
+        self.store.set_fuel(FUEL_PER_CALL)?;
+        let memory = self
+            .instance
+            .get_memory(&self.store, "memory")
+            .ok_or("Plugin exports no memory")?;
+        let alloc = self
+            .instance
+            .get_typed_func::<i32, i32>(&self.store, "alloc")?;
+        let function = self
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&self.store, hook)?;
+
+        let input = serde_json::to_vec(input)?;
+        let ptr = alloc.call(&mut self.store, input.len() as i32)?;
+        memory.write(&mut self.store, ptr as u32 as usize, &input)?;
+        let packed = function.call(&mut self.store, (ptr, input.len() as i32))? as u64;
+
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        if out_len == 0 {
+            return Ok(None);
+        }
+        let output = memory
+            .data(&self.store)
+            .get(out_ptr..out_ptr + out_len)
+            .ok_or("Plugin returned a result outside its memory")?;
+        Ok(Some(serde_json::from_slice(output)?))
+    }
+}
+
+/// Without the `wasm-plugins` feature no plugin is ever loaded, so none has hooks.
+#[cfg(not(feature = "wasm-plugins"))]
+impl Plugin {
+    pub fn has_hook(&self, _hook: &str) -> bool {
+        false
+    }
+
+    pub fn call(&mut self, _hook: &str, _input: &Value) -> Result<Option<Value>, Box<dyn Error>> {
+        Ok(None)
+    }
+}
+
+/// Loads every `.wasm` module in `dir`; broken plugins are logged and skipped.
+#[cfg(feature = "wasm-plugins")]
+pub fn load_dir(dir: &Path) -> Vec<Plugin> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        log::warn!("Plugin folder '{}' cannot be read", dir.display());
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+        .collect();
+    paths.sort();
+
+    let mut plugins = Vec::new();
+    for path in paths {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match fs::read(&path)
+            .map_err(Box::from)
+            .and_then(|bytes| Plugin::from_bytes(&name, &bytes))
+        {
+            Ok(plugin) => {
+                log::info!("Loaded plugin '{}'", name);
+                plugins.push(plugin);
+            }
+            Err(e) => log::error!("Cannot load plugin '{}': {}", path.display(), e),
+        }
+    }
+    plugins
+}
+
+#[cfg(not(feature = "wasm-plugins"))]
+pub fn load_dir(dir: &Path) -> Vec<Plugin> {
+    log::warn!(
+        "Not loading the plugins in '{}': obsidian-rs was built without the `wasm-plugins` feature",
+        dir.display()
+    );
+    Vec::new()
+}
+
+/// Loads the plugins of `[plugins] dir` for the rest of the process.
+pub fn install(vault_path: &Path, config: &AppConfig) {
+    let Some(dir) = &config.plugins.dir else {
+        return;
+    };
+    let dir = vault_path.join(util::expand_tilde(Path::new(dir)).unwrap_or_default());
+    let _ = REGISTRY.set(Mutex::new(load_dir(&dir)));
+}
+
+/// Whether any plugin was installed.
+pub fn active() -> bool {
+    REGISTRY
+        .get()
+        .is_some_and(|registry| registry.lock().is_ok_and(|plugins| !plugins.is_empty()))
+}
+
+/// Runs `f` on the installed plugins, if any.
+fn with_plugins<R>(default: R, f: impl FnOnce(&mut Vec<Plugin>) -> R) -> R {
+    match REGISTRY.get() {
+        Some(registry) => match registry.lock() {
+            Ok(mut plugins) => f(&mut plugins),
+            Err(_) => default,
+        },
+        None => default,
+    }
+}
+
+pub fn note_json(note: &Note) -> Value {
+    json!({
+        "path": note.path.to_string_lossy().replace('\\', "/"),
+        "title": note.title(),
+        "tags": note.tags,
+        "content": note.content,
+    })
+}
+
+/// Identifies the installed plugins and scripts; changes whenever one is added, removed
+/// or edited, so that notes get their metadata extracted again.
+pub fn fingerprint() -> String {
+    let mut hashes = with_plugins(Vec::new(), |plugins| {
+        plugins.iter().map(|plugin| plugin.hash.clone()).collect()
+    });
+    hashes.extend(scripts::hashes());
+    hashes.join(",")
+}
+
+/// Names of the installed plugins with the hooks each provides.
+pub fn installed() -> Vec<(String, Vec<&'static str>)> {
+    with_plugins(Vec::new(), |plugins| {
+        plugins
+            .iter()
+            .map(|plugin| {
+                let hooks = ["metadata", "lint", "query"]
+                    .into_iter()
+                    .filter(|hook| plugin.has_hook(hook))
+                    .collect();
+                (plugin.name.clone(), hooks)
+            })
+            .collect()
+    })
+}
+
+/// Extra metadata fields contributed by the plugins and then the scripts, later ones
+/// winning on conflicts.
+pub fn metadata(note: &Note) -> Map<String, Value> {
+    let input = note_json(note);
+    let mut fields = with_plugins(Map::new(), |plugins| {
+        let mut fields = Map::new();
+        for plugin in plugins.iter_mut() {
+            match plugin.call("metadata", &input) {
+                Ok(Some(Value::Object(extra))) => fields.extend(extra),
+                Ok(_) => {}
+                Err(e) => log::warn!("Plugin '{}' metadata failed: {}", plugin.name, e),
+            }
+        }
+        fields
+    });
+    fields.extend(scripts::metadata(note));
+    fields
+}
+
+/// Lint issues reported by the plugins, as `(plugin, line, message)`.
+pub fn lint(note: &Note) -> Vec<(String, usize, String)> {
+    let input = note_json(note);
+    with_plugins(Vec::new(), |plugins| {
+        let mut issues = Vec::new();
+        for plugin in plugins.iter_mut() {
+            match plugin.call("lint", &input) {
+                Ok(Some(Value::Array(found))) => {
+                    for issue in found {
+                        issues.push((
+                            plugin.name.clone(),
+                            issue.get("line").and_then(Value::as_u64).unwrap_or(1) as usize,
+                            issue
+                                .get("message")
+                                .and_then(Value::as_str)
+                                .unwrap_or_default()
+                                .to_string(),
+                        ));
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Plugin '{}' lint failed: {}", plugin.name, e),
+            }
+        }
+        issues
+    })
+}
+
+/// Evaluates a `plugin:<name>:<arg>` query term; unknown plugins match nothing.
+pub fn query(name: &str, arg: &str, note: &Note) -> bool {
+    let input = json!({ "note": note_json(note), "arg": arg });
+    with_plugins(false, |plugins| {
+        let Some(plugin) = plugins.iter_mut().find(|plugin| plugin.name == name) else {
+            log::warn!("No plugin named '{}' for query", name);
+            return false;
+        };
+        match plugin.call("query", &input) {
+            Ok(Some(Value::Bool(matched))) => matched,
+            Ok(_) => false,
+            Err(e) => {
+                log::warn!("Plugin '{}' query failed: {}", plugin.name, e);
+                false
+            }
+        }
+    })
+}
+
+pub fn run_plugins(vault_path: &Path, args: &PluginsArgs) -> Result<(), Box<dyn Error>> {
+    let Some(note) = &args.note else {
+        for (name, hooks) in installed() {
+            println!("{}: {}", name, hooks.join(", "));
+        }
+        for (name, hooks) in scripts::installed() {
+            println!("{}.lua: {}", name, hooks.join(", "));
+        }
+        return Ok(());
+    };
+    let rel_path = Resolver::from_vault(vault_path)?
+        .resolve(note, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", note))?
+        .to_path_buf();
+    let content = fs::read_to_string(vault_path.join(&rel_path))?;
+    let fields = metadata(&Note::from_content(rel_path, content));
+    println!("{}", serde_json::to_string_pretty(&fields)?);
+    Ok(())
+}
+
+#[cfg(all(test, feature = "wasm-plugins"))]
+mod tests {
+    use super::*;
+
+    /// Returns a fixed JSON result from every hook, after a bump allocator.
+    fn echo_plugin(result: &str) -> Plugin {
+        let wat = format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 1024))
+                (data (i32.const 16) "{}")
+                (func (export "alloc") (param $len i32) (result i32)
+                    (local $ptr i32)
+                    (local.set $ptr (global.get $next))
+                    (global.set $next (i32.add (global.get $next) (local.get $len)))
+                    (local.get $ptr))
+                (func (export "metadata") (param i32 i32) (result i64)
+                    (i64.or (i64.shl (i64.const 16) (i64.const 32)) (i64.const {})))
+                (func (export "spin") (param i32 i32) (result i64)
+                    (loop $forever (br $forever))
+                    (i64.const 0)))"#,
+            result.replace('"', "\\\""),
+            result.len()
+        );
+        Plugin::from_bytes("echo", wat.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_call_hook() {
+        let mut plugin = echo_plugin(r#"{"project":"alpha"}"#);
+        assert!(plugin.has_hook("metadata"));
+        assert!(!plugin.has_hook("lint"));
+
+        let note = Note::from_content("A.md".into(), String::from("body"));
+        let result = plugin.call("metadata", &note_json(&note)).unwrap();
+        assert_eq!(result, Some(json!({ "project": "alpha" })));
+        assert_eq!(plugin.call("lint", &json!({})).unwrap(), None);
+    }
+
+    #[test]
+    fn test_runaway_plugin_is_stopped() {
+        let mut plugin = echo_plugin("{}");
+        assert!(plugin.call("spin", &json!({})).is_err());
+        // The plugin stays usable afterwards
+        assert!(plugin.call("metadata", &json!({})).unwrap().is_some());
+    }
+}
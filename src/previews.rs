@@ -0,0 +1,377 @@
+//! Link previews: the title, description and favicon of the external pages notes link to,
+//! fetched politely (one request per host at a time, spaced out) and kept in the cache
+//! database until they are older than `[previews] ttl_days`.
+
+use crate::cli::{OutputFormat, PreviewsCommand, PreviewsFetchArgs};
+use crate::config::{AppConfig, PreviewsConfig};
+use crate::data;
+use crate::index;
+use crate::markdown::{self, LinkStyle};
+#[cfg(feature = "web")]
+use crate::net;
+use crate::query::Query;
+use crate::util;
+
+#[cfg(feature = "web")]
+use jiff::Timestamp;
+#[cfg(feature = "web")]
+use scraper::{Html, Selector};
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    path::Path,
+};
+#[cfg(feature = "web")]
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "web")]
+use url::Url;
+
+#[cfg(feature = "web")]
+const MS_PER_DAY: i64 = 86_400_000;
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct Preview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub favicon: Option<String>,
+    /// Why the page could not be fetched, if it could not
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the page was fetched, in milliseconds since the epoch
+    #[serde(skip)]
+    pub fetched_ms: i64,
+}
+
+impl Preview {
+    /// Text for a tooltip: the title, and the description when there is one.
+    pub fn summary(&self) -> Option<String> {
+        let title = self.title.as_deref()?;
+        Some(match &self.description {
+            Some(description) => format!("{} — {}", title, description),
+            None => title.to_string(),
+        })
+    }
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS link_previews (
+            url TEXT PRIMARY KEY,
+            title TEXT,
+            description TEXT,
+            favicon TEXT,
+            error TEXT,
+            fetched INTEGER NOT NULL
+        );",
+    )
+}
+
+/// Opens the cache database with the previews table in place.
+pub fn open(config: &AppConfig) -> Result<Connection, Box<dyn Error>> {
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    Ok(connection)
+}
+
+/// The preview `html`, served for `url`, describes.
+#[cfg(feature = "web")]
+fn extract(html: &str, url: &Url) -> Preview {
+    let document = Html::parse_document(html);
+    let first = |selector: &str, attribute: &str| {
+        let selector = Selector::parse(selector).ok()?;
+        let element = document.select(&selector).next()?;
+        let text = match attribute {
+            "" => element.text().collect::<String>(),
+            attribute => element.value().attr(attribute)?.to_string(),
+        };
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        (!text.is_empty()).then_some(text)
+    };
+    let title = first(r#"meta[property="og:title"]"#, "content").or_else(|| first("title", ""));
+    let description = first(r#"meta[property="og:description"]"#, "content")
+        .or_else(|| first(r#"meta[name="description"]"#, "content"));
+    let favicon = first(r#"link[rel~="icon"]"#, "href")
+        .and_then(|href| url.join(&href).ok())
+        .or_else(|| url.join("/favicon.ico").ok())
+        .map(String::from);
+    Preview {
+        url: url.to_string(),
+        title,
+        description,
+        favicon,
+        ..Preview::default()
+    }
+}
+
+#[cfg(feature = "web")]
+fn fetch(url: &str) -> Preview {
+    let agent = net::public_agent().timeout(Duration::from_secs(15)).build();
+    let fetched = Url::parse(url)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|parsed| {
+            let response = agent
+                .get(parsed.as_str())
+                .set(
+                    "User-Agent",
+                    concat!("obsidian-rs/", env!("CARGO_PKG_VERSION")),
+                )
+                .call()?;
+            Ok((parsed, response.into_string()?))
+        });
+    let mut preview = match fetched {
+        Ok((parsed, html)) => extract(&html, &parsed),
+        Err(e) => Preview {
+            error: Some(e.to_string()),
+            ..Preview::default()
+        },
+    };
+    preview.url = url.to_string();
+    preview.fetched_ms = Timestamp::now().as_millisecond();
+    preview
+}
+
+#[cfg(feature = "web")]
+fn save(connection: &Connection, preview: &Preview) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO link_previews (url, title, description, favicon, error, fetched)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )?;
+    statement.bind((1, preview.url.as_str()))?;
+    statement.bind((2, preview.title.as_deref()))?;
+    statement.bind((3, preview.description.as_deref()))?;
+    statement.bind((4, preview.favicon.as_deref()))?;
+    statement.bind((5, preview.error.as_deref()))?;
+    statement.bind((6, preview.fetched_ms))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Every stored preview by URL, stale ones included.
+pub fn cached(connection: &Connection) -> Result<HashMap<String, Preview>, Box<dyn Error>> {
+    let mut statement = connection
+        .prepare("SELECT url, title, description, favicon, error, fetched FROM link_previews")?;
+    let mut previews = HashMap::new();
+    while let State::Row = statement.next()? {
+        let preview = Preview {
+            url: statement.read(0)?,
+            title: statement.read(1)?,
+            description: statement.read(2)?,
+            favicon: statement.read(3)?,
+            error: statement.read(4)?,
+            fetched_ms: statement.read(5)?,
+        };
+        previews.insert(preview.url.clone(), preview);
+    }
+    Ok(previews)
+}
+
+/// Fetches the previews of `urls` missing from `known` or older than the TTL, at most
+/// `settings.max_fetches` of them, waiting between requests to the same host.
+#[cfg(feature = "web")]
+fn refresh(
+    connection: &Connection,
+    urls: &BTreeSet<String>,
+    known: &HashMap<String, Preview>,
+    settings: &PreviewsConfig,
+) -> Result<usize, Box<dyn Error>> {
+    let now = Timestamp::now().as_millisecond();
+    let ttl = settings.ttl_days as i64 * MS_PER_DAY;
+    let interval = Duration::from_millis(settings.host_interval_ms);
+    let mut last_request: HashMap<String, Instant> = HashMap::new();
+    let mut fetched = 0;
+    for url in urls {
+        if fetched >= settings.max_fetches {
+            log::info!(
+                "Fetched {} previews; the rest wait for the next run",
+                fetched
+            );
+            break;
+        }
+        if known
+            .get(url)
+            .is_some_and(|preview| now - preview.fetched_ms < ttl)
+        {
+            continue;
+        }
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        if let Some(last) = last_request.get(&host) {
+            thread::sleep(interval.saturating_sub(last.elapsed()));
+        }
+        last_request.insert(host, Instant::now());
+        let preview = fetch(url);
+        if let Some(error) = &preview.error {
+            log::warn!("Could not fetch {}: {}", url, error);
+        }
+        save(connection, &preview)?;
+        fetched += 1;
+    }
+    Ok(fetched)
+}
+
+#[cfg(not(feature = "web"))]
+fn refresh(
+    _connection: &Connection,
+    _urls: &BTreeSet<String>,
+    _known: &HashMap<String, Preview>,
+    _settings: &PreviewsConfig,
+) -> Result<usize, Box<dyn Error>> {
+    Err("Previews cannot be fetched: obsidian-rs was built without the `web` feature".into())
+}
+
+/// The preview of `url`, fetched first when previews are enabled and it is not cached.
+/// Only URLs a note links to are fetched; others have no preview.
+pub fn preview(
+    vault_path: &Path,
+    config: &AppConfig,
+    url: &str,
+) -> Result<Option<Preview>, Box<dyn Error>> {
+    let connection = open(config)?;
+    let mut known = cached(&connection)?;
+    if config.previews.enabled && !known.contains_key(url) {
+        let linked = data::load_notes(vault_path)?.iter().any(|note| {
+            markdown::find_web_urls(&note.content)
+                .iter()
+                .any(|linked| linked.url == url)
+        });
+        if !linked {
+            return Ok(None);
+        }
+        let urls = BTreeSet::from([url.to_string()]);
+        refresh(&connection, &urls, &known, &config.previews)?;
+        known = cached(&connection)?;
+    }
+    Ok(known.remove(url))
+}
+
+/// Cached previews for HTML export, or none when previews are disabled.
+pub fn for_export(config: &AppConfig) -> HashMap<String, Preview> {
+    if !config.previews.enabled {
+        return HashMap::new();
+    }
+    open(config)
+        .and_then(|connection| cached(&connection))
+        .unwrap_or_else(|e| {
+            log::warn!("Could not read link previews: {}", e);
+            HashMap::new()
+        })
+}
+
+/// `content` with the page title (and description) of each previewed Markdown link
+/// added as the link's title, which browsers show on hover.
+pub fn annotate(content: &str, previews: &HashMap<String, Preview>) -> String {
+    if previews.is_empty() {
+        return content.to_string();
+    }
+    let edits = markdown::parse_links(content)
+        .into_iter()
+        .filter(|link| link.style == LinkStyle::Markdown && link.is_external() && !link.embed)
+        .filter_map(|link| {
+            let summary = previews.get(&link.target)?.summary()?;
+            let text = link.text.as_deref().unwrap_or_default();
+            let title = summary.replace('\\', "\\\\").replace('"', "\\\"");
+            Some((
+                link.span,
+                format!("[{}](<{}> \"{}\")", text, link.target, title),
+            ))
+        })
+        .collect();
+    markdown::replace_spans(content, edits)
+}
+
+fn fetch_vault(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &PreviewsFetchArgs,
+) -> Result<(), Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    let notes = Query::parse(args.query.as_deref().unwrap_or(""))?.filter(&notes);
+    let urls: BTreeSet<String> = notes
+        .iter()
+        .flat_map(|note| markdown::find_web_urls(&note.content))
+        .map(|url| url.url)
+        .collect();
+    let connection = open(config)?;
+    let known = match args.refresh {
+        true => HashMap::new(),
+        false => cached(&connection)?,
+    };
+    let fetched = refresh(&connection, &urls, &known, &config.previews)?;
+    log::info!(
+        "Fetched {} preview(s) for {} linked URL(s)",
+        fetched,
+        urls.len()
+    );
+    Ok(())
+}
+
+fn show(
+    vault_path: &Path,
+    config: &AppConfig,
+    url: &str,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let preview = preview(vault_path, config, url)?
+        .ok_or_else(|| format!("No preview of {}; run `previews fetch` first", url))?;
+    match format {
+        OutputFormat::Text => {
+            for (label, value) in [
+                ("Title", &preview.title),
+                ("Description", &preview.description),
+                ("Favicon", &preview.favicon),
+                ("Error", &preview.error),
+            ] {
+                if let Some(value) = value {
+                    println!("{}: {}", label, value);
+                }
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&preview)?),
+        OutputFormat::Ndjson => util::print_ndjson(&preview)?,
+    }
+    Ok(())
+}
+
+pub fn run_previews(
+    vault_path: &Path,
+    config: &AppConfig,
+    command: &PreviewsCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        PreviewsCommand::Fetch(args) => fetch_vault(vault_path, config, args),
+        PreviewsCommand::Show(args) => show(vault_path, config, &args.url, args.format),
+    }
+}
+
+#[cfg(all(test, feature = "web"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_and_annotate() {
+        let url = Url::parse("https://example.com/post").unwrap();
+        let html = r#"<html><head><title> A  Post </title>
+            <meta name="description" content="All about &quot;things&quot;">
+            <link rel="shortcut icon" href="/static/icon.png"></head></html>"#;
+        let preview = extract(html, &url);
+        assert_eq!(preview.title.as_deref(), Some("A Post"));
+        assert_eq!(
+            preview.favicon.as_deref(),
+            Some("https://example.com/static/icon.png")
+        );
+
+        let previews = HashMap::from([(url.to_string(), preview)]);
+        assert_eq!(
+            annotate("See [post](https://example.com/post).\n", &previews),
+            "See [post](<https://example.com/post> \"A Post — All about \\\"things\\\"\").\n"
+        );
+    }
+}
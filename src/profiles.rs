@@ -0,0 +1,92 @@
+//! Named configuration profiles: `[profiles.<name>]` tables in config.toml laid over the
+//! rest of it, e.g. a work vault with its own ignore rules and server settings next to a
+//! personal one. `--profile` picks one for a single command, `$OBSIDIAN_RS_PROFILE` for a
+//! shell, and `profile switch` saves the one used otherwise.
+
+use crate::cli::ProfileCommand;
+use crate::config;
+
+use std::{env, error::Error, fs, io, path::PathBuf};
+
+static SAVED_PROFILE_PATH: &str = ".config/obsidian-rs/profile";
+
+fn saved_path() -> Result<PathBuf, Box<dyn Error>> {
+    let home_dir = home::home_dir().ok_or("Cannot find the home directory")?;
+    Ok(home_dir.join(SAVED_PROFILE_PATH))
+}
+
+/// The profile saved by `profile switch`, if any.
+pub fn saved() -> Option<String> {
+    let name = fs::read_to_string(saved_path().ok()?).ok()?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// The profile to load: `explicit` (from `--profile`), then `$OBSIDIAN_RS_PROFILE`, then
+/// the saved one.
+pub fn active(explicit: Option<&str>) -> Option<String> {
+    if let Some(name) = explicit {
+        return Some(name.to_string());
+    }
+    if let Ok(name) = env::var("OBSIDIAN_RS_PROFILE")
+        && !name.is_empty()
+    {
+        return Some(name);
+    }
+    saved()
+}
+
+/// Names of the profiles defined in config.toml.
+fn names() -> Result<Vec<String>, Box<dyn Error>> {
+    let config_path = config::get_config_path().ok_or("Failed to expand config path!")?;
+    let config: toml::Table = toml::from_str(&fs::read_to_string(&config_path)?)?;
+    Ok(config
+        .get("profiles")
+        .and_then(toml::Value::as_table)
+        .map(|profiles| profiles.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+pub fn run_profile(command: &ProfileCommand, explicit: Option<&str>) -> Result<(), Box<dyn Error>> {
+    match command {
+        ProfileCommand::List => {
+            let active = active(explicit);
+            for name in names()? {
+                let marker = if active.as_deref() == Some(name.as_str()) {
+                    '*'
+                } else {
+                    ' '
+                };
+                println!("{} {}", marker, name);
+            }
+        }
+        ProfileCommand::Switch(args) => {
+            if !names()?.contains(&args.name) {
+                return Err(format!("No profile '{}' in the configuration", args.name).into());
+            }
+            let path = saved_path()?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, format!("{}\n", args.name))?;
+            log::info!("Using profile '{}' from now on", args.name);
+            if let Ok(name) = env::var("OBSIDIAN_RS_PROFILE")
+                && !name.is_empty()
+            {
+                log::warn!(
+                    "$OBSIDIAN_RS_PROFILE still selects '{}' in this shell",
+                    name
+                );
+            }
+        }
+        ProfileCommand::Clear => {
+            if let Err(e) = fs::remove_file(saved_path()?)
+                && e.kind() != io::ErrorKind::NotFound
+            {
+                return Err(e.into());
+            }
+            log::info!("Using the configuration without a profile");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,531 @@
+use crate::aggregate::{self, GroupKey};
+use crate::cli::{OutputFormat, QueryArgs};
+use crate::collation;
+use crate::config::{AppConfig, NoteStatus};
+use crate::data::{self, Note};
+use crate::date_query::{DateCondition, DateField};
+use crate::entities;
+use crate::frontmatter;
+use crate::index::{self, SnippetOptions};
+use crate::listing::NoteEntry;
+use crate::markdown;
+use crate::plugins;
+use crate::query_cache;
+use crate::query_table;
+use crate::search;
+use crate::search_query::PROPERTY_PREFIX;
+use crate::status;
+use crate::util;
+
+use jiff::Zoned;
+use serde::Serialize;
+use std::{collections::HashMap, error::Error, fmt, path::Path};
+
+/// A single search condition; all predicates of a query must match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    /// `tag:#name` (also matches nested tags)
+    Tag(String),
+    /// `path:folder/` prefix of the vault-relative path
+    Path(String),
+    /// `title:word` substring of the title
+    Title(String),
+    /// `heading:word` substring of one of the note's headings
+    Heading(String),
+    /// `body:word` substring of the note without its front matter
+    Body(String),
+    /// `frontmatter.key:value`, a property equal to (or a list containing) the value
+    Property(String, String),
+    /// Plain word, matched anywhere in the note
+    Text(String),
+    /// `plugin:name:arg`, answered by the named WebAssembly plugin
+    Plugin(String, String),
+    /// `status:draft`, `status:published` or `status:archived`, from the `[status]` rules
+    Status(NoteStatus),
+    /// `type:person`, notes of a kind configured under `[entities]`
+    Type(String),
+    /// `created:>=-7d`, `due:<=today`, `modified:this-week` and the like
+    Date(DateCondition),
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Predicate::Tag(tag) => write!(f, "tag:#{}", tag),
+            Predicate::Path(prefix) => write!(f, "path:{}", prefix),
+            Predicate::Title(word) => write!(f, "title:{}", word),
+            Predicate::Heading(word) => write!(f, "heading:{}", word),
+            Predicate::Body(word) => write!(f, "body:{}", word),
+            Predicate::Property(key, value) => write!(f, "frontmatter.{}:{}", key, value),
+            Predicate::Text(word) => write!(f, "{}", word),
+            Predicate::Plugin(name, arg) => write!(f, "plugin:{}:{}", name, arg),
+            Predicate::Status(wanted) => {
+                write!(f, "status:{}", format!("{:?}", wanted).to_lowercase())
+            }
+            Predicate::Type(kind) => write!(f, "type:{}", kind),
+            Predicate::Date(condition) => write!(f, "{}", condition),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub predicates: Vec<Predicate>,
+}
+
+/// How one predicate matched a note
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PredicateMatch {
+    pub predicate: String,
+    pub how: String,
+}
+
+/// Why a note is in the results of a query
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Explanation {
+    pub path: String,
+    /// Whether the query cache answered for the note instead of evaluating the query
+    pub cached: bool,
+    pub matches: Vec<PredicateMatch>,
+    /// BM25 rank of the note for the query's plain words in the search index, when the
+    /// index matches them as words (queries match them anywhere, so it may not)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyword_score: Option<f64>,
+}
+
+/// A query's results with how each of them matched
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct QueryExplanation {
+    pub predicates: Vec<String>,
+    /// Notes the query was run against, and how many of them the query cache answered for
+    pub notes: usize,
+    pub cached: usize,
+    pub results: Vec<Explanation>,
+}
+
+/// Splits on whitespace, keeping `"quoted phrases"` (also after `field:`) together.
+fn tokenize(input: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for ch in input.chars() {
+        match ch {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!("Unterminated quote in query '{}'", input).into());
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, Box<dyn Error>> {
+        let mut predicates = Vec::new();
+        let today = Zoned::now().date();
+        for token in tokenize(input)? {
+            if let Some((name, value)) = token.split_once(':')
+                && let Some(field) = DateField::parse(name)
+            {
+                // Anything that is not a date is searched for as text, as it used to be
+                match DateCondition::parse(field, value, today) {
+                    Ok(condition) => {
+                        predicates.push(Predicate::Date(condition));
+                        continue;
+                    }
+                    Err(e) => log::debug!("'{}' searched for as text: {}", token, e),
+                }
+            }
+            let predicate = match token.split_once(':') {
+                Some(("tag", value)) => Predicate::Tag(value.trim_start_matches('#').to_string()),
+                Some(("path", value)) => Predicate::Path(value.to_string()),
+                Some(("title", value)) => Predicate::Title(collation::fold(value)),
+                Some(("heading", value)) => Predicate::Heading(collation::fold(value)),
+                Some(("body", value)) => Predicate::Body(collation::fold(value)),
+                Some((field, value)) if field.starts_with(PROPERTY_PREFIX) => Predicate::Property(
+                    field[PROPERTY_PREFIX.len()..].to_string(),
+                    value.to_string(),
+                ),
+                Some(("type", value)) => Predicate::Type(value.to_string()),
+                Some(("status", value)) => Predicate::Status(status::parse(value)?),
+                Some(("plugin", value)) => {
+                    let (name, arg) = value.split_once(':').unwrap_or((value, ""));
+                    Predicate::Plugin(name.to_string(), arg.to_string())
+                }
+                _ => Predicate::Text(collation::fold(&token)),
+            };
+            predicates.push(predicate);
+        }
+        Ok(Query { predicates })
+    }
+
+    pub fn matches(&self, note: &Note) -> bool {
+        self.predicates.iter().all(|predicate| match predicate {
+            Predicate::Tag(tag) => note.has_tag(tag),
+            Predicate::Path(prefix) => note
+                .path
+                .to_string_lossy()
+                .replace('\\', "/")
+                .starts_with(prefix.trim_start_matches('/')),
+            Predicate::Title(word) => collation::fold(&note.title()).contains(word),
+            Predicate::Heading(word) => markdown::parse_headings(&note.content)
+                .iter()
+                .any(|heading| collation::fold(&heading.text).contains(word)),
+            Predicate::Body(word) => {
+                collation::fold(frontmatter::split(&note.content).1).contains(word)
+            }
+            Predicate::Property(key, value) => frontmatter::scalar_properties(&note.content)
+                .iter()
+                .any(|(own, own_value)| own == key && own_value.eq_ignore_ascii_case(value)),
+            Predicate::Text(word) => {
+                collation::fold(&note.content).contains(word)
+                    || collation::fold(&note.title()).contains(word)
+            }
+            Predicate::Plugin(name, arg) => plugins::query(name, arg, note),
+            Predicate::Status(wanted) => status::of(note) == Some(*wanted),
+            Predicate::Type(kind) => entities::is_of(note, kind),
+            Predicate::Date(condition) => condition.matches_note(note),
+        })
+    }
+
+    /// Notes from `notes` matching the query, in their original order. Results come from
+    /// the query cache when one is installed, unless dates relative to today would make
+    /// them stale by tomorrow.
+    pub fn filter<'a>(&self, notes: &'a [Note]) -> Vec<&'a Note> {
+        let relative = self.predicates.iter().any(
+            |predicate| matches!(predicate, Predicate::Date(condition) if condition.is_relative()),
+        );
+        if !self.predicates.is_empty()
+            && !relative
+            && let Some(found) = query_cache::cached_filter(self, notes)
+        {
+            return found;
+        }
+        notes.iter().filter(|note| self.matches(note)).collect()
+    }
+}
+
+/// How `predicate`, which matches `note`, matched it.
+fn explain_predicate(predicate: &Predicate, note: &Note) -> String {
+    match predicate {
+        Predicate::Tag(tag) => {
+            let tag = tag.to_lowercase();
+            let own = note.tags.iter().find(|own| {
+                let own = own.to_lowercase();
+                own == tag || own.starts_with(&format!("{}/", tag))
+            });
+            format!("tagged #{}", own.map_or(tag.as_str(), String::as_str))
+        }
+        Predicate::Path(_) => format!("path {}", note.path.display()),
+        Predicate::Title(_) => format!("title \"{}\"", note.title()),
+        Predicate::Heading(word) => {
+            let headings = markdown::parse_headings(&note.content);
+            match headings
+                .iter()
+                .find(|heading| collation::fold(&heading.text).contains(word.as_str()))
+            {
+                Some(heading) => format!("line {}: {}", heading.line, heading.text),
+                None => String::from("a heading"),
+            }
+        }
+        Predicate::Body(word) => {
+            let line = markdown::body_lines(&note.content)
+                .into_iter()
+                .find(|(_, _, line)| collation::fold(line).contains(word.as_str()));
+            match line {
+                Some((line_no, _, line)) => format!("line {}: {}", line_no, line.trim()),
+                None => String::from("the body"),
+            }
+        }
+        Predicate::Property(key, value) => {
+            let own = frontmatter::scalar_properties(&note.content)
+                .into_iter()
+                .find(|(own, own_value)| own == key && own_value.eq_ignore_ascii_case(value));
+            let value = own.map_or_else(|| value.clone(), |(_, value)| value);
+            format!("{}: {}", key, value)
+        }
+        Predicate::Text(word) => {
+            let line = note
+                .content
+                .lines()
+                .enumerate()
+                .find(|(_, line)| collation::fold(line).contains(word.as_str()));
+            match line {
+                Some((index, line)) => format!("line {}: {}", index + 1, line.trim()),
+                None => format!("title \"{}\"", note.title()),
+            }
+        }
+        Predicate::Plugin(name, _) => format!("plugin {} answered yes", name),
+        Predicate::Status(wanted) => format!("status {:?}", wanted).to_lowercase(),
+        Predicate::Type(kind) => format!("a {} by the [entities.{}] rules", kind, kind),
+        Predicate::Date(condition) => match condition.field.of(note) {
+            Some(date) => format!("{} {}", condition.field.name(), date),
+            None => format!("no {} date", condition.field.name()),
+        },
+    }
+}
+
+/// Scores of the notes matching the plain words of `query` in the search index, by path.
+fn keyword_scores(
+    vault_path: &Path,
+    config: &AppConfig,
+    query: &Query,
+    notes: usize,
+) -> Result<HashMap<String, f64>, Box<dyn Error>> {
+    let words: Vec<String> = query
+        .predicates
+        .iter()
+        .filter_map(|predicate| match predicate {
+            Predicate::Text(word) => Some(word.clone()),
+            _ => None,
+        })
+        .collect();
+    if words.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let connection = index::open(config)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    let hits = index::keyword_search(
+        &connection,
+        &words.join(" "),
+        notes.max(1),
+        SnippetOptions::default(),
+    )?;
+    Ok(hits.into_iter().map(|hit| (hit.path, hit.score)).collect())
+}
+
+/// The notes of `notes` matching `query`, each with how it matched.
+pub fn explain(
+    vault_path: &Path,
+    config: &AppConfig,
+    query: &Query,
+    notes: &[Note],
+) -> Result<QueryExplanation, Box<dyn Error>> {
+    // Ask before filtering, which brings the cache up to date
+    let fresh = query_cache::fresh_paths(query, notes).unwrap_or_default();
+    let found = query.filter(notes);
+    let scores = keyword_scores(vault_path, config, query, notes.len())?;
+    let results = found
+        .iter()
+        .map(|note| {
+            let path = note.path.to_string_lossy().replace('\\', "/");
+            Explanation {
+                cached: fresh.contains(&path),
+                matches: query
+                    .predicates
+                    .iter()
+                    .map(|predicate| PredicateMatch {
+                        predicate: predicate.to_string(),
+                        how: explain_predicate(predicate, note),
+                    })
+                    .collect(),
+                keyword_score: scores.get(&path).copied(),
+                path,
+            }
+        })
+        .collect();
+    Ok(QueryExplanation {
+        predicates: query.predicates.iter().map(|p| p.to_string()).collect(),
+        notes: notes.len(),
+        cached: fresh.len(),
+        results,
+    })
+}
+
+fn print_explanation(
+    explanation: &QueryExplanation,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(explanation)?),
+        OutputFormat::Ndjson => {
+            for result in &explanation.results {
+                util::print_ndjson(result)?;
+            }
+        }
+        OutputFormat::Text => {
+            println!(
+                "{} note(s), {} answered from the query cache, {} evaluated",
+                explanation.notes,
+                explanation.cached,
+                explanation.notes - explanation.cached
+            );
+            for result in &explanation.results {
+                let mut notes = Vec::new();
+                if result.cached {
+                    notes.push(String::from("cached"));
+                }
+                if let Some(score) = result.keyword_score {
+                    notes.push(format!("keyword score {:.3}", score));
+                }
+                match notes.is_empty() {
+                    true => println!("\n{}", result.path),
+                    false => println!("\n{} ({})", result.path, notes.join(", ")),
+                }
+                for found in &result.matches {
+                    println!("  {}  {}", found.predicate, found.how);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists the notes matching the query, returning how many there were. NDJSON output is
+/// streamed note by note instead of loading the whole vault first.
+pub fn run_query(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &QueryArgs,
+) -> Result<usize, Box<dyn Error>> {
+    let text = args.query.join(" ");
+    if let Some(note) = &args.render_into {
+        return query_table::render_into(vault_path, note, &text, &args.columns, &args.changes);
+    }
+    let query = Query::parse(&text)?;
+    if let Some(key) = &args.group_by {
+        let key = GroupKey::parse(key)?;
+        let notes = data::load_notes(vault_path)?;
+        let found = query.filter(&notes);
+        aggregate::print_groups(&aggregate::group(&found, &key, args.count), args.format)?;
+        return Ok(found.len());
+    }
+    if args.explain {
+        let notes = data::load_notes(vault_path)?;
+        let explanation = explain(vault_path, config, &query, &notes)?;
+        print_explanation(&explanation, args.format)?;
+        return Ok(explanation.results.len());
+    }
+    if args.porcelain {
+        let mut count = 0;
+        for note in data::iter_notes(vault_path)?.filter(|note| query.matches(note)) {
+            let path = note.path.to_string_lossy().replace('\\', "/");
+            util::print_porcelain(&[&path, &note.title()])?;
+            count += 1;
+        }
+        return Ok(count);
+    }
+    if args.format == OutputFormat::Ndjson {
+        let mut count = 0;
+        for note in data::iter_notes(vault_path)?.filter(|note| query.matches(note)) {
+            util::print_ndjson(&NoteEntry::new(&note))?;
+            count += 1;
+        }
+        return Ok(count);
+    }
+
+    let notes = data::load_notes(vault_path)?;
+    let found = query.filter(&notes);
+    match args.format {
+        OutputFormat::Json => {
+            let entries: Vec<NoteEntry> = found.iter().map(|note| NoteEntry::new(note)).collect();
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        }
+        _ => {
+            for note in &found {
+                println!("{}", note.path.display());
+            }
+        }
+    }
+    Ok(found.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = Query::parse(r#"tag:#public path:blog/ title:"design review" rust"#).unwrap();
+        assert_eq!(
+            query.predicates,
+            vec![
+                Predicate::Tag(String::from("public")),
+                Predicate::Path(String::from("blog/")),
+                Predicate::Title(String::from("design review")),
+                Predicate::Text(String::from("rust")),
+            ]
+        );
+        assert!(Query::parse(r#"title:"open"#).is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let public = note(
+            "blog/Post.md",
+            "---\ntags: [public/blog]\n---\nAbout Rust\n",
+        );
+        let private = note("journal/Day.md", "#private thoughts on rust\n");
+
+        let query = Query::parse("tag:public").unwrap();
+        assert!(query.matches(&public));
+        assert!(!query.matches(&private));
+
+        let query = Query::parse("rust path:journal").unwrap();
+        assert!(!query.matches(&public));
+        assert!(query.matches(&private));
+
+        assert!(Query::parse("").unwrap().matches(&private));
+        assert!(Query::parse("title:post").unwrap().matches(&public));
+
+        let review = note(
+            "Review.md",
+            "---\nclient: [ACME]\n---\n## Design review\nBudget\n",
+        );
+        let query = Query::parse(r#"heading:"design review" body:budget frontmatter.client:acme"#);
+        assert!(query.unwrap().matches(&review));
+        assert!(!Query::parse("body:client").unwrap().matches(&review));
+
+        let draft = note("Draft.md", "---\npublish: false\n---\n");
+        assert!(Query::parse("status:draft").unwrap().matches(&draft));
+        assert!(!Query::parse("status:draft").unwrap().matches(&public));
+        assert!(Query::parse("status:done").is_err());
+        assert!(
+            Query::parse("type:Book")
+                .unwrap()
+                .matches(&note("B.md", "---\ntype: book\n---\n"))
+        );
+    }
+
+    #[test]
+    fn test_non_dates_are_text() {
+        let minutes = note("Minutes.md", "Next steps are due:asap\n");
+        let query = Query::parse("due:asap").unwrap();
+        assert!(matches!(query.predicates[..], [Predicate::Text(_)]));
+        assert!(query.matches(&minutes));
+        assert!(matches!(
+            Query::parse("due:today").unwrap().predicates[..],
+            [Predicate::Date(_)]
+        ));
+    }
+
+    #[test]
+    fn test_explain_predicate() {
+        let post = note(
+            "blog/Post.md",
+            "---\ntags: [public/blog]\n---\nIntro\n  About Rust here\n",
+        );
+        let query = Query::parse("tag:public rust status:draft").unwrap();
+        let how: Vec<String> = query.predicates[..2]
+            .iter()
+            .map(|predicate| explain_predicate(predicate, &post))
+            .collect();
+        assert_eq!(how, ["tagged #public/blog", "line 5: About Rust here"]);
+        assert_eq!(query.predicates[2].to_string(), "status:draft");
+    }
+}
@@ -0,0 +1,338 @@
+//! Query results kept in the cache database between runs.
+//!
+//! Each query remembers, per note, the content hash it was evaluated against and whether
+//! the note matched. Re-running the query only evaluates notes whose hash changed, and the
+//! watcher drops the entries of changed notes so every cached query forgets them. Only the
+//! `cache.max_queries` most recently run queries keep their answers.
+
+use crate::collation;
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::date_query::DateField;
+use crate::entities;
+use crate::index;
+use crate::plugins;
+use crate::query::{Predicate, Query};
+use crate::status;
+use crate::util;
+
+use sqlite::{Connection, State};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+static CACHE: OnceLock<(Mutex<Connection>, usize)> = OnceLock::new();
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS query_results (
+            query TEXT NOT NULL,
+            path TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            matched INTEGER NOT NULL,
+            PRIMARY KEY (query, path)
+        );
+        CREATE INDEX IF NOT EXISTS query_results_path ON query_results(path);
+        CREATE TABLE IF NOT EXISTS query_uses (
+            query TEXT PRIMARY KEY,
+            used INTEGER NOT NULL
+        );",
+    )
+}
+
+/// Caches query results in the vault's cache database for the rest of the process.
+pub fn install(config: &AppConfig) {
+    match data::get_data_path(config) {
+        Ok(data_path) => install_in(&data_path, config),
+        Err(e) => log::warn!("Query results will not be cached: {}", e),
+    }
+}
+
+/// Caches query results in the cache database kept in `data_path`.
+pub fn install_in(data_path: &Path, config: &AppConfig) {
+    let connection = index::open_in(data_path, config).and_then(|connection| {
+        ensure_schema(&connection)?;
+        Ok(connection)
+    });
+    match connection {
+        Ok(connection) => {
+            let _ = CACHE.set((Mutex::new(connection), config.cache.max_queries));
+        }
+        Err(e) => log::warn!("Query results will not be cached: {}", e),
+    }
+}
+
+fn query_key(query: &Query) -> String {
+    util::content_hash(format!("{:?}", query.predicates).as_bytes())
+}
+
+/// What besides a note's content decides whether `query` matches it: the plugins, status
+/// rules, kinds and collation its predicates depend on.
+fn extensions(query: &Query) -> String {
+    let mut extensions = String::new();
+    if query
+        .predicates
+        .iter()
+        .any(|p| matches!(p, Predicate::Plugin(..)))
+    {
+        extensions.push_str(&plugins::fingerprint());
+    }
+    if query
+        .predicates
+        .iter()
+        .any(|p| matches!(p, Predicate::Status(_)))
+    {
+        extensions.push_str(&status::fingerprint());
+    }
+    if query
+        .predicates
+        .iter()
+        .any(|p| matches!(p, Predicate::Type(_)))
+    {
+        extensions.push_str(&entities::fingerprint());
+    }
+    if query.predicates.iter().any(|p| {
+        matches!(
+            p,
+            Predicate::Title(_) | Predicate::Heading(_) | Predicate::Body(_) | Predicate::Text(_)
+        )
+    }) {
+        extensions.push_str(&collation::fingerprint());
+    }
+    extensions
+}
+
+/// Hash of what `query` sees of `note`: its content, and its modification time when the
+/// query compares it.
+fn note_hash(note: &Note, extensions: &str, query: &Query) -> String {
+    let modified = query
+        .predicates
+        .iter()
+        .any(|p| matches!(p, Predicate::Date(condition) if condition.field == DateField::Modified));
+    let mut text = format!("{}{}", note.content, extensions);
+    if modified {
+        text.push_str(&format!("{:?}", note.modified));
+    }
+    util::content_hash(text.as_bytes())
+}
+
+/// Stored answers to `query` by path: the hash each was evaluated against, and whether
+/// the note matched.
+fn stored_answers(
+    connection: &Connection,
+    key: &str,
+) -> Result<HashMap<String, (String, bool)>, sqlite::Error> {
+    let mut answers = HashMap::new();
+    let mut statement =
+        connection.prepare("SELECT path, hash, matched FROM query_results WHERE query = ?")?;
+    statement.bind((1, key))?;
+    while let State::Row = statement.next()? {
+        answers.insert(
+            statement.read::<String, _>(0)?,
+            (
+                statement.read::<String, _>(1)?,
+                statement.read::<i64, _>(2)? != 0,
+            ),
+        );
+    }
+    Ok(answers)
+}
+
+/// Marks `key` as the most recently run query and forgets the answers of all but the
+/// `max_queries` most recent ones.
+fn touch(connection: &Connection, key: &str, max_queries: usize) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO query_uses (query, used)
+         VALUES (?, (SELECT COALESCE(MAX(used), 0) + 1 FROM query_uses))",
+    )?;
+    statement.bind((1, key))?;
+    statement.next()?;
+    let mut statement = connection.prepare(
+        "DELETE FROM query_uses WHERE query IN
+         (SELECT query FROM query_uses ORDER BY used DESC LIMIT -1 OFFSET ?)",
+    )?;
+    statement.bind((1, max_queries.max(1) as i64))?;
+    statement.next()?;
+    connection
+        .execute("DELETE FROM query_results WHERE query NOT IN (SELECT query FROM query_uses)")
+}
+
+/// Notes from `notes` matching `query`, evaluating only notes the cache has no current
+/// answer for. Entries of notes that no longer exist are dropped, and so are the answers
+/// of queries past the `max_queries` most recently run.
+pub fn filter<'a>(
+    connection: &Connection,
+    query: &Query,
+    notes: &'a [Note],
+    max_queries: usize,
+) -> Result<Vec<&'a Note>, Box<dyn Error>> {
+    let key = query_key(query);
+    let extensions = extensions(query);
+    let mut cached = stored_answers(connection, &key)?;
+
+    let mut found = Vec::new();
+    connection.execute("BEGIN")?;
+    for note in notes {
+        let path = note.path.to_string_lossy().replace('\\', "/");
+        let hash = note_hash(note, &extensions, query);
+        let matched = match cached.remove(&path) {
+            Some((known, matched)) if known == hash => matched,
+            _ => {
+                let matched = query.matches(note);
+                let mut statement = connection.prepare(
+                    "INSERT OR REPLACE INTO query_results (query, path, hash, matched)
+                     VALUES (?, ?, ?, ?)",
+                )?;
+                statement.bind((1, key.as_str()))?;
+                statement.bind((2, path.as_str()))?;
+                statement.bind((3, hash.as_str()))?;
+                statement.bind((4, matched as i64))?;
+                statement.next()?;
+                matched
+            }
+        };
+        if matched {
+            found.push(note);
+        }
+    }
+    for path in cached.keys() {
+        let mut statement =
+            connection.prepare("DELETE FROM query_results WHERE query = ? AND path = ?")?;
+        statement.bind((1, key.as_str()))?;
+        statement.bind((2, path.as_str()))?;
+        statement.next()?;
+    }
+    touch(connection, &key, max_queries)?;
+    connection.execute("COMMIT")?;
+    Ok(found)
+}
+
+/// `filter` through the installed cache, or `None` when there is none or it fails.
+pub fn cached_filter<'a>(query: &Query, notes: &'a [Note]) -> Option<Vec<&'a Note>> {
+    let (connection, max_queries) = CACHE.get()?;
+    let connection = connection.lock().ok()?;
+    match filter(&connection, query, notes, *max_queries) {
+        Ok(found) => Some(found),
+        Err(e) => {
+            log::warn!("Query cache unavailable: {}", e);
+            let _ = connection.execute("ROLLBACK");
+            None
+        }
+    }
+}
+
+/// Paths of `notes` the installed cache holds a current answer to `query` for, or `None`
+/// without a cache.
+pub fn fresh_paths(query: &Query, notes: &[Note]) -> Option<HashSet<String>> {
+    let connection = CACHE.get()?.0.lock().ok()?;
+    let stored = stored_answers(&connection, &query_key(query)).ok()?;
+    let extensions = extensions(query);
+    let fresh = notes
+        .iter()
+        .filter_map(|note| {
+            let path = note.path.to_string_lossy().replace('\\', "/");
+            let (hash, _) = stored.get(&path)?;
+            (*hash == note_hash(note, &extensions, query)).then_some(path)
+        })
+        .collect();
+    Some(fresh)
+}
+
+/// Forgets the cached results of the vault-relative `paths` for every query.
+pub fn invalidate(connection: &Connection, paths: &[String]) -> Result<(), sqlite::Error> {
+    for path in paths {
+        let mut statement = connection.prepare("DELETE FROM query_results WHERE path = ?")?;
+        statement.bind((1, path.as_str()))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    fn paths(found: Vec<&Note>) -> Vec<&str> {
+        found
+            .iter()
+            .map(|note| note.path.to_str().unwrap())
+            .collect()
+    }
+
+    fn stored(connection: &Connection) -> Vec<(String, i64)> {
+        let mut statement = connection
+            .prepare("SELECT path, matched FROM query_results ORDER BY path")
+            .unwrap();
+        let mut rows = Vec::new();
+        while let State::Row = statement.next().unwrap() {
+            rows.push((
+                statement.read::<String, _>(0).unwrap(),
+                statement.read::<i64, _>(1).unwrap(),
+            ));
+        }
+        rows
+    }
+
+    #[test]
+    fn test_filter_reuses_and_refreshes_results() {
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let query = Query::parse("tag:rust").unwrap();
+        let mut notes = vec![note("A.md", "#rust"), note("B.md", "plain")];
+
+        let found = filter(&connection, &query, &notes, 10).unwrap();
+        assert_eq!(paths(found), vec!["A.md"]);
+        assert_eq!(
+            stored(&connection),
+            vec![(String::from("A.md"), 1), (String::from("B.md"), 0)]
+        );
+
+        // Unchanged notes are answered from the cache, even when it is wrong
+        connection
+            .execute("UPDATE query_results SET matched = 1 WHERE path = 'B.md'")
+            .unwrap();
+        assert_eq!(filter(&connection, &query, &notes, 10).unwrap().len(), 2);
+
+        // Changed notes are evaluated again and deleted ones forgotten
+        notes[1] = note("B.md", "now #rust");
+        notes.remove(0);
+        let found = filter(&connection, &query, &notes, 10).unwrap();
+        assert_eq!(paths(found), vec!["B.md"]);
+        assert_eq!(stored(&connection), vec![(String::from("B.md"), 1)]);
+
+        invalidate(&connection, &[String::from("B.md")]).unwrap();
+        assert!(stored(&connection).is_empty());
+    }
+
+    #[test]
+    fn test_least_recently_run_queries_are_forgotten() {
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let notes = vec![note("A.md", "#rust #go")];
+        let rust = Query::parse("tag:rust").unwrap();
+        let go = Query::parse("tag:go").unwrap();
+        let zig = Query::parse("tag:zig").unwrap();
+
+        filter(&connection, &rust, &notes, 2).unwrap();
+        filter(&connection, &go, &notes, 2).unwrap();
+        filter(&connection, &rust, &notes, 2).unwrap();
+        filter(&connection, &zig, &notes, 2).unwrap();
+
+        let mut statement = connection
+            .prepare("SELECT DISTINCT query FROM query_results")
+            .unwrap();
+        let mut kept = HashSet::new();
+        while let State::Row = statement.next().unwrap() {
+            kept.insert(statement.read::<String, _>(0).unwrap());
+        }
+        assert_eq!(kept, HashSet::from([query_key(&rust), query_key(&zig)]));
+    }
+}
@@ -0,0 +1,331 @@
+//! Live tables: query results written into a note as a Markdown table, one row per note,
+//! between `%% begin query (columns): query %%` and `%% end query %%` comments. The
+//! daemon rewrites them as notes change, like maps of content.
+
+use crate::aggregate::GroupKey;
+use crate::capture;
+use crate::changeset::{self, ChangeSet};
+use crate::cli::ChangeArgs;
+use crate::collation;
+use crate::data::{self, Note};
+use crate::link_to::{self, LinkSettings};
+use crate::query::Query;
+use crate::resolver::Resolver;
+use crate::search_query::PROPERTY_PREFIX;
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const BEGIN: &str = "%% begin query";
+const END: &str = "%% end query %%";
+
+/// What a table column shows
+#[derive(Debug, Clone, PartialEq)]
+enum Column {
+    /// A link to the note
+    Title,
+    Path,
+    /// Tags, folder, status, a date or a front matter property
+    Value(GroupKey),
+}
+
+impl Column {
+    /// Any other name is read as a front matter key, so `priority` works like
+    /// `frontmatter.priority`.
+    fn parse(name: &str) -> Result<Column, Box<dyn Error>> {
+        match name {
+            "" => Err("Empty column name".into()),
+            "title" => Ok(Column::Title),
+            "path" => Ok(Column::Path),
+            "tags" => Ok(Column::Value(GroupKey::Tag)),
+            name => Ok(Column::Value(GroupKey::parse(name).unwrap_or_else(|_| {
+                GroupKey::Property(name.trim_start_matches(PROPERTY_PREFIX).to_string())
+            }))),
+        }
+    }
+}
+
+/// What a table lists, as recorded in its begin marker
+#[derive(Debug, Clone, PartialEq)]
+struct TableSpec {
+    query: String,
+    columns: Vec<String>,
+}
+
+impl TableSpec {
+    fn new(query: &str, columns: &str) -> Result<TableSpec, Box<dyn Error>> {
+        let columns: Vec<String> = columns
+            .split(',')
+            .map(|column| column.trim().to_string())
+            .collect();
+        for column in &columns {
+            Column::parse(column)?;
+        }
+        Query::parse(query)?;
+        Ok(TableSpec {
+            query: query.trim().to_string(),
+            columns,
+        })
+    }
+
+    fn marker(&self) -> String {
+        format!("{} ({}): {} %%", BEGIN, self.columns.join(","), self.query)
+    }
+
+    /// The spec of a `%% begin query (columns): query %%` line.
+    fn parse(line: &str) -> Option<TableSpec> {
+        let rest = line.trim().strip_prefix(BEGIN)?.strip_suffix("%%")?;
+        let (columns, query) = rest.trim().strip_prefix('(')?.split_once("):")?;
+        TableSpec::new(query, columns).ok()
+    }
+}
+
+/// Everything needed to write links into a table
+struct Links {
+    resolver: Resolver,
+    settings: LinkSettings,
+}
+
+/// `text` made safe for a table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// The notes matching the query of `spec`, except the note at `own_path` holding the
+/// table, by title.
+fn rows<'a>(
+    spec: &TableSpec,
+    notes: &'a [Note],
+    own_path: &Path,
+) -> Result<Vec<&'a Note>, Box<dyn Error>> {
+    let mut found: Vec<&Note> = Query::parse(&spec.query)?
+        .filter(notes)
+        .into_iter()
+        .filter(|note| note.path != own_path)
+        .collect();
+    found.sort_by_cached_key(|note| collation::sort_key(&note.title()));
+    Ok(found)
+}
+
+/// The block for `spec`, markers included, with a row for each of `found`.
+fn render_block(
+    spec: &TableSpec,
+    found: &[&Note],
+    own_path: &Path,
+    links: &Links,
+) -> Result<String, Box<dyn Error>> {
+    let columns = spec
+        .columns
+        .iter()
+        .map(|name| Column::parse(name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut block = format!("{}\n", spec.marker());
+    block.push_str(&format!("| {} |\n", spec.columns.join(" | ")));
+    block.push_str(&format!("|{}\n", " --- |".repeat(columns.len())));
+    for note in found {
+        let cells: Vec<String> = columns
+            .iter()
+            .map(|column| match column {
+                Column::Title => {
+                    let link = link_to::link_text(
+                        &links.resolver,
+                        own_path,
+                        &note.path,
+                        None,
+                        links.settings,
+                    );
+                    cell(&link)
+                }
+                Column::Path => cell(&note.path.to_string_lossy().replace('\\', "/")),
+                Column::Value(key) => cell(&key.values(note).join(", ")),
+            })
+            .collect();
+        block.push_str(&format!("| {} |\n", cells.join(" | ")));
+    }
+    block.push_str(END);
+    block.push('\n');
+    Ok(block)
+}
+
+/// `content` of the note at `own_path` with every table regenerated.
+fn refresh_content(
+    content: &str,
+    notes: &[Note],
+    own_path: &Path,
+    links: &Links,
+) -> Result<String, Box<dyn Error>> {
+    let mut refreshed = String::new();
+    let mut lines = content.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        let Some(spec) = TableSpec::parse(line) else {
+            refreshed.push_str(line);
+            continue;
+        };
+        if !lines.any(|line| line.trim() == END) {
+            return Err(format!("'{}' has no matching '{}'", line.trim(), END).into());
+        }
+        let found = rows(&spec, notes, own_path)?;
+        refreshed.push_str(&render_block(&spec, &found, own_path, links)?);
+    }
+    Ok(refreshed)
+}
+
+fn contains_table(content: &str) -> bool {
+    content.lines().any(|line| TableSpec::parse(line).is_some())
+}
+
+/// Writes the table of `query` into `note`, replacing the table of the same spec if the
+/// note has one and appending it otherwise. Returns the number of rows.
+pub fn render_into(
+    vault_path: &Path,
+    note: &str,
+    query: &str,
+    columns: &str,
+    changes: &ChangeArgs,
+) -> Result<usize, Box<dyn Error>> {
+    let mut out = PathBuf::from(note);
+    if out.extension().is_none() {
+        out.set_extension("md");
+    }
+    capture::ensure_inside_vault(&out)?;
+    let spec = TableSpec::new(query, columns)?;
+    let notes = data::load_notes(vault_path)?;
+    let links = Links {
+        resolver: Resolver::from_vault(vault_path)?,
+        settings: LinkSettings::from_vault(vault_path),
+    };
+    let found = rows(&spec, &notes, &out)?;
+
+    let before = fs::read_to_string(vault_path.join(&out)).ok();
+    let after = match &before {
+        Some(content) if content.lines().any(|line| line.trim() == spec.marker()) => {
+            refresh_content(content, &notes, &out, &links)?
+        }
+        Some(content) => format!(
+            "{}\n\n{}",
+            content.trim_end(),
+            render_block(&spec, &found, &out, &links)?
+        ),
+        None => {
+            let title = out.file_stem().unwrap_or_default().to_string_lossy();
+            format!(
+                "# {}\n\n{}",
+                title,
+                render_block(&spec, &found, &out, &links)?
+            )
+        }
+    };
+    let mut edits = ChangeSet::new();
+    edits.propose(out, before, after);
+    edits.finish(vault_path, changes, "render query table", false)?;
+    Ok(found.len())
+}
+
+/// Edits regenerating every query table in the vault, or only those in the note at `only`.
+fn refresh_changes(vault_path: &Path, only: Option<&Path>) -> Result<ChangeSet, Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    let mut changes = ChangeSet::new();
+    let tables: Vec<&Note> = notes
+        .iter()
+        .filter(|note| only.is_none_or(|path| note.path == path) && contains_table(&note.content))
+        .collect();
+    if tables.is_empty() {
+        return Ok(changes);
+    }
+    let links = Links {
+        resolver: Resolver::from_vault(vault_path)?,
+        settings: LinkSettings::from_vault(vault_path),
+    };
+    for note in tables {
+        match refresh_content(&note.content, &notes, &note.path, &links) {
+            Ok(after) => changes.propose(&note.path, Some(note.content.clone()), after),
+            Err(e) => log::warn!("Skipping tables in {}: {}", note.path.display(), e),
+        }
+    }
+    Ok(changes)
+}
+
+/// Rewrites the query tables that are out of date, for the daemon once changes settle.
+pub fn refresh_vault(vault_path: &Path) {
+    changeset::refresh_generated(vault_path, "query tables", || {
+        refresh_changes(vault_path, None)
+    });
+}
+
+/// Regenerates the query tables in the note at `path`, returning the number of edits.
+pub fn refresh_note(vault_path: &Path, path: &Path) -> Result<usize, Box<dyn Error>> {
+    let changes = refresh_changes(vault_path, Some(path))?;
+    changes.apply(vault_path, "refresh query tables", false)?;
+    Ok(changes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh_content() {
+        let notes: Vec<Note> = [
+            (
+                "Projects/Beta.md",
+                "---\nstatus: archived\ndue: 2024-06-01\npriority: high\n---\n#project\n",
+            ),
+            ("Projects/Alpha.md", "---\ndue: 2024-05-01\n---\n#project\n"),
+            ("Other.md", "#misc\n"),
+            ("Dashboard.md", "#project\n"),
+        ]
+        .iter()
+        .map(|(path, content)| Note::from_content(PathBuf::from(path), content.to_string()))
+        .collect();
+        let links = Links {
+            resolver: Resolver::new(notes.iter().map(|note| note.path.clone()).collect()),
+            settings: LinkSettings::default(),
+        };
+        let own_path = Path::new("Dashboard.md");
+        let content = "# Dashboard\n\n\
+            %% begin query (title,status,due,priority): tag:#project %%\n\
+            stale\n\
+            %% end query %%\n\
+            After\n";
+
+        let refreshed = refresh_content(content, &notes, own_path, &links).unwrap();
+        assert_eq!(
+            refreshed,
+            "# Dashboard\n\n\
+             %% begin query (title,status,due,priority): tag:#project %%\n\
+             | title | status | due | priority |\n\
+             | --- | --- | --- | --- |\n\
+             | [[Alpha]] |  | 2024-05-01 |  |\n\
+             | [[Beta]] | archived | 2024-06-01 | high |\n\
+             %% end query %%\n\
+             After\n"
+        );
+        assert_eq!(
+            refresh_content(&refreshed, &notes, own_path, &links).unwrap(),
+            refreshed
+        );
+        assert_eq!(cell("a|b\nc"), "a\\|b c");
+        assert!(
+            refresh_content("%% begin query (title): x %%\n", &notes, own_path, &links).is_err()
+        );
+    }
+
+    #[test]
+    fn test_render_into_stays_in_the_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = dir.path().join("vault");
+        fs::create_dir(&vault).unwrap();
+        let changes = ChangeArgs {
+            dry_run: false,
+            changes_format: crate::cli::OutputFormat::Text,
+        };
+        for note in ["../Outside", "/tmp/Outside"] {
+            assert!(render_into(&vault, note, "tag:x", "title", &changes).is_err());
+        }
+        assert!(!dir.path().join("Outside.md").exists());
+    }
+}
@@ -0,0 +1,237 @@
+//! When and how often notes are used, kept in the cache database. Edits are recorded by
+//! the daemon's watcher; opens are reported by editors through `POST /open`.
+
+use crate::cli::{OutputFormat, RecentArgs};
+use crate::config::AppConfig;
+use crate::index;
+use crate::util;
+
+use jiff::Timestamp;
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{collections::HashMap, error::Error, path::Path};
+
+/// A visit's weight halves every this many days
+const HALF_LIFE_DAYS: f64 = 7.0;
+
+const MS_PER_DAY: f64 = 86_400_000.0;
+
+/// Saves to a note this soon after its last recorded edit are part of that edit
+const EDIT_SESSION_MS: i64 = 5 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    Open,
+    Edit,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RecentNote {
+    pub path: String,
+    pub opens: i64,
+    pub edits: i64,
+    /// When the note was last opened or edited
+    pub last_visit: String,
+    pub frecency: f64,
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS note_visits (
+            path TEXT PRIMARY KEY,
+            opens INTEGER NOT NULL,
+            edits INTEGER NOT NULL,
+            last_visit INTEGER NOT NULL
+        );",
+    )
+}
+
+/// Opens the cache database with the visits table in place.
+pub fn open(config: &AppConfig) -> Result<Connection, Box<dyn Error>> {
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    Ok(connection)
+}
+
+pub fn record(
+    connection: &Connection,
+    path: &str,
+    visit: Visit,
+    now_ms: i64,
+) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT INTO note_visits (path, opens, edits, last_visit) VALUES (?, ?, ?, ?)
+         ON CONFLICT(path) DO UPDATE SET
+            opens = opens + excluded.opens,
+            edits = edits + excluded.edits,
+            last_visit = excluded.last_visit",
+    )?;
+    statement.bind((1, path))?;
+    statement.bind((2, (visit == Visit::Open) as i64))?;
+    statement.bind((3, (visit == Visit::Edit) as i64))?;
+    statement.bind((4, now_ms))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// The daemon's record of edits, counting the saves of one editing session as a single
+/// edit so a note saved on every keystroke is not written to the cache each time.
+pub struct EditLog {
+    connection: Connection,
+    /// When each note's last edit was recorded
+    recorded: HashMap<String, i64>,
+}
+
+impl EditLog {
+    pub fn new(connection: Connection) -> EditLog {
+        EditLog {
+            connection,
+            recorded: HashMap::new(),
+        }
+    }
+
+    /// Records an edit of `path`, unless one was recorded within the last session.
+    pub fn record(&mut self, path: &str, now_ms: i64) -> Result<(), sqlite::Error> {
+        if let Some(&at) = self.recorded.get(path)
+            && now_ms - at < EDIT_SESSION_MS
+        {
+            return Ok(());
+        }
+        record(&self.connection, path, Visit::Edit, now_ms)?;
+        self.recorded.insert(path.to_string(), now_ms);
+        Ok(())
+    }
+
+    /// Drops the visits of a note that was deleted.
+    pub fn forget(&mut self, path: &str) -> Result<(), sqlite::Error> {
+        self.recorded.remove(path);
+        forget(&self.connection, path)
+    }
+}
+
+/// Drops the visits of a note that was deleted.
+pub fn forget(connection: &Connection, path: &str) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare("DELETE FROM note_visits WHERE path = ?")?;
+    statement.bind((1, path))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Visit count decayed by the time since the last visit.
+pub fn frecency(visits: i64, last_visit_ms: i64, now_ms: i64) -> f64 {
+    let age_days = (now_ms - last_visit_ms).max(0) as f64 / MS_PER_DAY;
+    visits as f64 * 0.5f64.powf(age_days / HALF_LIFE_DAYS)
+}
+
+/// Visited notes, most recently visited first.
+pub fn recent(connection: &Connection, now_ms: i64) -> Result<Vec<RecentNote>, Box<dyn Error>> {
+    let mut statement = connection.prepare(
+        "SELECT path, opens, edits, last_visit FROM note_visits ORDER BY last_visit DESC, path",
+    )?;
+    let mut notes = Vec::new();
+    while let State::Row = statement.next()? {
+        let (opens, edits) = (statement.read::<i64, _>(1)?, statement.read::<i64, _>(2)?);
+        let last_visit = statement.read::<i64, _>(3)?;
+        notes.push(RecentNote {
+            path: statement.read::<String, _>(0)?,
+            opens,
+            edits,
+            last_visit: Timestamp::from_millisecond(last_visit)?.to_string(),
+            frecency: frecency(opens + edits, last_visit, now_ms),
+        });
+    }
+    Ok(notes)
+}
+
+/// Frecency of every visited note, by vault-relative path.
+pub fn frecencies(
+    connection: &Connection,
+    now_ms: i64,
+) -> Result<HashMap<String, f64>, Box<dyn Error>> {
+    Ok(recent(connection, now_ms)?
+        .into_iter()
+        .map(|note| (note.path, note.frecency))
+        .collect())
+}
+
+pub fn run_recent(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &RecentArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = open(config)?;
+    let notes: Vec<RecentNote> = recent(&connection, Timestamp::now().as_millisecond())?
+        .into_iter()
+        .filter(|note| vault_path.join(&note.path).exists())
+        .take(args.limit)
+        .collect();
+    if args.porcelain {
+        for note in &notes {
+            util::print_porcelain(&[
+                &note.path,
+                &note.last_visit,
+                &note.opens.to_string(),
+                &note.edits.to_string(),
+            ])?;
+        }
+        return Ok(());
+    }
+    match args.format {
+        OutputFormat::Text => {
+            for note in &notes {
+                println!(
+                    "{}  {} ({} open(s), {} edit(s))",
+                    note.last_visit, note.path, note.opens, note.edits
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&notes)?),
+        OutputFormat::Ndjson => {
+            for note in &notes {
+                util::print_ndjson(note)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_rank() {
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let day = MS_PER_DAY as i64;
+        record(&connection, "Old.md", Visit::Edit, 0).unwrap();
+        record(&connection, "Old.md", Visit::Open, 0).unwrap();
+        record(&connection, "New.md", Visit::Open, 14 * day).unwrap();
+
+        let notes = recent(&connection, 14 * day).unwrap();
+        assert_eq!(notes[0].path, "New.md");
+        assert_eq!((notes[1].opens, notes[1].edits), (1, 1));
+        // Two visits two half-lives ago weigh half of one visit today
+        assert!((notes[1].frecency - 0.5).abs() < 1e-9);
+
+        forget(&connection, "Old.md").unwrap();
+        assert_eq!(frecencies(&connection, 14 * day).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_edit_log_coalesces_saves() {
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let mut edits = EditLog::new(connection);
+        for at in [0, 1_000, 60_000, EDIT_SESSION_MS, EDIT_SESSION_MS + 1_000] {
+            edits.record("A.md", at).unwrap();
+        }
+        let notes = recent(&edits.connection, EDIT_SESSION_MS).unwrap();
+        assert_eq!(notes[0].edits, 2);
+
+        // A note deleted and written again starts a new edit
+        edits.forget("A.md").unwrap();
+        edits.record("A.md", EDIT_SESSION_MS + 2_000).unwrap();
+        assert_eq!(recent(&edits.connection, 0).unwrap()[0].edits, 1);
+    }
+}
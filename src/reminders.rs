@@ -0,0 +1,331 @@
+//! Reminders from front matter: `reminder: 2024-05-01 09:00` reminds at that moment and
+//! `due: 2024-05-01` on the morning of that day. They are kept in the index as notes are
+//! indexed; `agenda` lists the upcoming ones and the daemon notifies when they come due.
+
+use crate::cli::{AgendaArgs, OutputFormat};
+use crate::config::{AppConfig, RemindersConfig};
+use crate::data::Note;
+use crate::frontmatter;
+use crate::index;
+use crate::notify::{self, Notification};
+use crate::search;
+use crate::util;
+
+use jiff::{SignedDuration, Span, Timestamp, Zoned, civil, tz::TimeZone};
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{error::Error, path::Path, thread, time::Duration};
+
+/// Front matter properties that remind
+const PROPERTIES: [&str; 2] = ["reminder", "due"];
+/// Reminders that came due longer ago than this while the daemon was stopped are not sent
+const MISSED_WINDOW: SignedDuration = SignedDuration::from_hours(24);
+/// Longest the notifier sleeps, so reminders added meanwhile are not missed by much
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// A reminder as stored in the index
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Reminder {
+    pub path: String,
+    pub title: String,
+    /// Property it comes from: "reminder" or "due"
+    pub kind: String,
+    /// Milliseconds since the epoch; the start of the day for all-day reminders
+    pub at: i64,
+    pub all_day: bool,
+}
+
+/// The moment `text` names, with whether it is a whole day. Dates and date-times are
+/// taken in the local time zone.
+fn parse_when(text: &str) -> Option<(Timestamp, bool)> {
+    let text = text.trim();
+    if text.len() == 10
+        && let Ok(date) = text.parse::<civil::Date>()
+    {
+        return Some((date.to_zoned(TimeZone::system()).ok()?.timestamp(), true));
+    }
+    if let Ok(timestamp) = text.parse::<Timestamp>() {
+        return Some((timestamp, false));
+    }
+    let datetime = text.parse::<civil::DateTime>().ok()?;
+    Some((
+        datetime.to_zoned(TimeZone::system()).ok()?.timestamp(),
+        false,
+    ))
+}
+
+/// The reminders set in the front matter of `note`.
+pub fn note_reminders(note: &Note) -> Vec<Reminder> {
+    let path = note.path.to_string_lossy().replace('\\', "/");
+    let properties = frontmatter::scalar_properties(&note.content);
+    let mut reminders = Vec::new();
+    for kind in PROPERTIES {
+        for (_, text) in properties.iter().filter(|(own, _)| own == kind) {
+            let Some((at, all_day)) = parse_when(text) else {
+                continue;
+            };
+            reminders.push(Reminder {
+                path: path.clone(),
+                title: note.title(),
+                kind: kind.to_string(),
+                at: at.as_millisecond(),
+                all_day,
+            });
+        }
+    }
+    reminders
+}
+
+/// Stores the reminders of `note` in the index.
+pub fn index_reminders(connection: &Connection, note: &Note) -> Result<(), sqlite::Error> {
+    for reminder in note_reminders(note) {
+        let mut statement = connection.prepare(
+            "INSERT INTO reminders (path, title, kind, at, all_day) VALUES (?, ?, ?, ?, ?)",
+        )?;
+        statement.bind((1, reminder.path.as_str()))?;
+        statement.bind((2, reminder.title.as_str()))?;
+        statement.bind((3, reminder.kind.as_str()))?;
+        statement.bind((4, reminder.at))?;
+        statement.bind((5, reminder.all_day as i64))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// Indexed reminders from `from` (milliseconds, inclusive) to `until`, earliest first.
+pub fn stored(
+    connection: &Connection,
+    from: Option<i64>,
+    until: i64,
+) -> Result<Vec<Reminder>, Box<dyn Error>> {
+    let mut statement = connection.prepare(
+        "SELECT path, title, kind, at, all_day FROM reminders
+         WHERE (?1 IS NULL OR at >= ?1) AND at < ?2
+         ORDER BY at, path",
+    )?;
+    statement.bind((1, from))?;
+    statement.bind((2, until))?;
+    let mut reminders = Vec::new();
+    while let State::Row = statement.next()? {
+        reminders.push(Reminder {
+            path: statement.read(0)?,
+            title: statement.read(1)?,
+            kind: statement.read(2)?,
+            at: statement.read(3)?,
+            all_day: statement.read::<i64, _>(4)? != 0,
+        });
+    }
+    Ok(reminders)
+}
+
+/// When the notification for `reminder` goes out: all-day reminders at `settings.time`.
+pub fn fire_time(reminder: &Reminder, settings: &RemindersConfig) -> Timestamp {
+    let at = Timestamp::from_millisecond(reminder.at).unwrap_or_default();
+    if !reminder.all_day {
+        return at;
+    }
+    let time = settings
+        .time
+        .parse::<civil::Time>()
+        .inspect_err(|e| log::warn!("Invalid [reminders] time '{}': {}", settings.time, e))
+        .unwrap_or_default();
+    at.to_zoned(TimeZone::system())
+        .date()
+        .to_datetime(time)
+        .to_zoned(TimeZone::system())
+        .map_or(at, |zoned| zoned.timestamp())
+}
+
+fn was_sent(connection: &Connection, reminder: &Reminder) -> Result<bool, sqlite::Error> {
+    let mut statement = connection
+        .prepare("SELECT 1 FROM reminders_sent WHERE path = ? AND kind = ? AND at = ?")?;
+    statement.bind((1, reminder.path.as_str()))?;
+    statement.bind((2, reminder.kind.as_str()))?;
+    statement.bind((3, reminder.at))?;
+    Ok(matches!(statement.next()?, State::Row))
+}
+
+fn mark_sent(connection: &Connection, reminder: &Reminder) -> Result<(), sqlite::Error> {
+    let mut statement = connection
+        .prepare("INSERT OR IGNORE INTO reminders_sent (path, kind, at) VALUES (?, ?, ?)")?;
+    statement.bind((1, reminder.path.as_str()))?;
+    statement.bind((2, reminder.kind.as_str()))?;
+    statement.bind((3, reminder.at))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Forgets reminders sent before `before` (milliseconds): they are out of reach of
+/// [`notify_due`] and can never be sent again.
+fn forget_sent(connection: &Connection, before: i64) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare("DELETE FROM reminders_sent WHERE at < ?")?;
+    statement.bind((1, before))?;
+    statement.next()?;
+    Ok(())
+}
+
+fn notification(reminder: &Reminder, settings: &RemindersConfig) -> Notification {
+    let when = fire_time(reminder, settings).to_zoned(TimeZone::system());
+    let title = match reminder.kind.as_str() {
+        "due" => format!("Due: {}", reminder.title),
+        _ => format!("Reminder: {}", reminder.title),
+    };
+    Notification {
+        event: "reminder",
+        title,
+        message: format!("{} ({})", reminder.path, when.strftime("%a %Y-%m-%d %H:%M")),
+    }
+}
+
+/// Sends the reminders that have come due and were not sent yet, returning when the
+/// next one is due.
+fn notify_due(config: &AppConfig, now: Timestamp) -> Result<Option<Timestamp>, Box<dyn Error>> {
+    let connection = index::open(config)?;
+    // All-day reminders are stored at midnight but fire later in the day
+    let from = (now - MISSED_WINDOW - SignedDuration::from_hours(24)).as_millisecond();
+    let until = (now + SignedDuration::from_hours(48)).as_millisecond();
+    forget_sent(&connection, from)?;
+    let mut next: Option<Timestamp> = None;
+    for reminder in stored(&connection, Some(from), until)? {
+        if !config.reminders.due && reminder.kind == "due" {
+            continue;
+        }
+        let fires = fire_time(&reminder, &config.reminders);
+        if fires > now {
+            next = Some(next.map_or(fires, |next| next.min(fires)));
+        } else if now.duration_since(fires) <= MISSED_WINDOW && !was_sent(&connection, &reminder)? {
+            notify::send(&config.notify, &notification(&reminder, &config.reminders));
+            mark_sent(&connection, &reminder)?;
+        }
+    }
+    Ok(next)
+}
+
+/// Starts notifying about reminders on a background thread, if notifications are set up.
+pub fn spawn_notifier(config: &AppConfig) -> Option<thread::JoinHandle<()>> {
+    if !config.notify.is_enabled() {
+        return None;
+    }
+    let config = config.clone();
+    Some(thread::spawn(move || {
+        loop {
+            let now = Timestamp::now();
+            let next = notify_due(&config, now)
+                .inspect_err(|e| log::error!("Checking reminders failed: {}", e))
+                .ok()
+                .flatten();
+            let wait = next
+                .and_then(|next| Duration::try_from(next.duration_since(now)).ok())
+                .map_or(MAX_SLEEP, |wait| wait.min(MAX_SLEEP));
+            thread::sleep(wait);
+        }
+    }))
+}
+
+/// Lists the reminders of the next `args.days` days, refreshing the index first.
+pub fn run_agenda(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &AgendaArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    let today = Zoned::now().date();
+    let start = today.to_zoned(TimeZone::system())?.timestamp();
+    let days = Span::new()
+        .try_days(i64::from(args.days))
+        .map_err(|_| "--days is out of range")?;
+    let end = today
+        .checked_add(days)?
+        .to_zoned(TimeZone::system())?
+        .timestamp();
+    let from = (!args.overdue).then(|| start.as_millisecond());
+    let reminders = stored(&connection, from, end.as_millisecond())?;
+
+    match args.format {
+        OutputFormat::Text => {
+            for reminder in &reminders {
+                let at = Timestamp::from_millisecond(reminder.at)?.to_zoned(TimeZone::system());
+                let when = match reminder.all_day {
+                    true => at.strftime("%a %Y-%m-%d      ").to_string(),
+                    false => at.strftime("%a %Y-%m-%d %H:%M").to_string(),
+                };
+                println!(
+                    "{}  {:<8}  {}  ({})",
+                    when, reminder.kind, reminder.title, reminder.path
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&reminders)?),
+        OutputFormat::Ndjson => {
+            for reminder in &reminders {
+                util::print_ndjson(reminder)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_note_reminders() {
+        let note = Note::from_content(
+            PathBuf::from("Taxes.md"),
+            String::from(
+                "---\nreminder: 2024-04-01T09:30:00Z\ndue: 2024-04-15\nstatus: todo\n---\n",
+            ),
+        );
+        let reminders = note_reminders(&note);
+        let found: Vec<(&str, bool)> = reminders
+            .iter()
+            .map(|reminder| (reminder.kind.as_str(), reminder.all_day))
+            .collect();
+        assert_eq!(found, vec![("reminder", false), ("due", true)]);
+        assert_eq!(
+            reminders[0].at,
+            "2024-04-01T09:30:00Z"
+                .parse::<Timestamp>()
+                .unwrap()
+                .as_millisecond()
+        );
+
+        let settings = RemindersConfig {
+            time: String::from("08:15"),
+            ..RemindersConfig::default()
+        };
+        let due = fire_time(&reminders[1], &settings).to_zoned(TimeZone::system());
+        assert_eq!(due.datetime(), civil::date(2024, 4, 15).at(8, 15, 0, 0));
+        assert_eq!(
+            fire_time(&reminders[0], &settings).as_millisecond(),
+            reminders[0].at
+        );
+
+        let undated = Note::from_content(
+            PathBuf::from("Later.md"),
+            String::from("---\ndue: someday\n---\n"),
+        );
+        assert!(note_reminders(&undated).is_empty());
+    }
+
+    #[test]
+    fn test_forget_sent() {
+        let connection = sqlite::open(":memory:").unwrap();
+        index::ensure_schema(&connection).unwrap();
+        let reminder = |at| Reminder {
+            path: String::from("A.md"),
+            title: String::from("A"),
+            kind: String::from("reminder"),
+            at,
+            all_day: false,
+        };
+        mark_sent(&connection, &reminder(1_000)).unwrap();
+        mark_sent(&connection, &reminder(5_000)).unwrap();
+        forget_sent(&connection, 2_000).unwrap();
+        assert!(!was_sent(&connection, &reminder(1_000)).unwrap());
+        assert!(was_sent(&connection, &reminder(5_000)).unwrap());
+    }
+}
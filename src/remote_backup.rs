@@ -0,0 +1,608 @@
+//! Uploads of the vault to a remote store (`[backup.remote]`) for `backup` jobs. Each file
+//! is stored once, under `objects/<SHA-256 of its content>`, so a backup only uploads the
+//! files whose content the store does not have yet; `snapshots/<time>.json` then records
+//! which object every path had. The hashes already uploaded are remembered in the cache,
+//! and forgotten when the store no longer has the last snapshot. With `[backup]
+//! recipient` set, objects and snapshots are encrypted with age first, and objects are
+//! named by an HMAC under a secret kept in the cache, so their names say nothing about
+//! their content.
+
+use crate::backup;
+use crate::config::{AppConfig, RemoteBackupConfig, RemoteKind};
+use crate::index;
+use crate::util;
+
+use hmac::{Hmac, Mac};
+use jiff::Zoned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlite::{Connection, State};
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fmt, fs,
+    path::Path,
+};
+
+/// A place backups are written to
+pub trait Store {
+    /// Writes `body` at `key`, a path under the backup folder.
+    fn put(&self, key: &str, body: &[u8]) -> Result<(), Box<dyn Error>>;
+
+    /// Whether something is stored at `key`.
+    fn exists(&self, key: &str) -> Result<bool, Box<dyn Error>>;
+}
+
+/// Which object each file had at one backup
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Manifest {
+    pub time: String,
+    /// Vault-relative path -> content hash
+    pub files: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Uploaded {
+    pub files: usize,
+    /// Files whose content was new to the store
+    pub objects: usize,
+    pub snapshot: String,
+}
+
+impl fmt::Display for Uploaded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "uploaded {} of {} file(s) and {}",
+            self.objects, self.files, self.snapshot
+        )
+    }
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS backup_objects (
+            target TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            PRIMARY KEY (target, hash)
+        );
+        CREATE TABLE IF NOT EXISTS backup_snapshots (
+            target TEXT PRIMARY KEY,
+            snapshot TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS backup_secrets (
+            target TEXT PRIMARY KEY,
+            secret TEXT NOT NULL
+        );",
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The name of the object holding `content`: its SHA-256, or its HMAC-SHA-256 under
+/// `secret` when objects are encrypted.
+fn object_hash(content: &[u8], secret: Option<&str>) -> String {
+    match secret {
+        Some(secret) => hex(&hmac_sha256(secret.as_bytes(), content)),
+        None => hex(&Sha256::digest(content)),
+    }
+}
+
+/// The secret that names `target`'s objects, made the first time it is needed.
+fn secret(connection: &Connection, target: &str) -> Result<String, Box<dyn Error>> {
+    let mut statement = connection.prepare("SELECT secret FROM backup_secrets WHERE target = ?")?;
+    statement.bind((1, target))?;
+    if let State::Row = statement.next()? {
+        return Ok(statement.read::<String, _>(0)?);
+    }
+    let secret = backup::random_secret()?;
+    let mut statement =
+        connection.prepare("INSERT INTO backup_secrets (target, secret) VALUES (?, ?)")?;
+    statement.bind((1, target))?;
+    statement.bind((2, secret.as_str()))?;
+    statement.next()?;
+    Ok(secret)
+}
+
+fn last_snapshot(connection: &Connection, target: &str) -> Result<Option<String>, sqlite::Error> {
+    let mut statement =
+        connection.prepare("SELECT snapshot FROM backup_snapshots WHERE target = ?")?;
+    statement.bind((1, target))?;
+    match statement.next()? {
+        State::Row => Ok(Some(statement.read::<String, _>(0)?)),
+        State::Done => Ok(None),
+    }
+}
+
+fn set_last_snapshot(
+    connection: &Connection,
+    target: &str,
+    snapshot: &str,
+) -> Result<(), sqlite::Error> {
+    let mut statement = connection
+        .prepare("INSERT OR REPLACE INTO backup_snapshots (target, snapshot) VALUES (?, ?)")?;
+    statement.bind((1, target))?;
+    statement.bind((2, snapshot))?;
+    statement.next()?;
+    Ok(())
+}
+
+fn forget(connection: &Connection, target: &str) -> Result<(), sqlite::Error> {
+    for table in ["backup_objects", "backup_snapshots"] {
+        let mut statement =
+            connection.prepare(format!("DELETE FROM {} WHERE target = ?", table))?;
+        statement.bind((1, target))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+fn uploaded_hashes(
+    connection: &Connection,
+    target: &str,
+) -> Result<HashSet<String>, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT hash FROM backup_objects WHERE target = ?")?;
+    statement.bind((1, target))?;
+    let mut hashes = HashSet::new();
+    while let State::Row = statement.next()? {
+        hashes.insert(statement.read::<String, _>(0)?);
+    }
+    Ok(hashes)
+}
+
+fn mark_uploaded(connection: &Connection, target: &str, hash: &str) -> Result<(), sqlite::Error> {
+    let mut statement =
+        connection.prepare("INSERT OR IGNORE INTO backup_objects (target, hash) VALUES (?, ?)")?;
+    statement.bind((1, target))?;
+    statement.bind((2, hash))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// `plain`, encrypted when there is a recipient.
+fn seal(plain: Vec<u8>, recipient: Option<&str>) -> Result<Vec<u8>, Box<dyn Error>> {
+    match recipient {
+        Some(recipient) => backup::encrypt(&plain, recipient),
+        None => Ok(plain),
+    }
+}
+
+/// The hashes the cache says `store` has, or none when the store lost what the last
+/// backup left there, e.g. because it was wiped or replaced.
+fn known_hashes(
+    store: &dyn Store,
+    connection: &Connection,
+    target: &str,
+) -> Result<HashSet<String>, Box<dyn Error>> {
+    let hashes = uploaded_hashes(connection, target)?;
+    // Without a snapshot, an earlier backup was interrupted after some objects
+    let probe = match last_snapshot(connection, target)? {
+        Some(snapshot) => snapshot,
+        None => match hashes.iter().next() {
+            Some(hash) => format!("objects/{}", hash),
+            None => return Ok(hashes),
+        },
+    };
+    if store.exists(&probe)? {
+        return Ok(hashes);
+    }
+    log::warn!("{} lacks {}; uploading every file again", target, probe);
+    forget(connection, target)?;
+    Ok(HashSet::new())
+}
+
+/// Uploads the files of the vault `store` lacks and a snapshot named `stamp`. `target`
+/// identifies the store in the cache's record of uploaded hashes.
+pub fn upload(
+    store: &dyn Store,
+    connection: &Connection,
+    target: &str,
+    vault_path: &Path,
+    recipient: Option<&str>,
+    stamp: &str,
+) -> Result<Uploaded, Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let secret = match recipient {
+        Some(_) => Some(secret(connection, target)?),
+        None => None,
+    };
+    let mut known = known_hashes(store, connection, target)?;
+    let mut manifest = Manifest {
+        time: stamp.to_string(),
+        files: BTreeMap::new(),
+    };
+    let mut objects = 0;
+    for file in backup::vault_files(vault_path)? {
+        let rel_path = util::get_relative_path(&file, vault_path)?;
+        let content = fs::read(&file)?;
+        let hash = object_hash(&content, secret.as_deref());
+        if !known.contains(&hash) {
+            store.put(&format!("objects/{}", hash), &seal(content, recipient)?)?;
+            // Recorded one by one, so an interrupted backup resumes where it stopped
+            mark_uploaded(connection, target, &hash)?;
+            known.insert(hash.clone());
+            objects += 1;
+        }
+        manifest
+            .files
+            .insert(rel_path.to_string_lossy().replace('\\', "/"), hash);
+    }
+    let snapshot = format!("snapshots/{}.json", stamp);
+    let body = seal(serde_json::to_vec_pretty(&manifest)?, recipient)?;
+    store.put(&snapshot, &body)?;
+    set_last_snapshot(connection, target, &snapshot)?;
+    Ok(Uploaded {
+        files: manifest.files.len(),
+        objects,
+        snapshot,
+    })
+}
+
+#[cfg(feature = "backup-webdav")]
+mod webdav {
+    use super::Store;
+    use crate::config::RemoteBackupConfig;
+
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use std::{env, error::Error, time::Duration};
+
+    pub struct WebDav {
+        agent: ureq::Agent,
+        /// URL of the backup folder, without a trailing slash
+        base: String,
+        authorization: Option<String>,
+    }
+
+    impl WebDav {
+        pub fn new(settings: &RemoteBackupConfig, prefix: &str) -> Result<WebDav, Box<dyn Error>> {
+            let authorization = match &settings.username {
+                Some(username) => {
+                    let password = env::var(&settings.password_env).map_err(|_| {
+                        format!("Set {} to the WebDAV password", settings.password_env)
+                    })?;
+                    let credentials = format!("{}:{}", username, password);
+                    Some(format!("Basic {}", STANDARD.encode(credentials)))
+                }
+                None => None,
+            };
+            let dav = WebDav {
+                agent: ureq::AgentBuilder::new()
+                    .timeout(Duration::from_secs(300))
+                    .build(),
+                base: format!("{}/{}", settings.url.trim_end_matches('/'), prefix),
+                authorization,
+            };
+            dav.make_folders(settings.url.trim_end_matches('/'), prefix)?;
+            Ok(dav)
+        }
+
+        fn request(&self, method: &str, url: &str) -> ureq::Request {
+            let request = self.agent.request(method, url);
+            match &self.authorization {
+                Some(authorization) => request.set("Authorization", authorization),
+                None => request,
+            }
+        }
+
+        /// Creates the backup folder and its `objects` and `snapshots` folders.
+        fn make_folders(&self, url: &str, prefix: &str) -> Result<(), Box<dyn Error>> {
+            let mut folders = Vec::new();
+            let mut folder = url.to_string();
+            for name in prefix.split('/') {
+                folder = format!("{}/{}", folder, name);
+                folders.push(folder.clone());
+            }
+            folders.push(format!("{}/objects", self.base));
+            folders.push(format!("{}/snapshots", self.base));
+            for url in folders {
+                match self.request("MKCOL", &url).call() {
+                    // 405: the folder is already there
+                    Ok(_) | Err(ureq::Error::Status(405, _)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Store for WebDav {
+        fn put(&self, key: &str, body: &[u8]) -> Result<(), Box<dyn Error>> {
+            self.request("PUT", &format!("{}/{}", self.base, key))
+                .send_bytes(body)?;
+            Ok(())
+        }
+
+        fn exists(&self, key: &str) -> Result<bool, Box<dyn Error>> {
+            match self
+                .request("HEAD", &format!("{}/{}", self.base, key))
+                .call()
+            {
+                Ok(_) => Ok(true),
+                Err(ureq::Error::Status(404, _)) => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "backup-s3")]
+mod s3 {
+    use super::{Store, hex};
+    use crate::config::RemoteBackupConfig;
+
+    use jiff::Timestamp;
+    use sha2::{Digest, Sha256};
+    use std::{env, error::Error, time::Duration};
+    use url::Url;
+
+    pub struct S3 {
+        agent: ureq::Agent,
+        /// Endpoint with the bucket, path style
+        endpoint: Url,
+        prefix: String,
+        region: String,
+        access_key: String,
+        secret_key: String,
+    }
+
+    fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+        super::hmac_sha256(key, data.as_bytes())
+    }
+
+    /// Percent-encodes everything but unreserved characters and `/`, as SigV4 expects.
+    fn uri_encode(path: &str) -> String {
+        path.bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect()
+    }
+
+    impl S3 {
+        pub fn new(settings: &RemoteBackupConfig, prefix: &str) -> Result<S3, Box<dyn Error>> {
+            let key =
+                |var: &str| env::var(var).map_err(|_| format!("Set {} for the S3 store", var));
+            Ok(S3 {
+                agent: ureq::AgentBuilder::new()
+                    .timeout(Duration::from_secs(300))
+                    .build(),
+                endpoint: Url::parse(&settings.url)?,
+                prefix: prefix.to_string(),
+                region: settings.region.clone(),
+                access_key: key(&settings.access_key_env)?,
+                secret_key: key(&settings.secret_key_env)?,
+            })
+        }
+    }
+
+    impl S3 {
+        /// A `method` request for `key`, signed with AWS Signature Version 4.
+        fn signed(
+            &self,
+            method: &str,
+            key: &str,
+            body: &[u8],
+        ) -> Result<ureq::Request, Box<dyn Error>> {
+            let host = match (self.endpoint.host_str(), self.endpoint.port()) {
+                (Some(host), Some(port)) => format!("{}:{}", host, port),
+                (Some(host), None) => host.to_string(),
+                (None, _) => return Err("The S3 URL has no host".into()),
+            };
+            let path = format!(
+                "{}/{}",
+                self.endpoint.path().trim_end_matches('/'),
+                uri_encode(&format!("{}/{}", self.prefix, key))
+            );
+            let now = Timestamp::now();
+            let amz_date = now.strftime("%Y%m%dT%H%M%SZ").to_string();
+            let day = now.strftime("%Y%m%d").to_string();
+            let payload_hash = hex(&Sha256::digest(body));
+
+            let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+            let canonical_request = format!(
+                "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+                method, path, host, payload_hash, amz_date, signed_headers, payload_hash
+            );
+            let scope = format!("{}/{}/s3/aws4_request", day, self.region);
+            let string_to_sign = format!(
+                "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                amz_date,
+                scope,
+                hex(&Sha256::digest(canonical_request.as_bytes()))
+            );
+            let signing_key = ["s3", "aws4_request"].iter().fold(
+                hmac_sha256(
+                    &hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), &day),
+                    &self.region,
+                ),
+                |key, part| hmac_sha256(&key, part),
+            );
+            let authorization = format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key,
+                scope,
+                signed_headers,
+                hex(&hmac_sha256(&signing_key, &string_to_sign))
+            );
+
+            let mut url = self.endpoint.clone();
+            url.set_path(&path);
+            Ok(self
+                .agent
+                .request(method, url.as_str())
+                .set("x-amz-date", &amz_date)
+                .set("x-amz-content-sha256", &payload_hash)
+                .set("Authorization", &authorization))
+        }
+    }
+
+    impl Store for S3 {
+        fn put(&self, key: &str, body: &[u8]) -> Result<(), Box<dyn Error>> {
+            self.signed("PUT", key, body)?.send_bytes(body)?;
+            Ok(())
+        }
+
+        fn exists(&self, key: &str) -> Result<bool, Box<dyn Error>> {
+            match self.signed("HEAD", key, b"")?.call() {
+                Ok(_) => Ok(true),
+                Err(ureq::Error::Status(404, _)) => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        }
+    }
+}
+
+/// The store `settings` describes, backups going under `prefix`.
+#[cfg_attr(
+    not(any(feature = "backup-s3", feature = "backup-webdav")),
+    allow(unused_variables)
+)]
+fn open_store(
+    settings: &RemoteBackupConfig,
+    prefix: &str,
+) -> Result<Box<dyn Store>, Box<dyn Error>> {
+    match settings.kind {
+        #[cfg(feature = "backup-s3")]
+        RemoteKind::S3 => Ok(Box::new(s3::S3::new(settings, prefix)?)),
+        #[cfg(not(feature = "backup-s3"))]
+        RemoteKind::S3 => Err(
+            "[backup.remote] is an S3 store, but this build lacks the `backup-s3` feature".into(),
+        ),
+        #[cfg(feature = "backup-webdav")]
+        RemoteKind::Webdav => Ok(Box::new(webdav::WebDav::new(settings, prefix)?)),
+        #[cfg(not(feature = "backup-webdav"))]
+        RemoteKind::Webdav => Err(
+            "[backup.remote] is a WebDAV folder, but this build lacks the `backup-webdav` feature"
+                .into(),
+        ),
+    }
+}
+
+/// Uploads the vault to `[backup.remote]`.
+pub fn run_upload(
+    vault_path: &Path,
+    config: &AppConfig,
+    settings: &RemoteBackupConfig,
+) -> Result<Uploaded, Box<dyn Error>> {
+    let prefix = match &settings.prefix {
+        Some(prefix) => prefix.trim_matches('/').to_string(),
+        None => vault_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("vault")),
+    };
+    let store = open_store(settings, &prefix)?;
+    let recipient = config.backup.recipient.as_deref();
+    // Objects sealed for another recipient do not count as uploaded
+    let target = format!(
+        "{}/{}#{}",
+        settings.url.trim_end_matches('/'),
+        prefix,
+        recipient.unwrap_or_default()
+    );
+    let connection = index::open(config)?;
+    let stamp = Zoned::now().strftime("%Y%m%dT%H%M%S").to_string();
+    let uploaded = upload(
+        store.as_ref(),
+        &connection,
+        &target,
+        vault_path,
+        recipient,
+        &stamp,
+    )?;
+    log::info!("Backup of {}: {}", target, uploaded);
+    Ok(uploaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct MemoryStore {
+        stored: RefCell<HashSet<String>>,
+        /// Keys written since the last `take`
+        written: RefCell<Vec<String>>,
+    }
+
+    impl MemoryStore {
+        fn take(&self) -> Vec<String> {
+            self.written.take()
+        }
+    }
+
+    impl Store for MemoryStore {
+        fn put(&self, key: &str, _body: &[u8]) -> Result<(), Box<dyn Error>> {
+            self.stored.borrow_mut().insert(key.to_string());
+            self.written.borrow_mut().push(key.to_string());
+            Ok(())
+        }
+
+        fn exists(&self, key: &str) -> Result<bool, Box<dyn Error>> {
+            Ok(self.stored.borrow().contains(key))
+        }
+    }
+
+    #[test]
+    fn test_upload_is_incremental() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("a.md"), "same").unwrap();
+        fs::write(vault.path().join("b.md"), "same").unwrap();
+        fs::write(vault.path().join("c.md"), "other").unwrap();
+        let connection = sqlite::open(":memory:").unwrap();
+
+        let store = MemoryStore::default();
+        let first = upload(&store, &connection, "t", vault.path(), None, "1").unwrap();
+        assert_eq!((first.files, first.objects), (3, 2));
+        assert_eq!(store.take().last().unwrap(), "snapshots/1.json");
+
+        fs::write(vault.path().join("c.md"), "changed").unwrap();
+        let second = upload(&store, &connection, "t", vault.path(), None, "2").unwrap();
+        assert_eq!((second.files, second.objects), (3, 1));
+        assert_eq!(
+            store.take(),
+            vec![
+                String::from(
+                    "objects/d67e2e944994496c8d8ec76eed0cf9f09679448d584b532bebf941852a37f5ed"
+                ),
+                String::from("snapshots/2.json"),
+            ]
+        );
+
+        // Another store starts from scratch
+        let third = upload(&store, &connection, "u", vault.path(), None, "3").unwrap();
+        assert_eq!(third.objects, 2);
+    }
+
+    #[test]
+    fn test_wiped_store_is_uploaded_again() {
+        let vault = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("a.md"), "a").unwrap();
+        let connection = sqlite::open(":memory:").unwrap();
+        upload(
+            &MemoryStore::default(),
+            &connection,
+            "t",
+            vault.path(),
+            None,
+            "1",
+        )
+        .unwrap();
+
+        let wiped = MemoryStore::default();
+        let again = upload(&wiped, &connection, "t", vault.path(), None, "2").unwrap();
+        assert_eq!(again.objects, 1);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::data::{self, Note};
+use crate::frontmatter;
+use crate::markdown::{self, LinkStyle};
+use crate::resolver::Resolver;
+use crate::util;
+
+use pulldown_cmark::{Event, Options, Parser, Tag, html};
+use std::path::Path;
+
+/// Rewrites internal links (wikilinks included) into plain Markdown links pointing at
+/// `href(target)`, so a CommonMark renderer can handle them. Links that do not resolve,
+/// or for which `href` returns `None`, become text.
+pub fn expand_links(
+    note: &Note,
+    resolver: &Resolver,
+    href: impl Fn(&Path) -> Option<String>,
+) -> String {
+    let edits = markdown::parse_links(&note.content)
+        .into_iter()
+        .filter(|link| !link.is_external())
+        .map(|link| {
+            let text = link
+                .text
+                .clone()
+                .filter(|text| !text.is_empty())
+                .unwrap_or_else(|| match link.style {
+                    LinkStyle::Wiki => link.target.clone(),
+                    LinkStyle::Markdown => String::new(),
+                });
+            let url = resolver.resolve(&link.target, &note.path).and_then(&href);
+            let replacement = match url {
+                Some(mut url) => {
+                    if let Some(anchor) = &link.anchor {
+                        url.push('#');
+                        url.push_str(&util::percent_encode(&util::percent_decode(anchor)));
+                    }
+                    let bang = if link.embed { "!" } else { "" };
+                    format!("{}[{}](<{}>)", bang, text, url)
+                }
+                None if link.target.is_empty() => note.content[link.span.clone()].to_string(),
+                None => text,
+            };
+            (link.span.clone(), replacement)
+        })
+        .collect();
+    markdown::replace_spans(&note.content, edits)
+}
+
+/// Absolute URL of `path` on a site rooted at `base_url`; notes are published as `.html`.
+pub fn site_url(base_url: &str, path: &Path) -> String {
+    let mut path = path.to_path_buf();
+    if data::is_note(&path) {
+        path.set_extension("html");
+    }
+    format!(
+        "{}/{}",
+        base_url.trim_end_matches('/'),
+        util::percent_encode(&path.to_string_lossy().replace('\\', "/"))
+    )
+}
+
+/// The GitHub-style Markdown extensions Obsidian supports.
+pub fn markdown_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES
+}
+
+/// Renders Markdown to HTML with the extensions Obsidian supports.
+pub fn to_html(markdown: &str) -> String {
+    let mut output = String::new();
+    html::push_html(&mut output, Parser::new_ext(markdown, markdown_options()));
+    output
+}
+
+/// Whether `url` is relative or uses a scheme that cannot run script.
+fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .chars()
+        .filter(|ch| !ch.is_whitespace() && !ch.is_control())
+        .collect();
+    match url.find(':') {
+        Some(colon) if !url[..colon].contains(['/', '?', '#']) => {
+            let scheme = url[..colon].to_ascii_lowercase();
+            ["http", "https", "mailto"].contains(&scheme.as_str())
+        }
+        _ => true,
+    }
+}
+
+/// Renders like [`to_html`] for pages that must not run anything a note contains: raw
+/// HTML is shown as text, and links and images with schemes other than http(s) and
+/// mailto lose their URL.
+pub fn to_safe_html(markdown: &str) -> String {
+    let events = Parser::new_ext(markdown, markdown_options()).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: "#".into(),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: "".into(),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    output
+}
+
+/// HTML for the body of `note`, front matter excluded.
+pub fn note_html(
+    note: &Note,
+    resolver: &Resolver,
+    href: impl Fn(&Path) -> Option<String>,
+) -> String {
+    let expanded = expand_links(note, resolver, href);
+    to_html(frontmatter::split(&expanded).1)
+}
+
+/// [`note_html`] through [`to_safe_html`], for the browser UI and other clients that
+/// insert it into a page.
+pub fn safe_note_html(
+    note: &Note,
+    resolver: &Resolver,
+    href: impl Fn(&Path) -> Option<String>,
+) -> String {
+    let expanded = expand_links(note, resolver, href);
+    to_safe_html(frontmatter::split(&expanded).1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_note_html() {
+        let resolver = Resolver::new(vec![
+            PathBuf::from("Post.md"),
+            PathBuf::from("blog/Other Post.md"),
+            PathBuf::from("img/pic.png"),
+        ]);
+        let note = Note::from_content(
+            PathBuf::from("Post.md"),
+            String::from(
+                "---\ntags: [blog]\n---\nSee [[Other Post#Intro|this]], [[Missing]] and ![[pic.png]].\n",
+            ),
+        );
+        let href = |path: &Path| {
+            Some(format!(
+                "/{}",
+                util::percent_encode(&path.to_string_lossy())
+            ))
+        };
+        assert_eq!(
+            note_html(&note, &resolver, href),
+            "<p>See <a href=\"/blog/Other%20Post.md#Intro\">this</a>, Missing and <img src=\"/img/pic.png\" alt=\"pic.png\" />.</p>\n"
+        );
+    }
+
+    #[test]
+    fn test_safe_html() {
+        let markdown = "<script>steal()</script>\n\nHi <img src=x onerror=steal()> \
+                        [a](javascript:steal()) [b]( JavaScript:x) [c](https://example.com) \
+                        [d](notes/a.md) ![e](data:text/html,x)\n";
+        let html = to_safe_html(markdown);
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("<img src=x"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.to_lowercase().contains("javascript:"));
+        assert!(!html.contains("data:"));
+        assert!(html.contains("href=\"https://example.com\""));
+        assert!(html.contains("href=\"notes/a.md\""));
+    }
+}
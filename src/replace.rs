@@ -0,0 +1,89 @@
+//! Regex find-and-replace across the vault, optionally limited to the notes a query
+//! matches. Rewrites go through a change set, so they can be previewed and undone.
+
+use crate::changeset::ChangeSet;
+use crate::cli::ReplaceArgs;
+use crate::data;
+use crate::query::Query;
+
+use regex::{Regex, RegexBuilder};
+use std::{error::Error, path::Path};
+
+/// A match in a note, as shown in the preview
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+/// `content` with every match of `regex` replaced, and the matches replaced.
+pub fn replace_all(content: &str, regex: &Regex, with: &str) -> (String, Vec<Replacement>) {
+    let replacements = regex
+        .captures_iter(content)
+        .map(|captures| {
+            let found = captures.get(0).unwrap();
+            let mut after = String::new();
+            captures.expand(with, &mut after);
+            Replacement {
+                line: content[..found.start()].matches('\n').count() + 1,
+                before: found.as_str().to_string(),
+                after,
+            }
+        })
+        .collect();
+    (regex.replace_all(content, with).into_owned(), replacements)
+}
+
+pub fn run_replace(vault_path: &Path, args: &ReplaceArgs) -> Result<usize, Box<dyn Error>> {
+    let regex = RegexBuilder::new(&args.regex)
+        .case_insensitive(args.ignore_case)
+        .multi_line(true)
+        .build()?;
+    let query = args.query.as_deref().map(Query::parse).transpose()?;
+
+    let mut changes = ChangeSet::new();
+    let mut total = 0;
+    for note in data::iter_notes(vault_path)? {
+        if query.as_ref().is_some_and(|query| !query.matches(&note)) {
+            continue;
+        }
+        let (replaced, replacements) = replace_all(&note.content, &regex, &args.with);
+        if replacements.is_empty() {
+            continue;
+        }
+        log::info!("{}: {} match(es)", note.path.display(), replacements.len());
+        for replacement in &replacements {
+            log::debug!(
+                "  {}: {:?} -> {:?}",
+                replacement.line,
+                replacement.before,
+                replacement.after
+            );
+        }
+        total += replacements.len();
+        changes.propose(&note.path, Some(note.content), replaced);
+    }
+    log::info!("{} match(es) in {} note(s)", total, changes.len());
+    changes.finish(vault_path, &args.changes, "replace text", args.backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_all() {
+        let regex = Regex::new(r"foo(\d+)").unwrap();
+        let (replaced, replacements) = replace_all("foo1 and\nfoo22\nbar", &regex, "bar$1");
+        assert_eq!(replaced, "bar1 and\nbar22\nbar");
+        assert_eq!(
+            replacements[1],
+            Replacement {
+                line: 2,
+                before: "foo22".into(),
+                after: "bar22".into()
+            }
+        );
+    }
+}
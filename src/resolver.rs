@@ -0,0 +1,377 @@
+use crate::data::{self, Note};
+use crate::markdown;
+use crate::util;
+
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Component, Path, PathBuf},
+};
+
+/// Resolves link targets to vault files the way Obsidian does: by exact vault path,
+/// path relative to the linking note, or by (case-insensitive) file name.
+#[derive(Debug, Default)]
+pub struct Resolver {
+    /// Vault-relative paths of every file
+    files: Vec<PathBuf>,
+    /// Lowercased file name -> indices into `files`
+    by_name: HashMap<String, Vec<usize>>,
+}
+
+fn lowercase(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/").to_lowercase()
+}
+
+/// Collapses `.` and `..` components without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(part) => normalized.push(part),
+            _ => {}
+        }
+    }
+    normalized
+}
+
+impl Resolver {
+    pub fn new(files: Vec<PathBuf>) -> Self {
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, file) in files.iter().enumerate() {
+            if let Some(name) = file.file_name() {
+                by_name
+                    .entry(name.to_string_lossy().to_lowercase())
+                    .or_default()
+                    .push(index);
+            }
+        }
+        Resolver { files, by_name }
+    }
+
+    pub fn from_vault(vault_path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut files = Vec::new();
+        for file in data::traverse_vault(vault_path)? {
+            files.push(util::get_relative_path(&file, vault_path)?);
+        }
+        Ok(Resolver::new(files))
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Resolves `target` (as written in a link inside `source`) to a vault-relative path.
+    pub fn resolve(&self, target: &str, source: &Path) -> Option<&Path> {
+        let target = util::percent_decode(target.trim());
+        if target.is_empty() {
+            return self
+                .files
+                .iter()
+                .find(|f| *f == source)
+                .map(PathBuf::as_path);
+        }
+
+        let mut candidates = Vec::new();
+        if !target.to_lowercase().ends_with(".md") {
+            candidates.push(format!("{}.md", target));
+        }
+        candidates.push(target.clone());
+
+        for candidate in &candidates {
+            let candidate_path = Path::new(candidate.trim_start_matches('/'));
+            let relative_to_source = candidate.starts_with("./") || candidate.starts_with("../");
+            let wanted = if relative_to_source {
+                normalize(
+                    &source
+                        .parent()
+                        .unwrap_or(Path::new(""))
+                        .join(candidate_path),
+                )
+            } else {
+                normalize(candidate_path)
+            };
+            if let Some(found) = self.find_by_suffix(&wanted) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Resolves `title` as a link target, falling back to the one note with that title or
+    /// alias in `titles`.
+    pub fn resolve_title<'a>(&'a self, titles: &'a TitleIndex, title: &str) -> Option<&'a Path> {
+        self.resolve(title, Path::new(""))
+            .or_else(|| match titles.lookup(title) {
+                [only] => Some(only.as_path()),
+                _ => None,
+            })
+    }
+
+    /// Finds the file whose path equals or ends with `wanted`, preferring the shortest path.
+    fn find_by_suffix(&self, wanted: &Path) -> Option<&Path> {
+        let name = wanted.file_name()?.to_string_lossy().to_lowercase();
+        let wanted = lowercase(wanted);
+        let suffix = format!("/{}", wanted);
+        self.by_name
+            .get(&name)?
+            .iter()
+            .map(|&index| &self.files[index])
+            .filter(|file| {
+                let file = lowercase(file);
+                file == wanted || file.ends_with(&suffix)
+            })
+            .min_by_key(|file| (file.components().count(), lowercase(file)))
+            .map(PathBuf::as_path)
+    }
+
+    /// Shortest text that links unambiguously to `file` (Obsidian's default link format).
+    pub fn shortest_link(&self, file: &Path) -> String {
+        let name = file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let unique = self
+            .by_name
+            .get(&name)
+            .is_none_or(|matches| matches.len() <= 1);
+        let text = if unique {
+            file.file_name().map(PathBuf::from).unwrap_or_default()
+        } else {
+            file.to_path_buf()
+        };
+        let text = text.to_string_lossy().replace('\\', "/");
+        match text.strip_suffix(".md") {
+            Some(stripped) => stripped.to_string(),
+            None => text,
+        }
+    }
+}
+
+/// Notes by every title a `[[Title]]` link may mean: front matter title, aliases and file
+/// stem, compared case-insensitively.
+#[derive(Debug, Default)]
+pub struct TitleIndex {
+    by_title: HashMap<String, Vec<PathBuf>>,
+}
+
+impl TitleIndex {
+    pub fn new(notes: &[Note]) -> Self {
+        let mut by_title: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for note in notes {
+            let stem = note
+                .path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned());
+            let titles = note
+                .front_matter
+                .title
+                .iter()
+                .chain(&note.front_matter.aliases)
+                .chain(&stem);
+            for title in titles {
+                let paths = by_title.entry(title.trim().to_lowercase()).or_default();
+                if !paths.contains(&note.path) {
+                    paths.push(note.path.clone());
+                }
+            }
+        }
+        for paths in by_title.values_mut() {
+            paths.sort();
+        }
+        TitleIndex { by_title }
+    }
+
+    /// Notes going by `title`, in path order.
+    pub fn lookup(&self, title: &str) -> &[PathBuf] {
+        self.by_title
+            .get(&title.trim().to_lowercase())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Titles shared by more than one note, which makes `[[Title]]` ambiguous.
+    pub fn duplicates(&self) -> Vec<(&str, &[PathBuf])> {
+        let mut duplicates: Vec<(&str, &[PathBuf])> = self
+            .by_title
+            .iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(title, paths)| (title.as_str(), paths.as_slice()))
+            .collect();
+        duplicates.sort();
+        duplicates
+    }
+}
+
+/// Vault files a note links to or embeds.
+pub fn linked_files(note: &Note, resolver: &Resolver) -> Vec<PathBuf> {
+    markdown::parse_links(&note.content)
+        .iter()
+        .filter(|link| !link.is_external())
+        .filter_map(|link| resolver.resolve(&link.target, &note.path))
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// A note linking to another one
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Backlink {
+    pub path: PathBuf,
+    pub line: usize,
+    /// The linking line, trimmed
+    pub context: String,
+}
+
+/// Every link to `target` from the notes of the vault, in path and line order.
+pub fn backlinks(notes: &[Note], resolver: &Resolver, target: &Path) -> Vec<Backlink> {
+    let mut found = Vec::new();
+    for note in notes {
+        let lines: Vec<&str> = note.content.lines().collect();
+        for link in markdown::parse_links(&note.content) {
+            if link.is_external() || link.target.is_empty() {
+                continue;
+            }
+            if resolver.resolve(&link.target, &note.path) == Some(target) {
+                found.push(Backlink {
+                    path: note.path.clone(),
+                    line: link.line,
+                    context: lines
+                        .get(link.line.saturating_sub(1))
+                        .map(|line| line.trim().to_string())
+                        .unwrap_or_default(),
+                });
+            }
+        }
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path).then(a.line.cmp(&b.line)));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver() -> Resolver {
+        Resolver::new(vec![
+            PathBuf::from("Home.md"),
+            PathBuf::from("projects/Plan.md"),
+            PathBuf::from("archive/Plan.md"),
+            PathBuf::from("archive/old/Plan.md"),
+            PathBuf::from("assets/diagram.png"),
+            PathBuf::from("people/Jane Doe.md"),
+        ])
+    }
+
+    #[test]
+    fn test_resolve_by_name_and_path() {
+        let r = resolver();
+        let source = Path::new("Home.md");
+        assert_eq!(r.resolve("home", source), Some(Path::new("Home.md")));
+        assert_eq!(
+            r.resolve("Jane%20Doe.md", source),
+            Some(Path::new("people/Jane Doe.md"))
+        );
+        assert_eq!(
+            r.resolve("diagram.png", source),
+            Some(Path::new("assets/diagram.png"))
+        );
+        assert_eq!(
+            r.resolve("old/Plan", source),
+            Some(Path::new("archive/old/Plan.md"))
+        );
+        assert_eq!(r.resolve("Missing", source), None);
+    }
+
+    #[test]
+    fn test_resolve_relative_and_ambiguous() {
+        let r = resolver();
+        let source = Path::new("projects/Plan.md");
+        assert_eq!(r.resolve("../Home.md", source), Some(Path::new("Home.md")));
+        assert_eq!(r.resolve("", source), Some(source));
+        // Ambiguous names pick the shortest path
+        assert_eq!(
+            r.resolve("Plan", Path::new("Home.md")),
+            Some(Path::new("archive/Plan.md"))
+        );
+    }
+
+    #[test]
+    fn test_shortest_link() {
+        let r = resolver();
+        assert_eq!(r.shortest_link(Path::new("people/Jane Doe.md")), "Jane Doe");
+        assert_eq!(
+            r.shortest_link(Path::new("projects/Plan.md")),
+            "projects/Plan"
+        );
+        assert_eq!(
+            r.shortest_link(Path::new("assets/diagram.png")),
+            "diagram.png"
+        );
+    }
+
+    #[test]
+    fn test_backlinks() {
+        let resolver = resolver();
+        let notes = vec![
+            Note::from_content(
+                PathBuf::from("Home.md"),
+                String::from("# Home\nSee [[Jane Doe]] and [[Jane Doe#Bio]].\n"),
+            ),
+            Note::from_content(
+                PathBuf::from("projects/Plan.md"),
+                String::from("Owner: [Jane](../people/Jane%20Doe.md), [x](https://x.org)\n"),
+            ),
+        ];
+        let found = backlinks(&notes, &resolver, Path::new("people/Jane Doe.md"));
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].path, PathBuf::from("Home.md"));
+        assert_eq!(found[0].line, 2);
+        assert_eq!(found[0].context, "See [[Jane Doe]] and [[Jane Doe#Bio]].");
+        assert_eq!(found[2].path, PathBuf::from("projects/Plan.md"));
+    }
+
+    #[test]
+    fn test_title_index() {
+        let notes = vec![
+            Note::from_content(
+                PathBuf::from("people/Jane Doe.md"),
+                String::from("---\ntitle: Jane\naliases: JD\n---\n"),
+            ),
+            Note::from_content(
+                PathBuf::from("Jane.md"),
+                String::from("---\naliases: [Jane Doe, Jane]\n---\n"),
+            ),
+        ];
+        let titles = TitleIndex::new(&notes);
+        assert_eq!(titles.lookup("jd"), [PathBuf::from("people/Jane Doe.md")]);
+        assert_eq!(
+            titles.duplicates(),
+            vec![
+                (
+                    "jane",
+                    &[
+                        PathBuf::from("Jane.md"),
+                        PathBuf::from("people/Jane Doe.md")
+                    ][..]
+                ),
+                (
+                    "jane doe",
+                    &[
+                        PathBuf::from("Jane.md"),
+                        PathBuf::from("people/Jane Doe.md")
+                    ][..]
+                ),
+            ]
+        );
+
+        let resolver = resolver();
+        assert_eq!(
+            resolver.resolve_title(&titles, "JD"),
+            Some(Path::new("people/Jane Doe.md"))
+        );
+        assert_eq!(resolver.resolve_title(&titles, "Jane"), None);
+    }
+}
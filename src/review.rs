@@ -0,0 +1,451 @@
+//! Spaced repetition. Notes tagged `#flashcards` hold cards, either `front::back` on one
+//! line or a front and a back separated by a line with a lone `?`. Each card's SM-2
+//! schedule (interval, ease, due date) is kept in the cache database.
+
+use crate::cli::{OutputFormat, ReviewArgs};
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::index;
+use crate::markdown;
+use crate::query::Query;
+use crate::util;
+
+use jiff::{ToSpan, Zoned, civil};
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+/// Tag marking notes that hold flashcards
+const DECK_TAG: &str = "flashcards";
+/// Ease new cards start with
+const INITIAL_EASE: f64 = 2.5;
+/// Lowest ease SM-2 allows
+const MIN_EASE: f64 = 1.3;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Flashcard {
+    /// Stable id from the note and the front, so cards survive lines moving around them
+    pub id: String,
+    pub path: String,
+    pub line: usize,
+    pub front: String,
+    pub back: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub struct Schedule {
+    /// Days until the next review
+    pub interval: i64,
+    pub ease: f64,
+    /// Reviews in a row graded 3 or better
+    pub repetitions: i64,
+    #[serde(serialize_with = "serialize_date")]
+    pub due: civil::Date,
+}
+
+fn serialize_date<S: serde::Serializer>(
+    date: &civil::Date,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(date)
+}
+
+impl Schedule {
+    /// A card never reviewed, due right away
+    fn new(today: civil::Date) -> Schedule {
+        Schedule {
+            interval: 0,
+            ease: INITIAL_EASE,
+            repetitions: 0,
+            due: today,
+        }
+    }
+
+    /// The schedule after a review graded `quality`, from 0 (blackout) to 5 (perfect).
+    pub fn review(&self, quality: u8, today: civil::Date) -> Schedule {
+        let quality = quality.min(5) as f64;
+        let (repetitions, interval) = match quality < 3.0 {
+            true => (0, 1),
+            false => match self.repetitions {
+                0 => (1, 1),
+                1 => (2, 6),
+                n => (n + 1, (self.interval as f64 * self.ease).round() as i64),
+            },
+        };
+        let miss = 5.0 - quality;
+        let ease = (self.ease + 0.1 - miss * (0.08 + miss * 0.02)).max(MIN_EASE);
+        Schedule {
+            interval,
+            ease,
+            repetitions,
+            due: today.checked_add(interval.days()).unwrap_or(today),
+        }
+    }
+}
+
+/// A card up for review with its schedule
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DueCard {
+    #[serde(flatten)]
+    pub card: Flashcard,
+    #[serde(flatten)]
+    pub schedule: Schedule,
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS reviews (
+            card TEXT PRIMARY KEY,
+            path TEXT NOT NULL,
+            interval INTEGER NOT NULL,
+            ease REAL NOT NULL,
+            repetitions INTEGER NOT NULL,
+            due TEXT NOT NULL
+        );",
+    )
+}
+
+/// Opens the cache database with the reviews table in place.
+pub fn open(config: &AppConfig) -> Result<Connection, Box<dyn Error>> {
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    Ok(connection)
+}
+
+fn today() -> civil::Date {
+    Zoned::now().date()
+}
+
+/// Whether a line split at `::` is a Dataview inline field, `key:: value` on its own or
+/// in `[...]`/`(...)`, rather than a card: the key is a plain field name ending right at
+/// the `::`, and a space follows it. Cards are written `front::back`.
+fn is_inline_field(front: &str, back: &str) -> bool {
+    let key = front
+        .rsplit(['[', '('])
+        .next()
+        .unwrap_or(front)
+        .trim_start_matches(['-', '*', ' '])
+        .trim_end_matches('*');
+    !key.is_empty()
+        && !key.ends_with(' ')
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        && back.starts_with([' ', '\t'])
+}
+
+/// The flashcards in `note`, if it is tagged as a deck.
+pub fn parse_cards(note: &Note) -> Vec<Flashcard> {
+    if !note.has_tag(DECK_TAG) {
+        return Vec::new();
+    }
+    let path = note.path.to_string_lossy().replace('\\', "/");
+    let card = |line: usize, front: &str, back: &str| Flashcard {
+        id: util::content_hash(format!("{}\0{}", path, front).as_bytes()),
+        path: path.clone(),
+        line,
+        front: front.to_string(),
+        back: back.to_string(),
+    };
+
+    let lines = markdown::body_lines(&note.content);
+    let mut cards = Vec::new();
+    for (i, (line_no, _, line)) in lines.iter().enumerate() {
+        if line.trim() == "?" {
+            // The paragraph above is the front, the one below the back
+            let front: Vec<&str> = lines[..i]
+                .iter()
+                .rev()
+                .take_while(|(_, _, line)| !line.trim().is_empty())
+                .map(|(_, _, line)| line.trim())
+                .collect();
+            let back: Vec<&str> = lines[i + 1..]
+                .iter()
+                .take_while(|(_, _, line)| !line.trim().is_empty())
+                .map(|(_, _, line)| line.trim())
+                .collect();
+            if !front.is_empty() && !back.is_empty() {
+                let front: Vec<&str> = front.into_iter().rev().collect();
+                cards.push(card(
+                    line_no - front.len(),
+                    &front.join("\n"),
+                    &back.join("\n"),
+                ));
+            }
+        } else if let Some((front, back)) = line.split_once("::")
+            && !front.trim().is_empty()
+            && !back.trim().is_empty()
+            && !is_inline_field(front, back)
+        {
+            cards.push(card(*line_no, front.trim(), back.trim()));
+        }
+    }
+    cards
+}
+
+fn load_schedules(connection: &Connection) -> Result<HashMap<String, Schedule>, Box<dyn Error>> {
+    let mut statement =
+        connection.prepare("SELECT card, interval, ease, repetitions, due FROM reviews")?;
+    let mut schedules = HashMap::new();
+    while let State::Row = statement.next()? {
+        let due: String = statement.read(4)?;
+        schedules.insert(
+            statement.read::<String, _>(0)?,
+            Schedule {
+                interval: statement.read(1)?,
+                ease: statement.read(2)?,
+                repetitions: statement.read(3)?,
+                due: due.parse()?,
+            },
+        );
+    }
+    Ok(schedules)
+}
+
+fn save(
+    connection: &Connection,
+    card: &Flashcard,
+    schedule: &Schedule,
+) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO reviews (card, path, interval, ease, repetitions, due)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )?;
+    statement.bind((1, card.id.as_str()))?;
+    statement.bind((2, card.path.as_str()))?;
+    statement.bind((3, schedule.interval))?;
+    statement.bind((4, schedule.ease))?;
+    statement.bind((5, schedule.repetitions))?;
+    statement.bind((6, schedule.due.to_string().as_str()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Cards of the notes matching `query` due by `today`, most overdue first, at most `limit`.
+fn due_cards(
+    notes: &[Note],
+    query: &Query,
+    schedules: &HashMap<String, Schedule>,
+    today: civil::Date,
+    limit: usize,
+) -> Vec<DueCard> {
+    let mut due: Vec<DueCard> = query
+        .filter(notes)
+        .into_iter()
+        .flat_map(parse_cards)
+        .filter_map(|card| {
+            let schedule = schedules
+                .get(&card.id)
+                .copied()
+                .unwrap_or(Schedule::new(today));
+            (schedule.due <= today).then_some(DueCard { card, schedule })
+        })
+        .collect();
+    due.sort_by(|a, b| {
+        a.schedule
+            .due
+            .cmp(&b.schedule.due)
+            .then_with(|| a.card.path.cmp(&b.card.path))
+            .then_with(|| a.card.line.cmp(&b.card.line))
+    });
+    due.truncate(limit);
+    due
+}
+
+/// The cards due today among the notes matching `query`.
+pub fn due(
+    vault_path: &Path,
+    config: &AppConfig,
+    query: &Query,
+    limit: usize,
+) -> Result<Vec<DueCard>, Box<dyn Error>> {
+    let connection = open(config)?;
+    let notes = data::load_notes(vault_path)?;
+    Ok(due_cards(
+        &notes,
+        query,
+        &load_schedules(&connection)?,
+        today(),
+        limit,
+    ))
+}
+
+/// Records a review of the card `id` graded `quality` and returns its new schedule.
+pub fn grade(
+    vault_path: &Path,
+    config: &AppConfig,
+    id: &str,
+    quality: u8,
+) -> Result<Schedule, Box<dyn Error>> {
+    if quality > 5 {
+        return Err("Grades go from 0 to 5".into());
+    }
+    let connection = open(config)?;
+    let card = data::load_notes(vault_path)?
+        .iter()
+        .flat_map(parse_cards)
+        .find(|card| card.id == id)
+        .ok_or_else(|| format!("No flashcard with id '{}'", id))?;
+    let today = today();
+    let schedule = load_schedules(&connection)?
+        .get(id)
+        .copied()
+        .unwrap_or(Schedule::new(today))
+        .review(quality, today);
+    save(&connection, &card, &schedule)?;
+    Ok(schedule)
+}
+
+/// Asks about each card in turn on the terminal until the cards run out or the user quits.
+fn session(connection: &Connection, cards: &[DueCard]) -> Result<(), Box<dyn Error>> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock().lines();
+    let mut prompt = |text: &str| -> Result<Option<String>, Box<dyn Error>> {
+        print!("{}", text);
+        io::stdout().flush()?;
+        Ok(input.next().transpose()?)
+    };
+
+    let today = today();
+    let mut reviewed = 0;
+    for (i, due) in cards.iter().enumerate() {
+        println!(
+            "\n[{}/{}] {}\n\n{}\n",
+            i + 1,
+            cards.len(),
+            due.card.path,
+            due.card.front
+        );
+        if prompt("Press Enter to show the answer ")?.is_none() {
+            break;
+        }
+        println!("\n{}\n", due.card.back);
+        let quality = loop {
+            match prompt("Grade 0-5 (0 forgot, 3 hard, 5 easy; q to stop): ")?.as_deref() {
+                None | Some("q") => {
+                    println!("Reviewed {} card(s).", reviewed);
+                    return Ok(());
+                }
+                Some(answer) => match answer.trim().parse::<u8>() {
+                    Ok(quality) if quality <= 5 => break quality,
+                    _ => continue,
+                },
+            }
+        };
+        let schedule = due.schedule.review(quality, today);
+        save(connection, &due.card, &schedule)?;
+        reviewed += 1;
+        println!("Next review on {}", schedule.due);
+    }
+    println!("Reviewed {} card(s).", reviewed);
+    Ok(())
+}
+
+pub fn run_review(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &ReviewArgs,
+) -> Result<(), Box<dyn Error>> {
+    let query = Query::parse(args.query.as_deref().unwrap_or(""))?;
+    let connection = open(config)?;
+    let notes = data::load_notes(vault_path)?;
+    let cards = due_cards(
+        &notes,
+        &query,
+        &load_schedules(&connection)?,
+        today(),
+        args.limit,
+    );
+
+    if !args.list {
+        if cards.is_empty() {
+            println!("No cards are due.");
+            return Ok(());
+        }
+        return session(&connection, &cards);
+    }
+    match args.format {
+        OutputFormat::Text => {
+            for due in &cards {
+                println!(
+                    "{}:{}  {}  (due {})",
+                    due.card.path,
+                    due.card.line,
+                    due.card.front.replace('\n', " "),
+                    due.schedule.due
+                );
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&cards)?),
+        OutputFormat::Ndjson => {
+            for due in &cards {
+                util::print_ndjson(due)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_parse_cards() {
+        let note = Note::from_content(
+            PathBuf::from("Spanish.md"),
+            String::from("#flashcards\nperro::dog\n\nWhat is\nthe capital?\n?\nMadrid\n\nplain\n"),
+        );
+        let cards = parse_cards(&note);
+        assert_eq!(cards.len(), 2);
+        assert_eq!(
+            (cards[0].front.as_str(), cards[0].back.as_str()),
+            ("perro", "dog")
+        );
+        assert_eq!(cards[1].front, "What is\nthe capital?");
+        assert_eq!((cards[1].line, cards[1].back.as_str()), (4, "Madrid"));
+
+        let untagged =
+            Note::from_content(PathBuf::from("A.md"), String::from("due:: 2024-01-01\n"));
+        assert!(parse_cards(&untagged).is_empty());
+    }
+
+    #[test]
+    fn test_review_schedule() {
+        let today = civil::date(2024, 1, 1);
+        let first = Schedule::new(today).review(4, today);
+        assert_eq!((first.interval, first.repetitions), (1, 1));
+        assert_eq!(first.due, civil::date(2024, 1, 2));
+        let second = first.review(5, today);
+        assert_eq!(second.interval, 6);
+        let third = second.review(3, today);
+        assert_eq!(third.interval, (6.0 * second.ease).round() as i64);
+        assert!(third.ease < second.ease);
+
+        let lapse = third.review(1, today);
+        assert_eq!((lapse.interval, lapse.repetitions), (1, 0));
+        assert!(lapse.ease >= MIN_EASE);
+    }
+
+    #[test]
+    fn test_inline_fields_are_not_cards() {
+        let note = Note::from_content(
+            PathBuf::from("Spanish.md"),
+            String::from(
+                "#flashcards\nstatus:: learning\n- **due**:: 2024-01-01\n\
+                 Met on [day:: Monday]\nperro::dog\ngato :: cat\n",
+            ),
+        );
+        let fronts: Vec<String> = parse_cards(&note)
+            .into_iter()
+            .map(|card| card.front)
+            .collect();
+        assert_eq!(fronts, ["perro", "gato"]);
+    }
+}
@@ -0,0 +1,665 @@
+//! Scheduled jobs. The daemon runs each `[[schedule.jobs]]` entry when its cron
+//! expression next matches. Report jobs write the job's query into its note as a table
+//! and refresh the note's query tables and maps of content, so dashboards built on
+//! relative dates (`due:<=today`) stay current even when nothing is edited; maintenance
+//! jobs re-index the vault, back it up or check its links, and notify jobs send a
+//! notification when notes match their query.
+//!
+//! When each job last ran is kept in the cache, so a run missed while the daemon was
+//! stopped is made up for when it starts again.
+
+use crate::backup;
+use crate::check_links;
+use crate::cli::{ChangeArgs, JobsCommand, OutputFormat};
+use crate::config::{AppConfig, JobTask, ScheduleConfig, ScheduledJob};
+use crate::data;
+use crate::index;
+use crate::moc;
+use crate::notify;
+use crate::query::Query;
+use crate::query_table;
+use crate::remote_backup;
+use crate::search;
+use crate::write_gate;
+
+use jiff::{Timestamp, ToSpan, Zoned, civil, tz::TimeZone};
+use sqlite::{Connection, State};
+use std::{
+    collections::HashMap,
+    error::Error,
+    hash::{BuildHasher, RandomState},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// Minutes, hours and days stepped through before a schedule is taken to never match
+const SEARCH_LIMIT: usize = 100_000;
+
+/// A five-field cron expression, each field as a bit set of the values it allows
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    /// Sunday is 0
+    weekdays: u64,
+    /// Whether day-of-month and day-of-week were both restricted, in which case a day
+    /// matching either runs the job
+    either_day: bool,
+}
+
+/// Value of one `field` item: a number, or a name from `names` counted from `first`.
+fn parse_value(text: &str, first: u32, names: &[&str], field: &str) -> Result<u32, Box<dyn Error>> {
+    if let Ok(value) = text.parse::<u32>() {
+        return Ok(value);
+    }
+    names
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(text))
+        .map(|i| first + i as u32)
+        .ok_or_else(|| format!("Invalid {} '{}'", field, text).into())
+}
+
+/// The bit set of a comma-separated list of `*`, `n`, `a-b`, each optionally `/step`.
+fn parse_field(
+    text: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    field: &str,
+) -> Result<u64, Box<dyn Error>> {
+    let mut bits = 0;
+    for item in text.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&s| s > 0)),
+            None => (item, Some(1)),
+        };
+        let step = step.ok_or_else(|| format!("Invalid step in {} '{}'", field, item))?;
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (
+                parse_value(start, min, names, field)?,
+                parse_value(end, min, names, field)?,
+            ),
+            None => {
+                let value = parse_value(range, min, names, field)?;
+                // `5/15` runs from 5 to the end of the range
+                (value, if item.contains('/') { max } else { value })
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("{} '{}' is outside {}-{}", field, item, min, max).into());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    /// Parses `minute hour day-of-month month day-of-week`, or `@hourly`, `@daily`,
+    /// `@weekly` or `@monthly`.
+    pub fn parse(text: &str) -> Result<Cron, Box<dyn Error>> {
+        let text = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            text => text,
+        };
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "Cron expression '{}' needs five fields: minute hour day month weekday",
+                text
+            )
+            .into());
+        };
+        let mut weekdays = parse_field(weekday, 0, 7, &WEEKDAYS, "weekday")?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Cron {
+            minutes: parse_field(minute, 0, 59, &[], "minute")?,
+            hours: parse_field(hour, 0, 23, &[], "hour")?,
+            days: parse_field(day, 1, 31, &[], "day")?,
+            months: parse_field(month, 1, 12, &MONTHS, "month")?,
+            weekdays,
+            either_day: day != "*" && weekday != "*",
+        })
+    }
+
+    fn matches_day(&self, date: civil::Date) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().to_sunday_zero_offset()) != 0;
+        match self.either_day {
+            true => day || weekday,
+            false => day && weekday,
+        }
+    }
+
+    /// The first minute after `time` the expression matches, or `None` if none does in
+    /// the next few years.
+    pub fn next_after(&self, time: civil::DateTime) -> Option<civil::DateTime> {
+        let mut time = time.date().at(time.hour(), time.minute(), 0, 0) + 1.minute();
+        for _ in 0..SEARCH_LIMIT {
+            let date = time.date();
+            time = if self.months & (1 << date.month()) == 0 {
+                date.first_of_month().checked_add(1.month()).ok()?.into()
+            } else if !self.matches_day(date) {
+                date.tomorrow().ok()?.into()
+            } else if self.hours & (1 << time.hour()) == 0 {
+                date.at(time.hour(), 0, 0, 0).checked_add(1.hour()).ok()?
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time.checked_add(1.minute()).ok()?
+            } else {
+                return Some(time);
+            };
+        }
+        None
+    }
+}
+
+/// When `cron` next runs after `now`, in the local time zone.
+fn next_run(cron: &Cron, now: &Zoned) -> Option<Zoned> {
+    let time = cron.next_after(now.datetime())?;
+    time.to_zoned(now.time_zone().clone()).ok()
+}
+
+fn task_name(task: JobTask) -> &'static str {
+    match task {
+        JobTask::Report => "report",
+        JobTask::Reindex => "reindex",
+        JobTask::Backup => "backup",
+        JobTask::CheckLinks => "check-links",
+        JobTask::Notify => "notify",
+    }
+}
+
+/// How a job's last run went
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobRun {
+    pub started_ms: i64,
+    pub ok: bool,
+    /// What the run did, or why it failed
+    pub message: String,
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS job_runs (
+            name TEXT PRIMARY KEY,
+            started INTEGER NOT NULL,
+            ok INTEGER NOT NULL,
+            message TEXT NOT NULL
+        );",
+    )
+}
+
+/// The last run of every job that has run, by job name.
+pub fn last_runs(connection: &Connection) -> Result<HashMap<String, JobRun>, Box<dyn Error>> {
+    let mut statement = connection.prepare("SELECT name, started, ok, message FROM job_runs")?;
+    let mut runs = HashMap::new();
+    while let State::Row = statement.next()? {
+        runs.insert(
+            statement.read::<String, _>(0)?,
+            JobRun {
+                started_ms: statement.read(1)?,
+                ok: statement.read::<i64, _>(2)? != 0,
+                message: statement.read(3)?,
+            },
+        );
+    }
+    Ok(runs)
+}
+
+fn save_run(connection: &Connection, name: &str, run: &JobRun) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT OR REPLACE INTO job_runs (name, started, ok, message) VALUES (?, ?, ?, ?)",
+    )?;
+    statement.bind((1, name))?;
+    statement.bind((2, run.started_ms))?;
+    statement.bind((3, run.ok as i64))?;
+    statement.bind((4, run.message.as_str()))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Opens the cache with the job run table in place.
+fn open_runs(config: &AppConfig) -> Result<Connection, Box<dyn Error>> {
+    let connection = index::open(config)?;
+    ensure_schema(&connection)?;
+    Ok(connection)
+}
+
+/// Problems SQLite finds in the cache database, empty when it is sound.
+fn integrity_problems(connection: &Connection) -> Result<Vec<String>, sqlite::Error> {
+    let mut statement = connection.prepare("PRAGMA integrity_check")?;
+    let mut problems = Vec::new();
+    while let State::Row = statement.next()? {
+        let row = statement.read::<String, _>(0)?;
+        if row != "ok" {
+            problems.push(row);
+        }
+    }
+    Ok(problems)
+}
+
+/// Writes a report job's table, then refreshes the tables and maps in its note.
+fn run_report(vault_path: &Path, job: &ScheduledJob) -> Result<String, Box<dyn Error>> {
+    let name = job
+        .note
+        .as_deref()
+        .ok_or_else(|| format!("Job '{}' is a report but names no note", job.name))?;
+    let mut note = PathBuf::from(name);
+    if note.extension().is_none() {
+        note.set_extension("md");
+    }
+    let mut rows = None;
+    if let Some(query) = &job.query {
+        let changes = ChangeArgs {
+            dry_run: false,
+            changes_format: OutputFormat::Text,
+        };
+        rows = Some(query_table::render_into(
+            vault_path,
+            name,
+            query,
+            &job.columns,
+            &changes,
+        )?);
+    }
+    let edits =
+        query_table::refresh_note(vault_path, &note)? + moc::refresh_note(vault_path, &note)?;
+    log::debug!("Job '{}': {} other block edit(s)", job.name, edits);
+    Ok(match rows {
+        Some(rows) => format!("{} row(s) in {}", rows, note.display()),
+        None => format!("refreshed {}", note.display()),
+    })
+}
+
+/// Runs `job` once, returning a summary of what it did.
+pub fn run_job(
+    vault_path: &Path,
+    config: &AppConfig,
+    job: &ScheduledJob,
+) -> Result<String, Box<dyn Error>> {
+    match job.task {
+        JobTask::Report => run_report(vault_path, job),
+        JobTask::Reindex => {
+            let connection = index::open(config)?;
+            let embed = config.embeddings.backend.is_some();
+            let stats = search::refresh_index(&connection, vault_path, config, embed)?;
+            search::refresh_attachments(&connection, vault_path, config)?;
+            let problems = integrity_problems(&connection)?;
+            if !problems.is_empty() {
+                return Err(format!(
+                    "the cache database is damaged, remove it to rebuild: {}",
+                    problems.join("; ")
+                )
+                .into());
+            }
+            Ok(format!("{}; cache verified", stats))
+        }
+        JobTask::Backup => {
+            let mut done = Vec::new();
+            if config.backup.dir.is_some() || config.backup.remote.is_none() {
+                let archive = backup::run_backup(vault_path, &config.backup)?;
+                done.push(format!("wrote {}", archive.display()));
+            }
+            if let Some(remote) = &config.backup.remote {
+                let uploaded = remote_backup::run_upload(vault_path, config, remote)?;
+                done.push(uploaded.to_string());
+            }
+            Ok(done.join("; "))
+        }
+        JobTask::CheckLinks => {
+            let problems = check_links::find_problems(vault_path, config, true)?;
+            for problem in &problems {
+                log::warn!("{}", problem);
+            }
+            Ok(format!("{} link problem(s)", problems.len()))
+        }
+        JobTask::Notify => {
+            let text = job
+                .query
+                .as_deref()
+                .ok_or_else(|| format!("Job '{}' notifies but has no query", job.name))?;
+            let query = Query::parse(text)?;
+            let notes = data::load_notes(vault_path)?;
+            let titles: Vec<String> = query
+                .filter(&notes)
+                .iter()
+                .map(|note| note.title())
+                .collect();
+            if let Some(notification) = notify::query_matches(&job.name, text, &titles) {
+                if !config.notify.is_enabled() {
+                    return Err("no channel is set under [notify]".into());
+                }
+                notify::send(&config.notify, &notification);
+            }
+            Ok(format!("{} note(s) matched", titles.len()))
+        }
+    }
+}
+
+/// Runs `job`, logs how it went and records the run in the cache.
+fn run_logged(vault_path: &Path, config: &AppConfig, job: &ScheduledJob) {
+    if job.task == JobTask::Report && write_gate::is_read_only() {
+        log::warn!("Job '{}' skipped: the vault is read-only", job.name);
+        return;
+    }
+    let started_ms = Timestamp::now().as_millisecond();
+    let started = Instant::now();
+    let run = match run_job(vault_path, config, job) {
+        Ok(message) => {
+            log::info!(
+                "Job '{}' finished in {:?}: {}",
+                job.name,
+                started.elapsed(),
+                message
+            );
+            JobRun {
+                started_ms,
+                ok: true,
+                message,
+            }
+        }
+        Err(e) => {
+            log::error!("Job '{}' failed: {}", job.name, e);
+            if config.notify.job_failures {
+                notify::send(
+                    &config.notify,
+                    &notify::job_failed(&job.name, &e.to_string()),
+                );
+            }
+            JobRun {
+                started_ms,
+                ok: false,
+                message: e.to_string(),
+            }
+        }
+    };
+    if let Err(e) = open_runs(config).and_then(|connection| {
+        save_run(&connection, &job.name, &run)?;
+        Ok(())
+    }) {
+        log::error!("Could not record the run of job '{}': {}", job.name, e);
+    }
+}
+
+/// When `cron` should first run once the daemon starts at `now`: right away if a run
+/// was due since `last`, the job's previous start, and otherwise at its next time.
+fn first_run(cron: &Cron, last: Option<&JobRun>, now: &Zoned) -> Option<Zoned> {
+    let missed = last
+        .and_then(|run| Timestamp::from_millisecond(run.started_ms).ok())
+        .and_then(|started| next_run(cron, &started.to_zoned(now.time_zone().clone())))
+        .filter(|due| due.timestamp() <= now.timestamp());
+    match missed {
+        Some(_) => Some(now.clone()),
+        None => next_run(cron, now),
+    }
+}
+
+/// The run of `cron` after the one due `at`. It is counted from `now` when that is later,
+/// so slots missed during a long run or while the machine slept are skipped rather than
+/// run back-to-back.
+fn run_after(cron: &Cron, at: &Zoned, now: &Zoned) -> Option<Zoned> {
+    next_run(cron, at.max(now))
+}
+
+/// The configured jobs with their parsed schedules; jobs that do not parse are logged
+/// and left out.
+fn parse_jobs(config: &ScheduleConfig) -> Vec<(ScheduledJob, Cron)> {
+    config
+        .jobs
+        .iter()
+        .filter_map(|job| match Cron::parse(&job.cron) {
+            Ok(cron) => Some((job.clone(), cron)),
+            Err(e) => {
+                log::error!("Job '{}' will not run: {}", job.name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Starts running the scheduled jobs on a background thread, if there are any.
+pub fn spawn_scheduler(vault_path: PathBuf, config: &AppConfig) -> Option<thread::JoinHandle<()>> {
+    let jobs = parse_jobs(&config.schedule);
+    if jobs.is_empty() {
+        return None;
+    }
+    let jitter_secs = config.schedule.jitter_secs;
+    let random = RandomState::new();
+    let config = config.clone();
+    Some(thread::spawn(move || {
+        let runs = open_runs(&config)
+            .and_then(|connection| last_runs(&connection))
+            .inspect_err(|e| log::warn!("Runs missed while stopped will not be made up: {}", e))
+            .unwrap_or_default();
+        let now = Zoned::now();
+        // Each job's next run is counted from its own previous slot, so jobs due in the
+        // same minute all run
+        let mut next: Vec<Option<Zoned>> = jobs
+            .iter()
+            .map(|(job, cron)| first_run(cron, runs.get(&job.name), &now))
+            .collect();
+        loop {
+            let Some((i, at)) = next
+                .iter()
+                .enumerate()
+                .filter_map(|(i, at)| Some((i, at.clone()?)))
+                .min_by_key(|(_, at)| at.timestamp())
+            else {
+                log::warn!("No scheduled job will run again");
+                return;
+            };
+            let (job, cron) = &jobs[i];
+            let jitter = Duration::from_secs(
+                random.hash_one((&job.name, at.timestamp())) % (jitter_secs + 1),
+            );
+            let wait = Duration::try_from(at.timestamp().duration_since(Timestamp::now()))
+                .unwrap_or_default();
+            thread::sleep(wait + jitter);
+            log::info!("Running job '{}' scheduled for {}", job.name, at.datetime());
+            run_logged(&vault_path, &config, job);
+            next[i] = run_after(cron, &at, &Zoned::now());
+        }
+    }))
+}
+
+/// When a job last ran, and whether it failed.
+fn describe_run(run: Option<&JobRun>) -> String {
+    let Some(run) = run else {
+        return String::from("never");
+    };
+    let started = match Timestamp::from_millisecond(run.started_ms) {
+        Ok(started) => started.to_zoned(TimeZone::system()).datetime().to_string(),
+        Err(_) => String::from("?"),
+    };
+    match run.ok {
+        true => started,
+        false => format!("{} (failed: {})", started, run.message),
+    }
+}
+
+fn list(config: &AppConfig) -> Result<(), Box<dyn Error>> {
+    let runs = last_runs(&open_runs(config)?)?;
+    let now = Zoned::now();
+    for job in &config.schedule.jobs {
+        let next = match Cron::parse(&job.cron) {
+            Ok(cron) => match next_run(&cron, &now) {
+                Some(at) => at.datetime().to_string(),
+                None => String::from("never"),
+            },
+            Err(e) => e.to_string(),
+        };
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            job.name,
+            task_name(job.task),
+            job.cron,
+            describe_run(runs.get(&job.name)),
+            next
+        );
+    }
+    Ok(())
+}
+
+pub fn run_jobs(
+    vault_path: &Path,
+    config: &AppConfig,
+    command: &JobsCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        JobsCommand::List => list(config),
+        JobsCommand::Run(args) => {
+            let job = config
+                .schedule
+                .jobs
+                .iter()
+                .find(|job| job.name == args.job)
+                .ok_or_else(|| format!("No job named '{}' under [[schedule.jobs]]", args.job))?;
+            if job.task == JobTask::Report {
+                write_gate::check(&format!("run job '{}'", job.name))?;
+            }
+            let started_ms = Timestamp::now().as_millisecond();
+            let outcome = run_job(vault_path, config, job);
+            let run = JobRun {
+                started_ms,
+                ok: outcome.is_ok(),
+                message: match &outcome {
+                    Ok(message) => message.clone(),
+                    Err(e) => e.to_string(),
+                },
+            };
+            save_run(&open_runs(config)?, &job.name, &run)?;
+            println!("{}", outcome?);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cron_next_after() {
+        // A Wednesday
+        let now = civil::date(2024, 5, 15).at(9, 30, 42, 0);
+        let next = |cron: &str| Cron::parse(cron).unwrap().next_after(now);
+
+        assert_eq!(
+            next("* * * * *"),
+            Some(civil::date(2024, 5, 15).at(9, 31, 0, 0))
+        );
+        assert_eq!(
+            next("*/15 * * * *"),
+            Some(civil::date(2024, 5, 15).at(9, 45, 0, 0))
+        );
+        assert_eq!(
+            next("0 8 * * mon-fri"),
+            Some(civil::date(2024, 5, 16).at(8, 0, 0, 0))
+        );
+        assert_eq!(
+            next("0 18 * * sat,7"),
+            Some(civil::date(2024, 5, 18).at(18, 0, 0, 0))
+        );
+        assert_eq!(
+            next("@monthly"),
+            Some(civil::date(2024, 6, 1).at(0, 0, 0, 0))
+        );
+        assert_eq!(
+            next("0 0 1 jan *"),
+            Some(civil::date(2025, 1, 1).at(0, 0, 0, 0))
+        );
+        // Day of month or day of week, as in cron
+        assert_eq!(
+            next("0 12 20 * fri"),
+            Some(civil::date(2024, 5, 17).at(12, 0, 0, 0))
+        );
+        assert_eq!(next("0 0 31 2 *"), None);
+
+        for bad in [
+            "* * * *",
+            "60 * * * *",
+            "* * * foo *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+        ] {
+            assert!(Cron::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_first_run_makes_up_missed_runs() {
+        let cron = Cron::parse("0 3 * * *").unwrap();
+        let now = civil::date(2024, 5, 15)
+            .at(9, 30, 0, 0)
+            .to_zoned(TimeZone::UTC)
+            .unwrap();
+        let ran = |time: civil::DateTime| JobRun {
+            started_ms: time
+                .to_zoned(TimeZone::UTC)
+                .unwrap()
+                .timestamp()
+                .as_millisecond(),
+            ok: true,
+            message: String::new(),
+        };
+        let tomorrow = civil::date(2024, 5, 16).at(3, 0, 0, 0);
+
+        // Never ran: wait for the next time
+        let first = first_run(&cron, None, &now).unwrap();
+        assert_eq!(first.datetime(), tomorrow);
+        // Ran this morning: nothing missed
+        let today = ran(civil::date(2024, 5, 15).at(3, 0, 5, 0));
+        let first = first_run(&cron, Some(&today), &now).unwrap();
+        assert_eq!(first.datetime(), tomorrow);
+        // Last ran two days ago: this morning's run was missed
+        let earlier = ran(civil::date(2024, 5, 13).at(3, 0, 5, 0));
+        assert_eq!(first_run(&cron, Some(&earlier), &now), Some(now.clone()));
+    }
+
+    #[test]
+    fn test_job_runs_round_trip() {
+        let connection = sqlite::open(":memory:").unwrap();
+        ensure_schema(&connection).unwrap();
+        let run = JobRun {
+            started_ms: 1_700_000_000_000,
+            ok: false,
+            message: String::from("Set [backup] dir"),
+        };
+        save_run(&connection, "weekly-backup", &run).unwrap();
+        assert_eq!(
+            last_runs(&connection).unwrap().get("weekly-backup"),
+            Some(&run)
+        );
+    }
+
+    #[test]
+    fn test_run_after_skips_missed_slots() {
+        let cron = Cron::parse("*/5 * * * *").unwrap();
+        let at = |hour, minute| {
+            civil::date(2024, 5, 15)
+                .at(hour, minute, 0, 0)
+                .to_zoned(TimeZone::UTC)
+                .unwrap()
+        };
+        let next = run_after(&cron, &at(9, 0), &at(9, 1)).unwrap();
+        assert_eq!(next, at(9, 5));
+        // Woke up at 11:02 after sleeping through the 9:05 to 11:00 slots
+        let next = run_after(&cron, &at(9, 0), &at(11, 2)).unwrap();
+        assert_eq!(next, at(11, 5));
+    }
+}
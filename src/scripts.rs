@@ -0,0 +1,227 @@
+//! Lua scripts loaded from the vault's script folder.
+//!
+//! A script is a `.lua` file defining a global `metadata(note)` function. It gets the note
+//! as a table with `path`, `title`, `tags` and `content`, and returns a table of extra
+//! metadata fields (or `nil`). Scripts only see the `string`, `table`, `math` and `utf8`
+//! libraries.
+//!
+//! Scripts only load in builds with the `lua-scripts` feature.
+
+use crate::config::AppConfig;
+use crate::data::Note;
+use crate::plugins;
+use crate::util;
+
+#[cfg(feature = "lua-scripts")]
+use mlua::{Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, StdLib};
+use serde_json::{Map, Value};
+#[cfg(feature = "lua-scripts")]
+use std::fs;
+use std::{
+    error::Error,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+/// Instructions a single call may execute before it is aborted
+#[cfg(feature = "lua-scripts")]
+static INSTRUCTIONS_PER_CALL: u32 = 50_000_000;
+
+static REGISTRY: OnceLock<Mutex<Vec<Script>>> = OnceLock::new();
+
+pub struct Script {
+    pub name: String,
+    /// Hash of the source, to notice when it changes
+    pub hash: String,
+    #[cfg(feature = "lua-scripts")]
+    lua: Lua,
+}
+
+#[cfg(feature = "lua-scripts")]
+impl Script {
+    pub fn from_source(name: &str, source: &str) -> Result<Script, Box<dyn Error>> {
+        let libs = StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8;
+        let lua = Lua::new_with(libs, LuaOptions::default())?;
+        lua.load(source).set_name(name).exec()?;
+        Ok(Script {
+            name: name.to_string(),
+            hash: util::content_hash(source.as_bytes()),
+            lua,
+        })
+    }
+
+    pub fn has_hook(&self, hook: &str) -> bool {
+        self.lua.globals().get::<Function>(hook).is_ok()
+    }
+
+    /// Calls the global function `hook` with `input`, returning its result as JSON.
+    /// Scripts without the function, or returning `nil`, give `None`.
+    pub fn call(&self, hook: &str, input: &Value) -> Result<Option<Value>, Box<dyn Error>> {
+        let Ok(function) = self.lua.globals().get::<Function>(hook) else {
+            return Ok(None);
+        };
+        // Setting the hook again restarts its instruction count
+        self.lua.set_hook(
+            HookTriggers::new().every_nth_instruction(INSTRUCTIONS_PER_CALL),
+            |_, _| Err(mlua::Error::runtime("script ran too long")),
+        )?;
+        let result: mlua::Value = function.call(self.lua.to_value(input)?)?;
+        self.lua.remove_hook();
+        if result.is_nil() {
+            return Ok(None);
+        }
+        Ok(Some(self.lua.from_value(result)?))
+    }
+}
+
+/// Without the `lua-scripts` feature no script is ever loaded, so none has hooks.
+#[cfg(not(feature = "lua-scripts"))]
+impl Script {
+    pub fn has_hook(&self, _hook: &str) -> bool {
+        false
+    }
+
+    pub fn call(&self, _hook: &str, _input: &Value) -> Result<Option<Value>, Box<dyn Error>> {
+        Ok(None)
+    }
+}
+
+/// Loads every `.lua` script in `dir`; broken scripts are logged and skipped.
+#[cfg(feature = "lua-scripts")]
+pub fn load_dir(dir: &Path) -> Vec<Script> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        log::warn!("Script folder '{}' cannot be read", dir.display());
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+        .collect();
+    paths.sort();
+
+    let mut scripts = Vec::new();
+    for path in paths {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match fs::read_to_string(&path)
+            .map_err(Box::from)
+            .and_then(|source| Script::from_source(&name, &source))
+        {
+            Ok(script) => {
+                log::info!("Loaded script '{}'", name);
+                scripts.push(script);
+            }
+            Err(e) => log::error!("Cannot load script '{}': {}", path.display(), e),
+        }
+    }
+    scripts
+}
+
+#[cfg(not(feature = "lua-scripts"))]
+pub fn load_dir(dir: &Path) -> Vec<Script> {
+    log::warn!(
+        "Not loading the scripts in '{}': obsidian-rs was built without the `lua-scripts` feature",
+        dir.display()
+    );
+    Vec::new()
+}
+
+/// Loads the scripts of `[plugins] scripts` for the rest of the process.
+pub fn install(vault_path: &Path, config: &AppConfig) {
+    let Some(dir) = &config.plugins.scripts else {
+        return;
+    };
+    let dir = vault_path.join(util::expand_tilde(Path::new(dir)).unwrap_or_default());
+    let _ = REGISTRY.set(Mutex::new(load_dir(&dir)));
+}
+
+/// Whether any script was installed.
+pub fn active() -> bool {
+    REGISTRY
+        .get()
+        .is_some_and(|registry| registry.lock().is_ok_and(|scripts| !scripts.is_empty()))
+}
+
+/// Source hashes of the installed scripts.
+pub fn hashes() -> Vec<String> {
+    let Some(Ok(scripts)) = REGISTRY.get().map(Mutex::lock) else {
+        return Vec::new();
+    };
+    scripts.iter().map(|script| script.hash.clone()).collect()
+}
+
+/// Names of the installed scripts with the hooks each defines.
+pub fn installed() -> Vec<(String, Vec<&'static str>)> {
+    let Some(Ok(scripts)) = REGISTRY.get().map(Mutex::lock) else {
+        return Vec::new();
+    };
+    scripts
+        .iter()
+        .map(|script| {
+            let hooks = ["metadata"]
+                .into_iter()
+                .filter(|hook| script.has_hook(hook))
+                .collect();
+            (script.name.clone(), hooks)
+        })
+        .collect()
+}
+
+/// Extra metadata fields returned by the scripts, later scripts winning on conflicts.
+pub fn metadata(note: &Note) -> Map<String, Value> {
+    let mut fields = Map::new();
+    let Some(Ok(scripts)) = REGISTRY.get().map(Mutex::lock) else {
+        return fields;
+    };
+    let input = plugins::note_json(note);
+    for script in scripts.iter() {
+        match script.call("metadata", &input) {
+            Ok(Some(Value::Object(extra))) => fields.extend(extra),
+            Ok(_) => {}
+            Err(e) => log::warn!("Script '{}' metadata failed: {}", script.name, e),
+        }
+    }
+    fields
+}
+
+#[cfg(all(test, feature = "lua-scripts"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_metadata_hook() {
+        let script = Script::from_source(
+            "project",
+            r#"
+            function metadata(note)
+                local project = note.content:match("project:%s*(%w+)")
+                if project then
+                    return { project = project, tag_count = #note.tags }
+                end
+            end
+            "#,
+        )
+        .unwrap();
+        assert!(script.has_hook("metadata"));
+
+        let note = Note::from_content("A.md".into(), String::from("project: alpha #x"));
+        let result = script.call("metadata", &plugins::note_json(&note)).unwrap();
+        assert_eq!(result, Some(json!({ "project": "alpha", "tag_count": 1 })));
+        let note = Note::from_content("B.md".into(), String::from("nothing"));
+        assert_eq!(
+            script.call("metadata", &plugins::note_json(&note)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_scripts_are_sandboxed_and_limited() {
+        assert!(Script::from_source("io", "io.open('/etc/passwd')").is_err());
+        let script = Script::from_source("spin", "function metadata() while true do end end");
+        assert!(script.unwrap().call("metadata", &json!({})).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use crate::cli::{OutputFormat, SearchArgs, SearchMode, SimilarArgs};
+use crate::config::AppConfig;
+use crate::content_store;
+use crate::data::{self, Note};
+use crate::embeddings::{self, Neighbor};
+use crate::index::{self, IndexStats, SearchHit, SnippetOptions};
+use crate::journal;
+use crate::media;
+use crate::resolver::Resolver;
+use crate::snapshots;
+use crate::util;
+use crate::writing;
+
+use jiff::{Timestamp, Zoned};
+use sqlite::Connection;
+use std::{collections::HashMap, error::Error, path::Path};
+
+/// Damping constant of reciprocal rank fusion; 60 is the value from the original paper
+static RRF_K: f64 = 60.0;
+
+/// Candidates taken from each ranking before fusing
+static FUSION_CANDIDATES: usize = 50;
+
+/// Combines keyword and semantic rankings by reciprocal rank fusion: each note scores
+/// `1 / (k + rank)` per ranking it appears in. Component scores are kept on the hit.
+pub fn fuse(keyword: Vec<SearchHit>, semantic: Vec<SearchHit>, limit: usize) -> Vec<SearchHit> {
+    let mut fused: HashMap<String, SearchHit> = HashMap::new();
+    for (rank, hit) in keyword.into_iter().enumerate() {
+        let entry = fused.entry(hit.path.clone()).or_insert_with(|| SearchHit {
+            score: 0.0,
+            ..hit.clone()
+        });
+        entry.score += 1.0 / (RRF_K + rank as f64 + 1.0);
+        entry.keyword_score = Some(hit.score);
+    }
+    for (rank, hit) in semantic.into_iter().enumerate() {
+        let entry = fused.entry(hit.path.clone()).or_insert_with(|| SearchHit {
+            score: 0.0,
+            ..hit.clone()
+        });
+        entry.score += 1.0 / (RRF_K + rank as f64 + 1.0);
+        entry.semantic_score = Some(hit.score);
+    }
+
+    let mut hits: Vec<SearchHit> = fused.into_values().collect();
+    hits.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    hits.truncate(limit);
+    hits
+}
+
+pub fn print_hits(hits: &[SearchHit], format: OutputFormat) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(hits)?),
+        OutputFormat::Ndjson => {
+            for hit in hits {
+                util::print_ndjson(hit)?;
+            }
+        }
+        OutputFormat::Text => {
+            for hit in hits {
+                let heading = if hit.heading.is_empty() {
+                    String::new()
+                } else {
+                    format!(" # {}", hit.heading)
+                };
+                let components: Vec<String> = [
+                    ("keyword", hit.keyword_score),
+                    ("semantic", hit.semantic_score),
+                ]
+                .iter()
+                .filter_map(|(name, score)| score.map(|score| format!("{} {:.3}", name, score)))
+                .collect();
+                let details = if components.is_empty() {
+                    String::new()
+                } else {
+                    format!("; {}", components.join(", "))
+                };
+                println!(
+                    "{}:{}{} ({:.3}{})",
+                    hit.path, hit.line, heading, hit.score, details
+                );
+                println!("    {}", hit.snippet);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Brings the search index up to date with the vault. With `embed`, missing chunk
+/// vectors are fetched too and, if `[embeddings] neighbors` is set, the stored nearest
+/// neighbours are recomputed whenever anything changed.
+pub fn refresh_index(
+    connection: &Connection,
+    vault_path: &Path,
+    config: &AppConfig,
+    embed: bool,
+) -> Result<IndexStats, Box<dyn Error>> {
+    let notes = data::load_notes(vault_path)?;
+    if config.cache.store_content {
+        content_store::ensure_schema(connection)?;
+        let stored = content_store::update(connection, &notes, &config.cache)?;
+        log::debug!("Stored the contents of {} note(s)", stored);
+    }
+    refresh_index_with(connection, vault_path, &notes, config, embed)
+}
+
+/// Like [`refresh_index`], from notes already read.
+fn refresh_index_with(
+    connection: &Connection,
+    vault_path: &Path,
+    notes: &[Note],
+    config: &AppConfig,
+    embed: bool,
+) -> Result<IndexStats, Box<dyn Error>> {
+    let stats = index::update(connection, notes)?;
+    log::debug!("Index: {}", stats);
+    if !stats.is_empty() {
+        embeddings::forget_neighbors(connection)?;
+    }
+    let today = Zoned::now().date();
+    let written = writing::update(connection, notes, &stats, today)?;
+    log::debug!("Credited {} word(s) written today", written);
+    if snapshots::update(connection, notes, today, config.cache.snapshot_days)? {
+        log::debug!("Took the snapshot of {}", today);
+    }
+    let now = Timestamp::now();
+    let journaled = journal::record(connection, notes, &stats, now)?;
+    log::debug!("Journaled {} change(s)", journaled);
+    if journaled > 0 {
+        journal::compact(connection, config.journal.keep_days, now)?;
+    }
+    #[cfg(feature = "ocr")]
+    {
+        let recognised = crate::ocr::update(connection, vault_path, notes, config)?;
+        log::debug!("Indexed image text of {} note(s)", recognised);
+    }
+    if index::has_attachments(connection)? {
+        let resolver = Resolver::from_vault(vault_path)?;
+        index::update_attachment_links(connection, &resolver, notes)?;
+    }
+    #[cfg(not(feature = "ocr"))]
+    if config.ocr.backend.is_some() {
+        log::warn!(
+            "[ocr] is set, but this build lacks the `ocr` feature; images in {} are not read",
+            vault_path.display()
+        );
+    }
+    if !embed {
+        return Ok(stats);
+    }
+
+    let embedder = embeddings::backend(&config.embeddings)?;
+    let embedded = embeddings::update(connection, embedder.as_ref())?;
+    let stale = embedded > 0 || !embeddings::has_neighbors(connection, embedder.model())?;
+    if config.embeddings.neighbors > 0 && stale {
+        let notes =
+            embeddings::store_neighbors(connection, embedder.model(), config.embeddings.neighbors)?;
+        log::info!("Stored nearest neighbours of {} note(s)", notes);
+    }
+    Ok(stats)
+}
+
+/// Indexes what is too slow to read on every refresh: the text of PDFs and transcripts
+/// of recordings. The daemon runs it once changes settle and the `reindex` job after its
+/// refresh; searches never wait for it.
+pub fn refresh_attachments(
+    connection: &Connection,
+    vault_path: &Path,
+    config: &AppConfig,
+) -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "pdf-text")]
+    {
+        let read = crate::pdf_text::update(connection, vault_path)?;
+        log::debug!("Extracted the text of {} PDF(s)", read);
+    }
+    let transcribed = media::update_transcripts(connection, vault_path, config)?;
+    log::debug!("Transcribed {} recording(s)", transcribed);
+    Ok(())
+}
+
+/// Notes most similar to `path` (vault-relative), from the stored neighbours when they
+/// cover `top`, otherwise computed from the note vectors.
+pub fn similar(
+    connection: &Connection,
+    config: &AppConfig,
+    path: &str,
+    top: usize,
+) -> Result<Vec<Neighbor>, Box<dyn Error>> {
+    let model = &config.embeddings.model;
+    if top <= config.embeddings.neighbors
+        && let Some(neighbors) = embeddings::stored_neighbors(connection, model, path, top)?
+    {
+        return Ok(neighbors);
+    }
+    let vectors = embeddings::note_vectors(connection, model)?;
+    if !vectors.contains_key(path) {
+        return Err(format!("'{}' has no embeddings; is it an indexed note?", path).into());
+    }
+    Ok(embeddings::nearest(&vectors, path, top))
+}
+
+pub fn run_similar(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &SimilarArgs,
+) -> Result<(), Box<dyn Error>> {
+    let resolver = Resolver::from_vault(vault_path)?;
+    let path = resolver
+        .resolve(&args.note, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", args.note))?
+        .to_string_lossy()
+        .replace('\\', "/");
+
+    let connection = index::open(config)?;
+    refresh_index(&connection, vault_path, config, true)?;
+    let neighbors = similar(&connection, config, &path, args.top)?;
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&neighbors)?),
+        OutputFormat::Ndjson => {
+            for neighbor in &neighbors {
+                util::print_ndjson(neighbor)?;
+            }
+        }
+        OutputFormat::Text => {
+            for neighbor in &neighbors {
+                println!("{} ({:.3})", neighbor.path, neighbor.score);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Refreshes the index and ranks notes against `query`. Without an explicit `mode`,
+/// hybrid is used once an embedding backend is configured, keyword search otherwise.
+pub fn search(
+    vault_path: &Path,
+    config: &AppConfig,
+    query: &str,
+    mode: Option<SearchMode>,
+    limit: usize,
+    snippets: SnippetOptions,
+) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+    let connection = index::open(config)?;
+    search_with(
+        &connection,
+        vault_path,
+        config,
+        query,
+        mode,
+        limit,
+        snippets,
+    )
+}
+
+/// Like [`search`], on the cache database `connection` is open on.
+pub fn search_with(
+    connection: &Connection,
+    vault_path: &Path,
+    config: &AppConfig,
+    query: &str,
+    mode: Option<SearchMode>,
+    limit: usize,
+    snippets: SnippetOptions,
+) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+    let mode = mode.unwrap_or(match config.embeddings.backend {
+        Some(_) => SearchMode::Hybrid,
+        None => SearchMode::Keyword,
+    });
+    let embed = mode != SearchMode::Keyword;
+    if content_store::is_installed() {
+        // The daemon keeps the stored contents current; leave the vault alone
+        let notes = content_store::load_notes(vault_path)?;
+        refresh_index_with(connection, vault_path, &notes, config, embed)?;
+    } else {
+        refresh_index(connection, vault_path, config, embed)?;
+    }
+    let hits = match mode {
+        SearchMode::Keyword => index::keyword_search(connection, query, limit, snippets)?,
+        SearchMode::Semantic | SearchMode::Hybrid => {
+            let embedder = embeddings::backend(&config.embeddings)?;
+            if mode == SearchMode::Semantic {
+                embeddings::semantic_search(connection, embedder.as_ref(), query, limit, snippets)?
+            } else {
+                let keyword =
+                    index::keyword_search(connection, query, FUSION_CANDIDATES, snippets)?;
+                let semantic = embeddings::semantic_search(
+                    connection,
+                    embedder.as_ref(),
+                    query,
+                    FUSION_CANDIDATES,
+                    snippets,
+                )?;
+                fuse(keyword, semantic, limit)
+            }
+        }
+    };
+    index::credit_attachment_hits(connection, hits)
+}
+
+pub fn run_search(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &SearchArgs,
+) -> Result<usize, Box<dyn Error>> {
+    let query = args.query.join(" ");
+    let snippets = SnippetOptions {
+        words: args.snippet_words.unwrap_or(config.search.snippet_words),
+        count: args.snippets.unwrap_or(config.search.snippets),
+    };
+    let hits = search(vault_path, config, &query, args.mode, args.limit, snippets)?;
+    if args.porcelain {
+        for hit in &hits {
+            let line = hit.line.to_string();
+            util::print_porcelain(&[&hit.path, &line, &hit.heading, &hit.snippet])?;
+        }
+    } else {
+        print_hits(&hits, args.format)?;
+    }
+    Ok(hits.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(path: &str, score: f64) -> SearchHit {
+        SearchHit {
+            path: path.to_string(),
+            score,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fuse() {
+        let keyword = vec![hit("A.md", 9.0), hit("B.md", 5.0)];
+        let semantic = vec![hit("B.md", 0.9), hit("C.md", 0.8), hit("A.md", 0.1)];
+        let fused = fuse(keyword, semantic, 10);
+
+        let order: Vec<&str> = fused.iter().map(|h| h.path.as_str()).collect();
+        assert_eq!(order, vec!["B.md", "A.md", "C.md"]);
+        assert_eq!(fused[0].keyword_score, Some(5.0));
+        assert_eq!(fused[0].semantic_score, Some(0.9));
+        assert_eq!(fused[2].keyword_score, None);
+        assert!((fused[0].score - (1.0 / 62.0 + 1.0 / 61.0)).abs() < 1e-12);
+        assert_eq!(fuse(Vec::new(), vec![hit("C.md", 1.0)], 0).len(), 0);
+    }
+}
@@ -0,0 +1,523 @@
+//! The `search` syntax, compiled to an FTS5 expression and filters on the notes searched.
+//!
+//! Words and `"quoted phrases"` must all match, unless joined with `OR`. `NOT` leaves out
+//! notes matching the term after it, parentheses group terms, and `word*` matches any word
+//! starting with `word`. `title:`, `heading:` and `body:` scope a term to one column of the
+//! index, while `path:folder/` and `frontmatter.key:value` narrow down the notes searched.
+
+use crate::collation;
+
+use std::error::Error;
+
+/// Field prefix of `frontmatter.key:value` terms
+pub const PROPERTY_PREFIX: &str = "frontmatter.";
+
+/// Deepest nesting of parentheses a search may have, well short of overflowing the stack
+const MAX_DEPTH: usize = 32;
+
+/// A search query split into what the full-text index matches and what narrows down the
+/// notes searched.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    /// FTS5 expression, empty when the query only has filters
+    pub fts: String,
+    /// Words of the terms notes should match, to rank chunks by meaning
+    pub text: String,
+    /// Prefixes of the vault-relative paths searched
+    pub paths: Vec<String>,
+    /// Front matter properties the notes searched must have, as (key, value)
+    pub properties: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term {
+        field: Option<String>,
+        text: String,
+        /// Quoted, so taken as one phrase and never as an operator or stop word
+        quoted: bool,
+        prefix: bool,
+    },
+}
+
+/// A filter on the notes searched
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Path(String),
+    Property(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// FTS5 text of one term, with its words, and the word itself when it is a plain one
+    Term {
+        fts: String,
+        words: String,
+        plain: Option<String>,
+    },
+    Filter(Filter),
+    /// Notes matching everything in `all` but nothing in `none`
+    All {
+        all: Vec<Node>,
+        none: Vec<Node>,
+    },
+    Any(Vec<Node>),
+}
+
+/// `text` quoted as an FTS5 string, so punctuation is taken literally.
+fn fts_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+fn term(word: &str, quoted: bool, prefix: bool) -> Result<Token, Box<dyn Error>> {
+    let (field, text) = match word.split_once(':') {
+        Some((field @ ("title" | "heading" | "body" | "path"), text)) => {
+            (Some(field.to_string()), text)
+        }
+        Some((field, text)) if field.starts_with(PROPERTY_PREFIX) => {
+            (Some(field.to_string()), text)
+        }
+        _ => (None, word),
+    };
+    Ok(Token::Term {
+        field,
+        text: text.to_string(),
+        quoted,
+        prefix,
+    })
+}
+
+/// Splits `input` into terms, operators and parentheses.
+fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut chars = input.chars().peekable();
+
+    let end_word = |word: &mut String, tokens: &mut Vec<Token>| -> Result<(), Box<dyn Error>> {
+        if word.is_empty() {
+            return Ok(());
+        }
+        let token = match word.as_str() {
+            "AND" => Token::And,
+            "OR" => Token::Or,
+            "NOT" => Token::Not,
+            text => match text.strip_suffix('*') {
+                Some(stem) if stem.is_empty() || stem.ends_with(':') => {
+                    return Err(format!("'*' in '{}' needs a word before it", text).into());
+                }
+                Some(stem) => term(stem, false, true)?,
+                None => term(text, false, false)?,
+            },
+        };
+        tokens.push(token);
+        word.clear();
+        Ok(())
+    };
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => {
+                // `field:"a phrase"` keeps its field; anything else before a quote is a word
+                let field = match word.ends_with(':') {
+                    true => std::mem::take(&mut word),
+                    false => {
+                        end_word(&mut word, &mut tokens)?;
+                        String::new()
+                    }
+                };
+                let mut phrase = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(ch) => phrase.push(ch),
+                        None => {
+                            return Err(format!("Unterminated quote in search '{}'", input).into());
+                        }
+                    }
+                }
+                let prefix = chars.next_if_eq(&'*').is_some();
+                tokens.push(term(&format!("{}{}", field, phrase), true, prefix)?);
+            }
+            '(' | ')' => {
+                end_word(&mut word, &mut tokens)?;
+                tokens.push(if ch == '(' { Token::Open } else { Token::Close });
+            }
+            ch if ch.is_whitespace() => end_word(&mut word, &mut tokens)?,
+            ch => word.push(ch),
+        }
+    }
+    end_word(&mut word, &mut tokens)?;
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens: `OR` binds loosest, then `AND` (also between terms
+/// with no operator), then `NOT`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Parentheses open at the position
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn any(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut options = vec![self.all()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            if matches!(self.peek(), None | Some(Token::Close | Token::Or)) {
+                return Err("OR needs a term after it".into());
+            }
+            options.push(self.all()?);
+        }
+        Ok(match options.len() {
+            1 => options.remove(0),
+            _ => Node::Any(options),
+        })
+    }
+
+    fn all(&mut self) -> Result<Node, Box<dyn Error>> {
+        let mut all = Vec::new();
+        let mut none = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) => break,
+                Some(Token::Or) if all.is_empty() && none.is_empty() => {
+                    return Err("OR needs a term before it".into());
+                }
+                Some(Token::Or) => break,
+                Some(Token::And) if all.is_empty() && none.is_empty() => {
+                    return Err("AND needs a term before it".into());
+                }
+                Some(Token::And) => {
+                    self.next();
+                    if matches!(
+                        self.peek(),
+                        None | Some(Token::Close | Token::Or | Token::And)
+                    ) {
+                        return Err("AND needs a term after it".into());
+                    }
+                }
+                Some(Token::Not) => {
+                    self.next();
+                    if matches!(
+                        self.peek(),
+                        None | Some(Token::Close | Token::Or | Token::And)
+                    ) {
+                        return Err("NOT needs a term after it".into());
+                    }
+                    none.push(self.primary()?);
+                }
+                Some(_) => all.push(self.primary()?),
+            }
+        }
+        if all.is_empty() && !none.is_empty() {
+            return Err("NOT only leaves out notes from others, e.g. 'rust NOT tutorial'".into());
+        }
+        if all.is_empty() {
+            return Err("Empty group in search".into());
+        }
+        Ok(match (all.len(), none.is_empty()) {
+            (1, true) => all.remove(0),
+            _ => Node::All { all, none },
+        })
+    }
+
+    fn primary(&mut self) -> Result<Node, Box<dyn Error>> {
+        match self.next() {
+            Some(Token::Open) => {
+                if self.depth == MAX_DEPTH {
+                    return Err(
+                        format!("Parentheses nest more than {} deep in search", MAX_DEPTH).into(),
+                    );
+                }
+                self.depth += 1;
+                let node = self.any()?;
+                self.depth -= 1;
+                match self.next() {
+                    Some(Token::Close) => Ok(node),
+                    _ => Err("Missing ')' in search".into()),
+                }
+            }
+            Some(Token::Term {
+                field,
+                text,
+                quoted,
+                prefix,
+            }) => {
+                let column = match field.as_deref() {
+                    None => None,
+                    Some("title") => Some("title"),
+                    Some("heading") => Some("heading"),
+                    Some("body") => Some("text"),
+                    Some("path") => {
+                        let prefix = text.trim_start_matches('/').to_string();
+                        return Ok(Node::Filter(Filter::Path(prefix)));
+                    }
+                    Some(field) => {
+                        let key = field[PROPERTY_PREFIX.len()..].to_string();
+                        return Ok(Node::Filter(Filter::Property(key, text)));
+                    }
+                };
+                if text.trim().is_empty() {
+                    return Err(match field {
+                        Some(field) => format!("Nothing to match after '{}:'", field).into(),
+                        None => "Empty phrase in search".into(),
+                    });
+                }
+                let mut fts = fts_string(&text);
+                if prefix {
+                    fts.push_str(" *");
+                }
+                if let Some(column) = column {
+                    fts = format!("{} : {}", column, fts);
+                }
+                let plain = (column.is_none() && !quoted && !prefix).then(|| text.clone());
+                Ok(Node::Term {
+                    fts,
+                    words: text,
+                    plain,
+                })
+            }
+            Some(Token::Close) => Err("Unmatched ')' in search".into()),
+            _ => Err("Expected a term in search".into()),
+        }
+    }
+}
+
+impl Node {
+    /// Drops plain stop words from every group of terms that has something else to match.
+    fn without_stop_words(self) -> Node {
+        match self {
+            Node::All { all, none } => {
+                let all: Vec<Node> = all.into_iter().map(Node::without_stop_words).collect();
+                let is_stop = |node: &Node| matches!(node, Node::Term { plain: Some(word), .. } if collation::is_stop_word(word));
+                let kept: Vec<Node> = match all
+                    .iter()
+                    .all(|node| is_stop(node) || matches!(node, Node::Filter(_)))
+                {
+                    true => all,
+                    false => all.into_iter().filter(|node| !is_stop(node)).collect(),
+                };
+                Node::All {
+                    all: kept,
+                    none: none.into_iter().map(Node::without_stop_words).collect(),
+                }
+            }
+            Node::Any(options) => {
+                Node::Any(options.into_iter().map(Node::without_stop_words).collect())
+            }
+            node => node,
+        }
+    }
+
+    /// The FTS5 expression for the node, or `None` for filters.
+    fn fts(&self) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(match self {
+            Node::Term { fts, .. } => Some(fts.clone()),
+            Node::Filter(_) => {
+                return Err(
+                    "path: and frontmatter. terms narrow down the whole search; \
+                            they cannot be used with OR or NOT, or in parentheses"
+                        .into(),
+                );
+            }
+            Node::All { all, none } => {
+                let all = all.iter().map(Node::fts).collect::<Result<Vec<_>, _>>()?;
+                let all: Vec<String> = all.into_iter().flatten().collect();
+                let mut fts = group(&all, " AND ");
+                if !none.is_empty() {
+                    let none = none.iter().map(Node::fts).collect::<Result<Vec<_>, _>>()?;
+                    let none: Vec<String> = none.into_iter().flatten().collect();
+                    fts = format!("({} NOT {})", fts, group(&none, " OR "));
+                }
+                Some(fts)
+            }
+            Node::Any(options) => {
+                let options = options
+                    .iter()
+                    .map(Node::fts)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let options: Vec<String> = options.into_iter().flatten().collect();
+                Some(group(&options, " OR "))
+            }
+        })
+    }
+
+    /// Words of the terms a matching note has, leaving out the excluded ones.
+    fn words(&self, words: &mut Vec<String>) {
+        match self {
+            Node::Term { words: text, .. } => words.push(text.clone()),
+            Node::Filter(_) => {}
+            Node::All { all, .. } => all.iter().for_each(|node| node.words(words)),
+            Node::Any(options) => options.iter().for_each(|node| node.words(words)),
+        }
+    }
+}
+
+/// `parts` joined with `operator`, in parentheses when there are several.
+fn group(parts: &[String], operator: &str) -> String {
+    match parts {
+        [one] => one.clone(),
+        parts => format!("({})", parts.join(operator)),
+    }
+}
+
+impl SearchQuery {
+    /// Parses `input`, with an error saying what is wrong when it is not valid syntax.
+    pub fn parse(input: &str) -> Result<SearchQuery, Box<dyn Error>> {
+        let tokens = lex(input)?;
+        let mut parsed = SearchQuery::default();
+        if tokens.is_empty() {
+            return Ok(parsed);
+        }
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+        let node = parser.any()?;
+        if parser.position < parser.tokens.len() {
+            return Err("Unmatched ')' in search".into());
+        }
+
+        // Filters are only allowed alongside the terms at the top
+        let node = match node.without_stop_words() {
+            Node::Filter(filter) => {
+                parsed.add_filter(filter);
+                None
+            }
+            Node::All { all, none } => {
+                let mut terms = Vec::new();
+                for node in all {
+                    match node {
+                        Node::Filter(filter) => parsed.add_filter(filter),
+                        node => terms.push(node),
+                    }
+                }
+                match (terms.is_empty(), none.is_empty()) {
+                    (true, true) => None,
+                    (true, false) => {
+                        return Err(
+                            "NOT only leaves out notes from others, e.g. 'rust NOT tutorial'"
+                                .into(),
+                        );
+                    }
+                    _ => Some(Node::All { all: terms, none }),
+                }
+            }
+            node => Some(node),
+        };
+        if let Some(node) = node {
+            parsed.fts = node.fts()?.unwrap_or_default();
+            let mut words = Vec::new();
+            node.words(&mut words);
+            parsed.text = words.join(" ");
+        }
+        Ok(parsed)
+    }
+
+    fn add_filter(&mut self, filter: Filter) {
+        match filter {
+            Filter::Path(prefix) => self.paths.push(prefix),
+            Filter::Property(key, value) => self.properties.push((key, value)),
+        }
+    }
+
+    pub fn has_filters(&self) -> bool {
+        !self.paths.is_empty() || !self.properties.is_empty()
+    }
+
+    /// SQL conditions on the chunks aliased `c` for the filters, each starting with `AND`,
+    /// and the values to bind to them in order.
+    pub fn filter_sql(&self) -> (String, Vec<&str>) {
+        let mut sql = String::new();
+        let mut values = Vec::new();
+        for prefix in &self.paths {
+            sql.push_str(" AND instr(c.path, ?) = 1");
+            values.push(prefix.as_str());
+        }
+        for (key, value) in &self.properties {
+            sql.push_str(
+                " AND c.path IN (SELECT path FROM properties
+                                 WHERE key = ? AND value = ? COLLATE NOCASE)",
+            );
+            values.push(key.as_str());
+            values.push(value.as_str());
+        }
+        (sql, values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fts(input: &str) -> String {
+        SearchQuery::parse(input).unwrap().fts
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(fts("rust borrow"), r#"("rust" AND "borrow")"#);
+        assert_eq!(
+            fts(r#"(rust OR go) "error handling" NOT panic*"#),
+            r#"((("rust" OR "go") AND "error handling") NOT "panic" *)"#
+        );
+        assert_eq!(
+            fts(r#"heading:"design review" title:plan*"#),
+            r#"(heading : "design review" AND title : "plan" *)"#
+        );
+        assert_eq!(fts(r#"say "AND" c++"#), r#"("say" AND "AND" AND "c++")"#);
+
+        let query = SearchQuery::parse("rust path:/Projects/ frontmatter.client:acme").unwrap();
+        assert_eq!(query.fts, r#""rust""#);
+        assert_eq!(query.paths, ["Projects/"]);
+        assert_eq!(
+            query.properties,
+            [(String::from("client"), String::from("acme"))]
+        );
+        assert_eq!(SearchQuery::parse("path:a").unwrap().fts, "");
+        assert_eq!(SearchQuery::parse("a OR b NOT c").unwrap().text, "a b");
+
+        for (input, error) in [
+            ("\"open", "Unterminated quote"),
+            ("(rust", "Missing ')'"),
+            ("rust)", "Unmatched ')'"),
+            ("OR rust", "OR needs a term before it"),
+            ("rust OR", "OR needs a term after it"),
+            ("NOT rust", "NOT only leaves out"),
+            ("*", "needs a word before it"),
+            ("rust OR path:a", "cannot be used with OR"),
+            ("()", "Empty group"),
+            ("title:", "Nothing to match after 'title:'"),
+        ] {
+            let message = SearchQuery::parse(input).unwrap_err().to_string();
+            assert!(message.contains(error), "{}: {}", input, message);
+        }
+    }
+
+    #[test]
+    fn test_nesting_is_capped() {
+        let nested = |depth| format!("{}rust{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(fts(&nested(MAX_DEPTH)), r#""rust""#);
+        let message = SearchQuery::parse(&nested(100_000))
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("nest more than"), "{}", message);
+    }
+}
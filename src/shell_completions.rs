@@ -0,0 +1,101 @@
+//! Shell completion scripts. The static part comes from clap; for bash, zsh and fish a
+//! wrapper completes note arguments from `find --porcelain` when the script runs.
+
+use crate::cli::{Cli, CompletionsArgs};
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+use std::io;
+
+static BIN_NAME: &str = "obsidian-rs";
+
+/// Subcommands whose first positional argument names a note.
+fn note_commands() -> Vec<String> {
+    Cli::command()
+        .get_subcommands()
+        .filter(|command| {
+            command
+                .get_positionals()
+                .next()
+                .is_some_and(|arg| ["note", "target"].contains(&arg.get_id().as_str()))
+        })
+        .map(|command| command.get_name().to_string())
+        .collect()
+}
+
+/// Completes note names for `commands` ahead of the clap-generated function.
+fn note_wrapper(shell: Shell, commands: &[String]) -> Option<String> {
+    let notes = format!("{} find --porcelain 2>/dev/null | cut -f1", BIN_NAME);
+    // clap's bash script escapes `-` in the function name as `__`
+    let bash_function = format!("_{}", BIN_NAME.replace('-', "__"));
+    let wrapper = match shell {
+        Shell::Bash => format!(
+            r#"
+_obsidian_rs_notes() {{
+    if [[ $COMP_CWORD -eq 2 && "${{COMP_WORDS[2]}}" != -* ]]; then
+        case "${{COMP_WORDS[1]}}" in
+            {cases})
+                local IFS=$'\n'
+                COMPREPLY=($(compgen -W "$({notes})" -- "${{COMP_WORDS[2]}}"))
+                return 0
+                ;;
+        esac
+    fi
+    {function} "$@"
+}}
+complete -F _obsidian_rs_notes -o bashdefault -o default {bin}
+"#,
+            cases = commands.join("|"),
+            notes = notes,
+            function = bash_function,
+            bin = BIN_NAME,
+        ),
+        Shell::Zsh => format!(
+            r#"
+_obsidian_rs_notes() {{
+    if (( CURRENT == 3 )) && [[ ${{words[2]}} == ({cases}) && ${{words[3]}} != -* ]]; then
+        local -a notes
+        notes=("${{(@f)$({notes})}}")
+        compadd -a notes
+    else
+        _{bin} "$@"
+    fi
+}}
+compdef _obsidian_rs_notes {bin}
+"#,
+            cases = commands.join("|"),
+            notes = notes,
+            bin = BIN_NAME,
+        ),
+        Shell::Fish => format!(
+            "complete -c {bin} -n \"__fish_seen_subcommand_from {commands}\" -f -a \"({notes})\"\n",
+            bin = BIN_NAME,
+            commands = commands.join(" "),
+            notes = notes,
+        ),
+        _ => return None,
+    };
+    Some(wrapper)
+}
+
+pub fn run_completions(args: &CompletionsArgs) {
+    let mut stdout = io::stdout();
+    clap_complete::generate(args.shell, &mut Cli::command(), BIN_NAME, &mut stdout);
+    if let Some(wrapper) = note_wrapper(args.shell, &note_commands()) {
+        print!("{}", wrapper);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_commands() {
+        let commands = note_commands();
+        for name in ["backlinks", "block-ref", "link-to", "similar"] {
+            assert!(commands.iter().any(|c| c == name), "{}", name);
+        }
+        assert!(!commands.iter().any(|c| c == "search"));
+    }
+}
@@ -0,0 +1,534 @@
+use crate::cli::ExportHtmlArgs;
+use crate::config::AppConfig;
+use crate::content_store;
+use crate::data::{self, Note};
+use crate::feed;
+use crate::frontmatter;
+use crate::previews::{self, Preview};
+use crate::query::Query;
+use crate::render;
+use crate::resolver::{self, Resolver};
+use crate::util::{self, xml_escape};
+use crate::watcher;
+
+use jiff::tz::TimeZone;
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fs,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+/// Where a published note ends up on the site.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub note: PathBuf,
+    /// Output file, relative to the export directory
+    pub file: PathBuf,
+    /// URL path relative to the site root, already percent-encoded
+    pub url: String,
+    /// URL the note would have had without a permalink
+    pub default_url: String,
+}
+
+fn front_matter_value(note: &Note, key: &str) -> Option<Value> {
+    frontmatter::parse_mapping(&note.content)
+        .ok()
+        .flatten()?
+        .get(key)
+        .cloned()
+}
+
+/// The `publish` front matter flag, if the note sets one.
+pub fn publish_flag(note: &Note) -> Option<bool> {
+    match front_matter_value(note, "publish")? {
+        Value::Bool(flag) => Some(flag),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+/// The note's `permalink`, without surrounding slashes. One with `.`, `..` or a drive
+/// in it could land outside the export, so it is ignored.
+pub fn permalink(note: &Note) -> Option<String> {
+    let value = front_matter_value(note, "permalink")?;
+    let permalink = value.as_str()?.trim().trim_matches('/');
+    if Path::new(permalink)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        log::warn!(
+            "Ignoring the permalink '{}' of {}",
+            permalink,
+            note.path.display()
+        );
+        return None;
+    }
+    (!permalink.is_empty()).then(|| permalink.to_string())
+}
+
+fn default_url(path: &Path) -> String {
+    let mut path = path.to_path_buf();
+    path.set_extension("html");
+    util::percent_encode(&path.to_string_lossy().replace('\\', "/"))
+}
+
+pub fn page_for(note: &Note) -> Page {
+    let default_url = default_url(&note.path);
+    match permalink(note) {
+        Some(permalink) => Page {
+            note: note.path.clone(),
+            file: Path::new(&permalink).join("index.html"),
+            url: format!("{}/", util::percent_encode(&permalink)),
+            default_url,
+        },
+        None => {
+            let mut file = note.path.clone();
+            file.set_extension("html");
+            Page {
+                note: note.path.clone(),
+                file,
+                url: default_url.clone(),
+                default_url,
+            }
+        }
+    }
+}
+
+/// Notes to publish: those matching `query`, minus `publish: false`, and, when
+/// `published_only` is set, minus everything without `publish: true`.
+pub fn select_notes<'a>(notes: &'a [Note], query: &Query, published_only: bool) -> Vec<&'a Note> {
+    query
+        .filter(notes)
+        .into_iter()
+        .filter(|note| match publish_flag(note) {
+            Some(flag) => flag,
+            None => !published_only,
+        })
+        .collect()
+}
+
+/// Absolute URL for `url_path` when a base URL is known, root-relative otherwise.
+fn absolute(base_url: Option<&str>, url_path: &str) -> String {
+    format!(
+        "{}/{}",
+        base_url.unwrap_or_default().trim_end_matches('/'),
+        url_path
+    )
+}
+
+pub fn render_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<article>\n{}</article>\n</body>\n</html>\n",
+        xml_escape(title),
+        body
+    )
+}
+
+fn render_redirect(target: &str) -> String {
+    let target = xml_escape(target);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta http-equiv=\"refresh\" content=\"0; url={0}\">\n<link rel=\"canonical\" href=\"{0}\">\n</head>\n<body><a href=\"{0}\">{0}</a></body>\n</html>\n",
+        target
+    )
+}
+
+pub fn render_sitemap(urls: &[(String, String)]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for (loc, lastmod) in urls {
+        xml.push_str(&format!(
+            "  <url><loc>{}</loc><lastmod>{}</lastmod></url>\n",
+            xml_escape(loc),
+            lastmod
+        ));
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+/// Redirects from the default location of every note that has a permalink.
+pub fn redirects(pages: &[Page]) -> Vec<(String, String)> {
+    pages
+        .iter()
+        .filter(|page| page.url != page.default_url)
+        .map(|page| (page.default_url.clone(), page.url.clone()))
+        .collect()
+}
+
+fn write_file(path: &Path, content: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Files written by the previous export, with the hash of what each was rendered from
+static MANIFEST_FILE: &str = ".obsidian-rs-export.json";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ExportStats {
+    pub rendered: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+}
+
+/// The export directory, tracking which generated files are still up to date.
+struct Output {
+    dir: PathBuf,
+    previous: BTreeMap<String, String>,
+    current: BTreeMap<String, String>,
+    stats: ExportStats,
+}
+
+impl Output {
+    fn open(dir: &Path) -> Output {
+        let previous = fs::read_to_string(dir.join(MANIFEST_FILE))
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Output {
+            dir: dir.to_path_buf(),
+            previous,
+            current: BTreeMap::new(),
+            stats: ExportStats::default(),
+        }
+    }
+
+    /// Writes `file` unless it already exists and was rendered from the same `key`.
+    fn emit(
+        &mut self,
+        file: &Path,
+        key: &str,
+        render: impl FnOnce() -> String,
+    ) -> Result<(), Box<dyn Error>> {
+        let name = file.to_string_lossy().replace('\\', "/");
+        let hash = util::content_hash(key.as_bytes());
+        let path = self.dir.join(file);
+        if self.previous.get(&name) == Some(&hash) && path.exists() {
+            self.stats.unchanged += 1;
+        } else {
+            write_file(&path, &render())?;
+            self.stats.rendered += 1;
+        }
+        self.current.insert(name, hash);
+        Ok(())
+    }
+
+    /// Copies the attachment `file` from `vault_path` unless the export has a copy at
+    /// least as new, and keeps it in the manifest.
+    fn attach(&mut self, vault_path: &Path, file: &Path) -> Result<(), Box<dyn Error>> {
+        copy_if_newer(&vault_path.join(file), &self.dir.join(file))?;
+        let name = file.to_string_lossy().replace('\\', "/");
+        self.current.insert(name, String::new());
+        Ok(())
+    }
+
+    /// Deletes files left over from the previous export and saves the new manifest.
+    /// Entries pointing outside the export directory, from a manifest edited by hand or
+    /// a symlink, are left alone.
+    fn finish(mut self) -> Result<ExportStats, Box<dyn Error>> {
+        for name in self.previous.keys() {
+            if !self.current.contains_key(name) {
+                let path = self.dir.join(name);
+                if !util::is_within(&path, &self.dir) {
+                    log::warn!("Not removing {}: outside the export", path.display());
+                    continue;
+                }
+                if path.exists() {
+                    fs::remove_file(&path)?;
+                }
+                self.stats.removed += 1;
+            }
+        }
+        write_file(
+            &self.dir.join(MANIFEST_FILE),
+            &serde_json::to_string_pretty(&self.current)?,
+        )?;
+        Ok(self.stats)
+    }
+}
+
+fn copy_if_newer(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    if to.exists() && modified(to) >= modified(from) {
+        return Ok(());
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(from, to)?;
+    Ok(())
+}
+
+/// Exports the selected notes to `out`. Pages whose content and resolved links are
+/// unchanged since the last export are left alone, so editing a note only re-renders
+/// it and the pages whose links to it changed.
+pub fn export_site(
+    vault_path: &Path,
+    out: &Path,
+    base_url: Option<&str>,
+    query: &Query,
+    published_only: bool,
+    previews: &HashMap<String, Preview>,
+) -> Result<ExportStats, Box<dyn Error>> {
+    let notes = content_store::load_notes(vault_path)?;
+    let resolver = Resolver::from_vault(vault_path)?;
+    let selected = select_notes(&notes, query, published_only);
+    let pages: Vec<Page> = selected.iter().map(|note| page_for(note)).collect();
+    let urls: HashMap<&Path, &str> = pages
+        .iter()
+        .map(|page| (page.note.as_path(), page.url.as_str()))
+        .collect();
+
+    let mut output = Output::open(out);
+    let mut attachments = BTreeSet::new();
+    let mut sitemap = Vec::new();
+    for (note, page) in selected.iter().zip(&pages) {
+        attachments.extend(
+            resolver::linked_files(note, &resolver)
+                .into_iter()
+                .filter(|file| !data::is_note(file)),
+        );
+        let href = |target: &Path| {
+            if data::is_note(target) {
+                urls.get(target).map(|url| absolute(base_url, url))
+            } else {
+                Some(absolute(
+                    base_url,
+                    &util::percent_encode(&target.to_string_lossy().replace('\\', "/")),
+                ))
+            }
+        };
+        // External links carry their page's title for hovering, when previews are on
+        let annotated = previews::annotate(&note.content, previews);
+        let note = &Note::from_content(note.path.clone(), annotated);
+        let expanded = render::expand_links(note, &resolver, href);
+        let title = note.title();
+        let key = format!("{}\0{}", title, expanded);
+        output.emit(&page.file, &key, || {
+            let body = render::to_html(frontmatter::split(&expanded).1);
+            render_page(&title, &body)
+        })?;
+
+        let (_, updated) = feed::note_times(note);
+        let lastmod = updated
+            .to_zoned(TimeZone::UTC)
+            .strftime("%Y-%m-%d")
+            .to_string();
+        sitemap.push((absolute(base_url, &page.url), lastmod));
+    }
+    for attachment in &attachments {
+        output.attach(vault_path, attachment)?;
+    }
+
+    let redirects = redirects(&pages);
+    let page_files: BTreeSet<&Path> = pages.iter().map(|page| page.file.as_path()).collect();
+    for (from, to) in &redirects {
+        let stub = PathBuf::from(util::percent_decode(from));
+        if !page_files.contains(stub.as_path()) {
+            let target = absolute(base_url, to);
+            output.emit(&stub, &target, || render_redirect(&target))?;
+        }
+    }
+    let redirect_map: String = redirects
+        .iter()
+        .map(|(from, to)| format!("/{} /{} 301\n", from, to))
+        .collect();
+    write_file(&out.join("_redirects"), &redirect_map)?;
+
+    match base_url {
+        Some(_) => write_file(&out.join("sitemap.xml"), &render_sitemap(&sitemap))?,
+        None => log::warn!(
+            "No base URL set, skipping sitemap.xml (pass --base-url or set [publish] base_url)"
+        ),
+    }
+
+    let stats = output.finish()?;
+    log::info!(
+        "Exported {} page(s) to {}: {} rendered, {} unchanged, {} removed; {} attachment(s).",
+        pages.len(),
+        out.display(),
+        stats.rendered,
+        stats.unchanged,
+        stats.removed,
+        attachments.len()
+    );
+    Ok(stats)
+}
+
+pub fn run_export_html(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &ExportHtmlArgs,
+) -> Result<(), Box<dyn Error>> {
+    let publish = &config.publish;
+    let out = util::expand_tilde(&args.out)
+        .map(|p| p.into_owned())
+        .ok_or("Failed to expand output path")?;
+    if util::is_within(&out, vault_path) {
+        return Err("The export directory must be outside the vault".into());
+    }
+    let base_url = args.base_url.clone().or_else(|| publish.base_url.clone());
+    let query = Query::parse(&args.query)?;
+    let previews = previews::for_export(config);
+
+    export_site(
+        vault_path,
+        &out,
+        base_url.as_deref(),
+        &query,
+        args.published_only,
+        &previews,
+    )?;
+    if !args.watch {
+        return Ok(());
+    }
+
+    watcher::watch_debounced(vault_path, Duration::from_millis(500), |paths| {
+        if paths.iter().all(|path| util::is_hidden_path(vault_path, path)) {
+            return;
+        }
+        if let Err(e) = content_store::refresh_paths(vault_path, &paths) {
+            log::error!("Error updating stored note contents: {}", e);
+        }
+        if let Err(e) = export_site(
+            vault_path,
+            &out,
+            base_url.as_deref(),
+            &query,
+            args.published_only,
+            &previews,
+        ) {
+            log::error!("Export failed: {}", e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    #[test]
+    fn test_page_for_permalink() {
+        let page = page_for(&note(
+            "blog/My Post.md",
+            "---\npermalink: /posts/hello/\n---\n",
+        ));
+        assert_eq!(page.file, PathBuf::from("posts/hello/index.html"));
+        assert_eq!(page.url, "posts/hello/");
+        assert_eq!(page.default_url, "blog/My%20Post.html");
+        assert_eq!(
+            redirects(&[page]),
+            vec![(
+                String::from("blog/My%20Post.html"),
+                String::from("posts/hello/")
+            )]
+        );
+
+        let page = page_for(&note("Plain.md", "text"));
+        assert_eq!(page.file, PathBuf::from("Plain.html"));
+        assert!(redirects(&[page]).is_empty());
+    }
+
+    #[test]
+    fn test_permalink_stays_inside_the_export() {
+        for permalink in ["../../.config/x", "posts/../../x", "./x"] {
+            let content = format!("---\npermalink: {}\n---\n", permalink);
+            let page = page_for(&note("Post.md", &content));
+            assert_eq!(page.file, PathBuf::from("Post.html"), "{}", permalink);
+        }
+    }
+
+    #[test]
+    fn test_export_site_is_incremental() {
+        // The default ".tmp" prefix would make the vault a hidden folder
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        fs::write(vault.path().join("A.md"), "[[B]]").unwrap();
+        fs::write(vault.path().join("B.md"), "b").unwrap();
+        fs::write(vault.path().join("C.md"), "c").unwrap();
+        let export = || {
+            export_site(
+                vault.path(),
+                out.path(),
+                None,
+                &Query::default(),
+                false,
+                &HashMap::new(),
+            )
+        };
+
+        assert_eq!(export().unwrap().rendered, 3);
+        assert_eq!(export().unwrap().unchanged, 3);
+
+        // A permalink on B changes its URL, so A (which links to it) is re-rendered too
+        fs::write(vault.path().join("B.md"), "---\npermalink: bee\n---\nb").unwrap();
+        fs::remove_file(vault.path().join("C.md")).unwrap();
+        let stats = export().unwrap();
+        assert_eq!((stats.rendered, stats.unchanged, stats.removed), (3, 0, 1));
+        assert!(out.path().join("bee/index.html").exists());
+        assert!(!out.path().join("C.html").exists());
+        let a = fs::read_to_string(out.path().join("A.html")).unwrap();
+        assert!(a.contains("href=\"/bee/\""));
+    }
+
+    #[test]
+    fn test_export_site_prunes_attachments_inside_out_only() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let out = root.path().join("site");
+        fs::write(vault.path().join("A.md"), "![[a.png]]").unwrap();
+        fs::write(vault.path().join("a.png"), "png").unwrap();
+        let export = || {
+            export_site(
+                vault.path(),
+                &out,
+                None,
+                &Query::default(),
+                false,
+                &HashMap::new(),
+            )
+        };
+        export().unwrap();
+        assert!(out.join("a.png").exists());
+
+        // A manifest naming a file outside the export must not get it deleted
+        fs::write(root.path().join("keep.txt"), "mine").unwrap();
+        let manifest = out.join(MANIFEST_FILE);
+        let mut entries: BTreeMap<String, String> =
+            serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+        entries.insert(String::from("../keep.txt"), String::new());
+        fs::write(&manifest, serde_json::to_string(&entries).unwrap()).unwrap();
+
+        fs::write(vault.path().join("A.md"), "no image").unwrap();
+        export().unwrap();
+        assert!(!out.join("a.png").exists());
+        assert!(root.path().join("keep.txt").exists());
+    }
+
+    #[test]
+    fn test_select_notes_honors_publish_flag() {
+        let notes = vec![
+            note("Yes.md", "---\npublish: true\n---\n"),
+            note("No.md", "---\npublish: false\n---\n"),
+            note("Unset.md", "text"),
+        ];
+        let query = Query::default();
+        let names = |published_only| -> Vec<String> {
+            select_notes(&notes, &query, published_only)
+                .iter()
+                .map(|n| n.title())
+                .collect()
+        };
+        assert_eq!(names(false), vec!["Yes", "Unset"]);
+        assert_eq!(names(true), vec!["Yes"]);
+    }
+}
@@ -0,0 +1,471 @@
+//! Daily snapshots of the vault kept in the cache: each note with its word and link
+//! counts, and how many notes carry each tag. The first refresh of the index on a day
+//! takes that day's snapshot and prunes those older than `cache.snapshot_days`.
+//! `stats --since` charts them up to the vault as it is now, and `diff-index` compares
+//! one with it.
+
+use crate::cli::{DiffIndexArgs, OutputFormat, StatsArgs};
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::date_query;
+use crate::index;
+use crate::markdown;
+use crate::search;
+use crate::util;
+use crate::writing;
+
+use jiff::{Span, Zoned, civil};
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{collections::BTreeMap, error::Error, path::Path};
+
+/// Width of the widest bar in the `stats` chart
+const CHART_WIDTH: usize = 40;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq)]
+pub struct NoteCounts {
+    pub words: i64,
+    /// Links going out of the note
+    pub links: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub notes: BTreeMap<String, NoteCounts>,
+    /// Notes per tag
+    pub tags: BTreeMap<String, i64>,
+}
+
+/// Vault totals on one day, for charting growth
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct GrowthPoint {
+    pub day: String,
+    pub notes: i64,
+    pub words: i64,
+    pub links: i64,
+    pub tags: i64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NoteChange {
+    pub path: String,
+    pub words: i64,
+    pub links: i64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TagChange {
+    pub tag: String,
+    pub before: i64,
+    pub after: i64,
+}
+
+/// What changed between two snapshots
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct SnapshotDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Notes whose word or link count changed, by how much
+    pub changed: Vec<NoteChange>,
+    pub tags: Vec<TagChange>,
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS snapshot_notes (
+            day TEXT NOT NULL,
+            path TEXT NOT NULL,
+            words INTEGER NOT NULL,
+            links INTEGER NOT NULL,
+            PRIMARY KEY (day, path)
+        );
+        CREATE TABLE IF NOT EXISTS snapshot_tags (
+            day TEXT NOT NULL,
+            tag TEXT NOT NULL,
+            notes INTEGER NOT NULL,
+            PRIMARY KEY (day, tag)
+        );",
+    )
+}
+
+/// The snapshot of `notes` as they are now.
+pub fn capture(notes: &[Note]) -> Snapshot {
+    let mut snapshot = Snapshot::default();
+    for note in notes {
+        let path = note.path.to_string_lossy().replace('\\', "/");
+        let counts = NoteCounts {
+            words: writing::word_count(&note.content) as i64,
+            links: markdown::parse_links(&note.content).len() as i64,
+        };
+        snapshot.notes.insert(path, counts);
+        for tag in &note.tags {
+            *snapshot.tags.entry(tag.to_lowercase()).or_default() += 1;
+        }
+    }
+    snapshot
+}
+
+fn save(connection: &Connection, day: &str, snapshot: &Snapshot) -> Result<(), sqlite::Error> {
+    connection.execute("BEGIN")?;
+    for table in ["snapshot_notes", "snapshot_tags"] {
+        let mut statement = connection.prepare(format!("DELETE FROM {} WHERE day = ?", table))?;
+        statement.bind((1, day))?;
+        statement.next()?;
+    }
+    for (path, counts) in &snapshot.notes {
+        let mut statement = connection
+            .prepare("INSERT INTO snapshot_notes (day, path, words, links) VALUES (?, ?, ?, ?)")?;
+        statement.bind((1, day))?;
+        statement.bind((2, path.as_str()))?;
+        statement.bind((3, counts.words))?;
+        statement.bind((4, counts.links))?;
+        statement.next()?;
+    }
+    for (tag, notes) in &snapshot.tags {
+        let mut statement =
+            connection.prepare("INSERT INTO snapshot_tags (day, tag, notes) VALUES (?, ?, ?)")?;
+        statement.bind((1, day))?;
+        statement.bind((2, tag.as_str()))?;
+        statement.bind((3, *notes))?;
+        statement.next()?;
+    }
+    connection.execute("COMMIT")
+}
+
+fn has_snapshot(connection: &Connection, day: &str) -> Result<bool, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT 1 FROM snapshot_notes WHERE day = ? LIMIT 1")?;
+    statement.bind((1, day))?;
+    Ok(matches!(statement.next()?, State::Row))
+}
+
+/// Drops the snapshots more than `keep_days` before `today`; 0 keeps them all.
+fn prune(connection: &Connection, today: civil::Date, keep_days: u32) -> Result<(), sqlite::Error> {
+    let cutoff = Span::new()
+        .try_days(i64::from(keep_days))
+        .ok()
+        .and_then(|span| today.checked_sub(span).ok());
+    let Some(cutoff) = cutoff.filter(|_| keep_days > 0) else {
+        return Ok(());
+    };
+    for table in ["snapshot_notes", "snapshot_tags"] {
+        let mut statement = connection.prepare(format!("DELETE FROM {} WHERE day < ?", table))?;
+        statement.bind((1, cutoff.to_string().as_str()))?;
+        statement.next()?;
+    }
+    Ok(())
+}
+
+/// Takes the snapshot of `today` unless there is one already, returning whether it did.
+pub fn update(
+    connection: &Connection,
+    notes: &[Note],
+    today: civil::Date,
+    keep_days: u32,
+) -> Result<bool, sqlite::Error> {
+    ensure_schema(connection)?;
+    let day = today.to_string();
+    if has_snapshot(connection, &day)? {
+        return Ok(false);
+    }
+    save(connection, &day, &capture(notes))?;
+    prune(connection, today, keep_days)?;
+    Ok(true)
+}
+
+/// The snapshot of the vault as it is now.
+fn current(vault_path: &Path) -> Result<Snapshot, Box<dyn Error>> {
+    Ok(capture(&data::load_notes(vault_path)?))
+}
+
+/// Totals of `snapshot`, charted as `day`.
+fn totals(day: String, snapshot: &Snapshot) -> GrowthPoint {
+    GrowthPoint {
+        day,
+        notes: snapshot.notes.len() as i64,
+        words: snapshot.notes.values().map(|counts| counts.words).sum(),
+        links: snapshot.notes.values().map(|counts| counts.links).sum(),
+        tags: snapshot.tags.len() as i64,
+    }
+}
+
+/// The latest day with a snapshot that is not after `day`.
+fn snapshot_day(
+    connection: &Connection,
+    day: civil::Date,
+) -> Result<Option<String>, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT MAX(day) FROM snapshot_notes WHERE day <= ?")?;
+    statement.bind((1, day.to_string().as_str()))?;
+    statement.next()?;
+    statement.read::<Option<String>, _>(0)
+}
+
+/// The snapshot stored for `day`.
+fn load(connection: &Connection, day: &str) -> Result<Snapshot, sqlite::Error> {
+    let mut snapshot = Snapshot::default();
+    let mut statement =
+        connection.prepare("SELECT path, words, links FROM snapshot_notes WHERE day = ?")?;
+    statement.bind((1, day))?;
+    while let State::Row = statement.next()? {
+        let counts = NoteCounts {
+            words: statement.read(1)?,
+            links: statement.read(2)?,
+        };
+        snapshot.notes.insert(statement.read(0)?, counts);
+    }
+    let mut statement = connection.prepare("SELECT tag, notes FROM snapshot_tags WHERE day = ?")?;
+    statement.bind((1, day))?;
+    while let State::Row = statement.next()? {
+        snapshot.tags.insert(statement.read(0)?, statement.read(1)?);
+    }
+    Ok(snapshot)
+}
+
+/// Totals of each snapshot from `since` on, oldest first.
+pub fn growth(
+    connection: &Connection,
+    since: civil::Date,
+) -> Result<Vec<GrowthPoint>, sqlite::Error> {
+    let mut statement = connection.prepare(
+        "SELECT day, COUNT(*), SUM(words), SUM(links),
+                (SELECT COUNT(*) FROM snapshot_tags WHERE snapshot_tags.day = snapshot_notes.day)
+         FROM snapshot_notes WHERE day >= ? GROUP BY day ORDER BY day",
+    )?;
+    statement.bind((1, since.to_string().as_str()))?;
+    let mut points = Vec::new();
+    while let State::Row = statement.next()? {
+        points.push(GrowthPoint {
+            day: statement.read(0)?,
+            notes: statement.read(1)?,
+            words: statement.read(2)?,
+            links: statement.read(3)?,
+            tags: statement.read(4)?,
+        });
+    }
+    Ok(points)
+}
+
+/// What changed from `old` to `new`.
+pub fn diff(old: &Snapshot, new: &Snapshot) -> SnapshotDiff {
+    let mut changes = SnapshotDiff::default();
+    for (path, counts) in &new.notes {
+        match old.notes.get(path) {
+            None => changes.added.push(path.clone()),
+            Some(before) if before != counts => changes.changed.push(NoteChange {
+                path: path.clone(),
+                words: counts.words - before.words,
+                links: counts.links - before.links,
+            }),
+            Some(_) => {}
+        }
+    }
+    changes.removed = old
+        .notes
+        .keys()
+        .filter(|path| !new.notes.contains_key(*path))
+        .cloned()
+        .collect();
+    let mut tags: Vec<&String> = old.tags.keys().chain(new.tags.keys()).collect();
+    tags.sort();
+    tags.dedup();
+    for tag in tags {
+        let before = old.tags.get(tag).copied().unwrap_or_default();
+        let after = new.tags.get(tag).copied().unwrap_or_default();
+        if before != after {
+            changes.tags.push(TagChange {
+                tag: tag.clone(),
+                before,
+                after,
+            });
+        }
+    }
+    changes
+}
+
+fn print_chart(points: &[GrowthPoint]) {
+    let most = points
+        .iter()
+        .map(|point| point.notes)
+        .max()
+        .unwrap_or_default();
+    for point in points {
+        let bar = (point.notes * CHART_WIDTH as i64 / most.max(1)) as usize;
+        println!(
+            "{}  {:>6} notes  {:>8} words  {:>6} links  {:>4} tags  {}",
+            point.day,
+            point.notes,
+            point.words,
+            point.links,
+            point.tags,
+            "#".repeat(bar)
+        );
+    }
+}
+
+/// Charts the vault's growth from the snapshots since `args.since`, today's point being
+/// the vault as it is now.
+pub fn run_stats(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &StatsArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    let today = Zoned::now().date();
+    let since = match &args.since {
+        Some(text) => date_query::period(text, today)?.0,
+        None => civil::Date::MIN,
+    };
+    let mut points = growth(&connection, since)?;
+    let now = totals(today.to_string(), &current(vault_path)?);
+    points.retain(|point| point.day != now.day);
+    if today >= since {
+        points.push(now);
+    }
+    match args.format {
+        OutputFormat::Text => print_chart(&points),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&points)?),
+        OutputFormat::Ndjson => {
+            for point in &points {
+                util::print_ndjson(point)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn signed(n: i64) -> String {
+    format!("{:+}", n)
+}
+
+/// Shows what changed in the vault since the snapshot of `args.date`, or the latest one
+/// before it, up to the vault as it is now.
+pub fn run_diff_index(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &DiffIndexArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    let today = Zoned::now().date();
+    let (date, _) = date_query::period(&args.date, today)?;
+    let day = snapshot_day(&connection, date)?.ok_or_else(|| {
+        format!(
+            "No index snapshot from {} or before; snapshots are kept from the first refresh on",
+            date
+        )
+    })?;
+    let changes = diff(&load(&connection, &day)?, &current(vault_path)?);
+
+    match args.format {
+        OutputFormat::Text => {
+            println!("Changes since {}:", day);
+            for path in &changes.added {
+                println!("A  {}", path);
+            }
+            for path in &changes.removed {
+                println!("D  {}", path);
+            }
+            for change in &changes.changed {
+                println!(
+                    "M  {}  ({} words, {} links)",
+                    change.path,
+                    signed(change.words),
+                    signed(change.links)
+                );
+            }
+            for tag in &changes.tags {
+                println!("#{}  {} -> {} notes", tag.tag, tag.before, tag.after);
+            }
+        }
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            println!("{}", serde_json::to_string(&changes)?)
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    #[test]
+    fn test_snapshots_over_time() {
+        let connection = sqlite::open(":memory:").unwrap();
+        let first = civil::date(2024, 1, 1);
+        let notes = vec![
+            note("A.md", "#draft one two [[B]]"),
+            note("B.md", "#draft three"),
+        ];
+        assert!(update(&connection, &notes, first, 0).unwrap());
+        // The day has its snapshot
+        assert!(!update(&connection, &notes, first, 0).unwrap());
+
+        let second = civil::date(2024, 1, 3);
+        let notes = vec![
+            note("A.md", "#draft one two [[B]] [[C]] four"),
+            note("C.md", "#idea five"),
+        ];
+        assert!(update(&connection, &notes, second, 0).unwrap());
+
+        let points = growth(&connection, civil::date(2024, 1, 2)).unwrap();
+        assert_eq!(
+            points,
+            vec![GrowthPoint {
+                day: String::from("2024-01-03"),
+                notes: 2,
+                words: 8,
+                links: 2,
+                tags: 2,
+            }]
+        );
+
+        let day = snapshot_day(&connection, civil::date(2024, 1, 2))
+            .unwrap()
+            .unwrap();
+        assert_eq!(day, "2024-01-01");
+        let changes = diff(
+            &load(&connection, &day).unwrap(),
+            &load(&connection, "2024-01-03").unwrap(),
+        );
+        assert_eq!(changes.added, vec!["C.md"]);
+        assert_eq!(changes.removed, vec!["B.md"]);
+        assert_eq!(
+            changes.changed,
+            vec![NoteChange {
+                path: String::from("A.md"),
+                words: 2,
+                links: 1,
+            }]
+        );
+        let tags: Vec<(&str, i64, i64)> = changes
+            .tags
+            .iter()
+            .map(|tag| (tag.tag.as_str(), tag.before, tag.after))
+            .collect();
+        assert_eq!(tags, vec![("draft", 2, 1), ("idea", 0, 1)]);
+    }
+
+    #[test]
+    fn test_old_snapshots_are_pruned() {
+        let connection = sqlite::open(":memory:").unwrap();
+        let notes = vec![note("A.md", "#draft one")];
+        for day in [1, 20, 31] {
+            assert!(update(&connection, &notes, civil::date(2024, 1, day), 14).unwrap());
+        }
+        let days: Vec<String> = growth(&connection, civil::Date::MIN)
+            .unwrap()
+            .into_iter()
+            .map(|point| point.day)
+            .collect();
+        assert_eq!(days, vec!["2024-01-20", "2024-01-31"]);
+        assert_eq!(
+            load(&connection, "2024-01-01").unwrap(),
+            Snapshot::default()
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Note status. Vaults mark drafts and finished notes in different ways (`draft: true`,
+//! `publish: false`, `status: archived`, a `cssclasses` entry); the `[status]` rules map
+//! them onto one [`NoteStatus`], so `status:draft` finds drafts whatever the convention.
+
+use crate::config::{NoteStatus, StatusConfig, StatusRule};
+use crate::data::Note;
+use crate::util;
+
+use serde_yaml::{Mapping, Value};
+use std::{error::Error, sync::OnceLock};
+
+static RULES: OnceLock<Vec<StatusRule>> = OnceLock::new();
+
+/// Uses the rules of `config` for the rest of the process.
+pub fn install(config: &StatusConfig) {
+    let _ = RULES.set(config.rules.clone());
+}
+
+fn rules() -> &'static [StatusRule] {
+    RULES.get_or_init(|| StatusConfig::default().rules)
+}
+
+/// Identifies the installed rules, so cached query results follow changes to them.
+pub fn fingerprint() -> String {
+    util::content_hash(format!("{:?}", rules()).as_bytes())
+}
+
+pub fn parse(name: &str) -> Result<NoteStatus, Box<dyn Error>> {
+    match name.to_lowercase().as_str() {
+        "draft" => Ok(NoteStatus::Draft),
+        "published" => Ok(NoteStatus::Published),
+        "archived" => Ok(NoteStatus::Archived),
+        _ => Err(format!(
+            "Unknown status '{}'; expected draft, published or archived",
+            name
+        )
+        .into()),
+    }
+}
+
+/// Whether the front matter `value` is `expected`, or contains it when it is a list.
+fn value_matches(value: &Value, expected: &toml::Value) -> bool {
+    match (value, expected) {
+        (Value::Sequence(items), _) => items.iter().any(|item| value_matches(item, expected)),
+        (Value::Bool(b), toml::Value::Boolean(expected)) => b == expected,
+        (Value::String(s), toml::Value::String(expected)) => s.eq_ignore_ascii_case(expected),
+        (Value::Number(n), toml::Value::Integer(expected)) => n.as_i64() == Some(*expected),
+        _ => false,
+    }
+}
+
+fn status_of(properties: &Mapping, rules: &[StatusRule]) -> Option<NoteStatus> {
+    rules
+        .iter()
+        .find(|rule| {
+            properties
+                .get(rule.property.as_str())
+                .is_some_and(|value| value_matches(value, &rule.value))
+        })
+        .map(|rule| rule.status)
+}
+
+/// The status the installed rules give `note`, if any.
+pub fn of(note: &Note) -> Option<NoteStatus> {
+    status_of(&note.properties, rules())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_of() {
+        let rules = StatusConfig::default().rules;
+        let status = |yaml: &str| status_of(&serde_yaml::from_str(yaml).unwrap(), &rules);
+        assert_eq!(status("draft: true"), Some(NoteStatus::Draft));
+        assert_eq!(status("publish: false"), Some(NoteStatus::Draft));
+        assert_eq!(status("publish: true"), Some(NoteStatus::Published));
+        assert_eq!(
+            status("status: Archived\ndraft: true"),
+            Some(NoteStatus::Archived)
+        );
+        assert_eq!(status("draft: false"), None);
+
+        let rules = vec![StatusRule {
+            property: String::from("cssclasses"),
+            value: "wip".into(),
+            status: NoteStatus::Draft,
+        }];
+        let properties = serde_yaml::from_str("cssclasses: [wide, wip]").unwrap();
+        assert_eq!(status_of(&properties, &rules), Some(NoteStatus::Draft));
+    }
+}
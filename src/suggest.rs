@@ -0,0 +1,253 @@
+use crate::changeset::ChangeSet;
+use crate::cli::{OutputFormat, SuggestTagsArgs};
+use crate::config::AppConfig;
+use crate::data::{self, Note};
+use crate::embeddings;
+use crate::frontmatter;
+use crate::index;
+use crate::resolver::Resolver;
+use crate::search;
+use crate::util;
+
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Most similar tagged notes whose tags are considered
+static NEIGHBOURS: usize = 10;
+
+/// Words too common to say anything about a note's topic
+static STOPWORDS: &[&str] = &[
+    "about", "after", "also", "and", "are", "but", "can", "for", "from", "has", "have", "into",
+    "its", "not", "one", "only", "our", "out", "that", "the", "their", "then", "there", "these",
+    "this", "was", "were", "what", "when", "which", "will", "with", "you", "your",
+];
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TagSuggestion {
+    pub tag: String,
+    pub score: f64,
+    /// Similar notes carrying the tag, best first
+    pub sources: Vec<PathBuf>,
+}
+
+/// Lowercased words of the note body, without stopwords, numbers and very short words.
+pub fn terms(note: &Note) -> Vec<String> {
+    frontmatter::split(&note.content)
+        .1
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3 && !word.chars().all(|c| c.is_ascii_digit()))
+        .map(str::to_lowercase)
+        .filter(|word| !STOPWORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// Unit-length TF-IDF vectors of every note, keyed by path.
+pub fn tfidf_vectors(notes: &[Note]) -> HashMap<PathBuf, HashMap<String, f64>> {
+    let counts: Vec<HashMap<String, usize>> = notes
+        .iter()
+        .map(|note| {
+            let mut counts = HashMap::new();
+            for term in terms(note) {
+                *counts.entry(term).or_insert(0) += 1;
+            }
+            counts
+        })
+        .collect();
+    let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+    for term in counts.iter().flat_map(HashMap::keys) {
+        *document_frequency.entry(term).or_insert(0) += 1;
+    }
+
+    let total = notes.len() as f64;
+    notes
+        .iter()
+        .zip(&counts)
+        .map(|(note, counts)| {
+            let mut vector: HashMap<String, f64> = counts
+                .iter()
+                .map(|(term, &count)| {
+                    let idf = (total / document_frequency[term.as_str()] as f64).ln();
+                    (term.clone(), (1.0 + (count as f64).ln()) * idf)
+                })
+                .collect();
+            let norm = vector.values().map(|w| w * w).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                vector.values_mut().for_each(|w| *w /= norm);
+            }
+            (note.path.clone(), vector)
+        })
+        .collect()
+}
+
+fn dot(a: &HashMap<String, f64>, b: &HashMap<String, f64>) -> f64 {
+    let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    small
+        .iter()
+        .filter_map(|(term, w)| large.get(term).map(|v| w * v))
+        .sum()
+}
+
+/// Tags of the notes most similar to `target`, weighted by similarity. `similarity`
+/// gives the score of each other note; tags `target` already has are left out.
+pub fn suggest(
+    notes: &[Note],
+    target: &Note,
+    similarity: impl Fn(&Note) -> f64,
+    limit: usize,
+) -> Vec<TagSuggestion> {
+    let mut neighbours: Vec<(&Note, f64)> = notes
+        .iter()
+        .filter(|note| note.path != target.path && !note.tags.is_empty())
+        .map(|note| (note, similarity(note)))
+        .filter(|(_, score)| *score > 0.0)
+        .collect();
+    neighbours.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.path.cmp(&b.0.path)));
+    neighbours.truncate(NEIGHBOURS);
+
+    let mut by_tag: HashMap<String, TagSuggestion> = HashMap::new();
+    for (note, score) in neighbours {
+        for tag in &note.tags {
+            if target.has_tag(tag) {
+                continue;
+            }
+            let suggestion = by_tag.entry(tag.clone()).or_insert_with(|| TagSuggestion {
+                tag: tag.clone(),
+                score: 0.0,
+                sources: Vec::new(),
+            });
+            suggestion.score += score;
+            suggestion.sources.push(note.path.clone());
+        }
+    }
+    let mut suggestions: Vec<TagSuggestion> = by_tag.into_values().collect();
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.tag.cmp(&b.tag)));
+    suggestions.truncate(limit);
+    suggestions
+}
+
+pub fn run_suggest_tags(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &SuggestTagsArgs,
+) -> Result<(), Box<dyn Error>> {
+    let resolver = Resolver::from_vault(vault_path)?;
+    let rel_path = resolver
+        .resolve(&args.note, Path::new(""))
+        .filter(|path| data::is_note(path))
+        .ok_or_else(|| format!("No note matches '{}'", args.note))?
+        .to_path_buf();
+    let notes = data::load_notes(vault_path)?;
+    let target = notes
+        .iter()
+        .find(|note| note.path == rel_path)
+        .ok_or_else(|| format!("Cannot read '{}'", rel_path.display()))?;
+
+    let tfidf = tfidf_vectors(&notes);
+    // With --semantic, note embeddings are averaged in with the term overlap
+    let vectors = if args.semantic {
+        let connection = index::open(config)?;
+        search::refresh_index(&connection, vault_path, config, true)?;
+        embeddings::note_vectors(&connection, &config.embeddings.model)?
+    } else {
+        HashMap::new()
+    };
+    let key = |note: &Note| note.path.to_string_lossy().replace('\\', "/");
+    let similarity = |note: &Note| {
+        let terms = dot(&tfidf[&target.path], &tfidf[&note.path]);
+        match (vectors.get(&key(target)), vectors.get(&key(note))) {
+            (Some(a), Some(b)) => (terms + embeddings::cosine(a, b)) / 2.0,
+            _ => terms,
+        }
+    };
+    let suggestions = suggest(&notes, target, similarity, args.limit);
+
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&suggestions)?),
+        OutputFormat::Ndjson => {
+            for suggestion in &suggestions {
+                util::print_ndjson(suggestion)?;
+            }
+        }
+        OutputFormat::Text => {
+            for suggestion in &suggestions {
+                let sources: Vec<String> = suggestion
+                    .sources
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                println!(
+                    "#{} ({:.3}) from {}",
+                    suggestion.tag,
+                    suggestion.score,
+                    sources.join(", ")
+                );
+            }
+        }
+    }
+
+    if args.apply && !suggestions.is_empty() {
+        let tags: Vec<String> = suggestions.iter().map(|s| s.tag.clone()).collect();
+        let content = fs::read_to_string(vault_path.join(&rel_path))?;
+        let updated = frontmatter::add_tags(&content, &tags)?;
+        let mut changes = ChangeSet::new();
+        changes.propose(&rel_path, Some(content), updated);
+        changes.apply(vault_path, "apply tags", false)?;
+        log::info!("Added {} tag(s) to {}", tags.len(), rel_path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    #[test]
+    fn test_suggest_from_term_overlap() {
+        let notes = vec![
+            note(
+                "Target.md",
+                "Borrow checker and lifetimes in the compiler.\n#lang",
+            ),
+            note(
+                "A.md",
+                "---\ntags: [rust, lang]\n---\nThe borrow checker rejects it.\n",
+            ),
+            note("B.md", "Lifetimes explained. #rust #compilers\n"),
+            note("C.md", "Tomatoes and compost for the garden. #gardening\n"),
+            note("D.md", "Untagged note about lifetimes.\n"),
+        ];
+        let tfidf = tfidf_vectors(&notes);
+        let target = &notes[0];
+        let suggestions = suggest(
+            &notes,
+            target,
+            |note| dot(&tfidf[&target.path], &tfidf[&note.path]),
+            5,
+        );
+
+        let tags: Vec<&str> = suggestions.iter().map(|s| s.tag.as_str()).collect();
+        assert_eq!(tags, vec!["rust", "compilers"]);
+        assert_eq!(
+            suggestions[0].sources,
+            vec![PathBuf::from("A.md"), PathBuf::from("B.md")]
+        );
+    }
+
+    #[test]
+    fn test_terms_skip_front_matter_and_stopwords() {
+        let words = terms(&note(
+            "A.md",
+            "---\ntitle: Ignored\n---\nThe Rust 2024 ed.\n",
+        ));
+        assert_eq!(words, vec!["rust"]);
+    }
+}
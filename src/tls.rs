@@ -0,0 +1,45 @@
+//! HTTPS for the daemon's listeners, built on rustls. Setting `client_ca` turns on mutual
+//! TLS: clients must then present a certificate issued by one of those CAs.
+
+use crate::config::TlsConfig;
+
+use rustls::{
+    RootCertStore, ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+};
+use std::{error::Error, fs::File, io::BufReader, path::Path, sync::Arc};
+
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certificates = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certificates.is_empty() {
+        return Err(format!("No certificates in '{}'", path.display()).into());
+    }
+    Ok(certificates)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>, Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| format!("No private key in '{}'", path.display()).into())
+}
+
+/// Loads the certificate, key and client CAs `config` names.
+pub fn server_config(config: &TlsConfig) -> Result<Arc<ServerConfig>, Box<dyn Error>> {
+    let builder = ServerConfig::builder();
+    let builder = match &config.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in certificates(client_ca)? {
+                roots.add(certificate)?;
+            }
+            builder
+                .with_client_cert_verifier(WebPkiClientVerifier::builder(Arc::new(roots)).build()?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let server =
+        builder.with_single_cert(certificates(&config.cert)?, private_key(&config.key)?)?;
+    Ok(Arc::new(server))
+}
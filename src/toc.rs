@@ -0,0 +1,128 @@
+//! Tables of contents. A note's outline goes between `<!-- toc -->` and `<!-- /toc -->`
+//! markers, where `toc` and `lint --fix` keep it in step with the headings.
+
+use crate::changeset::ChangeSet;
+use crate::cli::{LinkFormat, TocArgs};
+use crate::link_to::LinkSettings;
+use crate::markdown::{self, Heading};
+use crate::resolver::Resolver;
+
+use std::{error::Error, fs, path::Path};
+
+const BEGIN: &str = "<!-- toc -->";
+const END: &str = "<!-- /toc -->";
+
+/// The list of `headings` linking to each, indented by level. A lone H1 is the note's
+/// title and left out.
+fn render(headings: &[Heading], style: LinkFormat) -> String {
+    let h1s = headings.iter().filter(|h| h.level == 1).count();
+    let headings: Vec<&Heading> = headings
+        .iter()
+        .filter(|h| !(h1s == 1 && h.level == 1))
+        .collect();
+    let top = headings.iter().map(|h| h.level).min().unwrap_or(1);
+    let mut toc = String::new();
+    for heading in headings {
+        let link = match style {
+            LinkFormat::Wikilink => markdown::render_wikilink("", Some(&heading.text), None, false),
+            LinkFormat::Markdown => {
+                markdown::render_markdown_link("", Some(&heading.text), &heading.text, false)
+            }
+        };
+        let indent = "  ".repeat(heading.level - top);
+        toc.push_str(&format!("{}- {}\n", indent, link));
+    }
+    toc
+}
+
+/// Line range of the markers, both included, if the note has a complete block.
+fn find_block(lines: &[&str]) -> Option<(usize, usize)> {
+    let begin = lines.iter().position(|line| line.trim() == BEGIN)?;
+    let end = begin + lines[begin..].iter().position(|line| line.trim() == END)?;
+    Some((begin, end))
+}
+
+/// `content` with its table of contents rebuilt, or `None` if it has none. Links keep
+/// the style the block already uses, or `style` when it is empty.
+pub fn refresh(content: &str, style: LinkFormat) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let (begin, end) = find_block(&lines)?;
+    let old = lines[begin + 1..end].concat();
+    let style = match old.trim() {
+        "" => style,
+        old if old.contains("](#") => LinkFormat::Markdown,
+        _ => LinkFormat::Wikilink,
+    };
+    let mut refreshed = lines[..=begin].concat();
+    refreshed.push_str(&render(&markdown::parse_headings(content), style));
+    refreshed.push_str(&lines[end..].concat());
+    Some(refreshed)
+}
+
+/// `content` with a table of contents refreshed, or inserted after the title (or the
+/// front matter when there is no H1).
+pub fn insert_or_refresh(content: &str, style: LinkFormat) -> String {
+    if let Some(refreshed) = refresh(content, style) {
+        return refreshed;
+    }
+    let headings = markdown::parse_headings(content);
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let at = match headings.iter().find(|h| h.level == 1) {
+        Some(title) => title.line,
+        None => content[..markdown::body_start(content)].lines().count(),
+    };
+    let mut inserted = lines[..at].concat();
+    if !inserted.is_empty() && !inserted.ends_with('\n') {
+        inserted.push('\n');
+    }
+    inserted.push_str(&format!("{}\n{}{}\n", BEGIN, render(&headings, style), END));
+    if at < lines.len() {
+        inserted.push('\n');
+        inserted.push_str(lines[at..].concat().trim_start_matches('\n'));
+    }
+    inserted
+}
+
+pub fn run_toc(vault_path: &Path, args: &TocArgs) -> Result<(), Box<dyn Error>> {
+    let resolver = Resolver::from_vault(vault_path)?;
+    let path = resolver
+        .resolve(&args.note, Path::new(""))
+        .ok_or_else(|| format!("No note matches '{}'", args.note))?
+        .to_path_buf();
+    let content = fs::read_to_string(vault_path.join(&path))?;
+    let style = LinkSettings::from_vault(vault_path).style;
+
+    let mut changes = ChangeSet::new();
+    let updated = insert_or_refresh(&content, style);
+    changes.propose(path, Some(content), updated);
+    changes.finish(vault_path, &args.changes, "update table of contents", false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_refresh() {
+        let note = "---\ntags: [x]\n---\n# Title\nIntro\n## Setup\n### Install now\n## Use\n";
+        let with_toc = insert_or_refresh(note, LinkFormat::Wikilink);
+        assert_eq!(
+            with_toc,
+            "---\ntags: [x]\n---\n# Title\n<!-- toc -->\n- [[#Setup]]\n  - [[#Install now]]\n\
+             - [[#Use]]\n<!-- /toc -->\n\nIntro\n## Setup\n### Install now\n## Use\n"
+        );
+        assert_eq!(insert_or_refresh(&with_toc, LinkFormat::Markdown), with_toc);
+
+        let renamed = with_toc.replace("## Use", "## Usage");
+        let refreshed = refresh(&renamed, LinkFormat::Wikilink).unwrap();
+        assert!(refreshed.contains("- [[#Usage]]\n<!-- /toc -->"));
+
+        let markdown = "<!-- toc -->\n- [Old](#Old)\n<!-- /toc -->\n## Next steps\n";
+        assert_eq!(
+            refresh(markdown, LinkFormat::Wikilink).unwrap(),
+            "<!-- toc -->\n- [Next steps](#Next%20steps)\n<!-- /toc -->\n## Next steps\n"
+        );
+        assert_eq!(refresh("# Title\n", LinkFormat::Wikilink), None);
+    }
+}
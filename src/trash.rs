@@ -0,0 +1,151 @@
+//! Obsidian's `.trash` folder. Like other hidden folders it is never indexed; notes
+//! obsidian-rs removes are moved there, keeping their vault-relative path, so they can be
+//! restored.
+
+use crate::cli::TrashCommand;
+use crate::resolver::Resolver;
+use crate::util;
+use crate::write_gate;
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Vault-relative trash folder used by Obsidian's "Move to Obsidian trash"
+pub static TRASH_DIR: &str = ".trash";
+
+/// `path`, or the first of `name 1.ext`, `name 2.ext`, ... that does not exist yet.
+fn free_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{} {}{}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_default()
+}
+
+/// Moves the vault file `rel_path` into the trash instead of deleting it, returning its
+/// path inside the trash folder.
+pub fn move_to_trash(vault_path: &Path, rel_path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    write_gate::check("delete files")?;
+    let destination = free_path(&vault_path.join(TRASH_DIR).join(rel_path));
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(vault_path.join(rel_path), &destination)?;
+    Ok(util::get_relative_path(
+        &destination,
+        &vault_path.join(TRASH_DIR),
+    )?)
+}
+
+/// Files in the trash, relative to the trash folder.
+pub fn list(vault_path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let trash = vault_path.join(TRASH_DIR);
+    if !trash.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&trash) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            files.push(util::get_relative_path(entry.path(), &trash)?);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Moves a trashed file, named by path or note name as in a link, back to where it was.
+/// Restoring never overwrites: an occupied place is an error.
+pub fn restore(vault_path: &Path, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    write_gate::check("restore files")?;
+    let resolver = Resolver::new(list(vault_path)?);
+    let rel_path = resolver
+        .resolve(name, Path::new(""))
+        .ok_or_else(|| format!("Nothing in the trash matches '{}'", name))?
+        .to_path_buf();
+    let destination = vault_path.join(&rel_path);
+    if destination.exists() {
+        return Err(format!("'{}' already exists in the vault", rel_path.display()).into());
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(vault_path.join(TRASH_DIR).join(&rel_path), &destination)?;
+    Ok(rel_path)
+}
+
+pub fn run_trash(vault_path: &Path, command: &TrashCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        TrashCommand::List => {
+            for file in list(vault_path)? {
+                println!("{}", file.display());
+            }
+        }
+        TrashCommand::Put(args) => {
+            let rel_path = Resolver::from_vault(vault_path)?
+                .resolve(&args.note, Path::new(""))
+                .ok_or_else(|| format!("No file matches '{}'", args.note))?
+                .to_path_buf();
+            let trashed = move_to_trash(vault_path, &rel_path)?;
+            log::info!(
+                "Moved {} to {}/{}",
+                rel_path.display(),
+                TRASH_DIR,
+                trashed.display()
+            );
+        }
+        TrashCommand::Restore(args) => {
+            let rel_path = restore(vault_path, &args.note)?;
+            log::info!("Restored {}", rel_path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash_and_restore() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        let root = vault.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/A.md"), "first").unwrap();
+        assert_eq!(
+            move_to_trash(root, Path::new("sub/A.md")).unwrap(),
+            PathBuf::from("sub/A.md")
+        );
+        fs::write(root.join("sub/A.md"), "second").unwrap();
+        assert_eq!(
+            move_to_trash(root, Path::new("sub/A.md")).unwrap(),
+            PathBuf::from("sub/A 1.md")
+        );
+        assert_eq!(
+            list(root).unwrap(),
+            vec![PathBuf::from("sub/A 1.md"), PathBuf::from("sub/A.md")]
+        );
+
+        assert_eq!(restore(root, "A 1").unwrap(), PathBuf::from("sub/A 1.md"));
+        assert_eq!(
+            fs::read_to_string(root.join("sub/A 1.md")).unwrap(),
+            "second"
+        );
+        fs::write(root.join("sub/A.md"), "third").unwrap();
+        assert!(restore(root, "sub/A.md").is_err());
+    }
+}
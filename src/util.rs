@@ -1,18 +1,22 @@
+use serde::Serialize;
 use std::{
     borrow::Cow,
-    env,
-    path::{Path, PathBuf, StripPrefixError},
+    error::Error,
+    fs,
+    io::{self, Write},
+    path::{Component, Path, PathBuf, StripPrefixError},
+    time::UNIX_EPOCH,
 };
 
 // Helper function to get the home directory path based on OS
 pub fn get_home_dir() -> Option<PathBuf> {
     #[cfg(unix)]
     {
-        env::var("HOME").ok().map(PathBuf::from)
+        std::env::var("HOME").ok().map(PathBuf::from)
     }
     #[cfg(windows)]
     {
-        env::var("USERPROFILE").ok().map(PathBuf::from)
+        std::env::var("USERPROFILE").ok().map(PathBuf::from)
     }
     #[cfg(not(any(unix, windows)))]
     {
@@ -27,7 +31,7 @@ pub fn get_relative_path(full_path: &Path, base_path: &Path) -> Result<PathBuf,
 }
 
 /// Expands a path starting with '\~' to the user's home directory.
-pub fn expand_tilde(input_path: &Path) -> Option<Cow<Path>> {
+pub fn expand_tilde(input_path: &Path) -> Option<Cow<'_, Path>> {
     let path_str = match input_path.to_str() {
         Some(s) => s,
         None => return Some(Cow::Borrowed(input_path)), // Not UTF-8, return original
@@ -50,10 +54,220 @@ pub fn expand_tilde(input_path: &Path) -> Option<Cow<Path>> {
     }
 }
 
+/// `path` with symlinks, `.` and `..` resolved, like [`fs::canonicalize`], except that
+/// trailing components which do not exist yet (an output folder about to be created) are
+/// kept, resolved lexically.
+pub fn resolve_path(path: &Path) -> io::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let mut existing = path.as_path();
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(mut resolved) => {
+                for component in missing.iter().rev() {
+                    match component {
+                        Component::ParentDir => {
+                            resolved.pop();
+                        }
+                        Component::CurDir => {}
+                        component => resolved.push(component),
+                    }
+                }
+                return Ok(resolved);
+            }
+            Err(e) => match (existing.parent(), existing.components().next_back()) {
+                (Some(parent), Some(component)) => {
+                    missing.push(component);
+                    existing = parent;
+                }
+                _ => return Err(e),
+            },
+        }
+    }
+}
+
+/// Whether `path` is `dir` or inside it, once both are resolved with [`resolve_path`].
+pub fn is_within(path: &Path, dir: &Path) -> bool {
+    match (resolve_path(path), dir.canonicalize()) {
+        (Ok(path), Ok(dir)) => path.starts_with(dir),
+        _ => false,
+    }
+}
+
+/// Decodes `%XX` escapes as used in Markdown link destinations (e.g. `My%20Note.md`).
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+            && let Ok(byte) = u8::from_str_radix(&input[i + 1..i + 3], 16)
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Encodes characters that are not safe inside a Markdown link destination.
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            ' ' | '%' | '(' | ')' | '<' | '>' | '#' | '?' | '[' | ']' | '|' => {
+                encoded.push_str(&format!("%{:02X}", ch as u32));
+            }
+            _ => encoded.push(ch),
+        }
+    }
+    encoded
+}
+
+/// Escapes text for use in XML/HTML element content and attribute values.
+pub fn xml_escape(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Stable 64-bit FNV-1a hash of `bytes` as hex, for detecting content changes.
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Whether `path` lies in a hidden folder such as `.obsidian` or `.git`.
+pub fn is_hidden_path(base: &Path, path: &Path) -> bool {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+}
+
+/// Substitutes `{{name}}` placeholders in one pass, so placeholders inside the values are
+/// left alone; unknown placeholders are left as they are.
+pub fn render_template(template: &str, variables: &[(&str, String)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find("}}").and_then(|end| {
+            variables
+                .iter()
+                .find(|(name, _)| *name == &after[..end])
+                .map(|(_, value)| (value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                rendered.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                rendered.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Prints `item` as one line of newline-delimited JSON and flushes, so consumers see
+/// each result as soon as it is written.
+pub fn print_ndjson<T: Serialize>(item: &T) -> Result<(), Box<dyn Error>> {
+    let mut out = io::stdout().lock();
+    serde_json::to_writer(&mut out, item)?;
+    out.write_all(b"\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Prints `fields` as one tab-separated line for fzf and other pickers. Tabs and line
+/// breaks inside a field become spaces so the columns stay aligned.
+pub fn print_porcelain(fields: &[&str]) -> io::Result<()> {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|field| field.replace(['\t', '\n', '\r'], " "))
+        .collect();
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", fields.join("\t"))
+}
+
+/// Seconds since the epoch `file` was last modified, and its size.
+pub fn file_stamp(file: &Path) -> Result<(i64, u64), Box<dyn Error>> {
+    let metadata = fs::metadata(file)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+    Ok((modified as i64, metadata.len()))
+}
+
+/// Replaces `path` with `contents` so a crash leaves either the old or the new file, never
+/// a torn one: the data goes to a hidden temp file next to it, is synced, then renamed over
+/// the original. With `backup`, the previous contents are kept as `<path>.bak`.
+pub fn safe_write(path: &Path, contents: impl AsRef<[u8]>, backup: bool) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp = path.with_file_name(temp_name);
+
+    let written = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents.as_ref())?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    if backup && path.exists() {
+        let mut backup_path = path.as_os_str().to_owned();
+        backup_path.push(".bak");
+        fs::copy(path, &backup_path)?;
+    }
+    fs::rename(&temp, path)?;
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
 
     // Helper to run tests - set env var temporarily if needed
     // Note: Tests modifying env vars should be run serially (`cargo test -- --test-threads=1`)
@@ -124,4 +338,68 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_is_within() {
+        let root = tempfile::tempdir().unwrap();
+        let vault = root.path().join("vault");
+        fs::create_dir(&vault).unwrap();
+        assert!(is_within(&vault.join("new/out"), &vault));
+        assert!(is_within(&vault.join("./a/../b"), &vault));
+        assert!(!is_within(&vault.join("new/../../out"), &vault));
+        assert!(!is_within(root.path(), &vault));
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.path(), vault.join("link")).unwrap();
+            assert!(!is_within(&vault.join("link/out"), &vault));
+        }
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("My%20Note.md"), "My Note.md");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%C3%A9t%C3%A9"), "été");
+        assert_eq!(percent_encode("My Note (1).md"), "My%20Note%20%281%29.md");
+        assert_eq!(percent_decode(&percent_encode("a b#c")), "a b#c");
+    }
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(content_hash(b""), "cbf29ce484222325");
+        assert_eq!(content_hash(b"a"), "af63dc4c8601ec8c");
+        assert_ne!(content_hash(b"note"), content_hash(b"note "));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let vars = [("date", String::from("2024-01-02")), ("title", String::from("Inbox"))];
+        assert_eq!(
+            render_template("# {{title}}\ncreated: {{date}} {{other}}", &vars),
+            "# Inbox\ncreated: 2024-01-02 {{other}}"
+        );
+        let vars = [
+            ("title", String::from("{{path}}")),
+            ("path", String::from("a.md")),
+        ];
+        assert_eq!(
+            render_template("{{title}} {{path}}", &vars),
+            "{{path}} a.md"
+        );
+    }
+
+    #[test]
+    fn test_safe_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Note.md");
+        safe_write(&path, "first", true).unwrap();
+        assert!(!dir.path().join("Note.md.bak").exists());
+        safe_write(&path, "second", true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("Note.md.bak")).unwrap(),
+            "first"
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
 }
@@ -0,0 +1,338 @@
+//! `vault diff`: compares two copies of a vault note by note rather than file by file.
+//! Either side may be a vault folder or a zip archive written by a `backup` job. Notes
+//! that moved with their content unchanged are reported as renamed, and for notes on
+//! both sides the front matter properties and links that changed are listed.
+
+use crate::cli::{OutputFormat, VaultCommand, VaultDiffArgs};
+use crate::data::{self, Note};
+use crate::frontmatter;
+use crate::markdown;
+use crate::obsidian_vaults;
+
+use serde::Serialize;
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error::Error,
+    fs,
+    io::Read,
+    path::{Component, Path},
+};
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Renamed {
+    pub from: String,
+    pub to: String,
+}
+
+/// A front matter property that was added, removed or given another value
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PropertyChange {
+    pub key: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct NoteDiff {
+    pub path: String,
+    pub properties: Vec<PropertyChange>,
+    /// Link targets found only on the second side
+    pub links_added: Vec<String>,
+    pub links_removed: Vec<String>,
+    /// Whether the text below the front matter differs
+    pub body_changed: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct VaultDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub renamed: Vec<Renamed>,
+    pub changed: Vec<NoteDiff>,
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.components().any(|component| match component {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    })
+}
+
+/// The notes of a backup archive, as `data::load_notes` reads them from a folder.
+fn archive_notes(archive_path: &Path) -> Result<Vec<Note>, Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(archive_path)?)?;
+    let mut notes = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let Some(path) = file.enclosed_name() else {
+            continue;
+        };
+        if file.is_dir() || !data::is_note(&path) || is_hidden(&path) {
+            continue;
+        }
+        let mut content = String::new();
+        if let Err(e) = file.read_to_string(&mut content) {
+            log::warn!("Skipping '{}' in the archive: {}", path.display(), e);
+            continue;
+        }
+        notes.push(Note::from_content(path, content));
+    }
+    Ok(notes)
+}
+
+/// The notes of a vault folder or a zip archive of one.
+pub fn load_side(path: &Path) -> Result<Vec<Note>, Box<dyn Error>> {
+    if path.is_dir() {
+        data::load_notes(path)
+    } else if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+    {
+        archive_notes(path)
+    } else {
+        Err(format!(
+            "'{}' is neither a vault folder nor a zip archive",
+            path.display()
+        )
+        .into())
+    }
+}
+
+fn key_of(note: &Note) -> String {
+    note.path.to_string_lossy().replace('\\', "/")
+}
+
+/// Front matter properties as text, so that values of any type compare and print.
+fn properties(content: &str) -> BTreeMap<String, String> {
+    let mapping = frontmatter::parse_mapping(content)
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    mapping
+        .iter()
+        .filter_map(|(key, value)| {
+            let key = match key {
+                Value::String(key) => key.clone(),
+                key => serde_yaml::to_string(key).ok()?.trim().to_string(),
+            };
+            let value = match value {
+                Value::String(value) => value.clone(),
+                value => serde_yaml::to_string(value).ok()?.trim().to_string(),
+            };
+            Some((key, value))
+        })
+        .collect()
+}
+
+fn link_targets(content: &str) -> BTreeSet<String> {
+    markdown::parse_links(content)
+        .into_iter()
+        .map(|link| link.target)
+        .collect()
+}
+
+/// How `new` differs from `old`, or `None` if they are the same.
+fn compare_note(path: &str, old: &Note, new: &Note) -> Option<NoteDiff> {
+    if old.content == new.content {
+        return None;
+    }
+    let before = properties(&old.content);
+    let after = properties(&new.content);
+    let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let properties = keys
+        .into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| PropertyChange {
+            key: key.clone(),
+            before: before.get(key).cloned(),
+            after: after.get(key).cloned(),
+        })
+        .collect();
+    let old_links = link_targets(&old.content);
+    let new_links = link_targets(&new.content);
+    Some(NoteDiff {
+        path: path.to_string(),
+        properties,
+        links_added: new_links.difference(&old_links).cloned().collect(),
+        links_removed: old_links.difference(&new_links).cloned().collect(),
+        body_changed: frontmatter::split(&old.content).1 != frontmatter::split(&new.content).1,
+    })
+}
+
+/// Compares the notes of two vaults.
+pub fn diff_notes(old: &[Note], new: &[Note]) -> VaultDiff {
+    let old: BTreeMap<String, &Note> = old.iter().map(|note| (key_of(note), note)).collect();
+    let new: BTreeMap<String, &Note> = new.iter().map(|note| (key_of(note), note)).collect();
+    let mut diff = VaultDiff::default();
+    let removed: Vec<&String> = old.keys().filter(|path| !new.contains_key(*path)).collect();
+    // Removed notes by content, each list in reverse path order so `pop` takes the first
+    let mut by_content: HashMap<&str, Vec<&String>> = HashMap::new();
+    for path in removed.iter().rev() {
+        by_content
+            .entry(old[*path].content.as_str())
+            .or_default()
+            .push(*path);
+    }
+    let mut moved = HashSet::new();
+    for (path, note) in &new {
+        match old.get(path) {
+            Some(before) => diff.changed.extend(compare_note(path, before, note)),
+            None => {
+                // A note that disappeared while one with the same content appeared was moved
+                match by_content
+                    .get_mut(note.content.as_str())
+                    .and_then(|paths| paths.pop())
+                {
+                    Some(from) => {
+                        moved.insert(from);
+                        diff.renamed.push(Renamed {
+                            from: from.clone(),
+                            to: path.clone(),
+                        });
+                    }
+                    None => diff.added.push(path.clone()),
+                }
+            }
+        }
+    }
+    diff.removed = removed
+        .into_iter()
+        .filter(|path| !moved.contains(path))
+        .cloned()
+        .collect();
+    diff
+}
+
+fn print_diff(diff: &VaultDiff) {
+    for path in &diff.added {
+        println!("A  {}", path);
+    }
+    for path in &diff.removed {
+        println!("D  {}", path);
+    }
+    for renamed in &diff.renamed {
+        println!("R  {} -> {}", renamed.from, renamed.to);
+    }
+    for note in &diff.changed {
+        println!("M  {}", note.path);
+        for property in &note.properties {
+            println!(
+                "     {}: {} -> {}",
+                property.key,
+                property.before.as_deref().unwrap_or("(none)"),
+                property.after.as_deref().unwrap_or("(none)")
+            );
+        }
+        for target in &note.links_added {
+            println!("     + [[{}]]", target);
+        }
+        for target in &note.links_removed {
+            println!("     - [[{}]]", target);
+        }
+        if note.body_changed {
+            println!("     text changed");
+        }
+    }
+}
+
+fn run_diff(args: &VaultDiffArgs) -> Result<(), Box<dyn Error>> {
+    let diff = diff_notes(&load_side(&args.a)?, &load_side(&args.b)?);
+    match args.format {
+        OutputFormat::Text => print_diff(&diff),
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            println!("{}", serde_json::to_string(&diff)?)
+        }
+    }
+    Ok(())
+}
+
+pub fn run_vault(command: &VaultCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        VaultCommand::List(args) => obsidian_vaults::run_list(args),
+        VaultCommand::Diff(args) => run_diff(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup;
+    use std::path::PathBuf;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    #[test]
+    fn test_diff_notes() {
+        let old = vec![
+            note(
+                "Plan.md",
+                "---\nstatus: draft\ntags: [a]\n---\nSee [[Goals]].\n",
+            ),
+            note("Inbox/Idea.md", "An idea\n"),
+            note("Gone.md", "Bye\n"),
+            note("Same.md", "Same\n"),
+        ];
+        let new = vec![
+            note(
+                "Plan.md",
+                "---\nstatus: done\n---\nSee [[Goals]] and [[Risks]].\n",
+            ),
+            note("Ideas/Idea.md", "An idea\n"),
+            note("New.md", "Hello\n"),
+            note("Same.md", "Same\n"),
+        ];
+        let diff = diff_notes(&old, &new);
+        assert_eq!(diff.added, vec!["New.md"]);
+        assert_eq!(diff.removed, vec!["Gone.md"]);
+        assert_eq!(
+            diff.renamed,
+            vec![Renamed {
+                from: String::from("Inbox/Idea.md"),
+                to: String::from("Ideas/Idea.md"),
+            }]
+        );
+        assert_eq!(
+            diff.changed,
+            vec![NoteDiff {
+                path: String::from("Plan.md"),
+                properties: vec![
+                    PropertyChange {
+                        key: String::from("status"),
+                        before: Some(String::from("draft")),
+                        after: Some(String::from("done")),
+                    },
+                    PropertyChange {
+                        key: String::from("tags"),
+                        before: Some(String::from("- a")),
+                        after: None,
+                    },
+                ],
+                links_added: vec![String::from("Risks")],
+                links_removed: Vec::new(),
+                body_changed: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_load_side_reads_archives() {
+        let vault = tempfile::Builder::new().prefix("vault").tempdir().unwrap();
+        fs::create_dir_all(vault.path().join(".obsidian")).unwrap();
+        fs::write(vault.path().join("A.md"), "# A\n").unwrap();
+        fs::write(vault.path().join(".obsidian/B.md"), "hidden").unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let archive = out.path().join("vault.zip");
+        backup::write_archive(vault.path(), &archive).unwrap();
+
+        let from_folder = load_side(vault.path()).unwrap();
+        let from_archive = load_side(&archive).unwrap();
+        assert_eq!(from_archive.len(), 1);
+        assert_eq!(
+            diff_notes(&from_folder, &from_archive),
+            VaultDiff::default()
+        );
+    }
+}
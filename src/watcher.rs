@@ -1,22 +1,511 @@
+use crate::config::{WatchedEvent, WatcherBackend, WatcherConfig};
+use crate::data;
+use crate::folder_config;
+use crate::util;
+use crate::write_gate;
+
 use notify::{
-    Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher, event::ModifyKind,
+    Config, Event, EventHandler, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode,
+    Watcher, event::ModifyKind,
+};
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    fs,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        OnceLock,
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
 };
-use std::{error::Error, path::PathBuf};
+use walkdir::WalkDir;
+
+static SETTINGS: OnceLock<WatcherConfig> = OnceLock::new();
+
+/// File systems whose changes native watchers do not (reliably) report
+const NETWORK_FILE_SYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "drvfs",
+    "afs",
+    "davfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+];
+
+/// First wait before re-creating a failed watcher; doubled on every failed attempt, and
+/// on every restart that comes within `MAX_RESTART_DELAY` of the previous one
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// How long the native watcher may take to report the probe file before polling is used
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often `watch_while` asks an idle caller whether to go on
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+
+/// Uses `config` for every watcher created for the rest of the process.
+pub fn install(config: &WatcherConfig) {
+    let _ = SETTINGS.set(config.clone());
+}
+
+/// The configurable kind of `kind`; `None` for accesses, which never count as changes.
+fn watched_event(kind: &EventKind) -> Option<WatchedEvent> {
+    match kind {
+        EventKind::Access(_) => None,
+        EventKind::Create(_) => Some(WatchedEvent::Create),
+        EventKind::Remove(_) => Some(WatchedEvent::Remove),
+        EventKind::Modify(ModifyKind::Name(_)) => Some(WatchedEvent::Rename),
+        EventKind::Modify(ModifyKind::Metadata(_)) => Some(WatchedEvent::Metadata),
+        EventKind::Modify(_) | EventKind::Any | EventKind::Other => Some(WatchedEvent::Modify),
+    }
+}
+
+/// Whether the watcher settings drop events for `path` in the vault at `root`.
+fn is_ignored(settings: &WatcherConfig, root: &Path, path: &Path) -> bool {
+    let extension = path.extension().map(|ext| ext.to_string_lossy());
+    if extension.is_some_and(|ext| settings.ignore_extensions.iter().any(|i| *i == ext)) {
+        return true;
+    }
+    let rel_path = path.strip_prefix(root).unwrap_or(path);
+    let rel_name = rel_path.to_string_lossy().replace('\\', "/");
+    settings
+        .ignore
+        .iter()
+        .any(|pattern| match pattern.contains('/') {
+            true => folder_config::wildcard_match(pattern, &rel_name),
+            false => rel_path.components().any(|part| {
+                folder_config::wildcard_match(pattern, &part.as_os_str().to_string_lossy())
+            }),
+        })
+}
+
+/// `event` without the paths the settings ignore; `None` if nothing of it is left.
+fn filter_event(settings: &WatcherConfig, root: &Path, mut event: Event) -> Option<Event> {
+    let kind = watched_event(&event.kind)?;
+    if !settings.events.contains(&kind) {
+        return None;
+    }
+    event.paths.retain(|path| !is_ignored(settings, root, path));
+    (!event.paths.is_empty()).then_some(event)
+}
+
+/// File system type of the mount `path` is on, from `/proc/mounts`.
+fn mount_type(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (_, mount_point, fs_type) = (fields.next()?, fields.next()?, fields.next()?);
+            // Spaces in mount points are escaped as \040
+            let mount_point = PathBuf::from(mount_point.replace("\\040", " "));
+            path.starts_with(&mount_point)
+                .then(|| (mount_point.components().count(), fs_type.to_string()))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// Whether the native watcher notices a file created in `path`. Assumed when the vault
+/// may not be written to.
+fn native_reports_events(path: &Path) -> bool {
+    if write_gate::is_read_only() {
+        return true;
+    }
+    let (tx, rx) = mpsc::channel();
+    let Ok(mut watcher) = RecommendedWatcher::new(tx, Config::default()) else {
+        return false;
+    };
+    if watcher.watch(path, RecursiveMode::NonRecursive).is_err() {
+        return false;
+    }
+    let probe = path.join(format!(".obsidian-rs-probe-{}", std::process::id()));
+    if fs::write(&probe, "").is_err() {
+        return true;
+    }
+    let reported = rx.recv_timeout(PROBE_TIMEOUT).is_ok();
+    let _ = fs::remove_file(&probe);
+    reported
+}
+
+/// Whether `path` should be polled under the installed settings.
+fn use_polling(path: &Path, backend: WatcherBackend) -> bool {
+    match backend {
+        WatcherBackend::Native => false,
+        WatcherBackend::Poll => true,
+        WatcherBackend::Auto => match mount_type(path) {
+            Some(fs_type) if NETWORK_FILE_SYSTEMS.contains(&fs_type.as_str()) => {
+                log::info!("{} is on {}; polling for changes", path.display(), fs_type);
+                true
+            }
+            _ if !native_reports_events(path) => {
+                log::warn!(
+                    "No file events from {}; polling for changes",
+                    path.display()
+                );
+                true
+            }
+            _ => false,
+        },
+    }
+}
+
+/// A polling watcher of `path` under the installed settings.
+fn start_polling<F: EventHandler>(
+    path: &Path,
+    handler: F,
+) -> Result<Box<dyn Watcher>, Box<dyn Error>> {
+    let settings = SETTINGS.get().cloned().unwrap_or_default();
+    let config = Config::default()
+        .with_poll_interval(Duration::from_millis(settings.poll_interval_ms))
+        .with_compare_contents(true);
+    let mut watcher = PollWatcher::new(handler, config)?;
+    watcher.watch(path, RecursiveMode::Recursive)?;
+    Ok(Box::new(watcher))
+}
+
+/// Watches `root` and the visible directories below it one by one. inotify needs a watch
+/// per directory either way; leaving out `.git`, `.obsidian` and the like keeps huge
+/// vaults under the limit. Returns the number of directories watched.
+fn watch_directories(watcher: &mut dyn Watcher, root: &Path) -> notify::Result<usize> {
+    let directories = WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_dir());
+    let mut count = 0;
+    for directory in directories {
+        watcher.watch(directory.path(), RecursiveMode::NonRecursive)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// What to do when inotify runs out of watches for `path`.
+fn watch_limit_advice(path: &Path) -> String {
+    let needed = WalkDir::new(path)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_dir())
+        .count();
+    let limit = fs::read_to_string("/proc/sys/fs/inotify/max_user_watches")
+        .map(|limit| limit.trim().to_string())
+        .unwrap_or_else(|_| String::from("?"));
+    format!(
+        "{} needs {} directory watches but fs.inotify.max_user_watches is {} (shared by every \
+         program of the user). Raise it with `sudo sysctl fs.inotify.max_user_watches=524288` \
+         and make it permanent in /etc/sysctl.d/. Polling for changes until then.",
+        path.display(),
+        needed,
+        limit
+    )
+}
+
+/// What a [`ResilientWatcher`] received
+enum Signal {
+    Event(Event),
+    /// Events may have been lost; whoever consumes them should rescan the vault
+    Rescan,
+    /// A directory appeared, created or moved into the vault; files already inside it were
+    /// never reported, so this subtree should be rescanned
+    RescanDir(PathBuf),
+}
+
+/// Whether `error` leaves the watcher of `vault_path` unable to report further changes.
+/// A file or watch that vanished between two events only concerns that path.
+fn is_fatal(error: &notify::Error, vault_path: &Path) -> bool {
+    let vanished = match &error.kind {
+        notify::ErrorKind::PathNotFound | notify::ErrorKind::WatchNotFound => true,
+        notify::ErrorKind::Io(e) => e.kind() == std::io::ErrorKind::NotFound,
+        _ => false,
+    };
+    !vanished || error.paths.iter().any(|path| path == vault_path)
+}
+
+/// A watcher that logs its errors and queue overflows, and re-creates itself after the
+/// fatal ones instead of going quiet.
+struct ResilientWatcher {
+    path: PathBuf,
+    settings: WatcherConfig,
+    watcher: Box<dyn Watcher>,
+    /// Whether `watcher` polls; otherwise new directories must be watched as they appear
+    polling: bool,
+    rx: mpsc::Receiver<notify::Result<Event>>,
+    /// New directories waiting for their [`Signal::RescanDir`]
+    new_directories: Vec<PathBuf>,
+    /// When the watcher was last re-created, and the wait before it
+    restarted: Option<(Instant, Duration)>,
+}
+
+impl ResilientWatcher {
+    fn new(path: &Path) -> Result<ResilientWatcher, Box<dyn Error>> {
+        let settings = SETTINGS.get().cloned().unwrap_or_default();
+        let (tx, rx) = mpsc::channel();
+        if use_polling(path, settings.backend) {
+            return Ok(ResilientWatcher {
+                path: path.to_path_buf(),
+                settings,
+                watcher: start_polling(path, tx)?,
+                polling: true,
+                rx,
+                new_directories: Vec::new(),
+                restarted: None,
+            });
+        }
+
+        let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+        let watched = match cfg!(target_os = "linux") {
+            true => watch_directories(&mut watcher, path).map(|count| {
+                log::debug!("Watching {} directories", count);
+            }),
+            false => watcher.watch(path, RecursiveMode::Recursive),
+        };
+        let mut resilient = ResilientWatcher {
+            path: path.to_path_buf(),
+            settings,
+            watcher: Box::new(watcher),
+            polling: false,
+            rx,
+            new_directories: Vec::new(),
+            restarted: None,
+        };
+        match watched {
+            Ok(()) => {}
+            Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+                resilient.fall_back_to_polling()?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(resilient)
+    }
+
+    /// Replaces the native watcher, out of watches, with a polling one.
+    fn fall_back_to_polling(&mut self) -> Result<(), Box<dyn Error>> {
+        log::warn!("{}", watch_limit_advice(&self.path));
+        let (tx, rx) = mpsc::channel();
+        self.watcher = start_polling(&self.path, tx)?;
+        self.polling = true;
+        self.rx = rx;
+        Ok(())
+    }
+
+    /// Watches directories created or moved into the vault, with what is inside them, and
+    /// queues those that are not empty for a rescan.
+    fn watch_new_directories(&mut self, event: &Event) -> Option<Signal> {
+        if self.polling
+            || !matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+            )
+        {
+            return None;
+        }
+        for path in &event.paths {
+            if !path.is_dir() || util::is_hidden_path(&self.path, path) {
+                continue;
+            }
+            match watch_directories(self.watcher.as_mut(), path) {
+                // Anything added from now on is reported; only what is already there is not
+                Ok(_) if fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some()) => {
+                    self.new_directories.push(path.clone())
+                }
+                Ok(_) => {}
+                Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+                    if let Err(e) = self.fall_back_to_polling() {
+                        log::error!("Cannot poll {}: {}", self.path.display(), e);
+                    }
+                    return Some(Signal::Rescan);
+                }
+                Err(e) => log::error!("Cannot watch {}: {}", path.display(), e),
+            }
+        }
+        None
+    }
 
-pub fn run_watcher(vault_path: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let (tx, rx) = std::sync::mpsc::channel();
-    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+    /// Replaces the watcher, retrying with a growing delay until it works. A watcher that
+    /// keeps failing soon after each restart waits longer every time.
+    fn restart(&mut self) {
+        let mut delay = match self.restarted {
+            Some((at, delay)) if at.elapsed() < MAX_RESTART_DELAY => {
+                (delay * 2).min(MAX_RESTART_DELAY)
+            }
+            _ => RESTART_DELAY,
+        };
+        loop {
+            std::thread::sleep(delay);
+            match ResilientWatcher::new(&self.path) {
+                Ok(mut watcher) => {
+                    watcher.restarted = Some((Instant::now(), delay));
+                    *self = watcher;
+                    log::info!("Watching {} again", self.path.display());
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Cannot watch {}: {}", self.path.display(), e);
+                    delay = (delay * 2).min(MAX_RESTART_DELAY);
+                }
+            }
+        }
+    }
 
-    watcher.watch(vault_path, RecursiveMode::Recursive)?;
+    /// The next signal, waiting at most `timeout` if given; `None` when it passed. Events
+    /// the settings filter out are skipped.
+    fn recv(&mut self, timeout: Option<Duration>) -> Option<Signal> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(dir) = self.new_directories.pop() {
+                return Some(Signal::RescanDir(dir));
+            }
+            let received = match deadline {
+                Some(deadline) => self
+                    .rx
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let event = match received {
+                Ok(Ok(event)) if event.need_rescan() => {
+                    log::warn!(
+                        "File events were dropped; rescanning {}",
+                        self.path.display()
+                    );
+                    return Some(Signal::Rescan);
+                }
+                Ok(Ok(event)) => event,
+                Ok(Err(error)) if matches!(error.kind, notify::ErrorKind::MaxFilesWatch) => {
+                    if let Err(e) = self.fall_back_to_polling() {
+                        log::error!("Cannot poll {}: {}", self.path.display(), e);
+                    }
+                    return Some(Signal::Rescan);
+                }
+                Ok(Err(error)) if is_fatal(&error, &self.path) => {
+                    log::error!("Error receiving file event: {error:?}; restarting the watcher");
+                    self.restart();
+                    return Some(Signal::Rescan);
+                }
+                Ok(Err(error)) => {
+                    log::warn!("Error receiving file event: {error:?}");
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => {
+                    log::error!("The file watcher stopped; restarting it");
+                    self.restart();
+                    return Some(Signal::Rescan);
+                }
+            };
+            if let Some(signal) = self.watch_new_directories(&event) {
+                return Some(signal);
+            }
+            if let Some(event) = filter_event(&self.settings, &self.path, event) {
+                return Some(Signal::Event(event));
+            }
+        }
+    }
+}
+
+/// The files below `dir`, a directory new to the vault.
+fn files_in(dir: &Path) -> Vec<PathBuf> {
+    data::traverse_vault(dir).unwrap_or_else(|e| {
+        log::error!("Cannot rescan {}: {}", dir.display(), e);
+        Vec::new()
+    })
+}
+
+/// Logs vault events, calling `on_change` with every file the watcher settings let
+/// through, `on_rescan` when events were lost, and `on_settled` once `quiet` has passed
+/// after a burst of changes.
+pub fn run_watcher(
+    vault_path: &PathBuf,
+    quiet: Duration,
+    mut on_change: impl FnMut(&Path),
+    mut on_rescan: impl FnMut(),
+    mut on_settled: impl FnMut(),
+) -> Result<(), Box<dyn Error>> {
+    let mut watcher = ResilientWatcher::new(vault_path)?;
     log::info!("Successfully watching path: {:?}", vault_path);
 
-    for res in rx {
-        match res {
-            Ok(event) => callback_matcher(&event.kind, &event),
-            Err(error) => log::error!("Error receiving file event: {error:?}"),
+    let mut unsettled = false;
+    loop {
+        match watcher.recv(unsettled.then_some(quiet)) {
+            Some(Signal::Event(event)) => {
+                callback_matcher(&event.kind, &event);
+                event.paths.iter().for_each(|path| on_change(path));
+                unsettled = true;
+            }
+            Some(Signal::Rescan) => on_rescan(),
+            Some(Signal::RescanDir(dir)) => {
+                files_in(&dir).iter().for_each(|path| on_change(path));
+                unsettled = true;
+            }
+            None if unsettled => {
+                on_settled();
+                unsettled = false;
+            }
+            None => {}
+        }
+    }
+}
+
+/// Calls `on_change` with the paths touched by each burst of file events, once `quiet`
+/// has passed without further events. When events were lost,
+/// every file of the vault counts as touched.
+pub fn watch_debounced(
+    path: &Path,
+    quiet: Duration,
+    mut on_change: impl FnMut(Vec<PathBuf>),
+) -> Result<(), Box<dyn Error>> {
+    watch_while(path, quiet, |paths| {
+        if !paths.is_empty() {
+            on_change(paths);
+        }
+        ControlFlow::Continue(())
+    })
+}
+
+/// As `watch_debounced`, returning once `on_change` breaks. While nothing changes,
+/// `on_change` is called with no paths every `IDLE_CHECK`, so a caller that went away is
+/// noticed on a quiet vault too.
+pub fn watch_while(
+    path: &Path,
+    quiet: Duration,
+    mut on_change: impl FnMut(Vec<PathBuf>) -> ControlFlow<()>,
+) -> Result<(), Box<dyn Error>> {
+    let mut watcher = ResilientWatcher::new(path)?;
+    log::info!("Watching {} for changes", path.display());
+
+    let mut pending = BTreeSet::new();
+    loop {
+        let timeout = match pending.is_empty() {
+            true => IDLE_CHECK,
+            false => quiet,
+        };
+        match watcher.recv(Some(timeout)) {
+            Some(Signal::Event(event)) => pending.extend(event.paths),
+            Some(Signal::Rescan) => match data::traverse_vault(path) {
+                Ok(files) => pending.extend(files),
+                Err(e) => log::error!("Cannot rescan {}: {}", path.display(), e),
+            },
+            Some(Signal::RescanDir(dir)) => pending.extend(files_in(&dir)),
+            None => {
+                let paths = std::mem::take(&mut pending).into_iter().collect();
+                if on_change(paths).is_break() {
+                    return Ok(());
+                }
+            }
         }
     }
-    Ok(())
 }
 
 fn callback_matcher(event_kind: &EventKind, event: &Event) {
@@ -90,3 +579,120 @@ fn other_event_callback(_event: &Event) {
     //      log::info!("  Attributes: {:?}", event.attrs);
     //
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_use_polling() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(use_polling(dir.path(), WatcherBackend::Poll));
+        assert!(!use_polling(dir.path(), WatcherBackend::Native));
+        // A local temporary directory reports its events natively
+        assert!(!use_polling(dir.path(), WatcherBackend::Auto));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_restart_after_disconnect() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut watcher = ResilientWatcher::new(dir.path()).unwrap();
+        let (tx, rx) = mpsc::channel();
+        drop(tx);
+        watcher.rx = rx;
+
+        assert!(matches!(watcher.recv(None), Some(Signal::Rescan)));
+        fs::write(dir.path().join("A.md"), "a").unwrap();
+        let received = watcher.recv(Some(Duration::from_secs(5)));
+        assert!(matches!(received, Some(Signal::Event(_))));
+    }
+
+    #[test]
+    fn test_only_fatal_errors_restart() {
+        let vault = Path::new("/vault");
+        let vanished = notify::Error::path_not_found().add_path(vault.join("A.md"));
+        assert!(!is_fatal(&vanished, vault));
+        let root_gone = notify::Error::path_not_found().add_path(vault.to_path_buf());
+        assert!(is_fatal(&root_gone, vault));
+        assert!(is_fatal(&notify::Error::generic("queue closed"), vault));
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut watcher = ResilientWatcher::new(dir.path()).unwrap();
+        let (tx, rx) = mpsc::channel();
+        tx.send(Err(vanished)).unwrap();
+        watcher.rx = rx;
+        assert!(watcher.recv(Some(Duration::from_millis(100))).is_none());
+        assert!(watcher.restarted.is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_watch_new_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(".git/objects")).unwrap();
+        let mut watcher = RecommendedWatcher::new(|_| {}, Config::default()).unwrap();
+        assert_eq!(watch_directories(&mut watcher, dir.path()).unwrap(), 1);
+
+        let mut watcher = ResilientWatcher::new(dir.path()).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        // Events from probing the backend may come first
+        while let Some(Signal::Event(event)) = watcher.recv(Some(Duration::from_secs(5))) {
+            if event.kind.is_create() && event.paths[0].ends_with("sub") {
+                break;
+            }
+        }
+        fs::write(dir.path().join("sub/A.md"), "a").unwrap();
+        let mut paths = Vec::new();
+        while let Some(Signal::Event(event)) = watcher.recv(Some(Duration::from_secs(2))) {
+            paths.extend(event.paths);
+        }
+        assert!(paths.iter().any(|path| path.ends_with("sub/A.md")));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_moved_in_directories_are_rescanned() {
+        let root = tempfile::tempdir().unwrap();
+        let vault = root.path().join("vault");
+        fs::create_dir_all(root.path().join("outside/deep")).unwrap();
+        fs::create_dir(&vault).unwrap();
+        fs::write(root.path().join("outside/deep/B.md"), "b").unwrap();
+
+        let mut watcher = ResilientWatcher::new(&vault).unwrap();
+        fs::rename(root.path().join("outside"), vault.join("moved")).unwrap();
+        let mut rescanned = None;
+        while let Some(signal) = watcher.recv(Some(Duration::from_secs(5))) {
+            if let Signal::RescanDir(dir) = signal {
+                rescanned = Some(dir);
+                break;
+            }
+        }
+        let files = files_in(&rescanned.unwrap());
+        assert!(files.iter().any(|path| path.ends_with("moved/deep/B.md")));
+    }
+
+    #[test]
+    fn test_filter_event() {
+        let root = Path::new("/vault");
+        let settings = WatcherConfig {
+            ignore: vec![
+                String::from(".obsidian/workspace*.json"),
+                String::from("drafts"),
+            ],
+            ignore_extensions: vec![String::from("tmp")],
+            ..WatcherConfig::default()
+        };
+        let event = |kind, path: &str| Event::new(kind).add_path(root.join(path));
+        let write = EventKind::Modify(ModifyKind::Data(notify::event::DataChange::Content));
+
+        assert!(filter_event(&settings, root, event(write, "A.md")).is_some());
+        assert!(filter_event(&settings, root, event(write, ".obsidian/workspace.json")).is_none());
+        assert!(filter_event(&settings, root, event(write, "sub/drafts/A.md")).is_none());
+        assert!(filter_event(&settings, root, event(write, "A.md.tmp")).is_none());
+        let chmod = EventKind::Modify(ModifyKind::Metadata(notify::event::MetadataKind::Any));
+        assert!(filter_event(&settings, root, event(chmod, "A.md")).is_none());
+        let access = EventKind::Access(notify::event::AccessKind::Any);
+        assert!(filter_event(&settings, root, event(access, "A.md")).is_none());
+    }
+}
@@ -0,0 +1,46 @@
+//! A small browser front end embedded in the binary and served at `/ui`: search, notes
+//! rendered to HTML with their backlinks, and a graph of the links between notes. It
+//! only talks to the HTTP API, sending the API key the user enters once.
+
+use crate::http::{self, Response};
+
+use rust_embed::RustEmbed;
+use std::path::Path;
+
+#[derive(RustEmbed)]
+#[folder = "web/"]
+struct Assets;
+
+/// `GET /ui/<path>`: an asset of the front end, `index.html` for the page itself
+pub fn get(path: &str) -> Response {
+    let path = match path.trim_start_matches('/') {
+        "" => "index.html",
+        path => path,
+    };
+    match Assets::get(path) {
+        Some(asset) => Response {
+            status: 200,
+            content_type: String::from(http::content_type_of(Path::new(path))),
+            headers: vec![(String::from("Cache-Control"), String::from("no-cache"))],
+            body: asset.data.into_owned(),
+        },
+        None => Response::not_found(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let page = get("");
+        assert_eq!(page.status, 200);
+        assert!(page.content_type.starts_with("text/html"));
+        assert_eq!(
+            get("/app.js").content_type,
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(get("missing.js").status, 404);
+    }
+}
@@ -0,0 +1,47 @@
+//! Central switch for read-only mode: every operation that changes vault files asks
+//! `check` first, so `--read-only` (or `[workspace] read_only`) holds for the CLI, the
+//! HTTP API and the MCP server alike.
+
+use std::{
+    error::Error,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::Relaxed);
+    if read_only {
+        log::info!("Vault is read-only; commands that change notes are disabled");
+    }
+}
+
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// Refuses `action` (e.g. "capture notes") while the vault is read-only.
+pub fn check(action: &str) -> Result<(), Box<dyn Error>> {
+    gate(is_read_only(), action)
+}
+
+fn gate(read_only: bool, action: &str) -> Result<(), Box<dyn Error>> {
+    if read_only {
+        return Err(format!("Cannot {}: the vault is read-only", action).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gate() {
+        assert!(gate(false, "capture notes").is_ok());
+        assert_eq!(
+            gate(true, "capture notes").unwrap_err().to_string(),
+            "Cannot capture notes: the vault is read-only"
+        );
+    }
+}
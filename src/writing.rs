@@ -0,0 +1,430 @@
+//! Writing progress. Each time the index is refreshed the word counts of changed notes
+//! are compared with the counts stored in the cache, and words added are credited to the
+//! day. `writing stats` and `GET /writing` report them against the `[writing]` goals.
+
+use crate::cli::{OutputFormat, WritingCommand, WritingStatsArgs};
+use crate::config::{AppConfig, WritingConfig};
+use crate::data::Note;
+use crate::frontmatter;
+use crate::index::{self, IndexStats};
+use crate::search;
+
+use jiff::{Span, Zoned, civil};
+use serde::Serialize;
+use sqlite::{Connection, State};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    path::Path,
+};
+
+/// Most days of history `stats` lists: ten years
+const MAX_DAYS: usize = 3660;
+
+/// Words added on one day
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DayWords {
+    pub day: String,
+    pub words: i64,
+}
+
+/// Words a note has towards its goal in `[writing.goals]`
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NoteGoal {
+    pub path: String,
+    pub words: i64,
+    pub goal: usize,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct WritingStats {
+    pub today: i64,
+    pub daily_goal: Option<usize>,
+    /// Days in a row, up to today or yesterday, that met the daily goal (or had any
+    /// writing, without one)
+    pub streak: usize,
+    pub longest_streak: usize,
+    /// The last days, oldest first
+    pub days: Vec<DayWords>,
+    pub goals: Vec<NoteGoal>,
+}
+
+pub fn ensure_schema(connection: &Connection) -> Result<(), sqlite::Error> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS word_counts (
+            path TEXT PRIMARY KEY,
+            words INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS words_written (
+            day TEXT NOT NULL,
+            path TEXT NOT NULL,
+            words INTEGER NOT NULL,
+            PRIMARY KEY (day, path)
+        );",
+    )
+}
+
+/// Words in the body of `content`: runs of non-space text with a letter or digit.
+pub fn word_count(content: &str) -> usize {
+    frontmatter::split(content)
+        .1
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count()
+}
+
+fn stored_counts(connection: &Connection) -> Result<HashMap<String, i64>, sqlite::Error> {
+    let mut statement = connection.prepare("SELECT path, words FROM word_counts")?;
+    let mut counts = HashMap::new();
+    while let State::Row = statement.next()? {
+        counts.insert(
+            statement.read::<String, _>(0)?,
+            statement.read::<i64, _>(1)?,
+        );
+    }
+    Ok(counts)
+}
+
+fn store_count(connection: &Connection, path: &str, words: i64) -> Result<(), sqlite::Error> {
+    let mut statement =
+        connection.prepare("INSERT OR REPLACE INTO word_counts (path, words) VALUES (?, ?)")?;
+    statement.bind((1, path))?;
+    statement.bind((2, words))?;
+    statement.next()?;
+    Ok(())
+}
+
+fn credit(connection: &Connection, day: &str, path: &str, words: i64) -> Result<(), sqlite::Error> {
+    let mut statement = connection.prepare(
+        "INSERT INTO words_written (day, path, words) VALUES (?, ?, ?)
+         ON CONFLICT(day, path) DO UPDATE SET words = words + excluded.words",
+    )?;
+    statement.bind((1, day))?;
+    statement.bind((2, path))?;
+    statement.bind((3, words))?;
+    statement.next()?;
+    Ok(())
+}
+
+/// Credits `today` with the words the notes modified in `stats` gained, returning how
+/// many. The first time, the counts of all `notes` are only recorded, so the existing
+/// vault does not count as written today; notes added later start from the count they
+/// were first seen with, so an imported or moved-in note does not count either.
+pub fn update(
+    connection: &Connection,
+    notes: &[Note],
+    stats: &IndexStats,
+    today: civil::Date,
+) -> Result<i64, sqlite::Error> {
+    ensure_schema(connection)?;
+    connection.execute("BEGIN")?;
+    match update_counts(connection, notes, stats, today) {
+        Ok(written) => connection.execute("COMMIT").map(|_| written),
+        Err(e) => {
+            let _ = connection.execute("ROLLBACK");
+            Err(e)
+        }
+    }
+}
+
+fn update_counts(
+    connection: &Connection,
+    notes: &[Note],
+    stats: &IndexStats,
+    today: civil::Date,
+) -> Result<i64, sqlite::Error> {
+    let known = stored_counts(connection)?;
+    let by_path: HashMap<String, &Note> = notes
+        .iter()
+        .map(|note| (note.path.to_string_lossy().replace('\\', "/"), note))
+        .collect();
+    let day = today.to_string();
+    let mut written = 0;
+    if known.is_empty() {
+        for (path, note) in &by_path {
+            store_count(connection, path, word_count(&note.content) as i64)?;
+        }
+        return Ok(0);
+    }
+    for (from, to) in &stats.renamed {
+        let mut statement = connection.prepare("UPDATE word_counts SET path = ? WHERE path = ?")?;
+        statement.bind((1, to.as_str()))?;
+        statement.bind((2, from.as_str()))?;
+        statement.next()?;
+    }
+    for path in &stats.removed {
+        let mut statement = connection.prepare("DELETE FROM word_counts WHERE path = ?")?;
+        statement.bind((1, path.as_str()))?;
+        statement.next()?;
+    }
+    for path in stats.added.iter().chain(&stats.modified) {
+        let Some(note) = by_path.get(path) else {
+            continue;
+        };
+        let words = word_count(&note.content) as i64;
+        let gained = known.get(path).map_or(0, |&before| words - before);
+        if gained > 0 {
+            credit(connection, &day, path, gained)?;
+            written += gained;
+        }
+        store_count(connection, path, words)?;
+    }
+    Ok(written)
+}
+
+/// The current and longest runs of days up to `today` with at least `goal` words. A run
+/// that ended yesterday is still current, as today may yet meet the goal.
+pub fn streaks(
+    per_day: &BTreeMap<civil::Date, i64>,
+    goal: i64,
+    today: civil::Date,
+) -> (usize, usize) {
+    let met = |day: &civil::Date| per_day.get(day).is_some_and(|&words| words >= goal.max(1));
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<civil::Date> = None;
+    for day in per_day.keys().filter(|day| met(day)) {
+        run = match previous {
+            Some(previous) if previous.tomorrow().ok() == Some(*day) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(*day);
+    }
+
+    let mut current = 0;
+    let mut day = match met(&today) {
+        true => today,
+        false => today.yesterday().unwrap_or(today),
+    };
+    while met(&day) {
+        current += 1;
+        match day.yesterday() {
+            Ok(earlier) => day = earlier,
+            Err(_) => break,
+        }
+    }
+    (current, longest)
+}
+
+/// Words written per day across the vault.
+fn words_per_day(connection: &Connection) -> Result<BTreeMap<civil::Date, i64>, Box<dyn Error>> {
+    let mut statement =
+        connection.prepare("SELECT day, SUM(words) FROM words_written GROUP BY day")?;
+    let mut per_day = BTreeMap::new();
+    while let State::Row = statement.next()? {
+        let day = statement.read::<String, _>(0)?.parse::<civil::Date>()?;
+        per_day.insert(day, statement.read::<i64, _>(1)?);
+    }
+    Ok(per_day)
+}
+
+/// Progress over the last `days` days, at most [`MAX_DAYS`], and towards the configured
+/// goals.
+pub fn stats(
+    connection: &Connection,
+    settings: &WritingConfig,
+    today: civil::Date,
+    days: usize,
+) -> Result<WritingStats, Box<dyn Error>> {
+    ensure_schema(connection)?;
+    let per_day = words_per_day(connection)?;
+    let goal = settings.daily_goal.unwrap_or_default() as i64;
+    let (streak, longest_streak) = streaks(&per_day, goal, today);
+    let mut recent = Vec::new();
+    for back in (0..days.min(MAX_DAYS) as i64).rev() {
+        let day = today.checked_sub(Span::new().try_days(back)?)?;
+        recent.push(DayWords {
+            day: day.to_string(),
+            words: per_day.get(&day).copied().unwrap_or_default(),
+        });
+    }
+
+    let counts = stored_counts(connection)?;
+    let goals = settings
+        .goals
+        .iter()
+        .map(|(path, goal)| NoteGoal {
+            path: path.clone(),
+            words: counts.get(path).copied().unwrap_or_default(),
+            goal: *goal,
+        })
+        .collect();
+    Ok(WritingStats {
+        today: per_day.get(&today).copied().unwrap_or_default(),
+        daily_goal: settings.daily_goal,
+        streak,
+        longest_streak,
+        days: recent,
+        goals,
+    })
+}
+
+fn percent(words: i64, goal: usize) -> i64 {
+    words * 100 / goal.max(1) as i64
+}
+
+fn print_stats(stats: &WritingStats) {
+    match stats.daily_goal {
+        Some(goal) => println!(
+            "Today: {} / {} words ({}%)",
+            stats.today,
+            goal,
+            percent(stats.today, goal)
+        ),
+        None => println!("Today: {} words", stats.today),
+    }
+    println!(
+        "Streak: {} day(s), longest {}",
+        stats.streak, stats.longest_streak
+    );
+    println!();
+    for day in &stats.days {
+        println!("{}  {:>6}", day.day, day.words);
+    }
+    if !stats.goals.is_empty() {
+        println!();
+    }
+    for goal in &stats.goals {
+        println!(
+            "{}  {} / {} ({}%)",
+            goal.path,
+            goal.words,
+            goal.goal,
+            percent(goal.words, goal.goal)
+        );
+    }
+}
+
+pub fn run_writing(
+    vault_path: &Path,
+    config: &AppConfig,
+    command: &WritingCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        WritingCommand::Stats(args) => run_stats(vault_path, config, args),
+    }
+}
+
+fn run_stats(
+    vault_path: &Path,
+    config: &AppConfig,
+    args: &WritingStatsArgs,
+) -> Result<(), Box<dyn Error>> {
+    let connection = index::open(config)?;
+    search::refresh_index(&connection, vault_path, config, false)?;
+    let stats = stats(&connection, &config.writing, Zoned::now().date(), args.days)?;
+    match args.format {
+        OutputFormat::Text => print_stats(&stats),
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            println!("{}", serde_json::to_string(&stats)?)
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn note(path: &str, content: &str) -> Note {
+        Note::from_content(PathBuf::from(path), content.to_string())
+    }
+
+    #[test]
+    fn test_word_count() {
+        assert_eq!(
+            word_count("---\ntitle: A long title\n---\nOne two - three.\n"),
+            3
+        );
+        assert_eq!(word_count(""), 0);
+    }
+
+    #[test]
+    fn test_update_credits_words_added() {
+        let connection = sqlite::open(":memory:").unwrap();
+        let today = civil::date(2024, 5, 15);
+        let notes = vec![note("A.md", "one two"), note("B.md", "three")];
+
+        // The first run only records counts
+        assert_eq!(
+            update(&connection, &notes, &IndexStats::default(), today).unwrap(),
+            0
+        );
+
+        let notes = vec![
+            note("A.md", "one two three four"),
+            note("C.md", "five six"),
+            note("D.md", "three"),
+        ];
+        let stats = IndexStats {
+            added: vec![String::from("C.md")],
+            modified: vec![String::from("A.md")],
+            removed: Vec::new(),
+            renamed: vec![(String::from("B.md"), String::from("D.md"))],
+        };
+        // C.md is new: its words are recorded, not credited
+        assert_eq!(update(&connection, &notes, &stats, today).unwrap(), 2);
+
+        // Deleting words takes nothing back
+        let notes = vec![note("A.md", "one")];
+        let stats = IndexStats {
+            modified: vec![String::from("A.md")],
+            ..IndexStats::default()
+        };
+        assert_eq!(update(&connection, &notes, &stats, today).unwrap(), 0);
+        let counts = stored_counts(&connection).unwrap();
+        assert_eq!(counts.get("A.md"), Some(&1));
+        assert_eq!(counts.get("D.md"), Some(&1));
+        assert_eq!(words_per_day(&connection).unwrap().get(&today), Some(&2));
+    }
+
+    #[test]
+    fn test_streaks() {
+        let today = civil::date(2024, 5, 15);
+        let per_day = BTreeMap::from([
+            (civil::date(2024, 5, 1), 600),
+            (civil::date(2024, 5, 2), 700),
+            (civil::date(2024, 5, 3), 800),
+            (civil::date(2024, 5, 12), 100),
+            (civil::date(2024, 5, 13), 500),
+            (civil::date(2024, 5, 14), 550),
+        ]);
+        // Today has nothing yet, so the run ending yesterday still counts
+        assert_eq!(streaks(&per_day, 500, today), (2, 3));
+        assert_eq!(streaks(&per_day, 0, today), (3, 3));
+        assert_eq!(streaks(&per_day, 500, civil::date(2024, 5, 17)), (0, 3));
+    }
+
+    #[test]
+    fn test_stats_caps_days() {
+        let connection = sqlite::open(":memory:").unwrap();
+        let today = civil::date(2024, 5, 15);
+        let stats = stats(&connection, &WritingConfig::default(), today, usize::MAX).unwrap();
+        assert_eq!(stats.days.len(), MAX_DAYS);
+    }
+
+    #[test]
+    fn test_failed_update_rolls_back() {
+        let connection = sqlite::open(":memory:").unwrap();
+        let today = civil::date(2024, 5, 15);
+        let notes = vec![note("A.md", "one")];
+        update(&connection, &notes, &IndexStats::default(), today).unwrap();
+        connection.execute("DROP TABLE words_written").unwrap();
+        connection
+            .execute("CREATE TABLE words_written (day TEXT)")
+            .unwrap();
+
+        let notes = vec![note("A.md", "one two")];
+        let stats = IndexStats {
+            modified: vec![String::from("A.md")],
+            ..IndexStats::default()
+        };
+        assert!(update(&connection, &notes, &stats, today).is_err());
+        // No transaction was left open, and the count was not half-updated
+        connection.execute("BEGIN").unwrap();
+        connection.execute("COMMIT").unwrap();
+        assert_eq!(stored_counts(&connection).unwrap().get("A.md"), Some(&1));
+    }
+}
@@ -0,0 +1,214 @@
+// Front end for the obsidian-rs HTTP API. Views are picked from the URL hash:
+// "#search=<words>", "#note=<path>" and "#graph".
+"use strict";
+
+const KEY_STORAGE = "obsidian-rs-api-key";
+
+const $ = (id) => document.getElementById(id);
+
+function setStatus(text) {
+  $("status").textContent = text;
+}
+
+async function api(path) {
+  const headers = {};
+  const key = localStorage.getItem(KEY_STORAGE);
+  if (key) {
+    headers.Authorization = `Bearer ${key}`;
+  }
+  const response = await fetch(path, { headers });
+  if (response.status === 401) {
+    const entered = prompt("API key for this vault");
+    if (entered) {
+      localStorage.setItem(KEY_STORAGE, entered.trim());
+      return api(path);
+    }
+  }
+  if (!response.ok) {
+    const body = await response.json().catch(() => ({}));
+    throw new Error(body.error || `${response.status} ${response.statusText}`);
+  }
+  return response.json();
+}
+
+function show(view) {
+  for (const id of ["results", "note", "graph"]) {
+    $(id).hidden = id !== view;
+  }
+}
+
+function noteLink(path, text) {
+  const link = document.createElement("a");
+  link.href = `#note=${encodeURIComponent(path)}`;
+  link.textContent = text || path;
+  return link;
+}
+
+// Snippets mark matches as **bold**; everything else is shown as text
+function snippet(text) {
+  const paragraph = document.createElement("p");
+  text.split("**").forEach((part, i) => {
+    const node = i % 2 ? document.createElement("strong") : document.createTextNode(part);
+    if (i % 2) {
+      node.textContent = part;
+    }
+    paragraph.append(node);
+  });
+  return paragraph;
+}
+
+async function showSearch(words) {
+  show("results");
+  $("search").value = words;
+  const results = $("results");
+  results.replaceChildren();
+  if (!words) {
+    setStatus("");
+    return;
+  }
+  setStatus("Searching…");
+  const listing = await api(`/search?q=${encodeURIComponent(words)}&limit=30`);
+  for (const hit of listing.items) {
+    const item = document.createElement("div");
+    item.className = "hit";
+    const title = hit.heading ? `${hit.path} › ${hit.heading}` : hit.path;
+    item.append(noteLink(hit.path, title), snippet(hit.snippet));
+    results.append(item);
+  }
+  setStatus(listing.total ? "" : "No matches");
+}
+
+async function showNote(path) {
+  show("note");
+  setStatus("Loading…");
+  const note = await api(`/note?note=${encodeURIComponent(path)}`);
+  document.title = `${note.title} · obsidian-rs`;
+  $("note-title").textContent = note.title;
+  $("note-tags").replaceChildren(
+    ...note.tags.map((tag) => {
+      const span = document.createElement("span");
+      span.textContent = `#${tag}`;
+      return span;
+    }),
+  );
+  $("note-body").innerHTML = note.html;
+  // Links between notes open here rather than in the raw API
+  for (const link of $("note-body").querySelectorAll('a[href^="/note?note="]')) {
+    const target = new URL(link.href).searchParams.get("note");
+    link.href = `#note=${encodeURIComponent(target)}`;
+  }
+  const backlinks = await api(`/backlinks?note=${encodeURIComponent(note.path)}&limit=200`);
+  $("backlinks").replaceChildren(
+    ...backlinks.items.map((backlink) => {
+      const item = document.createElement("li");
+      const context = document.createElement("p");
+      context.textContent = backlink.context;
+      item.append(noteLink(backlink.path), context);
+      return item;
+    }),
+  );
+  setStatus("");
+}
+
+// A plain force-directed layout: nodes repel, links pull their ends together
+function layout(nodes, links, width, height) {
+  nodes.forEach((node, i) => {
+    const angle = (i / nodes.length) * 2 * Math.PI;
+    node.x = width / 2 + (Math.cos(angle) * width) / 3;
+    node.y = height / 2 + (Math.sin(angle) * height) / 3;
+  });
+  const index = new Map(nodes.map((node) => [node.id, node]));
+  const edges = links
+    .map((link) => [index.get(link.source), index.get(link.target)])
+    .filter(([a, b]) => a && b);
+  for (let step = 0; step < 200; step++) {
+    const heat = 1 - step / 200;
+    for (const a of nodes) {
+      a.dx = (width / 2 - a.x) * 0.01;
+      a.dy = (height / 2 - a.y) * 0.01;
+      for (const b of nodes) {
+        if (a === b) continue;
+        const x = a.x - b.x;
+        const y = a.y - b.y;
+        const distance = Math.max(Math.hypot(x, y), 1);
+        a.dx += (x / distance) * (900 / distance);
+        a.dy += (y / distance) * (900 / distance);
+      }
+    }
+    for (const [a, b] of edges) {
+      const x = b.x - a.x;
+      const y = b.y - a.y;
+      a.dx += x * 0.02;
+      a.dy += y * 0.02;
+      b.dx -= x * 0.02;
+      b.dy -= y * 0.02;
+    }
+    for (const node of nodes) {
+      node.x = Math.min(width - 10, Math.max(10, node.x + node.dx * heat));
+      node.y = Math.min(height - 10, Math.max(10, node.y + node.dy * heat));
+    }
+  }
+  return edges;
+}
+
+async function showGraph() {
+  show("graph");
+  setStatus("Loading…");
+  const graph = await api("/graph");
+  const canvas = $("graph-canvas");
+  const width = (canvas.width = canvas.clientWidth);
+  const height = (canvas.height = canvas.clientHeight);
+  const edges = layout(graph.nodes, graph.links, width, height);
+  const context = canvas.getContext("2d");
+  const style = getComputedStyle(document.documentElement);
+  context.clearRect(0, 0, width, height);
+  context.strokeStyle = style.getPropertyValue("--muted");
+  for (const [a, b] of edges) {
+    context.beginPath();
+    context.moveTo(a.x, a.y);
+    context.lineTo(b.x, b.y);
+    context.stroke();
+  }
+  context.fillStyle = style.getPropertyValue("--accent");
+  context.font = "11px system-ui, sans-serif";
+  for (const node of graph.nodes) {
+    context.beginPath();
+    context.arc(node.x, node.y, 4, 0, 2 * Math.PI);
+    context.fill();
+    context.fillText(node.title, node.x + 6, node.y + 4);
+  }
+  canvas.onclick = (event) => {
+    const bounds = canvas.getBoundingClientRect();
+    const x = event.clientX - bounds.left;
+    const y = event.clientY - bounds.top;
+    const hit = graph.nodes.find((node) => Math.hypot(node.x - x, node.y - y) < 8);
+    if (hit) {
+      location.hash = `note=${encodeURIComponent(hit.id)}`;
+    }
+  };
+  setStatus(`${graph.nodes.length} notes, ${edges.length} links`);
+}
+
+function route() {
+  const hash = decodeURIComponent(location.hash.slice(1));
+  let view;
+  if (hash.startsWith("note=")) {
+    view = showNote(hash.slice("note=".length));
+  } else if (hash === "graph") {
+    view = showGraph();
+  } else {
+    view = showSearch(hash.startsWith("search=") ? hash.slice("search=".length) : "");
+  }
+  view.catch((error) => setStatus(error.message));
+}
+
+$("search-form").addEventListener("submit", (event) => {
+  event.preventDefault();
+  location.hash = `search=${encodeURIComponent($("search").value)}`;
+});
+$("forget-key").addEventListener("click", () => {
+  localStorage.removeItem(KEY_STORAGE);
+  setStatus("The API key was forgotten");
+});
+window.addEventListener("hashchange", route);
+route();